@@ -10,18 +10,23 @@ extern "C" {
 
 int memcloud_init();
 int memcloud_init_with_path(const char *socket_path);
+int memcloud_init_named(const char *name);
 
 int memcloud_store(const void *data, size_t size, uint64_t *out_id);
 
 int memcloud_load(uint64_t id, void *out_buffer, size_t buffer_size);
 
 int memcloud_free(uint64_t id);
+// Frees `count` ids in one call; unknown ids are skipped. The out pointers may be NULL.
+int memcloud_free_many(const uint64_t *ids, size_t count, uint64_t *out_freed,
+                       uint64_t *out_bytes);
 
 int memcloud_vm_alloc(uint64_t size, uint64_t *out_region_id);
 int memcloud_vm_fetch(uint64_t region_id, uint64_t page_index, void *out_buffer,
                       size_t buffer_size);
 int memcloud_vm_store(uint64_t region_id, uint64_t page_index, const void *data,
                       size_t size);
+int memcloud_vm_threshold_mb(uint64_t *out_mb);
 
 #ifdef __cplusplus
 }
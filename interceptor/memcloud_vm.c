@@ -174,13 +174,22 @@ static void lazy_init() {
   pthread_create(&th, NULL, sync_thread, NULL);
   pthread_detach(th);
 
+  // Named after the program so its pages show up in `memcli stats --by-app`
+  log_msg("[memcloud-vm] lazy_init: calling memcloud_init_named\n");
+#ifdef __APPLE__
+  const char *name = getprogname();
+#else
+  const char *name = program_invocation_short_name;
+#endif
+  int connected = memcloud_init_named(name ? name : "intercepted") == 0;
+
+  // The env var wins; otherwise use the node's `memcli vm config` threshold
   const char *env = getenv("MEMCLOUD_MALLOC_THRESHOLD_MB");
+  uint64_t node_mb = 0;
   if (env)
     vm_threshold = (size_t)atoll(env) * 1024 * 1024;
-
-  const char *sock = getenv("MEMCLOUD_SOCKET");
-  log_msg("[memcloud-vm] lazy_init: calling memcloud_init\n");
-  memcloud_init_with_path(sock ? sock : "/tmp/memcloud.sock");
+  else if (connected && memcloud_vm_threshold_mb(&node_mb) == 0 && node_mb > 0)
+    vm_threshold = (size_t)node_mb * 1024 * 1024;
 
   sdk_initialized = 1;
   initializing = 0;
@@ -490,3 +499,26 @@ __attribute__((constructor)) void init_interceptor() {
   install_sigsegv_handler();
   log_msg("[memcloud-vm] constructor end\n");
 }
+
+// Regions the program never freed are released together, in one round trip
+__attribute__((destructor)) void release_remote_regions() {
+  if (!sdk_initialized || !regions)
+    return;
+  uint64_t ids[MAX_REGIONS];
+  size_t n = 0;
+  pthread_mutex_lock(&region_mutex);
+  for (int i = 0; i < MAX_REGIONS; i++) {
+    if (regions[i].active) {
+      ids[n++] = regions[i].region_id;
+      regions[i].active = 0;
+    }
+  }
+  pthread_mutex_unlock(&region_mutex);
+  if (n == 0)
+    return;
+  uint64_t freed = 0;
+  in_hook = 1;
+  memcloud_free_many(ids, n, &freed, NULL);
+  in_hook = 0;
+  log_fmt("[memcloud-vm] released %" PRIu64 " remote regions at exit\n", freed);
+}
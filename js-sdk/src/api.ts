@@ -1,14 +1,15 @@
-import { MemSocket } from './socket';
+import { MemSocket, defaultSocketPath } from './socket';
 import { Readable } from 'stream';
 
 export interface Handle {
     id: string; // Now safe as string
+    size?: number; // Bytes stored, when the node reports it
 }
 
 export class MemCloud {
     private socket: MemSocket;
 
-    constructor(pathOrPort: string | number = '/tmp/memcloud.sock') {
+    constructor(pathOrPort: string | number = defaultSocketPath()) {
         this.socket = new MemSocket(pathOrPort);
     }
 
@@ -33,7 +34,7 @@ export class MemCloud {
 
         if (resp.res === 'Stored') {
             console.log(`Stored Block ID: ${resp.id}`);
-            return { id: resp.id };
+            return { id: resp.id, size: resp.size ?? undefined };
         } else if (resp.res === 'Error') {
             throw new Error(resp.msg);
         }
@@ -43,8 +44,9 @@ export class MemCloud {
     async storeStream(stream: Readable, options?: { chunkSize?: number, target?: string }): Promise<Handle> {
         // 1. Start Stream
         console.log("Starting stream upload...");
-        // size_hint is optional, we pass null/undefined
-        const startResp = await this.socket.request({ cmd: 'StreamStart', size_hint: null });
+        // size_hint is optional, we pass null/undefined. With a target the node
+        // forwards chunks to that peer instead of buffering them.
+        const startResp = await this.socket.request({ cmd: 'StreamStart', size_hint: null, target: options?.target });
 
         if (startResp.res !== 'StreamStarted') {
             throw new Error(startResp.msg || "Failed to start stream: " + JSON.stringify(startResp));
@@ -79,7 +81,7 @@ export class MemCloud {
 
         if (finishResp.res === 'Stored') {
             console.log(`Stored Stream -> Block ID: ${finishResp.id}`);
-            return { id: finishResp.id };
+            return { id: finishResp.id, size: finishResp.size ?? undefined };
         } else if (finishResp.res === 'Error') {
             throw new Error(finishResp.msg);
         }
@@ -131,9 +133,9 @@ export class MemCloud {
         throw new Error("Unknown response: " + JSON.stringify(resp));
     }
 
-    async updatePeerQuota(peerId: string, quota: number): Promise<void> {
-        console.log(`Updating quota for peer ${peerId} to ${quota}...`);
-        const resp = await this.socket.request({ cmd: 'UpdatePeerQuota', peer_id: peerId, quota });
+    /** Move a key's value to another key; the data stays on the node. */
+    async rename(from: string, to: string, overwrite: boolean = false): Promise<void> {
+        const resp = await this.socket.request({ cmd: 'Rename', from, to, overwrite });
         if (resp.res === 'Success') {
             return;
         } else if (resp.res === 'Error') {
@@ -142,6 +144,19 @@ export class MemCloud {
         throw new Error("Unknown response: " + JSON.stringify(resp));
     }
 
+    async updatePeerQuota(peerId: string, quota: number): Promise<{ requested: number, applied: number, acknowledged: boolean }> {
+        console.log(`Updating quota for peer ${peerId} to ${quota}...`);
+        const resp = await this.socket.request({ cmd: 'UpdatePeerQuota', peer_id: peerId, quota });
+        if (resp.res === 'QuotaUpdated') {
+            return { requested: resp.requested, applied: resp.applied, acknowledged: resp.acknowledged };
+        } else if (resp.res === 'Success') {
+            return { requested: quota, applied: quota, acknowledged: false };
+        } else if (resp.res === 'Error') {
+            throw new Error(resp.msg);
+        }
+        throw new Error("Unknown response: " + JSON.stringify(resp));
+    }
+
     async peers(): Promise<string[]> {
         console.log("Listing peers...");
         const resp = await this.socket.request({ cmd: 'ListPeers' });
@@ -157,7 +172,8 @@ export class MemCloud {
     async free(id: string): Promise<void> {
         // id is string
         const resp = await this.socket.request({ cmd: 'Free', id });
-        if (resp.res === 'Success') {
+        // Newer nodes answer Freed with counts, older ones Success
+        if (resp.res === 'Freed' || resp.res === 'Success') {
             return;
         } else if (resp.res === 'Error') {
             throw new Error(resp.msg);
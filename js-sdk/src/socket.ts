@@ -1,12 +1,28 @@
 import * as net from 'net';
+import * as fs from 'fs';
+import * as os from 'os';
+import * as path from 'path';
 import { pack, unpack } from 'msgpackr';
 
+/**
+ * Same search order as the Rust SDK: $MEMCLOUD_SOCKET, else memcloud.sock in
+ * $MEMCLOUD_HOME or ~/.memcloud if a node made one there, else /tmp/memcloud.sock.
+ */
+export function defaultSocketPath(): string {
+    if (process.env.MEMCLOUD_SOCKET) {
+        return process.env.MEMCLOUD_SOCKET;
+    }
+    const dir = process.env.MEMCLOUD_HOME || path.join(os.homedir(), '.memcloud');
+    const perUser = path.join(dir, 'memcloud.sock');
+    return fs.existsSync(perUser) ? perUser : '/tmp/memcloud.sock';
+}
+
 export class MemSocket {
     private client: net.Socket;
     private pathOrPort: string | number;
     private host: string;
 
-    constructor(pathOrPort: string | number = '/tmp/memcloud.sock', host: string = '127.0.0.1') {
+    constructor(pathOrPort: string | number = defaultSocketPath(), host: string = '127.0.0.1') {
         this.pathOrPort = pathOrPort;
         this.host = host;
         this.client = new net.Socket();
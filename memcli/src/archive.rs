@@ -0,0 +1,168 @@
+//! Key-value snapshots for `memcli export` / `memcli import`.
+//!
+//! An archive is [`MAGIC`] followed by records, each a big-endian `u32` length
+//! and a MessagePack-encoded [`Record`]. Records are written and read one at a
+//! time, so neither side holds more than one value in memory.
+
+use anyhow::{bail, Context, Result};
+use memsdk::{Durability, MemCloudClient};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"MEMCKV01";
+
+/// Keys fetched per `list_keys_page` call while exporting.
+const EXPORT_PAGE: u32 = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub key: String,
+    /// `None` when the exporting node did not report it; import then uses `--mode` or pinned.
+    pub durability: Option<Durability>,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+pub struct ArchiveWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut inner: W) -> Result<Self> {
+        inner.write_all(MAGIC)?;
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        let bytes = rmp_serde::to_vec_named(record)?;
+        let len = u32::try_from(bytes.len()).context("Value too large for an archive record")?;
+        self.inner.write_all(&len.to_be_bytes())?;
+        self.inner.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+pub struct ArchiveReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        inner.read_exact(&mut magic).context("Not a memcli archive (too short)")?;
+        if &magic != MAGIC {
+            bail!("Not a memcli archive (bad header)");
+        }
+        Ok(Self { inner })
+    }
+
+    /// The next record, or `None` at the end of the archive.
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        let mut len = [0u8; 4];
+        match self.inner.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.inner.read_exact(&mut buf).context("Archive is truncated")?;
+        Ok(Some(rmp_serde::from_slice(&buf).context("Corrupt archive record")?))
+    }
+}
+
+/// Write every key matching `pattern` to `out`, page by page. Returns the number of records.
+pub async fn export<W: Write>(client: &mut MemCloudClient, pattern: &str, out: W) -> Result<usize> {
+    let mut writer = ArchiveWriter::new(out)?;
+    let mut count = 0;
+    let mut cursor = None;
+    loop {
+        let (keys, next) = client.list_keys_page(pattern, cursor, EXPORT_PAGE).await?;
+        for key in keys {
+            // Keys can expire or be evicted between listing and reading
+            let (data, durability) = match client.get_with_durability(&key, None).await {
+                Ok(loaded) => loaded,
+                Err(e) if e.is_not_found() => {
+                    log::warn!("Skipping '{}': {}", key, e);
+                    continue;
+                }
+                Err(e) => return Err(anyhow::Error::from(e).context(format!("Failed to read '{}'", key))),
+            };
+            writer.write(&Record { key, durability, data })?;
+            count += 1;
+        }
+        match next {
+            Some(c) => cursor = Some(c),
+            None => break,
+        }
+    }
+    writer.finish()?;
+    Ok(count)
+}
+
+/// Replay an archive with `set`, on the node or on `peer`. `mode` overrides the recorded durability.
+pub async fn import<R: Read>(client: &mut MemCloudClient, input: R, peer: Option<String>, mode: Option<Durability>) -> Result<usize> {
+    let mut reader = ArchiveReader::new(input)?;
+    let mut count = 0;
+    while let Some(record) = reader.next_record()? {
+        let durability = mode.or(record.durability).unwrap_or(Durability::Pinned);
+        client.set(&record.key, &record.data, peer.clone(), durability).await
+            .with_context(|| format!("Failed to import '{}'", record.key))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let dir = std::env::temp_dir().join(format!("memcli-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = memnode::testing::spawn_node(&dir, "source", 16 * 1024 * 1024).await;
+        let target = memnode::testing::spawn_node(&dir, "target", 16 * 1024 * 1024).await;
+
+        let mut client = MemCloudClient::connect_with_path(source.socket_path()).await.unwrap();
+        client.set("user:1", b"alice", None, Durability::Pinned).await.unwrap();
+        client.set("user:2", &[0u8; 70_000], None, Durability::Cache).await.unwrap();
+        client.set("empty", b"", None, Durability::Pinned).await.unwrap();
+        client.set("other", b"skip me", None, Durability::Pinned).await.unwrap();
+
+        let mut archive = Vec::new();
+        assert_eq!(export(&mut client, "*r:*", &mut archive).await.unwrap(), 2);
+        assert_eq!(export(&mut client, "e*", &mut Vec::new()).await.unwrap(), 1);
+
+        let mut target_client = MemCloudClient::connect_with_path(target.socket_path()).await.unwrap();
+        assert_eq!(import(&mut target_client, &archive[..], None, Some(Durability::Cache)).await.unwrap(), 2);
+        assert_eq!(target_client.get("user:1", None).await.unwrap(), b"alice");
+        assert_eq!(target_client.get("user:2", None).await.unwrap(), vec![0u8; 70_000]);
+        let mut keys = target_client.list_keys("*").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["user:1", "user:2"]);
+
+        // Without `--mode` each key keeps the durability it was exported with
+        let records: Vec<_> = std::iter::from_fn({
+            let mut reader = ArchiveReader::new(&archive[..]).unwrap();
+            move || reader.next_record().unwrap()
+        }).map(|r| (r.key, r.durability)).collect();
+        assert!(records.contains(&("user:1".to_string(), Some(Durability::Pinned))), "{:?}", records);
+        assert!(records.contains(&("user:2".to_string(), Some(Durability::Cache))), "{:?}", records);
+        assert_eq!(import(&mut target_client, &archive[..], None, None).await.unwrap(), 2);
+        assert_eq!(target_client.get_with_durability("user:1", None).await.unwrap().1, Some(Durability::Pinned));
+        assert_eq!(target_client.get_with_durability("user:2", None).await.unwrap().1, Some(Durability::Cache));
+
+        assert!(import(&mut target_client, &b"garbage!"[..], None, None).await.is_err());
+        assert!(import(&mut target_client, &archive[..archive.len() - 1], None, None).await.is_err());
+
+        drop((client, target_client));
+        source.shutdown().await;
+        target.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,326 @@
+//! `memcli bench`: time set/store/stream round trips against the node and
+//! report latency percentiles, throughput and errors.
+//!
+//! Every worker opens its own connection, so `--concurrency N` means N
+//! requests in flight. Payloads are random and hashed before they are sent;
+//! after a remote bench a sample is read back and compared against the hashes.
+
+use anyhow::{bail, Result};
+use memsdk::{BlockId, Durability, MemCloudClient};
+use rand::RngCore;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::output::Verbosity;
+
+/// Operations read back and compared after a remote bench.
+const VERIFY_SAMPLE: usize = 10;
+
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BenchMode {
+    /// `set` under a generated key
+    Set,
+    /// `store` as a block
+    Store,
+    /// `stream` upload in chunks
+    Stream,
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchPlan {
+    pub mode: BenchMode,
+    pub size: u64,
+    pub count: usize,
+    pub concurrency: usize,
+    pub peer: Option<String>,
+}
+
+/// Where to reach the node; every worker connects on its own.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    pub endpoint: crate::context::Endpoint,
+    pub timeout: Option<Duration>,
+}
+
+impl Connector {
+    pub async fn connect(&self) -> Result<MemCloudClient> {
+        crate::connect_client(&self.endpoint, self.timeout).await
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub mode: BenchMode,
+    pub peer: Option<String>,
+    pub size: u64,
+    /// Operations asked for, and run after trimming to the space available
+    pub requested: usize,
+    pub count: usize,
+    pub concurrency: usize,
+    pub ok: usize,
+    pub errors: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_error: Option<String>,
+    pub elapsed_secs: f64,
+    pub throughput_mb_s: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Remote benches: operations read back, and how many came back different or not at all
+    pub verified: usize,
+    pub mismatched: usize,
+}
+
+// Something an operation created, so it can be read back and cleaned up
+#[derive(Clone, Debug)]
+enum Created {
+    Key(String),
+    Block(BlockId),
+}
+
+struct Op {
+    created: Created,
+    hash: blake3::Hash,
+    latency: Duration,
+}
+
+/// A finished run: its report, plus what it left on the node for `cleanup`.
+pub struct BenchRun {
+    pub report: BenchReport,
+    run_id: String,
+    created: Vec<Created>,
+}
+
+/// Cut `plan.count` down to what fits in the node's free memory (or the
+/// quota `plan.peer` granted us). Returns a warning when it was trimmed.
+pub async fn fit_plan(client: &mut MemCloudClient, plan: &mut BenchPlan) -> Result<Option<String>> {
+    if plan.size == 0 {
+        bail!("Payload size must be at least 1 byte");
+    }
+    let (room, whose) = match &plan.peer {
+        None => (client.node_stats().await?.free_space, "the node's free memory".to_string()),
+        Some(target) => {
+            let peers = client.list_peers().await?;
+            let lower = target.to_lowercase();
+            let Some(peer) = peers.iter().find(|p| p.id == *target || p.name.to_lowercase() == lower || p.id.starts_with(target.as_str())) else {
+                bail!("Peer '{}' not found", target);
+            };
+            (peer.quota, format!("the quota {} granted", peer.name))
+        }
+    };
+    let fits = usize::try_from(room / plan.size).unwrap_or(usize::MAX);
+    if fits == 0 {
+        bail!("Not even one {} payload fits in {} ({})", crate::format_bytes(plan.size), whose, crate::format_bytes(room));
+    }
+    if plan.count <= fits {
+        return Ok(None);
+    }
+    let warning = format!("⚠️  {} x {} would exceed {} ({}); running {} operations",
+        plan.count, crate::format_bytes(plan.size), whose, crate::format_bytes(room), fits);
+    plan.count = fits;
+    Ok(Some(warning))
+}
+
+/// Run `plan` (already fitted) with `plan.concurrency` connections.
+pub async fn run(connector: &Connector, plan: &BenchPlan, requested: usize) -> Result<BenchRun> {
+    let run_id = format!("{:08x}", rand::random::<u32>());
+    let next = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let mut workers = Vec::new();
+    for _ in 0..plan.concurrency.min(plan.count) {
+        let mut client = connector.connect().await?;
+        let (next, plan, run_id) = (next.clone(), plan.clone(), run_id.clone());
+        workers.push(tokio::spawn(async move {
+            let mut results = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= plan.count {
+                    return results;
+                }
+                results.push(run_one(&mut client, &plan, &run_id, i).await);
+            }
+        }));
+    }
+    let mut results = Vec::with_capacity(plan.count);
+    for worker in workers {
+        results.extend(worker.await?);
+    }
+    let elapsed = started.elapsed();
+
+    let mut ops = Vec::new();
+    let mut first_error = None;
+    for result in results {
+        match result {
+            Ok(op) => ops.push(op),
+            Err(e) => {
+                first_error.get_or_insert(e.to_string());
+            }
+        }
+    }
+    let errors = plan.count - ops.len();
+
+    let (verified, mismatched) = if plan.peer.is_some() && !ops.is_empty() {
+        verify(&mut connector.connect().await?, &ops, plan.peer.clone()).await
+    } else {
+        (0, 0)
+    };
+
+    let mut latencies: Vec<Duration> = ops.iter().map(|op| op.latency).collect();
+    latencies.sort_unstable();
+    let bytes = plan.size as f64 * ops.len() as f64;
+    let report = BenchReport {
+        mode: plan.mode,
+        peer: plan.peer.clone(),
+        size: plan.size,
+        requested,
+        count: plan.count,
+        concurrency: plan.concurrency,
+        ok: ops.len(),
+        errors,
+        first_error,
+        elapsed_secs: elapsed.as_secs_f64(),
+        throughput_mb_s: bytes / (1024.0 * 1024.0) / elapsed.as_secs_f64().max(f64::EPSILON),
+        p50_ms: percentile_ms(&latencies, 50.0),
+        p95_ms: percentile_ms(&latencies, 95.0),
+        p99_ms: percentile_ms(&latencies, 99.0),
+        verified,
+        mismatched,
+    };
+    Ok(BenchRun { report, run_id, created: ops.into_iter().map(|op| op.created).collect() })
+}
+
+async fn run_one(client: &mut MemCloudClient, plan: &BenchPlan, run_id: &str, i: usize) -> Result<Op> {
+    let mut data = vec![0u8; plan.size as usize];
+    rand::thread_rng().fill_bytes(&mut data);
+    let hash = blake3::hash(&data);
+    let start = Instant::now();
+    let created = match plan.mode {
+        BenchMode::Set => {
+            let key = format!("bench:{}:{}", run_id, i);
+            client.set(&key, &data, plan.peer.clone(), Durability::Pinned).await?;
+            Created::Key(key)
+        }
+        BenchMode::Store if plan.peer.is_some() => Created::Block(client.store_remote(&data, plan.peer.clone(), Durability::Pinned, false).await?.0),
+        BenchMode::Store => Created::Block(client.store(&data, Durability::Pinned).await?),
+        BenchMode::Stream => Created::Block(client.stream_data(&data[..], Some(plan.size), plan.peer.clone()).await?),
+    };
+    Ok(Op { created, hash, latency: start.elapsed() })
+}
+
+// Read back up to VERIFY_SAMPLE operations spread over the run. Returns how
+// many were checked and how many did not come back intact.
+async fn verify(client: &mut MemCloudClient, ops: &[Op], peer: Option<String>) -> (usize, usize) {
+    let step = ops.len().div_ceil(VERIFY_SAMPLE);
+    let mut checked = 0;
+    let mut mismatched = 0;
+    for op in ops.iter().step_by(step) {
+        let data = match &op.created {
+            Created::Key(key) => client.get(key, peer.clone()).await,
+            Created::Block(id) => client.load(*id).await,
+        };
+        checked += 1;
+        if !data.is_ok_and(|d| blake3::hash(&d) == op.hash) {
+            mismatched += 1;
+        }
+    }
+    (checked, mismatched)
+}
+
+/// Free everything `run` created. Returns how many entries failed to go.
+pub async fn cleanup(client: &mut MemCloudClient, run: &BenchRun, peer: Option<String>) -> usize {
+    let mut failed = 0;
+    if run.created.iter().any(|c| matches!(c, Created::Key(_))) {
+        let pattern = format!("bench:{}:*", run.run_id);
+        if let Err(e) = client.flush_filtered(peer, Some(pattern), None).await {
+            log::warn!("Failed to flush bench keys: {}", e);
+            failed += run.created.len();
+        }
+    }
+    for created in &run.created {
+        if let Created::Block(id) = created {
+            if let Err(e) = client.free(*id).await {
+                log::warn!("Failed to free block {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+// Nearest-rank percentile of sorted latencies, in milliseconds
+fn percentile_ms(sorted: &[Duration], pct: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1].as_secs_f64() * 1000.0
+}
+
+pub fn print_report(report: &BenchReport, out: Verbosity) {
+    let target = report.peer.as_deref().map(|p| format!(" on {}", p)).unwrap_or_default();
+    status!(out, "📊 Bench: {} {} x {}{}, concurrency {}", format!("{:?}", report.mode).to_lowercase(), report.count,
+        crate::format_bytes(report.size), target, report.concurrency);
+    println!("   Ops:        {} ok, {} errors", report.ok, report.errors);
+    println!("   Throughput: {:.1} MB/s ({:.2}s)", report.throughput_mb_s, report.elapsed_secs);
+    println!("   Latency:    p50 {:.2}ms  p95 {:.2}ms  p99 {:.2}ms", report.p50_ms, report.p95_ms, report.p99_ms);
+    if report.verified > 0 {
+        println!("   Verified:   {} of {} read back intact", report.verified - report.mismatched, report.verified);
+    }
+    if let Some(e) = &report.first_error {
+        eprintln!("   First error: {}", e);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_use_nearest_rank() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile_ms(&sorted, 50.0), 50.0);
+        assert_eq!(percentile_ms(&sorted, 99.0), 99.0);
+        assert_eq!(percentile_ms(&sorted[..1], 95.0), 1.0);
+        assert_eq!(percentile_ms(&[], 50.0), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_bench_trims_to_free_memory_and_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("memcli-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let node = memnode::testing::spawn_node(&dir, "bench", 1024 * 1024).await;
+        let peer = memnode::testing::spawn_node(&dir, "bench-peer", 1024 * 1024).await;
+        let connector = Connector { endpoint: crate::context::Endpoint::socket(node.socket_path(), None), timeout: None };
+        let mut client = connector.connect().await.unwrap();
+        let mut peer_client = MemCloudClient::connect_with_path(peer.socket_path()).await.unwrap();
+        let peer_addr = format!("127.0.0.1:{}", peer.port());
+        client.connect_peer(&peer_addr, None).await.unwrap();
+        while client.poll_connection(&peer_addr).await.unwrap().0 != "connected" {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        for (mode, target) in [BenchMode::Set, BenchMode::Store, BenchMode::Stream].into_iter()
+            .flat_map(|mode| [(mode, None), (mode, Some("bench-peer".to_string()))])
+        {
+            let mut plan = BenchPlan { mode, size: 200 * 1024, count: 8, concurrency: 3, peer: target.clone() };
+            assert!(fit_plan(&mut client, &mut plan).await.unwrap().is_some());
+            assert_eq!(plan.count, 5);
+
+            let run = run(&connector, &plan, 8).await.unwrap();
+            assert_eq!((run.report.ok, run.report.errors), (5, 0), "{:?} {:?}", mode, run.report.first_error);
+            assert!(run.report.p50_ms <= run.report.p95_ms && run.report.p95_ms <= run.report.p99_ms);
+            let verified = if target.is_some() { 5 } else { 0 };
+            assert_eq!((run.report.verified, run.report.mismatched), (verified, 0), "{:?}", mode);
+            assert_eq!(cleanup(&mut client, &run, target).await, 0);
+            assert_eq!(client.node_stats().await.unwrap().memory_usage, 0, "{:?}", mode);
+            assert_eq!(peer_client.node_stats().await.unwrap().memory_usage, 0, "{:?}", mode);
+        }
+        drop((client, peer_client));
+        node.shutdown().await;
+        peer.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
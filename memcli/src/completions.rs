@@ -0,0 +1,117 @@
+//! Shell completion scripts, the man page, and the live candidates those
+//! scripts ask for (`memcli __complete ...`) while the user is typing.
+
+use clap::ValueEnum;
+use clap_complete::Shell;
+use crate::context::Endpoint;
+use memsdk::MemCloudClient;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Completion must never hang the shell, even if the daemon is wedged.
+const COMPLETE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Cap on key candidates so a huge keyspace does not flood the terminal.
+const MAX_KEY_CANDIDATES: u32 = 200;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CandidateKind {
+    /// Names of connected peers (for --peer)
+    Peers,
+    /// Keys starting with the given prefix (for get)
+    Keys,
+}
+
+// Appended to the generated bash script: answers `--peer` and the key of
+// `get` from the running node, falling back to the static completions.
+const BASH_DYNAMIC: &str = r#"
+_memcli_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "$prev" == "--peer" ]]; then
+        COMPREPLY=( $(compgen -W "$(memcli __complete peers 2>/dev/null)" -- "$cur") )
+        return 0
+    fi
+    if [[ "${COMP_WORDS[1]}" == "get" && $COMP_CWORD -eq 2 && "$cur" != -* ]]; then
+        COMPREPLY=( $(memcli __complete keys "$cur" 2>/dev/null) )
+        return 0
+    fi
+    _memcli "$@"
+}
+complete -F _memcli_dynamic -o bashdefault -o default memcli
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+complete -c memcli -l peer -f -a "(memcli __complete peers 2>/dev/null)"
+complete -c memcli -n "__fish_seen_subcommand_from get" -f -a "(memcli __complete keys (commandline -ct) 2>/dev/null)"
+"#;
+
+pub fn print_completions(shell: Shell, cmd: &mut clap::Command) {
+    let mut out = io::stdout();
+    clap_complete::generate(shell, cmd, "memcli", &mut out);
+    // zsh and PowerShell only get the static completions
+    let dynamic = match shell {
+        Shell::Bash => BASH_DYNAMIC,
+        Shell::Fish => FISH_DYNAMIC,
+        _ => "",
+    };
+    let _ = out.write_all(dynamic.as_bytes());
+}
+
+pub fn print_man(cmd: clap::Command) -> anyhow::Result<()> {
+    clap_mangen::Man::new(cmd).render(&mut io::stdout())?;
+    Ok(())
+}
+
+/// Print one candidate per line. Prints nothing at all (no errors, no emoji)
+/// when the node is unreachable or slow, since the output goes straight into
+/// the shell's completion list.
+pub async fn print_candidates(kind: CandidateKind, prefix: &str, endpoint: &Endpoint) {
+    let lookup = async {
+        let mut client = MemCloudClient::connect_endpoint(&endpoint.address).await?;
+        if let Some(token) = &endpoint.token {
+            client.authenticate(token).await?;
+        }
+        let candidates: Vec<String> = match kind {
+            CandidateKind::Peers => client.list_peers().await?
+                .into_iter()
+                .map(|p| p.name)
+                .filter(|name| name.starts_with(prefix))
+                .collect(),
+            CandidateKind::Keys => {
+                let pattern = format!("{}*", escape_glob(prefix));
+                client.list_keys_page(&pattern, None, MAX_KEY_CANDIDATES).await?.0
+            }
+        };
+        anyhow::Ok(candidates)
+    };
+
+    if let Ok(Ok(candidates)) = tokio::time::timeout(COMPLETE_TIMEOUT, lookup).await {
+        for candidate in candidates {
+            println!("{}", candidate);
+        }
+    }
+}
+
+// Treat the typed prefix literally in the key pattern
+fn escape_glob(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escaped_prefix_matches_literally() {
+        let pattern = format!("{}*", escape_glob("a*[b]?"));
+        assert!(memsdk::glob::glob_match(&pattern, "a*[b]?suffix"));
+        assert!(!memsdk::glob::glob_match(&pattern, "aXbY"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Named connections to nodes (`memcli context`), kept in `contexts.toml` in
+//! the data directory. Each maps a name to a socket path or `tcp://host:port`
+//! endpoint and an optional RPC token; commands use the one picked with
+//! `--context`, else the one `context use` made current.
+
+use anyhow::{bail, Context as _};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::data_dir::DataDir;
+
+/// Name that `context use` takes to go back to the default socket.
+pub const DEFAULT_CONTEXT: &str = "default";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeContext {
+    /// A socket path, or `tcp://host:port`
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Contexts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+    #[serde(default)]
+    pub contexts: BTreeMap<String, NodeContext>,
+}
+
+/// Where a command connects, and the context it came from, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub address: String,
+    pub token: Option<String>,
+    pub context: Option<String>,
+}
+
+impl Endpoint {
+    pub fn socket(address: impl Into<String>, token: Option<String>) -> Self {
+        Self { address: address.into(), token, context: None }
+    }
+
+    /// The socket path, for what only works over a Unix socket.
+    pub fn unix_path(&self) -> anyhow::Result<&str> {
+        if self.address.starts_with(memsdk::TCP_ENDPOINT_PREFIX) {
+            bail!("{} is a TCP endpoint; this command needs a node's Unix socket", self.describe());
+        }
+        Ok(&self.address)
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.context {
+            Some(name) => format!("context '{}' ({})", name, self.address),
+            None => self.address.clone(),
+        }
+    }
+}
+
+fn contexts_file(dir: &DataDir) -> PathBuf {
+    dir.path().join("contexts.toml")
+}
+
+impl Contexts {
+    pub fn load(dir: &DataDir) -> anyhow::Result<Self> {
+        let path = contexts_file(dir);
+        match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).with_context(|| format!("Invalid contexts file {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read contexts file {:?}", path)),
+        }
+    }
+
+    // Tokens are kept here, so only the owner may read the file. It is written
+    // to a temporary file created private and renamed over the old one, so the
+    // tokens are never readable by others, not even for a moment.
+    pub fn save(&self, dir: &DataDir) -> anyhow::Result<()> {
+        let path = contexts_file(dir);
+        fs::create_dir_all(dir.path())?;
+        let text = toml::to_string_pretty(self)?;
+        let tmp = path.with_extension("toml.tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let res = options.open(&tmp)
+            .and_then(|mut file| {
+                use std::io::Write;
+                file.write_all(text.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp, &path));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res.with_context(|| format!("Failed to write contexts file {:?}", path))
+    }
+
+    /// The endpoint of context `name`; a `token` given on the command line wins over its own.
+    pub fn endpoint(&self, name: &str, token: Option<String>) -> anyhow::Result<Endpoint> {
+        let Some(ctx) = self.contexts.get(name) else {
+            bail!("No context named '{}'; `memcli context list` shows the configured ones", name);
+        };
+        Ok(Endpoint { address: ctx.endpoint.clone(), token: token.or(ctx.token.clone()), context: Some(name.to_string()) })
+    }
+
+    pub fn current_endpoint(&self, token: Option<String>) -> anyhow::Result<Option<Endpoint>> {
+        self.current.as_deref().map(|name| self.endpoint(name, token)).transpose()
+    }
+}
+
+/// Check that `endpoint` is a socket path or `tcp://host:port`.
+pub fn check_endpoint(endpoint: &str) -> anyhow::Result<()> {
+    if endpoint.is_empty() {
+        bail!("The endpoint must be a socket path or tcp://host:port");
+    }
+    if let Some(addr) = endpoint.strip_prefix(memsdk::TCP_ENDPOINT_PREFIX) {
+        let port = addr.rsplit_once(':').map(|(host, port)| (host, port.parse::<u16>()));
+        if !matches!(port, Some((host, Ok(_))) if !host.is_empty()) {
+            bail!("'{}' is not a TCP endpoint; use tcp://host:port", endpoint);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contexts_round_trip_and_resolve() {
+        let dir = DataDir::resolve(Some(&std::env::temp_dir().join(format!("memcli-ctx-{}", rand::random::<u64>())))).unwrap();
+        assert!(Contexts::load(&dir).unwrap().contexts.is_empty());
+
+        let mut contexts = Contexts::default();
+        contexts.contexts.insert("home".to_string(), NodeContext { endpoint: "tcp://127.0.0.1:17070".to_string(), token: Some("t0k".to_string()) });
+        contexts.contexts.insert("laptop".to_string(), NodeContext { endpoint: "/tmp/memcloud.sock".to_string(), token: None });
+        contexts.current = Some("home".to_string());
+        contexts.save(&dir).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(contexts_file(&dir)).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        // Saving again replaces the file
+        contexts.save(&dir).unwrap();
+
+        let contexts = Contexts::load(&dir).unwrap();
+        let home = contexts.current_endpoint(None).unwrap().unwrap();
+        assert_eq!((home.token.as_deref(), home.context.as_deref()), (Some("t0k"), Some("home")));
+        assert!(home.unix_path().unwrap_err().to_string().contains("context 'home' (tcp://127.0.0.1:17070)"));
+        let laptop = contexts.endpoint("laptop", Some("flag".to_string())).unwrap();
+        assert_eq!((laptop.unix_path().unwrap(), laptop.token.as_deref()), ("/tmp/memcloud.sock", Some("flag")));
+        assert!(contexts.endpoint("nope", None).is_err());
+
+        assert!(check_endpoint("tcp://server:7070").is_ok());
+        assert!(check_endpoint("tcp://server").is_err());
+        assert!(check_endpoint("tcp://:7070").is_err());
+        fs::remove_dir_all(dir.path()).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+//! Where `node start` keeps the daemon's PID, port and log files:
+//! `--data-dir`, else `$MEMCLOUD_HOME`, else `~/.memcloud`. The node is
+//! started with `MEMCLOUD_HOME` set to the same directory so its own state
+//! (identity and trusted devices) ends up next to them.
+
+use anyhow::Context;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DATA_DIR_ENV: &str = memsdk::DATA_DIR_ENV;
+
+pub struct DataDir(PathBuf);
+
+impl DataDir {
+    pub fn resolve(flag: Option<&Path>) -> anyhow::Result<Self> {
+        if let Some(dir) = flag {
+            return Ok(Self(dir.to_path_buf()));
+        }
+        if let Some(dir) = std::env::var_os(DATA_DIR_ENV).filter(|d| !d.is_empty()) {
+            return Ok(Self(PathBuf::from(dir)));
+        }
+        let home = dirs::home_dir()
+            .with_context(|| format!("No home directory to keep MemCloud files in; set {} or pass --data-dir", DATA_DIR_ENV))?;
+        Ok(Self(home.join(".memcloud")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn pid_file(&self) -> PathBuf {
+        self.0.join("memnode.pid")
+    }
+
+    /// Transport port the running node actually bound, recorded by `node start`.
+    pub fn port_file(&self) -> PathBuf {
+        self.0.join("memnode.port")
+    }
+
+    /// Where a node started with this directory listens by default.
+    pub fn socket_file(&self) -> PathBuf {
+        self.0.join(memsdk::SOCKET_FILE)
+    }
+
+    /// Read by the node at startup when `node start` is not given `--config`.
+    pub fn config_file(&self) -> PathBuf {
+        self.0.join("config.toml")
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.0.join("memnode.log")
+    }
+
+    pub fn read_port(&self) -> Option<u16> {
+        fs::read_to_string(self.port_file()).ok()?.trim().parse().ok()
+    }
+
+    pub fn read_pid(&self) -> Option<i32> {
+        fs::read_to_string(self.pid_file()).ok()?.trim().parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memcloud_home_overrides_home() {
+        let tmp = std::env::temp_dir().join(format!("memcloud-home-{}", std::process::id()));
+        std::env::set_var(DATA_DIR_ENV, &tmp);
+        let dir = DataDir::resolve(None).unwrap();
+        std::env::remove_var(DATA_DIR_ENV);
+
+        assert_eq!(dir.pid_file(), tmp.join("memnode.pid"));
+        assert_eq!(dir.log_file(), tmp.join("memnode.log"));
+        assert_eq!(dir.read_pid(), None);
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(dir.pid_file(), "4242\n").unwrap();
+        assert_eq!(dir.read_pid(), Some(4242));
+        fs::remove_dir_all(&tmp).unwrap();
+
+        // The flag wins over the environment
+        let flag = tmp.join("flag");
+        assert_eq!(DataDir::resolve(Some(&flag)).unwrap().port_file(), flag.join("memnode.port"));
+    }
+}
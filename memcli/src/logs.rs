@@ -0,0 +1,165 @@
+//! Reading the node log without shelling out to `tail`, so it works on every platform.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often `--follow` checks the file for new data.
+pub const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lines are kept if they contain `pattern` (plain substring, case-sensitive).
+pub fn matches(line: &str, pattern: Option<&str>) -> bool {
+    pattern.is_none_or(|p| line.contains(p))
+}
+
+/// Error-level log records (text or `--log-format json`), plus the `Error: ...`
+/// memnode prints when it exits on a fatal error.
+pub fn is_error_line(line: &str) -> bool {
+    line.contains(" ERROR ") || line.contains("\"level\":\"ERROR\"") || line.starts_with("Error:")
+}
+
+/// The last `n` lines of the file that match `pattern`.
+pub fn tail_lines(path: &Path, n: usize, pattern: Option<&str>) -> anyhow::Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut last = VecDeque::with_capacity(n.min(4096));
+    for line in reader.lines() {
+        // The log may contain partial writes; skip what is not valid UTF-8
+        let Ok(line) = line else { continue };
+        if !matches(&line, pattern) {
+            continue;
+        }
+        if last.len() == n {
+            last.pop_front();
+        }
+        if n > 0 {
+            last.push_back(line);
+        }
+    }
+    Ok(last.into())
+}
+
+/// Every line mentioning request `id`, in the log and the generations rotated
+/// out of it (`path.1`, `path.2`, ...), oldest first.
+pub fn trace_lines(path: &Path, id: &str) -> anyhow::Result<Vec<String>> {
+    let mut files = vec![path.to_path_buf()];
+    loop {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{}", files.len()));
+        let rotated = PathBuf::from(name);
+        if !rotated.exists() {
+            break;
+        }
+        files.push(rotated);
+    }
+    let mut found = Vec::new();
+    for file in files.iter().rev().filter(|f| f.exists()) {
+        found.extend(tail_lines(file, usize::MAX, Some(id))?);
+    }
+    Ok(found)
+}
+
+/// Tracks the end of a growing file and hands out complete lines appended to it.
+/// Starts over from the beginning when the file shrinks (rotated or truncated).
+pub struct Follower {
+    path: PathBuf,
+    file: File,
+    pos: u64,
+    partial: String,
+}
+
+impl Follower {
+    /// Start following from the current end of the file.
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let mut file = File::open(path)?;
+        let pos = file.seek(SeekFrom::End(0))?;
+        Ok(Self { path: path.to_path_buf(), file, pos, partial: String::new() })
+    }
+
+    /// Complete lines written since the last call.
+    pub fn poll(&mut self) -> anyhow::Result<Vec<String>> {
+        let len = match fs::metadata(&self.path) {
+            Ok(m) => m.len(),
+            // Between the rename and the node reopening its log
+            Err(_) => return Ok(Vec::new()),
+        };
+        if len < self.pos {
+            self.file = File::open(&self.path)?;
+            self.pos = 0;
+            self.partial.clear();
+        }
+        if len == self.pos {
+            return Ok(Vec::new());
+        }
+
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let mut buf = Vec::new();
+        let read = (&mut self.file).take(len - self.pos).read_to_end(&mut buf)?;
+        self.pos += read as u64;
+        self.partial.push_str(&String::from_utf8_lossy(&buf));
+
+        let mut lines: Vec<String> = self.partial.split('\n').map(|l| l.trim_end_matches('\r').to_string()).collect();
+        // The last piece has no newline yet; keep it for the next poll
+        self.partial = lines.pop().unwrap_or_default();
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_log(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("memcli-{}-{}.log", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_tail_returns_last_n_lines() {
+        let path = temp_log("tail");
+        let content: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        fs::write(&path, content).unwrap();
+
+        assert_eq!(tail_lines(&path, 3, None).unwrap(), vec!["line 98", "line 99", "line 100"]);
+        assert_eq!(tail_lines(&path, 2, Some("line 5")).unwrap(), vec!["line 58", "line 59"]);
+        assert_eq!(tail_lines(&path, 500, None).unwrap().len(), 100);
+        assert!(tail_lines(&path, 0, None).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_trace_reads_rotated_generations_oldest_first() {
+        let path = temp_log("trace");
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+        fs::write(rotated(2), "rpc{request_id=\"ab12\"} oldest\nother\n").unwrap();
+        fs::write(rotated(1), "{\"span\":{\"request_id\":\"ab12\"}} middle\n").unwrap();
+        fs::write(&path, "peer{request_id=\"ab12\"} newest\nrpc{request_id=\"cd34\"}\n").unwrap();
+
+        let lines = trace_lines(&path, "ab12").unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("oldest") && lines[1].ends_with("middle") && lines[2].ends_with("newest"));
+        for file in [path.clone(), rotated(1), rotated(2)] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_follower_sees_appends_and_rotation() {
+        let path = temp_log("follow");
+        fs::write(&path, "old line\n").unwrap();
+        let mut follower = Follower::new(&path).unwrap();
+        assert!(follower.poll().unwrap().is_empty());
+
+        let mut f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(f, "first\nsecond\npart").unwrap();
+        assert_eq!(follower.poll().unwrap(), vec!["first", "second"]);
+        writeln!(f, "ial").unwrap();
+        assert_eq!(follower.poll().unwrap(), vec!["partial"]);
+
+        // Rotation replaces the file with a shorter one
+        fs::write(&path, "fresh\n").unwrap();
+        assert_eq!(follower.poll().unwrap(), vec!["fresh"]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,20 @@
-use clap::{Parser, Subcommand};
+#[macro_use]
+mod output;
+mod archive;
+mod bench;
+mod completions;
+mod context;
+mod data_dir;
+mod logs;
+mod value;
+
+use anyhow::Context;
+use clap::{CommandFactory, Parser, Subcommand};
 use memsdk::MemCloudClient;
-use std::time::Instant;
+use context::Endpoint;
+use data_dir::DataDir;
+use output::{show_id, Verbosity};
+use std::time::{Duration, Instant};
 use std::fs;
 use std::process::{Command, Stdio};
 use std::path::PathBuf;
@@ -14,25 +28,6 @@ use nix::unistd::Pid;
 #[cfg(windows)]
 use sysinfo::{System, Pid as SysPid};
 
-fn get_memcloud_dir() -> PathBuf {
-    let home = dirs::home_dir().expect("Could not find home directory");
-    home.join(".memcloud")
-}
-
-fn get_pid_file() -> PathBuf {
-    get_memcloud_dir().join("memnode.pid")
-}
-
-fn read_pid() -> Option<i32> {
-    let pid_file = get_pid_file();
-    if pid_file.exists() {
-        if let Ok(content) = fs::read_to_string(&pid_file) {
-            return content.trim().parse().ok();
-        }
-    }
-    None
-}
-
 fn is_process_running(pid: i32) -> bool {
     #[cfg(unix)]
     {
@@ -55,23 +50,73 @@ fn kill_process(pid: i32) -> anyhow::Result<()> {
     }
     #[cfg(windows)]
     {
-        let mut sys = System::new_all();
-        sys.refresh_processes();
-        if let Some(process) = sys.process(SysPid::from(pid as usize)) {
-             process.kill();
+        // /T takes the node's child processes along
+        let status = Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !status.success() && is_process_running(pid) {
+            anyhow::bail!("taskkill could not stop process {} ({})", pid, status);
         }
         Ok(())
     }
 }
 
+// Process creation flags for `node start` on Windows
+#[cfg(windows)]
+const DETACHED_PROCESS: u32 = 0x0000_0008;
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
 #[derive(Parser)]
 #[command(author = "Vibhanshu Garg <v2001.garg@gmail.com>", version, about = "MemCloud CLI - Manage your distributed in-memory data store", long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(short, long, default_value = "/tmp/memcloud.sock")]
-    socket: String,
+    /// Node socket [default: $MEMCLOUD_SOCKET, else the current context, else memcloud.sock
+    /// in the data directory if a node made one there, else /tmp/memcloud.sock]. On Windows
+    /// nodes only listen on TCP, so this is tcp://host:port [default: tcp://127.0.0.1:7070]
+    #[arg(short, long)]
+    socket: Option<String>,
+
+    /// Connect through this context instead of the current one (see `memcli context`)
+    #[arg(long, global = true)]
+    context: Option<String>,
+
+    /// Token for nodes started with --rpc-token (or --rpc-write-token / --rpc-read-token)
+    #[arg(long, global = true)]
+    rpc_token: Option<String>,
+
+    /// Print only results and errors (e.g. just the block ID for `store`)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print diagnostics (resolved targets, peer keys) and debug logs
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Print block IDs as decimal numbers instead of blk_..., for older scripts
+    #[arg(long, global = true)]
+    raw_ids: bool,
+
+    /// Encrypt data before it leaves this machine (store/set/stream) and decrypt
+    /// it on load/get. Prompts for the passphrase unless --passphrase-file is given
+    #[arg(long, global = true)]
+    encrypt: bool,
+
+    /// Read the encryption passphrase from this file (implies --encrypt)
+    #[arg(long, global = true)]
+    passphrase_file: Option<PathBuf>,
+
+    /// Give up on any request the node has not answered within SECS seconds
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Directory for the node's PID, port and log files [default: $MEMCLOUD_HOME or ~/.memcloud]
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -90,17 +135,85 @@ enum Commands {
         /// Optional: Target specific peer by name or ID
         #[arg(long)]
         peer: Option<String>,
-        /// Durability mode: 'pinned' (default) or 'cache'
+        /// Durability mode: 'pinned' (default), 'cache', or 'ttl:<secs>'
         #[arg(long, default_value = "pinned")]
         mode: String,
+        /// Derive the ID from the data; storing the same data again returns the same ID
+        #[arg(long, conflicts_with_all = ["remote", "peer"])]
+        content_addressed: bool,
+        /// With --remote or --peer: store locally if no peer takes the data
+        #[arg(long)]
+        fallback: bool,
+    },
+    /// Write matching keys and their values to an archive file
+    Export {
+        /// Only keys matching this pattern
+        #[arg(long, default_value = "*")]
+        pattern: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Set every key in an archive written by `export`
+    Import {
+        #[arg(long = "in", value_name = "FILE")]
+        input: PathBuf,
+        /// Store the keys on this peer instead of the local node
+        #[arg(long)]
+        peer: Option<String>,
+        /// Durability for imported keys: 'pinned', 'cache' or 'ttl:<secs>' (default: as recorded, else pinned)
+        #[arg(long)]
+        mode: Option<String>,
+    },
+    /// Move a key or block between this node and a peer without downloading it
+    Migrate {
+        /// Key or block ID
+        target: String,
+        /// Peer to move it to (default: the peer with the most free quota)
+        #[arg(long)]
+        to: Option<String>,
+        /// Peer to bring it back from
+        #[arg(long, conflicts_with = "to")]
+        from: Option<String>,
+        /// Copy instead of move: keep the source copy
+        #[arg(long)]
+        keep_local: bool,
+        /// Treat TARGET as a key even if it looks like a block ID
+        #[arg(long)]
+        key: bool,
     },
     /// Load a block by ID (as string)
     Load {
-        id: String, // Updated to String
+        /// Block ID, as blk_... or in decimal
+        id: memsdk::ShortId,
+        #[command(flatten)]
+        output: value::ValueOutput,
     },
-    /// Free a block by ID
+    /// Free blocks by ID, or the blocks of the keys matching a pattern
     Free {
-        id: String,
+        #[arg(required_unless_present = "pattern")]
+        ids: Vec<memsdk::ShortId>,
+        /// Also remove the keys matching this glob and free their blocks, e.g. 'tmp:*'
+        #[arg(long)]
+        pattern: Option<String>,
+    },
+    /// Pin a stored block by ID so it is never evicted
+    Pin {
+        id: memsdk::ShortId,
+    },
+    /// Let a pinned block be evicted again, like a cache block
+    Unpin {
+        id: memsdk::ShortId,
+    },
+    /// Change a block's eviction priority: lower priorities are evicted first
+    Touch {
+        /// Key or block ID
+        target: String,
+        /// 0 to 255; blocks stored without one have 128
+        #[arg(long)]
+        priority: u8,
+        /// Treat TARGET as a key even if it looks like a block ID
+        #[arg(long)]
+        key: bool,
     },
     /// Manage peers (list, update, disconnect)
     Peer {
@@ -108,18 +221,64 @@ enum Commands {
         action: PeerAction,
     },
     Peers,
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Connect {
-        addr: String,
+        #[command(subcommand)]
+        action: Option<ConnectAction>,
+        /// Peer address: ip:port, or host:port with a DNS or mDNS name (laptop.local:8080).
+        /// A name with several addresses is tried one address at a time
+        #[arg(required = true)]
+        addr: Option<String>,
         /// How much of YOUR memory capacity to offer this peer (e.g., "512mb", "1gb")
         /// This is the maximum they can store on your node.
         #[arg(long, short = 'o')]
         offer_storage: Option<String>,
+        /// Abort a connection attempt to ADDR that is still in progress
+        #[arg(long, conflicts_with = "offer_storage")]
+        cancel: bool,
+        /// Connect even though the peer presents a different key than last time
+        /// (e.g. it was reinstalled), and remember the new key
+        #[arg(long, conflicts_with = "cancel")]
+        accept_new_key: bool,
+        /// Start the connection and return at once; check on it with `connect status`
+        #[arg(long, conflicts_with = "cancel")]
+        no_wait: bool,
+    },
+    /// Show what happened on the node: peers, consent, trust, evictions, quota, flushes
+    Events {
+        /// Only these kinds, comma separated: peer, consent, trust, eviction, quota, flush, shutdown
+        #[arg(long, value_delimiter = ',')]
+        kind: Vec<String>,
+        /// Only events after this sequence number
+        #[arg(long)]
+        since: Option<u64>,
+        /// Show at most this many
+        #[arg(long, default_value_t = 50)]
+        limit: u64,
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+        /// Print one JSON object per event
+        #[arg(long)]
+        json: bool,
     },
     /// Show memory usage and stats
     Stats {
         /// Follow and refresh stats live
         #[arg(short, long)]
         follow: bool,
+        /// Break local memory down by the client application that stored it
+        #[arg(long)]
+        by_app: bool,
+        /// Show what is stored on this peer (name or ID) and the room left there
+        #[arg(long, conflicts_with_all = ["follow", "by_app"])]
+        peer: Option<String>,
+        /// Show how long the node spends running each kind of command
+        #[arg(long, conflicts_with_all = ["follow", "by_app", "peer"])]
+        commands: bool,
+        /// Show every connected node, this one included, with totals
+        #[arg(long, conflicts_with_all = ["by_app", "peer", "commands"])]
+        cluster: bool,
     },
     /// Set a key-value pair
     Set {
@@ -127,30 +286,112 @@ enum Commands {
         value: String,
         #[arg(long)]
         peer: Option<String>,
-        /// Durability mode: 'pinned' (default) or 'cache'
+        /// Durability mode: 'pinned' (default), 'cache', or 'ttl:<secs>'
         #[arg(long, default_value = "pinned")]
         mode: String,
+        /// Tag the value, e.g. --tag type=json (repeatable; replaces the key's tags)
+        #[arg(long = "tag", value_name = "NAME=VALUE", value_parser = parse_tag, conflicts_with = "peer")]
+        tags: Vec<(String, String)>,
+    },
+    /// Show a block's size, mode, key and tags
+    Info {
+        /// Key or block ID
+        target: String,
+        /// Treat TARGET as a key even if it looks like a block ID
+        #[arg(long)]
+        key: bool,
+    },
+    /// List every block stored on the node, with or without a key
+    Blocks {
+        /// Only blocks no key points at, such as those from a plain `store`
+        #[arg(long)]
+        unkeyed: bool,
     },
     /// Get a value by key
     Get {
         key: String,
         #[arg(long)]
         peer: Option<String>,
+        #[command(flatten)]
+        output: value::ValueOutput,
+    },
+    /// Atomically add to a counter key (created at 0); prints the new value
+    Incr {
+        key: String,
+        #[arg(long, default_value_t = 1, allow_negative_numbers = true)]
+        by: i64,
+    },
+    /// Atomically subtract from a counter key; prints the new value
+    Decr {
+        key: String,
+        #[arg(long, default_value_t = 1, allow_negative_numbers = true)]
+        by: i64,
     },
-    /// List keys matching patterns (default: *)
+    /// Atomically append to a key's value (created if absent); prints the new length
+    Append {
+        key: String,
+        value: String,
+    },
+    /// Move a key's value to another key without copying it
+    Rename {
+        from: String,
+        to: String,
+        /// Replace a value already at TO instead of failing
+        #[arg(long, visible_alias = "force")]
+        overwrite: bool,
+        #[arg(long)]
+        peer: Option<String>,
+    },
+    /// Give a key's value a second name; it stays stored until both are gone
+    Alias {
+        key: String,
+        alias: String,
+        #[arg(long)]
+        peer: Option<String>,
+    },
+    /// List keys matching glob patterns (default: *). Supports '*', '?', '[a-z]' and '\\' escapes
     Keys {
         #[arg(default_value = "*", num_args = 0..)]
         patterns: Vec<String>,
+        /// Print keys page by page as they arrive instead of collecting and sorting them first
+        #[arg(long)]
+        stream: bool,
+        /// Show the N most-read blocks (named or not) instead of listing keys
+        #[arg(long, value_name = "N", conflicts_with = "stream")]
+        hot: Option<u32>,
+        /// With --hot: only blocks read in the last SECS seconds, ranked by recent reads
+        #[arg(long, value_name = "SECS", requires = "hot")]
+        window: Option<u64>,
+        /// Also list keys held by connected peers, with where each key lives
+        #[arg(long, conflicts_with_all = ["stream", "hot"])]
+        remote: bool,
+        /// List the keys tagged NAME=VALUE instead of matching patterns
+        #[arg(long, value_name = "NAME=VALUE", value_parser = parse_tag, conflicts_with_all = ["stream", "hot", "remote"])]
+        tag: Option<(String, String)>,
+        /// Show each key's block, size, mode and the other keys naming it
+        #[arg(short, long, conflicts_with_all = ["stream", "hot", "remote", "tag"])]
+        long: bool,
     },
     /// Check the version of memcli and the connected node
     Version,
     /// View daemon logs
     Logs {
-        /// Follow log output
+        /// Keep printing new lines as they are written
         #[arg(short, long)]
         follow: bool,
+        /// Only show lines containing this text
+        #[arg(long)]
+        grep: Option<String>,
+        /// Number of lines to show from the end of the log
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+    },
+    /// Show every log line of one RPC request, including rotated logs
+    Trace {
+        /// The request id (`request_id` in the node's log, the SDK's trace id)
+        request_id: String,
     },
-    /// Flush all data from the node (Dangerous!)
+    /// Flush data from the node (all data unless --pattern/--mode narrow it down)
     Flush {
         /// Skip confirmation prompt
         #[arg(short, long)]
@@ -161,9 +402,19 @@ enum Commands {
         /// Optional: Flush ALL connected peers and local node
         #[arg(long)]
         all: bool,
+        /// Only flush keys matching this glob pattern (e.g. 'tmp:*')
+        #[arg(long)]
+        pattern: Option<String>,
+        /// Only flush blocks with this durability: 'pinned' or 'cache'
+        #[arg(long)]
+        mode: Option<String>,
     },
     /// Stream data from stdin or file
+    #[command(args_conflicts_with_subcommands = true)]
     Stream {
+        #[command(subcommand)]
+        action: Option<StreamAction>,
+
         /// Input file (if not provided, reads from stdin)
         #[arg(name = "FILE")]
         file: Option<String>,
@@ -172,34 +423,125 @@ enum Commands {
         #[arg(long)]
         peer: Option<String>,
     },
+    /// Measure round trips to the node: latency percentiles, throughput and errors
+    Bench {
+        /// Payload size per operation (e.g. "4kb", "1mb")
+        #[arg(long, default_value = "1mb")]
+        size: String,
+        /// Number of operations (trimmed to what fits in the node's free memory)
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+        /// Store on this peer, and read a sample back afterwards to check it
+        #[arg(long)]
+        peer: Option<String>,
+        #[arg(long, value_enum, default_value = "set")]
+        mode: bench::BenchMode,
+        /// Operations in flight at once, each on its own connection
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        concurrency: u32,
+        /// Free everything the bench created once it is done
+        #[arg(long)]
+        cleanup: bool,
+        /// Print the results as JSON (e.g. for tracking in CI)
+        #[arg(long)]
+        json: bool,
+    },
     /// Manage trusted devices
     Trust {
         #[command(subcommand)]
         action: TrustAction,
     },
-    /// Interactive consent management
-    Consent,
+    /// Answer connection requests from unknown devices (interactively without a subcommand)
+    Consent {
+        #[command(subcommand)]
+        action: Option<ConsentAction>,
+    },
+    /// Name the nodes memcli talks to and pick the one commands use
+    Context {
+        #[command(subcommand)]
+        action: ContextAction,
+    },
     /// Run a command with MemCloud VM interception
     Run {
-        /// Malloc threshold in MB
-        #[arg(short, long, default_value_t = 8)]
-        threshold: u64,
+        /// Malloc threshold in MB (default: the node's, see `memcli vm config`)
+        #[arg(short, long)]
+        threshold: Option<u64>,
         /// Command to execute
         command: String,
         /// Arguments for the command
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
+    /// Inspect and tune VM interception on the node
+    Vm {
+        #[command(subcommand)]
+        action: VmAction,
+    },
+    /// Print a shell completion script (e.g. `memcli completions bash > /etc/bash_completion.d/memcli`)
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page (e.g. `memcli man > memcli.1`)
+    Man,
+    /// Completion candidates from the running node, used by the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        kind: completions::CandidateKind,
+        #[arg(default_value = "")]
+        prefix: String,
+    },
 }
 
 #[derive(Subcommand)]
 enum TrustAction {
     List,
+    /// Trust a device by public key before it first connects
+    Add {
+        public_key: String,
+        #[arg(long)]
+        name: String,
+        /// Why the device is trusted
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Show a trusted device and its recent connections
+    Info {
+        key_or_name: String,
+    },
     Remove {
         key_or_name: String,
+        /// Why trust is removed; kept in the node's log
+        #[arg(long)]
+        note: Option<String>,
+        /// Drain the device before disconnecting it, as `peer disconnect --drain` does
+        #[arg(long)]
+        drain: bool,
     },
 }
 
+#[derive(Subcommand)]
+enum ContextAction {
+    /// Add a context, or change an existing one
+    Add {
+        name: String,
+        /// A socket path, or tcp://host:port for a node's RPC listener (127.0.0.1:7070 by default)
+        endpoint: String,
+        /// Token to authenticate with (kept in contexts.toml, readable only by you)
+        #[arg(long)]
+        token: Option<String>,
+    },
+    List,
+    /// Make commands use this context; `default` goes back to the default socket
+    Use {
+        name: String,
+    },
+    Remove {
+        name: String,
+    },
+    /// Ping every context and show which nodes answer
+    Status,
+}
+
 #[derive(Subcommand)]
 enum NodeAction {
     /// Start the MemCloud node daemon in background
@@ -207,69 +549,317 @@ enum NodeAction {
         /// Name for this node (visible to peers)
         #[arg(long, short)]
         name: Option<String>,
-        /// Port for peer-to-peer communication
-        #[arg(long, short, default_value_t = 8080)]
-        port: u16,
+        /// Port for peer-to-peer communication (default: 8080)
+        #[arg(long, short)]
+        port: Option<u16>,
         /// Total memory capacity this node allocates for the network (e.g., "4gb", "512mb")
-        /// This is the hard limit for ALL storage combined.
-        #[arg(long, short = 'm', default_value = "1gb")]
-        total_memory: String,
+        /// This is the hard limit for ALL storage combined. (default: 1gb)
+        #[arg(long, short = 'm')]
+        total_memory: Option<String>,
+        /// Path to a memnode TOML config file; flags given here override it
+        #[arg(long, short)]
+        config: Option<PathBuf>,
     },
     /// Stop the running MemCloud node daemon
     Stop,
-    /// Check if the node daemon is running
-    Status,
+    /// Re-read the node's config file and apply what can change without a restart
+    Reload,
+    /// Check that the node daemon is running and answering requests
+    Status {
+        /// Keep polling until the node reports healthy (e.g. right after 'node start')
+        #[arg(long)]
+        wait: bool,
+        /// Give up waiting after this many seconds
+        #[arg(long, default_value_t = 30, requires = "wait")]
+        timeout: u64,
+    },
 }
 
 #[derive(Subcommand)]
 enum PeerAction {
     List,
+    #[command(group(clap::ArgGroup::new("change").required(true).multiple(true)))]
     Update {
         id: String,
         /// New storage limit you ALLOW this peer to use on your node (e.g. "1gb")
-        #[arg(long, short = 'a')]
-        allowed_storage: String,
+        #[arg(long, short = 'a', group = "change")]
+        allowed_storage: Option<String>,
+        /// Let this trusted peer flush all data on your node, not only what it stored here
+        #[arg(long, group = "change", conflicts_with = "deny_remote_flush")]
+        allow_remote_flush: bool,
+        /// Limit this peer's flushes to what it stored here again (the default)
+        #[arg(long, group = "change")]
+        deny_remote_flush: bool,
     },
     Disconnect {
         id: String,
+        /// First take back what this node stored on the peer, moving it to
+        /// other peers or back here
+        #[arg(long)]
+        drain: bool,
+        /// Disconnect at once, cutting short a drain of the peer
+        #[arg(long, conflicts_with = "drain")]
+        force: bool,
+    },
+    /// Show how the session with a peer was set up and what went through it
+    Inspect {
+        id: String,
+        /// Print the details as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Connect to every trusted device that is not connected, at the address
+    /// this node last dialed it on
+    #[command(visible_alias = "connect-all-trusted")]
+    ReconnectAll {
+        /// How much of YOUR memory capacity to offer each device (e.g. "512mb")
+        #[arg(long, short = 'o')]
+        offer_storage: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConsentAction {
+    /// List pending requests with the storage each side would get
+    List {
+        /// Print the requests as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Approve a request, or with --all every request from one device
+    Approve {
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        session_id: Option<String>,
+        /// Also trust the device, so it connects without asking next time
+        #[arg(long)]
+        trust: bool,
+        /// Approve every pending request from the device given with --from
+        #[arg(long, requires = "from")]
+        all: bool,
+        /// Device name (any case) or public key, or a prefix of the key
+        #[arg(long, requires = "all")]
+        from: Option<String>,
+    },
+    /// Deny a request
+    Deny {
+        session_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConnectAction {
+    /// Show the state of the connection attempt to ADDR once, without waiting
+    Status {
+        addr: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum StreamAction {
+    /// Show uploads that were started but not finished
+    List,
+    /// Drop an unfinished upload and its buffered data
+    Abort {
+        stream_id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum VmAction {
+    /// Show the node's default malloc interception threshold, or change it
+    Config {
+        /// New threshold in MB; programs started afterwards pick it up
+        #[arg(long)]
+        threshold: Option<u64>,
     },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
     let cli = Cli::parse();
+    let out = Verbosity::from_flags(cli.quiet, cli.verbose);
+    output::set_raw_ids(cli.raw_ids);
+    let explicit_socket = cli.socket.clone().or_else(|| std::env::var(memsdk::SOCKET_ENV).ok().filter(|s| !s.is_empty()));
+    let socket = explicit_socket.clone().unwrap_or_else(|| default_socket(cli.data_dir.as_deref()));
+    let timeout = cli.timeout.map(Duration::from_secs);
+    let endpoint = {
+        let (socket_flag, context, data_dir, rpc_token) = (cli.socket.is_some(), cli.context.clone(), cli.data_dir.clone(), cli.rpc_token.clone());
+        let (explicit_socket, socket) = (explicit_socket.is_some(), socket.clone());
+        move || resolve_endpoint(socket_flag, context.as_deref(), explicit_socket, &socket, data_dir.as_deref(), rpc_token.clone())
+    };
+    // Completion output goes straight into the shell, so keep it free of logs
+    if !matches!(cli.command, Commands::Complete { .. }) {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(out.log_filter())).init();
+    }
 
     match cli.command {
+        Commands::Completions { shell } => {
+            completions::print_completions(shell, &mut Cli::command());
+        }
+        Commands::Man => {
+            completions::print_man(Cli::command())?;
+        }
+        Commands::Complete { kind, prefix } => {
+            // Completion stays silent rather than report a broken context
+            if let Ok(endpoint) = endpoint() {
+                completions::print_candidates(kind, &prefix, &endpoint).await;
+            }
+        }
+        Commands::Context { action } => {
+            handle_context_action(action, &DataDir::resolve(cli.data_dir.as_deref())?, out).await?;
+        }
         Commands::Node { action } => {
-            handle_node_action(action)?;
+            let dir = DataDir::resolve(cli.data_dir.as_deref())?;
+            // A node started here listens in its data directory unless told otherwise
+            let socket = match (&action, &explicit_socket) {
+                (NodeAction::Start { .. }, None) if cfg!(unix) => dir.socket_file().to_string_lossy().into_owned(),
+                _ => socket,
+            };
+            // The node managed here is always the local one, whatever context is current
+            let endpoint = Endpoint::socket(socket, cli.rpc_token.clone());
+            handle_node_action(action, &dir, &endpoint, explicit_socket.is_some(), out).await.map_err(explain_denied)?;
+        }
+        Commands::Logs { follow, grep, lines } => {
+            handle_logs(&DataDir::resolve(cli.data_dir.as_deref())?, follow, grep.as_deref(), lines)?;
+        }
+        Commands::Trace { request_id } => {
+            handle_trace(&DataDir::resolve(cli.data_dir.as_deref())?, &request_id)?;
         }
-        Commands::Logs { follow } => {
-            handle_logs(follow)?;
+        Commands::Consent { action } => {
+            let mut client = connect_client(&endpoint()?, timeout).await?;
+            match action {
+                None => handle_consent(&mut client).await,
+                Some(action) => handle_consent_action(&mut client, action, out).await,
+            }.map_err(explain_denied)?;
         }
-        Commands::Consent => {
-            let mut client = MemCloudClient::connect_with_path(&cli.socket).await?;
-            handle_consent(&mut client).await?;
+        Commands::Bench { size, count, peer, mode, concurrency, cleanup, json } => {
+            let connector = bench::Connector { endpoint: endpoint()?, timeout };
+            let plan = bench::BenchPlan { mode, size: memsdk::parse_size(&size)?, count: count as usize, concurrency: concurrency as usize, peer };
+            handle_bench(&connector, plan, cleanup, json, out).await.map_err(explain_denied)?;
         }
         Commands::Run { threshold, command, args } => {
+            // The interception library only reaches a node through its socket
+            let endpoint = endpoint()?;
+            let socket = endpoint.unix_path()?;
             // Verify daemon is running
-            let _ = MemCloudClient::connect_with_path(&cli.socket).await.map_err(|_| {
+            let _ = MemCloudClient::connect_with_path(socket).await.map_err(|_| {
                 anyhow::anyhow!("❌ MemCloud node is not running. Please start it with 'memcli node start' first.")
             })?;
-            handle_run(threshold, command, args, &cli.socket)?;
+            handle_run(threshold, command, args, socket, out)?;
         }
         other => {
             // All other commands require connecting to the daemon
-            let mut client = MemCloudClient::connect_with_path(&cli.socket).await?;
-            handle_data_command(other, &mut client).await?;
+            let mut client = connect_client(&endpoint()?, timeout).await?;
+            if cli.encrypt || cli.passphrase_file.is_some() {
+                let passphrase = read_passphrase(cli.passphrase_file.as_deref())?;
+                client = client.with_encryption(memsdk::crypto::derive_key(passphrase.as_bytes()));
+            }
+            handle_data_command(other, &mut client, out).await.map_err(explain_denied)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_passphrase(file: Option<&std::path::Path>) -> anyhow::Result<String> {
+    let passphrase = match file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read passphrase file {:?}: {}", path, e))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        None => dialoguer::Password::new().with_prompt("Encryption passphrase").interact()?,
+    };
+    if passphrase.is_empty() {
+        anyhow::bail!("Encryption passphrase is empty");
+    }
+    Ok(passphrase)
+}
+
+// The search order of `memsdk::default_socket_path`, in the data directory `--data-dir` picks
+fn default_socket(data_dir: Option<&std::path::Path>) -> String {
+    if cfg!(windows) {
+        return memsdk::default_socket_path();
+    }
+    match DataDir::resolve(data_dir) {
+        Ok(dir) if dir.socket_file().exists() => dir.socket_file().to_string_lossy().into_owned(),
+        _ => memsdk::LEGACY_SOCKET.to_string(),
+    }
+}
+
+// --socket (or $MEMCLOUD_SOCKET) wins, then --context, then the current context
+fn resolve_endpoint(socket_flag: bool, context: Option<&str>, explicit_socket: bool, socket: &str, data_dir: Option<&std::path::Path>, rpc_token: Option<String>) -> anyhow::Result<Endpoint> {
+    if let Some(name) = context {
+        if socket_flag {
+            anyhow::bail!("--socket and --context both pick the node to talk to; pass only one");
         }
+        return context::Contexts::load(&DataDir::resolve(data_dir)?)?.endpoint(name, rpc_token);
     }
+    if !explicit_socket {
+        if let Some(endpoint) = context::Contexts::load(&DataDir::resolve(data_dir)?)?.current_endpoint(rpc_token.clone())? {
+            return Ok(endpoint);
+        }
+    }
+    Ok(Endpoint::socket(socket, rpc_token))
+}
 
+async fn connect_client(endpoint: &Endpoint, timeout: Option<Duration>) -> anyhow::Result<MemCloudClient> {
+    let connect = async {
+        let mut client = MemCloudClient::connect_endpoint(&endpoint.address).await?;
+        client.set_timeout(timeout);
+        if let Some(token) = &endpoint.token {
+            client.authenticate(token).await?;
+        }
+        client.identify("memcli").await?;
+        anyhow::Ok(client)
+    };
+    match (connect.await, &endpoint.context) {
+        (Err(e), Some(name)) => Err(e.context(format!("Context '{}' failed at {}", name, endpoint.address))),
+        (result, _) => result,
+    }
+}
+
+// A refusal because of the socket or token used says how to get more access;
+// one by a read-only node says how to lift that
+fn explain_denied(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<memsdk::MemCloudError>() {
+        Some(memsdk::MemCloudError::PermissionDenied(_)) => e.context(
+            "⛔ This connection is not allowed to run that command. Use the node's main socket (--socket) or a token with more access (--rpc-token)."
+        ),
+        Some(memsdk::MemCloudError::ReadOnly(_)) => e.context(
+            "⛔ The node is read-only and only serves data already stored on it. Restart it without --read-only (or set read_only = false and reload) to write."
+        ),
+        _ => e,
+    }
+}
+
+async fn handle_bench(connector: &bench::Connector, mut plan: bench::BenchPlan, cleanup: bool, json: bool, out: Verbosity) -> anyhow::Result<()> {
+    let mut client = connector.connect().await?;
+    let requested = plan.count;
+    if let Some(warning) = bench::fit_plan(&mut client, &mut plan).await? {
+        eprintln!("{}", warning);
+    }
+    let run = bench::run(connector, &plan, requested).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&run.report)?);
+    } else {
+        bench::print_report(&run.report, out);
+    }
+    if cleanup {
+        let failed = bench::cleanup(&mut client, &run, plan.peer.clone()).await;
+        if failed > 0 {
+            eprintln!("⚠️  {} entries created by the bench could not be freed", failed);
+        }
+    } else if !json {
+        status!(out, "   Left {} entries on the node; pass --cleanup to free them", run.report.ok);
+    }
+    if run.report.mismatched > 0 {
+        anyhow::bail!("{} of {} values read back did not match what was written", run.report.mismatched, run.report.verified);
+    }
     Ok(())
 }
 
-fn handle_logs(follow: bool) -> anyhow::Result<()> {
-    let log_path = get_memcloud_dir().join("memnode.log");
+fn handle_logs(dir: &DataDir, follow: bool, grep: Option<&str>, lines: usize) -> anyhow::Result<()> {
+    let log_path = dir.log_file();
     
     if !log_path.exists() {
         println!("❌ No log file found at {:?}", log_path);
@@ -277,42 +867,187 @@ fn handle_logs(follow: bool) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    for line in logs::tail_lines(&log_path, lines, grep)? {
+        println!("{}", line);
+    }
+
     if follow {
-        // Use tail -f to follow logs
-        let mut child = Command::new("tail")
-            .arg("-f")
-            .arg(&log_path)
-            .spawn()?;
-        
-        // Wait for user to interrupt
-        child.wait()?;
+        // Runs until the user interrupts
+        let mut follower = logs::Follower::new(&log_path)?;
+        loop {
+            for line in follower.poll()? {
+                if logs::matches(&line, grep) {
+                    println!("{}", line);
+                }
+            }
+            std::thread::sleep(logs::FOLLOW_POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
+
+fn handle_trace(dir: &DataDir, request_id: &str) -> anyhow::Result<()> {
+    let log_path = dir.log_file();
+    if !log_path.exists() {
+        anyhow::bail!("No log file found at {:?}; start the node with --log-file to trace requests", log_path);
+    }
+    let lines = logs::trace_lines(&log_path, request_id)?;
+    if lines.is_empty() {
+        anyhow::bail!("No log lines for request '{}' in {:?}", request_id, log_path);
+    }
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// How long `node status` waits for the daemon to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long `node start` waits for the new daemon to answer before giving up.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay between pings while `node start` or `node status --wait` polls.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long `node stop` waits for the node to exit after asking it to.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Ask the node to shut down over RPC, which works the same on every platform,
+// and wait for process `pid` to exit. False if it could not be asked or is still running.
+async fn shutdown_node(endpoint: &Endpoint, pid: i32) -> bool {
+    let ask = async {
+        let mut client = connect_client(endpoint, Some(PING_TIMEOUT)).await?;
+        anyhow::Ok(client.shutdown().await?)
+    };
+    if !matches!(tokio::time::timeout(PING_TIMEOUT, ask).await, Ok(Ok(()))) {
+        return false;
+    }
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while is_process_running(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+    }
+    true
+}
+
+async fn ping_node(endpoint: &Endpoint) -> anyhow::Result<memsdk::NodeHealth> {
+    let ping = async {
+        let mut client = connect_client(endpoint, None).await?;
+        anyhow::Ok(client.ping().await?)
+    };
+    tokio::time::timeout(PING_TIMEOUT, ping).await
+        .map_err(|_| anyhow::anyhow!("no answer within {:?}", PING_TIMEOUT))?
+}
+
+fn print_counter(out: Verbosity, key: &str, value: i64, took: Duration) {
+    if out.is_quiet() {
+        println!("{}", value);
     } else {
-        // Print last lines or full file? Reading full file might be big.
-        // Let's print the whole file for now, user can pipe it.
-        let content = fs::read_to_string(log_path)?;
-        print!("{}", content);
+        println!("'{}' = {} (took {:?})", key, value, took);
+    }
+}
+
+fn print_health(health: &memsdk::NodeHealth) {
+    for line in &health.details {
+        println!("   {}", line);
+    }
+}
+
+async fn handle_context_action(action: ContextAction, dir: &DataDir, out: Verbosity) -> anyhow::Result<()> {
+    let mut contexts = context::Contexts::load(dir)?;
+    match action {
+        ContextAction::Add { name, endpoint, token } => {
+            if name == context::DEFAULT_CONTEXT {
+                anyhow::bail!("'{}' is reserved for the default socket; pick another name", name);
+            }
+            context::check_endpoint(&endpoint)?;
+            let replaced = contexts.contexts.insert(name.clone(), context::NodeContext { endpoint: endpoint.clone(), token }).is_some();
+            contexts.save(dir)?;
+            status!(out, "✅ {} context '{}' ({})", if replaced { "Updated" } else { "Added" }, name, endpoint);
+            if contexts.current.is_none() {
+                status!(out, "   Run `memcli context use {}` to make commands use it", name);
+            }
+        }
+        ContextAction::List => {
+            if contexts.contexts.is_empty() {
+                status!(out, "No contexts; commands use the default socket. Add one with `memcli context add`.");
+            }
+            for (name, ctx) in &contexts.contexts {
+                let marker = if contexts.current.as_deref() == Some(name.as_str()) { "*" } else { " " };
+                let token = if ctx.token.is_some() { " (token)" } else { "" };
+                println!("{} {:<16} {}{}", marker, name, ctx.endpoint, token);
+            }
+        }
+        ContextAction::Use { name } => {
+            if name == context::DEFAULT_CONTEXT {
+                contexts.current = None;
+                contexts.save(dir)?;
+                status!(out, "✅ Commands now use the default socket");
+            } else {
+                let endpoint = contexts.endpoint(&name, None)?;
+                contexts.current = Some(name);
+                contexts.save(dir)?;
+                status!(out, "✅ Commands now use {}", endpoint.describe());
+            }
+        }
+        ContextAction::Remove { name } => {
+            if contexts.contexts.remove(&name).is_none() {
+                anyhow::bail!("No context named '{}'", name);
+            }
+            if contexts.current.as_deref() == Some(name.as_str()) {
+                contexts.current = None;
+                status!(out, "   '{}' was the current context; commands now use the default socket", name);
+            }
+            contexts.save(dir)?;
+            status!(out, "✅ Removed context '{}'", name);
+        }
+        ContextAction::Status => {
+            if contexts.contexts.is_empty() {
+                status!(out, "No contexts configured.");
+                return Ok(());
+            }
+            let contexts = &contexts;
+            let pings = contexts.contexts.keys().map(|name| async move {
+                let endpoint = contexts.endpoint(name, None)?;
+                ping_node(&endpoint).await
+            });
+            let results = futures::future::join_all(pings).await;
+            for ((name, ctx), result) in contexts.contexts.iter().zip(results) {
+                let marker = if contexts.current.as_deref() == Some(name.as_str()) { "*" } else { " " };
+                match result {
+                    Ok(health) => {
+                        let node = health.name.as_deref().unwrap_or("unnamed node");
+                        let state = if health.healthy { "✅" } else { "⚠️ " };
+                        println!("{} {} {:<16} {} ({}, uptime: {}s)", marker, state, name, ctx.endpoint, node, health.uptime_secs);
+                    }
+                    Err(e) => println!("{} ❌ {:<16} {} unreachable: {}", marker, name, ctx.endpoint, e.root_cause()),
+                }
+            }
+        }
     }
     Ok(())
 }
 
-fn handle_node_action(action: NodeAction) -> anyhow::Result<()> {
-    let memcloud_dir = get_memcloud_dir();
-    let pid_file = get_pid_file();
-    let log_file_path = memcloud_dir.join("memnode.log");
+async fn handle_node_action(action: NodeAction, dir: &DataDir, endpoint: &Endpoint, explicit_socket: bool, out: Verbosity) -> anyhow::Result<()> {
+    let socket = endpoint.address.as_str();
+    let pid_file = dir.pid_file();
+    let log_file_path = dir.log_file();
 
     match action {
-        NodeAction::Start { name, port, total_memory } => {
+        NodeAction::Start { name, port, total_memory, config } => {
             // Check if already running
-            if let Some(pid) = read_pid() {
+            if let Some(pid) = dir.read_pid() {
                 if is_process_running(pid) {
                     println!("⚠️  MemCloud node is already running (PID: {})", pid);
                     return Ok(());
                 }
             }
             
-            // Resolve name
+            // Resolve name (a config file may provide it instead)
             let final_name = match name {
-                Some(n) => n,
+                Some(n) => Some(n),
+                None if config.is_some() || dir.config_file().exists() => None,
                 None => {
                     print!("Enter node name [MyNode]: ");
                     io::stdout().flush()?;
@@ -320,73 +1055,206 @@ fn handle_node_action(action: NodeAction) -> anyhow::Result<()> {
                     io::stdin().read_line(&mut input)?;
                     let trimmed = input.trim();
                     if trimmed.is_empty() {
-                        "MyNode".to_string()
+                        Some("MyNode".to_string())
                     } else {
-                        trimmed.to_string()
+                        Some(trimmed.to_string())
                     }
                 }
             };
 
             // Create directory if needed
-            fs::create_dir_all(&memcloud_dir)?;
-
-            // Log Rotation: Check if log file is too big (> 3MB)
-            if log_file_path.exists() {
-                if let Ok(metadata) = fs::metadata(&log_file_path) {
-                    if metadata.len() > 3 * 1024 * 1024 { // 3MB limit
-                        let old_log = memcloud_dir.join("memnode.log.old");
-                        println!("📦 Rotating logs (exceeded 3MB)...");
-                        let _ = fs::rename(&log_file_path, old_log);
-                    }
-                }
-            }
+            fs::create_dir_all(dir.path())?;
 
-            // Open log file for appending
+            // Open log file for appending. memnode takes ownership of it via --log-file
+            // (size-based rotation, SIGHUP reopen); this only catches output before that.
             let log_file = fs::OpenOptions::new()
                 .create(true)
                 .append(true)
                 .open(&log_file_path)?;
 
+            // Only forward flags the user set so memnode can fall back to its config file
+            let mut node_args: Vec<String> = vec!["--log-file".to_string(), log_file_path.to_string_lossy().to_string()];
+            if let Some(path) = &config {
+                let path = fs::canonicalize(path)
+                    .map_err(|e| anyhow::anyhow!("Config file {:?} not found: {}", path, e))?;
+                node_args.extend(["--config".to_string(), path.to_string_lossy().to_string()]);
+            }
+            if let Some(n) = &final_name {
+                node_args.extend(["--name".to_string(), n.clone()]);
+            }
+            if let Some(p) = port {
+                node_args.extend(["--port".to_string(), p.to_string()]);
+            }
+            if let Some(m) = &total_memory {
+                node_args.extend(["--memory".to_string(), m.clone()]);
+            }
+            // Start waits on this socket, so the node has to listen there
+            if explicit_socket {
+                node_args.extend(["--socket".to_string(), socket.to_string()]);
+            }
+
             // Spawn memnode as a detached background process
-            println!("🚀 Starting MemCloud node '{}' on port {}...", final_name, port);
+            match &final_name {
+                Some(n) => status!(out, "🚀 Starting MemCloud node '{}'...", n),
+                None => status!(out, "🚀 Starting MemCloud node using config file..."),
+            }
             
-            let child = Command::new("memnode")
-                .args(["--name", &final_name, "--port", &port.to_string(), "--memory", &total_memory])
+            // memnode detaches itself and writes the pid file once it is up, so closing
+            // this terminal does not take it down
+            #[cfg(unix)]
+            node_args.extend(["--daemonize".to_string(), "--pid-file".to_string(), pid_file.to_string_lossy().to_string()]);
+
+            // Only look at what this run writes to the log
+            let mut log_follower = logs::Follower::new(&log_file_path)?;
+            detail!(out, "Running: memnode {}", node_args.join(" "));
+            let mut command = Command::new("memnode");
+            command.args(&node_args)
+                .env(data_dir::DATA_DIR_ENV, dir.path())
                 .stdin(Stdio::null())
                 .stdout(Stdio::from(log_file.try_clone()?))
-                .stderr(Stdio::from(log_file))
-                .spawn()?;
-            
-            let pid = child.id();
-            fs::write(&pid_file, pid.to_string())?;
+                .stderr(Stdio::from(log_file));
+            // No --daemonize there: start the node outside this console and its
+            // process group instead, so neither closing it nor Ctrl-C stops the node
+            #[cfg(windows)]
+            {
+                use std::os::windows::process::CommandExt;
+                command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+            }
+            let mut child = command.spawn()?;
+
+            // Without --daemonize the child is the node itself
+            #[cfg(not(unix))]
+            fs::write(&pid_file, child.id().to_string())?;
+            let _ = fs::remove_file(dir.port_file());
 
-            println!("✅ Node started successfully (PID: {})", pid);
-            println!("\n   Use 'memcli node status' to check the node.");
-            println!("   Use 'memcli logs -f' to view logs.");
-            println!("   Use 'memcli node stop' to stop the node.");
+            // Wait for the RPC socket to answer; bind failures make memnode exit early
+            let deadline = Instant::now() + START_TIMEOUT;
+            let mut errors = Vec::new();
+            let health = loop {
+                errors.extend(log_follower.poll()?.into_iter().filter(|l| logs::is_error_line(l)));
+                if let Some(exit) = child.try_wait()? {
+                    // The daemonizing launcher exits 0 once the node is up; anything else is a
+                    // failed start, as is the node removing its pid file again (it exited)
+                    if cfg!(not(unix)) || !exit.success() || dir.read_pid().is_none() {
+                        let _ = fs::remove_file(&pid_file);
+                        let code = if exit.success() { String::new() } else { format!(" ({})", exit) };
+                        println!("❌ Node exited during startup{}", code);
+                        for line in &errors {
+                            println!("   {}", line);
+                        }
+                        anyhow::bail!("MemCloud node failed to start; see 'memcli logs' for details");
+                    }
+                }
+                if let Ok(health) = ping_node(endpoint).await {
+                    break health;
+                }
+                if Instant::now() >= deadline {
+                    println!("⚠️  Node process is running but not answering on {} after {:?}", socket, START_TIMEOUT);
+                    for line in &errors {
+                        println!("   {}", line);
+                    }
+                    anyhow::bail!("MemCloud node did not become ready; see 'memcli logs' for details");
+                }
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+            };
+            let pid = dir.read_pid().unwrap_or(child.id() as i32);
+
+            match health.port {
+                Some(bound) => {
+                    fs::write(dir.port_file(), bound.to_string())?;
+                    status!(out, "✅ Node started successfully (PID: {}, port: {})", pid, bound);
+                    if let Some(requested) = port.filter(|p| *p != bound) {
+                        println!("   Requested port {} was busy; peers should use {}.", requested, bound);
+                    }
+                }
+                None => status!(out, "✅ Node started successfully (PID: {})", pid),
+            }
+            if !health.healthy {
+                println!("⚠️  The node reports problems:");
+                print_health(&health);
+            }
+            status!(out, "\n   Use 'memcli node status' to check the node.");
+            status!(out, "   Use 'memcli logs -f' to view logs.");
+            status!(out, "   Use 'memcli node stop' to stop the node.");
         }
         NodeAction::Stop => {
-            if let Some(pid) = read_pid() {
+            if let Some(pid) = dir.read_pid() {
                 if is_process_running(pid) {
-                    println!("🛑 Stopping MemCloud node (PID: {})...", pid);
-                    kill_process(pid)?;
+                    status!(out, "🛑 Stopping MemCloud node (PID: {})...", pid);
+                    if !shutdown_node(endpoint, pid).await {
+                        detail!(out, "Node did not stop on request; terminating process {}", pid);
+                        kill_process(pid)?;
+                    }
                     let _ = fs::remove_file(&pid_file);
-                    println!("✅ Node stopped.");
+                    let _ = fs::remove_file(dir.port_file());
+                    status!(out, "✅ Node stopped.");
                 } else {
                     println!("⚠️  Node is not running (stale PID file found).");
                     let _ = fs::remove_file(&pid_file);
+                    let _ = fs::remove_file(dir.port_file());
                 }
             } else {
                 println!("⚠️  No MemCloud node is running.");
             }
         }
-        NodeAction::Status => {
-            if let Some(pid) = read_pid() {
+        NodeAction::Reload => {
+            let mut client = connect_client(endpoint, None).await?;
+            let (applied, restart_required) = client.reload_config().await?;
+            if applied.is_empty() && restart_required.is_empty() {
+                status!(out, "✅ Config reloaded; nothing changed.");
+            } else {
+                status!(out, "✅ Config reloaded.");
+            }
+            for key in &applied {
+                println!("   applied: {}", key);
+            }
+            for key in &restart_required {
+                println!("   needs restart: {}", key);
+            }
+        }
+        NodeAction::Status { wait: true, timeout } => {
+            let deadline = Instant::now() + Duration::from_secs(timeout);
+            let mut last = None;
+            loop {
+                match ping_node(endpoint).await {
+                    Ok(health) if health.healthy => {
+                        println!("✅ MemCloud node is up and healthy (uptime: {}s)", health.uptime_secs);
+                        return Ok(());
+                    }
+                    Ok(health) => last = Some(health),
+                    Err(_) => {}
+                }
+                if Instant::now() >= deadline {
+                    if let Some(health) = &last {
+                        println!("⚠️  MemCloud node is responding but unhealthy:");
+                        print_health(health);
+                    }
+                    anyhow::bail!("MemCloud node did not become healthy within {}s", timeout);
+                }
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+            }
+        }
+        NodeAction::Status { wait: false, .. } => {
+            if let Some(pid) = dir.read_pid() {
                 if is_process_running(pid) {
-                    println!("✅ MemCloud node is running (PID: {})", pid);
+                    match ping_node(endpoint).await {
+                        Ok(health) if health.healthy => {
+                            let port = health.port.or_else(|| dir.read_port()).map(|p| format!(", port: {}", p)).unwrap_or_default();
+                            println!("✅ MemCloud node is running (PID: {}{}, uptime: {}s)", pid, port, health.uptime_secs);
+                        }
+                        Ok(health) => {
+                            let port = health.port.or_else(|| dir.read_port()).map(|p| format!(", port: {}", p)).unwrap_or_default();
+                            println!("⚠️  MemCloud node is running but unhealthy (PID: {}{}):", pid, port);
+                            print_health(&health);
+                        }
+                        Err(e) => {
+                            println!("⚠️  MemCloud node process running but unresponsive (PID: {}): {}", pid, e);
+                        }
+                    }
                 } else {
                     println!("❌ MemCloud node is not running (stale PID file).");
                     let _ = fs::remove_file(&pid_file);
+                    let _ = fs::remove_file(dir.port_file());
                 }
             } else {
                 println!("❌ MemCloud node is not running.");
@@ -396,79 +1264,223 @@ fn handle_node_action(action: NodeAction) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyhow::Result<()> {
+async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient, out: Verbosity) -> anyhow::Result<()> {
     match cmd {
-        Commands::Store { data, remote, peer, mode } => {
+        Commands::Store { data, remote, peer, mode, content_addressed, fallback } => {
             let start = Instant::now();
             let is_remote = remote || peer.is_some();
-            let durability = match mode.to_lowercase().as_str() {
-                "cache" => memsdk::Durability::Cache,
-                "pinned" => memsdk::Durability::Pinned,
-                _ => anyhow::bail!("Invalid mode: {}. Use 'pinned' or 'cache'", mode),
-            };
+            if fallback && !is_remote {
+                anyhow::bail!("--fallback only applies to remote stores; add --remote or --peer");
+            }
+            let durability = parse_durability(&mode)?;
+            if is_remote && peer.is_none() {
+                detail!(out, "Target: a connected peer chosen by the node");
+            } else {
+                detail!(out, "Target: {}", describe_target(peer.as_deref()));
+            }
             
-            let id = if is_remote {
-                client.store_remote(data.as_bytes(), target_peer_string(peer), durability).await?
+            let (id, size, location) = if is_remote {
+                let (id, size, location) = client.store_remote_with_size(data.as_bytes(), target_peer_string(peer), durability, fallback).await?;
+                (id, size, Some(location))
+            } else if content_addressed {
+                let (id, size) = client.store_content_addressed(data.as_bytes(), durability).await?;
+                (id, size, None)
             } else {
-                client.store(data.as_bytes(), durability).await?
+                let (id, size) = client.store_with_size(data.as_bytes(), durability).await?;
+                (id, size, None)
             };
             let duration = start.elapsed();
-            println!("Stored block ID: {} (remote: {}, mode: {:?}) (took {:?})", id, is_remote, durability, duration);
+            println!("{}", format_stored(out, id, location.as_ref(), durability, size, duration));
         }
-        Commands::Load { id } => {
+        Commands::Export { pattern, out: path } => {
             let start = Instant::now();
-            // Parse string id back to number or handle string in SDK?
-            // The SDK client.load expects BlockId (u64) OR we updated SDK?
-            // Wait, we updated SDK library (memsdk) and memnode rpc.
-            // But strict typing in Rust: memsdk::BlockId is still u64 in lib.rs?
-            // Let's check memsdk lib.rs.
-            // memsdk::BlockId is u64.
-            // The JSON serialization uses "string" on the wire, but Rust type is u64.
-            // We should parse the string CLI arg to u64 here.
-            
-            // If the ID is truly a large u64 that JS couldn't handle, Rust CAN handle it.
-            // But the CLI input comes as string. We need to parse it to u64.
-            
-            let id_u64 = id.parse::<u64>()?;
-            let data = client.load(id_u64).await?;
+            let file = fs::File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+            let count = archive::export(client, &pattern, io::BufWriter::new(file)).await?;
+            status!(out, "Exported {} keys to {} (took {:?})", count, path.display(), start.elapsed());
+        }
+        Commands::Import { input, peer, mode } => {
+            let start = Instant::now();
+            let mode = mode.as_deref().map(parse_durability).transpose()?;
+            let file = fs::File::open(&input).with_context(|| format!("Failed to open {}", input.display()))?;
+            let count = archive::import(client, io::BufReader::new(file), target_peer_string(peer), mode).await?;
+            status!(out, "Imported {} keys from {} (took {:?})", count, input.display(), start.elapsed());
+        }
+        Commands::Migrate { target, to, from, keep_local, key } => {
+            let selector = block_or_key(&target, key)?;
+            let start = Instant::now();
+            let migration_id = client.start_migration(selector, to, from, keep_local).await?;
+            detail!(out, "Migration {}", migration_id);
+            let status = loop {
+                let status = client.migration_status(migration_id).await?;
+                if status.state != memsdk::MigrationState::Running {
+                    break status;
+                }
+                if !out.is_quiet() && status.bytes_total > 0 {
+                    use std::io::Write;
+                    print!("\rMigrating {}: {} / {}", target, format_bytes(status.bytes_done), format_bytes(status.bytes_total));
+                    let _ = std::io::stdout().flush();
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            };
+            if !out.is_quiet() && status.bytes_total > 0 {
+                println!();
+            }
+            match status.state {
+                memsdk::MigrationState::Done if out.is_quiet() => println!("{}", show_id(status.id)),
+                memsdk::MigrationState::Done => println!("✅ Migrated {} ({}) with peer '{}' in {:?}", target, format_bytes(status.bytes_total), status.peer, start.elapsed()),
+                _ => anyhow::bail!(status.error.unwrap_or_else(|| "Migration failed".to_string())),
+            }
+        }
+        Commands::Load { id, output } => {
+            let start = Instant::now();
+            let (data, durability) = client.load_with_durability(id.0).await?;
             let duration = start.elapsed();
-            let string_data = String::from_utf8_lossy(&data);
-            println!("Loaded block {}: '{}' (took {:?})", id, string_data, duration);
+            let id = show_id(id.0);
+            value::emit(&data, &output, out, &format!("Loaded block {}", id), duration, |v| {
+                format!("Loaded block {}: '{}' (took {:?})", id, v, duration)
+            })?;
+            value::emit_mode(durability, &output);
         }
-        Commands::Free { id } => {
+        Commands::Free { ids, pattern: None } if ids.len() == 1 => {
             let start = Instant::now();
-            let id_u64 = id.parse::<u64>()?;
-            client.free(id_u64).await?;
+            let freed = client.free(ids[0].0).await?;
             let duration = start.elapsed();
-            println!("Freed block {} (took {:?})", id, duration);
+            let id = show_id(ids[0].0);
+            match freed {
+                Some(stats) if stats.count == 0 => status!(out, "No block {} on this node", id),
+                Some(stats) => status!(out, "Freed block {} ({}, took {:?})", id, format_bytes(stats.bytes), duration),
+                None => status!(out, "Freed block {} (took {:?})", id, duration),
+            }
+        }
+        Commands::Free { ids, pattern } => {
+            let start = Instant::now();
+            let ids: Vec<memsdk::BlockId> = ids.iter().map(|id| id.0).collect();
+            let stats = client.free_many(&ids, pattern).await?;
+            status!(out, "Freed {} blocks ({}, took {:?})", stats.count, format_bytes(stats.bytes), start.elapsed());
+            if stats.missing > 0 {
+                status!(out, "{} not found", stats.missing);
+            }
+            if stats.failed > 0 {
+                status!(out, "{} could not be released on their peer", stats.failed);
+            }
+        }
+        Commands::Pin { id } => {
+            client.set_durability(id.0, memsdk::Durability::Pinned).await?;
+            status!(out, "Pinned block {}", show_id(id.0));
+        }
+        Commands::Unpin { id } => {
+            client.set_durability(id.0, memsdk::Durability::Cache).await?;
+            status!(out, "Block {} may now be evicted", show_id(id.0));
+        }
+        Commands::Touch { target, priority, key } => {
+            client.set_priority(block_or_key(&target, key)?, priority).await?;
+            status!(out, "Set the priority of {} to {}", target, priority);
         }
         Commands::Peers => {
-             handle_peer_list(client).await?;
+             handle_peer_list(client, out).await?;
         }
         Commands::Peer { action } => {
             match action {
-                PeerAction::List => handle_peer_list(client).await?,
-                PeerAction::Update { id, allowed_storage } => {
-                    let quota_bytes = memsdk::parse_size(&allowed_storage)?;
-                    client.update_peer_quota(&id, quota_bytes).await?;
-                    println!("Updated peer {} allowed storage to {} bytes", id, quota_bytes);
+                PeerAction::List => handle_peer_list(client, out).await?,
+                PeerAction::Update { id, allowed_storage, allow_remote_flush, deny_remote_flush } => {
+                    if allow_remote_flush || deny_remote_flush {
+                        client.allow_remote_flush(&id, allow_remote_flush).await?;
+                        if allow_remote_flush {
+                            status!(out, "✅ Peer {} may now flush all data on this node", id);
+                        } else {
+                            status!(out, "✅ Peer {} may now only flush what it stored here", id);
+                        }
+                    }
+                    if let Some(allowed_storage) = allowed_storage {
+                        let quota_bytes = memsdk::parse_size(&allowed_storage)?;
+                        let update = client.update_peer_quota(&id, quota_bytes).await?;
+                        if !update.acknowledged {
+                            println!("⚠️  Updated peer {} allowed storage to {}, but the peer did not acknowledge", id, format_bytes(update.requested));
+                        } else if update.applied != update.requested {
+                            println!("⚠️  Peer {} clamped allowed storage to {} (requested {})", id, format_bytes(update.applied), format_bytes(update.requested));
+                        } else {
+                            status!(out, "✅ Peer {} accepted {} allowed storage", id, format_bytes(update.applied));
+                        }
+                    }
+                }
+                PeerAction::Inspect { id, json } => {
+                    let session = client.peer_inspect(&id).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&session)?);
+                    } else {
+                        print_peer_session(&session);
+                    }
+                }
+                PeerAction::ReconnectAll { offer_storage } => {
+                    let quota = offer_storage.as_deref().map(memsdk::parse_size).transpose()?;
+                    status!(out, "🔗 Reconnecting trusted devices...");
+                    let results = client.reconnect_trusted(quota).await?;
+                    let mut failed = Vec::new();
+                    for outcome in &results {
+                        let addr = outcome.addr.as_deref().unwrap_or("-");
+                        match outcome.state.as_str() {
+                            "connected" => status!(out, "   ✅ {:<20} {}", outcome.name, addr),
+                            "already_connected" => detail!(out, "   -  {:<20} {} (already connected)", outcome.name, addr),
+                            "no_address" => status!(out, "   -  {:<20} never dialed from here; connect it once by address", outcome.name),
+                            _ => {
+                                println!("   ❌ {:<20} {}: {}", outcome.name, addr, outcome.msg.as_deref().unwrap_or("failed"));
+                                failed.push(outcome.name.clone());
+                            }
+                        }
+                    }
+                    if !failed.is_empty() {
+                        anyhow::bail!("{} trusted devices could not be reached: {}", failed.len(), failed.join(", "));
+                    }
+                }
+                PeerAction::Disconnect { id, drain: true, .. } => {
+                    let start = Instant::now();
+                    client.start_drain(&id).await?;
+                    wait_for_drain(client, &id, out, start).await?;
                 }
-                PeerAction::Disconnect { id } => {
-                    client.disconnect_peer(&id).await?;
-                    println!("Disconnected peer {}", id);
+                PeerAction::Disconnect { id, force, .. } => {
+                    if force {
+                        client.force_disconnect(&id).await?;
+                    } else {
+                        client.disconnect_peer(&id).await?;
+                    }
+                    status!(out, "Disconnected peer {}", id);
                 }
             }
         }
-        Commands::Connect { addr, offer_storage } => {
+        Commands::Connect { action: Some(ConnectAction::Status { addr }), .. } => {
+            let (state, msg) = client.poll_connection(&addr).await?;
+            println!("{}", state);
+            if let Some(msg) = msg {
+                status!(out, "   {}", msg);
+            }
+        }
+        Commands::Connect { addr: None, .. } => unreachable!("clap requires ADDR without a subcommand"),
+        Commands::Connect { addr: Some(addr), cancel: true, .. } => {
+            client.cancel_connect(&addr).await?;
+            status!(out, "Cancelled connection attempt to {}", addr);
+        }
+        Commands::Connect { addr: Some(addr), offer_storage, accept_new_key, no_wait, .. } => {
             let quota_val = if let Some(q) = offer_storage {
                 memsdk::parse_size(&q)?
             } else {
                 0 // Default to 0 (Unidirectional access: Initiator writes to Responder, but Responder cannot write to Initiator)
             };
             
-            println!("🔗 Initiating connection to {}...", addr);
+            status!(out, "🔗 Initiating connection to {}...", addr);
             
-            let (mut state, mut msg) = client.connect_peer(&addr, Some(quota_val)).await?;
+            let (mut state, mut msg) = if accept_new_key {
+                client.connect_peer_accepting_new_key(&addr, Some(quota_val)).await?
+            } else {
+                client.connect_peer(&addr, Some(quota_val)).await?
+            };
+            if no_wait {
+                if state == "failed" {
+                    anyhow::bail!("Connection failed: {}", msg.unwrap_or_else(|| "Unknown error".to_string()));
+                }
+                println!("{}", state);
+                status!(out, "Check on it with: memcli connect status {}", addr);
+                return Ok(());
+            }
             
             let mut indicated_consent = false;
             
@@ -479,6 +1491,15 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
                         let err = msg.unwrap_or_else(|| "Unknown error".to_string());
                         anyhow::bail!("Connection failed: {}", err);
                     }
+                    "identity_changed" => {
+                        println!("\n🚨 PEER IDENTITY CHANGED at {}", addr);
+                        if let Some(msg) = &msg {
+                            println!("   {}", msg);
+                        }
+                        println!("   Someone may be impersonating this peer. If it was reinstalled or its data");
+                        println!("   directory was reset, reconnect with: memcli connect {} --accept-new-key", addr);
+                        anyhow::bail!("Refused to connect: peer identity changed");
+                    }
                     "waiting_consent" => {
                         if !indicated_consent {
                             println!("\n⚠️  Peer requires consent. Please approve on the remote device.");
@@ -488,41 +1509,148 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
                         }
                     }
                     "pending" | _ => {
-                        print!(".");
-                        io::stdout().flush()?;
+                        if !out.is_quiet() {
+                            print!(".");
+                            io::stdout().flush()?;
+                        }
                     }
                 }
                 
-                tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_millis(1000)) => {}
+                    // Don't leave the attempt running on the node, or the prompt on the peer
+                    _ = tokio::signal::ctrl_c() => {
+                        // Fails only if the attempt ended in the meantime
+                        let _ = client.cancel_connect(&addr).await;
+                        println!();
+                        anyhow::bail!("Cancelled connection attempt to {}", addr);
+                    }
+                }
                 let res = client.poll_connection(&addr).await?;
                 state = res.0;
                 msg = res.1;
             }
             
             if indicated_consent {
-                println!("\n✅ Consent granted.");
+                status!(out, "\n✅ Consent granted.");
             }
             let peers = client.list_peers().await?;
             
-            let meta_opt = peers.into_iter().find(|p| p.addr == addr);
+            // Peers are listed by IP, so a host name is looked up again to find this one
+            let resolved: Vec<String> = match tokio::net::lookup_host(&addr).await {
+                Ok(addrs) => addrs.map(|a| a.to_string()).collect(),
+                Err(_) => Vec::new(),
+            };
+            let meta_opt = peers.into_iter().find(|p| p.addr == addr || resolved.contains(&p.addr));
             
             if let Some(meta) = meta_opt {
-                println!("\n✅ Connection established!");
-                println!("🔐 Secure Session Established (Noise XX / ChaCha20-Poly1305)");
-                println!("\n📡 Handshake successful (Node ID: {})", meta.name);
+                status!(out, "\n✅ Connection established!");
+                status!(out, "🔐 Secure Session Established (Noise XX / ChaCha20-Poly1305)");
+                status!(out, "\n📡 Handshake successful (Node ID: {})", meta.name);
+                detail!(out, "   Peer key: {}", meta.public_key);
                 
                 // Format stats
                 let total_ram = format_bytes(meta.total_memory);
                 let pooled_ram = format_bytes(quota_val); 
                 
-                println!("   Latency: <1ms | Total RAM: {} | RAM Pooled: {}", total_ram, pooled_ram);
+                status!(out, "   Latency: {} | Total RAM: {} | RAM Pooled: {}", format_latency(meta.last_rtt_micros), total_ram, pooled_ram);
+            } else {
+                 status!(out, "\n✅ Connection established, but could not retrieve stats immediately.");
+            }
+        }
+        Commands::Events { kind, since, limit, follow, json } => {
+            let kinds = (!kind.is_empty()).then_some(kind);
+            let (mut events, mut next) = client.events(since, kinds.clone(), limit, None).await?;
+            loop {
+                for event in &events {
+                    if json {
+                        println!("{}", serde_json::to_string(event)?);
+                    } else {
+                        let subject = event.subject.as_deref().unwrap_or("-");
+                        let bytes = event.bytes.map_or(String::new(), |b| format!(" [{}]", format_bytes(b)));
+                        println!("{:>6}  {}  {:<9} {:<13} {:<20} {}{}", event.seq, format_timestamp(event.at), event.kind, event.action, subject, event.detail, bytes);
+                    }
+                }
+                if !follow {
+                    if events.is_empty() {
+                        status!(out, "No events.");
+                    }
+                    break;
+                }
+                (events, next) = client.events(Some(next), kinds.clone(), limit, Some(memsdk::EVENTS_MAX_WAIT_SECS)).await?;
+            }
+        }
+        Commands::Stats { by_app: true, .. } => {
+            let apps = client.app_usage().await?;
+            if apps.is_empty() {
+                status!(out, "No data stored by clients of this node.");
+            } else {
+                println!("{:<24} {:>8} {:>8} {:>8} {:>12}", "App", "PID", "Blocks", "Keys", "Memory");
+                for app in apps {
+                    let pid = app.pid.map_or("-".to_string(), |p| p.to_string());
+                    println!("{:<24} {:>8} {:>8} {:>8} {:>12}", app.app_name, pid, app.blocks, app.keys, format_bytes(app.bytes));
+                }
+            }
+        }
+        Commands::Stats { commands: true, .. } => {
+            let metrics = client.command_metrics().await?;
+            if metrics.is_empty() {
+                status!(out, "No commands run on this node yet.");
+            } else {
+                println!("{:<20} {:>10} {:>10} {:>10} {:>10} {:>10}", "Command", "Count", "Mean", "p50", "p99", "Max");
+                for m in metrics {
+                    println!(
+                        "{:<20} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                        m.command, m.count, format_latency(Some(m.mean_micros())), format_latency(Some(m.quantile_micros(0.5))),
+                        format_latency(Some(m.quantile_micros(0.99))), format_latency(Some(m.max_micros)),
+                    );
+                }
+            }
+        }
+        Commands::Stats { cluster: true, follow, .. } => {
+            loop {
+                let stats = client.cluster_stats().await?;
+                if follow {
+                    print!("\x1B[2J\x1B[H");
+                }
+                println!("{:<24} {:>8} {:>12} {:>12} {:>6} {:>10}", "Node", "Blocks", "Memory", "Max", "Peers", "Version");
+                for node in &stats.nodes {
+                    let name = if node.local { format!("{} (self)", node.name) } else { node.name.clone() };
+                    match node.status {
+                        memsdk::ClusterNodeStatus::Ok => println!(
+                            "{:<24} {:>8} {:>12} {:>12} {:>6} {:>10}",
+                            name, node.blocks, format_bytes(node.memory_usage), format_bytes(node.max_memory), node.peers,
+                            node.version.as_deref().unwrap_or("-"),
+                        ),
+                        memsdk::ClusterNodeStatus::Unreachable => println!("{:<24} unreachable", name),
+                        memsdk::ClusterNodeStatus::Unsupported => println!("{:<24} unknown version, no stats", name),
+                    }
+                }
+                println!(
+                    "{:<24} {:>8} {:>12} {:>12}",
+                    "Total", stats.blocks, format_bytes(stats.memory_usage), format_bytes(stats.max_memory),
+                );
+                if !follow {
+                    break;
+                }
+                status!(out, "\n(Press Ctrl+C to stop following)");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+        Commands::Stats { peer: Some(peer), .. } => {
+            let stats = client.peer_stats(&peer).await?;
+            println!("-------- Peer {} --------", peer);
+            println!("Blocks Stored:    {}", stats.blocks_remote);
+            if stats.quota > 0 {
+                println!("Storage Used:     {} / {} ({}%)", format_bytes(stats.used), format_bytes(stats.quota), stats.used * 100 / stats.quota);
+                println!("Free:             {}", format_bytes(stats.quota.saturating_sub(stats.used)));
             } else {
-                 println!("\n✅ Connection established, but could not retrieve stats immediately.");
+                println!("Storage Used:     {} (no quota granted)", format_bytes(stats.used));
             }
         }
-        Commands::Stats { follow } => {
+        Commands::Stats { follow, .. } => {
             loop {
-                let (blocks, peers, memory, vm_regions, vm_pages, vm_bytes) = client.stats().await?;
+                let stats = client.node_stats().await?;
                 
                 // Clear screen (ANSI escape code)
                 if follow {
@@ -530,42 +1658,213 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
                 }
 
                 println!("-------- MemCloud Stats --------");
-                println!("Blocks Stored:    {}", blocks);
-                println!("Peers Connected:  {}", peers);
-                println!("Memory Usage:     {}", format_bytes(memory as u64));
+                println!("Blocks Stored:    {}", stats.blocks);
+                println!("Peers Connected:  {}", stats.peers);
+                let used = stats.memory_usage as u64;
+                if stats.total_capacity > 0 {
+                    println!("Memory Usage:     {} / {} ({}%)", format_bytes(used), format_bytes(stats.total_capacity), used * 100 / stats.total_capacity);
+                    println!("  Pinned:         {}", format_bytes(stats.pinned_bytes));
+                    println!("  Cache:          {}", format_bytes(stats.cache_bytes));
+                    println!("Free:             {}", format_bytes(stats.free_space));
+                } else {
+                    println!("Memory Usage:     {}", format_bytes(used));
+                }
+                if follow {
+                    // Older nodes keep no history; show the snapshot alone
+                    if let Ok(samples) = client.stat_history(STATS_TREND_SECS).await {
+                        if let Some(last) = samples.last() {
+                            let memory: Vec<u64> = samples.iter().map(|s| s.memory_usage).collect();
+                            println!("  Last {}s:       {} (peak {})", STATS_TREND_SECS, sparkline(&memory), format_bytes(memory.iter().copied().max().unwrap_or(0)));
+                            println!("RPC Traffic:      {}/s in, {}/s out", format_bytes(last.bytes_in), format_bytes(last.bytes_out));
+                        }
+                    }
+                }
+                if stats.rss_bytes > 0 {
+                    println!("Process RSS:      {} ({:?})", format_bytes(stats.rss_bytes), stats.pressure);
+                }
+                if stats.read_only {
+                    println!("Mode:             read-only");
+                }
+                if stats.streams_rejected > 0 || stats.streams_reaped > 0 {
+                    println!("Streams refused:  {} over a limit, {} expired", stats.streams_rejected, stats.streams_reaped);
+                }
                 println!("--------------------------------");
-                println!("Remote VM regions:      {}", vm_regions);
-                println!("Remote VM pages mapped: {}", vm_pages);
-                println!("Remote VM memory in use: {}", format_bytes(vm_bytes as u64));
+                println!("Remote VM regions:      {}", stats.vm_regions);
+                println!("Remote VM pages mapped: {}", stats.vm_pages_mapped);
+                println!("Remote VM memory in use: {}", format_bytes(stats.vm_memory_in_use as u64));
                 println!("--------------------------------");
 
                 if !follow {
                     break;
                 }
                 
-                println!("\n(Press Ctrl+C to stop following)");
+                status!(out, "\n(Press Ctrl+C to stop following)");
                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             }
         }
-        Commands::Set { key, value, peer, mode } => {
+        Commands::Set { key, value, peer, mode, tags } => {
             let start = Instant::now();
-            let durability = match mode.to_lowercase().as_str() {
-                "cache" => memsdk::Durability::Cache,
-                "pinned" => memsdk::Durability::Pinned,
-                _ => anyhow::bail!("Invalid mode: {}. Use 'pinned' or 'cache'", mode),
+            let durability = parse_durability(&mode)?;
+            detail!(out, "Target: {}", describe_target(peer.as_deref()));
+            let id = if tags.is_empty() {
+                client.set(&key, value.as_bytes(), peer, durability).await?
+            } else {
+                client.set_tagged(&key, value.as_bytes(), durability, tags.into_iter().collect()).await?
             };
-            let id = client.set(&key, value.as_bytes(), peer, durability).await?;
             let duration = start.elapsed();
-            println!("Set '{}' -> {} (Block ID: {}, mode: {:?}) (took {:?})", key, value, id, durability, duration);
+            if out.is_quiet() {
+                println!("{}", show_id(id));
+            } else {
+                println!("Set '{}' -> {} (Block ID: {}, mode: {:?}) (took {:?})", key, value, show_id(id), durability, duration);
+            }
+        }
+        Commands::Info { target, key } => {
+            let info = client.block_info(block_or_key(&target, key)?).await?;
+            println!("Block ID: {}", show_id(info.id));
+            if let Some(k) = &info.key {
+                println!("Key:      {}", k);
+            }
+            if !info.aliases.is_empty() {
+                println!("Aliases:  {}", info.aliases.join(", "));
+            }
+            println!("Size:     {}", format_bytes(info.size));
+            println!("Mode:     {:?}", info.durability);
+            println!("Priority: {}", info.priority);
+            println!("Encrypted: {}", yes_no(info.encrypted));
+            if let Some(sum) = &info.checksum {
+                println!("Checksum: {}", sum);
+            }
+            let mut tags: Vec<_> = info.tags.into_iter().collect();
+            tags.sort();
+            for (name, value) in tags {
+                println!("Tag:      {}={}", name, value);
+            }
+        }
+        Commands::Blocks { unkeyed } => {
+            let blocks: Vec<_> = client.list_blocks().await?.into_iter().filter(|b| !unkeyed || !b.has_key).collect();
+            if out.is_quiet() {
+                for b in &blocks {
+                    println!("{}", show_id(b.id));
+                }
+            } else if blocks.is_empty() {
+                println!("No blocks stored.");
+            } else {
+                print_blocks_table(&blocks);
+                let total: u64 = blocks.iter().map(|b| b.size).sum();
+                println!("\n{} blocks, {}; free one with `memcli free <ID>`", blocks.len(), format_bytes(total));
+            }
         }
-        Commands::Get { key, peer } => {
+        Commands::Get { key, peer, output } => {
             let start = Instant::now();
-            let data = client.get(&key, peer).await?;
+            detail!(out, "Target: {}", describe_target(peer.as_deref()));
+            let (data, durability) = client.get_with_durability(&key, peer).await?;
             let duration = start.elapsed();
-            let value = String::from_utf8_lossy(&data);
-            println!("Get '{}' -> '{}' (took {:?})", key, value, duration);
+            value::emit(&data, &output, out, &format!("Get '{}'", key), duration, |v| {
+                format!("Get '{}' -> '{}' (took {:?})", key, v, duration)
+            })?;
+            value::emit_mode(durability, &output);
+        }
+        Commands::Incr { key, by } => {
+            let start = Instant::now();
+            let value = client.incr(&key, by).await?;
+            print_counter(out, &key, value, start.elapsed());
+        }
+        Commands::Decr { key, by } => {
+            let start = Instant::now();
+            let value = client.decr(&key, by).await?;
+            print_counter(out, &key, value, start.elapsed());
+        }
+        Commands::Append { key, value } => {
+            let start = Instant::now();
+            let len = client.append(&key, value.as_bytes()).await?;
+            if out.is_quiet() {
+                println!("{}", len);
+            } else {
+                println!("Appended {} bytes to '{}' ({} bytes total) (took {:?})", value.len(), key, len, start.elapsed());
+            }
+        }
+        Commands::Rename { from, to, overwrite, peer } => {
+            client.rename(&from, &to, overwrite, peer).await?;
+            status!(out, "Renamed '{}' -> '{}'", from, to);
+        }
+        Commands::Alias { key, alias, peer } => {
+            client.alias(&key, &alias, peer).await?;
+            status!(out, "'{}' now also names the value of '{}'", alias, key);
+        }
+        Commands::Keys { tag: Some((name, value)), .. } => {
+            let keys = client.find_by_tag(&name, &value).await?;
+            if keys.is_empty() {
+                status!(out, "No keys tagged {}={}", name, value);
+            }
+            for k in keys {
+                println!("{}", k);
+            }
+        }
+        Commands::Keys { hot: Some(top_n), window, .. } => {
+            let items = client.hot_keys(top_n, window).await?;
+            if items.is_empty() {
+                status!(out, "No reads recorded yet.");
+            } else {
+                print_hot_table(&items, window.is_some());
+            }
+        }
+        Commands::Keys { patterns, remote: true, .. } => {
+            let start = Instant::now();
+            // Merge the listings of every pattern, keeping each holder once
+            let mut holders: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
+            let (mut truncated, mut unanswered) = (std::collections::BTreeSet::new(), std::collections::BTreeSet::new());
+            for pattern in &patterns {
+                let listing = client.list_keys_everywhere(pattern).await?;
+                for item in listing.items {
+                    let entry = holders.entry(item.key).or_default();
+                    for holder in item.holders {
+                        if !entry.contains(&holder) {
+                            entry.push(holder);
+                        }
+                    }
+                }
+                truncated.extend(listing.truncated);
+                unanswered.extend(listing.unanswered);
+            }
+
+            if holders.is_empty() {
+                status!(out, "No keys found matching {:?}", patterns);
+            } else if out.is_quiet() {
+                for key in holders.keys() {
+                    println!("{}", key);
+                }
+            } else {
+                let width = holders.keys().map(|k| k.len()).max().unwrap_or(0).max("Key".len());
+                println!("{:<width$}  Location", "Key", width = width);
+                for (key, at) in &holders {
+                    println!("{:<width$}  {}", key, at.join(", "), width = width);
+                }
+                status!(out, "\nFound {} unique keys (took {:?})", holders.len(), start.elapsed());
+            }
+            // Incomplete results are worth knowing about even with --quiet
+            for peer in &truncated {
+                eprintln!("Note: peer '{}' has more matching keys than it may send; only some are listed", peer);
+            }
+            for peer in &unanswered {
+                eprintln!("Note: peer '{}' did not answer in time and was skipped", peer);
+            }
+        }
+        Commands::Keys { patterns, stream: true, .. } => {
+            use futures::StreamExt;
+            // Keys matching several patterns are printed once per pattern
+            let mut count = 0;
+            for pattern in &patterns {
+                let mut keys = client.scan(pattern);
+                while let Some(key) = keys.next().await {
+                    println!("{}", key?);
+                    count += 1;
+                }
+            }
+            if count == 0 {
+                status!(out, "No keys found matching {:?}", patterns);
+            }
         }
-        Commands::Keys { patterns } => {
+        Commands::Keys { patterns, stream: false, long, .. } => {
             let start = Instant::now();
             let mut all_keys = std::collections::HashSet::new();
             
@@ -582,17 +1881,26 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
             let duration = start.elapsed();
             
             if sorted_keys.is_empty() {
-                println!("No keys found matching {:?}", patterns);
+                status!(out, "No keys found matching {:?}", patterns);
                 // Hint about shell expansion if likely cause
-                let looks_like_expansion = patterns.len() > 1 && !patterns.iter().any(|p| p.contains('*') || p.contains('?'));
+                let looks_like_expansion = patterns.len() > 1 && !patterns.iter().any(|p| p.contains(['*', '?', '[']));
                 if looks_like_expansion {
-                     println!("(Hint: wildcard '*' might have been expanded by your shell. Try quoting it: 'memcli keys \"*\"')");
+                     status!(out, "(Hint: wildcards like '*', '?' or '[a-z]' might have been expanded by your shell. Quote the pattern: memcli keys 'user:*:session')");
                 }
+            } else if long && !out.is_quiet() {
+                let mut rows = Vec::with_capacity(sorted_keys.len());
+                for k in &sorted_keys {
+                    // Evicted or offloaded values have no local block to describe
+                    let info = client.block_info(memsdk::BlockOrKey::Key { key: k.clone() }).await.ok();
+                    rows.push((k.as_str(), info));
+                }
+                print_keys_long(&rows);
+                status!(out, "\nFound {} unique keys (took {:?})", sorted_keys.len(), duration);
             } else {
                 for k in &sorted_keys {
                     println!("{}", k);
                 }
-                println!("\nFound {} unique keys (took {:?})", sorted_keys.len(), duration);
+                status!(out, "\nFound {} unique keys (took {:?})", sorted_keys.len(), duration);
             }
         }
         Commands::Trust { action } => {
@@ -600,24 +1908,59 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
                 TrustAction::List => {
                     let items = client.list_trusted().await?;
                     if items.is_empty() {
-                         println!("No trusted devices found.");
+                         status!(out, "No trusted devices found.");
                     } else {
-                         println!("{:<20} {:<30} {:<64}", "Name", "Last Approved", "Public Key");
-                         println!("{}", "-".repeat(116));
+                         let now = unix_now();
+                         println!("{:<20} {:<16} {:<16} {:<64}", "Name", "Last Approved", "Last Connected", "Public Key");
+                         println!("{}", "-".repeat(118));
                          for item in items {
-                             // Format time
-                             let time_str = format!("{}", item.last_approved);
-                             println!("{:<20} {:<30} {:<64}", item.name, time_str, item.public_key);
+                             let connected = item.last_connected.map_or("never".to_string(), |t| format_ago(t, now));
+                             println!("{:<20} {:<16} {:<16} {:<64}", item.name, format_ago(item.last_approved, now), connected, item.public_key);
                          }
                     }
                 }
-                TrustAction::Remove { key_or_name } => {
-                    client.remove_trusted(&key_or_name).await?;
-                    println!("Removed '{}' from trusted devices.", key_or_name);
+                TrustAction::Add { public_key, name, note } => {
+                    client.add_trusted(&public_key, &name, note.as_deref()).await?;
+                    status!(out, "Trusted '{}' ({}).", name, public_key);
+                }
+                TrustAction::Info { key_or_name } => {
+                    let memsdk::TrustInfo { device, history } = client.trust_info(&key_or_name).await?;
+                    let now = unix_now();
+                    println!("Name:            {}", device.name);
+                    println!("Public Key:      {}", device.public_key);
+                    if let Some(note) = &device.note {
+                        println!("Note:            {}", note);
+                    }
+                    println!("First Seen:      {}", format_ago(device.first_seen, now));
+                    println!("Last Approved:   {}", format_ago(device.last_approved, now));
+                    println!("Last Connected:  {}", device.last_connected.map_or("never".to_string(), |t| format_ago(t, now)));
+                    println!("Sessions:        {}", device.total_sessions);
+                    println!("Remote Flush:    {}", if device.allow_remote_flush { "everything" } else { "only its own blocks" });
+                    println!("Last Address:    {}", device.last_addr.as_deref().unwrap_or("never dialed"));
+                    if !history.is_empty() {
+                        println!("\nRecent connections (newest first):");
+                        for conn in history.iter().rev() {
+                            println!("  {:<16} {}", format_ago(conn.at, now), conn.addr);
+                        }
+                    }
+                }
+                TrustAction::Remove { key_or_name, note, drain: true } => {
+                    let start = Instant::now();
+                    client.remove_trusted_draining(&key_or_name, note.as_deref()).await?;
+                    status!(out, "Removed '{}' from trusted devices.", key_or_name);
+                    // Only a device named by its peer name can be followed here
+                    if client.drain_status(&key_or_name).await.is_ok() {
+                        wait_for_drain(client, &key_or_name, out, start).await?;
+                    }
+                }
+                TrustAction::Remove { key_or_name, note, drain: false } => {
+                    client.remove_trusted_with_note(&key_or_name, note.as_deref()).await?;
+                    status!(out, "Removed '{}' from trusted devices.", key_or_name);
                 }
             }
         }
-        Commands::Consent | Commands::Node { .. } | Commands::Logs { .. } => unreachable!(),
+        Commands::Consent { .. } | Commands::Context { .. } | Commands::Node { .. } | Commands::Logs { .. } | Commands::Trace { .. } | Commands::Bench { .. }
+        | Commands::Completions { .. } | Commands::Man | Commands::Complete { .. } => unreachable!(),
         Commands::Version => {
             println!("memcli {}", env!("CARGO_PKG_VERSION"));
             // Try to connect to node to get its version?
@@ -626,15 +1969,25 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
         }
             // For now, simple client version is enough.
 
-        Commands::Flush { force, peer, all } => {
+        Commands::Flush { force, peer, all, pattern, mode } => {
+            let durability = mode.as_deref().map(parse_durability).transpose()?;
             let target_desc = if all {
                 "WHOLE CLUSTER (all peers + local)".to_string()
             } else {
                 peer.clone().unwrap_or_else(|| "LOCAL node".to_string())
             };
+            let filter_desc = match (&pattern, durability) {
+                (Some(p), Some(d)) => format!("keys matching '{}' with mode {:?}", p, d),
+                (Some(p), None) => format!("keys matching '{}'", p),
+                (None, Some(d)) => format!("ALL {:?} blocks", d),
+                (None, None) => "ALL data".to_string(),
+            };
 
-            if !force {
-                println!("⚠️  WARNING: This will delete ALL data stored on the {}.", target_desc);
+            // A pattern limits the blast radius, so only ask for total wipes,
+            // including patterns such as `*` that every key matches
+            let wipes_all = pattern.as_deref().is_none_or(|p| memsdk::glob::Glob::new(p).matches_all());
+            if !force && wipes_all {
+                println!("⚠️  WARNING: This will delete {} stored on the {}.", filter_desc, target_desc);
                 print!("   Are you sure? [y/N]: ");
                 io::stdout().flush()?;
                 let mut input = String::new();
@@ -646,28 +1999,50 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
             }
             
             if all {
-                println!("🧹 Flushing CLUSTER...");
-                let peers = client.list_peers().await?;
-                for p in peers {
-                    print!("   - Flushing peer {} ({}) ... ", p.name, p.addr);
-                    if let Err(e) = client.flush(Some(p.id)).await {
-                        println!("❌ Failed: {}", e);
-                    } else {
-                        println!("✅");
+                status!(out, "🧹 Flushing {} on CLUSTER...", filter_desc);
+                // The node flushes its peers itself, unless it is too old to
+                let server_side = match client.node_info().await {
+                    Ok(info) => info.version_at_least(memsdk::FLUSH_CLUSTER_SINCE),
+                    // Nodes from before NodeInfo hang up on it
+                    Err(memsdk::MemCloudError::Io(_)) => {
+                        client.reconnect().await?;
+                        false
                     }
+                    Err(e) => return Err(e.into()),
+                };
+                let (failed, peers) = if server_side {
+                    flush_cluster(client, pattern, durability, out).await?
+                } else {
+                    flush_each_peer(client, pattern, durability, out).await?
+                };
+                if !failed.is_empty() {
+                    anyhow::bail!("{} of {} peers did not confirm the flush: {}", failed.len(), peers, failed.join(", "));
                 }
-                print!("   - Flushing LOCAL node ... ");
-                client.flush(None).await?;
-                println!("✅");
-                println!("✅ Cluster flushed.");
+                status!(out, "✅ Cluster flushed.");
             } else {
-                println!("🧹 Flushing memory on {}...", target_desc);
-                client.flush(peer).await?;
-                println!("✅ Memory flushed.");
+                status!(out, "🧹 Flushing {} on {}...", filter_desc, target_desc);
+                let stats = client.flush_filtered(peer, pattern, durability).await?;
+                status!(out, "✅ Memory flushed.{}", format_flush_stats(stats));
             }
         }
-        Commands::Stream { file, peer } => {
+        Commands::Stream { action: Some(StreamAction::List), .. } => {
+            let streams = client.list_streams().await?;
+            if streams.is_empty() {
+                status!(out, "No active streams.");
+            }
+            for s in streams {
+                let expected = s.size_hint.map(|h| format!(" of {}", format_bytes(h))).unwrap_or_default();
+                let target = s.target.map(|t| format!(" -> {}", t)).unwrap_or_default();
+                println!("{}  {}{}{}  started {}s ago", s.stream_id, format_bytes(s.bytes_buffered + s.bytes_forwarded), expected, target, s.age_secs);
+            }
+        }
+        Commands::Stream { action: Some(StreamAction::Abort { stream_id }), .. } => {
+            client.abort_stream(stream_id).await?;
+            status!(out, "Aborted stream {}", stream_id);
+        }
+        Commands::Stream { action: None, file, peer } => {
             let start = Instant::now();
+            detail!(out, "Target: {}", describe_target(peer.as_deref()));
             let id = if let Some(path) = file {
                  // Open file
                  let f = tokio::fs::File::open(&path).await?;
@@ -675,12 +2050,26 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
                  client.stream_data(f, Some(meta.len()), peer.clone()).await?
             } else {
                  // Stdin
-                 println!("Reading from stdin (Ctrl+D to finish)...");
+                 status!(out, "Reading from stdin (Ctrl+D to finish)...");
                  let stdin = tokio::io::stdin();
                  client.stream_data(stdin, None, peer.clone()).await?
             };
             let duration = start.elapsed();
-            println!("Streamed block ID: {} (took {:?})", id, duration);
+            if out.is_quiet() {
+                println!("{}", show_id(id));
+            } else {
+                println!("Streamed block ID: {} (took {:?})", show_id(id), duration);
+            }
+        }
+        Commands::Vm { action: VmAction::Config { threshold } } => {
+            let current = client.vm_config(threshold).await?;
+            if out.is_quiet() {
+                println!("{}", current);
+            } else if threshold.is_some() {
+                println!("Malloc threshold set to {} MB", current);
+            } else {
+                println!("Malloc threshold: {} MB", current);
+            }
         }
         Commands::Run { .. } => {
             // Handled in main
@@ -690,7 +2079,7 @@ async fn handle_data_command(cmd: Commands, client: &mut MemCloudClient) -> anyh
     Ok(())
 }
 
-fn handle_run(threshold: u64, command: String, args: Vec<String>, socket: &str) -> anyhow::Result<()> {
+fn handle_run(threshold: Option<u64>, command: String, args: Vec<String>, socket: &str, out: Verbosity) -> anyhow::Result<()> {
     #[cfg(unix)]
     {
         use std::os::unix::process::CommandExt;
@@ -737,8 +2126,11 @@ fn handle_run(threshold: u64, command: String, args: Vec<String>, socket: &str)
             cmd.env("LD_PRELOAD", &interceptor_path);
         }
 
-        cmd.env("MEMCLOUD_MALLOC_THRESHOLD_MB", threshold.to_string());
-        cmd.env("MEMCLOUD_SOCKET", socket);
+        // Without the env var the interceptor asks the node on startup
+        if let Some(threshold) = threshold {
+            cmd.env("MEMCLOUD_MALLOC_THRESHOLD_MB", threshold.to_string());
+        }
+        cmd.env(memsdk::SOCKET_ENV, socket);
 
         // Help the dynamic linker find libmemsdk if needed
         let lib_env = if cfg!(target_os = "macos") { "DYLD_LIBRARY_PATH" } else { "LD_LIBRARY_PATH" };
@@ -750,8 +2142,9 @@ fn handle_run(threshold: u64, command: String, args: Vec<String>, socket: &str)
         lib_path.push_str(&sdk_dir.to_string_lossy());
         cmd.env(lib_env, lib_path);
 
-        println!("🚀 Running '{}' with MemCloud interception...", command);
-        println!("   (Threshold: {} MB, Socket: {})", threshold, socket);
+        status!(out, "🚀 Running '{}' with MemCloud interception...", command);
+        let threshold = threshold.map_or("node default".to_string(), |t| format!("{} MB", t));
+        status!(out, "   (Threshold: {}, Socket: {})", threshold, socket);
 
         // Execute and replace process
         let err = cmd.exec();
@@ -766,20 +2159,164 @@ fn handle_run(threshold: u64, command: String, args: Vec<String>, socket: &str)
     }
 }
 
+fn parse_durability(mode: &str) -> anyhow::Result<memsdk::Durability> {
+    let mode_lower = mode.to_lowercase();
+    match mode_lower.as_str() {
+        "cache" => Ok(memsdk::Durability::Cache),
+        "pinned" => Ok(memsdk::Durability::Pinned),
+        other => match other.strip_prefix("ttl:").map(str::parse::<u64>) {
+            Some(Ok(secs)) if secs > 0 => Ok(memsdk::Durability::Ttl { secs }),
+            _ => anyhow::bail!("Invalid mode: {}. Use 'pinned', 'cache' or 'ttl:<secs>'", mode),
+        },
+    }
+}
+
+fn parse_tag(tag: &str) -> anyhow::Result<(String, String)> {
+    match tag.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => anyhow::bail!("Invalid tag '{}'. Use NAME=VALUE", tag),
+    }
+}
+
+/// `flush --all` as one `FlushCluster`: the node flushes every peer and
+/// itself. Returns the peers that failed, and how many there were.
+async fn flush_cluster(client: &mut MemCloudClient, pattern: Option<String>, durability: Option<memsdk::Durability>, out: Verbosity) -> anyhow::Result<(Vec<String>, usize)> {
+    let results = client.flush_cluster(true, pattern, durability).await?;
+    let mut failed = Vec::new();
+    for outcome in &results {
+        let node = match outcome.local {
+            true => "LOCAL node".to_string(),
+            false => format!("peer {}", outcome.node),
+        };
+        match &outcome.error {
+            None => status!(out, "   - Flushed {} ✅{}", node, format_flush_stats(outcome.stats)),
+            Some(e) => {
+                println!("   - Failed to flush {}: ❌ {}", node, e);
+                failed.push(outcome.node.clone());
+            }
+        }
+    }
+    Ok((failed, results.iter().filter(|o| !o.local).count()))
+}
+
+/// `flush --all` against nodes without `FlushCluster`: one `Flush` per
+/// peer, then the local node.
+async fn flush_each_peer(client: &mut MemCloudClient, pattern: Option<String>, durability: Option<memsdk::Durability>, out: Verbosity) -> anyhow::Result<(Vec<String>, usize)> {
+    let peers = client.list_peers().await?;
+    let mut failed = Vec::new();
+    for p in &peers {
+        if !out.is_quiet() {
+            print!("   - Flushing peer {} ({}) ... ", p.name, p.addr);
+            io::stdout().flush()?;
+        }
+        match client.flush_filtered(Some(p.id.clone()), pattern.clone(), durability).await {
+            Ok(stats) => status!(out, "✅{}", format_flush_stats(stats)),
+            Err(e) => {
+                if out.is_quiet() {
+                    println!("❌ Failed to flush peer {}: {}", p.name, e);
+                } else {
+                    println!("❌ Failed: {}", e);
+                }
+                failed.push(p.name.clone());
+            }
+        }
+    }
+    if !out.is_quiet() {
+        print!("   - Flushing LOCAL node ... ");
+    }
+    let stats = client.flush_filtered(None, pattern, durability).await?;
+    status!(out, "✅{}", format_flush_stats(stats));
+    Ok((failed, peers.len()))
+}
+
+fn format_flush_stats(stats: Option<memsdk::FlushStats>) -> String {
+    match stats {
+        Some(s) => format!(" ({} blocks, {} freed)", s.blocks_removed, format_bytes(s.bytes_freed)),
+        None => String::new(),
+    }
+}
+
 fn target_peer_string(peer: Option<String>) -> Option<String> {
     peer
 }
 
-async fn handle_peer_list(client: &mut MemCloudClient) -> anyhow::Result<()> {
+/// What `store` prints: just the ID with --quiet, so `id=$(memcli -q store ...)` works.
+/// `location` is where a remote store put the block, `None` for a local store.
+fn format_stored(out: Verbosity, id: u64, location: Option<&memsdk::StoredLocation>, durability: memsdk::Durability, size: Option<u64>, duration: Duration) -> String {
+    if out.is_quiet() {
+        return show_id(id);
+    }
+    let is_remote = matches!(location, Some(memsdk::StoredLocation::Remote(_)));
+    let size = size.map(|s| format!(", {} bytes", s)).unwrap_or_default();
+    let on = location.map(|l| format!(", on {}", l)).unwrap_or_default();
+    format!("Stored block ID: {} (remote: {}, mode: {:?}{}{}) (took {:?})", show_id(id), is_remote, durability, size, on, duration)
+}
+
+// A key, or with `key` unset a block ID in either form. An ID that fails its
+// checksum is an error rather than a key, since that is almost always a typo.
+fn block_or_key(target: &str, key: bool) -> anyhow::Result<memsdk::BlockOrKey> {
+    if key {
+        return Ok(memsdk::BlockOrKey::Key { key: target.to_string() });
+    }
+    match target.parse::<memsdk::ShortId>() {
+        Ok(id) => Ok(memsdk::BlockOrKey::Id { id: id.0 }),
+        Err(e) if target.get(..memsdk::block_id::BLOCK_ID_PREFIX.len()).is_some_and(|p| p.eq_ignore_ascii_case(memsdk::block_id::BLOCK_ID_PREFIX)) => {
+            anyhow::bail!("{}; pass --key if '{}' is a key", e, target)
+        }
+        Err(_) => Ok(memsdk::BlockOrKey::Key { key: target.to_string() }),
+    }
+}
+
+fn describe_target(peer: Option<&str>) -> String {
+    match peer {
+        Some(p) => format!("peer '{}'", p),
+        None => "local node".to_string(),
+    }
+}
+
+fn print_peer_session(s: &memsdk::PeerSession) {
+    let dialed = if s.initiated { "this node dialed the peer" } else { "the peer dialed this node" };
+    println!("Peer:            {} ({})", s.name, s.id);
+    println!("Address:         {}", s.addr);
+    println!("Key fingerprint: {}", s.key_fingerprint);
+    println!("Protocol:        v{} ({})", s.protocol_version, s.capabilities.join(", "));
+    println!("Handshake:       {}, {}", format_ago(s.established_at / 1000, unix_now()), dialed);
+    println!("Uptime:          {}s", s.uptime_secs);
+    println!("Sent:            {} frames, {}", s.frames_sent, format_bytes(s.bytes_sent));
+    println!("Received:        {} frames, {}", s.frames_received, format_bytes(s.bytes_received));
+    println!("Nonce counters:  send {}, receive {}", s.frames_sent, s.frames_received);
+    println!("Stored here:     {} of {} allowed", format_bytes(s.used_here), format_bytes(s.allowed_quota));
+    println!("Stored there:    {} of {} offered", format_bytes(s.used_there), format_bytes(s.quota));
+    println!("Health:          {}, latency {}", if s.healthy { "healthy" } else { "unhealthy" }, format_latency(s.last_rtt_micros));
+}
+
+async fn handle_peer_list(client: &mut MemCloudClient, out: Verbosity) -> anyhow::Result<()> {
      let peers = client.list_peers().await?;
      if peers.is_empty() {
-         println!("No peers connected.");
+         status!(out, "No peers connected.");
      } else {
          print_peers_table(&peers);
+         if out.is_verbose() {
+             println!("\nPeer keys:");
+             for p in &peers {
+                 let key = if p.public_key.is_empty() { "(not reported)" } else { p.public_key.as_str() };
+                 println!("   {:<20} {}", p.name, key);
+             }
+         }
      }
      Ok(())
 }
 
+/// Seconds of history `stats --follow` draws under the memory usage.
+const STATS_TREND_SECS: u64 = 60;
+
+/// One block character per value, from ▁ for zero up to █ for the largest.
+fn sparkline(values: &[u64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values.iter().map(|v| BARS[(v * (BARS.len() as u64 - 1) / max) as usize]).collect()
+}
+
 fn format_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -789,22 +2326,123 @@ fn format_bytes(bytes: u64) -> String {
         format!("{:.1} GB", bytes as f64 / GB as f64)
     } else if bytes >= MB {
         format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.1} KB", bytes as f64 / KB as f64)
     } else {
         format!("{} B", bytes)
     }
 }
 
+fn print_hot_table(items: &[memsdk::HotBlock], windowed: bool) {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let name = |b: &memsdk::HotBlock| b.key.clone().unwrap_or_else(|| show_id(b.id));
+    let width = items.iter().map(|b| name(b).len()).max().unwrap_or(0).max("Key / Block".len());
+
+    print!("{:<width$}  {:>8}  {:>8}  {:>10}  {:>10}", "Key / Block", "Local", "Peer", "Served", "Last Read", width = width);
+    if windowed {
+        print!("  {:>8}", "Recent");
+    }
+    println!();
+    for b in items {
+        let ago = format!("{}s ago", now.saturating_sub(b.last_accessed));
+        print!("{:<width$}  {:>8}  {:>8}  {:>10}  {:>10}", name(b), b.local_hits, b.peer_hits, format_bytes(b.bytes_served), ago, width = width);
+        if windowed {
+            print!("  {:>8.1}", b.score);
+        }
+        println!();
+    }
+}
+
+/// Follow the drain of `peer` until it ends, then report any blocks left there.
+async fn wait_for_drain(client: &mut MemCloudClient, peer: &str, out: Verbosity, start: Instant) -> anyhow::Result<()> {
+    let mut shown = false;
+    let status = loop {
+        let status = client.drain_status(peer).await?;
+        if status.state != memsdk::DrainState::Running {
+            break status;
+        }
+        if !out.is_quiet() {
+            use std::io::Write;
+            print!("\rDraining {}: {} / {} blocks ({})", status.peer, status.blocks_done, status.blocks_total, format_bytes(status.bytes_done));
+            let _ = std::io::stdout().flush();
+            shown = true;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    };
+    if shown {
+        println!();
+    }
+    for failure in &status.failed {
+        if out.is_quiet() {
+            println!("{}", show_id(failure.id));
+        } else {
+            eprintln!("   block {}: {}", show_id(failure.id), failure.error);
+        }
+    }
+    match status.state {
+        memsdk::DrainState::Aborted => anyhow::bail!("The drain of peer '{}' was cut short; {} blocks were left there", status.peer, status.failed.len()),
+        _ if !status.failed.is_empty() => anyhow::bail!("{} blocks could not be drained and were left on peer '{}'", status.failed.len(), status.peer),
+        _ => status!(out, "✅ Drained {} blocks ({}, {} of them to other peers) from peer '{}' and disconnected (took {:?})",
+            status.blocks_done, format_bytes(status.bytes_done), status.blocks_moved, status.peer, start.elapsed()),
+    }
+    Ok(())
+}
+
+fn print_blocks_table(blocks: &[memsdk::BlockSummary]) {
+    let width = blocks.iter().map(|b| show_id(b.id).len()).max().unwrap_or(0).max("Block ID".len());
+    println!("{:<width$}  {:>10}  {:<12}  {:>8}  {:<9}  Key", "Block ID", "Size", "Mode", "Priority", "Encrypted", width = width);
+    for b in blocks {
+        println!("{:<width$}  {:>10}  {:<12}  {:>8}  {:<9}  {}", show_id(b.id), format_bytes(b.size), mode_name(b.durability), b.priority, yes_no(b.encrypted), yes_no(b.has_key), width = width);
+    }
+}
+
+// As given to --mode
+fn mode_name(durability: memsdk::Durability) -> String {
+    match durability {
+        memsdk::Durability::Pinned => "pinned".to_string(),
+        memsdk::Durability::Cache => "cache".to_string(),
+        memsdk::Durability::Ttl { secs } => format!("ttl:{}", secs),
+    }
+}
+
+fn print_keys_long(rows: &[(&str, Option<memsdk::BlockInfo>)]) {
+    let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0).max("Key".len());
+    println!("{:<width$}  {:<20}  {:>10}  {:<12}  {:>8}  {:<9}  Aliases", "Key", "Block ID", "Size", "Mode", "Priority", "Encrypted", width = width);
+    for (key, info) in rows {
+        let Some(info) = info else {
+            println!("{:<width$}  {:<20}  {:>10}  {:<12}  {:>8}  {:<9}  -", key, "-", "-", "not local", "-", "-", width = width);
+            continue;
+        };
+        let aliases: Vec<&str> = info.key.iter().chain(&info.aliases).map(String::as_str).filter(|k| k != key).collect();
+        let aliases = if aliases.is_empty() { "-".to_string() } else { aliases.join(", ") };
+        println!("{:<width$}  {:<20}  {:>10}  {:<12}  {:>8}  {:<9}  {}", key, show_id(info.id), format_bytes(info.size), mode_name(info.durability), info.priority, yes_no(info.encrypted), aliases, width = width);
+    }
+}
+
+fn yes_no(flag: bool) -> &'static str {
+    if flag { "yes" } else { "no" }
+}
+
 fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
     // 1. Calculate column widths
     let h_node = "Node";
     let h_addr = "Address";
     let h_in = "Allowed Storage";
     let h_out = "Capacity Offered";
+    let h_status = "Status";
+    let h_rtt = "Latency";
+    let h_seen = "Last Seen";
+    let status = |p: &memsdk::PeerMetadata| if p.healthy { "healthy" } else { "unhealthy" };
+    let now_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+    let seen = |p: &memsdk::PeerMetadata| format_last_seen(p.last_seen, now_ms);
     
     let mut w_node = h_node.len();
     let mut w_addr = h_addr.len();
     let mut w_in = h_in.len();
     let mut w_out = h_out.len();
+    let mut w_status = h_status.len();
+    let mut w_rtt = h_rtt.len();
+    let mut w_seen = h_seen.len();
 
     // Scan data
     for p in peers {
@@ -812,6 +2450,9 @@ fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
         w_addr = w_addr.max(p.addr.len());
         w_in = w_in.max(format_bytes(p.allowed_quota).len());
         w_out = w_out.max(format_bytes(p.quota).len());
+        w_status = w_status.max(status(p).len());
+        w_rtt = w_rtt.max(format_latency(p.last_rtt_micros).chars().count());
+        w_seen = w_seen.max(seen(p).len());
     }
 
     // Padding
@@ -819,6 +2460,9 @@ fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
     w_addr += 2;
     w_in += 2;
     w_out += 2;
+    w_status += 2;
+    w_rtt += 2;
+    w_seen += 2;
 
     // Helper to print separator
     let print_sep = |start: &str, mid: &str, end: &str, line: &str| {
@@ -830,6 +2474,12 @@ fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
         print!("{}", line.repeat(w_in));
         print!("{}", mid);
         print!("{}", line.repeat(w_out));
+        print!("{}", mid);
+        print!("{}", line.repeat(w_status));
+        print!("{}", mid);
+        print!("{}", line.repeat(w_rtt));
+        print!("{}", mid);
+        print!("{}", line.repeat(w_seen));
         println!("{}", end);
     };
 
@@ -837,9 +2487,10 @@ fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
     print_sep("┌", "┬", "┐", "─");
 
     // Header
-    println!("│ {:<width_n$} │ {:<width_a$} │ {:<width_i$} │ {:<width_o$} │", 
-             h_node, h_addr, h_in, h_out,
-             width_n = w_node-2, width_a = w_addr-2, width_i = w_in-2, width_o = w_out-2);
+    println!("│ {:<width_n$} │ {:<width_a$} │ {:<width_i$} │ {:<width_o$} │ {:<width_s$} │ {:<width_r$} │ {:<width_l$} │", 
+             h_node, h_addr, h_in, h_out, h_status, h_rtt, h_seen,
+             width_n = w_node-2, width_a = w_addr-2, width_i = w_in-2, width_o = w_out-2, width_s = w_status-2,
+             width_r = w_rtt-2, width_l = w_seen-2);
 
     // Mid
     print_sep("├", "┼", "┤", "─");
@@ -851,9 +2502,10 @@ fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
         let q_out = format_bytes(p.quota);
         total_pooled += p.quota;
         
-        println!("│ {:<width_n$} │ {:<width_a$} │ {:<width_i$} │ {:<width_o$} │", 
-                 p.name, p.addr, q_in, q_out,
-                 width_n = w_node-2, width_a = w_addr-2, width_i = w_in-2, width_o = w_out-2);
+        println!("│ {:<width_n$} │ {:<width_a$} │ {:<width_i$} │ {:<width_o$} │ {:<width_s$} │ {:<width_r$} │ {:<width_l$} │", 
+                 p.name, p.addr, q_in, q_out, status(p), format_latency(p.last_rtt_micros), seen(p),
+                 width_n = w_node-2, width_a = w_addr-2, width_i = w_in-2, width_o = w_out-2, width_s = w_status-2,
+                 width_r = w_rtt-2, width_l = w_seen-2);
     }
 
     // Bottom
@@ -862,6 +2514,57 @@ fn print_peers_table(peers: &[memsdk::PeerMetadata]) {
     println!("\n📊 Total Pooled RAM (Capacity Offered): {}", format_bytes(total_pooled));
 }
 
+/// Keepalive round trip, "-" until the peer has answered a ping.
+fn format_latency(micros: Option<u64>) -> String {
+    match micros {
+        None => "-".to_string(),
+        Some(us) if us < 1000 => format!("{}us", us),
+        Some(us) => format!("{:.1}ms", us as f64 / 1000.0),
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Unix millis as a UTC date and time, like "2026-10-16 03:12:45Z".
+fn format_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}Z", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// `then` (unix seconds) relative to `now`, like "3 days ago".
+fn format_ago(then: u64, now: u64) -> String {
+    let secs = now.saturating_sub(then);
+    let (n, unit) = match secs {
+        0..60 => return "just now".to_string(),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        86400..2_592_000 => (secs / 86400, "day"),
+        2_592_000..31_536_000 => (secs / 2_592_000, "month"),
+        _ => (secs / 31_536_000, "year"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
+fn format_last_seen(last_seen_ms: u64, now_ms: u64) -> String {
+    if last_seen_ms == 0 {
+        return "-".to_string();
+    }
+    format!("{}s ago", now_ms.saturating_sub(last_seen_ms) / 1000)
+}
+
 async fn handle_consent(client: &mut MemCloudClient) -> anyhow::Result<()> {
     loop {
         let pending = client.list_consent().await?;
@@ -877,6 +2580,7 @@ async fn handle_consent(client: &mut MemCloudClient) -> anyhow::Result<()> {
             println!("\nDevice: {} ({})", req.peer_name, req.peer_pubkey); 
             println!("Wants to connect. Request ID: {}", req.session_id);
             println!("Offering Capacity: {}  (This capacity will be available to you)", format_bytes(req.quota));
+            println!("Allowed Storage:   {}  (Approving lets them store this much on this node)", format_bytes(req.allowed_quota));
             
             // Interaction
             let selection = dialoguer::Select::new()
@@ -907,3 +2611,191 @@ async fn handle_consent(client: &mut MemCloudClient) -> anyhow::Result<()> {
         println!("Checking for more...");
     }
 }
+
+async fn handle_consent_action(client: &mut MemCloudClient, action: ConsentAction, out: Verbosity) -> anyhow::Result<()> {
+    match action {
+        ConsentAction::List { json } => {
+            let pending = client.list_consent().await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&pending)?);
+            } else if pending.is_empty() {
+                status!(out, "No pending consent requests.");
+            } else {
+                println!("{:<36}  {:<20} {:<16} {:>10} {:>10} {:>12}", "Request ID", "Device", "Key", "Offers", "Allowed", "Asked");
+                for req in pending {
+                    println!(
+                        "{:<36}  {:<20} {:<16} {:>10} {:>10} {:>12}",
+                        req.session_id, req.peer_name, req.peer_pubkey.get(..16).unwrap_or(&req.peer_pubkey),
+                        format_bytes(req.quota), format_bytes(req.allowed_quota), format_ago(req.created_at, unix_now()),
+                    );
+                }
+            }
+        }
+        ConsentAction::Approve { session_id: Some(id), trust, .. } => {
+            client.approve_consent(&id, trust).await?;
+            status!(out, "✅ Approved {}{}", id, if trust { " and trusted the device" } else { "" });
+        }
+        ConsentAction::Approve { from, trust, .. } => {
+            let from = from.unwrap_or_default();
+            let matching: Vec<_> = client.list_consent().await?.into_iter().filter(|req| consent_from(req, &from)).collect();
+            if matching.is_empty() {
+                if is_hex(&from) && from.len() < MIN_KEY_PREFIX {
+                    anyhow::bail!("No pending consent requests from a device named '{}'; a key prefix needs at least {} hex characters", from, MIN_KEY_PREFIX);
+                }
+                anyhow::bail!("No pending consent requests from '{}'", from);
+            }
+            for req in matching {
+                client.approve_consent(&req.session_id, trust).await?;
+                status!(out, "✅ Approved {} from {}", req.session_id, req.peer_name);
+            }
+        }
+        ConsentAction::Deny { session_id } => {
+            client.deny_consent(&session_id).await?;
+            status!(out, "❌ Denied {}", session_id);
+        }
+    }
+    Ok(())
+}
+
+// Shortest public key prefix `--from` accepts, so a few characters cannot
+// approve devices that merely happen to share them
+const MIN_KEY_PREFIX: usize = 8;
+
+// Whether `req` comes from the device `from` names: its name in any case, or
+// its key or a prefix of it of at least `MIN_KEY_PREFIX` hex characters
+fn consent_from(req: &memsdk::PendingConsent, from: &str) -> bool {
+    let key_prefix = is_hex(from) && from.len() >= MIN_KEY_PREFIX;
+    req.peer_name.eq_ignore_ascii_case(from) || (key_prefix && req.peer_pubkey.starts_with(&from.to_ascii_lowercase()))
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quiet_store_prints_only_id() {
+        let took = Duration::from_millis(3);
+        let peer = memsdk::StoredLocation::Remote("b".to_string());
+        assert_eq!(format_stored(Verbosity::Quiet, 42, Some(&peer), memsdk::Durability::Pinned, Some(5), took), "blk_tr00000000000ag");
+        let normal = format_stored(Verbosity::Normal, 42, None, memsdk::Durability::Cache, Some(5), took);
+        assert_eq!(normal, "Stored block ID: blk_tr00000000000ag (remote: false, mode: Cache, 5 bytes) (took 3ms)");
+        let fallback = format_stored(Verbosity::Normal, 42, Some(&memsdk::StoredLocation::Local), memsdk::Durability::Cache, Some(5), took);
+        assert_eq!(fallback, "Stored block ID: blk_tr00000000000ag (remote: false, mode: Cache, 5 bytes, on this node) (took 3ms)");
+    }
+
+    #[test]
+    fn test_block_ids_are_checked_before_use() {
+        let id = |target: &str, key| match block_or_key(target, key) {
+            Ok(memsdk::BlockOrKey::Id { id }) => Ok(Some(id)),
+            Ok(memsdk::BlockOrKey::Key { .. }) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        };
+        assert_eq!(id("blk_tr00000000000ag", false), Ok(Some(42)));
+        assert_eq!(id("42", false), Ok(Some(42)));
+        assert_eq!(id("42", true), Ok(None));
+        assert_eq!(id("user:42", false), Ok(None));
+        let typo = id("blk_tr00000000000bg", false).unwrap_err();
+        assert!(typo.contains("checksum mismatch") && typo.contains("--key"), "{}", typo);
+        assert_eq!(id("blk_tr00000000000bg", true), Ok(None));
+
+        let err = Cli::try_parse_from(["memcli", "load", "blk_tr00000000000bg"]).err().unwrap().to_string();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        let cli = Cli::try_parse_from(["memcli", "free", "blk_tr00000000000ag", "7", "--raw-ids"]).unwrap();
+        assert!(cli.raw_ids);
+        assert!(matches!(cli.command, Commands::Free { ref ids, .. } if ids == &[memsdk::ShortId(42), memsdk::ShortId(7)]));
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        assert!(Cli::try_parse_from(["memcli", "-q", "-v", "stats"]).is_err());
+        let cli = Cli::try_parse_from(["memcli", "store", "hello", "--quiet"]).unwrap();
+        assert!(cli.quiet);
+    }
+
+    #[test]
+    fn test_connect_status_is_a_subcommand() {
+        let cli = Cli::try_parse_from(["memcli", "connect", "status", "10.0.0.2:8080"]).unwrap();
+        assert!(matches!(cli.command, Commands::Connect { action: Some(ConnectAction::Status { ref addr }), addr: None, .. } if addr == "10.0.0.2:8080"));
+        let cli = Cli::try_parse_from(["memcli", "connect", "10.0.0.2:8080", "--no-wait"]).unwrap();
+        assert!(matches!(cli.command, Commands::Connect { action: None, addr: Some(_), no_wait: true, .. }));
+        assert!(Cli::try_parse_from(["memcli", "connect"]).is_err());
+        assert!(Cli::try_parse_from(["memcli", "connect", "10.0.0.2:8080", "--no-wait", "--cancel"]).is_err());
+    }
+
+    #[test]
+    fn test_peer_update_needs_a_change() {
+        assert!(Cli::try_parse_from(["memcli", "peer", "update", "laptop"]).is_err());
+        assert!(Cli::try_parse_from(["memcli", "peer", "update", "laptop", "--allow-remote-flush", "--deny-remote-flush"]).is_err());
+        let cli = Cli::try_parse_from(["memcli", "peer", "update", "laptop", "--allow-remote-flush"]).unwrap();
+        assert!(matches!(cli.command, Commands::Peer { action: PeerAction::Update { allowed_storage: None, allow_remote_flush: true, .. } }));
+    }
+
+    #[test]
+    fn test_touch_takes_a_priority_byte() {
+        let cli = Cli::try_parse_from(["memcli", "touch", "--priority", "200", "report:q3"]).unwrap();
+        assert!(matches!(cli.command, Commands::Touch { ref target, priority: 200, key: false } if target == "report:q3"));
+        assert!(Cli::try_parse_from(["memcli", "touch", "report:q3"]).is_err());
+        assert!(Cli::try_parse_from(["memcli", "touch", "--priority", "256", "report:q3"]).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_all_has_an_alias() {
+        for name in ["reconnect-all", "connect-all-trusted"] {
+            let cli = Cli::try_parse_from(["memcli", "peer", name, "--offer-storage", "256mb"]).unwrap();
+            assert!(matches!(cli.command, Commands::Peer { action: PeerAction::ReconnectAll { offer_storage: Some(_) } }));
+        }
+    }
+
+    #[test]
+    fn test_consent_approve_all_needs_a_device() {
+        assert!(Cli::try_parse_from(["memcli", "consent", "approve", "--all"]).is_err());
+        assert!(Cli::try_parse_from(["memcli", "consent", "approve", "abc", "--all", "--from", "laptop"]).is_err());
+        let cli = Cli::try_parse_from(["memcli", "consent", "approve", "--all", "--from", "Laptop", "--trust"]).unwrap();
+        assert!(matches!(cli.command, Commands::Consent { action: Some(ConsentAction::Approve { session_id: None, trust: true, all: true, .. }) }));
+
+        let req = memsdk::PendingConsent {
+            session_id: "s".to_string(),
+            peer_pubkey: "3fa9c1d2".repeat(8),
+            peer_name: "laptop".to_string(),
+            quota: 0,
+            allowed_quota: 0,
+            created_at: 0,
+        };
+        assert!(consent_from(&req, "LAPTOP") && consent_from(&req, "3FA9C1D2") && consent_from(&req, &req.peer_pubkey));
+        assert!(!consent_from(&req, "lap") && !consent_from(&req, "") && !consent_from(&req, "3fa9c1") && !consent_from(&req, "3fa9c1d2x"));
+    }
+
+    #[test]
+    fn test_format_ago() {
+        let now = 1_700_000_000;
+        assert_eq!(format_ago(now - 5, now), "just now");
+        assert_eq!(format_ago(now - 60, now), "1 minute ago");
+        assert_eq!(format_ago(now - 3 * 86400 - 100, now), "3 days ago");
+        assert_eq!(format_ago(now + 10, now), "just now");
+    }
+
+    #[test]
+    fn test_process_detection() {
+        assert!(is_process_running(std::process::id() as i32));
+        // A child that has exited and been waited for is gone on every platform
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id() as i32;
+        child.wait().unwrap();
+        assert!(!is_process_running(pid));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00Z");
+        assert_eq!(format_timestamp(951_782_400_000), "2000-02-29 00:00:00Z");
+        assert_eq!(format_timestamp(1_700_000_000_999), "2023-11-14 22:13:20Z");
+    }
+}
@@ -0,0 +1,69 @@
+//! How much the CLI prints. `--quiet` keeps results and errors only, so scripts
+//! can capture them; `--verbose` adds diagnostics and debug-level SDK logs.
+//! Block IDs print as `blk_...` unless `--raw-ids` asks for decimals.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (_, true) => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        }
+    }
+
+    pub fn is_quiet(self) -> bool {
+        self == Verbosity::Quiet
+    }
+
+    pub fn is_verbose(self) -> bool {
+        self == Verbosity::Verbose
+    }
+
+    /// Log filter used unless RUST_LOG is set.
+    pub fn log_filter(self) -> &'static str {
+        if self.is_verbose() { "debug" } else { "error" }
+    }
+}
+
+static RAW_IDS: AtomicBool = AtomicBool::new(false);
+
+/// Print block IDs in decimal, as `--raw-ids` asks, instead of `blk_...`.
+pub fn set_raw_ids(raw: bool) {
+    RAW_IDS.store(raw, Ordering::Relaxed);
+}
+
+/// A block ID as the CLI prints it.
+pub fn show_id(id: memsdk::BlockId) -> String {
+    if RAW_IDS.load(Ordering::Relaxed) {
+        id.to_string()
+    } else {
+        memsdk::ShortId(id).to_string()
+    }
+}
+
+/// Progress lines, hints and timings: dropped with `--quiet`.
+macro_rules! status {
+    ($out:expr, $($arg:tt)*) => {
+        if !$out.is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Diagnostics only shown with `--verbose`.
+macro_rules! detail {
+    ($out:expr, $($arg:tt)*) => {
+        if $out.is_verbose() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,132 @@
+//! How `load` and `get` print a value. UTF-8 values are printed inline as
+//! before; anything else gets a short preview unless the bytes are asked for
+//! with `--raw` or `--out`, so binary data never lands on the terminal as-is.
+
+use crate::output::Verbosity;
+use anyhow::{Context, Result};
+use memsdk::Durability;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ValueOutput {
+    /// Write exactly the value's bytes to stdout; timing goes to stderr
+    #[arg(long, conflicts_with_all = ["out", "hex", "base64"])]
+    pub raw: bool,
+    /// Write the value to this file and print a summary
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["hex", "base64"])]
+    pub out: Option<PathBuf>,
+    /// Preview the value as a hex dump (default for non-UTF-8 values)
+    #[arg(long, conflicts_with = "base64")]
+    pub hex: bool,
+    /// Preview the value as base64
+    #[arg(long)]
+    pub base64: bool,
+    /// Bytes shown by --hex/--base64 previews
+    #[arg(long, value_name = "N", default_value_t = 256)]
+    pub preview_bytes: usize,
+    /// Also print the durability mode the value is held under
+    #[arg(long, short = 'l')]
+    pub long: bool,
+}
+
+/// Print `data` fetched as `label` (e.g. "Get 'user:1'"). `inline` formats
+/// UTF-8 values in the caller's usual one-line style.
+pub fn emit(data: &[u8], opts: &ValueOutput, out: Verbosity, label: &str, took: Duration, inline: impl FnOnce(&str) -> String) -> Result<()> {
+    if opts.raw {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(data)?;
+        stdout.flush()?;
+        if !out.is_quiet() {
+            eprintln!("{}: {} bytes (took {:?})", label, data.len(), took);
+        }
+        return Ok(());
+    }
+    if let Some(path) = &opts.out {
+        std::fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+        status!(out, "{}: wrote {} bytes to {} (took {:?})", label, data.len(), path.display(), took);
+        return Ok(());
+    }
+    let text = if opts.hex || opts.base64 { None } else { std::str::from_utf8(data).ok() };
+    if let Some(text) = text {
+        if out.is_quiet() {
+            println!("{}", text);
+        } else {
+            println!("{}", inline(text));
+        }
+        return Ok(());
+    }
+
+    let shown = &data[..data.len().min(opts.preview_bytes)];
+    status!(out, "{}: {} bytes{} (took {:?})", label, data.len(), if opts.hex || opts.base64 { "" } else { " of binary data" }, took);
+    if opts.base64 {
+        println!("{}", base64(shown));
+    } else {
+        print!("{}", hex_dump(shown));
+    }
+    if shown.len() < data.len() {
+        status!(out, "... {} more bytes (use --raw or --out for the full value)", data.len() - shown.len());
+    }
+    Ok(())
+}
+
+/// With `--long`, print the mode after the value (on stderr with `--raw`).
+pub fn emit_mode(durability: Option<Durability>, opts: &ValueOutput) {
+    if !opts.long {
+        return;
+    }
+    let mode = match durability {
+        Some(Durability::Pinned) => "pinned".to_string(),
+        Some(Durability::Cache) => "cache".to_string(),
+        Some(Durability::Ttl { secs }) => format!("ttl:{}", secs),
+        None => "unknown (node did not report it)".to_string(),
+    };
+    if opts.raw {
+        eprintln!("Mode: {}", mode);
+    } else {
+        println!("Mode: {}", mode);
+    }
+}
+
+/// Sixteen bytes per line, with offsets and printable ASCII on the right.
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        dump.push_str(&format!("{:08x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii));
+    }
+    dump
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_previews() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(hex_dump(b"ab\x00"), format!("00000000  {:<47}  |ab.|\n", "61 62 00"));
+        assert_eq!(hex_dump(&[0u8; 17]).lines().nth(1).unwrap().split_whitespace().next(), Some("00000010"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Bytes allocated by a 64MB Store and Load through a node's RPC socket, in
+//! multiples of the payload, for both ends (client and node share the process).
+//! Run with `cargo bench -p memnode --bench store_load`.
+
+use memnode::Node;
+use memsdk::{Durability, MemCloudClient};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const MB: usize = 1024 * 1024;
+const PAYLOAD: usize = 64 * MB;
+const ROUNDS: usize = 5;
+
+struct Counting;
+
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    // A grown buffer is a fresh allocation the old contents are copied into
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()) as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Default)]
+struct Sample {
+    allocated: u64,
+    took: Duration,
+}
+
+impl Sample {
+    fn add(&mut self, allocated: u64, took: Duration) {
+        self.allocated += allocated;
+        self.took += took;
+    }
+
+    fn report(&self, what: &str) {
+        let per_round = self.allocated as f64 / ROUNDS as f64;
+        println!(
+            "{:<6} {:>6.2}x payload allocated ({:>7.1} MB), {:?} per round",
+            what,
+            per_round / PAYLOAD as f64,
+            per_round / MB as f64,
+            self.took / ROUNDS as u32,
+        );
+    }
+}
+
+async fn measure<T>(f: impl std::future::Future<Output = T>) -> (T, u64, Duration) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let out = f.await;
+    (out, ALLOCATED.load(Ordering::Relaxed) - before, start.elapsed())
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("memnode-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let node = Node::builder()
+        .name("bench")
+        .memory(4 * PAYLOAD as u64)
+        .port(0)
+        .socket(dir.join("bench.sock"))
+        .local_only(true)
+        .spawn()
+        .await
+        .expect("spawn node");
+    let mut client = MemCloudClient::connect_with_path(node.socket_path()).await.unwrap();
+    let payload = vec![7u8; PAYLOAD];
+
+    let (mut store, mut load) = (Sample::default(), Sample::default());
+    for _ in 0..ROUNDS {
+        let (id, allocated, took) = measure(client.store(&payload, Durability::Pinned)).await;
+        let id = id.unwrap();
+        store.add(allocated, took);
+
+        let (data, allocated, took) = measure(client.load(id)).await;
+        assert_eq!(data.unwrap().len(), PAYLOAD);
+        load.add(allocated, took);
+        client.free(id).await.unwrap();
+    }
+    println!("{} MB payload, {} rounds", PAYLOAD / MB, ROUNDS);
+    store.report("store");
+    load.report("load");
+
+    node.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
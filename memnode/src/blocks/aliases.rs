@@ -0,0 +1,199 @@
+//! Several keys naming one block, and moving a value to another key
+//! (`SdkCommand::Alias`, `SdkCommand::Rename`).
+//!
+//! Both only rebind keys; the data never moves or is copied. `indexed_ids`
+//! holds every key naming a block, the one it was stored under first, and a
+//! block stays stored while any of them is set: flushing one name of an
+//! aliased block leaves the data to the others. A key is bound to an existing
+//! block only while that block still has the name it was looked up by, checked
+//! under the block's `indexed_ids` entry, so a rename racing a flush of its
+//! source cannot revive a block the flush is freeing. Bindings take the
+//! `key_index` entry of the key first and then `indexed_ids`, never the other
+//! way round.
+
+use anyhow::{bail, Result};
+use dashmap::mapref::entry::Entry;
+use log::info;
+
+use super::{BlockId, InMemoryBlockManager};
+
+impl InMemoryBlockManager {
+    /// Record `key` as a name of the new block `id`.
+    pub(super) fn name_block(&self, id: BlockId, key: &str) {
+        let mut names = self.indexed_ids.entry(id).or_default();
+        if !names.iter().any(|k| k == key) {
+            names.push(key.to_string());
+        }
+    }
+
+    /// Record `key` as another name of `id`, if `id` is still named `existing`.
+    fn name_alongside(&self, id: BlockId, existing: &str, key: &str) -> bool {
+        let Some(mut names) = self.indexed_ids.get_mut(&id) else {
+            return false;
+        };
+        if !names.iter().any(|k| k == existing) {
+            return false;
+        }
+        if !names.iter().any(|k| k == key) {
+            names.push(key.to_string());
+        }
+        true
+    }
+
+    /// `key` no longer names `id`. Returns whether other keys still do.
+    pub(super) fn unname_block(&self, id: BlockId, key: &str) -> bool {
+        let Entry::Occupied(mut names) = self.indexed_ids.entry(id) else {
+            return false;
+        };
+        names.get_mut().retain(|k| k != key);
+        if names.get().is_empty() {
+            names.remove();
+            return false;
+        }
+        true
+    }
+
+    /// Unbind `key` if it still names `id`. Returns `None` if it does not, or
+    /// else whether other keys still name `id`.
+    pub(super) fn unbind_key(&self, key: &str, id: BlockId) -> Option<bool> {
+        let still_named = match self.key_index.entry(key.to_string()) {
+            Entry::Occupied(slot) if *slot.get() == id => {
+                let still_named = self.unname_block(id, key);
+                slot.remove();
+                still_named
+            }
+            _ => return None,
+        };
+        self.unorder_key(key);
+        Some(still_named)
+    }
+
+    /// The key `id` was first stored under, or the oldest name left.
+    pub fn key_of(&self, id: BlockId) -> Option<String> {
+        self.indexed_ids.get(&id).and_then(|names| names.first().cloned())
+    }
+
+    /// Every key naming `id`, oldest first.
+    pub fn names_of(&self, id: BlockId) -> Vec<String> {
+        self.indexed_ids.get(&id).map(|names| names.clone()).unwrap_or_default()
+    }
+
+    /// Bind `to` to the block at `from` and unbind `from`. A block `to` named
+    /// before is let go as by a `set`; without `overwrite` that fails instead.
+    pub fn rename_key(&self, from: &str, to: &str, overwrite: bool) -> Result<BlockId> {
+        let Some(id) = self.get_named_block_id(from) else {
+            bail!("Key '{}' not found", from);
+        };
+        if from == to {
+            return Ok(id);
+        }
+        let replaced = match self.key_index.entry(to.to_string()) {
+            Entry::Occupied(_) if !overwrite => bail!("Key '{}' already exists", to),
+            Entry::Occupied(mut slot) => {
+                if !self.name_alongside(id, from, to) {
+                    bail!("Key '{}' not found", from);
+                }
+                let old = slot.insert(id);
+                (old != id).then(|| {
+                    self.unname_block(old, to);
+                    old
+                })
+            }
+            Entry::Vacant(slot) => {
+                if !self.name_alongside(id, from, to) {
+                    bail!("Key '{}' not found", from);
+                }
+                self.order_key(to);
+                slot.insert(id);
+                None
+            }
+        };
+        if let Some(old) = replaced {
+            self.detach_tags(old, to);
+        }
+        // Unless `from` was set again meanwhile; then that value stays
+        if self.unbind_key(from, id).is_some() {
+            self.retag_key(id, from, to);
+        }
+        info!("Renamed key '{}' -> '{}' ({})", from, to, id);
+        Ok(id)
+    }
+
+    /// Bind `alias` to the block at `key` as well.
+    pub fn alias_key(&self, key: &str, alias: &str) -> Result<BlockId> {
+        let Some(id) = self.get_named_block_id(key) else {
+            bail!("Key '{}' not found", key);
+        };
+        match self.key_index.entry(alias.to_string()) {
+            Entry::Occupied(_) => bail!("Key '{}' already exists", alias),
+            Entry::Vacant(slot) => {
+                if !self.name_alongside(id, key, alias) {
+                    bail!("Key '{}' not found", key);
+                }
+                self.order_key(alias);
+                slot.insert(id);
+            }
+        }
+        info!("Aliased key '{}' -> '{}' ({})", alias, key, id);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TestNode;
+    use memsdk::{BlockOrKey, Durability};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rename_and_alias_keep_the_index_consistent() {
+        let node = TestNode::spawn("aliases", 1 << 20).await;
+        let bm = node.block_manager.clone();
+
+        bm.set("a", b"one".to_vec(), Durability::Pinned).unwrap();
+        bm.set("b", b"two".to_vec(), Durability::Pinned).unwrap();
+        let err = bm.rename_key("a", "b", false).unwrap_err();
+        assert_eq!(err.to_string(), "Key 'b' already exists");
+        let id = bm.rename_key("a", "c", false).unwrap();
+        assert_eq!(bm.get_named_block_id("a"), None);
+        assert_eq!(bm.list_keys_page("*", None, 10).0, vec!["b", "c"]);
+
+        // Flushing one name of an aliased block leaves the data to the other
+        bm.alias_key("c", "d").unwrap();
+        assert!(bm.alias_key("b", "d").is_err());
+        let info = bm.block_info(&BlockOrKey::Key { key: "d".to_string() }).unwrap();
+        assert_eq!((info.key.as_deref(), info.aliases), (Some("c"), vec!["d".to_string()]));
+        assert_eq!(bm.flush_filtered(Some("c"), None).blocks_removed, 0);
+        assert_eq!(bm.get_named_block_id("d"), Some(id));
+        assert_eq!(bm.flush_filtered(Some("d"), None).blocks_removed, 1);
+
+        // Renames racing sets and flushes of the same keys: every key left
+        // names a live block that lists it, and every name is in the index
+        let tasks: Vec<_> = (0..4u8).map(|t| {
+            let bm = bm.clone();
+            tokio::spawn(async move {
+                for i in 0..300u32 {
+                    let (x, y) = (format!("k{}", i % 5), format!("k{}", (i + t as u32) % 5));
+                    match (i + t as u32) % 4 {
+                        0 => { bm.set(&x, vec![t], Durability::Pinned).unwrap(); }
+                        1 => { let _ = bm.rename_key(&x, &y, t % 2 == 0); }
+                        2 => { let _ = bm.alias_key(&x, &y); }
+                        _ => { bm.flush_filtered(Some(&y), None); }
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for key in bm.list_keys("*") {
+            let id = bm.get_named_block_id(&key).unwrap();
+            assert!(bm.names_of(id).contains(&key), "{} -> {}", key, id);
+        }
+        for entry in bm.indexed_ids.iter() {
+            for key in entry.value() {
+                assert_eq!(bm.get_named_block_id(key), Some(*entry.key()), "{}", key);
+            }
+        }
+    }
+}
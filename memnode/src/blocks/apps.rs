@@ -0,0 +1,34 @@
+//! Which client application stored each local block.
+//!
+//! RPC commands run inside [`CURRENT_APP`], set from the connection's
+//! `ClientHello` (or "unknown" until one arrives), and `put_block` records the
+//! owner of every block it inserts. Usage is summed from the live blocks when
+//! asked for, so freed, evicted and expired blocks drop out on their own.
+//! Blocks stored on behalf of peers have no owner and are not reported.
+
+use std::sync::Arc;
+
+/// Application name for connections that never sent `ClientHello`.
+pub const UNKNOWN_APP: &str = "unknown";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AppTag {
+    pub name: String,
+    pub pid: Option<u32>,
+}
+
+impl AppTag {
+    pub fn unknown() -> Self {
+        Self { name: UNKNOWN_APP.to_string(), pid: None }
+    }
+}
+
+tokio::task_local! {
+    /// The application a command is running for.
+    pub static CURRENT_APP: Arc<AppTag>;
+}
+
+/// The application behind the command being executed, if any.
+pub fn current_app() -> Option<Arc<AppTag>> {
+    CURRENT_APP.try_with(|app| app.clone()).ok()
+}
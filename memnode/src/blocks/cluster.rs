@@ -0,0 +1,103 @@
+//! Commands that reach every connected peer at once.
+//!
+//! `ClusterStat`: this node's counts next to those of every connected peer.
+//! Peers are asked with a `StatRequest` and answer from counters they keep
+//! anyway, so a slow or busy peer costs at most `CLUSTER_STAT_TIMEOUT`.
+//!
+//! `FlushCluster`: a `Flush` sent to every peer concurrently, each given
+//! `CLUSTER_FLUSH_TIMEOUT` to confirm. Nothing is kept between runs, so a
+//! client that lost the reply, or saw some peers fail, just runs it again.
+
+use memsdk::{ClusterNodeStat, ClusterNodeStatus, ClusterStats, Durability, FlushOutcome};
+use std::time::Duration;
+
+use super::{BlockManager, InMemoryBlockManager};
+use crate::net::NodeStat;
+use crate::peers::{PeerStatReply, FLUSH_ACK_TIMEOUT};
+
+/// How long `FlushCluster` waits for one peer, sending included.
+pub const CLUSTER_FLUSH_TIMEOUT: Duration = FLUSH_ACK_TIMEOUT.saturating_add(Duration::from_secs(5));
+
+impl InMemoryBlockManager {
+    /// What this node answers to a peer's `StatRequest`. Only reads atomics
+    /// and map lengths.
+    pub fn node_stat(&self) -> NodeStat {
+        NodeStat {
+            blocks: self.blocks.len() as u64,
+            used_memory: self.used_space(),
+            max_memory: self.get_max_memory(),
+            peers: self.peer_manager.peer_count() as u64,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// This node, then every peer by name, with totals over those that answered.
+    pub async fn cluster_stats(&self) -> ClusterStats {
+        let local = node_row(self.peer_manager.get_self_id().to_string(), self.peer_manager.get_self_name(), true, PeerStatReply::Answered(self.node_stat()));
+        let mut nodes = vec![local];
+        for (id, name, reply) in self.peer_manager.request_stats().await {
+            nodes.push(node_row(id.to_string(), name, false, reply));
+        }
+        let mut stats = ClusterStats::default();
+        for node in nodes.iter().filter(|n| n.status == ClusterNodeStatus::Ok) {
+            stats.blocks += node.blocks;
+            stats.memory_usage += node.memory_usage;
+            stats.max_memory += node.max_memory;
+        }
+        stats.nodes = nodes;
+        stats
+    }
+
+    /// Flush every connected peer at once, then this node with
+    /// `include_local`. A peer that fails or does not confirm in time is
+    /// reported with its error and does not hold up the others.
+    pub async fn flush_cluster(&self, include_local: bool, pattern: Option<String>, durability: Option<Durability>) -> Vec<FlushOutcome> {
+        let mut peers = self.peer_manager.get_peer_metadata_list();
+        peers.sort_by(|a, b| a.name.cmp(&b.name));
+        let flushes = peers.into_iter().map(|peer| {
+            let pattern = pattern.clone();
+            async move {
+                let res = match tokio::time::timeout(CLUSTER_FLUSH_TIMEOUT, self.flush_remote(peer.id, pattern, durability)).await {
+                    Ok(res) => res,
+                    Err(_) => Err(anyhow::anyhow!("No flush acknowledgment within {}s", CLUSTER_FLUSH_TIMEOUT.as_secs())),
+                };
+                match res {
+                    Ok(stats) => FlushOutcome { node: peer.name, local: false, stats: Some(stats), error: None },
+                    Err(e) => FlushOutcome { node: peer.name, local: false, stats: None, error: Some(e.to_string()) },
+                }
+            }
+        });
+        let mut results = futures::future::join_all(flushes).await;
+        if include_local {
+            let stats = self.flush_filtered(pattern.as_deref(), durability);
+            results.push(FlushOutcome { node: self.peer_manager.get_self_name(), local: true, stats: Some(stats), error: None });
+        }
+        results
+    }
+}
+
+fn node_row(id: String, name: String, local: bool, reply: PeerStatReply) -> ClusterNodeStat {
+    let mut row = ClusterNodeStat {
+        id,
+        name,
+        local,
+        status: ClusterNodeStatus::Ok,
+        blocks: 0,
+        memory_usage: 0,
+        max_memory: 0,
+        peers: 0,
+        version: None,
+    };
+    match reply {
+        PeerStatReply::Answered(stat) => {
+            row.blocks = stat.blocks;
+            row.memory_usage = stat.used_memory;
+            row.max_memory = stat.max_memory;
+            row.peers = stat.peers;
+            row.version = Some(stat.version);
+        }
+        PeerStatReply::Unreachable => row.status = ClusterNodeStatus::Unreachable,
+        PeerStatReply::Unsupported => row.status = ClusterNodeStatus::Unsupported,
+    }
+    row
+}
@@ -0,0 +1,142 @@
+//! Read-modify-write on keys (`SdkCommand::Incr` and `SdkCommand::Append`).
+//!
+//! Each update holds the key's `key_index` entry for its whole duration, so
+//! concurrent updates to the same key run one after another while other keys
+//! are unaffected. The block is locked inside that entry, never the other way
+//! round. A key that is missing, expired or evicted starts out empty and is
+//! created pinned; an existing key keeps its mode and expiry. A key whose
+//! value was migrated to a peer is refused rather than started afresh.
+
+use anyhow::{anyhow, bail, Result};
+use dashmap::mapref::entry::Entry;
+use std::sync::atomic::Ordering;
+
+use super::{unix_now, BlockManager, InMemoryBlockManager};
+
+/// Counters are stored as little-endian `i64`s.
+pub const COUNTER_SIZE: usize = 8;
+
+impl InMemoryBlockManager {
+    /// Add `delta` to the counter at `key`, creating it at `delta`, and return the new value.
+    pub fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        self.update_key(key, COUNTER_SIZE as u64, |data, created| {
+            let current = if created {
+                0
+            } else {
+                let bytes: [u8; COUNTER_SIZE] = data.as_slice().try_into()
+                    .map_err(|_| anyhow!("Value of '{}' is {} bytes, not an {}-byte counter", key, data.len(), COUNTER_SIZE))?;
+                i64::from_le_bytes(bytes)
+            };
+            let value = current.checked_add(delta).ok_or_else(|| anyhow!("Counter '{}' would overflow", key))?;
+            *data = value.to_le_bytes().to_vec();
+            Ok(value)
+        })
+    }
+
+    /// Append `extra` to the value at `key`, creating it, and return the new length.
+    pub fn append(&self, key: &str, extra: &[u8]) -> Result<u64> {
+        self.update_key(key, extra.len() as u64, |data, _| {
+            data.extend_from_slice(extra);
+            Ok(data.len() as u64)
+        })
+    }
+
+    // `update` gets the current value (empty and `true` for a new key) and must
+    // leave it untouched when it fails. `growth` bounds how much it adds.
+    fn update_key<T>(&self, key: &str, growth: u64, update: impl FnOnce(&mut Vec<u8>, bool) -> Result<T>) -> Result<T> {
+        self.memory_guard.check_store()?;
+        let current = self.current_memory.load(Ordering::Relaxed);
+        if current + growth > self.max_memory {
+            let needed = current + growth - self.max_memory;
+            if self.evict_garbage(needed) < needed {
+                bail!("Out of Memory: cannot grow '{}' by {} bytes", key, growth);
+            }
+        }
+
+        let entry = self.key_index.entry(key.to_string());
+        let mut stale = None;
+        if let Entry::Occupied(slot) = &entry {
+            let id = *slot.get();
+            if let Some(peer_id) = self.remote_locations.get(&id).map(|l| l.0) {
+                let peer = self.peer_manager.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string());
+                bail!("'{}' was migrated to peer {}; migrate it back (memcli migrate {} --from {}) before updating it", key, peer, key, peer);
+            }
+            if self.is_expired(id, unix_now()) {
+                self.evict_block(id)?;
+            } else if let Some(mut block) = self.blocks.get_mut(&id) {
+                let before = block.data.len() as u64;
+                // Copies only while a reader still holds the old value
+                let mut data = Vec::from(std::mem::take(&mut block.data));
+                let result = update(&mut data, false);
+                block.data = data.into();
+                let result = result?;
+                let after = block.data.len() as u64;
+                block.last_accessed.store(unix_now(), Ordering::Relaxed);
+                let durability = block.durability;
+                drop(block);
+                if after >= before {
+                    self.current_memory.fetch_add(after - before, Ordering::Relaxed);
+                    self.durability_bytes(durability).fetch_add(after - before, Ordering::Relaxed);
+                } else {
+                    self.current_memory.fetch_sub(before - after, Ordering::Relaxed);
+                    self.durability_bytes(durability).fetch_sub(before - after, Ordering::Relaxed);
+                }
+                return Ok(result);
+            }
+            stale = Some(id);
+        }
+
+        let mut data = Vec::new();
+        let result = update(&mut data, true)?;
+        let (id, _) = self.put_block(self.new_block(data, memsdk::Durability::Pinned))?;
+        if stale.is_none() {
+            self.order_key(key);
+        }
+        self.name_block(id, key);
+        if let Some(old) = stale {
+            self.unname_block(old, key);
+        }
+        entry.insert(id);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerManager;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_incr_and_append_are_exact() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = Arc::new(InMemoryBlockManager::new(pm, 1024 * 1024));
+        let tasks: Vec<_> = (0..100).map(|i| {
+            let bm = bm.clone();
+            tokio::spawn(async move {
+                bm.incr("hits", 1).unwrap();
+                bm.append("log", &[i as u8]).unwrap();
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(bm.incr("hits", 0).unwrap(), 100);
+        assert_eq!(bm.incr("hits", -101).unwrap(), -1);
+        let id = bm.get_named_block_id("log").unwrap();
+        let mut log = bm.get_block(id).unwrap().unwrap().data.to_vec();
+        log.sort_unstable();
+        assert_eq!(log, (0..100).collect::<Vec<u8>>());
+        assert_eq!(bm.used_space(), (COUNTER_SIZE + 100) as u64);
+
+        // Not a counter, and overflow: both fail and leave the value alone
+        bm.set("name", b"alice".to_vec(), memsdk::Durability::Cache).unwrap();
+        assert!(bm.incr("name", 1).is_err());
+        bm.incr("max", i64::MAX).unwrap();
+        assert!(bm.incr("max", 1).is_err());
+        assert_eq!(bm.incr("max", 0).unwrap(), i64::MAX);
+        assert_eq!(bm.append("name", b"!").unwrap(), 6);
+        assert_eq!(bm.usage_by_durability().1, 6);
+    }
+}
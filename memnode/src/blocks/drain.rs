@@ -0,0 +1,218 @@
+//! Taking back what this node stored on a peer before disconnecting from it
+//! (`SdkCommand::Disconnect` with `drain`).
+//!
+//! Each block is fetched from the peer and goes to another peer with room if
+//! there is one, so local memory stays under its limit, and is kept here
+//! otherwise. Reads only move to the new copy once it is in place, and the
+//! peer's copy is released after that. When no blocks are left on the peer,
+//! apart from those that failed, the node says Bye. A forced disconnect stops
+//! the drain between two blocks.
+
+use anyhow::{bail, Result};
+use dashmap::mapref::entry::Entry;
+use log::{info, warn};
+use memsdk::{DrainFailure, DrainState, DrainStatus, Durability};
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::{Block, BlockId, BlockManager, InMemoryBlockManager};
+use crate::net::Message;
+
+impl InMemoryBlockManager {
+    /// Start draining `target` in the background, or return the drain of it
+    /// that is already running.
+    pub fn start_drain(self: &Arc<Self>, target: &str) -> Result<DrainStatus> {
+        let peer_id = self.peer_manager.resolve_peer(target)?;
+        let status = match self.drains.entry(peer_id) {
+            Entry::Occupied(e) if e.get().state == DrainState::Running => return Ok(e.get().clone()),
+            entry => {
+                let status = DrainStatus {
+                    state: DrainState::Running,
+                    peer: self.peer_manager.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string()),
+                    blocks_total: self.blocks_on(peer_id).len() as u64,
+                    blocks_done: 0,
+                    bytes_done: 0,
+                    blocks_moved: 0,
+                    failed: Vec::new(),
+                };
+                entry.insert(status.clone());
+                status
+            }
+        };
+        info!("Draining {} blocks from peer {} before disconnecting", status.blocks_total, peer_id);
+
+        let bm = self.clone();
+        tokio::spawn(async move { bm.drain(peer_id).await });
+        Ok(status)
+    }
+
+    /// The drain of a peer, by name or id; it may have been disconnected since.
+    pub fn drain_status(&self, target: &str) -> Option<DrainStatus> {
+        if let Ok(peer_id) = self.peer_manager.resolve_peer(target) {
+            if let Some(status) = self.drains.get(&peer_id) {
+                return Some(status.clone());
+            }
+        }
+        self.drains.iter()
+            .find(|d| d.key().to_string() == target || d.value().peer == target)
+            .map(|d| d.value().clone())
+    }
+
+    pub fn is_draining(&self, peer_id: Uuid) -> bool {
+        self.drains.get(&peer_id).is_some_and(|d| d.state == DrainState::Running)
+    }
+
+    /// Stop a running drain of `peer_id`, listing the blocks it did not get to
+    /// as failed. The caller disconnects.
+    pub fn abort_drain(&self, peer_id: Uuid) {
+        let left = self.blocks_on(peer_id);
+        if let Some(mut status) = self.drains.get_mut(&peer_id) {
+            if status.state == DrainState::Running {
+                status.state = DrainState::Aborted;
+                let failed: HashSet<BlockId> = status.failed.iter().map(|f| f.id).collect();
+                for (id, _) in left.into_iter().filter(|(id, _)| !failed.contains(id)) {
+                    status.failed.push(DrainFailure { id, error: "Drain aborted by a forced disconnect".to_string() });
+                }
+                warn!("Drain of peer {} aborted with {} blocks left there", peer_id, status.failed.len());
+            }
+        }
+    }
+
+    /// Blocks we stored on `peer_id`, with their sizes.
+    fn blocks_on(&self, peer_id: Uuid) -> Vec<(BlockId, u64)> {
+        self.remote_locations.iter().filter(|l| l.value().0 == peer_id).map(|l| (*l.key(), l.value().1)).collect()
+    }
+
+    async fn drain(&self, peer_id: Uuid) {
+        loop {
+            let Some(failed) = self.drains.get(&peer_id)
+                .filter(|d| d.state == DrainState::Running)
+                .map(|d| d.failed.iter().map(|f| f.id).collect::<HashSet<_>>()) else {
+                return;
+            };
+            // Blocks stored on the peer meanwhile are drained too
+            let left: Vec<_> = self.blocks_on(peer_id).into_iter().filter(|(id, _)| !failed.contains(id)).collect();
+            let Some(&(id, size)) = left.first() else {
+                break;
+            };
+            self.update_drain(peer_id, |s| s.blocks_total = s.blocks_done + s.failed.len() as u64 + left.len() as u64);
+
+            match self.drain_block(peer_id, id).await {
+                Ok(moved) => self.update_drain(peer_id, |s| {
+                    // An abort while it was under way listed it as left behind
+                    s.failed.retain(|f| f.id != id);
+                    s.blocks_done += 1;
+                    s.bytes_done += size;
+                    s.blocks_moved += moved as u64;
+                }),
+                Err(e) => {
+                    warn!("Could not drain block {} from peer {}: {}", id, peer_id, e);
+                    self.update_drain(peer_id, |s| s.failed.push(DrainFailure { id, error: e.to_string() }));
+                }
+            }
+        }
+
+        let Some(status) = self.drains.get(&peer_id).map(|d| d.clone()) else {
+            return;
+        };
+        if status.state != DrainState::Running {
+            return;
+        }
+        info!("Drained {} blocks from peer {} ({} to other peers, {} failed); disconnecting",
+            status.blocks_done, peer_id, status.blocks_moved, status.failed.len());
+        self.peer_manager.disconnect_peer(peer_id).await;
+        self.update_drain(peer_id, |s| s.state = DrainState::Done);
+    }
+
+    /// Move block `id` off `peer_id`. Returns true if it went to another peer,
+    /// false if it is now stored here.
+    async fn drain_block(&self, peer_id: Uuid, id: BlockId) -> Result<bool> {
+        let fut = self.peer_manager.wait_for_block(peer_id, id);
+        self.peer_manager.request_block(peer_id, id).await?;
+        let (data, durability, priority) = fut.await?;
+        let block = Block { id, priority: priority.unwrap_or(memsdk::DEFAULT_PRIORITY), ..self.new_block(data, durability.unwrap_or(Durability::Pinned)) };
+        let size = block.data.len() as u64;
+
+        let other = match self.peer_manager.get_available_peer_except(size, peer_id).await {
+            Ok(other) => match self.put_block_on_peer(other, block.clone()).await {
+                Ok(()) => Some(other),
+                Err(e) => {
+                    warn!("Could not move block {} to peer {}, keeping it here: {}", id, other, e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        match other {
+            Some(other) => {
+                if let Some((old, old_size)) = self.remote_locations.insert(id, (other, size)) {
+                    self.peer_manager.release_offloaded(old, old_size);
+                }
+                self.peer_manager.record_offloaded(other, size);
+            }
+            None => {
+                if let Err(e) = self.put_block(block) {
+                    bail!("No other peer took it and it does not fit here: {}", e);
+                }
+                self.forget_remote_block(id, peer_id);
+            }
+        }
+
+        // The new copy is in place; a failed release only leaves the old one behind
+        if let Err(e) = self.peer_manager.send_to_peer(peer_id, &Message::ReleaseBlock { id }).await {
+            warn!("Could not release block {} on peer {}: {}", id, peer_id, e);
+        }
+        Ok(other.is_some())
+    }
+
+    fn update_drain(&self, peer_id: Uuid, update: impl FnOnce(&mut DrainStatus)) {
+        if let Some(mut status) = self.drains.get_mut(&peer_id) {
+            update(&mut status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{wait_until, TestNode};
+    use memsdk::{DrainState, Durability};
+
+    #[tokio::test]
+    async fn test_drain_moves_blocks_then_disconnects() {
+        let a = TestNode::spawn("drain-a", 64 * 1024).await;
+        let b = TestNode::spawn("drain-b", 64 * 1024).await;
+        // Room for one of the two blocks
+        let c = TestNode::spawn("drain-c", 1500).await;
+        a.connect_to(&b, 0).await;
+        a.connect_to(&c, 0).await;
+
+        let bm = &a.block_manager;
+        let mut ids = Vec::new();
+        for byte in [1u8, 2] {
+            let block = crate::blocks::Block { priority: 40 + byte, ..bm.new_block(vec![byte; 1000], Durability::Pinned) };
+            ids.push(bm.put_block_remote(block, Some("drain-b".to_string())).await.unwrap().0);
+        }
+        wait_until(|| ids.iter().all(|id| b.block_manager.blocks.contains_key(id))).await;
+
+        bm.start_drain("drain-b").unwrap();
+        assert!(bm.disconnect_peer("drain-b", false).await.is_err(), "a plain disconnect waits for the drain");
+        wait_until(|| bm.drain_status("drain-b").is_some_and(|s| s.state != DrainState::Running)).await;
+
+        let status = bm.drain_status("drain-b").unwrap();
+        assert_eq!(status.state, DrainState::Done);
+        assert_eq!((status.blocks_total, status.blocks_done, status.blocks_moved), (2, 2, 1));
+        assert_eq!(status.bytes_done, 2000);
+        assert!(status.failed.is_empty());
+        assert!(a.peer_manager.peer_name(b.id).is_none());
+        wait_until(|| ids.iter().all(|id| !b.block_manager.blocks.contains_key(id))).await;
+        assert_eq!(ids.iter().filter(|id| bm.blocks.contains_key(id)).count(), 1);
+        assert_eq!(ids.iter().filter(|id| c.block_manager.blocks.contains_key(id)).count(), 1);
+        for (id, byte) in ids.iter().zip([1u8, 2]) {
+            let block = bm.get_block_async(*id).await.unwrap().unwrap();
+            assert_eq!(&block.data[..], &[byte; 1000][..]);
+            // Wherever it went, the block keeps its eviction priority
+            assert_eq!(block.priority, 40 + byte);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Streamed uploads with a target peer (`SdkCommand::StreamStart { target }`).
+//!
+//! Rather than buffering the whole upload and offloading it on StreamFinish,
+//! the node opens the block on the peer with `StreamBlockStart` and passes
+//! each client chunk on as `StreamBlockChunk`, so it only ever holds what is
+//! queued on the peer connection. `StreamBlockEnd` completes the block and the
+//! peer answers `BlockStored` or `PutBlockRejected`, like a migration push.
+//!
+//! The peer reserves the size hint up front and grows the reservation as the
+//! bytes sent pass it, so a stream that outgrows the quota is refused part-way
+//! instead of after all of it has been sent.
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use memsdk::Durability;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{broadcast, Mutex};
+use uuid::Uuid;
+
+use super::migrate::{IncomingBlock, BLOCK_ACK_TIMEOUT, MIGRATE_CHUNK_SIZE};
+use super::{BlockExists, BlockId, InMemoryBlockManager};
+use crate::net::Message;
+
+/// The peer refused a block streamed through this node.
+#[derive(Debug)]
+pub struct StreamRejected {
+    pub peer: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for StreamRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Peer '{}' rejected the stream: {}", self.peer, self.reason)
+    }
+}
+
+impl std::error::Error for StreamRejected {}
+
+/// Where an upload is being forwarded to.
+pub(crate) struct Forward {
+    peer_id: Uuid,
+    pub(super) peer_name: String,
+    id: BlockId,
+    size_hint: Option<u64>,
+    pub(super) sent: AtomicU64,
+    // Held while sending, which keeps the chunks of one stream in order
+    acks: Mutex<broadcast::Receiver<Result<(), String>>>,
+}
+
+impl Forward {
+    fn rejected(&self, reason: String) -> anyhow::Error {
+        StreamRejected { peer: self.peer_name.clone(), reason }.into()
+    }
+}
+
+impl InMemoryBlockManager {
+    pub(super) async fn start_forward(&self, target: &str, size_hint: Option<u64>) -> Result<Forward> {
+        let peer_id = self.peer_manager.resolve_peer(target)?;
+        self.check_read_back(size_hint.unwrap_or(0))?;
+        let id = self.allocate_id();
+        let acks = self.peer_manager.expect_block_ack(id);
+        let msg = Message::StreamBlockStart { id, reserve: size_hint.unwrap_or(0) };
+        if let Err(e) = self.peer_manager.send_to_peer(peer_id, &msg).await {
+            self.peer_manager.forget_block_ack(id);
+            return Err(e);
+        }
+        info!("Forwarding stream as block {} to peer {}", id, peer_id);
+        Ok(Forward {
+            peer_id,
+            peer_name: self.peer_manager.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string()),
+            id,
+            size_hint,
+            sent: AtomicU64::new(0),
+            acks: Mutex::new(acks),
+        })
+    }
+
+    /// Pass `data` on to the peer, waiting while the connection's queue is full.
+    pub(super) async fn forward_chunk(&self, forward: &Forward, data: &[u8]) -> Result<()> {
+        let mut acks = forward.acks.lock().await;
+        if let Ok(Err(reason)) = acks.try_recv() {
+            return Err(forward.rejected(reason));
+        }
+        for piece in data.chunks(MIGRATE_CHUNK_SIZE) {
+            let offset = forward.sent.load(Ordering::Relaxed);
+            let end = offset + piece.len() as u64;
+            self.check_read_back(end)?;
+            let msg = Message::StreamBlockChunk {
+                id: forward.id,
+                offset,
+                reserve: forward.size_hint.unwrap_or(0).max(end),
+                data: piece.to_vec(),
+            };
+            self.peer_manager.send_to_peer(forward.peer_id, &msg).await?;
+            forward.sent.store(end, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Complete the block on the peer and route reads for it there.
+    pub(super) async fn finish_forward(&self, forward: &Forward, durability: Durability, priority: u8) -> Result<(BlockId, u64)> {
+        let mut acks = forward.acks.lock().await;
+        let (id, size) = (forward.id, forward.sent.load(Ordering::Relaxed));
+        let res = async {
+            self.peer_manager.send_to_peer(forward.peer_id, &Message::StreamBlockEnd { id, size, durability, priority: Some(priority) }).await?;
+            match tokio::time::timeout(BLOCK_ACK_TIMEOUT, acks.recv()).await {
+                Ok(Ok(Ok(()))) => Ok(()),
+                Ok(Ok(Err(reason))) => Err(forward.rejected(reason)),
+                Ok(Err(e)) => bail!("Recv error: {}", e),
+                Err(_) => bail!("Timeout waiting for the peer to confirm block {}", id),
+            }
+        }.await;
+        self.peer_manager.forget_block_ack(id);
+        res?;
+
+        self.remote_locations.insert(id, (forward.peer_id, size));
+        self.peer_manager.record_offloaded(forward.peer_id, size);
+        info!("Stream forwarded as block {} ({} bytes) to peer {}", id, size, forward.peer_id);
+        Ok((id, size))
+    }
+
+    /// Give up on a forwarded upload after `err`. A peer that refused the
+    /// block has dropped it already.
+    pub(super) async fn fail_forward(&self, forward: &Forward, err: &anyhow::Error) {
+        if err.downcast_ref::<StreamRejected>().is_some() {
+            self.peer_manager.forget_block_ack(forward.id);
+        } else {
+            self.abort_forward(forward).await;
+        }
+    }
+
+    /// Drop the partial block on the peer.
+    pub(super) async fn abort_forward(&self, forward: &Forward) {
+        self.peer_manager.forget_block_ack(forward.id);
+        if let Err(e) = self.peer_manager.send_to_peer(forward.peer_id, &Message::ReleaseBlock { id: forward.id }).await {
+            warn!("Could not drop streamed block {} on peer {}: {}", forward.id, forward.peer_id, e);
+        }
+    }
+
+    /// `from` starts streaming block `id` to us, holding `reserve` bytes of its quota.
+    pub fn receive_stream_start(&self, from: Uuid, id: BlockId, reserve: u64) -> Result<()> {
+        // A retry starts over
+        self.abort_incoming(id);
+        if self.blocks.contains_key(&id) {
+            return Err(BlockExists(id).into());
+        }
+        if reserve > self.max_memory {
+            bail!("Block of {} bytes cannot fit in this node's {} byte capacity", reserve, self.max_memory);
+        }
+        if !self.peer_manager.try_reserve_storage(from, reserve) {
+            bail!("Quota exceeded");
+        }
+        let capacity = reserve.min(MIGRATE_CHUNK_SIZE as u64 * 64) as usize;
+        self.incoming.insert(id, IncomingBlock {
+            from,
+            key: None,
+            durability: Durability::Pinned,
+            priority: memsdk::DEFAULT_PRIORITY,
+            total: reserve,
+            data: Vec::with_capacity(capacity),
+        });
+        Ok(())
+    }
+
+    /// Take one piece of a streamed block, first growing its reservation to `reserve`.
+    pub fn receive_stream_chunk(&self, from: Uuid, id: BlockId, offset: u64, reserve: u64, data: Vec<u8>) -> Result<()> {
+        let Some(mut incoming) = self.incoming.get_mut(&id).filter(|i| i.from == from) else {
+            // Rest of a stream we already refused
+            return Ok(());
+        };
+        if incoming.data.len() as u64 != offset {
+            drop(incoming);
+            self.abort_incoming(id);
+            bail!("Chunk for block {} out of order", id);
+        }
+        if reserve > incoming.total {
+            let grown = reserve <= self.max_memory && self.peer_manager.try_reserve_storage(from, reserve - incoming.total);
+            if !grown {
+                drop(incoming);
+                self.abort_incoming(id);
+                bail!("Quota exceeded after {} bytes", offset);
+            }
+            incoming.total = reserve;
+        }
+        if offset + data.len() as u64 > incoming.total {
+            drop(incoming);
+            self.abort_incoming(id);
+            bail!("Chunk for block {} runs past the storage reserved for it", id);
+        }
+        incoming.data.extend_from_slice(&data);
+        Ok(())
+    }
+
+    /// Store a streamed block once `from` says it is complete at `size` bytes.
+    /// Returns false for a stream that was already refused.
+    pub fn receive_stream_end(&self, from: Uuid, id: BlockId, size: u64, durability: Durability, priority: u8) -> Result<bool> {
+        let Some((_, mut incoming)) = self.incoming.remove_if(&id, |_, i| i.from == from) else {
+            return Ok(false);
+        };
+        if incoming.data.len() as u64 != size {
+            self.peer_manager.release_storage(from, incoming.total);
+            bail!("Stream for block {} ended at {} bytes but {} arrived", id, size, incoming.data.len());
+        }
+        // Give back whatever the size hint reserved beyond the actual size
+        self.peer_manager.release_storage(from, incoming.total - size);
+        incoming.total = size;
+        incoming.durability = durability;
+        incoming.priority = priority;
+        self.store_incoming(id, incoming)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocks::BlockManager;
+    use crate::test_support::{wait_until, TestNode};
+    use memsdk::Durability;
+
+    #[tokio::test]
+    async fn test_forwarded_stream_stays_bounded_on_the_forwarding_node() {
+        let a = TestNode::spawn("fwd-a", 16 * 1024 * 1024).await;
+        let b = TestNode::spawn("fwd-b", 256 * 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+
+        // Far more than a can hold
+        let chunk = vec![7u8; 256 * 1024];
+        let total = 48 * 1024 * 1024u64;
+        let bm = &a.block_manager;
+        let stream_id = bm.start_stream(Some(total), Some(b.id.to_string())).await.unwrap();
+        let mut sent = 0;
+        while sent < total {
+            bm.append_stream(stream_id, chunk.clone()).await.unwrap();
+            sent += chunk.len() as u64;
+            let info = bm.list_streams().pop().unwrap();
+            assert_eq!(info.bytes_buffered, 0);
+            assert_eq!(info.bytes_forwarded, sent);
+            assert_eq!(bm.used_space(), 0);
+        }
+        let (id, size) = bm.finish_stream(stream_id, None, Durability::Pinned, memsdk::DEFAULT_PRIORITY).await.unwrap();
+        assert_eq!(size, total);
+        assert_eq!(b.block_manager.blocks.get(&id).unwrap().data.len() as u64, total);
+        assert_eq!(*bm.remote_locations.get(&id).unwrap(), (b.id, total));
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_stream_is_refused_once_over_quota() {
+        let a = TestNode::spawn("fwdq-a", 16 * 1024 * 1024).await;
+        let b = TestNode::spawn("fwdq-b", 4 * 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let bm = &a.block_manager;
+
+        // A hint past the quota is refused as soon as the first chunk goes out
+        let stream_id = bm.start_stream(Some(8 * 1024 * 1024), Some(b.id.to_string())).await.unwrap();
+        let mut res = Ok(());
+        for _ in 0..64 {
+            res = bm.append_stream(stream_id, vec![1u8; 64 * 1024]).await;
+            if res.is_err() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let err = res.unwrap_err();
+        assert!(err.downcast_ref::<super::StreamRejected>().is_some(), "{}", err);
+        assert!(bm.list_streams().is_empty());
+
+        // Without a hint, the stream goes until it outgrows the quota; nothing is left reserved on b
+        let stream_id = bm.start_stream(None, Some(b.id.to_string())).await.unwrap();
+        let mut res = Ok(());
+        for _ in 0..128 {
+            res = bm.append_stream(stream_id, vec![1u8; 64 * 1024]).await;
+            if res.is_err() {
+                break;
+            }
+        }
+        let err = match res {
+            Ok(()) => bm.finish_stream(stream_id, None, Durability::Pinned, memsdk::DEFAULT_PRIORITY).await.unwrap_err(),
+            Err(e) => e,
+        };
+        assert!(err.to_string().contains("Quota exceeded"), "{}", err);
+        wait_until(|| b.peer_manager.remote_used_storage(a.id) == Some(0)).await;
+    }
+
+    #[tokio::test]
+    async fn test_blocks_too_large_to_read_back_are_not_sent() {
+        let a = TestNode::spawn("frame-a", 16 * 1024 * 1024).await;
+        let b = TestNode::spawn("frame-b", 16 * 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let bm = &a.block_manager;
+        let max_block = 1024 * 1024;
+        a.peer_manager.set_max_peer_frame(max_block + crate::net::frame::BLOCK_FRAME_OVERHEAD);
+
+        let err = bm.start_stream(Some(max_block as u64 + 1), Some(b.id.to_string())).await.unwrap_err();
+        assert!(err.to_string().contains("could not be read back"), "{}", err);
+
+        // Without a hint, the chunk that passes the limit fails and the partial block is dropped
+        let stream_id = bm.start_stream(None, Some(b.id.to_string())).await.unwrap();
+        bm.append_stream(stream_id, vec![1u8; max_block]).await.unwrap();
+        let err = bm.append_stream(stream_id, vec![1u8; 1]).await.unwrap_err();
+        assert!(err.to_string().contains("could not be read back"), "{}", err);
+        wait_until(|| b.peer_manager.remote_used_storage(a.id) == Some(0)).await;
+
+        // Migrations are refused up front
+        bm.set("big", vec![2u8; max_block + 1], Durability::Pinned).unwrap();
+        let big = memsdk::BlockOrKey::Key { key: "big".to_string() };
+        let err = bm.start_migration(big, Some(b.id.to_string()), None, false).await.unwrap_err();
+        assert!(err.to_string().contains("could not be read back"), "{}", err);
+    }
+}
@@ -0,0 +1,139 @@
+//! `Free` and `FreeMany`: releasing blocks by id, or through the keys naming
+//! them. Each id may be a VM region, a block this node stored on a peer, or
+//! a local block, tried in that order as a single `Free` always has.
+
+use anyhow::Result;
+use log::{info, warn};
+use memsdk::FreeStats;
+
+use super::{BlockManager, InMemoryBlockManager};
+use crate::metadata::BlockId;
+
+impl InMemoryBlockManager {
+    /// Free one id. Returns the bytes released, or None if nothing here has
+    /// that id; releasing a content-addressed block another store still holds
+    /// releases 0 bytes. Fails only if the peer holding it could not be told.
+    pub async fn free(&self, id: BlockId) -> Result<Option<u64>> {
+        if let Ok(bytes) = self.vm_free(id) {
+            return Ok(Some(bytes));
+        }
+        if let Some(bytes) = self.free_remote(id).await? {
+            return Ok(Some(bytes));
+        }
+        if self.release_content_ref(id) {
+            return Ok(Some(0));
+        }
+        Ok(self.evict_block(id)?.map(|block| block.data.len() as u64))
+    }
+
+    /// Drop one reference to a content-addressed block, returning true if
+    /// other stores still hold it and it must stay.
+    fn release_content_ref(&self, id: BlockId) -> bool {
+        let Some(mut refs) = self.content_refs.get_mut(&id) else {
+            return false;
+        };
+        if *refs <= 1 {
+            return false;
+        }
+        *refs -= 1;
+        info!("Released a reference to block {} ({} left)", id, *refs);
+        true
+    }
+
+    /// Free every id in `ids`, carrying on past ids that are missing or
+    /// could not be released.
+    pub async fn free_many(&self, ids: &[BlockId]) -> FreeStats {
+        let mut stats = FreeStats::default();
+        for &id in ids {
+            match self.free(id).await {
+                Ok(Some(bytes)) => {
+                    stats.count += 1;
+                    stats.bytes += bytes;
+                }
+                Ok(None) => stats.missing += 1,
+                Err(e) => {
+                    warn!("Could not free block {}: {}", id, e);
+                    stats.failed += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    /// Remove the keys matching `pattern` and free their blocks, except
+    /// those another key still names.
+    pub async fn free_matching(&self, pattern: &str) -> FreeStats {
+        let mut doomed = Vec::new();
+        for key in self.list_keys(pattern) {
+            let Some(id) = self.get_named_block_id(&key) else {
+                continue;
+            };
+            match self.unbind_key(&key, id) {
+                Some(false) => doomed.push(id),
+                Some(true) => self.detach_tags(id, &key),
+                None => {}
+            }
+        }
+        let stats = self.free_many(&doomed).await;
+        info!("Freed {} blocks ({} bytes) under keys matching {:?}", stats.count, stats.bytes, pattern);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{wait_until, TestNode};
+    use memsdk::Durability;
+
+    #[tokio::test]
+    async fn test_free_many_counts_missing_and_releases_remote_blocks() {
+        let a = TestNode::spawn("free-a", 1024 * 1024).await;
+        let b = TestNode::spawn("free-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let local = a.block_manager.set("tmp:1", vec![1; 100], Durability::Pinned).unwrap();
+        let (remote, _) = a.block_manager.put_block_remote(a.block_manager.new_block(vec![2; 300], Durability::Pinned), None).await.unwrap();
+        wait_until(|| b.block_manager.used_space() == 300).await;
+
+        let stats = a.block_manager.free_many(&[local, 424242, remote]).await;
+        assert_eq!(stats, FreeStats { count: 2, bytes: 400, missing: 1, failed: 0 });
+        assert_eq!(a.block_manager.used_space(), 0);
+        wait_until(|| b.block_manager.used_space() == 0).await;
+        assert_eq!(a.block_manager.free(remote).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_free_matching_keeps_blocks_other_keys_name() {
+        let a = TestNode::spawn("free-pattern", 1024 * 1024).await;
+        let bm = &a.block_manager;
+        bm.set("tmp:1", vec![1; 10], Durability::Pinned).unwrap();
+        bm.set("tmp:2", vec![2; 20], Durability::Cache).unwrap();
+        let shared = bm.set("tmp:3", vec![3; 40], Durability::Pinned).unwrap();
+        bm.alias_key("tmp:3", "keep:3").unwrap();
+        bm.set("keep:4", vec![4; 80], Durability::Pinned).unwrap();
+
+        assert_eq!(bm.free_matching("tmp:*").await, FreeStats { count: 2, bytes: 30, missing: 0, failed: 0 });
+        assert!(bm.list_keys("tmp:*").is_empty());
+        assert_eq!(bm.get_named_block_id("keep:3"), Some(shared));
+        assert_eq!(bm.used_space(), 120);
+    }
+
+    #[tokio::test]
+    async fn test_free_keeps_content_blocks_until_every_store_releases() {
+        let a = TestNode::spawn("free-content", 1024 * 1024).await;
+        let bm = &a.block_manager;
+        let (id, _) = bm.put_content_addressed(vec![5; 64], Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap();
+        assert_eq!(bm.put_content_addressed(vec![5; 64], Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap().0, id);
+
+        // The first free drops one store's reference and leaves the data
+        assert_eq!(bm.free(id).await.unwrap(), Some(0));
+        assert_eq!(bm.get_block(id).unwrap().unwrap().data.as_ref(), &[5; 64][..]);
+        assert_eq!(bm.free(id).await.unwrap(), Some(64));
+        assert!(bm.get_block(id).unwrap().is_none());
+
+        // A store after the block is gone starts counting afresh
+        assert_eq!(bm.put_content_addressed(vec![5; 64], Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap().0, id);
+        assert_eq!(bm.free(id).await.unwrap(), Some(64));
+        assert_eq!(bm.used_space(), 0);
+    }
+}
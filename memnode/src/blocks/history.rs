@@ -0,0 +1,99 @@
+//! A short history of the node's usage for `StatHistory`, so `memcli stats
+//! --follow` can show a trend. Every `SAMPLE_INTERVAL` the memory in use, the
+//! block count and the RPC bytes received and sent since the last sample are
+//! recorded; the newest `HISTORY_SAMPLES` samples are kept.
+
+use memsdk::StatSample;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{unix_now, BlockManager, InMemoryBlockManager};
+
+/// Samples kept: five minutes at one a second.
+pub const HISTORY_SAMPLES: usize = 300;
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub(crate) struct StatHistory {
+    samples: Mutex<VecDeque<StatSample>>,
+    // RPC bytes since the last sample
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl InMemoryBlockManager {
+    /// Count bytes received from and sent to RPC clients.
+    pub fn record_rpc_traffic(&self, bytes_in: u64, bytes_out: u64) {
+        self.history.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+        self.history.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+    }
+
+    /// Record the node's usage at `at` (unix seconds), dropping the oldest
+    /// sample once `HISTORY_SAMPLES` are kept.
+    pub fn sample_stats(&self, at: u64) {
+        let sample = StatSample {
+            at,
+            memory_usage: self.used_space(),
+            blocks: self.blocks.len() as u64,
+            bytes_in: self.history.bytes_in.swap(0, Ordering::Relaxed),
+            bytes_out: self.history.bytes_out.swap(0, Ordering::Relaxed),
+        };
+        let mut samples = self.history.samples.lock().unwrap();
+        if samples.len() == HISTORY_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Samples from the last `window_secs` seconds, oldest first.
+    pub fn stat_history(&self, window_secs: u64) -> Vec<StatSample> {
+        self.samples_since(unix_now().saturating_sub(window_secs))
+    }
+
+    fn samples_since(&self, since: u64) -> Vec<StatSample> {
+        self.history.samples.lock().unwrap().iter().filter(|s| s.at > since).copied().collect()
+    }
+
+    /// Sample usage every `SAMPLE_INTERVAL`, for the life of the node.
+    pub async fn run_stat_sampler(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            self.sample_stats(unix_now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerManager;
+    use memsdk::Durability;
+
+    #[test]
+    fn test_history_accumulates_and_is_bounded() {
+        let bm = InMemoryBlockManager::new(Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "history".to_string())), 1000);
+        let start = unix_now() - HISTORY_SAMPLES as u64 - 10;
+        bm.record_rpc_traffic(40, 300);
+        bm.sample_stats(start);
+        bm.put_block(bm.new_block(vec![1; 100], Durability::Pinned)).unwrap();
+        bm.sample_stats(start + 1);
+
+        let first = bm.stat_history(u64::MAX);
+        assert_eq!(first.len(), 2);
+        assert_eq!((first[0].memory_usage, first[0].blocks, first[0].bytes_in, first[0].bytes_out), (0, 0, 40, 300));
+        // Traffic counts towards the sample it happened before
+        assert_eq!((first[1].memory_usage, first[1].blocks, first[1].bytes_in), (100, 1, 0));
+
+        for at in start + 2..start + 2 + HISTORY_SAMPLES as u64 {
+            bm.sample_stats(at);
+        }
+        let all = bm.stat_history(u64::MAX);
+        assert_eq!(all.len(), HISTORY_SAMPLES);
+        assert_eq!(all[0].at, start + 2);
+        assert_eq!(bm.samples_since(start + HISTORY_SAMPLES as u64 - 1).len(), 2);
+        assert!(bm.stat_history(5).is_empty());
+    }
+}
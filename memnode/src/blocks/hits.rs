@@ -0,0 +1,66 @@
+//! Per-block read counters behind the hot-keys report.
+//!
+//! Every read served from a local block bumps a few atomics: lifetime counts
+//! split by who asked (local clients or peers), bytes served, the time of the
+//! last read, and a recent-reads score that `decay` shrinks on a timer so it
+//! tracks the last few minutes rather than all time.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How often recent-read scores are decayed.
+pub const HIT_DECAY_INTERVAL: Duration = Duration::from_secs(10);
+
+// One read adds this much to the score, so decay can use integer math
+const SCORE_UNIT: u64 = 1000;
+// Applied every HIT_DECAY_INTERVAL: 2^(-10/60), a half-life of one minute
+const DECAY_NUM: u64 = 891;
+const DECAY_DEN: u64 = 1000;
+
+#[derive(Default)]
+pub struct BlockHits {
+    local: AtomicU64,
+    peer: AtomicU64,
+    bytes_served: AtomicU64,
+    last_hit: AtomicU64,
+    score: AtomicU64,
+}
+
+impl BlockHits {
+    pub fn record(&self, bytes: u64, from_peer: bool, now: u64) {
+        let counter = if from_peer { &self.peer } else { &self.local };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+        self.last_hit.store(now, Ordering::Relaxed);
+        self.score.fetch_add(SCORE_UNIT, Ordering::Relaxed);
+    }
+
+    pub fn decay(&self) {
+        let _ = self.score.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |s| Some(s * DECAY_NUM / DECAY_DEN));
+    }
+
+    pub fn total(&self) -> u64 {
+        self.local.load(Ordering::Relaxed) + self.peer.load(Ordering::Relaxed)
+    }
+
+    pub fn last_hit(&self) -> u64 {
+        self.last_hit.load(Ordering::Relaxed)
+    }
+
+    /// Recent reads, roughly: each read counts 1 and halves every minute.
+    pub fn score(&self) -> f64 {
+        self.score.load(Ordering::Relaxed) as f64 / SCORE_UNIT as f64
+    }
+
+    pub fn report(&self, id: u64, key: Option<String>) -> memsdk::HotBlock {
+        memsdk::HotBlock {
+            id,
+            key,
+            local_hits: self.local.load(Ordering::Relaxed),
+            peer_hits: self.peer.load(Ordering::Relaxed),
+            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+            last_accessed: self.last_hit(),
+            score: self.score(),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Named keys in sorted order, so a `ListKeys` page (cursor + limit) is read
+//! from a range of the index instead of sorting every key on each call.
+//!
+//! `key_order` holds every key of `key_index`, and for a moment may still hold
+//! one that was just removed. A key goes in while its `key_index` entry is held
+//! and before it is bound, and only comes out under that same entry once it is
+//! gone from `key_index`. So a key that stays set for a whole scan is always on
+//! one of its pages, however many keys are written or removed around it.
+
+use dashmap::mapref::entry::Entry;
+use std::ops::Bound;
+
+use super::InMemoryBlockManager;
+
+impl InMemoryBlockManager {
+    /// Record `key` as named. Call with its `key_index` entry held.
+    pub(crate) fn order_key(&self, key: &str) {
+        if !self.key_order.read().unwrap().contains(key) {
+            self.key_order.write().unwrap().insert(key.to_string());
+        }
+    }
+
+    /// Take `key` out of the order unless it is named again. Must not be called
+    /// while holding a reference into `key_index`.
+    pub(crate) fn unorder_key(&self, key: &str) {
+        let entry = self.key_index.entry(key.to_string());
+        if matches!(entry, Entry::Vacant(_)) {
+            self.key_order.write().unwrap().remove(key);
+        }
+    }
+
+    /// Drop every key that is no longer named, after a bulk removal.
+    pub(crate) fn prune_key_order(&self) {
+        let keys: Vec<String> = self.key_order.read().unwrap().iter().cloned().collect();
+        for key in keys {
+            if !self.key_index.contains_key(&key) {
+                self.unorder_key(&key);
+            }
+        }
+    }
+
+    /// One page of matching keys in sorted order, starting after `cursor`.
+    /// The returned cursor is the last key of the page, or `None` on the last page.
+    pub fn list_keys_page(&self, pattern: &str, cursor: Option<&str>, limit: usize) -> (Vec<String>, Option<String>) {
+        let glob = memsdk::glob::Glob::new(pattern);
+        let prefix = glob.literal_prefix();
+        let limit = limit.max(1);
+        let mut keys = Vec::with_capacity(limit + 1);
+        let mut stale = Vec::new();
+
+        // Start at the cursor or the pattern's prefix, whichever comes later
+        let mut from = match cursor {
+            Some(c) if c >= prefix.as_str() => Bound::Excluded(c.to_string()),
+            _ => Bound::Included(prefix.clone()),
+        };
+        while keys.len() <= limit {
+            let batch: Vec<String> = self.key_order.read().unwrap()
+                .range::<str, _>((from.as_ref().map(String::as_str), Bound::Unbounded))
+                .take_while(|k| k.starts_with(&prefix))
+                .filter(|k| glob.is_match(k))
+                .take(limit + 1 - keys.len())
+                .cloned()
+                .collect();
+            let Some(last) = batch.last() else { break };
+            from = Bound::Excluded(last.clone());
+            for key in batch {
+                if self.key_index.contains_key(&key) {
+                    keys.push(key);
+                } else {
+                    stale.push(key);
+                }
+            }
+        }
+        for key in stale {
+            self.unorder_key(&key);
+        }
+
+        if keys.len() > limit {
+            keys.truncate(limit);
+            let next = keys.last().cloned();
+            (keys, next)
+        } else {
+            (keys, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerManager;
+    use memsdk::Durability;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_scan_sees_every_key_despite_concurrent_writes() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = Arc::new(InMemoryBlockManager::new(pm, 64 * 1024 * 1024));
+        let stable: BTreeSet<String> = (0..2000).map(|i| format!("user:{:05}", i * 2)).collect();
+        for key in &stable {
+            bm.set(key, vec![1], Durability::Pinned).unwrap();
+        }
+        bm.set("other:1", vec![1], Durability::Pinned).unwrap();
+
+        // Keys between the stable ones come and go while the scan runs
+        let churn = {
+            let bm = bm.clone();
+            tokio::spawn(async move {
+                for round in 0..20 {
+                    for i in 0..2000 {
+                        let key = format!("user:{:05}", i * 2 + 1);
+                        if round % 2 == 0 {
+                            bm.set(&key, vec![2], Durability::Pinned).unwrap();
+                        } else {
+                            bm.flush_filtered(Some(&key), None);
+                        }
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = bm.list_keys_page("user:*", cursor.as_deref(), 7);
+            seen.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+            tokio::task::yield_now().await;
+        }
+        churn.await.unwrap();
+
+        assert!(seen.windows(2).all(|w| w[0] < w[1]), "pages are sorted and never repeat a key");
+        let seen: BTreeSet<String> = seen.into_iter().collect();
+        assert!(stable.is_subset(&seen));
+        assert!(!seen.contains("other:1"));
+
+        // Removed keys leave the order too
+        bm.flush_filtered(Some("user:*"), None);
+        assert_eq!(bm.list_keys_page("*", None, 10), (vec!["other:1".to_string()], None));
+        assert_eq!(bm.key_order.read().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+//! Every local block, named or not, for `SdkCommand::ListBlocks`. A raw
+//! `Store` leaves no key behind, so this is how operators find such blocks
+//! and free the ones nobody uses any more.
+
+use memsdk::BlockSummary;
+
+use super::{BlockId, InMemoryBlockManager};
+
+impl InMemoryBlockManager {
+    /// One page of blocks by ascending ID, starting after `cursor`. The
+    /// returned cursor is the last ID of the page, or `None` on the last page.
+    pub fn list_blocks_page(&self, cursor: Option<BlockId>, limit: usize) -> (Vec<BlockSummary>, Option<BlockId>) {
+        let limit = limit.max(1);
+        // Blocks are not kept in order, so each page sorts the IDs past the cursor
+        let mut page: Vec<(BlockId, u64, memsdk::Durability, u8, bool)> = self.blocks.iter()
+            .filter(|b| cursor.is_none_or(|c| *b.key() > c))
+            .map(|b| (*b.key(), b.data.len() as u64, b.durability, b.priority, memsdk::crypto::is_encrypted(&b.data)))
+            .collect();
+        page.sort_unstable_by_key(|b| b.0);
+        let more = page.len() > limit;
+        page.truncate(limit);
+
+        let next = if more { page.last().map(|b| b.0) } else { None };
+        // Looked up after the block map is released, never while holding it
+        let items = page.into_iter()
+            .map(|(id, size, durability, priority, encrypted)| BlockSummary { id, size, durability, has_key: self.indexed_ids.contains_key(&id), priority, encrypted })
+            .collect();
+        (items, next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BlockManager;
+    use crate::peers::PeerManager;
+    use memsdk::Durability;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_list_blocks_includes_unkeyed_blocks() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 1024 * 1024);
+        let keyed = bm.set("config", vec![1; 10], Durability::Pinned).unwrap();
+        let mut raw: Vec<BlockId> = (0..5)
+            .map(|i| bm.put_block(bm.new_block(vec![2; 20 + i], Durability::Cache)).unwrap().0)
+            .collect();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = bm.list_blocks_page(cursor, 2);
+            assert!(page.len() <= 2);
+            seen.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen.len(), 6);
+        assert!(seen.windows(2).all(|w| w[0].id < w[1].id));
+        let config = seen.iter().find(|b| b.id == keyed).unwrap();
+        assert_eq!((config.size, config.durability, config.has_key), (10, Durability::Pinned, true));
+        let mut unkeyed: Vec<BlockId> = seen.iter().filter(|b| !b.has_key).map(|b| b.id).collect();
+        raw.sort();
+        unkeyed.sort();
+        assert_eq!(unkeyed, raw);
+    }
+}
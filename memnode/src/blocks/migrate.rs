@@ -0,0 +1,353 @@
+//! Moving existing blocks between this node and a peer (`SdkCommand::Migrate`).
+//!
+//! Pushes send the block in `MIGRATE_CHUNK_SIZE` pieces as `PutBlockChunk`;
+//! the peer assembles them and answers `BlockStored` or `PutBlockRejected`.
+//! Pulls fetch the block (or key) with the usual read messages. Either way the
+//! source copy is only dropped once the destination holds the data, so a
+//! transfer that fails part-way leaves it untouched and can simply be retried.
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use memsdk::{BlockOrKey, Durability, MigrationState, MigrationStatus};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{unix_now, Block, BlockExists, BlockId, BlockManager, InMemoryBlockManager};
+use crate::net::Message;
+
+pub const MIGRATE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How long the peer has to confirm a pushed block once the last chunk is sent.
+pub(super) const BLOCK_ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A pushed block still arriving from `from`.
+pub(crate) struct IncomingBlock {
+    pub(super) from: Uuid,
+    pub(super) key: Option<String>,
+    pub(super) durability: Durability,
+    pub(super) priority: u8,
+    pub(super) total: u64,
+    pub(super) data: Vec<u8>,
+}
+
+struct Plan {
+    id: Option<BlockId>,
+    key: Option<String>,
+    peer_id: Uuid,
+    size: u64,
+    push: bool,
+    // Pulls only: the block is one we offloaded to the peer ourselves
+    owned: bool,
+}
+
+impl InMemoryBlockManager {
+    /// Check the request, then run the migration in the background. Returns
+    /// its id for `migration_status`.
+    pub async fn start_migration(self: &Arc<Self>, selector: BlockOrKey, to: Option<String>, from: Option<String>, keep_local: bool) -> Result<u64> {
+        let plan = match (to, from) {
+            (Some(_), Some(_)) => bail!("Give either a destination or a source peer, not both"),
+            (to, None) => self.plan_push(selector, to).await?,
+            (None, Some(from)) => self.plan_pull(selector, &from, keep_local)?,
+        };
+
+        let migration_id = rand::random::<u64>();
+        self.migrations.insert(migration_id, MigrationStatus {
+            migration_id,
+            state: MigrationState::Running,
+            id: plan.id.unwrap_or(0),
+            peer: self.peer_manager.peer_name(plan.peer_id).unwrap_or_else(|| plan.peer_id.to_string()),
+            bytes_done: 0,
+            bytes_total: plan.size,
+            error: None,
+            retriable: false,
+        });
+        info!("Migration {} started: {:?} {} peer {}", migration_id, plan.id, if plan.push { "to" } else { "from" }, plan.peer_id);
+
+        let bm = self.clone();
+        tokio::spawn(async move {
+            let res = if plan.push {
+                bm.push(migration_id, &plan, keep_local).await
+            } else {
+                bm.pull(migration_id, &plan, keep_local).await
+            };
+            bm.finish_migration(migration_id, res);
+        });
+        Ok(migration_id)
+    }
+
+    pub fn migration_status(&self, migration_id: u64) -> Option<MigrationStatus> {
+        self.migrations.get(&migration_id).map(|s| s.clone())
+    }
+
+    async fn plan_push(&self, selector: BlockOrKey, to: Option<String>) -> Result<Plan> {
+        let (id, key) = match selector {
+            BlockOrKey::Id { id } => (id, self.key_of(id)),
+            BlockOrKey::Key { key } => match self.get_named_block_id(&key) {
+                Some(id) => (id, Some(key)),
+                None => bail!("Key '{}' not found", key),
+            },
+        };
+        let Some(size) = self.blocks.get(&id).map(|b| b.data.len() as u64) else {
+            bail!("Block {} is not stored on this node", id);
+        };
+        if self.hosted_for.contains_key(&id) {
+            bail!("Block {} is held here for a peer and cannot be migrated", id);
+        }
+        self.check_read_back(size)?;
+        let peer_id = match to {
+            Some(target) => self.peer_manager.resolve_peer(&target)?,
+            None => self.peer_manager.get_available_peer(size).await?,
+        };
+        Ok(Plan { id: Some(id), key, peer_id, size, push: true, owned: false })
+    }
+
+    /// Blocks sent to a peer in chunks still come back in a single frame, so
+    /// ones too large for that are not sent at all.
+    pub(super) fn check_read_back(&self, size: u64) -> Result<()> {
+        let max = self.peer_manager.max_peer_block();
+        if size > max {
+            bail!("A block of {} bytes could not be read back from a peer, which sends at most {} bytes at once (see --peer-max-frame)", size, max);
+        }
+        Ok(())
+    }
+
+    fn plan_pull(&self, selector: BlockOrKey, from: &str, keep_local: bool) -> Result<Plan> {
+        let peer_id = self.peer_manager.resolve_peer(from)?;
+        let (id, key) = match selector {
+            BlockOrKey::Id { id } => (Some(id), self.key_of(id)),
+            BlockOrKey::Key { key } => (self.get_named_block_id(&key), Some(key)),
+        };
+        if let Some(id) = id {
+            if self.blocks.contains_key(&id) {
+                bail!("Block {} is already stored on this node", id);
+            }
+        }
+        let location = id.and_then(|id| self.remote_locations.get(&id).map(|l| *l.value()));
+        let owned = location.is_some_and(|(p, _)| p == peer_id);
+        if !keep_local && !owned {
+            bail!("Only data this node stored on '{}' can be moved off it; keep the source copy to copy it instead", from);
+        }
+        let size = location.filter(|_| owned).map_or(0, |(_, size)| size);
+        // The peer's own keys are fetched by name; our id for the key means nothing there
+        let id = id.filter(|_| owned || key.is_none());
+        Ok(Plan { id, key, peer_id, size, push: false, owned })
+    }
+
+    async fn push(&self, migration_id: u64, plan: &Plan, keep_local: bool) -> Result<BlockId> {
+        let id = plan.id.expect("pushes name a block");
+        let (durability, priority) = match self.blocks.get(&id).map(|b| (b.durability, b.priority)) {
+            // The remote copy expires when this one would have
+            Some((Durability::Ttl { .. }, priority)) => (Durability::Ttl {
+                secs: self.expiries.get(&id).map_or(0, |at| at.saturating_sub(unix_now())),
+            }, priority),
+            Some(held) => held,
+            None => bail!("Block {} was removed before the migration started", id),
+        };
+
+        let mut acks = self.peer_manager.expect_block_ack(id);
+        let res = async {
+            let mut offset = 0;
+            loop {
+                // Copy one chunk at a time rather than holding the map entry across sends
+                let chunk = match self.blocks.get(&id) {
+                    Some(block) => block.data[offset..(offset + MIGRATE_CHUNK_SIZE).min(block.data.len())].to_vec(),
+                    None => bail!("Block {} was removed during the migration", id),
+                };
+                let len = chunk.len();
+                let msg = Message::PutBlockChunk { id, key: plan.key.clone(), offset: offset as u64, total: plan.size, data: chunk, durability, priority };
+                self.peer_manager.send_to_peer(plan.peer_id, &msg).await?;
+                offset += len;
+                self.update_migration(migration_id, |s| s.bytes_done = offset as u64);
+
+                if let Ok(Err(reason)) = acks.try_recv() {
+                    bail!("Peer rejected block {}: {}", id, reason);
+                }
+                if offset as u64 >= plan.size {
+                    break;
+                }
+            }
+            match tokio::time::timeout(BLOCK_ACK_TIMEOUT, acks.recv()).await {
+                Ok(Ok(Ok(()))) => Ok(()),
+                Ok(Ok(Err(reason))) => bail!("Peer rejected block {}: {}", id, reason),
+                Ok(Err(e)) => bail!("Recv error: {}", e),
+                Err(_) => bail!("Timeout waiting for the peer to confirm block {}", id),
+            }
+        }.await;
+        self.peer_manager.forget_block_ack(id);
+        res?;
+
+        self.remote_locations.insert(id, (plan.peer_id, plan.size));
+        self.peer_manager.record_offloaded(plan.peer_id, plan.size);
+        if !keep_local {
+            // Keys stay indexed here; reads now follow remote_locations to the peer
+            self.evict_block(id)?;
+        }
+        Ok(id)
+    }
+
+    async fn pull(&self, migration_id: u64, plan: &Plan, keep_local: bool) -> Result<BlockId> {
+        let id = match plan.id {
+            Some(id) => {
+                let fut = self.peer_manager.wait_for_block(plan.peer_id, id);
+                self.peer_manager.request_block(plan.peer_id, id).await?;
+                let (data, durability, priority) = fut.await?;
+                let size = data.len() as u64;
+                self.put_block(Block { id, priority: priority.unwrap_or(memsdk::DEFAULT_PRIORITY), ..self.new_block(data, durability.unwrap_or(Durability::Pinned)) })?;
+                self.update_migration(migration_id, |s| { s.bytes_done = size; s.bytes_total = size; });
+                id
+            }
+            None => {
+                let key = plan.key.as_deref().expect("pulls name a block or a key");
+                let Some((data, durability, priority)) = self.get_remote(key, &plan.peer_id.to_string()).await? else {
+                    bail!("Key '{}' not found on the peer", key);
+                };
+                let size = data.len() as u64;
+                let block = Block { priority: priority.unwrap_or(memsdk::DEFAULT_PRIORITY), ..self.new_block(data, durability.unwrap_or(Durability::Pinned)) };
+                let (id, _) = self.put_named_block(key.to_string(), block)?;
+                self.update_migration(migration_id, |s| { s.id = id; s.bytes_done = size; s.bytes_total = size; });
+                id
+            }
+        };
+
+        if plan.owned && !keep_local {
+            // Local copy is in place; a failed release only leaves the peer's copy behind
+            match self.peer_manager.send_to_peer(plan.peer_id, &Message::ReleaseBlock { id }).await {
+                Ok(()) => {
+                    if let Some((_, (peer_id, size))) = self.remote_locations.remove(&id) {
+                        self.peer_manager.release_offloaded(peer_id, size);
+                    }
+                }
+                Err(e) => warn!("Could not release block {} on peer {}: {}", id, plan.peer_id, e),
+            }
+        }
+        Ok(id)
+    }
+
+    fn update_migration(&self, migration_id: u64, update: impl FnOnce(&mut MigrationStatus)) {
+        if let Some(mut status) = self.migrations.get_mut(&migration_id) {
+            update(&mut status);
+        }
+    }
+
+    fn finish_migration(&self, migration_id: u64, res: Result<BlockId>) {
+        match res {
+            Ok(id) => {
+                info!("Migration {} of block {} done", migration_id, id);
+                self.update_migration(migration_id, |s| {
+                    s.state = MigrationState::Done;
+                    s.id = id;
+                    s.bytes_done = s.bytes_total;
+                });
+            }
+            Err(e) => {
+                warn!("Migration {} failed: {}", migration_id, e);
+                self.update_migration(migration_id, |s| {
+                    s.state = MigrationState::Failed;
+                    s.error = Some(format!("{} (source copy kept; safe to retry)", e));
+                    s.retriable = true;
+                });
+            }
+        }
+    }
+
+    /// Take one chunk of a block `from` is pushing to us. Returns true once the
+    /// whole block has arrived and is stored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn receive_chunk(&self, from: Uuid, id: BlockId, key: Option<String>, offset: u64, total: u64, data: Vec<u8>, durability: Durability, priority: u8) -> Result<bool> {
+        if offset == 0 {
+            // A retry starts over
+            self.abort_incoming(id);
+            if self.blocks.contains_key(&id) {
+                return Err(BlockExists(id).into());
+            }
+            if total > self.max_memory {
+                bail!("Block of {} bytes cannot fit in this node's {} byte capacity", total, self.max_memory);
+            }
+            if !self.peer_manager.try_reserve_storage(from, total) {
+                bail!("Quota exceeded");
+            }
+            let capacity = total.min(MIGRATE_CHUNK_SIZE as u64 * 64) as usize;
+            self.incoming.insert(id, IncomingBlock { from, key, durability, priority, total, data: Vec::with_capacity(capacity) });
+        }
+
+        let complete = match self.incoming.get_mut(&id) {
+            Some(mut incoming) if incoming.from == from => {
+                if incoming.data.len() as u64 != offset {
+                    drop(incoming);
+                    self.abort_incoming(id);
+                    bail!("Chunk for block {} out of order", id);
+                }
+                if offset + data.len() as u64 > incoming.total {
+                    drop(incoming);
+                    self.abort_incoming(id);
+                    bail!("Chunk for block {} runs past its declared size", id);
+                }
+                incoming.data.extend_from_slice(&data);
+                incoming.data.len() as u64 >= incoming.total
+            }
+            // Rest of a transfer we already refused
+            _ => return Ok(false),
+        };
+        if !complete {
+            return Ok(false);
+        }
+
+        let (_, incoming) = self.incoming.remove(&id).expect("checked above");
+        self.store_incoming(id, incoming)?;
+        Ok(true)
+    }
+
+    /// Store a block that has fully arrived, handing back its reservation if that fails.
+    pub(super) fn store_incoming(&self, id: BlockId, incoming: IncomingBlock) -> Result<()> {
+        let from = incoming.from;
+        let block = Block { id, priority: incoming.priority, ..self.new_block(incoming.data, incoming.durability) };
+        let res = match incoming.key {
+            Some(key) => self.put_named_block(key, block),
+            None => self.put_block(block),
+        };
+        match res {
+            Ok(_) => {
+                self.record_hosted_block(id, from);
+                Ok(())
+            }
+            Err(e) => {
+                self.peer_manager.release_storage(from, incoming.total);
+                Err(e)
+            }
+        }
+    }
+
+    pub(super) fn abort_incoming(&self, id: BlockId) {
+        if let Some((_, incoming)) = self.incoming.remove(&id) {
+            self.peer_manager.release_storage(incoming.from, incoming.total);
+        }
+    }
+
+    /// Drop half-received blocks from a peer that went away.
+    pub fn abort_incoming_from(&self, peer_id: Uuid) {
+        let ids: Vec<BlockId> = self.incoming.iter().filter(|e| e.value().from == peer_id).map(|e| *e.key()).collect();
+        for id in ids {
+            self.abort_incoming(id);
+        }
+    }
+
+    /// `from` no longer needs a block it stored here; blocks held for anyone else are left alone.
+    pub fn release_hosted_block(&self, id: BlockId, from: Uuid) {
+        // Also how a sender gives up on a block it was still streaming to us
+        if let Some((_, incoming)) = self.incoming.remove_if(&id, |_, i| i.from == from) {
+            self.peer_manager.release_storage(from, incoming.total);
+            return;
+        }
+        if self.hosted_for.remove_if(&id, |_, owner| *owner == from).is_none() {
+            warn!("Peer {} asked to release block {}, which is not held for it", from, id);
+            return;
+        }
+        for key in self.names_of(id) {
+            self.unbind_key(&key, id);
+        }
+        if let Ok(Some(block)) = self.evict_block(id) {
+            self.peer_manager.release_storage(from, block.data.len() as u64);
+        }
+    }
+}
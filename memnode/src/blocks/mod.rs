@@ -1,25 +1,86 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use crate::metadata::BlockId;
+use bytes::Bytes;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use log::info;
-use crate::peers::PeerManager;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use log::{info, warn};
+use crate::peers::{PeerManager, RemoteValue};
 use crate::net::Message;
 pub mod vm;
+pub mod hits;
+pub mod apps;
+pub mod counters;
+pub mod tags;
+pub mod key_order;
+pub mod listing;
+pub mod migrate;
+pub mod drain;
+pub mod orphans;
+pub mod uploads;
+pub mod history;
+pub mod timings;
+pub mod cluster;
+pub mod free;
+pub mod forward;
+pub mod pressure;
+pub mod aliases;
+pub mod reconnect;
 use self::vm::VmRegionManager;
+use self::hits::{BlockHits, HIT_DECAY_INTERVAL};
+use memsdk::{FlushStats, StoredLocation};
+use self::migrate::BLOCK_ACK_TIMEOUT;
+
+/// How often expired TTL blocks are swept.
+pub const TTL_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// Most an upload's size hint reserves before any data has arrived
+const STREAM_PREALLOCATE_MAX: u64 = 64 * 1024 * 1024;
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
 
 #[derive(Debug, Clone)]
 pub struct Block {
     pub id: BlockId,
-    pub data: Vec<u8>,
+    /// Reference-counted, so handing a block out or keeping a copy never copies its data
+    pub data: Bytes,
     pub durability: memsdk::Durability,
     pub last_accessed: std::sync::Arc<AtomicU64>,
+    /// Evictable blocks of lower priority go first; see `memsdk::DEFAULT_PRIORITY`.
+    pub priority: u8,
+}
+
+// A streamed upload being buffered until StreamFinish, or passed on to a peer as it arrives
+struct Upload {
+    data: Vec<u8>,
+    size_hint: Option<u64>,
+    started: std::time::Instant,
+    // When the last chunk arrived, for reaping idle uploads
+    last_activity: std::time::Instant,
+    forward: Option<Arc<forward::Forward>>,
 }
 
+/// Returned by `put_block` when a block with the same id is already stored.
+/// Remote puts relay it back to the sender as `Message::PutBlockRejected`.
+#[derive(Debug)]
+pub struct BlockExists(pub BlockId);
+
+impl std::fmt::Display for BlockExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Block {} already exists", self.0)
+    }
+}
+
+impl std::error::Error for BlockExists {}
+
 #[allow(dead_code)]
 pub trait BlockManager: Send + Sync {
-    fn put_block(&self, block: Block) -> Result<()>;
+    /// Store a new block, returning its id and size. Never overwrites: an id
+    /// that is already present fails with `BlockExists`.
+    fn put_block(&self, block: Block) -> Result<(BlockId, u64)>;
     fn get_block(&self, id: BlockId) -> Result<Option<Block>>;
     fn evict_block(&self, id: BlockId) -> Result<Option<Block>>;
     fn free_space(&self) -> u64;
@@ -30,65 +91,416 @@ pub trait BlockManager: Send + Sync {
 pub struct InMemoryBlockManager {
     pub(crate) blocks: Arc<DashMap<BlockId, Block>>,
     key_index: Arc<DashMap<String, BlockId>>,
+    // The keys of key_index in sorted order, for paged listings
+    key_order: Arc<std::sync::RwLock<std::collections::BTreeSet<String>>>,
+    // Reverse of key_index, every key naming a block (see aliases.rs); a key can
+    // outlive its block (cache eviction), so its id stays taken
+    indexed_ids: Arc<DashMap<BlockId, Vec<String>>>,
     pub peer_manager: Arc<PeerManager>,
-    // Map to track if a block ID is stored remotely to route GETs
-    remote_locations: Arc<DashMap<BlockId, uuid::Uuid>>,
+    // Blocks stored remotely, with the peer holding each and its size, to route GETs
+    remote_locations: Arc<DashMap<BlockId, (uuid::Uuid, u64)>>,
+    // Keys set on a peer with `set_remote`, with the peer and the value's size
+    remote_keys: Arc<DashMap<String, (uuid::Uuid, u64)>>,
+    // Blocks we hold on behalf of a peer; their sizes make up that peer's used storage here
+    hosted_for: Arc<DashMap<BlockId, uuid::Uuid>>,
+    // Unix time after which a TTL block is gone, by block id
+    expiries: Arc<DashMap<BlockId, u64>>,
+    // Read counters for local blocks, created on first read
+    hits: Arc<DashMap<BlockId, BlockHits>>,
+    // Client application that stored each local block, for RPC-stored blocks
+    owners: Arc<DashMap<BlockId, Arc<apps::AppTag>>>,
+    // Client tags of local blocks, and the keys carrying each (name, value)
+    tags: Arc<DashMap<BlockId, tags::BlockTags>>,
+    tag_index: Arc<DashMap<(String, String), std::collections::HashSet<String>>>,
+    // Migrations started here, and blocks peers are migrating to us
+    migrations: Arc<DashMap<u64, memsdk::MigrationStatus>>,
+    // Peers being drained before a disconnect, by peer id
+    drains: Arc<DashMap<uuid::Uuid, memsdk::DrainStatus>>,
+    // Seconds after which an unread cache block with no key is freed; 0 keeps them
+    orphan_gc_idle: Arc<AtomicU64>,
+    // Refuse clients' and peers' writes, serving only what is already stored
+    read_only: Arc<AtomicBool>,
+    // Algorithm for new checksums, and the checksum of each content-addressed block
+    checksum_algo: Arc<Mutex<memsdk::HashAlgo>>,
+    checksums: Arc<DashMap<BlockId, memsdk::Checksum>>,
+    // Stores still holding each content-addressed block; `free` drops one
+    content_refs: Arc<DashMap<BlockId, u64>>,
+    incoming: Arc<DashMap<BlockId, migrate::IncomingBlock>>,
     // Track total memory usage in bytes
     current_memory: Arc<AtomicU64>,
+    // Split of current_memory by durability; Ttl blocks count as cache
+    pinned_bytes: Arc<AtomicU64>,
+    cache_bytes: Arc<AtomicU64>,
     max_memory: u64,
     // Streaming partial uploads
-    active_uploads: Arc<DashMap<u64, Vec<u8>>>,
+    active_uploads: Arc<DashMap<u64, Upload>>,
+    upload_limits: Arc<uploads::UploadLimits>,
+    history: Arc<history::StatHistory>,
+    // Time spent running each kind of RPC command
+    timings: Arc<timings::CommandTimings>,
     pub vm_manager: Arc<VmRegionManager>,
+    pub health: Arc<crate::health::Health>,
+    pub memory_guard: Arc<pressure::MemoryGuard>,
+    // Set for nodes started from flags and a config file
+    pub(crate) config_reloader: Arc<std::sync::OnceLock<crate::reload::ConfigReloader>>,
+    // Signalled by the `Shutdown` command; the node stops when it sees it
+    shutdown_requested: Arc<tokio::sync::Notify>,
+    // Source of candidate block ids
+    id_rng: Arc<Mutex<StdRng>>,
 }
 
 impl InMemoryBlockManager {
     pub fn new(peer_manager: Arc<PeerManager>, max_memory: u64) -> Self {
+        peer_manager.set_capacity(max_memory);
         Self {
             blocks: Arc::new(DashMap::new()),
             key_index: Arc::new(DashMap::new()),
+            key_order: Arc::new(std::sync::RwLock::new(std::collections::BTreeSet::new())),
+            indexed_ids: Arc::new(DashMap::new()),
             peer_manager,
             remote_locations: Arc::new(DashMap::new()),
+            remote_keys: Arc::new(DashMap::new()),
+            hosted_for: Arc::new(DashMap::new()),
+            expiries: Arc::new(DashMap::new()),
+            hits: Arc::new(DashMap::new()),
+            owners: Arc::new(DashMap::new()),
+            tags: Arc::new(DashMap::new()),
+            tag_index: Arc::new(DashMap::new()),
+            migrations: Arc::new(DashMap::new()),
+            drains: Arc::new(DashMap::new()),
+            orphan_gc_idle: Arc::new(AtomicU64::new(0)),
+            read_only: Arc::new(AtomicBool::new(false)),
+            checksum_algo: Arc::new(Mutex::new(memsdk::HashAlgo::default())),
+            checksums: Arc::new(DashMap::new()),
+            content_refs: Arc::new(DashMap::new()),
+            incoming: Arc::new(DashMap::new()),
             current_memory: Arc::new(AtomicU64::new(0)),
+            pinned_bytes: Arc::new(AtomicU64::new(0)),
+            cache_bytes: Arc::new(AtomicU64::new(0)),
             max_memory,
             active_uploads: Arc::new(DashMap::new()),
+            upload_limits: Arc::new(uploads::UploadLimits::default()),
+            history: Arc::new(history::StatHistory::default()),
+            timings: Arc::new(timings::CommandTimings::default()),
             vm_manager: Arc::new(VmRegionManager::new()),
+            health: Arc::new(crate::health::Health::new()),
+            memory_guard: Arc::new(pressure::MemoryGuard::default()),
+            config_reloader: Arc::new(std::sync::OnceLock::new()),
+            shutdown_requested: Arc::new(tokio::sync::Notify::new()),
+            id_rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+        }
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        if self.read_only.swap(read_only, Ordering::Relaxed) != read_only {
+            info!("Node is now {}", if read_only { "read-only" } else { "accepting writes" });
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Have the node shut down, as the `Shutdown` command does. A request
+    /// made before anyone waits is kept until they do.
+    pub fn request_shutdown(&self) {
+        self.shutdown_requested.notify_one();
+    }
+
+    /// Resolves once a shutdown has been requested.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown_requested.notified().await;
+    }
+
+    pub fn set_checksum_algo(&self, algo: memsdk::HashAlgo) {
+        *self.checksum_algo.lock().unwrap() = algo;
+    }
+
+    pub fn checksum_algo(&self) -> memsdk::HashAlgo {
+        *self.checksum_algo.lock().unwrap()
+    }
+
+    /// The checksum recorded when a content-addressed block was stored.
+    pub fn checksum(&self, id: BlockId) -> Option<memsdk::Checksum> {
+        self.checksums.get(&id).map(|c| c.clone())
+    }
+
+    /// Fails on a read-only node; checked before storing or removing data for
+    /// a client or peer.
+    pub fn check_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            bail!("Node is read-only");
+        }
+        Ok(())
+    }
+
+    /// Pick a random block id not used by a local block, a known remote one, or a key.
+    /// Every call site that creates blocks goes through here.
+    pub fn allocate_id(&self) -> BlockId {
+        let mut rng = self.id_rng.lock().unwrap();
+        loop {
+            let id = rng.gen::<u64>();
+            if !self.id_in_use(id) {
+                return id;
+            }
+            log::warn!("Block id {} already in use, drawing another", id);
+        }
+    }
+
+    fn id_in_use(&self, id: BlockId) -> bool {
+        self.blocks.contains_key(&id) || self.remote_locations.contains_key(&id) || self.indexed_ids.contains_key(&id)
+    }
+
+    #[cfg(test)]
+    fn seed_ids(&self, seed: u64) {
+        *self.id_rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+    }
+
+    /// A block with a freshly allocated id, marked as accessed now.
+    pub fn new_block(&self, data: impl Into<Bytes>, durability: memsdk::Durability) -> Block {
+        Block {
+            id: self.allocate_id(),
+            data: data.into(),
+            durability,
+            last_accessed: std::sync::Arc::new(AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs())),
+            priority: memsdk::DEFAULT_PRIORITY,
+        }
+    }
+
+    /// Store `data` under [`memsdk::content_id`]. Storing bytes that are already
+    /// here is a no-op returning the same id, except that a pinned store pins an
+    /// evictable copy. An id held by different data is a hash collision and fails.
+    /// Each store takes a reference, and `free` removes the block only once
+    /// every one has been released; a flush or eviction still drops it outright.
+    /// The block's checksum is recorded with the node's `--checksum-algo`.
+    /// A new block gets `priority`; a block already holding the same content
+    /// keeps its own, since other stores share it.
+    pub fn put_content_addressed(&self, data: Vec<u8>, durability: memsdk::Durability, priority: u8) -> Result<(BlockId, u64)> {
+        let id = memsdk::content_id(&data);
+        // Duplicates are recognised by the full 256-bit digest, not just the id
+        let checksum = self.checksum_algo().checksum(&data);
+        if let Some(existing) = self.get_block(id)? {
+            return self.reuse_content_block(existing, &checksum, durability);
+        }
+        if self.remote_locations.contains_key(&id) || self.indexed_ids.contains_key(&id) {
+            anyhow::bail!("Content hash collision: block id {} is already in use", id);
+        }
+        let block = Block { id, priority, ..self.new_block(data, durability) };
+        match self.put_block(block) {
+            Ok(stored) => {
+                self.checksums.insert(id, checksum);
+                self.content_refs.insert(id, 1);
+                Ok(stored)
+            }
+            // Someone stored the same id between our lookup and the insert
+            Err(e) if e.downcast_ref::<BlockExists>().is_some() => match self.get_block(id)? {
+                Some(existing) => self.reuse_content_block(existing, &checksum, durability),
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn reuse_content_block(&self, existing: Block, checksum: &memsdk::Checksum, durability: memsdk::Durability) -> Result<(BlockId, u64)> {
+        // Compare against the recorded digest when it used the same algorithm
+        let held = self.checksum(existing.id)
+            .filter(|c| c.algo == checksum.algo)
+            .unwrap_or_else(|| checksum.algo.checksum(&existing.data));
+        if held != *checksum {
+            anyhow::bail!("Content hash collision: block {} holds different data", existing.id);
         }
+        let size = existing.data.len() as u64;
+        if existing.durability.is_evictable() && !durability.is_evictable() {
+            if let Some(mut block) = self.blocks.get_mut(&existing.id) {
+                if block.durability.is_evictable() {
+                    block.durability = durability;
+                    self.expiries.remove(&existing.id);
+                    self.cache_bytes.fetch_sub(size, Ordering::Relaxed);
+                    self.pinned_bytes.fetch_add(size, Ordering::Relaxed);
+                    info!("Pinned existing block {} for a content-addressed store", existing.id);
+                }
+            }
+        }
+        *self.content_refs.entry(existing.id).or_insert(1) += 1;
+        Ok((existing.id, size))
+    }
+
+    /// Change a stored block's durability in place, moving its bytes between
+    /// the pinned and cache totals. A `Ttl` starts counting from now.
+    pub fn set_durability(&self, id: BlockId, durability: memsdk::Durability) -> Result<()> {
+        // The peer that stored it chose how long it lasts, and pays for it in quota
+        if let Some(owner) = self.hosted_for.get(&id).map(|o| *o) {
+            anyhow::bail!("Permission denied: block {} is stored here for peer {}, which decides its durability", id, owner);
+        }
+        let Some(mut block) = self.blocks.get_mut(&id) else {
+            anyhow::bail!("Block {} not found", id);
+        };
+        let (old, size) = (block.durability, block.data.len() as u64);
+        block.durability = durability;
+        match durability {
+            memsdk::Durability::Ttl { secs } => { self.expiries.insert(id, unix_now().saturating_add(secs)); }
+            _ => { self.expiries.remove(&id); }
+        }
+        if old.is_evictable() != durability.is_evictable() {
+            self.durability_bytes(old).fetch_sub(size, Ordering::Relaxed);
+            self.durability_bytes(durability).fetch_add(size, Ordering::Relaxed);
+        }
+        info!("Block {} changed from {:?} to {:?}", id, old, durability);
+        Ok(())
+    }
+
+    /// Change a stored block's eviction priority.
+    pub fn set_priority(&self, id: BlockId, priority: u8) -> Result<()> {
+        let Some(mut block) = self.blocks.get_mut(&id) else {
+            anyhow::bail!("Block {} not found", id);
+        };
+        if block.priority != priority {
+            info!("Block {} priority changed from {} to {}", id, block.priority, priority);
+            block.priority = priority;
+        }
+        Ok(())
     }
 
     // New explicit method for remote storage (for demo/policy)
     // In a real system, put_block would decide automatically
-    pub async fn put_block_remote(&self, block: Block, target: Option<String>) -> Result<()> {
+    pub async fn put_block_remote(&self, block: Block, target: Option<String>) -> Result<(BlockId, u64)> {
+         let (id, size) = (block.id, block.data.len() as u64);
+         self.send_block_remote(block, target).await?;
+         Ok((id, size))
+    }
+
+    /// Send `block` to `target`, or to any peer with room, and route reads
+    /// for it there. Returns the peer it went to.
+    async fn send_block_remote(&self, block: Block, target: Option<String>) -> Result<uuid::Uuid> {
          // Find a peer
-         let peer_id = if let Some(t) = target {
-             // Try to parse as UUID first
-             if let Ok(uid) = uuid::Uuid::parse_str(&t) {
-                 Some(uid)
-             } else {
-                 // Try name
-                 self.peer_manager.get_peer_id_by_name(&t)
-             }
-         } else {
-             self.peer_manager.get_available_peer().await
+         let peer_id = match target {
+             Some(t) => self.peer_manager.resolve_peer(&t)?,
+             None => self.peer_manager.get_available_peer(block.data.len() as u64).await?,
          };
 
-         if let Some(peer_id) = peer_id {
-             info!("Offloading block {} to peer {}", block.id, peer_id);
-             
-             let msg = Message::PutBlock {
-                 id: block.id,
-                 data: block.data,
-                 durability: Some(block.durability),
-             };
-             
-             // Send
-             self.peer_manager.send_to_peer(peer_id, &msg).await?;
-             
-             // Record location
-             self.remote_locations.insert(block.id, peer_id);
-             Ok(())
-         } else {
-             anyhow::bail!("No suitable peer found for remote storage");
-         }
+         info!("Offloading block {} to peer {}", block.id, peer_id);
+         let (id, size) = (block.id, block.data.len() as u64);
+         
+         let msg = Message::PutBlock {
+             id: block.id,
+             data: block.data,
+             durability: Some(block.durability),
+             trace_id: crate::trace::current_request_id(),
+             priority: Some(block.priority),
+         };
+         
+         // Send
+         self.peer_manager.send_to_peer(peer_id, &msg).await?;
+         
+         // Record location
+         self.remote_locations.insert(id, (peer_id, size));
+         self.peer_manager.record_offloaded(peer_id, size);
+         Ok(peer_id)
+    }
+
+    /// Store `block` on a peer for `StoreRemote`. With `fallback_local` the
+    /// peer must confirm it, and a block no peer takes (none available, a
+    /// rejection, no answer in time) is kept here under the same durability.
+    pub async fn store_remote(&self, block: Block, target: Option<String>, fallback_local: bool) -> Result<(BlockId, u64, StoredLocation)> {
+        let (id, size) = (block.id, block.data.len() as u64);
+        let remote = |peer_id| StoredLocation::Remote(self.peer_manager.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string()));
+        if !fallback_local {
+            let peer_id = self.send_block_remote(block, target).await?;
+            return Ok((id, size, remote(peer_id)));
+        }
+
+        let remote_err = match self.send_block_acked(block.clone(), target).await {
+            Ok(peer_id) => return Ok((id, size, remote(peer_id))),
+            Err(e) => e,
+        };
+        warn!("Keeping block {} locally: {}", id, remote_err);
+        match self.put_block(block) {
+            Ok((id, size)) => Ok((id, size, StoredLocation::Local)),
+            Err(local_err) => bail!("Remote store failed: {}; local fallback failed: {}", remote_err, local_err),
+        }
+    }
+
+    /// Like `send_block_remote`, but only routes reads to the peer once it has
+    /// confirmed it stored the block.
+    async fn send_block_acked(&self, block: Block, target: Option<String>) -> Result<uuid::Uuid> {
+        let peer_id = match target {
+            Some(t) => self.peer_manager.resolve_peer(&t)?,
+            None => self.peer_manager.get_available_peer(block.data.len() as u64).await?,
+        };
+        let (id, size) = (block.id, block.data.len() as u64);
+        self.put_block_on_peer(peer_id, block).await?;
+        self.remote_locations.insert(id, (peer_id, size));
+        self.peer_manager.record_offloaded(peer_id, size);
+        Ok(peer_id)
+    }
+
+    /// Send `block` to `peer_id` and wait for it to confirm it stored it.
+    /// Records nothing here.
+    pub(crate) async fn put_block_on_peer(&self, peer_id: uuid::Uuid, block: Block) -> Result<()> {
+        let id = block.id;
+        let mut acks = self.peer_manager.expect_block_ack(id);
+        let res = async {
+            info!("Offloading block {} to peer {}", id, peer_id);
+            let msg = Message::PutBlock { id, data: block.data, durability: Some(block.durability), trace_id: crate::trace::current_request_id(), priority: Some(block.priority) };
+            self.peer_manager.send_to_peer(peer_id, &msg).await?;
+            match tokio::time::timeout(BLOCK_ACK_TIMEOUT, acks.recv()).await {
+                Ok(Ok(Ok(()))) => Ok(()),
+                Ok(Ok(Err(reason))) => bail!("Peer rejected block {}: {}", id, reason),
+                Ok(Err(e)) => bail!("Recv error: {}", e),
+                Err(_) => {
+                    // It may still arrive there; ask the peer not to keep it
+                    let _ = self.peer_manager.send_to_peer(peer_id, &Message::ReleaseBlock { id }).await;
+                    bail!("Timeout waiting for the peer to confirm block {}", id)
+                }
+            }
+        }.await;
+        self.peer_manager.forget_block_ack(id);
+        res
+    }
+
+    /// The peer refused a block we offloaded; stop routing reads for it there.
+    pub fn forget_remote_block(&self, id: BlockId, peer_id: uuid::Uuid) {
+        if let Some((_, (_, size))) = self.remote_locations.remove_if(&id, |_, (p, _)| *p == peer_id) {
+            self.peer_manager.release_offloaded(peer_id, size);
+        }
+    }
+
+    /// Ask the peer holding `id` for us to drop it. Returns the block's size,
+    /// or None if `id` is not stored on a peer.
+    pub async fn free_remote(&self, id: BlockId) -> Result<Option<u64>> {
+        let Some((peer_id, size)) = self.remote_locations.get(&id).map(|l| *l.value()) else {
+            return Ok(None);
+        };
+        self.peer_manager.send_to_peer(peer_id, &crate::net::Message::ReleaseBlock { id }).await?;
+        self.forget_remote_block(id, peer_id);
+        Ok(Some(size))
+    }
+
+    /// Remember that `id` was stored here at `peer_id`'s request.
+    pub fn record_hosted_block(&self, id: BlockId, peer_id: uuid::Uuid) {
+        self.hosted_for.insert(id, peer_id);
+    }
+
+    /// Bytes of blocks still held here for `peer_id`. Entries whose block has
+    /// since been evicted are dropped along the way.
+    pub fn hosted_storage_for(&self, peer_id: uuid::Uuid) -> u64 {
+        let mut used = 0;
+        self.hosted_for.retain(|id, owner| match self.blocks.get(id) {
+            Some(block) => {
+                if *owner == peer_id {
+                    used += block.data.len() as u64;
+                }
+                true
+            }
+            None => false,
+        });
+        used
+    }
+
+    /// Bring a (re)connected peer's storage accounting in line with what we actually hold for it.
+    pub fn resync_peer_storage(&self, peer_id: uuid::Uuid) {
+        let used = self.hosted_storage_for(peer_id);
+        self.peer_manager.resync_remote_used_storage(peer_id, used);
     }
 
     pub fn get_peer_list(&self) -> Vec<String> {
@@ -99,40 +511,39 @@ impl InMemoryBlockManager {
         self.peer_manager.get_peer_metadata_list()
     }
 
-    pub async fn connect_peer(&self, addr: &str, block_manager: Arc<InMemoryBlockManager>, quota: u64) -> Result<crate::peers::PeerMetadata> {
-        self.peer_manager.manual_connect(addr, block_manager, self.peer_manager.clone(), quota).await
+    pub async fn connect_peer(&self, addr: &str, block_manager: Arc<InMemoryBlockManager>, quota: u64, policy: crate::peers::DialPolicy) -> Result<crate::peers::PeerMetadata> {
+        self.peer_manager.manual_connect(addr, block_manager, self.peer_manager.clone(), quota, policy).await
     }
     
-    pub async fn disconnect_peer(&self, target: &str) -> Result<bool> {
-         let peer_id = if let Ok(uid) = uuid::Uuid::parse_str(target) {
-              Some(uid)
-         } else {
-              self.peer_manager.get_peer_id_by_name(target)
-         };
-         
-         if let Some(id) = peer_id {
-             Ok(self.peer_manager.disconnect_peer(id).await)
-         } else {
-             Ok(false)
+    /// Refused while `target` is being drained, unless `force` cuts the drain short.
+    pub async fn disconnect_peer(&self, target: &str, force: bool) -> Result<bool> {
+         let id = self.peer_manager.resolve_peer(target)?;
+         if self.is_draining(id) && !force {
+             bail!("Peer {} is being drained; force the disconnect to cut it short", target);
          }
+         self.abort_drain(id);
+         Ok(self.peer_manager.disconnect_peer(id).await)
     }
 
-    pub async fn update_peer_quota(&self, target: &str, quota: u64) -> Result<()> {
-        let peer_id = if let Ok(uid) = uuid::Uuid::parse_str(target) {
-             Some(uid)
-        } else {
-             self.peer_manager.get_peer_id_by_name(target)
-        };
+    pub async fn update_peer_quota(&self, target: &str, quota: u64) -> Result<Option<u64>> {
+        let id = self.peer_manager.resolve_peer(target)?;
+        self.peer_manager.set_allowed_quota(id, quota).await
+    }
 
-        if let Some(id) = peer_id {
-             self.peer_manager.set_allowed_quota(id, quota).await
-        } else {
-             anyhow::bail!("Peer '{}' not found", target)
-        }
+    /// How much we keep on the connected peer `target`.
+    pub fn peer_stats(&self, target: &str) -> Result<memsdk::PeerStats> {
+        let id = self.peer_manager.resolve_peer(target)?;
+        Ok(memsdk::PeerStats {
+            used: self.peer_manager.offloaded_storage(id),
+            quota: self.peer_manager.remote_quota(id).unwrap_or(0),
+            blocks_remote: self.remote_locations.iter().filter(|l| l.value().0 == id).count(),
+        })
     }
 
     fn evict_garbage(&self, needed: u64) -> u64 {
-        let mut freed = 0;
+        // Expired blocks go first; they are dead weight whatever their last access
+        let expired = self.sweep_expired(unix_now()).bytes_freed;
+        let (mut freed, mut evicted) = (expired, 0);
         let mut attempts = 0;
         let max_attempts = 100; // Prevent infinite loop
 
@@ -140,14 +551,15 @@ impl InMemoryBlockManager {
             
             let mut candidates: Vec<(BlockId, u64)> = Vec::new();
             
+            // Lowest priority first, then least recently used
             let mut best_candidate: Option<BlockId> = None;
-            let mut oldest_time = u64::MAX;
+            let mut lowest = (u8::MAX, u64::MAX);
             
             for entry in self.blocks.iter() {
-                if entry.value().durability == memsdk::Durability::Cache {
-                    let last = entry.value().last_accessed.load(Ordering::Relaxed);
-                    if last < oldest_time {
-                        oldest_time = last;
+                if entry.value().durability.is_evictable() {
+                    let rank = (entry.value().priority, entry.value().last_accessed.load(Ordering::Relaxed));
+                    if best_candidate.is_none() || rank < lowest {
+                        lowest = rank;
                         best_candidate = Some(*entry.key());
                     }
                 }
@@ -156,6 +568,7 @@ impl InMemoryBlockManager {
             if let Some(id) = best_candidate {
                 if let Ok(Some(block)) = self.evict_block(id) {
                      freed += block.data.len() as u64;
+                     evicted += 1;
                 }
             } else {
                 // No cache blocks found
@@ -163,75 +576,83 @@ impl InMemoryBlockManager {
             }
             attempts += 1;
         }
+        if evicted > 0 {
+            self.peer_manager.events.record("eviction", "evicted", None, Some(freed - expired), format!("{} lowest priority, least recently used blocks, to make room for {} bytes", evicted, needed));
+        }
         freed
     }
 
-    pub async fn get_remote(&self, key: &str, target: &str) -> Result<Option<Vec<u8>>> {
-        let peer_id_opt = if let Ok(uid) = uuid::Uuid::parse_str(target) {
-            Some(uid)
-        } else {
-            self.peer_manager.get_peer_id_by_name(target)
-        };
-
-        if let Some(peer_id) = peer_id_opt {
-             let msg = crate::net::Message::GetKey { key: key.to_string() };
-             self.peer_manager.send_to_peer(peer_id, &msg).await?;
-             // Reuse existing wait logic
-             match self.peer_manager.wait_for_key(key).await {
-                 Ok(data) => Ok(Some(data)),
-                 Err(_) => Ok(None), 
-             }
-        } else {
-             anyhow::bail!("Peer not found: {}", target)
-        }
+    pub async fn get_remote(&self, key: &str, target: &str) -> Result<Option<RemoteValue>> {
+        let peer_id = self.peer_manager.resolve_peer(target)?;
+        let msg = crate::net::Message::GetKey { key: key.to_string(), trace_id: crate::trace::current_request_id() };
+        let fut = self.peer_manager.wait_for_key(key, Some(peer_id));
+        self.peer_manager.send_to_peer(peer_id, &msg).await?;
+        fut.await
     }
 
-    pub fn put_named_block(&self, key: String, block: Block) -> Result<()> {
-        let id = block.id;
-        self.put_block(block)?;
-        self.key_index.insert(key.clone(), id);
+    pub fn put_named_block(&self, key: String, block: Block) -> Result<(BlockId, u64)> {
+        let (id, size) = self.put_block(block)?;
+        let old = match self.key_index.entry(key.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(mut slot) => {
+                let old = slot.insert(id);
+                self.name_block(id, &key);
+                (old != id).then(|| {
+                    self.unname_block(old, &key);
+                    old
+                })
+            }
+            dashmap::mapref::entry::Entry::Vacant(slot) => {
+                self.order_key(&key);
+                self.name_block(id, &key);
+                slot.insert(id);
+                None
+            }
+        };
+        if let Some(old) = old {
+            self.detach_tags(old, &key);
+        }
         info!("Stored named block '{}' -> {}", key, id);
-        Ok(())
+        Ok((id, size))
     }
     
     pub fn get_named_block_id(&self, key: &str) -> Option<BlockId> {
         self.key_index.get(key).map(|v| *v)
     }
 
-    pub fn set(&self, key: &str, data: Vec<u8>, durability: memsdk::Durability) -> Result<BlockId> {
-        let id = rand::random::<u64>();
-        let block = Block { 
-            id, 
-            data, 
-            durability,
-            last_accessed: std::sync::Arc::new(AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs())) 
-        };
-        self.put_named_block(key.to_string(), block)?;
+    pub fn set(&self, key: &str, data: impl Into<Bytes>, durability: memsdk::Durability) -> Result<BlockId> {
+        let block = self.new_block(data, durability);
+        let (id, _) = self.put_named_block(key.to_string(), block)?;
         Ok(id)
     }
 
-    pub async fn set_remote(&self, key: &str, data: Vec<u8>, target: &str, durability: memsdk::Durability) -> Result<BlockId> {
-        
-        let peer_id_opt = if let Ok(uid) = uuid::Uuid::parse_str(target) {
-            Some(uid)
-        } else {
-            self.peer_manager.get_peer_id_by_name(target)
-        };
+    /// `set` at `priority`, replacing the key's tags with `tags`.
+    pub fn set_tagged(&self, key: &str, data: Vec<u8>, durability: memsdk::Durability, priority: u8, tags: memsdk::Tags) -> Result<BlockId> {
+        tags::check_tags(&tags)?;
+        let block = Block { priority, ..self.new_block(data, durability) };
+        let (id, _) = self.put_named_block(key.to_string(), block)?;
+        self.tag_block(id, Some(key), tags);
+        Ok(id)
+    }
 
-        if let Some(peer_id) = peer_id_opt {
-             self.peer_manager.set_key_remote(peer_id, key.to_string(), data, durability).await?;
-             // Wait for ack
-             self.peer_manager.wait_for_key_store(key).await
-        } else {
-             anyhow::bail!("Peer not found: {}", target)
+    pub async fn set_remote(&self, key: &str, data: Vec<u8>, target: &str, durability: memsdk::Durability, priority: Option<u8>) -> Result<BlockId> {
+        let peer_id = self.peer_manager.resolve_peer(target)?;
+        let size = data.len() as u64;
+        self.peer_manager.set_key_remote(peer_id, key.to_string(), data, durability, priority).await?;
+        // Wait for ack
+        let id = self.peer_manager.wait_for_key_store(key).await?;
+        self.peer_manager.record_offloaded(peer_id, size);
+        // An overwritten value is gone from the peer that held it
+        if let Some((old_peer, old_size)) = self.remote_keys.insert(key.to_string(), (peer_id, size)) {
+            self.peer_manager.release_offloaded(old_peer, old_size);
         }
+        Ok(id)
     }
 
-    pub async fn get_distributed_key(&self, key: &str) -> Result<Option<Vec<u8>>> {
+    pub async fn get_distributed_key(&self, key: &str) -> Result<Option<RemoteValue>> {
         // 1. Try Local
         if let Some(id) = self.get_named_block_id(key) {
             if let Ok(Some(block)) = self.get_block_async(id).await {
-                return Ok(Some(block.data));
+                return Ok(Some((block.data, Some(block.durability), Some(block.priority))));
             }
         }
         
@@ -239,74 +660,91 @@ impl InMemoryBlockManager {
         // info!("Key '{}' not found locally, broadcasting query...", key);
         
         // Start waiting
-        let fut = self.peer_manager.wait_for_key(key);
+        let fut = self.peer_manager.wait_for_key(key, None);
         
         // Broadcast
         self.peer_manager.broadcast_get_key(key).await?;
         
-        // Wait
+        // Wait; a peer too slow to answer counts as not having the key
         match fut.await {
-            Ok(data) => {
+            Ok(Some(value)) => {
                 info!("Found key '{}' on a peer!", key);
-                Ok(Some(data))
+                Ok(Some(value))
             }
-            Err(_) => {
+            Ok(None) | Err(_) => {
                 Ok(None)
             }
         }
     }
 
     pub fn list_keys(&self, pattern: &str) -> Vec<String> {
-        let starts_wild = pattern.starts_with('*');
-        let ends_wild = pattern.ends_with('*');
-        let clean_pat = pattern.trim_matches('*');
-        
-        // Optimize: Special case for "*" to just collect directly
-        if pattern == "*" {
+        let glob = memsdk::glob::Glob::new(pattern);
+
+        // Fast paths: "*" collects everything, a pattern without wildcards is a lookup
+        if glob.matches_all() {
             return self.key_index.iter().map(|kv| kv.key().clone()).collect();
         }
+        if let Some(key) = glob.literal() {
+            return if self.key_index.contains_key(&key) { vec![key] } else { Vec::new() };
+        }
 
         self.key_index.iter()
-            .filter(|kv| {
-                let k = kv.key();
-                if starts_wild && ends_wild {
-                    k.contains(clean_pat)
-                } else if starts_wild {
-                    k.ends_with(clean_pat)
-                } else if ends_wild {
-                    k.starts_with(clean_pat)
-                } else {
-                    k == clean_pat
-                }
-            })
+            .filter(|kv| glob.is_match(kv.key()))
             .map(|kv| kv.key().clone())
             .collect()
     }
 
+    /// Matching keys here and on every peer that answers in time, one entry
+    /// per key with the nodes holding it, local copies first.
+    pub async fn list_keys_with_remote(&self, pattern: &str) -> memsdk::KeyListing {
+        let mut holders: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
+        for key in self.list_keys(pattern) {
+            holders.entry(key).or_default().push(memsdk::LOCAL_HOLDER.to_string());
+        }
+        let (answered, unanswered) = self.peer_manager.list_remote_keys(pattern).await;
+        let mut truncated = Vec::new();
+        for peer in answered {
+            for key in peer.keys {
+                holders.entry(key).or_default().push(peer.name.clone());
+            }
+            if peer.truncated {
+                truncated.push(peer.name);
+            }
+        }
+        memsdk::KeyListing {
+            items: holders.into_iter().map(|(key, holders)| memsdk::KeyLocation { key, holders }).collect(),
+            truncated,
+            unanswered,
+        }
+    }
+
     pub async fn get_block_async(&self, id: BlockId) -> Result<Option<Block>> {
          // 1. Try Local
          if let Some(entry) = self.blocks.get(&id) {
+            self.record_hit(id, entry.data.len() as u64, false);
             return Ok(Some(entry.clone()));
          }
          
          // 2. Check Remote
-         if let Some(peer_id) = self.remote_locations.get(&id) {
-             info!("Block {} is remote at {}, fetching...", id, peer_id.value());
+         if let Some(peer_id) = self.remote_locations.get(&id).map(|l| l.value().0) {
+             info!("Block {} is remote at {}, fetching...", id, peer_id);
              
              // A. Start Waiting
-             let fut = self.peer_manager.wait_for_block(id);
+             let fut = self.peer_manager.wait_for_block(peer_id, id);
              
              // B. Send Request
-             self.peer_manager.request_block(*peer_id.value(), id).await?;
+             self.peer_manager.request_block(peer_id, id).await?;
              
              // C. Wait Result
-             let data = fut.await?;
+             let (data, durability, priority) = fut.await?;
              info!("Fetched block {} from peer", id);
              return Ok(Some(Block { 
                  id, 
                  data,
-                 durability: memsdk::Durability::Cache, 
-                 last_accessed: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()))
+                 // Same default the peer applies to a PutBlock without a mode
+                 durability: durability.unwrap_or(memsdk::Durability::Pinned), 
+                 last_accessed: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs())),
+                 priority: priority.unwrap_or(memsdk::DEFAULT_PRIORITY),
              }));
          }
          
@@ -314,68 +752,397 @@ impl InMemoryBlockManager {
     }
 
     // Streaming Logic
-    pub fn start_stream(&self, size_hint: Option<u64>) -> u64 {
+    /// Refused with `MemoryPressure` while over the hard RSS limit, and once
+    /// `uploads::MAX_STREAMS` are open. With a `target` the upload goes
+    /// straight to that peer instead of being buffered.
+    pub async fn start_stream(&self, size_hint: Option<u64>, target: Option<String>) -> Result<u64> {
+        self.memory_guard.check_stream()?;
+        self.check_stream_slot()?;
+        let forward = match target {
+            Some(target) => Some(Arc::new(self.start_forward(&target, size_hint).await?)),
+            None => None,
+        };
+        if let Some(hint) = size_hint.filter(|h| forward.is_none() && *h > self.max_stream_memory()) {
+            self.record_stream_rejected();
+            anyhow::bail!("Stream of {} bytes cannot fit in this node's {} byte stream memory", hint, self.max_stream_memory());
+        }
         let stream_id = rand::random::<u64>();
-        let capacity = size_hint.unwrap_or(0) as usize;
-        self.active_uploads.insert(stream_id, Vec::with_capacity(capacity));
+        // The hint is the client's word; let the buffer grow past this as chunks arrive
+        let capacity = if forward.is_some() { 0 } else { size_hint.unwrap_or(0).min(STREAM_PREALLOCATE_MAX) as usize };
+        self.active_uploads.insert(stream_id, Upload {
+            data: Vec::with_capacity(capacity),
+            size_hint,
+            started: std::time::Instant::now(),
+            last_activity: std::time::Instant::now(),
+            forward,
+        });
         info!("Started stream upload ID: {} (Hint: {:?})", stream_id, size_hint);
-        stream_id
+        Ok(stream_id)
     }
 
-    pub fn append_stream(&self, stream_id: u64, data: Vec<u8>) -> Result<()> {
-        if let Some(mut stream_buffer) = self.active_uploads.get_mut(&stream_id) {
-            stream_buffer.extend_from_slice(&data);
-            Ok(())
-        } else {
-            anyhow::bail!("Stream ID {} not found or already closed", stream_id);
+    /// A forwarded upload fails for good, and is dropped, once the peer refuses
+    /// it; a buffered one once all uploads together would outgrow the node and
+    /// it is the least progressed (see `uploads`).
+    pub async fn append_stream(&self, stream_id: u64, data: Vec<u8>) -> Result<()> {
+        let forward = match self.active_uploads.get_mut(&stream_id) {
+            Some(mut upload) => {
+                upload.last_activity = std::time::Instant::now();
+                upload.forward.clone()
+            }
+            None => return Err(self.unknown_stream(stream_id)),
+        };
+        // Released first: making room looks at every upload
+        let Some(forward) = forward else {
+            return self.buffer_chunk(stream_id, &data);
+        };
+        let res = self.forward_chunk(&forward, &data).await;
+        if let Err(e) = &res {
+            if self.active_uploads.remove(&stream_id).is_some() {
+                self.fail_forward(&forward, e).await;
+            }
         }
+        res
     }
 
-    pub fn finalize_stream(&self, stream_id: u64) -> Result<Vec<u8>> {
-        if let Some((_, data)) = self.active_uploads.remove(&stream_id) {
-            Ok(data)
-        } else {
-            anyhow::bail!("Stream ID {} not found", stream_id);
+    /// Store the upload here, or on `target`, or complete it on the peer it was
+    /// forwarded to.
+    pub async fn finish_stream(&self, stream_id: u64, target: Option<String>, durability: memsdk::Durability, priority: u8) -> Result<(BlockId, u64)> {
+        let Some((_, upload)) = self.active_uploads.remove(&stream_id) else {
+            return Err(self.unknown_stream(stream_id));
+        };
+        self.release_upload_bytes(upload.data.len() as u64);
+        if let Some(forward) = upload.forward {
+            let res = self.finish_forward(&forward, durability, priority).await;
+            if let Err(e) = &res {
+                self.fail_forward(&forward, e).await;
+            }
+            return res;
+        }
+        let block = Block { priority, ..self.new_block(upload.data, durability) };
+        match target {
+            Some(t) => self.put_block_remote(block, Some(t)).await,
+            None => self.put_block(block),
         }
     }
 
-    pub fn flush(&self) {
+    /// Uploads started but not yet finished, oldest first.
+    pub fn list_streams(&self) -> Vec<memsdk::StreamInfo> {
+        let mut items: Vec<memsdk::StreamInfo> = self.active_uploads.iter().map(|e| memsdk::StreamInfo {
+            stream_id: *e.key(),
+            bytes_buffered: e.value().data.len() as u64,
+            size_hint: e.value().size_hint,
+            age_secs: e.value().started.elapsed().as_secs(),
+            target: e.value().forward.as_ref().map(|f| f.peer_name.clone()),
+            bytes_forwarded: e.value().forward.as_ref().map_or(0, |f| f.sent.load(Ordering::Relaxed)),
+        }).collect();
+        items.sort_by(|a, b| b.age_secs.cmp(&a.age_secs).then(a.stream_id.cmp(&b.stream_id)));
+        items
+    }
+
+    /// Drop an unfinished upload and its buffered bytes, or what a peer has of it.
+    pub async fn abort_stream(&self, stream_id: u64) -> Result<()> {
+        match self.active_uploads.remove(&stream_id) {
+            Some((_, upload)) => {
+                self.release_upload_bytes(upload.data.len() as u64);
+                if let Some(forward) = &upload.forward {
+                    self.abort_forward(forward).await;
+                }
+                info!("Aborted stream upload ID: {} ({} bytes buffered)", stream_id, upload.data.len());
+                Ok(())
+            }
+            None => anyhow::bail!("Stream ID {} not found", stream_id),
+        }
+    }
+
+    /// Remove every TTL block whose expiry is at or before `now` (unix seconds).
+    pub fn sweep_expired(&self, now: u64) -> FlushStats {
+        let expired: Vec<BlockId> = self.expiries.iter()
+            .filter(|e| *e.value() <= now)
+            .map(|e| *e.key())
+            .collect();
+        let mut stats = FlushStats::default();
+        for id in expired {
+            if let Ok(Some(block)) = self.evict_block(id) {
+                stats.blocks_removed += 1;
+                stats.bytes_freed += block.data.len() as u64;
+            }
+        }
+        if stats.blocks_removed > 0 {
+            info!("Expired {} TTL blocks ({} bytes)", stats.blocks_removed, stats.bytes_freed);
+            self.peer_manager.events.record("eviction", "expired", None, Some(stats.bytes_freed), format!("{} TTL blocks", stats.blocks_removed));
+        }
+        stats
+    }
+
+    /// Sweep expired TTL blocks every `TTL_SWEEP_INTERVAL`, for the life of the node.
+    pub async fn run_ttl_sweeper(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(TTL_SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            self.sweep_expired(unix_now());
+        }
+    }
+
+    /// Sample process RSS every `PRESSURE_SAMPLE_INTERVAL`, shedding cache
+    /// blocks while over the limits. Freed blocks only lower RSS once the
+    /// allocator hands pages back, so shedding repeats on each sample until
+    /// the node recovers or no cache is left.
+    pub async fn run_memory_guard(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(pressure::PRESSURE_SAMPLE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            // Limits can be switched on by a config reload
+            if !self.memory_guard.enabled() {
+                continue;
+            }
+            let Some(rss) = pressure::process_rss() else { continue };
+            if self.memory_guard.observe(rss) == memsdk::PressureLevel::Normal {
+                continue;
+            }
+            let freed = self.evict_garbage(self.memory_guard.excess());
+            if freed > 0 {
+                info!("Shed {} bytes of cache under memory pressure", freed);
+            }
+        }
+    }
+
+    /// Count a read of a local block, served to a client of this node or to a peer.
+    pub fn record_hit(&self, id: BlockId, bytes: u64, from_peer: bool) {
+        let now = unix_now();
+        if let Some(hits) = self.hits.get(&id) {
+            hits.record(bytes, from_peer, now);
+            return;
+        }
+        self.hits.entry(id).or_default().record(bytes, from_peer, now);
+    }
+
+    /// Decay recent-read scores every `HIT_DECAY_INTERVAL`, for the life of the node.
+    pub async fn run_hit_decay(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(HIT_DECAY_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for entry in self.hits.iter() {
+                entry.value().decay();
+            }
+        }
+    }
+
+    /// The `top_n` most-read blocks, busiest first. With a window only blocks
+    /// read within the last `window_secs` are considered, ranked by recent reads.
+    pub fn hot_blocks(&self, top_n: usize, window_secs: Option<u64>) -> Vec<memsdk::HotBlock> {
+        let since = window_secs.map(|w| unix_now().saturating_sub(w));
+        let mut ranked: Vec<(f64, BlockId)> = self.hits.iter()
+            .filter(|e| since.is_none_or(|t| e.value().last_hit() >= t) && self.blocks.contains_key(e.key()))
+            .map(|e| {
+                let rank = if since.is_some() { e.value().score() } else { e.value().total() as f64 };
+                (rank, *e.key())
+            })
+            .collect();
+        let busiest_first = |a: &(f64, BlockId), b: &(f64, BlockId)| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1));
+        if ranked.len() > top_n {
+            ranked.select_nth_unstable_by(top_n, busiest_first);
+            ranked.truncate(top_n);
+        }
+        ranked.sort_unstable_by(busiest_first);
+        ranked.into_iter()
+            .filter_map(|(_, id)| {
+                let key = self.key_of(id);
+                self.hits.get(&id).map(|h| h.report(id, key))
+            })
+            .collect()
+    }
+
+    /// Local blocks, bytes and keys per client application, largest first.
+    pub fn app_usage(&self) -> Vec<memsdk::AppUsage> {
+        let mut usage: std::collections::HashMap<Arc<apps::AppTag>, memsdk::AppUsage> = std::collections::HashMap::new();
+        for entry in self.owners.iter() {
+            let Some(size) = self.blocks.get(entry.key()).map(|b| b.data.len() as u64) else { continue };
+            let app = entry.value();
+            let row = usage.entry(app.clone()).or_insert_with(|| memsdk::AppUsage {
+                app_name: app.name.clone(),
+                pid: app.pid,
+                blocks: 0,
+                bytes: 0,
+                keys: 0,
+            });
+            row.blocks += 1;
+            row.bytes += size;
+            row.keys += self.indexed_ids.contains_key(entry.key()) as u64;
+        }
+        let mut rows: Vec<_> = usage.into_values().collect();
+        rows.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.app_name.cmp(&b.app_name)));
+        rows
+    }
+
+    fn is_expired(&self, id: BlockId, now: u64) -> bool {
+        self.expiries.get(&id).is_some_and(|at| *at <= now)
+    }
+
+    pub fn flush(&self) -> FlushStats {
+        let stats = FlushStats {
+            blocks_removed: self.blocks.len() as u64,
+            bytes_freed: self.current_memory.load(Ordering::Relaxed),
+        };
         self.blocks.clear();
         self.key_index.clear();
+        self.prune_key_order();
+        self.indexed_ids.clear();
         self.remote_locations.clear();
+        // Peers whose blocks we held have nothing stored here any more
+        for owner in self.hosted_for.iter().map(|h| *h.value()).collect::<std::collections::HashSet<_>>() {
+            self.peer_manager.resync_remote_used_storage(owner, 0);
+        }
+        self.hosted_for.clear();
+        self.expiries.clear();
+        self.hits.clear();
+        self.owners.clear();
+        self.tags.clear();
+        self.tag_index.clear();
+        self.checksums.clear();
+        self.content_refs.clear();
         self.active_uploads.clear();
+        self.reset_upload_bytes();
         self.current_memory.store(0, Ordering::Relaxed);
+        self.pinned_bytes.store(0, Ordering::Relaxed);
+        self.cache_bytes.store(0, Ordering::Relaxed);
         info!("Cluster memory flushed locally.");
+        stats
     }
 
-    pub async fn flush_remote(&self, target: String) -> Result<()> {
-        let peer_id = if let Ok(uid) = uuid::Uuid::parse_str(&target) {
-             Some(uid)
-        } else {
-             self.peer_manager.get_peer_id_by_name(&target)
+    /// Selective flush. With a pattern only matching keys are removed, and
+    /// their blocks once no other key names them; with a durability only blocks of that mode, named or not. Both
+    /// filters combine; with neither this is a full `flush`.
+    pub fn flush_filtered(&self, pattern: Option<&str>, durability: Option<memsdk::Durability>) -> FlushStats {
+        if pattern.is_none() && durability.is_none() {
+            return self.flush();
+        }
+
+        let mut stats = FlushStats::default();
+        let matches_durability = |id: &BlockId| match durability {
+            Some(d) => self.blocks.get(id).is_some_and(|b| b.durability == d),
+            None => true,
         };
 
-        if let Some(id) = peer_id {
-            info!("Sending Flush command to peer {}", id);
-            let msg = Message::Flush;
-            self.peer_manager.send_to_peer(id, &msg).await?;
-            Ok(())
+        let doomed: Vec<BlockId> = if let Some(p) = pattern {
+            let keys: Vec<(String, BlockId)> = self.list_keys(p).into_iter()
+                .filter_map(|k| self.get_named_block_id(&k).map(|id| (k, id)))
+                .filter(|(_, id)| matches_durability(id))
+                .collect();
+            let mut doomed = Vec::new();
+            for (k, id) in keys {
+                match self.unbind_key(&k, id) {
+                    Some(false) => doomed.push(id),
+                    Some(true) => self.detach_tags(id, &k),
+                    None => {}
+                }
+            }
+            doomed
         } else {
-            anyhow::bail!("Peer '{}' not found", target);
+            let ids: std::collections::HashSet<BlockId> = self.blocks.iter()
+                .filter(|e| Some(e.value().durability) == durability)
+                .map(|e| *e.key())
+                .collect();
+            self.key_index.retain(|_, id| !ids.contains(id));
+            self.prune_key_order();
+            self.indexed_ids.retain(|id, _| !ids.contains(id));
+            ids.into_iter().collect()
+        };
+
+        for id in doomed {
+            if let Ok(Some(block)) = self.evict_block(id) {
+                let size = block.data.len() as u64;
+                if let Some((_, owner)) = self.hosted_for.remove(&id) {
+                    self.peer_manager.release_storage(owner, size);
+                }
+                stats.blocks_removed += 1;
+                stats.bytes_freed += size;
+            }
+        }
+        info!("Selective flush (pattern: {:?}, durability: {:?}) removed {} blocks ({} bytes)", pattern, durability, stats.blocks_removed, stats.bytes_freed);
+        stats
+    }
+
+    /// What a peer's Flush removes unless it may flush everything: only the
+    /// blocks stored here for `peer_id`, picked by pattern and durability as
+    /// in `flush_filtered`. A block goes with all its keys.
+    pub fn flush_hosted(&self, peer_id: uuid::Uuid, pattern: Option<&str>, durability: Option<memsdk::Durability>) -> FlushStats {
+        let candidates: std::collections::HashSet<BlockId> = match pattern {
+            Some(p) => self.list_keys(p).iter().filter_map(|k| self.get_named_block_id(k)).collect(),
+            None => self.hosted_for.iter().filter(|h| *h.value() == peer_id).map(|h| *h.key()).collect(),
+        };
+        let mut stats = FlushStats::default();
+        for id in candidates {
+            if durability.is_some_and(|d| self.blocks.get(&id).is_none_or(|b| b.durability != d)) {
+                continue;
+            }
+            if self.hosted_for.remove_if(&id, |_, owner| *owner == peer_id).is_none() {
+                continue;
+            }
+            for key in self.names_of(id) {
+                self.unbind_key(&key, id);
+            }
+            if let Ok(Some(block)) = self.evict_block(id) {
+                let size = block.data.len() as u64;
+                self.peer_manager.release_storage(peer_id, size);
+                stats.blocks_removed += 1;
+                stats.bytes_freed += size;
+            }
         }
+        info!("Flush from peer {} (pattern: {:?}, durability: {:?}) removed {} of its blocks ({} bytes)", peer_id, pattern, durability, stats.blocks_removed, stats.bytes_freed);
+        stats
+    }
+
+    /// Flush a peer and return what it removed, once it has confirmed. Unless
+    /// the peer allows this node to flush it entirely, that is only what this
+    /// node stored there.
+    pub async fn flush_remote(&self, target: String, pattern: Option<String>, durability: Option<memsdk::Durability>) -> Result<FlushStats> {
+        let id = self.peer_manager.resolve_peer(&target)?;
+        info!("Sending Flush command to peer {}", id);
+        let full = pattern.is_none() && durability.is_none();
+        let stats = self.peer_manager.flush_peer(id, pattern, durability).await?;
+        if full {
+            // The peer dropped everything we had stored there
+            self.remote_locations.retain(|_, (p, _)| *p != id);
+            self.remote_keys.retain(|_, (p, _)| *p != id);
+            self.peer_manager.release_offloaded(id, u64::MAX);
+        }
+        Ok(stats)
     }
 
     pub fn get_max_memory(&self) -> u64 {
         self.max_memory
     }
 
+    fn durability_bytes(&self, durability: memsdk::Durability) -> &AtomicU64 {
+        if durability.is_evictable() { &self.cache_bytes } else { &self.pinned_bytes }
+    }
+
+    /// Bytes held in pinned blocks and in evictable (cache and TTL) blocks.
+    pub fn usage_by_durability(&self) -> (u64, u64) {
+        (self.pinned_bytes.load(Ordering::Relaxed), self.cache_bytes.load(Ordering::Relaxed))
+    }
+
+    /// Self-check for `Ping`: supervised tasks and registered checks, plus memory use.
+    pub async fn health_report(&self) -> (bool, Vec<String>) {
+        let (mut healthy, mut details) = self.health.report().await;
+        let used = self.used_space();
+        if used > self.max_memory {
+            healthy = false;
+            details.push(format!("memory: {} bytes used, over the {} byte limit", used, self.max_memory));
+        } else {
+            details.push(format!("memory: ok ({} of {} bytes)", used, self.max_memory));
+        }
+        (healthy, details)
+    }
+
     pub fn vm_alloc(&self, size: u64) -> u64 {
         let id = self.vm_manager.create_region(size);
         info!("VM: Allocated region {} of size {} bytes", id, size);
         id
     }
 
-    pub async fn vm_fetch(&self, region_id: u64, page_index: u64) -> Result<Vec<u8>> {
+    /// A page's data, shared with the block holding it; a page never stored reads as zeros.
+    pub async fn vm_fetch(&self, region_id: u64, page_index: u64) -> Result<Bytes> {
         info!("VM: Fetching page {} for region {}", page_index, region_id);
         let region = self.vm_manager.get_region(region_id).ok_or_else(|| anyhow::anyhow!("Region not found"))?;
         let block_id_opt = region.pages.get(&page_index).map(|v| *v);
@@ -385,7 +1152,7 @@ impl InMemoryBlockManager {
                 None => anyhow::bail!("Page data lost (block {} not found)", block_id),
             }
         } else {
-            Ok(vec![0u8; 4096])
+            Ok(Bytes::from_static(&[0u8; 4096]))
         }
     }
 
@@ -393,13 +1160,11 @@ impl InMemoryBlockManager {
         info!("VM: Storing page {} for region {}", page_index, region_id);
         let region = self.vm_manager.get_region(region_id).ok_or_else(|| anyhow::anyhow!("Region not found"))?;
         
-        let id = rand::random::<u64>();
         let block = Block {
-            id,
-            data,
-            durability: memsdk::Durability::Pinned,
             last_accessed: Arc::new(AtomicU64::new(0)),
+            ..self.new_block(data, memsdk::Durability::Pinned)
         };
+        let id = block.id;
 
         if let Err(e) = self.put_block_remote(block.clone(), None).await {
             log::warn!("Failed to store VM page remote: {}. Storing locally.", e);
@@ -410,14 +1175,18 @@ impl InMemoryBlockManager {
         Ok(())
     }
 
-    pub fn vm_free(&self, region_id: u64) -> Result<()> {
+    /// Free a VM region and its stored pages. Returns the bytes the pages held.
+    pub fn vm_free(&self, region_id: u64) -> Result<u64> {
         if let Some(region) = self.vm_manager.remove_region(region_id) {
             info!("Freeing VM region {} ({} bytes)", region_id, region.size);
+            let mut freed = 0;
             for entry in region.pages.iter() {
                 let block_id = *entry.value();
-                let _ = self.evict_block(block_id);
+                if let Ok(Some(page)) = self.evict_block(block_id) {
+                    freed += page.data.len() as u64;
+                }
             }
-            Ok(())
+            Ok(freed)
         } else {
             anyhow::bail!("VM Region not found")
         }
@@ -425,8 +1194,12 @@ impl InMemoryBlockManager {
 }
 
 impl BlockManager for InMemoryBlockManager {
-    fn put_block(&self, block: Block) -> Result<()> {
+    fn put_block(&self, block: Block) -> Result<(BlockId, u64)> {
+        self.memory_guard.check_store()?;
         let size = block.data.len() as u64;
+        if self.blocks.contains_key(&block.id) {
+            return Err(BlockExists(block.id).into());
+        }
         
         // Check Memory Limit
         let current = self.current_memory.load(Ordering::Relaxed);
@@ -446,13 +1219,30 @@ impl BlockManager for InMemoryBlockManager {
             }
         }
 
-        self.blocks.insert(block.id, block.clone());
+        let id = block.id;
+        let durability = block.durability;
+        match self.blocks.entry(id) {
+            dashmap::mapref::entry::Entry::Occupied(_) => return Err(BlockExists(id).into()),
+            dashmap::mapref::entry::Entry::Vacant(slot) => { slot.insert(block); }
+        }
+        if let memsdk::Durability::Ttl { secs } = durability {
+            self.expiries.insert(id, unix_now().saturating_add(secs));
+        }
+        if let Some(app) = apps::current_app() {
+            self.owners.insert(id, app);
+        }
         self.current_memory.fetch_add(size, Ordering::Relaxed);
-        info!("Stored block {} ({} bytes, mode: {:?})", block.id, size, block.durability);
-        Ok(())
+        self.durability_bytes(durability).fetch_add(size, Ordering::Relaxed);
+        info!("Stored block {} ({} bytes, mode: {:?})", id, size, durability);
+        Ok((id, size))
     }
 
     fn get_block(&self, id: BlockId) -> Result<Option<Block>> {
+        // Past its TTL but not swept yet: already gone as far as readers are concerned
+        if self.is_expired(id, unix_now()) {
+            self.evict_block(id)?;
+            return Ok(None);
+        }
         if let Some(entry) = self.blocks.get(&id) {
             // Update LRU
             entry.value().last_accessed.store(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(), Ordering::Relaxed);
@@ -467,9 +1257,16 @@ impl BlockManager for InMemoryBlockManager {
     }
 
     fn evict_block(&self, id: BlockId) -> Result<Option<Block>> {
+        self.expiries.remove(&id);
+        self.hits.remove(&id);
+        self.owners.remove(&id);
+        self.checksums.remove(&id);
+        self.content_refs.remove(&id);
+        self.untag_block(id);
         if let Some((_, block)) = self.blocks.remove(&id) {
             let size = block.data.len() as u64;
             self.current_memory.fetch_sub(size, Ordering::Relaxed);
+            self.durability_bytes(block.durability).fetch_sub(size, Ordering::Relaxed);
             info!("Evicted block {}", id);
             Ok(Some(block))
         } else {
@@ -478,10 +1275,373 @@ impl BlockManager for InMemoryBlockManager {
     }
 
     fn free_space(&self) -> u64 {
-        u64::MAX // Unlimited for now
+        self.max_memory.saturating_sub(self.used_space())
     }
 
     fn used_space(&self) -> u64 {
         self.current_memory.load(Ordering::Relaxed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memsdk::{Durability, HashAlgo};
+
+    fn manager() -> InMemoryBlockManager {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        InMemoryBlockManager::new(pm, 1024 * 1024)
+    }
+
+    #[test]
+    fn test_flush_by_pattern_keeps_other_keys() {
+        let bm = manager();
+        bm.set("tmp:a", vec![0; 10], Durability::Pinned).unwrap();
+        bm.set("tmp:b", vec![0; 20], Durability::Cache).unwrap();
+        bm.set("keep", vec![0; 5], Durability::Pinned).unwrap();
+
+        let stats = bm.flush_filtered(Some("tmp:*"), None);
+        assert_eq!(stats, FlushStats { blocks_removed: 2, bytes_freed: 30 });
+        assert_eq!(bm.list_keys("*"), vec!["keep".to_string()]);
+        assert_eq!(bm.used_space(), 5);
+    }
+
+    #[test]
+    fn test_flush_by_durability_includes_unnamed_blocks() {
+        let bm = manager();
+        bm.set("cached", vec![0; 10], Durability::Cache).unwrap();
+        bm.set("pinned", vec![0; 10], Durability::Pinned).unwrap();
+        let unnamed = Block {
+            id: 42,
+            data: vec![0; 7].into(),
+            durability: Durability::Cache,
+            last_accessed: Arc::new(AtomicU64::new(0)),
+            priority: memsdk::DEFAULT_PRIORITY,
+        };
+        bm.put_block(unnamed).unwrap();
+
+        let stats = bm.flush_filtered(None, Some(Durability::Cache));
+        assert_eq!(stats, FlushStats { blocks_removed: 2, bytes_freed: 17 });
+        assert_eq!(bm.list_keys("*"), vec!["pinned".to_string()]);
+        assert!(bm.get_block(42).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_flush_pattern_and_durability_combine() {
+        let bm = manager();
+        bm.set("tmp:a", vec![0; 10], Durability::Pinned).unwrap();
+        bm.set("tmp:b", vec![0; 10], Durability::Cache).unwrap();
+
+        let stats = bm.flush_filtered(Some("tmp:*"), Some(Durability::Cache));
+        assert_eq!(stats.blocks_removed, 1);
+        assert_eq!(bm.list_keys("*"), vec!["tmp:a".to_string()]);
+    }
+
+    #[test]
+    fn test_put_block_rejects_existing_id() {
+        let bm = manager();
+        let block = bm.new_block(vec![1; 8], Durability::Pinned);
+        let id = block.id;
+        assert_eq!(bm.put_block(block).unwrap(), (id, 8));
+
+        let dup = Block { id, ..bm.new_block(vec![2; 4], Durability::Pinned) };
+        let err = bm.put_block(dup).unwrap_err();
+        assert!(err.downcast_ref::<BlockExists>().is_some());
+        assert_eq!(bm.get_block(id).unwrap().unwrap().data, vec![1; 8]);
+        assert_eq!(bm.used_space(), 8);
+    }
+
+    #[test]
+    fn test_content_addressed_store_deduplicates() {
+        let bm = manager();
+        let (id, size) = bm.put_content_addressed(vec![3; 100], Durability::Cache, 30).unwrap();
+        assert_eq!(id, memsdk::content_id(&[3; 100]));
+        assert_eq!(bm.put_content_addressed(vec![3; 100], Durability::Pinned, 200).unwrap(), (id, size));
+        assert_eq!(bm.used_space(), 100);
+        // The pinned store pinned the cached copy, but left its priority alone
+        assert_eq!(bm.usage_by_durability(), (100, 0));
+        assert_eq!(bm.get_block(id).unwrap().unwrap().priority, 30);
+
+        let other = bm.put_content_addressed(vec![4; 100], Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap().0;
+        assert_ne!(other, id);
+        assert_eq!(bm.used_space(), 200);
+    }
+
+    #[test]
+    fn test_checksum_uses_configured_algo() {
+        let data = b"checksummed".to_vec();
+        let (blake3, sha256) = (HashAlgo::Blake3.checksum(&data), HashAlgo::Sha256.checksum(&data));
+        assert_ne!(blake3.digest, sha256.digest);
+
+        for expected in [blake3, sha256] {
+            let bm = manager();
+            bm.set_checksum_algo(expected.algo);
+            let (id, _) = bm.put_content_addressed(data.clone(), Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap();
+            // The id stays a BLAKE3 content id whatever the checksum algorithm
+            assert_eq!(id, memsdk::content_id(&data));
+            let info = bm.block_info(&memsdk::BlockOrKey::Id { id }).unwrap();
+            assert_eq!(info.checksum.as_ref(), Some(&expected));
+            // A repeated store is still recognised as the same data
+            assert_eq!(bm.put_content_addressed(data.clone(), Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap().0, id);
+            bm.evict_block(id).unwrap();
+            assert_eq!(bm.checksum(id), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_and_abort_streams() {
+        let bm = manager();
+        let first = bm.start_stream(Some(100), None).await.unwrap();
+        let second = bm.start_stream(None, None).await.unwrap();
+        bm.append_stream(first, vec![1; 40]).await.unwrap();
+
+        let mut streams = bm.list_streams();
+        streams.sort_by_key(|s| s.stream_id != first);
+        assert_eq!(streams.len(), 2);
+        assert_eq!((streams[0].stream_id, streams[0].bytes_buffered, streams[0].size_hint), (first, 40, Some(100)));
+        assert_eq!((streams[1].stream_id, streams[1].bytes_buffered, streams[1].size_hint), (second, 0, None));
+
+        bm.abort_stream(first).await.unwrap();
+        assert!(bm.abort_stream(first).await.is_err());
+        assert!(bm.append_stream(first, vec![1]).await.is_err());
+        assert_eq!(bm.list_streams().iter().map(|s| s.stream_id).collect::<Vec<_>>(), [second]);
+
+        // A hint beyond the node's capacity is refused before anything is buffered
+        assert!(bm.start_stream(Some(u64::MAX), None).await.is_err());
+        assert_eq!(bm.list_streams().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_memory_pressure_refuses_stores_then_streams() {
+        let bm = manager();
+        bm.memory_guard.set_limits(1000, 2000);
+        assert_eq!(bm.memory_guard.observe(1500), memsdk::PressureLevel::Soft);
+        let err = bm.set("k", vec![1; 8], Durability::Pinned).unwrap_err();
+        assert!(err.downcast_ref::<pressure::MemoryPressure>().is_some());
+        assert!(bm.start_stream(None, None).await.is_ok());
+        assert_eq!(bm.memory_guard.excess(), 500);
+
+        bm.memory_guard.observe(2500);
+        assert!(bm.start_stream(None, None).await.unwrap_err().downcast_ref::<pressure::MemoryPressure>().is_some());
+
+        bm.memory_guard.observe(900);
+        assert!(bm.set("k", vec![1; 8], Durability::Pinned).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_hot_blocks_ranks_reads_and_resets_on_flush() {
+        let bm = manager();
+        let named = bm.set("user:1", vec![0; 10], Durability::Pinned).unwrap();
+        let (unnamed, _) = bm.put_block(bm.new_block(vec![0; 4], Durability::Pinned)).unwrap();
+        for _ in 0..3 {
+            bm.get_block_async(unnamed).await.unwrap();
+        }
+        bm.get_block_async(named).await.unwrap();
+        bm.record_hit(named, 10, true);
+
+        let hot = bm.hot_blocks(10, None);
+        assert_eq!(hot.iter().map(|b| b.id).collect::<Vec<_>>(), vec![unnamed, named]);
+        assert_eq!(hot[0].key, None);
+        assert_eq!((hot[0].local_hits, hot[0].bytes_served), (3, 12));
+        assert_eq!(hot[1].key.as_deref(), Some("user:1"));
+        assert_eq!((hot[1].local_hits, hot[1].peer_hits, hot[1].bytes_served), (1, 1, 20));
+        assert_eq!(bm.hot_blocks(1, Some(60)).len(), 1);
+
+        bm.flush();
+        assert!(bm.hot_blocks(10, None).is_empty());
+    }
+
+    #[test]
+    fn test_allocate_id_skips_ids_in_use() {
+        let bm = manager();
+        let mut rng = StdRng::seed_from_u64(7);
+        let taken: Vec<BlockId> = (0..3).map(|_| rng.gen()).collect();
+        let free: BlockId = rng.gen();
+
+        // First candidate: a local block
+        bm.put_block(Block { id: taken[0], ..bm.new_block(vec![0; 4], Durability::Pinned) }).unwrap();
+        // Second: a block offloaded to a peer
+        bm.remote_locations.insert(taken[1], (uuid::Uuid::new_v4(), 4));
+        // Third: a key whose cache block was evicted
+        bm.put_named_block("evicted".to_string(), Block { id: taken[2], ..bm.new_block(vec![0; 4], Durability::Cache) }).unwrap();
+        bm.evict_block(taken[2]).unwrap();
+
+        bm.seed_ids(7);
+        assert_eq!(bm.allocate_id(), free);
+    }
+
+    #[test]
+    fn test_ttl_blocks_evict_like_cache_and_expire() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 100);
+        let ttl = bm.set("ttl", vec![0; 60], Durability::Ttl { secs: 3600 }).unwrap();
+        // Memory pressure evicts it long before it expires
+        bm.set("pinned", vec![0; 60], Durability::Pinned).unwrap();
+        assert!(bm.get_block(ttl).unwrap().is_none());
+
+        let cache = bm.set("cache", vec![0; 10], Durability::Cache).unwrap();
+        let ttl = bm.set("ttl", vec![0; 10], Durability::Ttl { secs: 60 }).unwrap();
+        assert_eq!(bm.sweep_expired(unix_now()).blocks_removed, 0);
+        // Expiry removes it without any pressure, leaving the cache block alone
+        assert_eq!(bm.sweep_expired(unix_now() + 60), FlushStats { blocks_removed: 1, bytes_freed: 10 });
+        assert!(bm.get_block(ttl).unwrap().is_none());
+        assert!(bm.get_block(cache).unwrap().is_some());
+        assert_eq!(bm.used_space(), 70);
+    }
+
+    #[test]
+    fn test_eviction_takes_expired_blocks_before_lru() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 100);
+        let lru = bm.set("lru", vec![0; 40], Durability::Cache).unwrap();
+        bm.blocks.get(&lru).unwrap().last_accessed.store(0, Ordering::Relaxed);
+        let expired = bm.set("expired", vec![0; 40], Durability::Ttl { secs: 0 }).unwrap();
+
+        bm.set("new", vec![0; 40], Durability::Pinned).unwrap();
+        assert!(!bm.blocks.contains_key(&expired));
+        assert!(bm.blocks.contains_key(&lru));
+        // Reads never see an expired block, swept or not
+        let expired = bm.set("expired", vec![0; 10], Durability::Ttl { secs: 0 }).unwrap();
+        assert!(bm.get_block(expired).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_eviction_takes_lowest_priority_then_lru() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 100);
+        let costly = bm.set("costly", vec![0; 30], Durability::Cache).unwrap();
+        let cheap_old = bm.set("cheap-old", vec![0; 30], Durability::Cache).unwrap();
+        let cheap_new = bm.set("cheap-new", vec![0; 30], Durability::Cache).unwrap();
+        bm.set_priority(costly, 200).unwrap();
+        bm.set_priority(cheap_old, 10).unwrap();
+        bm.set_priority(cheap_new, 10).unwrap();
+        // The costly block is the least recently used, yet outlasts both cheap ones
+        for (id, at) in [(costly, 0), (cheap_old, 1), (cheap_new, 2)] {
+            bm.blocks.get(&id).unwrap().last_accessed.store(at, Ordering::Relaxed);
+        }
+
+        bm.set("a", vec![0; 20], Durability::Pinned).unwrap();
+        assert!(!bm.blocks.contains_key(&cheap_old));
+        assert!(bm.blocks.contains_key(&cheap_new));
+        bm.set("b", vec![0; 30], Durability::Pinned).unwrap();
+        assert!(!bm.blocks.contains_key(&cheap_new));
+        assert!(bm.blocks.contains_key(&costly));
+        assert_eq!(bm.block_info(&memsdk::BlockOrKey::Key { key: "costly".to_string() }).unwrap().priority, 200);
+        assert!(bm.set_priority(cheap_old, 0).is_err());
+    }
+
+    #[test]
+    fn test_usage_breakdown_tracks_stores_and_evictions() {
+        let bm = manager();
+        bm.set("p1", vec![0; 100], Durability::Pinned).unwrap();
+        bm.set("p2", vec![0; 50], Durability::Pinned).unwrap();
+        bm.set("c", vec![0; 30], Durability::Cache).unwrap();
+        let ttl = bm.set("t", vec![0; 20], Durability::Ttl { secs: 60 }).unwrap();
+        assert_eq!(bm.usage_by_durability(), (150, 50));
+        assert_eq!(bm.used_space(), 200);
+        assert_eq!(bm.free_space(), 1024 * 1024 - 200);
+
+        bm.evict_block(ttl).unwrap();
+        bm.flush_filtered(Some("p1"), None);
+        assert_eq!(bm.usage_by_durability(), (50, 30));
+        assert_eq!(bm.used_space(), 80);
+    }
+
+    #[test]
+    fn test_unpinned_block_becomes_evictable() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 100);
+        let old = bm.set("old", vec![0; 60], Durability::Pinned).unwrap();
+        assert!(bm.set("new", vec![0; 60], Durability::Pinned).is_err());
+        assert!(bm.blocks.contains_key(&old));
+
+        bm.set_durability(old, Durability::Cache).unwrap();
+        assert_eq!(bm.usage_by_durability(), (0, 60));
+        bm.set("new", vec![0; 60], Durability::Pinned).unwrap();
+        assert!(!bm.blocks.contains_key(&old));
+        assert_eq!(bm.usage_by_durability(), (60, 0));
+
+        // And back: a pinned cache block survives the next squeeze
+        let cache = bm.set("cache", vec![0; 30], Durability::Ttl { secs: 60 }).unwrap();
+        bm.set_durability(cache, Durability::Pinned).unwrap();
+        assert!(bm.set("more", vec![0; 20], Durability::Pinned).is_err());
+        assert!(bm.get_block(cache).unwrap().is_some());
+        assert_eq!(bm.sweep_expired(unix_now() + 60).blocks_removed, 0);
+        assert!(bm.set_durability(old, Durability::Pinned).is_err());
+
+        // Blocks held for a peer keep the durability it gave them
+        let hosted = bm.set("hosted", vec![0; 10], Durability::Cache).unwrap();
+        bm.record_hosted_block(hosted, uuid::Uuid::new_v4());
+        let err = bm.set_durability(hosted, Durability::Pinned).unwrap_err();
+        assert!(err.to_string().starts_with("Permission denied"), "{}", err);
+        assert_eq!(bm.get_block(hosted).unwrap().unwrap().durability, Durability::Cache);
+    }
+
+    #[tokio::test]
+    async fn test_peer_stats_counts_blocks_stored_there() {
+        use crate::test_support::TestNode;
+        let a = TestNode::spawn("stat-a", 1024 * 1024).await;
+        let b = TestNode::spawn("stat-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+
+        for size in [100, 300] {
+            let block = a.block_manager.new_block(vec![1; size], Durability::Pinned);
+            a.block_manager.put_block_remote(block, Some("stat-b".to_string())).await.unwrap();
+        }
+        a.block_manager.set("local", vec![3; 50], Durability::Pinned).unwrap();
+        // A key set on the peer is indexed there, so it only adds to the bytes used
+        a.block_manager.set_remote("k", vec![2; 20], "stat-b", Durability::Pinned, None).await.unwrap();
+
+        let stats = a.block_manager.peer_stats("stat-b").unwrap();
+        assert_eq!(stats.blocks_remote, 2);
+        assert_eq!(stats.used, 420);
+        assert_eq!(stats.quota, a.peer_manager.remote_quota(b.id).unwrap());
+        assert!(stats.quota > 0);
+        assert!(a.block_manager.peer_stats("nobody").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_priority_travels_with_offloaded_blocks() {
+        use crate::test_support::{wait_until, TestNode};
+        let a = TestNode::spawn("prio-a", 1024 * 1024).await;
+        let b = TestNode::spawn("prio-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+
+        let block = Block { priority: 7, ..a.block_manager.new_block(vec![1; 100], Durability::Cache) };
+        let (id, _) = a.block_manager.put_block_remote(block, Some("prio-b".to_string())).await.unwrap();
+        wait_until(|| b.block_manager.blocks.contains_key(&id)).await;
+        assert_eq!(b.block_manager.blocks.get(&id).unwrap().priority, 7);
+        let id = a.block_manager.set_remote("k", vec![2; 20], "prio-b", Durability::Cache, Some(250)).await.unwrap();
+        assert_eq!(b.block_manager.blocks.get(&id).unwrap().priority, 250);
+        let id = a.block_manager.set_remote("plain", vec![2; 20], "prio-b", Durability::Cache, None).await.unwrap();
+        assert_eq!(b.block_manager.blocks.get(&id).unwrap().priority, memsdk::DEFAULT_PRIORITY);
+    }
+
+    #[tokio::test]
+    async fn test_store_remote_falls_back_to_local() {
+        use crate::test_support::TestNode;
+        let a = TestNode::spawn("fallback-a", 64 * 1024).await;
+        let bm = &a.block_manager;
+
+        // No peer at all: kept here, under the durability asked for
+        let (id, _, location) = bm.store_remote(bm.new_block(vec![1; 100], Durability::Cache), None, true).await.unwrap();
+        assert_eq!(location, StoredLocation::Local);
+        assert_eq!(bm.get_block(id).unwrap().unwrap().durability, Durability::Cache);
+        assert!(bm.store_remote(bm.new_block(vec![1; 100], Durability::Cache), None, false).await.is_err());
+
+        let b = TestNode::spawn("fallback-b", 1024).await;
+        a.connect_to(&b, 0).await;
+        let target = Some("fallback-b".to_string());
+        let (_, _, location) = bm.store_remote(bm.new_block(vec![2; 100], Durability::Pinned), target.clone(), true).await.unwrap();
+        assert_eq!(location, StoredLocation::Remote("fallback-b".to_string()));
+
+        // Over the peer's quota: rejected there, kept here
+        let (id, _, location) = bm.store_remote(bm.new_block(vec![3; 4096], Durability::Pinned), target.clone(), true).await.unwrap();
+        assert_eq!(location, StoredLocation::Local);
+        assert!(!bm.remote_locations.contains_key(&id));
+
+        // Too big for either node
+        let err = bm.store_remote(bm.new_block(vec![4; 128 * 1024], Durability::Pinned), target, true).await.unwrap_err().to_string();
+        assert!(err.contains("Peer rejected") && err.contains("local fallback failed: Out of Memory"), "{}", err);
+    }
+}
@@ -0,0 +1,93 @@
+//! Freeing cache blocks nobody can find any more (`--orphan-gc-idle`).
+//!
+//! A block from a plain `Store` is only reachable through its ID; if the
+//! client loses that, the block sits in memory until a flush. With the GC on,
+//! such a block is freed once it has gone unread for the idle period, if it
+//! is a cache block that no key points at. Pinned and TTL blocks, and blocks
+//! held here for a peer, are never collected.
+
+use log::info;
+use memsdk::{Durability, FlushStats};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{unix_now, BlockId, BlockManager, InMemoryBlockManager};
+
+/// How often the GC looks for idle orphans.
+pub const ORPHAN_GC_INTERVAL: Duration = Duration::from_secs(30);
+
+impl InMemoryBlockManager {
+    /// Free unnamed cache blocks unread for `idle_secs`; 0 turns the GC off.
+    pub fn set_orphan_gc_idle(&self, idle_secs: u64) {
+        self.orphan_gc_idle.store(idle_secs, Ordering::Relaxed);
+    }
+
+    /// Free every unnamed cache block last read `idle_secs` or more before
+    /// `now` (unix seconds).
+    pub fn sweep_orphans(&self, now: u64, idle_secs: u64) -> FlushStats {
+        let cutoff = now.saturating_sub(idle_secs);
+        let is_orphan = |id: &BlockId, durability: Durability, last_accessed: u64| {
+            durability == Durability::Cache
+                && last_accessed <= cutoff
+                && !self.indexed_ids.contains_key(id)
+                && !self.hosted_for.contains_key(id)
+        };
+        let candidates: Vec<(BlockId, Durability, u64)> = self.blocks.iter()
+            .map(|b| (*b.key(), b.durability, b.last_accessed.load(Ordering::Relaxed)))
+            .collect();
+
+        let mut stats = FlushStats::default();
+        for (id, durability, last_accessed) in candidates {
+            if !is_orphan(&id, durability, last_accessed) {
+                continue;
+            }
+            if let Ok(Some(block)) = self.evict_block(id) {
+                stats.blocks_removed += 1;
+                stats.bytes_freed += block.data.len() as u64;
+            }
+        }
+        if stats.blocks_removed > 0 {
+            info!("Reclaimed {} orphaned cache blocks ({} bytes) unread for {}s", stats.blocks_removed, stats.bytes_freed, idle_secs);
+        }
+        stats
+    }
+
+    /// Collect idle orphans every `ORPHAN_GC_INTERVAL` while the GC is on, for the life of the node.
+    pub async fn run_orphan_gc(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(ORPHAN_GC_INTERVAL);
+        loop {
+            ticker.tick().await;
+            // Turned on and off by config reloads
+            let idle = self.orphan_gc_idle.load(Ordering::Relaxed);
+            if idle > 0 {
+                self.sweep_orphans(unix_now(), idle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerManager;
+
+    #[test]
+    fn test_orphan_gc_frees_idle_unnamed_cache_blocks_only() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 1024 * 1024);
+        let orphan = bm.put_block(bm.new_block(vec![1; 100], Durability::Cache)).unwrap().0;
+        let pinned = bm.put_block(bm.new_block(vec![2; 100], Durability::Pinned)).unwrap().0;
+        let named = bm.set("kept", vec![3; 100], Durability::Cache).unwrap();
+
+        // Not idle long enough yet
+        assert_eq!(bm.sweep_orphans(unix_now(), 600).blocks_removed, 0);
+
+        let stats = bm.sweep_orphans(unix_now() + 601, 600);
+        assert_eq!(stats, FlushStats { blocks_removed: 1, bytes_freed: 100 });
+        assert!(!bm.blocks.contains_key(&orphan));
+        assert!(bm.blocks.contains_key(&pinned));
+        assert!(bm.blocks.contains_key(&named));
+        assert_eq!(bm.used_space(), 200);
+    }
+}
@@ -0,0 +1,137 @@
+//! Watchdog on the node's own resident memory.
+//!
+//! Block bytes are only part of what the process holds: connection buffers,
+//! streamed uploads and allocator slack come on top, and none of it counts
+//! against `--memory`. The guard samples RSS on a timer and, past the soft
+//! limit, sheds cache blocks and refuses new data; past the hard limit it also
+//! refuses new streams, so the node degrades before the OS OOM killer steps in.
+
+use memsdk::PressureLevel;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+
+/// How often the watchdog samples RSS.
+pub const PRESSURE_SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Returned when a store or stream is refused under memory pressure. The RPC
+/// layer reports it as `SdkResponse::Overloaded` rather than a plain error.
+#[derive(Debug)]
+pub struct MemoryPressure {
+    pub rss: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for MemoryPressure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Node under memory pressure (rss {} bytes, limit {} bytes)", self.rss, self.limit)
+    }
+}
+
+impl std::error::Error for MemoryPressure {}
+
+/// RSS limits and the last sample. A limit of 0 disables that level.
+#[derive(Default)]
+pub struct MemoryGuard {
+    soft_limit: AtomicU64,
+    hard_limit: AtomicU64,
+    rss: AtomicU64,
+    level: AtomicU8,
+}
+
+impl MemoryGuard {
+    pub fn set_limits(&self, soft: u64, hard: u64) {
+        self.soft_limit.store(soft, Ordering::Relaxed);
+        self.hard_limit.store(hard, Ordering::Relaxed);
+    }
+
+    pub fn limits(&self) -> (u64, u64) {
+        (self.soft_limit.load(Ordering::Relaxed), self.hard_limit.load(Ordering::Relaxed))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.limits() != (0, 0)
+    }
+
+    /// Last sampled RSS, 0 before the first sample or where it cannot be measured.
+    pub fn rss(&self) -> u64 {
+        self.rss.load(Ordering::Relaxed)
+    }
+
+    pub fn level(&self) -> PressureLevel {
+        match self.level.load(Ordering::Relaxed) {
+            0 => PressureLevel::Normal,
+            1 => PressureLevel::Soft,
+            _ => PressureLevel::Hard,
+        }
+    }
+
+    /// Record a sample, logging when the level changes, and return the new level.
+    pub fn observe(&self, rss: u64) -> PressureLevel {
+        let (soft, hard) = self.limits();
+        let level = if hard > 0 && rss >= hard {
+            PressureLevel::Hard
+        } else if soft > 0 && rss >= soft {
+            PressureLevel::Soft
+        } else {
+            PressureLevel::Normal
+        };
+        self.rss.store(rss, Ordering::Relaxed);
+        let previous = self.level();
+        self.level.store(level as u8, Ordering::Relaxed);
+        if level != previous {
+            match level {
+                PressureLevel::Hard => log::error!(
+                    "RSS {} bytes is over the hard limit ({} bytes): refusing stores and new streams, shedding cache",
+                    rss, hard
+                ),
+                PressureLevel::Soft => log::warn!(
+                    "RSS {} bytes is over the soft limit ({} bytes): refusing stores, shedding cache",
+                    rss, soft
+                ),
+                PressureLevel::Normal => log::info!("RSS {} bytes is back under the limits, accepting stores", rss),
+            }
+        }
+        level
+    }
+
+    /// Bytes over the lowest enabled limit at the last sample.
+    pub fn excess(&self) -> u64 {
+        let (soft, hard) = self.limits();
+        let limit = if soft > 0 { soft } else { hard };
+        if limit == 0 { 0 } else { self.rss().saturating_sub(limit) }
+    }
+
+    /// Fails with `MemoryPressure` once over the soft limit.
+    pub fn check_store(&self) -> anyhow::Result<()> {
+        self.refuse_at(PressureLevel::Soft)
+    }
+
+    /// Fails with `MemoryPressure` once over the hard limit.
+    pub fn check_stream(&self) -> anyhow::Result<()> {
+        self.refuse_at(PressureLevel::Hard)
+    }
+
+    fn refuse_at(&self, at: PressureLevel) -> anyhow::Result<()> {
+        let level = self.level();
+        if level < at {
+            return Ok(());
+        }
+        let (soft, hard) = self.limits();
+        let limit = if level == PressureLevel::Hard { hard } else { soft };
+        Err(MemoryPressure { rss: self.rss(), limit }.into())
+    }
+}
+
+/// Resident set size of this process, where the platform exposes it.
+#[cfg(target_os = "linux")]
+pub fn process_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_rss() -> Option<u64> {
+    None
+}
@@ -0,0 +1,108 @@
+//! `ReconnectTrusted`: dialing the trusted devices that are not connected.
+//!
+//! After a restart mDNS finds the peers on the local network again, but not
+//! those connected by address, e.g. on another subnet. Each trusted device
+//! keeps the address this node last dialed it on, and this dials them all
+//! there at once. A device only ever seen connecting in has no such address.
+
+use anyhow::{bail, Result};
+use futures::future::join_all;
+use memsdk::ReconnectOutcome;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::InMemoryBlockManager;
+use crate::peers::DialPolicy;
+
+/// How long one device may take to connect, consent on its side included.
+pub const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl InMemoryBlockManager {
+    /// Dial every trusted device that is not connected, offering each
+    /// `quota`. Devices that are connected already or have no address are
+    /// reported without being dialed. Sorted by device name.
+    ///
+    /// Fails without dialing when the offers together would over-commit
+    /// this node's memory, since every device dialed may accept.
+    pub async fn reconnect_trusted(self: &Arc<Self>, quota: u64) -> Result<Vec<ReconnectOutcome>> {
+        let connected: HashSet<String> = self.peer_manager.get_peer_metadata_list().into_iter().map(|p| p.public_key).collect();
+        let mut devices = self.peer_manager.trusted_store.list_trusted();
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        let dialed = devices.iter().filter(|d| d.last_addr.is_some() && !connected.contains(&d.public_key)).count() as u64;
+        if let Err(e) = self.peer_manager.check_offer(quota.saturating_mul(dialed), None) {
+            bail!("{} ({} trusted devices to dial at {} bytes each)", e, dialed, quota);
+        }
+        let attempts = devices.into_iter().map(|device| {
+            let already = connected.contains(&device.public_key);
+            async move {
+                let mut outcome = ReconnectOutcome {
+                    name: device.name,
+                    public_key: device.public_key,
+                    addr: device.last_addr,
+                    state: "failed".to_string(),
+                    msg: None,
+                };
+                let Some(addr) = outcome.addr.clone().filter(|_| !already) else {
+                    outcome.state = if already { "already_connected" } else { "no_address" }.to_string();
+                    return outcome;
+                };
+                // Only the device it was: a different key at the address is refused
+                let policy = DialPolicy { accept_new_key: false, require_known: true };
+                match tokio::time::timeout(RECONNECT_TIMEOUT, self.connect_peer(&addr, self.clone(), quota, policy)).await {
+                    Ok(Ok(_)) => outcome.state = "connected".to_string(),
+                    Ok(Err(e)) => outcome.msg = Some(e.to_string()),
+                    Err(_) => outcome.msg = Some(format!("Not connected within {}s", RECONNECT_TIMEOUT.as_secs())),
+                }
+                outcome
+            }
+        });
+        Ok(join_all(attempts).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{wait_until, TestNode};
+
+    #[tokio::test]
+    async fn test_dials_trusted_devices_at_their_last_address() {
+        let a = TestNode::spawn("home-a", 1024 * 1024).await;
+        let b = TestNode::spawn("home-b", 1024 * 1024).await;
+        let key = |node: &TestNode| hex::encode(node.peer_manager.get_identity().public_key().to_bytes());
+        a.peer_manager.trusted_store.add_trusted(key(&b), "desk".to_string(), None).unwrap();
+        b.peer_manager.trusted_store.add_trusted(key(&a), "laptop".to_string(), None).unwrap();
+        a.connect_to(&b, 0).await;
+        let desk = a.peer_manager.trusted_store.find_trusted("desk").unwrap().unwrap();
+        assert_eq!(desk.last_addr, Some(b.addr()));
+        // b only ever saw a connecting in, from a port nobody listens on
+        assert_eq!(b.peer_manager.trusted_store.find_trusted("laptop").unwrap().unwrap().last_addr, None);
+
+        // A device whose last address no longer answers, and one never dialed
+        let gone = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        let store = &a.peer_manager.trusted_store;
+        store.add_trusted("ab".repeat(32), "gone".to_string(), None).unwrap();
+        store.record_connection(&"ab".repeat(32), &gone, true).unwrap();
+        store.add_trusted("cd".repeat(32), "phone".to_string(), None).unwrap();
+
+        a.block_manager.disconnect_peer(&b.id.to_string(), false).await.unwrap();
+        wait_until(|| a.peer_manager.peer_count() == 0 && b.peer_manager.peer_count() == 0).await;
+
+        // Both devices with an address would be offered the quota; 1MB holds only one
+        let err = a.block_manager.reconnect_trusted(600 * 1024).await.unwrap_err();
+        assert!(err.to_string().contains("2 trusted devices to dial"), "{}", err);
+        assert_eq!(a.peer_manager.peer_count(), 0);
+
+        let results = a.block_manager.reconnect_trusted(0).await.unwrap();
+        let states: Vec<_> = results.iter().map(|o| (o.name.as_str(), o.state.as_str())).collect();
+        assert_eq!(states, [("desk", "connected"), ("gone", "failed"), ("phone", "no_address")]);
+        assert_eq!(results[1].addr.as_deref(), Some(gone.as_str()));
+        assert!(results[1].msg.is_some());
+        assert_eq!(a.peer_manager.peer_count(), 1);
+
+        // Connected devices are left alone
+        let results = a.block_manager.reconnect_trusted(0).await.unwrap();
+        assert_eq!(results[0].state, "already_connected");
+        assert_eq!(a.peer_manager.peer_count(), 1);
+    }
+}
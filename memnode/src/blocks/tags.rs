@@ -0,0 +1,222 @@
+//! Small string tags stored alongside local blocks (`tags` on `SdkCommand::Set`
+//! and `Store`), and the `tag -> keys` index behind `SdkCommand::FindByTag`.
+//!
+//! Tags belong to the block, so they go when it is evicted, expires or is
+//! flushed, and setting a key again replaces them with the new value's. A
+//! content-addressed store that finds its block already there adds its tags
+//! to the shared block's instead. Only named blocks are indexed; unnamed ones
+//! show their tags in `BlockInfo`.
+
+use anyhow::{bail, Result};
+use memsdk::{BlockOrKey, Tags};
+
+use super::{BlockId, BlockManager, InMemoryBlockManager};
+
+/// Most bytes of names and values one block's tags may take.
+pub const MAX_TAG_BYTES: usize = 4096;
+
+pub(crate) struct BlockTags {
+    // The key the tags are indexed under, until it is bound to another block;
+    // aliases of the block are not indexed
+    key: Option<String>,
+    tags: Tags,
+}
+
+pub fn check_tags(tags: &Tags) -> Result<()> {
+    let size: usize = tags.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if size > MAX_TAG_BYTES {
+        bail!("Tags must be at most {} bytes in total, got {}", MAX_TAG_BYTES, size);
+    }
+    if tags.keys().any(|k| k.is_empty()) {
+        bail!("Tag names must be non-empty");
+    }
+    Ok(())
+}
+
+impl InMemoryBlockManager {
+    /// Attach `tags` to block `id`, replacing any it had, and index them under
+    /// `key` for a named block.
+    pub(crate) fn tag_block(&self, id: BlockId, key: Option<&str>, tags: Tags) {
+        self.untag_block(id);
+        if tags.is_empty() {
+            return;
+        }
+        if let Some(key) = key {
+            for tag in tags.iter() {
+                self.tag_index.entry((tag.0.clone(), tag.1.clone())).or_default().insert(key.to_string());
+            }
+        }
+        self.tags.insert(id, BlockTags { key: key.map(str::to_string), tags });
+    }
+
+    /// Add `tags` to the unnamed block `id` without replacing any it has, for
+    /// a block other stores share. Names it already has keep their values,
+    /// and tags that would take it past `MAX_TAG_BYTES` are left off.
+    pub(crate) fn merge_tags(&self, id: BlockId, tags: Tags) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut entry = self.tags.entry(id).or_insert_with(|| BlockTags { key: None, tags: Tags::new() });
+        let mut size: usize = entry.tags.iter().map(|(k, v)| k.len() + v.len()).sum();
+        for (name, value) in tags {
+            if entry.tags.contains_key(&name) || size + name.len() + value.len() > MAX_TAG_BYTES {
+                continue;
+            }
+            size += name.len() + value.len();
+            entry.tags.insert(name, value);
+        }
+    }
+
+    /// Drop the tags of block `id` and their index entries.
+    pub(crate) fn untag_block(&self, id: BlockId) {
+        if let Some((_, old)) = self.tags.remove(&id) {
+            if let Some(key) = &old.key {
+                self.unindex_tags(key, &old.tags);
+            }
+        }
+    }
+
+    /// `key` no longer names `old`; if its tags were indexed under it they
+    /// stay with the block but no longer turn up in searches.
+    pub(crate) fn detach_tags(&self, old: BlockId, key: &str) {
+        if let Some(mut entry) = self.tags.get_mut(&old) {
+            if entry.key.as_deref() == Some(key) {
+                entry.key = None;
+                self.unindex_tags(key, &entry.tags);
+            }
+        }
+    }
+
+    /// Index the tags of `id` under `to` instead of `from`, after a rename.
+    pub(crate) fn retag_key(&self, id: BlockId, from: &str, to: &str) {
+        if let Some(mut entry) = self.tags.get_mut(&id) {
+            if entry.key.as_deref() == Some(from) {
+                self.unindex_tags(from, &entry.tags);
+                for tag in entry.tags.iter() {
+                    self.tag_index.entry((tag.0.clone(), tag.1.clone())).or_default().insert(to.to_string());
+                }
+                entry.key = Some(to.to_string());
+            }
+        }
+    }
+
+    fn unindex_tags(&self, key: &str, tags: &Tags) {
+        for (name, value) in tags {
+            let tag = (name.clone(), value.clone());
+            if let Some(mut keys) = self.tag_index.get_mut(&tag) {
+                keys.remove(key);
+            }
+            self.tag_index.remove_if(&tag, |_, keys| keys.is_empty());
+        }
+    }
+
+    /// Keys whose value carries `name = value`, sorted.
+    pub fn find_by_tag(&self, name: &str, value: &str) -> Vec<String> {
+        let Some(keys) = self.tag_index.get(&(name.to_string(), value.to_string())).map(|k| k.clone()) else {
+            return Vec::new();
+        };
+        // A key being set concurrently may briefly be indexed for its old tags
+        let mut keys: Vec<String> = keys.into_iter()
+            .filter(|key| self.get_named_block_id(key)
+                .and_then(|id| self.tags.get(&id))
+                .is_some_and(|t| t.tags.get(name).map(String::as_str) == Some(value)))
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// The block `selector` names: the id itself, or the block of a local key.
+    pub fn selected_id(&self, selector: &BlockOrKey) -> Result<BlockId> {
+        match selector {
+            BlockOrKey::Id { id } => Ok(*id),
+            BlockOrKey::Key { key } => match self.get_named_block_id(key) {
+                Some(id) => Ok(id),
+                None => bail!("Key '{}' not found", key),
+            },
+        }
+    }
+
+    /// Size, mode, priority, key, tags, checksum and whether it is encrypted,
+    /// of a block stored on this node.
+    pub fn block_info(&self, selector: &BlockOrKey) -> Result<memsdk::BlockInfo> {
+        let id = self.selected_id(selector)?;
+        let Some(block) = self.get_block(id)? else {
+            bail!("Block {} is not stored on this node", id);
+        };
+        let names = self.names_of(id);
+        Ok(memsdk::BlockInfo {
+            id,
+            key: names.first().cloned(),
+            aliases: names.iter().skip(1).cloned().collect(),
+            size: block.data.len() as u64,
+            durability: block.durability,
+            tags: self.tags.get(&id).map(|t| t.tags.clone()).unwrap_or_default(),
+            checksum: self.checksum(id),
+            priority: block.priority,
+            encrypted: memsdk::crypto::is_encrypted(&block.data),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerManager;
+    use memsdk::Durability;
+    use std::sync::Arc;
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_find_by_tag_follows_overwrites_and_evictions() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 1024 * 1024);
+        bm.set_tagged("a.json", vec![1], Durability::Pinned, memsdk::DEFAULT_PRIORITY, tags(&[("type", "json"), ("src", "api")])).unwrap();
+        bm.set_tagged("b.json", vec![2], Durability::Pinned, memsdk::DEFAULT_PRIORITY, tags(&[("type", "json")])).unwrap();
+        bm.set_tagged("c.txt", vec![3], Durability::Pinned, memsdk::DEFAULT_PRIORITY, tags(&[("type", "text")])).unwrap();
+        assert_eq!(bm.find_by_tag("type", "json"), ["a.json", "b.json"]);
+        assert_eq!(bm.find_by_tag("src", "api"), ["a.json"]);
+        assert!(bm.find_by_tag("type", "xml").is_empty());
+
+        // Overwriting replaces the tags, and a plain set clears them
+        bm.set_tagged("a.json", vec![4], Durability::Pinned, memsdk::DEFAULT_PRIORITY, tags(&[("type", "text")])).unwrap();
+        bm.set("b.json", vec![5], Durability::Pinned).unwrap();
+        assert!(bm.find_by_tag("type", "json").is_empty());
+        assert!(bm.find_by_tag("src", "api").is_empty());
+        assert_eq!(bm.find_by_tag("type", "text"), ["a.json", "c.txt"]);
+        let info = bm.block_info(&BlockOrKey::Key { key: "a.json".to_string() }).unwrap();
+        assert_eq!((info.key.as_deref(), info.size, info.tags), (Some("a.json"), 1, tags(&[("type", "text")])));
+        assert!(bm.block_info(&BlockOrKey::Key { key: "b.json".to_string() }).unwrap().tags.is_empty());
+
+        // Tags go with the block
+        let id = bm.get_named_block_id("c.txt").unwrap();
+        bm.evict_block(id).unwrap();
+        assert_eq!(bm.find_by_tag("type", "text"), ["a.json"]);
+        bm.flush_filtered(Some("a.*"), None);
+        assert!(bm.find_by_tag("type", "text").is_empty());
+        assert!(bm.tag_index.is_empty());
+
+        let big = tags(&[("blob", &"x".repeat(MAX_TAG_BYTES))]);
+        assert!(bm.set_tagged("big", vec![6], Durability::Pinned, memsdk::DEFAULT_PRIORITY, big).is_err());
+        assert!(bm.get_named_block_id("big").is_none());
+    }
+
+    #[test]
+    fn test_shared_content_block_gathers_tags() {
+        let pm = Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "test".to_string()));
+        let bm = InMemoryBlockManager::new(pm, 1024 * 1024);
+        let (id, _) = bm.put_content_addressed(vec![7; 16], Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap();
+        bm.merge_tags(id, tags(&[("owner", "alice"), ("kind", "thumb")]));
+
+        // A second store of the same bytes adds to the tags, and cannot change them
+        bm.put_content_addressed(vec![7; 16], Durability::Pinned, memsdk::DEFAULT_PRIORITY).unwrap();
+        bm.merge_tags(id, tags(&[("owner", "bob"), ("size", "small")]));
+        let info = bm.block_info(&BlockOrKey::Id { id }).unwrap();
+        assert_eq!(info.tags, tags(&[("owner", "alice"), ("kind", "thumb"), ("size", "small")]));
+
+        bm.merge_tags(id, tags(&[("blob", &"x".repeat(MAX_TAG_BYTES))]));
+        assert_eq!(bm.block_info(&BlockOrKey::Id { id }).unwrap().tags.len(), 3);
+    }
+}
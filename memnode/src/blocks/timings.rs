@@ -0,0 +1,90 @@
+//! How long the node spends running each kind of RPC command, for `Metrics`.
+//! Clients measure round trips; these times start when a command is
+//! dispatched and stop when its response is ready, so together they tell
+//! the network apart from the node. Each command name gets a histogram over
+//! `LATENCY_BUCKETS_MICROS`, kept for the life of the node.
+
+use dashmap::DashMap;
+use memsdk::{CommandMetrics, LATENCY_BUCKETS_MICROS};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::InMemoryBlockManager;
+
+#[derive(Default)]
+pub(crate) struct CommandTimings {
+    by_command: DashMap<String, Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+    buckets: [AtomicU64; LATENCY_BUCKETS_MICROS.len() + 1],
+}
+
+impl Histogram {
+    fn record(&self, micros: u64) {
+        let bucket = LATENCY_BUCKETS_MICROS.partition_point(|bound| *bound < micros);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, command: &str) -> CommandMetrics {
+        let buckets: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        CommandMetrics {
+            command: command.to_string(),
+            count: buckets.iter().sum(),
+            total_micros: self.total_micros.load(Ordering::Relaxed),
+            max_micros: self.max_micros.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+}
+
+impl InMemoryBlockManager {
+    /// Count one run of the command `name` that took `elapsed`.
+    pub fn record_command_time(&self, name: &str, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        if let Some(histogram) = self.timings.by_command.get(name) {
+            histogram.record(micros);
+            return;
+        }
+        self.timings.by_command.entry(name.to_string()).or_default().record(micros);
+    }
+
+    /// The histogram of every command run so far, by name.
+    pub fn command_metrics(&self) -> Vec<CommandMetrics> {
+        let mut all: Vec<CommandMetrics> = self.timings.by_command.iter().map(|e| e.value().snapshot(e.key())).collect();
+        all.sort_by(|a, b| a.command.cmp(&b.command));
+        all
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerManager;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_times_land_in_their_buckets() {
+        let bm = InMemoryBlockManager::new(Arc::new(PeerManager::new(uuid::Uuid::new_v4(), "timings".to_string())), 1000);
+        for micros in [10, 50, 51, 900, 2_000_000] {
+            bm.record_command_time("Load", Duration::from_micros(micros));
+        }
+        bm.record_command_time("Store", Duration::from_micros(300));
+
+        let metrics = bm.command_metrics();
+        assert_eq!(metrics.iter().map(|m| m.command.as_str()).collect::<Vec<_>>(), ["Load", "Store"]);
+        let load = &metrics[0];
+        assert_eq!((load.count, load.max_micros, load.total_micros), (5, 2_000_000, 2_001_011));
+        // 10 and 50 go up to 50us, 51 up to 100us, 900 up to 1ms, 2s past the last bound
+        assert_eq!(&load.buckets[..5], [2, 1, 0, 0, 1]);
+        assert_eq!(load.buckets[LATENCY_BUCKETS_MICROS.len()], 1);
+        assert_eq!(load.quantile_micros(0.5), 100);
+        assert_eq!(load.quantile_micros(1.0), 2_000_000);
+        assert_eq!(metrics[1].quantile_micros(0.99), 300);
+    }
+}
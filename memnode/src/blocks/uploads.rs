@@ -0,0 +1,278 @@
+//! Limits on streamed uploads (`StreamStart` .. `StreamFinish`).
+//!
+//! Buffered uploads hold their bytes until they finish, so the node caps how
+//! many may be open (`MAX_STREAMS`, and per RPC connection `--rpc-max-streams`)
+//! and how much they may buffer together (`--max-stream-memory`, by default
+//! the node's `--memory`). When a chunk would take them past that, the
+//! least-progressed upload (the newest among equals) is dropped to make room,
+//! so uploads that are furthest along finish first. An upload
+//! that sends nothing for `--stream-idle-timeout` is dropped; its next chunk
+//! fails with "expired". Uploads left open by a client that disconnects are
+//! dropped when its connection closes.
+
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use log::{info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::InMemoryBlockManager;
+
+/// Uploads open at once across all connections.
+pub const MAX_STREAMS: usize = 256;
+/// How often uploads are checked for idleness.
+pub const STREAM_REAP_INTERVAL: Duration = Duration::from_secs(5);
+// How long the ids of reaped uploads are remembered to explain later chunks
+const EXPIRED_REMEMBERED: Duration = Duration::from_secs(3600);
+
+// Why an upload was dropped before it finished, to explain its later chunks
+#[derive(Clone, Copy)]
+enum Dropped {
+    Idle,
+    ForRoom,
+}
+
+#[derive(Default)]
+pub(crate) struct UploadLimits {
+    // Seconds without a chunk after which an upload is dropped; 0 never drops it
+    idle_secs: AtomicU64,
+    // Bytes held by all buffered uploads, and how many they may hold; 0 is the node's memory
+    buffered: AtomicU64,
+    max_buffered: AtomicU64,
+    rejected: AtomicU64,
+    reaped: AtomicU64,
+    // Reaped and displaced upload ids, with when and why they were dropped
+    expired: DashMap<u64, (Instant, Dropped)>,
+}
+
+impl InMemoryBlockManager {
+    /// Drop uploads idle for `idle_secs`; 0 keeps them open indefinitely.
+    pub fn set_stream_idle_timeout(&self, idle_secs: u64) {
+        self.upload_limits.idle_secs.store(idle_secs, Ordering::Relaxed);
+    }
+
+    /// Cap the bytes all buffered uploads hold together; 0 uses the node's memory.
+    /// Uploads already over it keep their bytes, but fail their next chunk.
+    pub fn set_max_stream_memory(&self, bytes: u64) {
+        self.upload_limits.max_buffered.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn max_stream_memory(&self) -> u64 {
+        match self.upload_limits.max_buffered.load(Ordering::Relaxed) {
+            0 => self.max_memory,
+            bytes => bytes,
+        }
+    }
+
+    pub fn has_stream(&self, stream_id: u64) -> bool {
+        self.active_uploads.contains_key(&stream_id)
+    }
+
+    /// Uploads refused or cut off by a limit, and uploads dropped for idleness.
+    pub fn stream_counters(&self) -> (u64, u64) {
+        (self.upload_limits.rejected.load(Ordering::Relaxed), self.upload_limits.reaped.load(Ordering::Relaxed))
+    }
+
+    /// Count an upload refused by a limit checked outside this module.
+    pub fn record_stream_rejected(&self) {
+        self.upload_limits.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fails once `MAX_STREAMS` uploads are open.
+    pub(super) fn check_stream_slot(&self) -> Result<()> {
+        if self.active_uploads.len() >= MAX_STREAMS {
+            self.record_stream_rejected();
+            bail!("Too many open streams on this node (limit {}); finish or abort some first", MAX_STREAMS);
+        }
+        Ok(())
+    }
+
+    /// Buffer a chunk of `stream_id`. While all uploads together would hold
+    /// more than `max_stream_memory`, the buffered upload with the fewest bytes
+    /// (the newest among equals) is dropped, until the chunk fits or that
+    /// upload is `stream_id` itself.
+    pub(super) fn buffer_chunk(&self, stream_id: u64, data: &[u8]) -> Result<()> {
+        let len = data.len() as u64;
+        loop {
+            let cap = self.max_stream_memory();
+            let reserved = self.upload_limits.buffered.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |held| {
+                held.checked_add(len).filter(|total| *total <= cap)
+            });
+            let Err(held) = reserved else { break };
+            // Uploads holding nothing free nothing, so only this one may be picked empty
+            let victim = self.active_uploads.iter()
+                .filter(|u| u.value().forward.is_none() && (*u.key() == stream_id || !u.value().data.is_empty()))
+                .min_by_key(|u| (u.value().data.len(), std::cmp::Reverse(u.value().started)))
+                .map(|u| *u.key());
+            match victim {
+                Some(victim) if victim != stream_id => self.drop_for_room(victim),
+                _ => {
+                    self.drop_for_room(stream_id);
+                    bail!("Stream chunk cannot fit: open streams would buffer more than this node's {} byte stream memory ({} bytes held)", cap, held);
+                }
+            }
+        }
+        match self.active_uploads.get_mut(&stream_id) {
+            Some(mut upload) => {
+                upload.data.extend_from_slice(data);
+                Ok(())
+            }
+            None => {
+                self.release_upload_bytes(len);
+                Err(self.unknown_stream(stream_id))
+            }
+        }
+    }
+
+    fn drop_for_room(&self, stream_id: u64) {
+        if let Some((_, upload)) = self.active_uploads.remove(&stream_id) {
+            self.release_upload_bytes(upload.data.len() as u64);
+            self.record_stream_rejected();
+            self.upload_limits.expired.insert(stream_id, (Instant::now(), Dropped::ForRoom));
+            warn!("Dropped stream upload ID: {} to make room in the stream memory ({} bytes buffered)", stream_id, upload.data.len());
+        }
+    }
+
+    pub(super) fn release_upload_bytes(&self, len: u64) {
+        let _ = self.upload_limits.buffered.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |held| Some(held.saturating_sub(len)));
+    }
+
+    pub(super) fn reset_upload_bytes(&self) {
+        self.upload_limits.buffered.store(0, Ordering::Relaxed);
+    }
+
+    /// The error for a chunk or finish naming an upload that is not open.
+    pub(super) fn unknown_stream(&self, stream_id: u64) -> anyhow::Error {
+        match self.upload_limits.expired.get(&stream_id).map(|e| e.1) {
+            Some(Dropped::Idle) => anyhow::anyhow!("Stream ID {} expired after {}s without data", stream_id, self.upload_limits.idle_secs.load(Ordering::Relaxed)),
+            Some(Dropped::ForRoom) => anyhow::anyhow!("Stream ID {} was dropped to make room for further-along streams in this node's {} byte stream memory", stream_id, self.max_stream_memory()),
+            None => anyhow::anyhow!("Stream ID {} not found or already closed", stream_id),
+        }
+    }
+
+    /// Drop uploads that have not received a chunk for the idle timeout.
+    /// Returns how many were dropped.
+    pub async fn reap_idle_streams(&self, now: Instant) -> usize {
+        self.upload_limits.expired.retain(|_, (at, _)| now.saturating_duration_since(*at) < EXPIRED_REMEMBERED);
+        let idle_secs = self.upload_limits.idle_secs.load(Ordering::Relaxed);
+        if idle_secs == 0 {
+            return 0;
+        }
+        let idle = Duration::from_secs(idle_secs);
+        let stale: Vec<u64> = self.active_uploads.iter()
+            .filter(|u| now.saturating_duration_since(u.value().last_activity) >= idle)
+            .map(|u| *u.key())
+            .collect();
+        let mut reaped = 0;
+        for stream_id in stale {
+            let Some((_, upload)) = self.active_uploads.remove_if(&stream_id, |_, u| now.saturating_duration_since(u.last_activity) >= idle) else {
+                continue;
+            };
+            self.release_upload_bytes(upload.data.len() as u64);
+            if let Some(forward) = &upload.forward {
+                self.abort_forward(forward).await;
+            }
+            self.upload_limits.expired.insert(stream_id, (now, Dropped::Idle));
+            self.upload_limits.reaped.fetch_add(1, Ordering::Relaxed);
+            warn!("Dropped stream upload ID: {} after {}s without data ({} bytes buffered)", stream_id, idle_secs, upload.data.len());
+            reaped += 1;
+        }
+        reaped
+    }
+
+    /// Drop the uploads a closed client connection left open.
+    pub async fn abort_streams(&self, stream_ids: impl IntoIterator<Item = u64>) {
+        for stream_id in stream_ids {
+            if self.has_stream(stream_id) && self.abort_stream(stream_id).await.is_ok() {
+                info!("Dropped stream upload ID: {} of a closed connection", stream_id);
+            }
+        }
+    }
+
+    /// Reap idle uploads every `STREAM_REAP_INTERVAL`, for the life of the node.
+    pub async fn run_stream_reaper(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(STREAM_REAP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            self.reap_idle_streams(Instant::now()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TestNode;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_uploads_are_bounded_and_reaped() {
+        let node = TestNode::spawn("uploads", 1000).await;
+        let bm = &node.block_manager;
+
+        // Together the uploads may not buffer more than the node's memory
+        let a = bm.start_stream(Some(u64::MAX / 2), None).await.unwrap_err();
+        assert!(a.to_string().contains("cannot fit"), "{}", a);
+        let a = bm.start_stream(Some(600), None).await.unwrap();
+        let b = bm.start_stream(None, None).await.unwrap();
+        bm.append_stream(a, vec![1; 600]).await.unwrap();
+        let err = bm.append_stream(b, vec![2; 500]).await.unwrap_err();
+        assert!(err.to_string().contains("cannot fit"), "{}", err);
+        assert!(!bm.has_stream(b));
+        assert_eq!(bm.stream_counters(), (2, 0));
+
+        // An idle upload is dropped and its next chunk says why
+        bm.set_stream_idle_timeout(30);
+        assert_eq!(bm.reap_idle_streams(Instant::now()).await, 0);
+        assert_eq!(bm.reap_idle_streams(Instant::now() + Duration::from_secs(31)).await, 1);
+        let err = bm.append_stream(a, vec![3]).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("Stream ID {} expired after 30s without data", a));
+        assert_eq!(bm.stream_counters(), (2, 1));
+
+        // Its bytes no longer count against the others
+        let c = bm.start_stream(None, None).await.unwrap();
+        bm.append_stream(c, vec![4; 900]).await.unwrap();
+        assert_eq!(bm.finish_stream(c, None, memsdk::Durability::Pinned, memsdk::DEFAULT_PRIORITY).await.unwrap().1, 900);
+    }
+
+    #[tokio::test]
+    async fn test_streams_share_the_stream_memory_cap() {
+        let node = TestNode::spawn("stream-cap", 1000).await;
+        let bm = &node.block_manager;
+        bm.set_max_stream_memory(300);
+        assert!(bm.start_stream(Some(400), None).await.is_err());
+
+        // Each upload fits on its own, but not all three together
+        let mut streams = Vec::new();
+        for _ in 0..3 {
+            streams.push(bm.start_stream(None, None).await.unwrap());
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        for &id in &streams {
+            bm.append_stream(id, vec![1; 50]).await.unwrap();
+        }
+        bm.append_stream(streams[0], vec![1; 100]).await.unwrap();
+
+        // The newest of the least-progressed uploads gives way; here the one sending
+        let err = bm.append_stream(streams[2], vec![1; 100]).await.unwrap_err();
+        assert!(err.to_string().contains("300 byte stream memory (250 bytes held)"), "{}", err);
+        assert!(!bm.has_stream(streams[2]));
+
+        // The refused upload's bytes are given back to the others
+        bm.append_stream(streams[1], vec![1; 100]).await.unwrap();
+        assert_eq!(bm.list_streams().iter().map(|s| s.bytes_buffered).sum::<u64>(), 300);
+
+        // Over the cap again: the younger of the two equals is dropped, not the one sending
+        bm.append_stream(streams[0], vec![1]).await.unwrap();
+        assert!(!bm.has_stream(streams[1]));
+        let err = bm.append_stream(streams[1], vec![1]).await.unwrap_err();
+        assert!(err.to_string().contains("dropped to make room"), "{}", err);
+        assert_eq!(bm.stream_counters(), (3, 0));
+
+        // 0 goes back to the node's memory, and the furthest-along upload finishes
+        bm.set_max_stream_memory(0);
+        assert_eq!(bm.max_stream_memory(), 1000);
+        bm.append_stream(streams[0], vec![1; 500]).await.unwrap();
+        assert_eq!(bm.finish_stream(streams[0], None, memsdk::Durability::Pinned, memsdk::DEFAULT_PRIORITY).await.unwrap().1, 651);
+    }
+}
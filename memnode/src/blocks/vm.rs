@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use dashmap::DashMap;
 use crate::metadata::BlockId;
 
@@ -8,15 +9,34 @@ pub struct VmRegion {
     pub pages: DashMap<u64, BlockId>,
 }
 
+/// Allocation size, in MB, from which the interceptor offloads to the node
+/// unless `MEMCLOUD_MALLOC_THRESHOLD_MB` overrides it.
+pub const DEFAULT_MALLOC_THRESHOLD_MB: u64 = 8;
+
 pub struct VmRegionManager {
     regions: DashMap<u64, Arc<VmRegion>>,
+    threshold_mb: AtomicU64,
 }
 
 impl VmRegionManager {
     pub fn new() -> Self {
         Self {
             regions: DashMap::new(),
+            threshold_mb: AtomicU64::new(DEFAULT_MALLOC_THRESHOLD_MB),
+        }
+    }
+
+    /// Threshold handed to interceptors that ask on startup.
+    pub fn threshold_mb(&self) -> u64 {
+        self.threshold_mb.load(Ordering::Relaxed)
+    }
+
+    pub fn set_threshold_mb(&self, mb: u64) -> anyhow::Result<()> {
+        if mb == 0 {
+            anyhow::bail!("Threshold must be at least 1 MB");
         }
+        self.threshold_mb.store(mb, Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn create_region(&self, size: u64) -> u64 {
@@ -0,0 +1,753 @@
+use serde::Deserialize;
+use clap::{Parser, ValueEnum};
+use anyhow::{Result, Context};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PORT: u16 = 8080;
+pub const DEFAULT_MEMORY: &str = "1gb";
+/// Socket used when there is no data directory to put `memcloud.sock` in
+pub const DEFAULT_SOCKET: &str = memsdk::LEGACY_SOCKET;
+/// RPC socket in the data directory, so each user's node gets its own
+pub const SOCKET_FILE: &str = memsdk::SOCKET_FILE;
+pub const DEFAULT_BIND: &str = "0.0.0.0";
+pub const DEFAULT_NAME: &str = "Unnamed Node";
+pub const DEFAULT_LOG_MAX_SIZE: &str = "3mb";
+pub const DEFAULT_LOG_GENERATIONS: u32 = 3;
+pub const DEFAULT_RPC_PIPELINE_DEPTH: usize = 16;
+pub const DEFAULT_RPC_MAX_HEAVY: usize = 4;
+pub const DEFAULT_RPC_MAX_STREAMS: usize = 8;
+pub const DEFAULT_STREAM_IDLE_TIMEOUT_SECS: u64 = 300;
+pub const DEFAULT_RPC_HEAVY_THRESHOLD: &str = "1mb";
+pub const DEFAULT_KEEPALIVE_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_MDNS_READVERTISE_INTERVAL_SECS: u64 = 120;
+/// Permissions of the RPC socket: only the user running the node
+pub const DEFAULT_SOCKET_MODE: u32 = 0o600;
+/// TCP address the RPC server listens on next to the unix socket (the only one on Windows)
+pub const DEFAULT_RPC_ADDR: &str = memsdk::DEFAULT_RPC_ADDR;
+/// Overrides `~/.memcloud` as the directory for persisted state.
+pub const DATA_DIR_ENV: &str = memsdk::DATA_DIR_ENV;
+/// Config file read from the data directory when `--config` is not given.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Where persisted state (the node identity and trusted devices) lives:
+/// `$MEMCLOUD_HOME`, else `~/.memcloud`.
+pub fn data_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV).filter(|d| !d.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    dirs::home_dir()
+        .map(|home| home.join(".memcloud"))
+        .with_context(|| format!("No home directory to keep MemCloud state in; set {}", DATA_DIR_ENV))
+}
+
+/// `memcloud.sock` in the data directory, else the shared `/tmp` socket.
+pub fn default_socket() -> String {
+    match data_dir() {
+        Ok(dir) => dir.join(SOCKET_FILE).to_string_lossy().into_owned(),
+        Err(_) => DEFAULT_SOCKET.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryMode {
+    /// Advertise and browse for peers via mDNS
+    Mdns,
+    /// Only connect to peers explicitly (memcli connect)
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per record
+    #[default]
+    Text,
+    /// One JSON object per record, with the fields of the spans it happened in
+    Json,
+}
+
+/// Command line options. Every field is optional so that values coming from
+/// `--config` can be told apart from values the user typed; CLI flags win.
+#[derive(Parser, Debug, Default, Clone)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// Path to a TOML config file (default: config.toml in the data directory, if present)
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    #[arg(short, long, value_parser = memsdk::parse_size)]
+    pub memory: Option<u64>,
+
+    #[arg(long)]
+    pub socket: Option<String>,
+
+    /// Permissions of the RPC socket in octal, e.g. 0660 (default: 0600)
+    #[arg(long, value_parser = parse_socket_mode)]
+    pub socket_mode: Option<u32>,
+
+    /// Group (name or id) to give the RPC sockets, for use with --socket-mode 0660
+    #[arg(long)]
+    pub socket_group: Option<String>,
+
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Address the peer transport listens on
+    #[arg(long)]
+    pub bind: Option<String>,
+
+    /// Storage offered to peers by default (defaults to --memory)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub default_allowed_quota: Option<u64>,
+
+    #[arg(long, value_enum)]
+    pub discovery_mode: Option<DiscoveryMode>,
+
+    /// Write logs to this file instead of stderr (rotated by size, reopened on SIGHUP)
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it exceeds this size (0 disables, default: 3mb)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub log_max_size: Option<u64>,
+
+    /// Number of rotated log files to keep (default: 3)
+    #[arg(long)]
+    pub log_generations: Option<u32>,
+
+    /// Log verbosity (error, warn, info, debug, trace); overrides RUST_LOG and can be reloaded
+    #[arg(long)]
+    pub log_level: Option<log::LevelFilter>,
+
+    /// Log line format (default: text); json needs the `tracing` feature
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Require TCP RPC clients to authenticate with this token first; it grants admin access
+    #[arg(long)]
+    pub rpc_token: Option<String>,
+
+    /// TCP token that only grants write access (no flush, peer, trust or consent commands)
+    #[arg(long)]
+    pub rpc_write_token: Option<String>,
+
+    /// TCP token that only grants read access (loads, key listings, stats)
+    #[arg(long)]
+    pub rpc_read_token: Option<String>,
+
+    /// Also listen on this unix socket (`unix:PATH` or `PATH`) for read-only clients
+    #[arg(long)]
+    pub rpc_readonly: Option<String>,
+
+    /// Commands one RPC connection may run concurrently (default: 16)
+    #[arg(long)]
+    pub rpc_pipeline_depth: Option<usize>,
+
+    /// Heavy RPC commands allowed in flight across all connections (default: 4)
+    #[arg(long)]
+    pub rpc_max_heavy: Option<usize>,
+
+    /// Uploads one RPC connection may have open at once (default: 8)
+    #[arg(long)]
+    pub rpc_max_streams: Option<usize>,
+
+    /// Drop an upload that sends no chunk for this many seconds (0 never drops it; default: 300)
+    #[arg(long)]
+    pub stream_idle_timeout: Option<u64>,
+
+    /// Bytes all buffered uploads may hold together (default: --memory)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub max_stream_memory: Option<u64>,
+
+    /// Payload size from which Store/StreamChunk/Load count as heavy (default: 1mb)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub rpc_heavy_threshold: Option<u64>,
+
+    /// Largest RPC request accepted in one frame; larger values must be streamed (default: 256mb)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub rpc_max_frame: Option<u64>,
+
+    /// Largest encrypted frame accepted from a peer (default: 256mb)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub peer_max_frame: Option<u64>,
+
+    /// Seconds between keepalive pings to each peer; a peer silent for twice this is dropped (default: 10)
+    #[arg(long)]
+    pub keepalive_interval: Option<u64>,
+
+    /// Shed cache and refuse stores once the process RSS exceeds this (0 disables, default: 1.5x --memory + 64mb)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub rss_soft_limit: Option<u64>,
+
+    /// Also refuse new streams past this RSS (0 disables, default: 2x --memory + 128mb)
+    #[arg(long, value_parser = memsdk::parse_size)]
+    pub rss_hard_limit: Option<u64>,
+
+    /// Detach from the terminal and run in the background (Unix only). Returns
+    /// once the node is up, with a non-zero status if startup failed.
+    #[arg(long)]
+    pub daemonize: bool,
+
+    /// Write the node's pid here once startup succeeded; removed on shutdown
+    #[arg(long)]
+    pub pid_file: Option<PathBuf>,
+
+    /// Let mDNS discovery dial peers whose key was never pinned or trusted
+    /// (by default only known peers are connected automatically)
+    #[arg(long)]
+    pub auto_connect_untrusted: bool,
+
+    /// Seconds between mDNS re-announcements of this node, jittered by up to a quarter (default: 120)
+    #[arg(long)]
+    pub mdns_readvertise_interval: Option<u64>,
+
+    /// Deny a consent request nobody answered within this many seconds (0 waits forever, the default)
+    #[arg(long)]
+    pub consent_timeout: Option<u64>,
+
+    /// Free cache blocks that no key points at once they have not been read
+    /// for this many seconds (0 never frees them, the default)
+    #[arg(long)]
+    pub orphan_gc_idle: Option<u64>,
+
+    /// Serve the data already stored and refuse every command or peer
+    /// message that stores, changes or frees data
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Digest recorded for content-addressed blocks: blake3 or sha256 (default: blake3)
+    #[arg(long)]
+    pub checksum_algo: Option<memsdk::HashAlgo>,
+}
+
+/// On-disk representation of the config file. Sizes are strings ("512mb"),
+/// parsed while reading so a bad one is reported with its key and line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub port: Option<u16>,
+    #[serde(default, deserialize_with = "size")]
+    pub memory: Option<u64>,
+    pub name: Option<String>,
+    pub socket: Option<String>,
+    #[serde(default, deserialize_with = "mode")]
+    pub socket_mode: Option<u32>,
+    pub socket_group: Option<String>,
+    pub bind: Option<String>,
+    #[serde(default, deserialize_with = "size")]
+    pub default_allowed_quota: Option<u64>,
+    pub discovery_mode: Option<DiscoveryMode>,
+    pub log_file: Option<PathBuf>,
+    #[serde(default, deserialize_with = "size")]
+    pub log_max_size: Option<u64>,
+    pub log_generations: Option<u32>,
+    #[serde(default, deserialize_with = "level")]
+    pub log_level: Option<log::LevelFilter>,
+    pub log_format: Option<LogFormat>,
+    pub rpc_token: Option<String>,
+    pub rpc_write_token: Option<String>,
+    pub rpc_read_token: Option<String>,
+    pub rpc_readonly: Option<String>,
+    pub rpc_pipeline_depth: Option<usize>,
+    pub rpc_max_heavy: Option<usize>,
+    pub rpc_max_streams: Option<usize>,
+    pub stream_idle_timeout: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub max_stream_memory: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub rpc_heavy_threshold: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub rpc_max_frame: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub peer_max_frame: Option<u64>,
+    pub pid_file: Option<PathBuf>,
+    pub keepalive_interval: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub rss_soft_limit: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub rss_hard_limit: Option<u64>,
+    pub auto_connect_untrusted: Option<bool>,
+    pub mdns_readvertise_interval: Option<u64>,
+    pub consent_timeout: Option<u64>,
+    pub orphan_gc_idle: Option<u64>,
+    pub read_only: Option<bool>,
+    pub checksum_algo: Option<memsdk::HashAlgo>,
+}
+
+fn size<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<u64>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    memsdk::parse_size(&s).map(Some).map_err(|e| serde::de::Error::custom(format!("invalid size '{}': {}", s, e)))
+}
+
+fn mode<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<u32>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_socket_mode(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// An octal file mode such as "0600" or "660"; only permission bits.
+pub fn parse_socket_mode(s: &str) -> std::result::Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 && !digits.is_empty() => Ok(mode),
+        _ => Err(format!("invalid socket mode '{}'; give octal permissions such as 0600", s)),
+    }
+}
+
+fn level<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<log::LevelFilter>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Some).map_err(|_| serde::de::Error::custom(format!("invalid log level '{}'", s)))
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {:?}: {}", path, e.to_string().trim_end()))
+    }
+}
+
+impl Args {
+    /// Fill any option not given on the command line from the config file.
+    pub fn apply_file(&mut self, file: FileConfig) {
+        if self.port.is_none() {
+            self.port = file.port;
+        }
+        if self.memory.is_none() {
+            self.memory = file.memory;
+        }
+        if self.name.is_none() {
+            self.name = file.name;
+        }
+        if self.socket.is_none() {
+            self.socket = file.socket;
+        }
+        if self.socket_mode.is_none() {
+            self.socket_mode = file.socket_mode;
+        }
+        if self.socket_group.is_none() {
+            self.socket_group = file.socket_group;
+        }
+        if self.bind.is_none() {
+            self.bind = file.bind;
+        }
+        if self.default_allowed_quota.is_none() {
+            self.default_allowed_quota = file.default_allowed_quota;
+        }
+        if self.discovery_mode.is_none() {
+            self.discovery_mode = file.discovery_mode;
+        }
+        if self.log_file.is_none() {
+            self.log_file = file.log_file;
+        }
+        if self.log_max_size.is_none() {
+            self.log_max_size = file.log_max_size;
+        }
+        if self.log_generations.is_none() {
+            self.log_generations = file.log_generations;
+        }
+        if self.log_level.is_none() {
+            self.log_level = file.log_level;
+        }
+        if self.log_format.is_none() {
+            self.log_format = file.log_format;
+        }
+        if self.rpc_token.is_none() {
+            self.rpc_token = file.rpc_token;
+        }
+        if self.rpc_write_token.is_none() {
+            self.rpc_write_token = file.rpc_write_token;
+        }
+        if self.rpc_read_token.is_none() {
+            self.rpc_read_token = file.rpc_read_token;
+        }
+        if self.rpc_readonly.is_none() {
+            self.rpc_readonly = file.rpc_readonly;
+        }
+        if self.rpc_pipeline_depth.is_none() {
+            self.rpc_pipeline_depth = file.rpc_pipeline_depth;
+        }
+        if self.rpc_max_heavy.is_none() {
+            self.rpc_max_heavy = file.rpc_max_heavy;
+        }
+        if self.rpc_max_streams.is_none() {
+            self.rpc_max_streams = file.rpc_max_streams;
+        }
+        if self.stream_idle_timeout.is_none() {
+            self.stream_idle_timeout = file.stream_idle_timeout;
+        }
+        if self.max_stream_memory.is_none() {
+            self.max_stream_memory = file.max_stream_memory;
+        }
+        if self.rpc_heavy_threshold.is_none() {
+            self.rpc_heavy_threshold = file.rpc_heavy_threshold;
+        }
+        if self.rpc_max_frame.is_none() {
+            self.rpc_max_frame = file.rpc_max_frame;
+        }
+        if self.peer_max_frame.is_none() {
+            self.peer_max_frame = file.peer_max_frame;
+        }
+        if self.pid_file.is_none() {
+            self.pid_file = file.pid_file;
+        }
+        if self.keepalive_interval.is_none() {
+            self.keepalive_interval = file.keepalive_interval;
+        }
+        if self.rss_soft_limit.is_none() {
+            self.rss_soft_limit = file.rss_soft_limit;
+        }
+        if self.rss_hard_limit.is_none() {
+            self.rss_hard_limit = file.rss_hard_limit;
+        }
+        if !self.auto_connect_untrusted {
+            self.auto_connect_untrusted = file.auto_connect_untrusted.unwrap_or(false);
+        }
+        if self.mdns_readvertise_interval.is_none() {
+            self.mdns_readvertise_interval = file.mdns_readvertise_interval;
+        }
+        if self.consent_timeout.is_none() {
+            self.consent_timeout = file.consent_timeout;
+        }
+        if self.orphan_gc_idle.is_none() {
+            self.orphan_gc_idle = file.orphan_gc_idle;
+        }
+        if !self.read_only {
+            self.read_only = file.read_only.unwrap_or(false);
+        }
+        if self.checksum_algo.is_none() {
+            self.checksum_algo = file.checksum_algo;
+        }
+    }
+
+    /// Parse the command line and merge in the config file.
+    pub fn load() -> Result<NodeConfig> {
+        ConfigSource::new(Self::parse()).load()
+    }
+
+    pub fn resolve(self) -> Result<NodeConfig> {
+        let memory = match self.memory {
+            Some(m) => m,
+            None => memsdk::parse_size(DEFAULT_MEMORY)?,
+        };
+        let rss_soft_limit = self.rss_soft_limit.unwrap_or(memory / 2 * 3 + 64 * 1024 * 1024);
+        let rss_hard_limit = self.rss_hard_limit.unwrap_or(memory * 2 + 128 * 1024 * 1024);
+        let max_frame = |v: Option<u64>, flag: &str| -> Result<usize> {
+            let max = match v {
+                Some(m) => m,
+                None => crate::net::frame::DEFAULT_MAX_FRAME as u64,
+            };
+            if !(1024..=u32::MAX as u64).contains(&max) {
+                anyhow::bail!("--{} must be between 1kb and 4gb", flag);
+            }
+            Ok(max as usize)
+        };
+        let rpc_max_frame = max_frame(self.rpc_max_frame, "rpc-max-frame")?;
+        let peer_max_frame = max_frame(self.peer_max_frame, "peer-max-frame")?;
+        let rpc_token = self.rpc_token.filter(|t| !t.is_empty());
+        let rpc_write_token = self.rpc_write_token.filter(|t| !t.is_empty());
+        let rpc_read_token = self.rpc_read_token.filter(|t| !t.is_empty());
+        if rpc_token.is_none() && (rpc_write_token.is_some() || rpc_read_token.is_some()) {
+            anyhow::bail!("--rpc-write-token and --rpc-read-token need --rpc-token for admin access");
+        }
+        let tokens = [&rpc_token, &rpc_write_token, &rpc_read_token];
+        if tokens.iter().enumerate().any(|(i, t)| t.is_some() && tokens[i + 1..].contains(t)) {
+            anyhow::bail!("--rpc-token, --rpc-write-token and --rpc-read-token must differ");
+        }
+        let rpc_readonly = self.rpc_readonly.map(|p| p.strip_prefix("unix:").map(str::to_string).unwrap_or(p));
+        if rss_soft_limit > 0 && rss_hard_limit > 0 && rss_hard_limit < rss_soft_limit {
+            anyhow::bail!("--rss-hard-limit ({}) is below --rss-soft-limit ({})", rss_hard_limit, rss_soft_limit);
+        }
+        Ok(NodeConfig {
+            port: self.port.unwrap_or(DEFAULT_PORT),
+            memory,
+            socket: self.socket.unwrap_or_else(default_socket),
+            socket_mode: self.socket_mode.unwrap_or(DEFAULT_SOCKET_MODE),
+            socket_group: self.socket_group.filter(|g| !g.is_empty()),
+            name: self.name.unwrap_or_else(|| DEFAULT_NAME.to_string()),
+            bind: self.bind.unwrap_or_else(|| DEFAULT_BIND.to_string()),
+            default_allowed_quota: self.default_allowed_quota.unwrap_or(memory),
+            discovery_mode: self.discovery_mode.unwrap_or(DiscoveryMode::Mdns),
+            log_file: self.log_file,
+            log_max_size: match self.log_max_size {
+                Some(s) => s,
+                None => memsdk::parse_size(DEFAULT_LOG_MAX_SIZE)?,
+            },
+            log_generations: self.log_generations.unwrap_or(DEFAULT_LOG_GENERATIONS),
+            log_level: self.log_level,
+            log_format: self.log_format.unwrap_or_default(),
+            rpc_token,
+            rpc_write_token,
+            rpc_read_token,
+            rpc_readonly,
+            rpc_addr: Some(DEFAULT_RPC_ADDR.to_string()),
+            rpc_pipeline_depth: self.rpc_pipeline_depth.unwrap_or(DEFAULT_RPC_PIPELINE_DEPTH),
+            rpc_max_heavy: self.rpc_max_heavy.unwrap_or(DEFAULT_RPC_MAX_HEAVY),
+            rpc_max_streams: self.rpc_max_streams.unwrap_or(DEFAULT_RPC_MAX_STREAMS),
+            stream_idle_timeout: self.stream_idle_timeout.unwrap_or(DEFAULT_STREAM_IDLE_TIMEOUT_SECS),
+            max_stream_memory: self.max_stream_memory.filter(|m| *m > 0).unwrap_or(memory),
+            rpc_heavy_threshold: match self.rpc_heavy_threshold {
+                Some(s) => s,
+                None => memsdk::parse_size(DEFAULT_RPC_HEAVY_THRESHOLD)?,
+            },
+            rpc_max_frame,
+            peer_max_frame,
+            keepalive_interval: self.keepalive_interval.filter(|s| *s > 0).unwrap_or(DEFAULT_KEEPALIVE_INTERVAL_SECS),
+            rss_soft_limit,
+            rss_hard_limit,
+            daemonize: self.daemonize,
+            pid_file: self.pid_file,
+            auto_connect_untrusted: self.auto_connect_untrusted,
+            mdns_readvertise_interval: self.mdns_readvertise_interval.filter(|s| *s > 0).unwrap_or(DEFAULT_MDNS_READVERTISE_INTERVAL_SECS),
+            consent_timeout: self.consent_timeout.unwrap_or(0),
+            orphan_gc_idle: self.orphan_gc_idle.unwrap_or(0),
+            read_only: self.read_only,
+            checksum_algo: self.checksum_algo.unwrap_or_default(),
+            data_dir: data_dir().ok(),
+            source: None,
+        })
+    }
+}
+
+/// Fully resolved node settings after merging CLI, config file and defaults.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub port: u16,
+    pub memory: u64,
+    pub socket: String,
+    /// Permissions of the RPC socket; the read-only socket also lets the group in
+    pub socket_mode: u32,
+    /// Group given to the RPC sockets; `None` keeps the node's own
+    pub socket_group: Option<String>,
+    pub name: String,
+    pub bind: String,
+    pub default_allowed_quota: u64,
+    pub discovery_mode: DiscoveryMode,
+    pub log_file: Option<PathBuf>,
+    pub log_max_size: u64,
+    pub log_generations: u32,
+    /// `None` leaves verbosity to RUST_LOG
+    pub log_level: Option<log::LevelFilter>,
+    pub log_format: LogFormat,
+    /// Admin token for the TCP listener
+    pub rpc_token: Option<String>,
+    pub rpc_write_token: Option<String>,
+    pub rpc_read_token: Option<String>,
+    /// Extra unix socket for read-only clients
+    pub rpc_readonly: Option<String>,
+    /// TCP RPC listener; `None` serves the unix socket only
+    pub rpc_addr: Option<String>,
+    pub rpc_pipeline_depth: usize,
+    pub rpc_max_heavy: usize,
+    /// Uploads one RPC connection may have open
+    pub rpc_max_streams: usize,
+    /// Seconds an upload may go without a chunk; 0 waits forever
+    pub stream_idle_timeout: u64,
+    /// Bytes buffered uploads may hold together
+    pub max_stream_memory: u64,
+    pub rpc_heavy_threshold: u64,
+    pub rpc_max_frame: usize,
+    pub peer_max_frame: usize,
+    pub keepalive_interval: u64,
+    /// Process RSS limits for the memory guard; 0 disables a level
+    pub rss_soft_limit: u64,
+    pub rss_hard_limit: u64,
+    pub daemonize: bool,
+    pub pid_file: Option<PathBuf>,
+    pub auto_connect_untrusted: bool,
+    pub mdns_readvertise_interval: u64,
+    /// Seconds a consent request waits for an answer; 0 waits forever
+    pub consent_timeout: u64,
+    /// Seconds an unnamed cache block may go unread before it is freed; 0 keeps it
+    pub orphan_gc_idle: u64,
+    /// Refuse client commands and peer messages that change data
+    pub read_only: bool,
+    /// Algorithm of the digests recorded for content-addressed blocks
+    pub checksum_algo: memsdk::HashAlgo,
+    /// Where the identity and trusted devices are kept; `None` keeps them in memory
+    pub data_dir: Option<PathBuf>,
+    /// The flags and file this was resolved from; `None` for nodes built in-process
+    pub source: Option<ConfigSource>,
+}
+
+/// Settings `ReloadConfig` applies to a running node; changes to any other key
+/// are reported as needing a restart.
+pub const RELOADABLE_KEYS: &[&str] = &["log_level", "rss_soft_limit", "rss_hard_limit", "default_allowed_quota", "consent_timeout", "orphan_gc_idle", "read_only", "stream_idle_timeout", "max_stream_memory"];
+
+/// The command line and config file a node was started from, kept so the
+/// file can be read again and merged under the same flags.
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    args: Args,
+    file: Option<PathBuf>,
+    // Given with --config, so it has to exist
+    explicit: bool,
+}
+
+impl ConfigSource {
+    /// Use `--config`, else `config.toml` in the data directory.
+    pub fn new(args: Args) -> Self {
+        let explicit = args.config.is_some();
+        let file = args.config.clone().or_else(|| data_dir().ok().map(|dir| dir.join(CONFIG_FILE)));
+        Self { args, file, explicit }
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Read the file again and resolve it under the original flags.
+    pub fn load(&self) -> Result<NodeConfig> {
+        let mut args = self.args.clone();
+        if let Some(path) = &self.file {
+            if self.explicit || path.exists() {
+                args.apply_file(FileConfig::load(path)?);
+            }
+        }
+        let mut config = args.resolve()?;
+        config.source = Some(self.clone());
+        Ok(config)
+    }
+}
+
+impl NodeConfig {
+    /// Config keys whose resolved value differs between `self` and `other`.
+    pub fn changed_keys(&self, other: &NodeConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        macro_rules! compare {
+            ($($field:ident),*) => {
+                $(if self.$field != other.$field {
+                    changed.push(stringify!($field));
+                })*
+            };
+        }
+        compare!(
+            port, memory, socket, socket_mode, socket_group, name, bind, default_allowed_quota, discovery_mode, log_file, log_max_size,
+            log_generations, log_level, log_format, rpc_token, rpc_write_token, rpc_read_token, rpc_readonly, rpc_pipeline_depth, rpc_max_heavy, rpc_max_streams, stream_idle_timeout, max_stream_memory, rpc_heavy_threshold,
+            rpc_max_frame, peer_max_frame, keepalive_interval, rss_soft_limit, rss_hard_limit, pid_file,
+            auto_connect_untrusted, mdns_readvertise_interval, consent_timeout, orphan_gc_idle, read_only, checksum_algo
+        );
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp_config(content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("memnode-config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    const SAMPLE: &str = r#"
+port = 9090
+memory = "512mb"
+name = "ConfigNode"
+socket = "/tmp/test-memcloud.sock"
+socket_mode = "0660"
+bind = "127.0.0.1"
+default_allowed_quota = "256mb"
+discovery_mode = "manual"
+"#;
+
+    #[test]
+    fn test_config_file_populates_args() {
+        let path = write_temp_config(SAMPLE);
+        let mut args = Args::try_parse_from(["memnode", "--config", path.to_str().unwrap()]).unwrap();
+        args.apply_file(FileConfig::load(&path).unwrap());
+        let cfg = args.resolve().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(cfg.port, 9090);
+        assert_eq!(cfg.memory, 512 * 1024 * 1024);
+        assert_eq!(cfg.name, "ConfigNode");
+        assert_eq!(cfg.socket, "/tmp/test-memcloud.sock");
+        assert_eq!(cfg.socket_mode, 0o660);
+        assert_eq!(cfg.bind, "127.0.0.1");
+        assert_eq!(cfg.default_allowed_quota, 256 * 1024 * 1024);
+        assert_eq!(cfg.discovery_mode, DiscoveryMode::Manual);
+    }
+
+    #[test]
+    fn test_cli_flag_overrides_config_file() {
+        let path = write_temp_config(SAMPLE);
+        let mut args = Args::try_parse_from([
+            "memnode", "--config", path.to_str().unwrap(), "--port", "7000", "--memory", "2gb",
+        ]).unwrap();
+        args.apply_file(FileConfig::load(&path).unwrap());
+        let cfg = args.resolve().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(cfg.port, 7000);
+        assert_eq!(cfg.memory, 2 * 1024 * 1024 * 1024);
+        // Untouched values still come from the file
+        assert_eq!(cfg.name, "ConfigNode");
+    }
+
+    #[test]
+    fn test_defaults_without_config() {
+        let args = Args::try_parse_from(["memnode"]).unwrap();
+        let cfg = args.resolve().unwrap();
+        assert_eq!(cfg.port, DEFAULT_PORT);
+        assert_eq!(cfg.memory, 1024 * 1024 * 1024);
+        assert_eq!(cfg.default_allowed_quota, cfg.memory);
+        assert_eq!(cfg.max_stream_memory, cfg.memory);
+        assert_eq!(cfg.discovery_mode, DiscoveryMode::Mdns);
+        assert_eq!((cfg.socket_mode, cfg.socket_group), (0o600, None));
+        assert_eq!(cfg.rss_soft_limit, 1600 * 1024 * 1024);
+        assert_eq!(cfg.rss_hard_limit, 2176 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_socket_mode_is_octal() {
+        assert_eq!(parse_socket_mode("0600"), Ok(0o600));
+        assert_eq!(parse_socket_mode("660"), Ok(0o660));
+        assert_eq!(parse_socket_mode("0o640"), Ok(0o640));
+        for bad in ["", "0800", "1777", "rw-"] {
+            assert!(parse_socket_mode(bad).is_err(), "{}", bad);
+        }
+        assert!(Args::try_parse_from(["memnode", "--socket-mode", "999"]).is_err());
+    }
+
+    #[test]
+    fn test_rss_limits_must_be_ordered() {
+        let args = Args::try_parse_from(["memnode", "--rss-soft-limit", "2gb", "--rss-hard-limit", "1gb"]).unwrap();
+        assert!(args.resolve().is_err());
+        let args = Args::try_parse_from(["memnode", "--rss-soft-limit", "2gb", "--rss-hard-limit", "0"]).unwrap();
+        assert_eq!(args.resolve().unwrap().rss_hard_limit, 0);
+    }
+
+    #[test]
+    fn test_limited_rpc_tokens_need_an_admin_token() {
+        let args = Args::try_parse_from(["memnode", "--rpc-read-token", "r"]).unwrap();
+        assert!(args.resolve().is_err());
+        let args = Args::try_parse_from(["memnode", "--rpc-token", "a", "--rpc-write-token", "a"]).unwrap();
+        assert!(args.resolve().is_err());
+        let args = Args::try_parse_from(["memnode", "--rpc-token", "a", "--rpc-read-token", "r", "--rpc-readonly", "unix:/run/ro.sock"]).unwrap();
+        let cfg = args.resolve().unwrap();
+        assert_eq!((cfg.rpc_read_token.as_deref(), cfg.rpc_readonly.as_deref()), (Some("r"), Some("/run/ro.sock")));
+    }
+
+    #[test]
+    fn test_invalid_config_names_key_and_line() {
+        for (content, key) in [("port = 1\nbogus = 2\n", "bogus"), ("port = 1\nmemory = \"lots\"\n", "memory")] {
+            let path = write_temp_config(content);
+            let err = FileConfig::load(&path).unwrap_err().to_string();
+            std::fs::remove_file(&path).unwrap();
+            assert!(err.contains("line 2"), "{}", err);
+            assert!(err.contains(key), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_missing_config_file_is_error() {
+        let path = std::env::temp_dir().join("memnode-config-does-not-exist.toml");
+        assert!(FileConfig::load(&path).is_err());
+    }
+}
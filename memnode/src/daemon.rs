@@ -0,0 +1,137 @@
+//! Running memnode in the background: detaching from the terminal, the pid
+//! file, and telling the launching process when startup is done.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Held by a daemonized node until initialization is done. The process that
+/// ran `memnode --daemonize` waits on it and exits 0 once notified, or 1 if
+/// the node dies first.
+pub struct Readiness {
+    #[cfg(unix)]
+    pipe: File,
+}
+
+impl Readiness {
+    pub fn notify(self) {
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+            let mut pipe = self.pipe;
+            let _ = pipe.write_all(b"1");
+        }
+    }
+}
+
+/// Detach from the controlling terminal and process group (fork, setsid, fork)
+/// so closing the terminal no longer takes the node down. Stdin is pointed at
+/// /dev/null and stdout/stderr at `log_file` (or /dev/null), so panics still
+/// end up in the log. Must be called before any threads exist.
+#[cfg(unix)]
+pub fn daemonize(log_file: Option<&Path>) -> Result<Readiness> {
+    use nix::unistd::{dup2, fork, pipe, setsid, ForkResult};
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    let (read_end, write_end) = pipe()?;
+    // Safety: no other threads exist yet (the runtime starts after this)
+    match unsafe { fork() }? {
+        ForkResult::Parent { .. } => {
+            drop(write_end);
+            let mut status = [0u8; 1];
+            let ready = File::from(read_end).read(&mut status).unwrap_or(0) == 1;
+            std::process::exit(if ready { 0 } else { 1 });
+        }
+        ForkResult::Child => {}
+    }
+    drop(read_end);
+    setsid()?;
+    // Fork again so the node is not a session leader and can never reacquire a terminal
+    match unsafe { fork() }? {
+        ForkResult::Parent { .. } => std::process::exit(0),
+        ForkResult::Child => {}
+    }
+
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    let out = match log_file {
+        Some(path) => File::options().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open log file {:?}", path))?,
+        None => null.try_clone()?,
+    };
+    dup2(null.as_raw_fd(), 0)?;
+    dup2(out.as_raw_fd(), 1)?;
+    dup2(out.as_raw_fd(), 2)?;
+
+    Ok(Readiness { pipe: File::from(write_end) })
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_log_file: Option<&Path>) -> Result<Readiness> {
+    anyhow::bail!("--daemonize is only supported on Unix")
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// The pid file of a running node; removed again when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write our pid via a temporary file and rename, so readers never see a partial file.
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, std::process::id().to_string())
+            .with_context(|| format!("Failed to write pid file {:?}", tmp))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write pid file {:?}", path))?;
+        Ok(Self { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Only remove it if it is still ours (a newer node may have replaced it)
+        if std::fs::read_to_string(&self.path).ok().as_deref() == Some(std::process::id().to_string().as_str()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_written_and_removed() {
+        let path = std::env::temp_dir().join(format!("memnode-{}.pid", uuid::Uuid::new_v4()));
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), std::process::id().to_string());
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
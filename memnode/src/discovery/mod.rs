@@ -3,12 +3,25 @@ use mdns_sd::{ServiceDaemon, ServiceInfo, ServiceEvent};
 use log::{info, error, warn, debug};
 use uuid::Uuid;
 use std::sync::Arc;
-use crate::peers::PeerManager;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use crate::peers::{DialPolicy, PeerManager};
+use rand::Rng;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use tokio::task::JoinHandle;
 
 use crate::blocks::InMemoryBlockManager;
 
+/// How often the supervisor checks that browsing is still running.
+const BROWSE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// `interval` shortened or stretched by up to a quarter, so nodes started
+/// together do not all announce at once.
+fn jittered(interval: Duration) -> Duration {
+    interval.mul_f64(rand::thread_rng().gen_range(0.75..=1.25))
+}
+
 pub struct MdnsDiscovery {
     daemon: ServiceDaemon,
     service_type: &'static str,
@@ -16,11 +29,17 @@ pub struct MdnsDiscovery {
     port: u16,
     peer_manager: Arc<PeerManager>,
     block_manager: Arc<InMemoryBlockManager>,
-    default_quota: u64,
+    // Dial peers whose advertised key is neither pinned nor trusted
+    auto_connect_untrusted: bool,
+    readvertise_interval: Duration,
+    // The browser loop; the supervisor starts a new one once it ends
+    browser: std::sync::Mutex<Option<JoinHandle<()>>>,
+    browse_starts: AtomicU32,
+    stopped: Arc<AtomicBool>,
 }
 
 impl MdnsDiscovery {
-    pub fn new(node_id: Uuid, port: u16, peer_manager: Arc<PeerManager>, block_manager: Arc<InMemoryBlockManager>, default_quota: u64) -> Result<Self> {
+    pub fn new(node_id: Uuid, port: u16, peer_manager: Arc<PeerManager>, block_manager: Arc<InMemoryBlockManager>, auto_connect_untrusted: bool, readvertise_interval: Duration) -> Result<Self> {
         let daemon = ServiceDaemon::new().map_err(|e| {
             error!("Failed to create mDNS daemon: {}. Auto-discovery will not work.", e);
             error!("This may be due to: firewall blocking port 5353, another mDNS service running, or network restrictions.");
@@ -34,13 +53,30 @@ impl MdnsDiscovery {
             port,
             peer_manager,
             block_manager,
-            default_quota,
+            auto_connect_untrusted,
+            readvertise_interval,
+            browser: std::sync::Mutex::new(None),
+            browse_starts: AtomicU32::new(0),
+            stopped: Arc::new(AtomicBool::new(false)),
         })
     }
 
     pub fn start_advertising(&self) -> Result<()> {
+        self.register().map_err(|e| {
+            error!("Failed to register mDNS service: {}. Other devices won't discover this node.", e);
+            e
+        })?;
+        
+        info!("✅ mDNS advertising started for {} on port {}", self.node_id, self.port);
+        info!("   Service type: {}", self.service_type);
+        Ok(())
+    }
+
+    // Registering the same instance again re-announces it
+    fn register(&self) -> Result<()> {
         let hostname = format!("memcloud-{}", self.node_id);
-        let properties = [("id", self.node_id.to_string())];
+        let public_key = hex::encode(self.peer_manager.get_identity().public_key().to_bytes());
+        let properties = [("id", self.node_id.to_string()), ("pk", public_key)];
         
         let my_service = ServiceInfo::new(
             self.service_type,
@@ -51,18 +87,8 @@ impl MdnsDiscovery {
             match std::collections::HashMap::from_iter(properties.iter().map(|(k, v)| (k.to_string(), v.to_string()))) {
                  props => Some(props)
             },
-        ).map_err(|e| {
-            error!("Failed to create mDNS service info: {}", e);
-            e
-        })?;
-
-        self.daemon.register(my_service).map_err(|e| {
-            error!("Failed to register mDNS service: {}. Other devices won't discover this node.", e);
-            e
-        })?;
-        
-        info!("✅ mDNS advertising started for {} on port {}", self.node_id, self.port);
-        info!("   Service type: {}", self.service_type);
+        )?;
+        self.daemon.register(my_service)?;
         Ok(())
     }
 
@@ -74,14 +100,16 @@ impl MdnsDiscovery {
         })?;
         
         let my_id = self.node_id;
+        let my_key = hex::encode(self.peer_manager.get_identity().public_key().to_bytes());
         let peer_manager = self.peer_manager.clone();
         let block_manager = self.block_manager.clone();
-        let quota = self.default_quota;
+        let policy = DialPolicy { accept_new_key: false, require_known: !self.auto_connect_untrusted };
+        let stopped = self.stopped.clone();
 
-        tokio::spawn(async move {
+        let handle = self.block_manager.health.supervise("mdns-browser", async move {
             info!("🔍 mDNS browser started, listening for MemCloud peers...");
             
-            while let Ok(event) = receiver.recv() {
+            while let Ok(event) = receiver.recv_async().await {
                 match event {
                     ServiceEvent::ServiceFound(service_type, fullname) => {
                         debug!("mDNS ServiceFound: {} (type: {})", fullname, service_type);
@@ -90,11 +118,18 @@ impl MdnsDiscovery {
                         let fullname = info.get_fullname();
                         debug!("mDNS ServiceResolved: {}", fullname);
                         
-                        // Check if it's our own service
-                        if fullname.contains(&my_id.to_string()) {
+                        // Our own service advertises our key; another key with
+                        // our id is a cloned machine, which the handshake refuses
+                        let pk = info.get_property_val_str("pk");
+                        if pk == Some(my_key.as_str()) || (pk.is_none() && fullname.contains(&my_id.to_string())) {
                             debug!("Ignoring own service: {}", fullname);
                             continue;
                         }
+                        if info.get_property_val_str("id") == Some(my_id.to_string().as_str()) {
+                            error!("Service '{}' advertises this node's id {} with key {}; was one machine cloned from the other? Not connecting.",
+                                fullname, my_id, pk.unwrap_or("?"));
+                            continue;
+                        }
 
                         // Extract peer ID from properties
                         let id_prop_raw = match info.get_property_val("id") {
@@ -152,9 +187,18 @@ impl MdnsDiscovery {
                         
                         let socket_addr = SocketAddr::new(*addr, info.get_port());
                         info!("🔗 Discovered peer {} at {}", peer_id, socket_addr);
+
+                        // The handshake checks the key again; this only saves dialing strangers
+                        if policy.require_known {
+                            let known = info.get_property_val_str("pk").is_some_and(|pk| peer_manager.trusted_store.is_known(pk));
+                            if !known {
+                                info!("Not connecting to untrusted peer {} at {}; use 'memcli connect {}' or start with --auto-connect-untrusted", peer_id, socket_addr, socket_addr);
+                                continue;
+                            }
+                        }
                         
                         // Attempt to connect
-                        match peer_manager.add_discovered_peer(peer_id, socket_addr, block_manager.clone(), peer_manager.clone(), quota).await {
+                        match peer_manager.add_discovered_peer(peer_id, socket_addr, block_manager.clone(), peer_manager.clone(), peer_manager.default_quota(), policy).await {
                             Ok(_) => {
                                 info!("✅ Successfully connected to discovered peer {}", peer_id);
                             }
@@ -171,15 +215,91 @@ impl MdnsDiscovery {
                         debug!("mDNS search started for: {}", service_type);
                     }
                     ServiceEvent::SearchStopped(service_type) => {
-                        warn!("mDNS search stopped for: {}. Discovery may not work.", service_type);
+                        warn!("mDNS search stopped for: {}. Browsing will be restarted.", service_type);
                     }
                 }
             }
             
-            warn!("mDNS browser loop exited unexpectedly. Auto-discovery is no longer active.");
+            if !stopped.load(Ordering::Relaxed) {
+                warn!("mDNS browser loop exited unexpectedly. Auto-discovery pauses until it is restarted.");
+            }
         });
+        *self.browser.lock().unwrap() = Some(handle);
+        self.browse_starts.fetch_add(1, Ordering::Relaxed);
         
         info!("✅ mDNS browsing started for service type: {}", self.service_type);
         Ok(())
     }
+
+    /// Keep discovery alive across network changes: re-announce this node every
+    /// `readvertise_interval` and restart browsing once the browser loop ends
+    /// (the daemon drops searches when interfaces come and go). Runs until [`shutdown`](Self::shutdown).
+    pub fn start_supervisor(self: &Arc<Self>) {
+        let discovery = self.clone();
+        self.block_manager.health.supervise("mdns-supervisor", async move {
+            let check_every = BROWSE_CHECK_INTERVAL.min(discovery.readvertise_interval);
+            let mut next_advert = Instant::now() + jittered(discovery.readvertise_interval);
+            loop {
+                tokio::time::sleep(jittered(check_every)).await;
+                if discovery.stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                // A failed restart is logged by start_browsing and retried on the next check
+                if discovery.browser_exited() {
+                    info!("Restarting mDNS browsing");
+                    let _ = discovery.start_browsing();
+                }
+                if Instant::now() >= next_advert {
+                    match discovery.register() {
+                        Ok(()) => debug!("Re-announced {} over mDNS", discovery.node_id),
+                        Err(e) => warn!("Failed to re-announce this node over mDNS: {}", e),
+                    }
+                    next_advert = Instant::now() + jittered(discovery.readvertise_interval);
+                }
+            }
+        });
+    }
+
+    fn browser_exited(&self) -> bool {
+        self.browser.lock().unwrap().as_ref().is_some_and(|h| h.is_finished())
+    }
+
+    /// Whether the mDNS daemon thread still answers; gives up after `timeout`.
+    pub fn daemon_running(&self, timeout: std::time::Duration) -> bool {
+        match self.daemon.status() {
+            Ok(rx) => matches!(rx.recv_timeout(timeout), Ok(mdns_sd::DaemonStatus::Running)),
+            Err(_) => false,
+        }
+    }
+
+    /// Stop advertising and browsing; the browser task ends with the daemon.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Err(e) = self.daemon.shutdown() {
+            warn!("Failed to stop mDNS daemon: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{wait_until, TestNode};
+
+    #[tokio::test]
+    async fn test_supervisor_restarts_stopped_browsing() {
+        let node = TestNode::spawn("mdns", 1024 * 1024).await;
+        let discovery = Arc::new(MdnsDiscovery::new(node.id, node.port, node.peer_manager.clone(), node.block_manager.clone(), false, Duration::from_millis(50)).unwrap());
+        discovery.start_browsing().unwrap();
+        discovery.start_supervisor();
+
+        // Stopping the search closes the browser's receiver, as a network change can
+        discovery.daemon.stop_browse(discovery.service_type).unwrap();
+        wait_until(|| discovery.browse_starts.load(Ordering::Relaxed) >= 2 && !discovery.browser_exited()).await;
+
+        discovery.shutdown();
+        let starts = discovery.browse_starts.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(discovery.browse_starts.load(Ordering::Relaxed), starts, "no restarts after shutdown");
+    }
 }
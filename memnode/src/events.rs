@@ -0,0 +1,145 @@
+//! The node's event journal: what happened to peers, consent, trust and
+//! stored data, without the rest of the log. The last `EVENTS_KEPT` events
+//! are kept in memory and served by the `Events` command. Recording takes a
+//! short lock to append and never waits on readers.
+
+use memsdk::NodeEvent;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Events kept; older ones are dropped as new ones arrive.
+pub const EVENTS_KEPT: usize = 10_000;
+
+pub struct EventLog {
+    ring: Mutex<VecDeque<NodeEvent>>,
+    // Sequence number of the newest event, for waiting readers
+    latest: watch::Sender<u64>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self { ring: Mutex::new(VecDeque::new()), latest: watch::channel(0).0 }
+    }
+}
+
+impl EventLog {
+    /// Append an event. `kind` is one of `memsdk::EVENT_KINDS`.
+    pub fn record(&self, kind: &str, action: &str, subject: Option<&str>, bytes: Option<u64>, detail: impl Into<String>) {
+        let seq = {
+            let mut ring = self.ring.lock().unwrap();
+            let seq = ring.back().map_or(1, |e| e.seq + 1);
+            if ring.len() == EVENTS_KEPT {
+                ring.pop_front();
+            }
+            ring.push_back(NodeEvent {
+                seq,
+                at: unix_millis(),
+                kind: kind.to_string(),
+                action: action.to_string(),
+                subject: subject.map(str::to_string),
+                bytes,
+                detail: detail.into(),
+            });
+            seq
+        };
+        self.latest.send_replace(seq);
+    }
+
+    /// Up to `limit` events of `kinds` (all without it), oldest first: those
+    /// after `since_seq`, or else the latest. Also returns the `since_seq`
+    /// that continues after them.
+    pub fn query(&self, since_seq: Option<u64>, kinds: Option<&[String]>, limit: usize) -> (Vec<NodeEvent>, u64) {
+        let wanted = |e: &&NodeEvent| kinds.is_none_or(|k| k.contains(&e.kind));
+        let ring = self.ring.lock().unwrap();
+        let newest = ring.back().map_or(0, |e| e.seq);
+        match since_seq {
+            Some(since) => {
+                let start = ring.partition_point(|e| e.seq <= since);
+                let events: Vec<NodeEvent> = ring.range(start..).filter(wanted).take(limit).cloned().collect();
+                // Cut short by the limit: carry on from the last one returned
+                let next = match events.last() {
+                    Some(last) if events.len() == limit => last.seq,
+                    _ => newest.max(since),
+                };
+                (events, next)
+            }
+            None => {
+                let mut events: Vec<NodeEvent> = ring.iter().rev().filter(wanted).take(limit).cloned().collect();
+                events.reverse();
+                (events, newest)
+            }
+        }
+    }
+
+    /// Like `query`, but wait up to `wait` for a matching event when there
+    /// is none after `since_seq` yet.
+    pub async fn wait_query(&self, since_seq: u64, kinds: Option<&[String]>, limit: usize, wait: Duration) -> (Vec<NodeEvent>, u64) {
+        let deadline = tokio::time::Instant::now() + wait;
+        let mut latest = self.latest.subscribe();
+        let mut since = since_seq;
+        loop {
+            latest.mark_unchanged();
+            let (events, next) = self.query(Some(since), kinds, limit);
+            if !events.is_empty() {
+                return (events, next);
+            }
+            since = next;
+            if tokio::time::timeout_at(deadline, latest.changed()).await.is_err() {
+                return (events, next);
+            }
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_filters_pages_and_waits() {
+        let log = EventLog::default();
+        log.record("peer", "connected", Some("laptop"), None, "10.0.0.2:8080");
+        log.record("eviction", "evicted", None, Some(4096), "2 cache blocks");
+        log.record("peer", "disconnected", Some("laptop"), None, "connection closed");
+
+        let (latest, next) = log.query(None, None, 2);
+        assert_eq!(latest.iter().map(|e| e.seq).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(next, 3);
+        let peers = ["peer".to_string()];
+        let (page, next) = log.query(Some(0), Some(&peers), 1);
+        assert_eq!((page[0].action.as_str(), next), ("connected", 1));
+        let (page, next) = log.query(Some(next), Some(&peers), 10);
+        assert_eq!((page[0].action.as_str(), next), ("disconnected", 3));
+
+        // Nothing new yet: the wait ends with the next matching event
+        let flush = ["flush".to_string()];
+        let (none, next) = log.wait_query(3, Some(&flush), 10, Duration::from_millis(50)).await;
+        assert!(none.is_empty() && next == 3);
+        let waiter = log.wait_query(3, Some(&flush), 10, Duration::from_secs(5));
+        let record = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            log.record("eviction", "expired", None, Some(10), "1 TTL block");
+            log.record("flush", "flushed", None, Some(100), "pattern tmp:*");
+        };
+        let ((events, next), ()) = tokio::join!(waiter, record);
+        assert_eq!(events.iter().map(|e| e.seq).collect::<Vec<_>>(), [5]);
+        assert_eq!(next, 5);
+    }
+
+    #[test]
+    fn test_keeps_the_latest_events() {
+        let log = EventLog::default();
+        for i in 0..EVENTS_KEPT + 5 {
+            log.record("flush", "flushed", None, Some(i as u64), "");
+        }
+        let (events, next) = log.query(Some(0), None, usize::MAX);
+        assert_eq!(events.len(), EVENTS_KEPT);
+        assert_eq!((events[0].seq, next), (6, (EVENTS_KEPT + 5) as u64));
+    }
+}
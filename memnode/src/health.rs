@@ -0,0 +1,143 @@
+//! Node self-checks behind the `Ping` RPC: long-running tasks are spawned
+//! through a supervisor that records when they exit or panic, and components
+//! without a task of their own register a check.
+
+use dashmap::DashMap;
+use log::{error, warn};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::task::JoinHandle;
+
+/// A component check: `Err` carries what is wrong with it.
+pub type Check = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum TaskState {
+    Running,
+    Exited,
+    Panicked(String),
+}
+
+pub struct Health {
+    started: Instant,
+    tasks: Arc<DashMap<&'static str, TaskState>>,
+    checks: Mutex<Vec<(&'static str, Check)>>,
+    // Set on shutdown, when tasks ending is expected
+    stopping: Arc<AtomicBool>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            tasks: Arc::new(DashMap::new()),
+            checks: Mutex::new(Vec::new()),
+            stopping: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    /// Spawn a task that is meant to run for the life of the node. The returned
+    /// handle completes once the task has ended and its fate has been recorded.
+    pub fn supervise<F>(&self, name: &'static str, task: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.insert(name, TaskState::Running);
+        let tasks = self.tasks.clone();
+        let stopping = self.stopping.clone();
+        let handle = tokio::spawn(task);
+        tokio::spawn(async move {
+            let state = match handle.await {
+                Err(e) if e.is_panic() => {
+                    let payload = e.into_panic();
+                    let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    error!("Background task '{}' panicked: {}", name, msg);
+                    TaskState::Panicked(msg)
+                }
+                _ => {
+                    if !stopping.load(Ordering::Relaxed) {
+                        warn!("Background task '{}' exited", name);
+                    }
+                    TaskState::Exited
+                }
+            };
+            tasks.insert(name, state);
+        })
+    }
+
+    /// The node is shutting down; supervised tasks ending is no longer worth a warning.
+    pub fn set_stopping(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    pub fn add_check(&self, name: &'static str, check: Check) {
+        self.checks.lock().unwrap().push((name, check));
+    }
+
+    /// Run every check and collect one line per component. The flag is false
+    /// if anything failed. Checks may block briefly, so they run off the runtime.
+    pub async fn report(&self) -> (bool, Vec<String>) {
+        // Answering this at all means the RPC loop is alive
+        let mut healthy = true;
+        let mut details = vec!["rpc: ok".to_string()];
+
+        let mut tasks: Vec<(&'static str, TaskState)> = self.tasks.iter().map(|e| (*e.key(), e.value().clone())).collect();
+        tasks.sort_by_key(|(name, _)| *name);
+        for (name, state) in tasks {
+            healthy &= state == TaskState::Running;
+            details.push(match state {
+                TaskState::Running => format!("{}: ok", name),
+                TaskState::Exited => format!("{}: task exited", name),
+                TaskState::Panicked(msg) => format!("{}: task panicked: {}", name, msg),
+            });
+        }
+
+        let checks = self.checks.lock().unwrap().clone();
+        let results = tokio::task::spawn_blocking(move || {
+            checks.into_iter().map(|(name, check)| (name, check())).collect::<Vec<_>>()
+        }).await.unwrap_or_default();
+        for (name, result) in results {
+            match result {
+                Ok(()) => details.push(format!("{}: ok", name)),
+                Err(e) => {
+                    healthy = false;
+                    details.push(format!("{}: {}", name, e));
+                }
+            }
+        }
+        (healthy, details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_flags_dead_tasks_and_failed_checks() {
+        let health = Health::new();
+        let _running = health.supervise("listener", std::future::pending());
+        assert_eq!(health.report().await, (true, vec!["rpc: ok".to_string(), "listener: ok".to_string()]));
+
+        health.supervise("worker", async { panic!("boom") }).await.unwrap();
+        health.add_check("disk", Arc::new(|| Err("unplugged".to_string())));
+        let (healthy, details) = health.report().await;
+        assert!(!healthy);
+        assert!(details.contains(&"worker: task panicked: boom".to_string()));
+        assert!(details.contains(&"disk: unplugged".to_string()));
+    }
+}
@@ -0,0 +1,23 @@
+//! The MemCloud node. The `memnode` binary is a thin wrapper around [`Node`];
+//! embedders and integration tests start nodes in-process the same way.
+
+mod blocks;
+mod discovery;
+mod peers;
+mod net;
+mod metadata;
+mod rpc;
+mod health;
+mod node;
+mod reload;
+mod trace;
+mod events;
+pub mod config;
+pub mod logging;
+pub mod daemon;
+#[cfg(test)]
+mod test_support;
+#[cfg(feature = "test-support")]
+pub mod testing;
+
+pub use node::{Node, NodeBuilder};
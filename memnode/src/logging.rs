@@ -0,0 +1,283 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use log::info;
+use crate::config::{LogFormat, NodeConfig};
+
+// Used when RUST_LOG is not set
+const DEFAULT_FILTER: &str = "info";
+// mDNS logs are suppressed to avoid "No route to host" spam on macOS
+const QUIET_MODULE: &str = "mdns_sd";
+
+/// Log file owned by the node. Rotates itself once it grows past `max_size`
+/// (`memnode.log` -> `memnode.log.1` -> ... -> `memnode.log.N`) and can be
+/// reopened on SIGHUP after an external tool (logrotate) has moved it away.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_size: u64,
+    generations: u32,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, generations: u32) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(path)?;
+        let written = file.metadata()?.len();
+        let rf = Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+            max_size,
+            generations,
+        };
+        rf.redirect_stdio();
+        Ok(rf)
+    }
+
+    /// Reopen the log path, picking up a fresh file if it was moved away.
+    pub fn reopen(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file = open_append(&self.path)?;
+        self.written = self.file.metadata()?.len();
+        self.redirect_stdio();
+        Ok(())
+    }
+
+    /// Shift `path.{n}` to `path.{n+1}`, dropping the oldest, then start a new file.
+    pub fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+        if self.generations == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = generation_path(&self.path, self.generations);
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for n in (1..self.generations).rev() {
+                let from = generation_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, generation_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, generation_path(&self.path, 1))?;
+        }
+        self.reopen()
+    }
+
+    // Point stdout/stderr at the current file so panics and stray prints
+    // follow the rotation instead of writing into an already-rotated file.
+    #[cfg(unix)]
+    fn redirect_stdio(&self) {
+        #[cfg(not(test))]
+        {
+            use std::os::fd::AsRawFd;
+            let fd = self.file.as_raw_fd();
+            let _ = nix::unistd::dup2(fd, 1);
+            let _ = nix::unistd::dup2(fd, 2);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn redirect_stdio(&self) {}
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_size > 0 && self.written + buf.len() as u64 > self.max_size && self.written > 0 {
+            self.rotate().map_err(io::Error::other)?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Cloneable handle so the logger and the SIGHUP task can share the file.
+#[derive(Clone)]
+pub struct LogHandle(Arc<Mutex<RotatingFile>>);
+
+impl LogHandle {
+    pub fn new(file: RotatingFile) -> Self {
+        Self(Arc::new(Mutex::new(file)))
+    }
+
+    pub fn reopen(&self) -> Result<()> {
+        self.0.lock().unwrap().reopen()
+    }
+}
+
+impl Write for LogHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+/// Reopen the log file whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn spawn_sighup_reopen(handle: LogHandle) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            match handle.reopen() {
+                Ok(_) => info!("Received SIGHUP, reopened log file"),
+                Err(e) => eprintln!("Failed to reopen log file on SIGHUP: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_reopen(_handle: LogHandle) -> Result<()> {
+    Ok(())
+}
+
+fn open_log(config: &NodeConfig) -> Result<Option<LogHandle>> {
+    match &config.log_file {
+        Some(path) => Ok(Some(LogHandle::new(RotatingFile::open(path, config.log_max_size, config.log_generations)?))),
+        None => Ok(None),
+    }
+}
+
+/// Install the logger `config` asks for. Records from the `log` macros and
+/// spans alike are formatted by a tracing subscriber, filtered by RUST_LOG
+/// the way env_logger would. Returns the log file, if there is one.
+#[cfg(feature = "tracing")]
+pub fn init(config: &NodeConfig) -> Result<Option<LogHandle>> {
+    use tracing_subscriber::layer::{Layer, SubscriberExt};
+    use tracing_subscriber::{fmt, Registry};
+
+    let mut filter = env_filter::Builder::new();
+    filter.parse(&std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string()));
+    filter.filter_module(QUIET_MODULE, log::LevelFilter::Off);
+    // With log_level set, verbosity is the global max level so ReloadConfig can change it
+    if config.log_level.is_some() {
+        filter.filter_level(log::LevelFilter::Trace);
+    }
+    let filter = filter.build();
+    let max_level = config.log_level.unwrap_or(filter.filter());
+
+    let handle = open_log(config)?;
+    let file = handle.clone();
+    let writer = move || -> Box<dyn Write> {
+        match &file {
+            Some(file) => Box::new(file.clone()),
+            None => Box::new(io::stderr()),
+        }
+    };
+    let layer = fmt::layer().with_ansi(false).with_writer(writer);
+    let layer: Box<dyn Layer<Registry> + Send + Sync> = match config.log_format {
+        LogFormat::Text => Box::new(layer),
+        LogFormat::Json => Box::new(layer.json()),
+    };
+    let layer = layer.with_filter(tracing_subscriber::filter::filter_fn(move |meta| {
+        use tracing_log::AsLog;
+        let level = meta.level().as_log();
+        level <= log::max_level() && filter.enabled(&log::Metadata::builder().level(level).target(meta.target()).build())
+    }));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+    tracing_log::LogTracer::init()?;
+    log::set_max_level(max_level);
+    Ok(handle)
+}
+
+/// Install env_logger as `config` asks. Returns the log file, if there is one.
+#[cfg(not(feature = "tracing"))]
+pub fn init(config: &NodeConfig) -> Result<Option<LogHandle>> {
+    if config.log_format == LogFormat::Json {
+        anyhow::bail!("--log-format json needs memnode built with the `tracing` feature");
+    }
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(DEFAULT_FILTER));
+    logger.filter_module(QUIET_MODULE, log::LevelFilter::Off);
+    // With log_level set, verbosity is the global max level so ReloadConfig can change it
+    if config.log_level.is_some() {
+        logger.filter_level(log::LevelFilter::Trace);
+    }
+    let handle = open_log(config)?;
+    if let Some(file) = &handle {
+        logger.target(env_logger::Target::Pipe(Box::new(file.clone())));
+    }
+    logger.init();
+    if let Some(level) = config.log_level {
+        log::set_max_level(level);
+    }
+    Ok(handle)
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn generation_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("memnode-log-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rotates_past_threshold() {
+        let dir = temp_log_dir();
+        let path = dir.join("memnode.log");
+        let mut f = RotatingFile::open(&path, 100, 2).unwrap();
+
+        let line = [b'x'; 60];
+        f.write_all(&line).unwrap();
+        assert!(!generation_path(&path, 1).exists());
+
+        // Crosses 100 bytes -> first rotation
+        f.write_all(&line).unwrap();
+        assert!(generation_path(&path, 1).exists());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 60);
+
+        // Two more rotations; only two generations are kept
+        f.write_all(&line).unwrap();
+        f.write_all(&line).unwrap();
+        assert!(generation_path(&path, 2).exists());
+        assert!(!generation_path(&path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_after_external_move() {
+        let dir = temp_log_dir();
+        let path = dir.join("memnode.log");
+        let mut f = RotatingFile::open(&path, 0, 3).unwrap();
+        f.write_all(b"before\n").unwrap();
+
+        // Simulate logrotate moving the file away
+        fs::rename(&path, dir.join("moved.log")).unwrap();
+        f.reopen().unwrap();
+        f.write_all(b"after\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+        assert_eq!(fs::read_to_string(dir.join("moved.log")).unwrap(), "before\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,74 +1,39 @@
-mod blocks;
-mod discovery;
-mod peers;
-mod net;
-mod metadata;
-mod rpc;
-
-use log::{info, error};
-use uuid::Uuid;
-use clap::Parser;
-use std::sync::Arc;
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(short, long, default_value_t = 8080)]
-    port: u16,
-
-    #[arg(short, long, value_parser = memsdk::parse_size, default_value = "1gb")]
-    memory: u64,
-
-    #[arg(long, default_value = "/tmp/memcloud.sock")]
-    socket: String,
-
-    #[arg(long, default_value = "Unnamed Node")]
-    name: String,
+use log::info;
+use memnode::{config, daemon, logging, NodeBuilder};
+
+fn main() -> anyhow::Result<()> {
+    let args = config::Args::load()?;
+    // Forking has to happen before the runtime starts its threads
+    let readiness = if args.daemonize {
+        Some(daemon::daemonize(args.log_file.as_deref())?)
+    } else {
+        None
+    };
+    tokio::runtime::Runtime::new()?.block_on(run(args, readiness))
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize logger with mDNS logs suppressed to avoid "No route to host" spam on macOS
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .filter_module("mdns_sd", log::LevelFilter::Off)
-        .init();
-    let args = Args::parse();
-    let node_id = Uuid::new_v4();
-
-
-
-    // 1. Init PeerManager
-    let peer_manager = Arc::new(peers::PeerManager::new(node_id, args.name.clone()));
-
-    // 4. Initialize Block Manager
-    let block_manager = Arc::new(blocks::InMemoryBlockManager::new(peer_manager.clone(), args.memory));
+async fn run(args: config::NodeConfig, readiness: Option<daemon::Readiness>) -> anyhow::Result<()> {
+    let log_handle = logging::init(&args)?;
+    if let Some(handle) = log_handle {
+        logging::spawn_sighup_reopen(handle)?;
+    }
 
-    // 3. Start RPC Server
-    let rpc_server = rpc::RpcServer::new(&args.socket, block_manager.clone());
-    let rpc_handle = tokio::spawn(async move {
-        if let Err(e) = rpc_server.run().await {
-            error!("RPC Server failed: {}", e);
-        }
-    });
+    let pid_file = args.pid_file.clone();
+    let mut node = NodeBuilder::from_config(args).spawn().await?;
 
-    // 4. Start Transport Listener
-    let (transport, actual_port) = net::TransportServer::bind(args.port, block_manager.clone(), peer_manager.clone()).await?;
-    
-    if actual_port != args.port {
-        info!("Required port {} was busy, bound to {} instead", args.port, actual_port);
+    // Startup succeeded: publish the pid and release `memnode --daemonize`
+    let _pid_file = pid_file.as_deref().map(daemon::PidFile::create).transpose()?;
+    if let Some(readiness) = readiness {
+        readiness.notify();
     }
-    info!("Starting MemCloud Node {} on port {}", node_id, actual_port);
-
-    // 5. Start Discovery (mDNS)
-    let discovery = discovery::MdnsDiscovery::new(node_id, actual_port, peer_manager.clone(), block_manager.clone(), args.memory)?;
-    discovery.start_advertising()?;
-    discovery.start_browsing()?;
 
-    // 6. Run Transport Loop
     tokio::select! {
-        _ = transport.run() => {},
-        _ = rpc_handle => {},
+        _ = node.stopped() => {},
+        _ = daemon::shutdown_signal() => {
+            info!("Shutting down, saying Bye to peers");
+        }
     }
+    node.shutdown().await;
 
     Ok(())
 }
@@ -4,16 +4,55 @@ use x25519_dalek::{EphemeralSecret, PublicKey as XPublicKey, StaticSecret};
 use rand::rngs::OsRng;
 use anyhow::{Result, bail, Context};
 use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 use crate::peers::PeerMetadata;
+use super::secure_stream::{SecureReader, SecureWriter, SessionCounters};
 use super::transcript::Transcript;
-use crate::peers::trusted::TrustedStore;
+use crate::peers::trusted::{PinCheck, PinnedPeer, TrustedStore};
+use crate::peers::DialPolicy;
 use crate::peers::consent::{ConsentManager, ConsentDecision};
 use std::sync::Arc;
 use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use chacha20poly1305::aead::{Aead, AeadCore, KeyInit};
-use log::{info, error};
+use log::{info, error, warn};
+
+/// Version this node announces in its `Hello`.
+pub const PROTOCOL_VERSION: u16 = 8;
+
+/// Oldest version whose messages this node still encodes the same way, raised
+/// whenever an existing message changes shape. Peers announcing an older one
+/// are refused; newer ones are accepted and refuse us themselves if we are
+/// too old for them. Each side only sends the other messages its version knows.
+pub const MIN_PROTOCOL_VERSION: u16 = 7;
+
+/// First version that answers `StatRequest`. Older peers cannot decode it
+/// and would drop the connection, so they are never sent one.
+pub const STATS_VERSION: u16 = 3;
+
+/// First version that reports `HeldForYou` after answering a `Ping`, and
+/// understands it.
+pub const USAGE_VERSION: u16 = 5;
+
+/// First version that understands `FlushRefused`. Older peers are sent an
+/// empty `FlushAck` instead.
+pub const FLUSH_REFUSAL_VERSION: u16 = 8;
+
+/// What every session is set up with: X25519 key agreement, Ed25519 identity
+/// signatures and ChaCha20-Poly1305 frames.
+pub const SESSION_SUITE: &[&str] = &["x25519", "ed25519", "chacha20-poly1305"];
+
+/// The suite plus the optional messages a peer announcing `version` answers.
+pub fn capabilities(version: u16) -> Vec<String> {
+    let mut caps: Vec<String> = SESSION_SUITE.iter().map(|s| s.to_string()).collect();
+    if version >= STATS_VERSION {
+        caps.push("stats".to_string());
+    }
+    if version >= USAGE_VERSION {
+        caps.push("usage".to_string());
+    }
+    caps
+}
 
 // --- Wire Messages ---
 
@@ -23,6 +62,13 @@ pub enum HandshakeMessage {
     Auth(Vec<u8>), // Encrypted HandshakeAuth
     ConsentRequired { reason: String },
     ConsentDenied,
+    /// The initiator turned out to be the responder itself
+    SelfConnection,
+    /// The initiator uses the responder's node id with a different key
+    DuplicateNodeId,
+    /// The initiator announced a protocol version older than the responder
+    /// accepts; `version` is the one the responder speaks
+    VersionMismatch { version: u16 },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -63,18 +109,145 @@ impl Identity {
         }
     }
     
+    /// The identity saved in `path`, or a new one saved there. Peers pin the
+    /// key they first see, so it has to stay the same across restarts.
+    pub fn load_or_create(path: &std::path::Path, name: String) -> Result<Self> {
+        if path.exists() {
+            let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let saved: SavedIdentity = serde_json::from_str(&content).with_context(|| format!("Invalid identity file {}", path.display()))?;
+            let secret: [u8; 32] = hex::decode(&saved.secret_key).ok().and_then(|k| k.try_into().ok())
+                .with_context(|| format!("Invalid secret key in {}", path.display()))?;
+            return Ok(Self { keypair: SigningKey::from_bytes(&secret), node_id: saved.node_id, name });
+        }
+
+        let identity = Self::new(Uuid::new_v4(), name);
+        let saved = SavedIdentity { node_id: identity.node_id, secret_key: hex::encode(identity.keypair.to_bytes()) };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        std::io::Write::write_all(&mut file, serde_json::to_string_pretty(&saved)?.as_bytes())?;
+        info!("Created node identity {} in {}", identity.node_id, path.display());
+        Ok(identity)
+    }
+    
     pub fn public_key(&self) -> VerifyingKey {
         self.keypair.verifying_key()
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct SavedIdentity {
+    node_id: Uuid,
+    secret_key: String,
+}
+
 pub struct Session {
     pub send_key: [u8; 32],
     pub recv_key: [u8; 32],
     pub peer_id: Uuid,
     pub peer_name: String,
+    /// Hex-encoded ed25519 key the peer authenticated with
+    pub peer_public_key: String,
     pub peer_quota: u64,
     pub peer_total_memory: u64,
+    /// Version the peer announced in its `Hello`
+    pub peer_version: u16,
+    /// Whether this node dialed the peer
+    pub initiated: bool,
+    /// Traffic of the session, once `split` has set it up
+    pub counters: Arc<SessionCounters>,
+}
+
+impl Session {
+    /// The encrypted halves of the connection the handshake ran on, counting
+    /// their traffic in `counters`.
+    pub fn split(&self, stream: TcpStream, max_frame: usize) -> (SecureReader, SecureWriter) {
+        let (reader, writer) = stream.into_split();
+        (
+            SecureReader::new(reader, &self.recv_key, max_frame).counting(self.counters.clone()),
+            SecureWriter::from_raw(writer, &self.send_key).counting(self.counters.clone()),
+        )
+    }
+}
+
+/// How an outgoing handshake checks the key the responder presents against
+/// the keys pinned on earlier connections.
+pub struct KeyPinning<'a> {
+    pub store: &'a TrustedStore,
+    pub addr: std::net::SocketAddr,
+    pub policy: DialPolicy,
+}
+
+/// The responder presented a different key than the one pinned for its node
+/// or address. Either it was reinstalled, or something is impersonating it.
+#[derive(Debug)]
+pub struct IdentityChanged {
+    pub addr: String,
+    pub pinned: PinnedPeer,
+    pub presented: String,
+}
+
+impl std::fmt::Display for IdentityChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Peer identity changed at {}: '{}' ({}) was pinned to key {} but presented {}",
+            self.addr, self.pinned.name, self.pinned.node_id, self.pinned.public_key, self.presented)
+    }
+}
+
+impl std::error::Error for IdentityChanged {}
+
+/// The handshake reached this node itself, e.g. `memcli connect` to one of
+/// its own addresses. Nothing is registered.
+#[derive(Debug)]
+pub struct ConnectedToSelf;
+
+impl std::fmt::Display for ConnectedToSelf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connected to this node itself; give the address of another node")
+    }
+}
+
+impl std::error::Error for ConnectedToSelf {}
+
+fn version_mismatch(peer_version: u16) -> anyhow::Error {
+    anyhow::anyhow!("Peer speaks protocol version {} but this node speaks {} and accepts {} or later; upgrade the older node", peer_version, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION)
+}
+
+fn cloned_identity(node_id: Uuid) -> anyhow::Error {
+    anyhow::anyhow!("Peer uses this node's id {} with a different key; was one node cloned from the other (copied identity.json)?", node_id)
+}
+
+impl KeyPinning<'_> {
+    fn verify(&self, node_id: Uuid, name: &str, public_key: &str) -> Result<()> {
+        let addr = self.addr.to_string();
+        match self.store.check_pin(node_id, &addr, public_key) {
+            PinCheck::Matches => {}
+            PinCheck::Changed(pinned) if !self.policy.accept_new_key => {
+                return Err(IdentityChanged { addr, pinned, presented: public_key.to_string() }.into());
+            }
+            PinCheck::Changed(pinned) => {
+                warn!("Replacing pinned key {} of '{}' at {} with {}", pinned.public_key, pinned.name, addr, public_key);
+            }
+            PinCheck::Unknown if self.policy.require_known && !self.store.is_known(public_key) => {
+                bail!("Peer '{}' at {} is not trusted", name, addr);
+            }
+            PinCheck::Unknown => {
+                info!("First connection to '{}' at {}; pinning its key {}", name, addr, public_key);
+            }
+        }
+        if let Err(e) = self.store.pin(node_id, &addr, name, public_key) {
+            warn!("Failed to save the pinned key of '{}': {}", name, e);
+        }
+        if let Err(e) = self.store.record_connection(public_key, &addr, true) {
+            warn!("Failed to record the connection to '{}': {}", name, e);
+        }
+        Ok(())
+    }
 }
 
 // --- Handshake Implementation ---
@@ -84,6 +257,7 @@ pub async fn handshake_initiator(
     identity: &Identity,
     ram_quota: u64,
     total_memory: u64,
+    pinning: Option<&KeyPinning<'_>>,
     mut on_consent_required: impl FnMut(),
 ) -> Result<Session> {
     let mut transcript = Transcript::new("MemCloud-v2");
@@ -93,7 +267,7 @@ pub async fn handshake_initiator(
     let nonce_a: [u8; 32] = rand::random();
 
     let hello_a = HandshakeHello {
-        version: 2,
+        version: PROTOCOL_VERSION,
         nonce: nonce_a,
         eph_pub: *eph_pub.as_bytes(),
         quota: ram_quota,
@@ -102,15 +276,19 @@ pub async fn handshake_initiator(
     send_msg(stream, &HandshakeMessage::Hello(hello_a)).await?;
     
     let hello_bytes = bincode::serialize(&HandshakeMessage::Hello(HandshakeHello {
-        version: 2, nonce: nonce_a, eph_pub: *eph_pub.as_bytes(), quota: ram_quota, total_memory
+        version: PROTOCOL_VERSION, nonce: nonce_a, eph_pub: *eph_pub.as_bytes(), quota: ram_quota, total_memory
     }))?;
     transcript.mix("hello_a", &hello_bytes);
 
     let msg = recv_msg(stream).await?;
     let (hello_b_bytes, hello_b) = match msg {
         (b, HandshakeMessage::Hello(h)) => (b, h),
+        (_, HandshakeMessage::VersionMismatch { version }) => return Err(version_mismatch(version)),
         (_, m) => bail!("Expected Hello, got {:?}", m),
     };
+    if hello_b.version < MIN_PROTOCOL_VERSION {
+        return Err(version_mismatch(hello_b.version));
+    }
     transcript.mix("hello_b", &hello_b_bytes);
 
     let eph_pub_b = XPublicKey::from(hello_b.eph_pub);
@@ -154,6 +332,8 @@ pub async fn handshake_initiator(
             (b, HandshakeMessage::ConsentDenied) => {
                 bail!("Connection rejected by peer user.");
             }
+            (_, HandshakeMessage::SelfConnection) => return Err(ConnectedToSelf.into()),
+            (_, HandshakeMessage::DuplicateNodeId) => return Err(cloned_identity(identity.node_id)),
             (b, HandshakeMessage::Auth(c)) => {
                 // This is effectively "Granted"
                 msg = (b, HandshakeMessage::Auth(c));
@@ -183,6 +363,18 @@ pub async fn handshake_initiator(
     peer_key.verify(&transcript.current_hash(), &peer_signature)
         .context("Peer signature verification failed")?;
 
+    let peer_public_key = hex::encode(auth_b.pub_key);
+    if auth_b.pub_key == identity.public_key().to_bytes() {
+        return Err(ConnectedToSelf.into());
+    }
+    if auth_b.node_id == identity.node_id {
+        error!("Peer '{}' presented this node's id {} with key {}", auth_b.name, identity.node_id, peer_public_key);
+        return Err(cloned_identity(identity.node_id));
+    }
+    if let Some(pinning) = pinning {
+        pinning.verify(auth_b.node_id, &auth_b.name, &peer_public_key)?;
+    }
+
     transcript.mix("auth_b", &auth_b_msg_bytes);
 
     let final_hash = transcript.current_hash();
@@ -194,8 +386,12 @@ pub async fn handshake_initiator(
         recv_key, // Initiator (A) recvs with Key B
         peer_id: auth_b.node_id,
         peer_name: auth_b.name,
+        peer_public_key,
         peer_quota: hello_b.quota,
         peer_total_memory: hello_b.total_memory,
+        peer_version: hello_b.version,
+        initiated: true,
+        counters: Arc::default(),
     })
 }
 
@@ -214,6 +410,10 @@ pub async fn handshake_responder(
         (b, HandshakeMessage::Hello(h)) => (b, h),
         (_, m) => bail!("Expected Hello, got {:?}", m),
     };
+    if hello_a.version < MIN_PROTOCOL_VERSION {
+        send_msg(stream, &HandshakeMessage::VersionMismatch { version: PROTOCOL_VERSION }).await?;
+        return Err(version_mismatch(hello_a.version));
+    }
     transcript.mix("hello_a", &hello_a_bytes);
 
     let eph_pub_a = XPublicKey::from(hello_a.eph_pub);
@@ -223,7 +423,7 @@ pub async fn handshake_responder(
     let nonce_b: [u8; 32] = rand::random();
     
     let hello_b = HandshakeHello {
-        version: 2,
+        version: PROTOCOL_VERSION,
         nonce: nonce_b,
         eph_pub: *eph_pub.as_bytes(),
         quota: ram_quota,
@@ -232,7 +432,7 @@ pub async fn handshake_responder(
     send_msg(stream, &HandshakeMessage::Hello(hello_b)).await?;
     
     let hello_b_bytes = bincode::serialize(&HandshakeMessage::Hello(HandshakeHello {
-        version: 2, nonce: nonce_b, eph_pub: *eph_pub.as_bytes(), quota: ram_quota, total_memory
+        version: PROTOCOL_VERSION, nonce: nonce_b, eph_pub: *eph_pub.as_bytes(), quota: ram_quota, total_memory
     }))?;
     transcript.mix("hello_b", &hello_b_bytes);
 
@@ -260,16 +460,36 @@ pub async fn handshake_responder(
         .context("Peer signature verification failed")?;
 
     let peer_pub_key_hex = hex::encode(auth_a.pub_key);
-    if !trusted_store.is_trusted(&peer_pub_key_hex) {
+    // Checked before consent, so nobody is asked to approve this node itself
+    if auth_a.pub_key == identity.public_key().to_bytes() {
+        send_msg(stream, &HandshakeMessage::SelfConnection).await?;
+        return Err(ConnectedToSelf.into());
+    }
+    if auth_a.node_id == identity.node_id {
+        error!("Peer '{}' presented this node's id {} with key {}", auth_a.name, identity.node_id, peer_pub_key_hex);
+        send_msg(stream, &HandshakeMessage::DuplicateNodeId).await?;
+        return Err(cloned_identity(identity.node_id));
+    }
+    if consent_manager.auto_approve() {
+        info!("Peer {} ({}) accepted without consent (trust_all)", auth_a.name, peer_pub_key_hex);
+    } else if !trusted_store.is_trusted(&peer_pub_key_hex) {
         info!("Peer {} ({}) is unknown. Requesting consent...", auth_a.name, peer_pub_key_hex);
         
         send_msg(stream, &HandshakeMessage::ConsentRequired { reason: "untrusted_peer".to_string() }).await?;
 
         let session_id = Uuid::new_v4().to_string();
-        consent_manager.request_consent(session_id.clone(), peer_pub_key_hex.clone(), auth_a.name.clone(), hello_a.quota);
+        consent_manager.request_consent(session_id.clone(), peer_pub_key_hex.clone(), auth_a.name.clone(), hello_a.quota, ram_quota);
         
-        // Wait
-        let decision = consent_manager.wait_for_decision(&session_id).await;
+        // The initiator sends nothing until we answer, so anything readable
+        // (normally EOF) means it gave up, e.g. `memcli connect --cancel`
+        let mut probe = [0u8; 1];
+        let decision = tokio::select! {
+            decision = consent_manager.wait_for_decision(&session_id) => decision,
+            _ = stream.peek(&mut probe) => {
+                consent_manager.withdraw(&session_id);
+                bail!("Peer {} went away while waiting for consent", auth_a.name);
+            }
+        };
         
         match decision {
             ConsentDecision::ApprovedOnce => {
@@ -277,7 +497,8 @@ pub async fn handshake_responder(
             }
             ConsentDecision::ApprovedAndTrusted => {
                 info!("Consent granted (trusted) for {}", auth_a.name);
-                trusted_store.add_trusted(peer_pub_key_hex, auth_a.name.clone())?;
+                trusted_store.add_trusted(peer_pub_key_hex.clone(), auth_a.name.clone(), None)?;
+                consent_manager.events.record("trust", "added", Some(&auth_a.name), None, "approved with trust when it connected");
             }
             ConsentDecision::Denied | ConsentDecision::Pending => {
                 info!("Consent denied for {}", auth_a.name);
@@ -314,14 +535,23 @@ pub async fn handshake_responder(
     let final_hash = transcript.current_hash();
     let send_key = derive_key("traffic_b", &shared_secret.to_bytes(), &final_hash); // B sends on Key B
     let recv_key = derive_key("traffic_a", &shared_secret.to_bytes(), &final_hash); // B recvs on Key A
+
+    let addr = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    if let Err(e) = trusted_store.record_connection(&peer_pub_key_hex, &addr, false) {
+        warn!("Failed to record the connection from '{}': {}", auth_a.name, e);
+    }
     
     Ok(Session {
         send_key,
         recv_key,
         peer_id: auth_a.node_id,
         peer_name: auth_a.name,
+        peer_public_key: peer_pub_key_hex,
         peer_quota: hello_a.quota,
         peer_total_memory: hello_a.total_memory,
+        peer_version: hello_a.version,
+        initiated: false,
+        counters: Arc::default(),
     })
 }
 
@@ -338,11 +568,8 @@ fn derive_key(label: &str, shared: &[u8], context: &[u8]) -> [u8; 32] {
 
 // Helper that returns raw bytes + deserialized msg for mixing
 async fn recv_msg(stream: &mut TcpStream) -> Result<(Vec<u8>, HandshakeMessage)> {
-    let mut len_buf = [0u8; 4];
-    stream.read_exact(&mut len_buf).await?;
-    let len = u32::from_be_bytes(len_buf) as usize;
-    let mut buf = vec![0u8; len];
-    stream.read_exact(&mut buf).await?;
+    // Nothing is authenticated yet, so keep the limit small
+    let buf = super::frame::read_frame(stream, super::frame::MAX_HANDSHAKE_FRAME).await?;
     let msg: HandshakeMessage = bincode::deserialize(&buf)?;
     Ok((buf, msg))
 }
@@ -0,0 +1,172 @@
+//! Length-prefixed frames as read off peer, handshake and RPC connections: a
+//! big-endian `u32` length, then that many bytes.
+//!
+//! The length comes from the other side, so it is checked against a limit for
+//! the channel before anything is allocated, and the buffer only grows as
+//! bytes actually arrive. A corrupt or hostile prefix costs nothing.
+
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// Default limit for peer data frames and RPC frames.
+pub const DEFAULT_MAX_FRAME: usize = 256 * 1024 * 1024;
+/// Room a `BlockData` or `KeyFound` frame needs besides the value itself.
+pub const BLOCK_FRAME_OVERHEAD: usize = 64 * 1024;
+/// Handshake messages carry keys, names and signatures only.
+pub const MAX_HANDSHAKE_FRAME: usize = 64 * 1024;
+// Buffer reserved up front; larger frames grow it as their bytes arrive
+const INITIAL_BUFFER: usize = 64 * 1024;
+
+/// A frame declared a length over the limit for its channel. The connection
+/// cannot be resynchronised after this and should be closed.
+#[derive(Debug)]
+pub struct FrameTooLarge {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl std::fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Frame of {} bytes exceeds the {} byte limit", self.len, self.max)
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+/// Fails reads with `TimedOut` once `idle` passes without a byte arriving.
+/// Every byte that does arrive restarts the wait, so a large frame that keeps
+/// coming in on a slow link is not cut off part-way.
+pub struct IdleTimeout<R> {
+    inner: R,
+    idle: Duration,
+    // When a byte last arrived; the sleep is only moved once it runs out
+    last_read: tokio::time::Instant,
+    sleep: Pin<Box<tokio::time::Sleep>>,
+}
+
+impl<R> IdleTimeout<R> {
+    pub fn new(inner: R, idle: Duration) -> Self {
+        Self { inner, idle, last_read: tokio::time::Instant::now(), sleep: Box::pin(tokio::time::sleep(idle)) }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for IdleTimeout<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        if let Poll::Ready(res) = Pin::new(&mut self.inner).poll_read(cx, buf) {
+            if buf.filled().len() > before {
+                self.last_read = tokio::time::Instant::now();
+            }
+            return Poll::Ready(res);
+        }
+        loop {
+            ready!(self.sleep.as_mut().poll(cx));
+            let deadline = self.last_read + self.idle;
+            if deadline <= tokio::time::Instant::now() {
+                return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("nothing received for {:?}", self.idle))));
+            }
+            self.sleep.as_mut().reset(deadline);
+        }
+    }
+}
+
+/// Whether a read failed because an [`IdleTimeout`] ran out.
+pub fn is_idle_timeout(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut)
+}
+
+/// Read one frame of at most `max` bytes.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, max: usize) -> Result<Vec<u8>> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf).await?;
+    read_frame_body(reader, u32::from_be_bytes(len_buf) as usize, max).await
+}
+
+/// Read the body of a frame whose length prefix has already been read.
+pub async fn read_frame_body<R: AsyncRead + Unpin>(reader: &mut R, len: usize, max: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    read_frame_body_into(reader, len, max, &mut buf).await?;
+    Ok(buf)
+}
+
+/// [`read_frame_body`] into `buf`, replacing its contents, so a connection can
+/// reuse one buffer for all its frames.
+pub async fn read_frame_body_into<R: AsyncRead + Unpin>(reader: &mut R, len: usize, max: usize, buf: &mut Vec<u8>) -> Result<()> {
+    if len > max {
+        return Err(FrameTooLarge { len, max }.into());
+    }
+    buf.clear();
+    let mut body = (&mut *reader).take(len as u64);
+    while buf.len() < len {
+        // Double as bytes arrive, but never past the end of the frame
+        if buf.len() == buf.capacity() {
+            buf.reserve_exact(buf.capacity().max(INITIAL_BUFFER).min(len - buf.len()));
+        }
+        if body.read_buf(buf).await? == 0 {
+            anyhow::bail!("Connection closed mid-frame ({} of {} bytes)", buf.len(), len);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_idle_timeout_restarts_as_bytes_arrive() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let mut reader = IdleTimeout::new(rx, Duration::from_millis(100));
+        let sender = tokio::spawn(async move {
+            let mut frame = 600u32.to_be_bytes().to_vec();
+            frame.extend([7u8; 600]);
+            // Longer than the idle timeout in total, but never quiet for that long
+            for piece in frame.chunks(60) {
+                tx.write_all(piece).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(40)).await;
+            }
+            tx
+        });
+        assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), [7u8; 600]);
+        let _tx = sender.await.unwrap();
+
+        let err = read_frame(&mut reader, 1024).await.unwrap_err();
+        assert!(is_idle_timeout(&err), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_random_prefixes_and_truncated_frames() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for _ in 0..500 {
+            let len: u32 = if rng.gen_bool(0.5) { rng.gen() } else { rng.gen_range(0..4096) };
+            let sent = rng.gen_range(0..=(len as usize).min(8192));
+            let mut input = len.to_be_bytes().to_vec();
+            input.extend(std::iter::repeat_n(0xAB, sent));
+            if rng.gen_bool(0.1) {
+                input.truncate(rng.gen_range(0..4));
+            }
+
+            let mut reader = &input[..];
+            match read_frame(&mut reader, 4096).await {
+                Ok(frame) => assert!(frame.len() == len as usize && frame.len() <= 4096),
+                Err(e) => {
+                    let too_large = e.downcast_ref::<FrameTooLarge>().is_some();
+                    assert_eq!(too_large, len > 4096 && input.len() >= 4, "{}", e);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lying_prefix_does_not_preallocate() {
+        let input = [0xFF, 0xFF, 0xFF, 0xFF, 1, 2, 3];
+        let err = read_frame(&mut &input[..], usize::MAX).await.unwrap_err();
+        assert!(err.to_string().contains("3 of 4294967295"), "{}", err);
+    }
+}
@@ -1,7 +1,10 @@
 pub mod auth;
 pub mod transcript;
 pub mod secure_stream;
+pub mod frame;
+pub mod outbound;
 
+use bytes::Bytes;
 use serde::{Serialize, Deserialize};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
@@ -9,8 +12,13 @@ use anyhow::Result;
 use log::{info, error};
 use std::net::SocketAddr;
 use crate::metadata::{BlockId, NodeId};
-use tokio::sync::Mutex;
+use crate::trace;
 
+// Block payloads are `Bytes`, sent straight from the stored block; bincode
+// encodes them exactly as it does a `Vec<u8>`. `trace_id` is the id of the
+// RPC request a message was sent for (see `crate::trace`); replies carry it back.
+// bincode numbers variants by position, so new ones go at the end, and any
+// change to the encoding bumps `auth::PROTOCOL_VERSION`.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message {
     Hello {
@@ -20,46 +28,179 @@ pub enum Message {
         total_memory: u64,
         used_memory: u64,
     },
+    /// Answered with BlockStored or PutBlockRejected.
+    /// `priority` ranks the block for eviction on the receiver, as it did
+    /// on the sender; `memsdk::DEFAULT_PRIORITY` when the sender gave none.
     PutBlock {
         id: BlockId,
-        data: Vec<u8>,
+        data: Bytes,
         durability: Option<memsdk::Durability>,
+        trace_id: Option<String>,
+        priority: Option<u8>,
     },
     GetBlock {
         id: BlockId,
+        trace_id: Option<String>,
     },
+    /// `durability` and `priority` are what the sender holds the block under.
     BlockData {
         id: BlockId,
-        data: Option<Vec<u8>>,
+        data: Option<Bytes>,
+        durability: Option<memsdk::Durability>,
+        trace_id: Option<String>,
+        priority: Option<u8>,
     },
     GetKey {
         key: String,
+        trace_id: Option<String>,
     },
     KeyFound {
         key: String,
-        data: Option<Vec<u8>>,
+        data: Option<Bytes>,
+        durability: Option<memsdk::Durability>,
+        trace_id: Option<String>,
+        priority: Option<u8>,
     },
     PutKey {
         key: String,
         data: Vec<u8>,
         durability: Option<memsdk::Durability>,
+        priority: Option<u8>,
     },
     KeyStored {
         key: String,
         id: BlockId,
     },
+    /// Answered with a QuotaAck carrying the same `request_id`.
     UpdateQuota {
         quota: u64,
+        request_id: u64,
     },
     Ack,
-    Flush,
+    /// Answered with FlushAck once the flush is done. The receiver removes
+    /// only blocks the sender stored there, unless the sender's trust record
+    /// allows it to flush everything.
+    Flush {
+        request_id: u64,
+        pattern: Option<String>,
+        durability: Option<memsdk::Durability>,
+    },
     Bye,
+    /// Reply to UpdateQuota with the value the peer actually applied (it may clamp).
+    QuotaAck {
+        request_id: u64,
+        accepted: bool,
+        quota: u64,
+    },
+    /// Reply to PutBlock when the block was not stored (quota, memory, or id collision).
+    PutBlockRejected {
+        id: BlockId,
+        reason: String,
+    },
+    /// Keepalive probe; answered with a Pong carrying the same nonce.
+    Ping {
+        nonce: u64,
+    },
+    Pong {
+        nonce: u64,
+    },
+    /// One piece of a migrated block; the receiver stores the block once
+    /// `total` bytes have arrived in order and answers BlockStored.
+    PutBlockChunk {
+        id: BlockId,
+        key: Option<String>,
+        offset: u64,
+        total: u64,
+        data: Vec<u8>,
+        durability: memsdk::Durability,
+        priority: u8,
+    },
+    BlockStored {
+        id: BlockId,
+    },
+    /// The sender no longer needs a block it had stored on us, or one it was
+    /// still streaming.
+    ReleaseBlock {
+        id: BlockId,
+    },
+    /// Ask for the keys matching `pattern`; answered with KeyList.
+    ListKeys {
+        pattern: String,
+        request_id: u64,
+    },
+    /// At most `REMOTE_LIST_LIMIT` keys; `truncated` when there were more.
+    KeyList {
+        request_id: u64,
+        keys: Vec<String>,
+        truncated: bool,
+    },
+    /// Open a block a client is streaming to us through the sender, holding
+    /// `reserve` bytes of its quota for it.
+    StreamBlockStart {
+        id: BlockId,
+        reserve: u64,
+    },
+    /// Next piece of a streamed block. `reserve` grows once the bytes sent
+    /// pass what StreamBlockStart reserved.
+    StreamBlockChunk {
+        id: BlockId,
+        offset: u64,
+        reserve: u64,
+        data: Vec<u8>,
+    },
+    /// The streamed block is complete at `size` bytes; answered with
+    /// BlockStored or PutBlockRejected.
+    StreamBlockEnd {
+        id: BlockId,
+        size: u64,
+        durability: memsdk::Durability,
+        priority: Option<u8>,
+    },
+    /// Reply to Flush with what it removed.
+    FlushAck {
+        request_id: u64,
+        blocks_removed: u64,
+        bytes_freed: u64,
+    },
+    /// Ask for the peer's NodeStat; answered with StatReply.
+    StatRequest {
+        request_id: u64,
+    },
+    StatReply {
+        request_id: u64,
+        stat: NodeStat,
+    },
+    /// Bytes the sender holds for the receiver, sent after each Pong to peers
+    /// at `auth::USAGE_VERSION` or later. The receiver takes it as what it has
+    /// offloaded there, which the sender may have evicted without telling it.
+    HeldForYou {
+        bytes: u64,
+    },
+    /// Reply to Flush from a node that removed nothing because it refused,
+    /// e.g. being read-only. Sent to peers at `auth::FLUSH_REFUSAL_VERSION`
+    /// or later.
+    FlushRefused {
+        request_id: u64,
+        reason: String,
+    },
+}
+
+/// What a node reports about itself for `ClusterStat`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeStat {
+    pub blocks: u64,
+    pub used_memory: u64,
+    pub max_memory: u64,
+    pub peers: u64,
+    /// The memnode release, e.g. "0.1.2"
+    pub version: String,
 }
 
 use std::sync::Arc;
-use crate::peers::PeerManager;
+use crate::peers::{PeerManager, REMOTE_LIST_LIMIT};
 use crate::blocks::{InMemoryBlockManager, BlockManager}; 
-use crate::net::secure_stream::{SecureReader, SecureWriter};
+use crate::net::secure_stream::SecureReader;
+use crate::net::outbound::PeerSender;
 
 pub struct TransportServer {
     listener: TcpListener,
@@ -68,14 +209,16 @@ pub struct TransportServer {
 }
 
 impl TransportServer {
-    pub async fn bind(start_port: u16, block_manager: Arc<InMemoryBlockManager>, peer_manager: Arc<PeerManager>) -> Result<(Self, u16)> {
+    pub async fn bind(host: &str, start_port: u16, block_manager: Arc<InMemoryBlockManager>, peer_manager: Arc<PeerManager>) -> Result<(Self, u16)> {
         let mut port = start_port;
         // Try up to 10 ports
         for _ in 0..10 {
-            let addr = format!("0.0.0.0:{}", port);
+            let addr = format!("{}:{}", host, port);
             match TcpListener::bind(&addr).await {
                 Ok(listener) => {
                     info!("Transport listening on {}", addr);
+                    // Report the real port (differs from the requested one when binding port 0)
+                    let port = listener.local_addr()?.port();
                     return Ok((Self { listener, block_manager, peer_manager }, port));
                 }
                 Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
@@ -93,8 +236,10 @@ impl TransportServer {
             match self.listener.accept().await {
                 Ok((mut stream, addr)) => {
                     info!("Incoming connection from {}", addr);
+                    tune_socket(&stream);
                     let bm = self.block_manager.clone();
                     let pm = self.peer_manager.clone();
+                    let my_quota = pm.clamp_offer(pm.default_quota(), None);
                     
                      // Spawn per-connection handler
                      tokio::spawn(async move {
@@ -102,24 +247,32 @@ impl TransportServer {
                          info!("Starting handshake with {}", addr);
                          
                          let sys_mem = pm.get_total_system_memory();
-                         let my_quota = bm.get_max_memory();
                          
                          match auth::handshake_responder(&mut stream, &identity, pm.trusted_store.clone(), pm.consent_manager.clone(), my_quota, sys_mem).await {
                              Ok(session) => {
                                  info!("Handshake accepted from {} ({}). Negotiated secure session.", session.peer_name, session.peer_id);
                                  
-                                 let (reader, writer) = stream.into_split();
-                                 let secure_reader = SecureReader::new(reader, &session.recv_key);
-                                 let secure_writer = SecureWriter::from_raw(writer, &session.send_key);
+                                 let (secure_reader, secure_writer) = session.split(stream, pm.max_peer_frame());
                                  
-                                 let writer_arc = Arc::new(tokio::sync::Mutex::new(secure_writer));
+                                 let (sender, writer_task) = PeerSender::spawn(secure_writer);
                                  
-                                 pm.register_authenticated_peer(session.peer_id, addr, session.peer_name, writer_arc.clone(), my_quota, session.peer_total_memory, session.peer_quota);
+                                 let peer_id = match pm.register_authenticated_peer(&session, addr, sender.clone(), my_quota) {
+                                     Ok(id) => id,
+                                     Err(e) => {
+                                         error!("Not registering {}: {}", addr, e);
+                                         return;
+                                     }
+                                 };
+                                 bm.resync_peer_storage(peer_id);
                                  
-                                 if let Err(e) = handle_connection_split(secure_reader, writer_arc, addr, session.peer_id, bm, pm).await {
+                                 if let Err(e) = handle_connection_split(secure_reader, sender, writer_task, addr, peer_id, bm, pm).await {
                                      error!("Connection error from {}: {}", addr, e);
                                  }
                              }
+                             // The dialing side reports this one
+                             Err(e) if e.is::<auth::ConnectedToSelf>() => {
+                                 info!("Closed connection from {} to this node itself", addr);
+                             }
                              Err(e) => {
                                  error!("Handshake failed handling {}: {}", addr, e);
                              }
@@ -136,21 +289,95 @@ impl TransportServer {
 
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 
-pub async fn send_message_locked(writer: &mut tokio::sync::MutexGuard<'_, SecureWriter>, msg: &Message) -> Result<()> {
-    let bytes = bincode::serialize(msg)?;
-    writer.send_frame(&bytes).await?;
-    Ok(())
+/// Idle time before the kernel starts probing a silent peer connection.
+const TCP_KEEPALIVE_IDLE: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Disable Nagle (frames are small request/response pairs) and let the kernel
+/// probe idle peer connections.
+pub fn tune_socket(stream: &TcpStream) {
+    if let Err(e) = stream.set_nodelay(true) {
+        error!("Failed to set TCP_NODELAY: {}", e);
+    }
+    let keepalive = socket2::TcpKeepalive::new().with_time(TCP_KEEPALIVE_IDLE);
+    if let Err(e) = socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+        error!("Failed to enable SO_KEEPALIVE: {}", e);
+    }
 }
 
 pub async fn handle_connection_split(
-    mut reader: SecureReader, 
-    writer: Arc<Mutex<SecureWriter>>, 
+    reader: SecureReader, 
+    writer: PeerSender, 
+    mut writer_task: tokio::task::JoinHandle<Result<()>>,
     addr: SocketAddr, 
     peer_id: crate::metadata::NodeId, // Added peer_id
     block_manager: Arc<InMemoryBlockManager>, 
     peer_manager: Arc<PeerManager>
 ) -> Result<()> {
-    
+    let (res, reason) = tokio::select! {
+        res = read_loop(reader, writer.clone(), addr, peer_id, block_manager.clone(), peer_manager.clone()) => {
+            let reason = match &res {
+                Ok(()) => "connection closed".to_string(),
+                Err(e) => format!("protocol error: {}", e),
+            };
+            (res, reason)
+        }
+        reason = keepalive(writer.clone(), peer_id, peer_manager.clone()) => {
+            error!("Peer {} at {}: {} (Disconnecting)", peer_id, addr, reason);
+            (Ok(()), reason.to_string())
+        }
+        res = &mut writer_task => {
+            match res {
+                Ok(Err(e)) => error!("Write error to {}: {} (Disconnecting)", addr, e),
+                _ => error!("Writer for {} stopped (Disconnecting)", addr),
+            }
+            (Ok(()), "write failed".to_string())
+        }
+    };
+
+    // Cleanup on disconnect (graceful, error or dead peer)
+    peer_manager.handle_peer_disconnect(peer_id, &writer, &reason);
+    writer_task.abort();
+    block_manager.abort_incoming_from(peer_id);
+    res
+}
+
+// Ping the peer every keepalive interval. Returns why it stopped: the peer
+// missed too many pongs in a row, or the connection can no longer be written
+// to. A peer that stops sending altogether is dropped sooner, by the read
+// deadline in `read_loop`.
+async fn keepalive(writer: PeerSender, peer_id: crate::metadata::NodeId, peer_manager: Arc<PeerManager>) -> &'static str {
+    let mut ticker = tokio::time::interval(peer_manager.keepalive_interval());
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let nonce = rand::random();
+        let Some(missed) = peer_manager.record_ping_sent(peer_id, nonce) else {
+            return "peer gone";
+        };
+        if missed >= crate::peers::KEEPALIVE_MAX_MISSED {
+            return "stopped answering pings";
+        }
+        if writer.send(&Message::Ping { nonce }).await.is_err() {
+            return "ping failed";
+        }
+    }
+}
+
+async fn read_loop(
+    mut reader: SecureReader,
+    writer: PeerSender,
+    addr: SocketAddr,
+    peer_id: crate::metadata::NodeId,
+    block_manager: Arc<InMemoryBlockManager>,
+    peer_manager: Arc<PeerManager>
+) -> Result<()> {
+    // Both sides ping every keepalive interval, so this much silence means the link is dead
+    // (host gone without a FIN) even though the socket still looks open. Any
+    // byte counts, so a large frame arriving slowly keeps the connection.
+    let read_deadline = peer_manager.keepalive_interval() * crate::peers::KEEPALIVE_READ_DEADLINE_INTERVALS;
+    reader.set_idle_timeout(read_deadline);
+    // Set once the peer said Bye; replies it queued after the Bye are still read
+    let mut leaving = false;
     loop {
         match reader.recv_frame().await {
             Ok(frame_data) => {
@@ -161,85 +388,146 @@ pub async fn handle_connection_split(
                     Message::Hello { .. } => {
                         // Ignored securely; legacy
                     }
-                    Message::GetBlock { id } => {
+                    Message::GetBlock { id, trace_id } => {
                         use crate::blocks::BlockManager;
-                        match block_manager.get_block(id) {
+                        let span = trace::peer_span(trace_id.as_deref(), "GetBlock", peer_id);
+                        let resp = span.in_scope(|| match block_manager.get_block(id) {
                             Ok(Some(block)) => {
-                                let resp = Message::BlockData { id, data: Some(block.data) };
-                                let mut w = writer.lock().await;
-                                send_message_locked(&mut w, &resp).await?;
-                            }
-                            Ok(None) => {
-                                let resp = Message::BlockData { id, data: None };
-                                let mut w = writer.lock().await;
-                                send_message_locked(&mut w, &resp).await?;
+                                log::debug!("Serving block {} to {}", id, peer_id);
+                                block_manager.record_hit(id, block.data.len() as u64, true);
+                                Message::BlockData { id, data: Some(block.data), durability: Some(block.durability), trace_id, priority: Some(block.priority) }
                             }
+                            Ok(None) => Message::BlockData { id, data: None, durability: None, trace_id, priority: None },
                             Err(e) => {
                                  error!("Error retrieving block {}: {}", id, e);
-                                 let resp = Message::BlockData { id, data: None };
-                                 let mut w = writer.lock().await;
-                                 send_message_locked(&mut w, &resp).await?;
+                                 Message::BlockData { id, data: None, durability: None, trace_id, priority: None }
                             }
-                        }
+                        });
+                        writer.send(&resp).await?;
                     }
-                    Message::BlockData { id, data } => {
-                        if let Some(d) = data {
-                            peer_manager.satisfy_request(id, d);
-                        }
+                    Message::BlockData { id, data, durability, trace_id, priority } => {
+                        trace::peer_span(trace_id.as_deref(), "BlockData", peer_id).in_scope(|| {
+                            log::debug!("Block {} from {}: {}", id, peer_id, if data.is_some() { "found" } else { "not found" });
+                            if let Some(d) = data {
+                                peer_manager.satisfy_request(id, (d, durability, priority));
+                            }
+                        });
                     }
-                    Message::PutBlock { id, data, durability } => {
+                    Message::PutBlock { id, data, durability, trace_id, priority } => {
                          use crate::blocks::{BlockManager, Block};
                          let size = data.len() as u64;
                          let mode = durability.unwrap_or(memsdk::Durability::Pinned); 
+                         let span = trace::peer_span(trace_id.as_deref(), "PutBlock", peer_id);
                          
-                         if peer_manager.try_reserve_storage(peer_id, size) {
+                         let rejection = span.in_scope(|| if let Err(e) = block_manager.check_writable() {
+                             log::warn!("Refused PutBlock {} from {}: {}", id, peer_id, e);
+                             Some(e.to_string())
+                         } else if peer_manager.try_reserve_storage(peer_id, size) {
                              info!("Storing remote block {} from authenticated peer {}", id, peer_id);
                              let block = Block { 
                                  id, 
                                  data, 
                                  durability: mode,
-                                 last_accessed: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs())) 
+                                 last_accessed: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs())),
+                                 priority: priority.unwrap_or(memsdk::DEFAULT_PRIORITY),
                              };
-                             if let Err(e) = block_manager.put_block(block) {
-                                 error!("Failed to store remote block: {}", e);
-                                 peer_manager.release_storage(peer_id, size);
+                             match block_manager.put_block(block) {
+                                 Ok(_) => {
+                                     block_manager.record_hosted_block(id, peer_id);
+                                     None
+                                 }
+                                 Err(e) => {
+                                     error!("Failed to store remote block: {}", e);
+                                     peer_manager.release_storage(peer_id, size);
+                                     Some(e.to_string())
+                                 }
                              }
                          } else {
                              error!("Rejected PutBlock from {}: Quota Exceeded", peer_id);
-                             // TODO: Send NACK?
-                         }
+                             Some("Quota exceeded".to_string())
+                         });
+
+                         let resp = match rejection {
+                             Some(reason) => Message::PutBlockRejected { id, reason },
+                             None => Message::BlockStored { id },
+                         };
+                         writer.send(&resp).await?;
                     }
-                    Message::GetKey { key } => {
-                        let id_opt = block_manager.get_named_block_id(&key);
-                        let mut data_opt = None;
-                        if let Some(id) = id_opt {
-                            if let Ok(Some(block)) = block_manager.get_block(id) {
-                                 data_opt = Some(block.data);
+                    Message::PutBlockRejected { id, reason } => {
+                        log::warn!("Peer {} rejected block {}: {}", peer_id, id, reason);
+                        block_manager.forget_remote_block(id, peer_id);
+                        peer_manager.satisfy_block_ack(id, Err(reason));
+                    }
+                    Message::GetKey { key, trace_id } => {
+                        let span = trace::peer_span(trace_id.as_deref(), "GetKey", peer_id);
+                        let (mut data_opt, mut durability, mut priority) = (None, None, None);
+                        span.in_scope(|| {
+                            if let Some(id) = block_manager.get_named_block_id(&key) {
+                                if let Ok(Some(block)) = block_manager.get_block(id) {
+                                     log::debug!("Serving key '{}' (block {}) to {}", key, id, peer_id);
+                                     block_manager.record_hit(id, block.data.len() as u64, true);
+                                     data_opt = Some(block.data);
+                                     durability = Some(block.durability);
+                                     priority = Some(block.priority);
+                                }
                             }
-                        }
-                        let resp = Message::KeyFound { key, data: data_opt };
-                        let mut w = writer.lock().await;
-                        send_message_locked(&mut w, &resp).await?;
+                        });
+                        let resp = Message::KeyFound { key, data: data_opt, durability, trace_id, priority };
+                        writer.send(&resp).await?;
                     }
-                    Message::KeyFound { key, data } => {
-                        if let Some(d) = data {
-                            peer_manager.satisfy_key_request(&key, d);
-                        }
+                    Message::KeyFound { key, data, durability, trace_id, priority } => {
+                        trace::peer_span(trace_id.as_deref(), "KeyFound", peer_id).in_scope(|| {
+                            // `None` is the peer saying it has no such key; an empty value is `Some`
+                            peer_manager.satisfy_key_request(peer_id, &key, data.map(|d| (d, durability, priority)));
+                        });
                     }
-                    Message::Flush => {
-                        info!("Received Flush command from authenticated peer. Clearing local memory.");
-                        block_manager.flush();
+                    Message::Flush { request_id, pattern, durability } => {
+                        info!("Received Flush command from authenticated peer {} (pattern: {:?}, durability: {:?}).", peer_id, pattern, durability);
+                        let ack = |stats: memsdk::FlushStats| Message::FlushAck { request_id, blocks_removed: stats.blocks_removed, bytes_freed: stats.bytes_freed };
+                        let reply = match block_manager.check_writable() {
+                            // Without the grant a peer only reaches what it stored here
+                            Ok(()) if peer_manager.allows_remote_flush(peer_id) => {
+                                let stats = block_manager.flush_filtered(pattern.as_deref(), durability);
+                                let by = peer_manager.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string());
+                                peer_manager.events.record("flush", "flushed", Some(&by), Some(stats.bytes_freed), format!("{} blocks, asked by the peer", stats.blocks_removed));
+                                ack(stats)
+                            }
+                            Ok(()) => {
+                                let stats = block_manager.flush_hosted(peer_id, pattern.as_deref(), durability);
+                                let by = peer_manager.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string());
+                                peer_manager.events.record("flush", "flushed peer data", Some(&by), Some(stats.bytes_freed), format!("{} blocks it stored here", stats.blocks_removed));
+                                ack(stats)
+                            }
+                            Err(e) => {
+                                log::warn!("Refused Flush from {}: {}", peer_id, e);
+                                if peer_manager.peer_protocol_version(peer_id).is_some_and(|v| v >= crate::net::auth::FLUSH_REFUSAL_VERSION) {
+                                    Message::FlushRefused { request_id, reason: e.to_string() }
+                                } else {
+                                    ack(memsdk::FlushStats::default())
+                                }
+                            }
+                        };
+                        writer.send(&reply).await?;
+                    }
+                    Message::FlushAck { request_id, blocks_removed, bytes_freed } => {
+                        peer_manager.satisfy_flush_ack(peer_id, request_id, Ok(memsdk::FlushStats { blocks_removed, bytes_freed }));
+                    }
+                    Message::FlushRefused { request_id, reason } => {
+                        peer_manager.satisfy_flush_ack(peer_id, request_id, Err(reason));
                     }
-                    Message::PutKey { key, data, durability } => {
+                    Message::PutKey { key, data, durability, priority } => {
                         let size = data.len() as u64;
                         let mode = durability.unwrap_or(memsdk::Durability::Pinned);
 
-                        if peer_manager.try_reserve_storage(peer_id, size) {
-                             match block_manager.set(&key, data, mode) { 
+                        if let Err(e) = block_manager.check_writable() {
+                             log::warn!("Refused PutKey '{}' from {}: {}", key, peer_id, e);
+                        } else if peer_manager.try_reserve_storage(peer_id, size) {
+                             let block = crate::blocks::Block { priority: priority.unwrap_or(memsdk::DEFAULT_PRIORITY), ..block_manager.new_block(data, mode) };
+                             match block_manager.put_named_block(key.clone(), block).map(|(id, _)| id) {
                                   Ok(id) => {
+                                      block_manager.record_hosted_block(id, peer_id);
                                       let resp = Message::KeyStored { key, id };
-                                      let mut w = writer.lock().await;
-                                      if let Err(e) = send_message_locked(&mut w, &resp).await {
+                                      if let Err(e) = writer.send(&resp).await {
                                            error!("Failed to send KeyStored ack: {}", e);
                                       }
                                   }
@@ -255,17 +543,103 @@ pub async fn handle_connection_split(
                     Message::KeyStored { key, id } => {
                         peer_manager.satisfy_key_store(&key, id);
                     }
-                    Message::UpdateQuota { quota } => {
+                    Message::ListKeys { pattern, request_id } => {
+                        let (keys, more) = block_manager.list_keys_page(&pattern, None, REMOTE_LIST_LIMIT);
+                        let truncated = more.is_some();
+                        writer.send(&Message::KeyList { request_id, keys, truncated }).await?;
+                    }
+                    Message::KeyList { request_id, keys, truncated } => {
+                        peer_manager.satisfy_key_list(peer_id, request_id, keys, truncated);
+                    }
+                    Message::UpdateQuota { quota, request_id } => {
                         info!("Received quota update from {}: {} bytes", peer_id, quota);
-                        peer_manager.update_peer_ram_quota(peer_id, quota);
+                        let resp = match peer_manager.update_peer_ram_quota(peer_id, quota) {
+                            Some(applied) => Message::QuotaAck { request_id, accepted: true, quota: applied },
+                            None => Message::QuotaAck { request_id, accepted: false, quota: 0 },
+                        };
+                        writer.send(&resp).await?;
+                    }
+                    Message::QuotaAck { request_id, accepted, quota } => {
+                        peer_manager.satisfy_quota_ack(peer_id, request_id, accepted, quota);
+                    }
+                    Message::Ping { nonce } => {
+                        writer.send(&Message::Pong { nonce }).await?;
+                        if peer_manager.peer_protocol_version(peer_id).is_some_and(|v| v >= crate::net::auth::USAGE_VERSION) {
+                            let held = block_manager.hosted_storage_for(peer_id);
+                            peer_manager.resync_remote_used_storage(peer_id, held);
+                            writer.send(&Message::HeldForYou { bytes: held }).await?;
+                        }
+                    }
+                    Message::Pong { nonce } => {
+                        peer_manager.record_pong(peer_id, nonce);
+                    }
+                    Message::HeldForYou { bytes } => {
+                        peer_manager.resync_offloaded(peer_id, bytes);
                     }
                     Message::Bye => {
                         info!("Peer {} disconnected gracefully.", peer_id);
-                        break;
+                        leaving = true;
+                        reader.set_idle_timeout(crate::peers::BYE_DRAIN_TIMEOUT);
+                    }
+                    Message::PutBlockChunk { id, key, offset, total, data, durability, priority } => {
+                        let resp = match block_manager.check_writable().and_then(|_| block_manager.receive_chunk(peer_id, id, key, offset, total, data, durability, priority)) {
+                            Ok(true) => Some(Message::BlockStored { id }),
+                            Ok(false) => None,
+                            Err(e) => {
+                                error!("Failed to receive block {} from {}: {}", id, peer_id, e);
+                                Some(Message::PutBlockRejected { id, reason: e.to_string() })
+                            }
+                        };
+                        if let Some(resp) = resp {
+                            writer.send(&resp).await?;
+                        }
+                    }
+                    Message::StreamBlockStart { id, reserve } => {
+                        if let Err(e) = block_manager.check_writable().and_then(|_| block_manager.receive_stream_start(peer_id, id, reserve)) {
+                            error!("Refused streamed block {} from {}: {}", id, peer_id, e);
+                            writer.send(&Message::PutBlockRejected { id, reason: e.to_string() }).await?;
+                        }
+                    }
+                    Message::StreamBlockChunk { id, offset, reserve, data } => {
+                        if let Err(e) = block_manager.receive_stream_chunk(peer_id, id, offset, reserve, data) {
+                            error!("Failed to receive streamed block {} from {}: {}", id, peer_id, e);
+                            writer.send(&Message::PutBlockRejected { id, reason: e.to_string() }).await?;
+                        }
+                    }
+                    Message::StreamBlockEnd { id, size, durability, priority } => {
+                        let resp = match block_manager.receive_stream_end(peer_id, id, size, durability, priority.unwrap_or(memsdk::DEFAULT_PRIORITY)) {
+                            Ok(true) => Some(Message::BlockStored { id }),
+                            Ok(false) => None,
+                            Err(e) => {
+                                error!("Failed to store streamed block {} from {}: {}", id, peer_id, e);
+                                Some(Message::PutBlockRejected { id, reason: e.to_string() })
+                            }
+                        };
+                        if let Some(resp) = resp {
+                            writer.send(&resp).await?;
+                        }
+                    }
+                    Message::BlockStored { id } => {
+                        peer_manager.satisfy_block_ack(id, Ok(()));
+                    }
+                    Message::ReleaseBlock { id } => {
+                        block_manager.release_hosted_block(id, peer_id);
+                    }
+                    Message::StatRequest { request_id } => {
+                        writer.send(&Message::StatReply { request_id, stat: block_manager.node_stat() }).await?;
+                    }
+                    Message::StatReply { request_id, stat } => {
+                        peer_manager.satisfy_stat(peer_id, request_id, stat);
                     }
                     _ => {}
                 }
             }
+            // The peer closing its side is expected once it said Bye
+            Err(_) if leaving => break,
+            Err(e) if crate::net::frame::is_idle_timeout(&e) => {
+                error!("Nothing received from {} for {:?} (Disconnecting)", addr, read_deadline);
+                break;
+            }
             Err(e) => {
                 // Connection closed or error
                  error!("Read error from {}: {} (Disconnecting)", addr, e);
@@ -273,9 +647,6 @@ pub async fn handle_connection_split(
             }
         }
     }
-    
-    // Cleanup on disconnect (graceful or error)
-    peer_manager.handle_peer_disconnect(peer_id);
     Ok(())
 }
 
@@ -287,3 +658,403 @@ pub async fn send_message(stream: &mut TcpStream, msg: &Message) -> Result<()> {
     stream.write_all(&bytes).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::net::auth::{handshake_initiator, Identity};
+    use crate::peers::consent::ConsentDecision;
+    use crate::peers::{FLUSH_ACK_TIMEOUT, REMOTE_LIST_LIMIT, REMOTE_LIST_TIMEOUT};
+    use crate::test_support::{TestNode, wait_until};
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_silent_peer_dropped_after_read_deadline() {
+        let node = TestNode::spawn("deadline", 1024 * 1024).await;
+        node.peer_manager.set_keepalive_interval(Duration::from_millis(100));
+        let consent = node.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(req) = consent.get_pending_list().into_iter().next() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        // A peer that completes the handshake and then never sends or reads anything
+        let identity = Identity::new(uuid::Uuid::new_v4(), "silent".to_string());
+        let mut stream = tokio::net::TcpStream::connect(node.addr()).await.unwrap();
+        handshake_initiator(&mut stream, &identity, 0, 0, None, || {}).await.unwrap();
+        wait_until(|| node.peer_manager.get_peer_id_by_name("silent").is_some()).await;
+
+        // Still connected part-way through the 200ms deadline, gone after it
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(node.peer_manager.list_peers().len(), 1);
+        wait_until(|| node.peer_manager.list_peers().is_empty()).await;
+        drop(stream);
+    }
+
+    #[tokio::test]
+    async fn test_peer_that_never_pongs_is_dropped() {
+        use crate::net::secure_stream::SecureWriter;
+        let node = TestNode::spawn("pongless", 1024 * 1024).await;
+        node.peer_manager.set_keepalive_interval(Duration::from_millis(100));
+        let consent = node.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(req) = consent.get_pending_list().into_iter().next() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        // Sends a ping well within every read deadline, but never reads, so never pongs
+        let identity = Identity::new(uuid::Uuid::new_v4(), "pongless".to_string());
+        let mut stream = tokio::net::TcpStream::connect(node.addr()).await.unwrap();
+        let session = handshake_initiator(&mut stream, &identity, 0, 0, None, || {}).await.unwrap();
+        let (_read, write) = stream.into_split();
+        let mut writer = SecureWriter::from_raw(write, &session.send_key);
+        let chatter = tokio::spawn(async move {
+            loop {
+                let ping = bincode::serialize(&super::Message::Ping { nonce: 0 }).unwrap();
+                if writer.send_frame(&ping).await.is_err() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        wait_until(|| node.peer_manager.get_peer_id_by_name("pongless").is_some()).await;
+
+        wait_until(|| node.peer_manager.list_peers().is_empty()).await;
+        let dropped = node.peer_manager.events.query(None, Some(&["peer".to_string()]), 10).0;
+        assert!(dropped.iter().any(|e| e.detail.contains("stopped answering pings")), "{:?}", dropped);
+        chatter.abort();
+    }
+
+    #[tokio::test]
+    async fn test_key_listing_includes_peers() {
+        let a = TestNode::spawn("list-a", 1024 * 1024).await;
+        let b = TestNode::spawn("list-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let pinned = memsdk::Durability::Pinned;
+        for key in ["shared", "only-a"] {
+            a.block_manager.set(key, vec![1], pinned).unwrap();
+        }
+        for key in ["shared", "only-b"] {
+            b.block_manager.set(key, vec![2], pinned).unwrap();
+        }
+        for i in 0..=REMOTE_LIST_LIMIT {
+            b.block_manager.set(&format!("bulk:{}", i), vec![3], pinned).unwrap();
+        }
+
+        let listing = a.block_manager.list_keys_with_remote("[so]*").await;
+        let found: Vec<(&str, Vec<&str>)> = listing.items.iter()
+            .map(|i| (i.key.as_str(), i.holders.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(found, [("only-a", vec!["local"]), ("only-b", vec!["list-b"]), ("shared", vec!["local", "list-b"])]);
+        assert!(listing.truncated.is_empty() && listing.unanswered.is_empty());
+
+        let listing = a.block_manager.list_keys_with_remote("bulk:*").await;
+        assert_eq!(listing.items.len(), REMOTE_LIST_LIMIT);
+        assert_eq!(listing.truncated, ["list-b"]);
+
+        // A peer that never answers is skipped once the deadline passes
+        let consent = a.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(req) = consent.get_pending_list().into_iter().next() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let identity = Identity::new(uuid::Uuid::new_v4(), "mute".to_string());
+        let mut stream = tokio::net::TcpStream::connect(a.addr()).await.unwrap();
+        handshake_initiator(&mut stream, &identity, 0, 0, None, || {}).await.unwrap();
+        wait_until(|| a.peer_manager.get_peer_id_by_name("mute").is_some()).await;
+
+        let start = Instant::now();
+        let listing = a.block_manager.list_keys_with_remote("only-*").await;
+        assert!(start.elapsed() >= REMOTE_LIST_TIMEOUT && start.elapsed() < REMOTE_LIST_TIMEOUT * 2);
+        assert_eq!(listing.items.len(), 2);
+        assert_eq!(listing.unanswered, ["mute"]);
+        drop(stream);
+    }
+
+    #[tokio::test]
+    async fn test_empty_values_are_not_missing() {
+        let a = TestNode::spawn("empty-a", 1024 * 1024).await;
+        let b = TestNode::spawn("empty-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let pinned = memsdk::Durability::Pinned;
+        let value = |v: Option<crate::peers::RemoteValue>| v.map(|(data, _, _)| data.to_vec());
+
+        a.block_manager.set("here", Vec::new(), pinned).unwrap();
+        assert_eq!(value(a.block_manager.get_distributed_key("here").await.unwrap()), Some(Vec::new()));
+        b.block_manager.set("there", Vec::new(), pinned).unwrap();
+        assert_eq!(value(a.block_manager.get_distributed_key("there").await.unwrap()), Some(Vec::new()));
+        assert_eq!(value(a.block_manager.get_remote("there", "empty-b").await.unwrap()), Some(Vec::new()));
+        a.block_manager.set_remote("pushed", Vec::new(), "empty-b", pinned, None).await.unwrap();
+        assert_eq!(value(b.block_manager.get_distributed_key("pushed").await.unwrap()), Some(Vec::new()));
+
+        // The peer says it has no such key, so nobody waits out the timeout
+        let start = Instant::now();
+        assert!(a.block_manager.get_distributed_key("nowhere").await.unwrap().is_none());
+        assert!(a.block_manager.get_remote("nowhere", "empty-b").await.unwrap().is_none());
+        assert!(start.elapsed() < crate::peers::KEY_LOOKUP_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_peer_flush_only_reaches_its_own_blocks() {
+        let a = TestNode::spawn("owner-a", 1024 * 1024).await;
+        let b = TestNode::spawn("owner-b", 1024 * 1024).await;
+        let c = TestNode::spawn("owner-c", 1024 * 1024).await;
+        b.peer_manager.set_default_quota(64 * 1024);
+        a.connect_to(&b, 0).await;
+        c.connect_to(&b, 0).await;
+        let pinned = memsdk::Durability::Pinned;
+        a.block_manager.set_remote("tmp:a", vec![1; 10], "owner-b", pinned, None).await.unwrap();
+        a.block_manager.set_remote("keep:a", vec![1; 30], "owner-b", pinned, None).await.unwrap();
+        c.block_manager.set_remote("tmp:c", vec![2; 20], "owner-b", pinned, None).await.unwrap();
+        b.block_manager.set("tmp:b", vec![3; 40], pinned).unwrap();
+        let a_on_b = b.peer_manager.get_peer_id_by_name(&a.id.to_string()).unwrap();
+        let keys_on_b = || {
+            let mut keys = b.block_manager.list_keys("*");
+            keys.sort();
+            keys
+        };
+
+        let stats = a.block_manager.flush_remote(b.id.to_string(), Some("tmp:*".to_string()), None).await.unwrap();
+        assert_eq!(stats, memsdk::FlushStats { blocks_removed: 1, bytes_freed: 10 });
+        assert_eq!(keys_on_b(), ["keep:a", "tmp:b", "tmp:c"]);
+        let stats = a.block_manager.flush_remote(b.id.to_string(), None, None).await.unwrap();
+        assert_eq!(stats, memsdk::FlushStats { blocks_removed: 1, bytes_freed: 30 });
+        assert_eq!(keys_on_b(), ["tmp:b", "tmp:c"]);
+        assert_eq!(b.block_manager.hosted_storage_for(a_on_b), 0);
+
+        // Only a trusted device granted the flag wipes the node
+        let a_key = hex::encode(a.peer_manager.get_identity().public_key().to_bytes());
+        assert!(b.peer_manager.set_remote_flush(&a.id.to_string(), true).is_err());
+        b.peer_manager.trusted_store.add_trusted(a_key, "owner-a".to_string(), None).unwrap();
+        b.peer_manager.set_remote_flush(&a.id.to_string(), true).unwrap();
+        let stats = a.block_manager.flush_remote(b.id.to_string(), None, None).await.unwrap();
+        assert_eq!(stats, memsdk::FlushStats { blocks_removed: 2, bytes_freed: 60 });
+        assert!(b.block_manager.list_keys("*").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remote_flush_waits_for_ack() {
+        let a = TestNode::spawn("flush-a", 1024 * 1024).await;
+        let b = TestNode::spawn("flush-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        a.block_manager.set_remote("tmp:1", vec![1; 10], "flush-b", memsdk::Durability::Pinned, None).await.unwrap();
+        a.block_manager.set_remote("keep", vec![2; 20], "flush-b", memsdk::Durability::Pinned, None).await.unwrap();
+
+        let stats = a.block_manager.flush_remote(b.id.to_string(), Some("tmp:*".to_string()), None).await.unwrap();
+        assert_eq!(stats, memsdk::FlushStats { blocks_removed: 1, bytes_freed: 10 });
+        assert_eq!(b.block_manager.list_keys("*"), ["keep"]);
+
+        // Two flushes at once each get the answer to their own
+        a.block_manager.set_remote("tmp:2", vec![3; 30], "flush-b", memsdk::Durability::Pinned, None).await.unwrap();
+        a.block_manager.set_remote("old:1", vec![4; 40], "flush-b", memsdk::Durability::Pinned, None).await.unwrap();
+        let (tmp, old) = tokio::join!(
+            a.block_manager.flush_remote(b.id.to_string(), Some("tmp:*".to_string()), None),
+            a.block_manager.flush_remote(b.id.to_string(), Some("old:*".to_string()), None),
+        );
+        assert_eq!(tmp.unwrap().bytes_freed, 30);
+        assert_eq!(old.unwrap().bytes_freed, 40);
+
+        // A peer that never answers fails the flush once the wait is up
+        let consent = a.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(req) = consent.get_pending_list().into_iter().next() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let identity = Identity::new(uuid::Uuid::new_v4(), "deaf".to_string());
+        let mut stream = tokio::net::TcpStream::connect(a.addr()).await.unwrap();
+        handshake_initiator(&mut stream, &identity, 0, 0, None, || {}).await.unwrap();
+        wait_until(|| a.peer_manager.get_peer_id_by_name("deaf").is_some()).await;
+
+        let start = Instant::now();
+        let err = a.block_manager.flush_remote("deaf".to_string(), None, None).await.unwrap_err();
+        assert!(err.to_string().starts_with("Timeout waiting for flush acknowledgment"), "{}", err);
+        assert!(start.elapsed() >= FLUSH_ACK_TIMEOUT);
+        drop(stream);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_flush_reports_every_peer() {
+        let a = TestNode::spawn("cluster-a", 1024 * 1024).await;
+        let b = TestNode::spawn("cluster-b", 1024 * 1024).await;
+        let c = TestNode::spawn("cluster-c", 1024 * 1024).await;
+        a.peer_manager.set_default_quota(64 * 1024);
+        a.connect_to(&b, 0).await;
+        a.connect_to(&c, 0).await;
+        let pinned = memsdk::Durability::Pinned;
+        a.block_manager.set("tmp:a", vec![1; 5], pinned).unwrap();
+        a.block_manager.set_remote("tmp:b", vec![2; 10], "cluster-b", pinned, None).await.unwrap();
+        a.block_manager.set_remote("tmp:c", vec![3; 20], "cluster-c", pinned, None).await.unwrap();
+
+        // Two peers that never answer: waited for side by side, not one after the other
+        let consent = a.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                for req in consent.get_pending_list() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let mut deaf = Vec::new();
+        for name in ["deaf-1", "deaf-2"] {
+            let identity = Identity::new(uuid::Uuid::new_v4(), name.to_string());
+            let mut stream = tokio::net::TcpStream::connect(a.addr()).await.unwrap();
+            handshake_initiator(&mut stream, &identity, 0, 0, None, || {}).await.unwrap();
+            wait_until(|| a.peer_manager.get_peer_id_by_name(name).is_some()).await;
+            deaf.push(stream);
+        }
+
+        let start = Instant::now();
+        let results = a.block_manager.flush_cluster(true, Some("tmp:*".to_string()), None).await;
+        assert!(start.elapsed() < FLUSH_ACK_TIMEOUT * 2, "{:?}", start.elapsed());
+        let summary: Vec<_> = results.iter().map(|o| (o.node.as_str(), o.local, o.stats.map(|s| s.bytes_freed), o.error.is_some())).collect();
+        assert_eq!(summary, [
+            ("cluster-b", false, Some(10), false),
+            ("cluster-c", false, Some(20), false),
+            ("deaf-1", false, None, true),
+            ("deaf-2", false, None, true),
+            ("cluster-a", true, Some(5), false),
+        ]);
+        assert!(b.block_manager.list_keys("*").is_empty() && c.block_manager.list_keys("*").is_empty());
+
+        // Running it again is harmless for the peers already flushed
+        drop(deaf);
+        wait_until(|| a.peer_manager.peer_count() == 2).await;
+        let results = a.block_manager.flush_cluster(false, None, None).await;
+        assert!(results.iter().all(|o| !o.local && o.error.is_none() && o.stats == Some(memsdk::FlushStats::default())), "{:?}", results);
+    }
+
+    #[tokio::test]
+    async fn test_ping_overtakes_queued_bulk_frames() {
+        use crate::net::outbound::{PeerSender, BULK_QUEUE};
+        use crate::net::secure_stream::{SecureReader, SecureWriter};
+        // Small socket buffers, so the writer stalls inside the first block until it is read
+        let listen = tokio::net::TcpSocket::new_v4().unwrap();
+        listen.set_recv_buffer_size(16 * 1024).unwrap();
+        listen.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = listen.listen(1).unwrap();
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.set_send_buffer_size(16 * 1024).unwrap();
+        let stream = socket.connect(listener.local_addr().unwrap()).await.unwrap();
+        let (peer, _) = listener.accept().await.unwrap();
+        let key = [7; 32];
+        let (sender, _writer) = PeerSender::spawn(SecureWriter::from_raw(stream.into_split().1, &key));
+
+        // One block being written and a full bulk lane behind it
+        for id in 0..=BULK_QUEUE as u64 {
+            let msg = super::Message::PutBlock { id, data: vec![id as u8; 256 * 1024].into(), durability: None, trace_id: None, priority: None };
+            sender.send(&msg).await.unwrap();
+        }
+        sender.send(&super::Message::Ping { nonce: 42 }).await.unwrap();
+
+        let mut reader = SecureReader::new(peer.into_split().0, &key, 1024 * 1024);
+        let mut order = Vec::new();
+        for _ in 0..BULK_QUEUE + 2 {
+            let msg: super::Message = bincode::deserialize(&reader.recv_frame().await.unwrap()).unwrap();
+            order.push(match msg {
+                super::Message::PutBlock { id, .. } => Some(id),
+                super::Message::Ping { .. } => None,
+                other => panic!("unexpected {:?}", other),
+            });
+        }
+        // Only the block already on the wire goes first; the rest keep their order
+        let mut expected: Vec<_> = (0..=BULK_QUEUE as u64).map(Some).collect();
+        expected.insert(1, None);
+        assert_eq!(order, expected);
+    }
+
+    // A peer driven by hand: handshake with `node` (approving its consent
+    // prompt) and hand back the encrypted ends of the connection.
+    async fn raw_peer(node: &TestNode, name: &str) -> (uuid::Uuid, super::secure_stream::SecureReader, super::secure_stream::SecureWriter) {
+        let (id, stream, session) = handshake_with(node, name).await;
+        let (reader, writer) = session.split(stream, super::frame::DEFAULT_MAX_FRAME);
+        (id, reader, writer)
+    }
+
+    // The connection of a peer `node` has just registered, before any framing
+    async fn handshake_with(node: &TestNode, name: &str) -> (uuid::Uuid, tokio::net::TcpStream, super::auth::Session) {
+        let consent = node.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(req) = consent.get_pending_list().into_iter().next() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let identity = Identity::new(uuid::Uuid::new_v4(), name.to_string());
+        let mut stream = tokio::net::TcpStream::connect(node.addr()).await.unwrap();
+        let session = handshake_initiator(&mut stream, &identity, 0, 0, None, || {}).await.unwrap();
+        wait_until(|| node.peer_manager.get_peer_id_by_name(name).is_some()).await;
+        (identity.node_id, stream, session)
+    }
+
+    async fn send(writer: &mut super::secure_stream::SecureWriter, msg: &super::Message) {
+        writer.send_frame(&bincode::serialize(msg).unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replies_around_bye_reach_waiters() {
+        let node = TestNode::spawn("bye", 1024 * 1024).await;
+        let pm = &node.peer_manager;
+
+        // Data sent right before and right after the peer's Bye is still delivered
+        let (peer, _reader, mut writer) = raw_peer(&node, "leaving").await;
+        let before = pm.wait_for_block(peer, 1);
+        let after = pm.wait_for_block(peer, 2);
+        send(&mut writer, &super::Message::BlockData { id: 1, data: Some(super::Bytes::from_static(&[1])), durability: None, trace_id: None, priority: None }).await;
+        send(&mut writer, &super::Message::Bye).await;
+        send(&mut writer, &super::Message::BlockData { id: 2, data: Some(super::Bytes::from_static(&[2])), durability: None, trace_id: None, priority: None }).await;
+        assert_eq!(before.await.unwrap().0, [1][..]);
+        assert_eq!(after.await.unwrap().0, [2][..]);
+        wait_until(|| pm.get_peer_id_by_name("leaving").is_none()).await;
+
+        // A waiter whose answer never comes fails once the peer is gone, not at the timeout
+        let (peer, _reader, mut writer) = raw_peer(&node, "quiet").await;
+        let start = Instant::now();
+        let waiting = pm.wait_for_block(peer, 3);
+        send(&mut writer, &super::Message::Bye).await;
+        let err = waiting.await.unwrap_err();
+        assert!(err.is::<crate::peers::PeerDisconnected>(), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(pm.wait_for_block(peer, 3).await.unwrap_err().is::<crate::peers::PeerDisconnected>());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_peer_frame_drops_the_peer() {
+        use tokio::io::AsyncWriteExt;
+        let node = TestNode::spawn("frames", 1024 * 1024).await;
+        node.peer_manager.set_max_peer_frame(1024 * 1024);
+
+        // A 4GB length prefix is refused as soon as it is read, not after the
+        // body fails to arrive or the read deadline passes
+        let (_, mut stream, _session) = handshake_with(&node, "liar").await;
+        let start = Instant::now();
+        stream.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        wait_until(|| node.peer_manager.get_peer_id_by_name("liar").is_none()).await;
+        assert!(start.elapsed() < Duration::from_secs(2));
+        // The node closes its end; anything it sent before that is drained
+        let closed = tokio::time::timeout(Duration::from_secs(2), tokio::io::AsyncReadExt::read_to_end(&mut stream, &mut Vec::new())).await;
+        assert!(closed.is_ok(), "the node kept the connection open");
+    }
+}
@@ -0,0 +1,109 @@
+//! Outgoing side of a peer connection.
+//!
+//! Each connection has one writer task that owns the `SecureWriter`. Senders
+//! queue serialized frames on one of two bounded lanes and never touch the
+//! socket themselves. The writer always takes a waiting control frame
+//! (keepalive, quota updates, acks) before the next bulk frame, so small
+//! messages are delayed by at most the frame being written, not by everything
+//! queued behind it. Bulk frames keep their relative order, which is what
+//! reads-after-writes and Flush/Bye rely on. A full bulk lane makes senders
+//! wait, which pushes back on whoever is offloading data.
+
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use super::secure_stream::SecureWriter;
+use super::Message;
+
+/// Control frames that may wait for the writer.
+const CONTROL_QUEUE: usize = 64;
+/// Bulk frames that may wait for the writer; each can be up to a full block.
+pub(crate) const BULK_QUEUE: usize = 8;
+/// How long `send_flushed` waits for a frame to reach the socket.
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Frame {
+    data: Vec<u8>,
+    // Told once the frame has been written
+    written: Option<oneshot::Sender<()>>,
+}
+
+/// Handle for queueing messages to one peer connection. Cheap to clone; the
+/// writer task stops once every handle is gone or a write fails.
+#[derive(Clone, Debug)]
+pub struct PeerSender {
+    control: mpsc::Sender<Frame>,
+    bulk: mpsc::Sender<Frame>,
+}
+
+// Messages that carry no data and nothing later traffic depends on
+fn is_control(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::Ping { .. }
+            | Message::Pong { .. }
+            | Message::UpdateQuota { .. }
+            | Message::QuotaAck { .. }
+            | Message::FlushAck { .. }
+            | Message::FlushRefused { .. }
+            | Message::BlockStored { .. }
+            | Message::KeyStored { .. }
+            | Message::PutBlockRejected { .. }
+    )
+}
+
+impl PeerSender {
+    /// Start the writer task for `writer`. Its handle finishes with the write
+    /// error that ended it, or `Ok` once every sender has been dropped.
+    pub fn spawn(writer: SecureWriter) -> (Self, JoinHandle<Result<()>>) {
+        let (control, control_rx) = mpsc::channel(CONTROL_QUEUE);
+        let (bulk, bulk_rx) = mpsc::channel(BULK_QUEUE);
+        let task = tokio::spawn(write_frames(writer, control_rx, bulk_rx));
+        (Self { control, bulk }, task)
+    }
+
+    /// Queue `msg`, waiting only if its lane is full.
+    pub async fn send(&self, msg: &Message) -> Result<()> {
+        self.queue(msg, None).await
+    }
+
+    /// Queue `msg` and wait until it has been written, e.g. a Bye before the
+    /// connection is dropped.
+    pub async fn send_flushed(&self, msg: &Message) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.queue(msg, Some(tx)).await?;
+        match tokio::time::timeout(FLUSH_TIMEOUT, rx).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(anyhow!("Peer connection closed before the message was sent")),
+            Err(_) => Err(anyhow!("Timed out sending to peer")),
+        }
+    }
+
+    /// Whether both handles feed the same connection.
+    pub fn same_connection(&self, other: &PeerSender) -> bool {
+        self.control.same_channel(&other.control)
+    }
+
+    async fn queue(&self, msg: &Message, written: Option<oneshot::Sender<()>>) -> Result<()> {
+        let frame = Frame { data: bincode::serialize(msg)?, written };
+        let lane = if is_control(msg) { &self.control } else { &self.bulk };
+        lane.send(frame).await.map_err(|_| anyhow!("Peer connection closed"))
+    }
+}
+
+async fn write_frames(mut writer: SecureWriter, mut control: mpsc::Receiver<Frame>, mut bulk: mpsc::Receiver<Frame>) -> Result<()> {
+    loop {
+        let frame = tokio::select! {
+            biased;
+            Some(frame) = control.recv() => frame,
+            Some(frame) = bulk.recv() => frame,
+            else => return Ok(()),
+        };
+        writer.send_frame(&frame.data).await?;
+        if let Some(written) = frame.written {
+            let _ = written.send(());
+        }
+    }
+}
@@ -2,13 +2,30 @@ use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use chacha20poly1305::aead::{Aead, KeyInit}; 
 use anyhow::Result;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use super::frame::{read_frame, IdleTimeout};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Frames and bytes through both directions of a session, shared with the
+/// peer registry (`memcli peer inspect`). Each frame is sealed under the next
+/// nonce of its direction, so the frame counts are also the nonce counters.
+#[derive(Debug, Default)]
+pub struct SessionCounters {
+    pub frames_sent: AtomicU64,
+    pub frames_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub bytes_received: AtomicU64,
+}
 
 pub struct SecureReader {
     inner: OwnedReadHalf,
     cipher: ChaCha20Poly1305,
     nonce_counter: u64,
+    max_frame: usize,
+    idle_timeout: Option<std::time::Duration>,
+    counters: Arc<SessionCounters>,
 }
 
 impl fmt::Debug for SecureReader {
@@ -20,24 +37,37 @@ impl fmt::Debug for SecureReader {
 }
 
 impl SecureReader {
-    pub fn new(inner: OwnedReadHalf, key: &[u8; 32]) -> Self {
+    /// `max_frame` bounds the ciphertext of a single frame; a peer announcing
+    /// more gets a `FrameTooLarge` error and should be disconnected.
+    pub fn new(inner: OwnedReadHalf, key: &[u8; 32], max_frame: usize) -> Self {
         Self {
             inner,
             cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
             nonce_counter: 0,
+            max_frame,
+            idle_timeout: None,
+            counters: Arc::default(),
         }
     }
 
+    /// Fail `recv_frame` once the peer sends nothing for `idle`; see [`IdleTimeout`].
+    pub fn set_idle_timeout(&mut self, idle: std::time::Duration) {
+        self.idle_timeout = Some(idle);
+    }
+
+    /// Count received frames in `counters`.
+    pub fn counting(mut self, counters: Arc<SessionCounters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
     /// Reads a length-prefixed, encrypted frame and returns the decrypted plaintext.
     pub async fn recv_frame(&mut self) -> Result<Vec<u8>> {
-        // 1. Read Length (4 bytes)
-        let mut len_buf = [0u8; 4];
-        self.inner.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-
-        // 2. Read Ciphertext (len bytes)
-        let mut buf = vec![0u8; len];
-        self.inner.read_exact(&mut buf).await?;
+        // 1-2. Read Length and Ciphertext
+        let buf = match self.idle_timeout {
+            Some(idle) => read_frame(&mut IdleTimeout::new(&mut self.inner, idle), self.max_frame).await?,
+            None => read_frame(&mut self.inner, self.max_frame).await?,
+        };
 
         // 3. Construct Nonce
         let mut nonce_bytes = [0u8; 12];
@@ -51,6 +81,8 @@ impl SecureReader {
 
         // Increment nonce
         self.nonce_counter += 1;
+        self.counters.frames_received.fetch_add(1, Ordering::Relaxed);
+        self.counters.bytes_received.fetch_add(4 + buf.len() as u64, Ordering::Relaxed);
 
         Ok(plaintext)
     }
@@ -60,6 +92,7 @@ pub struct SecureWriter {
     inner: BufWriter<OwnedWriteHalf>,
     cipher: ChaCha20Poly1305,
     nonce_counter: u64,
+    counters: Arc<SessionCounters>,
 }
 
 impl fmt::Debug for SecureWriter {
@@ -76,8 +109,15 @@ impl SecureWriter {
             inner,
             cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
             nonce_counter: 0,
+            counters: Arc::default(),
         }
     }
+
+    /// Count sent frames in `counters`.
+    pub fn counting(mut self, counters: Arc<SessionCounters>) -> Self {
+        self.counters = counters;
+        self
+    }
     
     // Helper to accept raw inner without bufwriter wrapping (it wraps it internally)
     pub fn from_raw(inner: OwnedWriteHalf, key: &[u8; 32]) -> Self {
@@ -105,6 +145,8 @@ impl SecureWriter {
 
         // Increment nonce
         self.nonce_counter += 1;
+        self.counters.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.counters.bytes_sent.fetch_add(4 + len as u64, Ordering::Relaxed);
 
         Ok(())
     }
@@ -0,0 +1,343 @@
+//! Starting and stopping a node in-process: the bootstrap behind the `memnode`
+//! binary, exposed so embedders and integration tests can run real nodes
+//! without spawning the binary.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::blocks::InMemoryBlockManager;
+use crate::config::{Args, DiscoveryMode, NodeConfig};
+use crate::discovery::MdnsDiscovery;
+use crate::health::Health;
+use crate::net::auth::Identity;
+use crate::peers::trusted::TrustedStore;
+use crate::peers::PeerManager;
+use crate::reload::ConfigReloader;
+use crate::{net, rpc};
+use memsdk::MemCloudClient;
+
+/// Settings for a [`Node`], started with [`NodeBuilder::spawn`] (or
+/// [`NodeBuilder::embedded`]). Starts from the same defaults as `memnode`
+/// without arguments, except that on Unix the TCP RPC listener is off, the
+/// RSS guard is disabled and nothing is saved to the data directory, so
+/// several nodes can run in one process.
+pub struct NodeBuilder {
+    config: NodeConfig,
+    local_only: bool,
+    trust_all: bool,
+    // Set once a socket or RPC address was chosen, which an embedded node then serves
+    rpc_requested: bool,
+}
+
+impl NodeBuilder {
+    /// Use settings already resolved from the command line and config file.
+    pub fn from_config(config: NodeConfig) -> Self {
+        Self { config, local_only: false, trust_all: false, rpc_requested: false }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.config.name = name.into();
+        self
+    }
+
+    /// Memory the node stores blocks in; also the default quota offered to peers.
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.config.memory = bytes;
+        self.config.default_allowed_quota = bytes;
+        self
+    }
+
+    /// Peer transport port. 0 picks a free one; see [`Node::port`].
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Unix socket the RPC server listens on.
+    pub fn socket(mut self, path: impl AsRef<Path>) -> Self {
+        self.config.socket = path.as_ref().to_string_lossy().into_owned();
+        self.rpc_requested = true;
+        self
+    }
+
+    /// Also listen on this unix socket for clients that may only read.
+    pub fn readonly_socket(mut self, path: impl AsRef<Path>) -> Self {
+        self.config.rpc_readonly = Some(path.as_ref().to_string_lossy().into_owned());
+        self.rpc_requested = true;
+        self
+    }
+
+    /// Also serve RPC over TCP on this address (`None` for the unix socket only).
+    pub fn rpc_addr(mut self, addr: Option<String>) -> Self {
+        self.rpc_requested |= addr.is_some();
+        self.config.rpc_addr = addr;
+        self
+    }
+
+    /// Process RSS past which the node sheds cache and refuses stores
+    /// (`soft`) and new streams (`hard`); 0 disables a level. RSS covers the
+    /// whole process, every node in it included.
+    pub fn rss_limits(mut self, soft: u64, hard: u64) -> Self {
+        self.config.rss_soft_limit = soft;
+        self.config.rss_hard_limit = hard;
+        self
+    }
+
+    /// Listen on localhost only and skip mDNS, so the node neither finds nor
+    /// is found by anything else on the network.
+    pub fn local_only(mut self, local_only: bool) -> Self {
+        self.local_only = local_only;
+        self
+    }
+
+    /// Accept every incoming peer without a consent prompt. For tests only.
+    pub fn trust_all(mut self, trust_all: bool) -> Self {
+        self.trust_all = trust_all;
+        self
+    }
+
+    /// Start the node. Returns once the RPC server and the peer transport accept connections.
+    pub async fn spawn(self) -> Result<Node> {
+        self.start(true).await
+    }
+
+    /// Start the node and return a client that owns it, for programs that
+    /// want a node of their own without running the daemon. The client's
+    /// commands are handed to the node directly. No RPC socket is bound unless
+    /// one was set with [`socket`](Self::socket) or [`rpc_addr`](Self::rpc_addr),
+    /// so this can run next to a `memnode` daemon. Dropping the client shuts
+    /// the node down; use [`spawn`](Self::spawn) and [`Node::client`] to keep
+    /// a handle on it instead.
+    pub async fn embedded(self) -> Result<MemCloudClient> {
+        let serve_rpc = self.rpc_requested;
+        let node = self.start(serve_rpc).await?;
+        let connection = rpc::DirectConnection::new(node.block_manager.clone(), node.rpc_limits.clone(), Some(Box::new(ShutdownOnDrop(Some(node)))));
+        Ok(MemCloudClient::embedded(connection))
+    }
+
+    async fn start(self, serve_rpc: bool) -> Result<Node> {
+        let mut args = self.config;
+        if self.local_only {
+            args.bind = "127.0.0.1".to_string();
+            args.discovery_mode = DiscoveryMode::Manual;
+        }
+        let (shutdown, stop) = watch::channel(false);
+
+        // Peers pin this node's key, so it is kept across restarts when there is somewhere to keep it
+        let peer_manager = match &args.data_dir {
+            Some(dir) => {
+                let identity = Identity::load_or_create(&dir.join("identity.json"), args.name.clone())?;
+                PeerManager::with_identity(identity, TrustedStore::open(dir.join("trusted_devices.json")))
+            }
+            None => {
+                warn!("No data directory; this node's identity and trusted devices will not outlive it");
+                PeerManager::new(Uuid::new_v4(), args.name.clone())
+            }
+        };
+        let node_id = peer_manager.get_identity().node_id;
+        let peer_manager = Arc::new(peer_manager);
+        peer_manager.set_keepalive_interval(std::time::Duration::from_secs(args.keepalive_interval));
+        peer_manager.set_max_peer_frame(args.peer_max_frame);
+        peer_manager.set_default_quota(args.default_allowed_quota);
+        peer_manager.consent_manager.set_timeout(std::time::Duration::from_secs(args.consent_timeout));
+        peer_manager.consent_manager.set_auto_approve(self.trust_all);
+
+        let block_manager = Arc::new(InMemoryBlockManager::new(peer_manager.clone(), args.memory));
+        let health = block_manager.health.clone();
+        block_manager.memory_guard.set_limits(args.rss_soft_limit, args.rss_hard_limit);
+        block_manager.set_orphan_gc_idle(args.orphan_gc_idle);
+        block_manager.set_read_only(args.read_only);
+        block_manager.set_checksum_algo(args.checksum_algo);
+        block_manager.set_stream_idle_timeout(args.stream_idle_timeout);
+        block_manager.set_max_stream_memory(args.max_stream_memory);
+        if let Some(reloader) = ConfigReloader::new(&args) {
+            let _ = block_manager.config_reloader.set(reloader);
+        }
+
+        let rpc_limits = rpc::RpcLimits::new(args.rpc_pipeline_depth, args.rpc_max_heavy, args.rpc_heavy_threshold, args.rpc_max_frame, args.rpc_max_streams);
+        let mut critical = Vec::new();
+        let mut rpc_sockets = Vec::new();
+        if serve_rpc {
+            let rpc_server = rpc::RpcServer::new(
+                &args.socket,
+                args.rpc_readonly.clone(),
+                args.rpc_addr.clone(),
+                rpc::RpcTokens {
+                    admin: args.rpc_token.clone(),
+                    write: args.rpc_write_token.clone(),
+                    read: args.rpc_read_token.clone(),
+                },
+                rpc_limits.clone(),
+                block_manager.clone(),
+            ).with_socket_perms(rpc::SocketPerms { mode: args.socket_mode, group: args.socket_group.clone() });
+            let listeners = rpc_server.bind().await.context("Failed to start RPC server")?;
+            critical.push(health.supervise("rpc", until_stopped(stop.clone(), async move {
+                rpc_server.serve(listeners).await
+            })));
+            rpc_sockets.extend(std::iter::once(args.socket.clone()).chain(args.rpc_readonly.clone()));
+        } else {
+            info!("Embedded node; not serving RPC on a socket");
+        }
+
+        let (transport, actual_port) = net::TransportServer::bind(&args.bind, args.port, block_manager.clone(), peer_manager.clone()).await?;
+        if actual_port != args.port {
+            info!("Required port {} was busy, bound to {} instead", args.port, actual_port);
+        }
+        peer_manager.set_listen_port(actual_port);
+        info!("Starting MemCloud Node {} on port {}", node_id, actual_port);
+
+        // Keep the daemon alive for the lifetime of the node; dropping it stops advertising.
+        let discovery = if args.discovery_mode == DiscoveryMode::Mdns {
+            let discovery = Arc::new(MdnsDiscovery::new(node_id, actual_port, peer_manager.clone(), block_manager.clone(), args.auto_connect_untrusted, std::time::Duration::from_secs(args.mdns_readvertise_interval))?);
+            discovery.start_advertising()?;
+            discovery.start_browsing()?;
+            discovery.start_supervisor();
+            let daemon = discovery.clone();
+            health.add_check("mdns", Arc::new(move || {
+                if daemon.daemon_running(std::time::Duration::from_millis(500)) {
+                    Ok(())
+                } else {
+                    Err("daemon not responding".to_string())
+                }
+            }));
+            Some(discovery)
+        } else {
+            info!("Discovery mode is manual; mDNS advertising and browsing disabled");
+            None
+        };
+
+        let ttl_handle = health.supervise("ttl-sweeper", until_stopped(stop.clone(), block_manager.clone().run_ttl_sweeper()));
+        let decay_handle = health.supervise("hit-decay", until_stopped(stop.clone(), block_manager.clone().run_hit_decay()));
+        let guard_handle = health.supervise("memory-guard", until_stopped(stop.clone(), block_manager.clone().run_memory_guard()));
+        let orphan_handle = health.supervise("orphan-gc", until_stopped(stop.clone(), block_manager.clone().run_orphan_gc()));
+        let reaper_handle = health.supervise("stream-reaper", until_stopped(stop.clone(), block_manager.clone().run_stream_reaper()));
+        let sampler_handle = health.supervise("stat-sampler", until_stopped(stop.clone(), block_manager.clone().run_stat_sampler()));
+        let background = vec![ttl_handle, decay_handle, guard_handle, orphan_handle, reaper_handle, sampler_handle];
+        critical.push(health.supervise("transport", until_stopped(stop, async move { transport.run().await })));
+
+        Ok(Node {
+            id: node_id,
+            port: actual_port,
+            socket_path: args.socket,
+            rpc_sockets,
+            peer_manager,
+            block_manager,
+            rpc_limits,
+            health,
+            discovery,
+            shutdown,
+            critical,
+            background,
+        })
+    }
+}
+
+/// A running node. Dropping it leaves the node running for as long as the
+/// runtime does; call [`Node::shutdown`] to stop it.
+pub struct Node {
+    id: Uuid,
+    port: u16,
+    socket_path: String,
+    // Sockets the RPC server listens on, removed at shutdown
+    rpc_sockets: Vec<String>,
+    peer_manager: Arc<PeerManager>,
+    block_manager: Arc<InMemoryBlockManager>,
+    rpc_limits: rpc::RpcLimits,
+    health: Arc<Health>,
+    discovery: Option<Arc<MdnsDiscovery>>,
+    shutdown: watch::Sender<bool>,
+    // Transport and RPC: the node is of no use once either has ended
+    critical: Vec<JoinHandle<()>>,
+    background: Vec<JoinHandle<()>>,
+}
+
+impl Node {
+    pub fn builder() -> NodeBuilder {
+        let mut config = Args::default().resolve().expect("default config is valid");
+        if cfg!(unix) {
+            config.rpc_addr = None;
+        }
+        config.rss_soft_limit = 0;
+        config.rss_hard_limit = 0;
+        config.data_dir = None;
+        NodeBuilder::from_config(config)
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The port the peer transport actually bound.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
+
+    /// A client whose commands are handed to this node directly, with admin
+    /// access, as if it had connected to the node's socket.
+    pub fn client(&self) -> MemCloudClient {
+        MemCloudClient::embedded(rpc::DirectConnection::new(self.block_manager.clone(), self.rpc_limits.clone(), None))
+    }
+
+    /// Resolves if the transport or the RPC server stops on its own, or a
+    /// client sent `Shutdown`.
+    pub async fn stopped(&mut self) {
+        tokio::select! {
+            _ = futures::future::select_all(self.critical.iter_mut()) => {}
+            _ = self.block_manager.shutdown_requested() => {}
+        }
+    }
+
+    /// Say Bye to every peer, stop all tasks and remove the RPC sockets.
+    pub async fn shutdown(self) {
+        self.peer_manager.events.record("shutdown", "stopping", None, None, format!("{} peers connected", self.peer_manager.peer_count()));
+        self.peer_manager.disconnect_all().await;
+        if let Some(discovery) = &self.discovery {
+            discovery.shutdown();
+        }
+        self.health.set_stopping();
+        let _ = self.shutdown.send(true);
+        for handle in self.critical.into_iter().chain(self.background) {
+            if !handle.is_finished() {
+                let _ = handle.await;
+            }
+        }
+        #[cfg(unix)]
+        for path in &self.rpc_sockets {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// The node behind an embedded client, shut down when the client is dropped
+struct ShutdownOnDrop(Option<Node>);
+
+impl Drop for ShutdownOnDrop {
+    fn drop(&mut self) {
+        let Some(node) = self.0.take() else { return };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(node.shutdown());
+            }
+            // Without a runtime the node's tasks are gone already
+            Err(_) => warn!("Embedded node {} dropped outside a Tokio runtime", node.id),
+        }
+    }
+}
+
+// Run `task` until it finishes or the node is shut down.
+async fn until_stopped(mut stop: watch::Receiver<bool>, task: impl Future<Output = ()>) {
+    tokio::select! {
+        _ = task => {}
+        _ = stop.changed() => {}
+    }
+}
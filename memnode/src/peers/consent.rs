@@ -1,10 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use tokio::sync::broadcast;
 use uuid::Uuid;
-use std::time::Instant;
+use std::time::Duration;
 use anyhow::Result;
 use log::{info, warn};
+use crate::events::EventLog;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConsentDecision {
@@ -19,37 +21,98 @@ pub struct PendingConsent {
     pub session_id: String,
     pub peer_pubkey: String,
     pub peer_name: String,
+    // What the peer offers us, and what accepting lets it store here
     pub quota: u64,
+    pub allowed_quota: u64,
     pub created_at: u64,
 }
 
+/// Ended requests remembered, so answering one late says why it is gone.
+const ENDED_KEPT: usize = 64;
+
+// Why a request left the pending list without an answer
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ended {
+    Expired(u64),
+    Withdrawn,
+}
+
 pub struct ConsentManager {
     pending: Arc<Mutex<HashMap<String, PendingConsent>>>,
     notifier: broadcast::Sender<(String, ConsentDecision)>,
+    // Skip the prompt and accept every peer (in-process test clusters)
+    auto_approve: AtomicBool,
+    // Seconds before an unanswered request is denied; 0 waits forever
+    timeout_secs: AtomicU64,
+    // Requests that timed out or were given up by the peer, newest last
+    ended: Mutex<VecDeque<(String, Ended)>>,
+    pub(crate) events: Arc<EventLog>,
 }
 
 impl ConsentManager {
-    pub fn new() -> Self {
+    pub fn new(events: Arc<EventLog>) -> Self {
         let (tx, _) = broadcast::channel(100);
         Self {
             pending: Arc::new(Mutex::new(HashMap::new())),
             notifier: tx,
+            auto_approve: AtomicBool::new(false),
+            timeout_secs: AtomicU64::new(0),
+            ended: Mutex::new(VecDeque::new()),
+            events,
         }
     }
 
-    pub fn request_consent(&self, session_id: String, peer_pubkey: String, peer_name: String, quota: u64) {
+    pub fn set_auto_approve(&self, enabled: bool) {
+        self.auto_approve.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn auto_approve(&self) -> bool {
+        self.auto_approve.load(Ordering::Relaxed)
+    }
+
+    /// Applies to requests made afterwards. Zero waits forever.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout_secs.store(timeout.as_secs(), Ordering::Relaxed);
+    }
+
+    /// Ask the user about a peer offering `quota` bytes, which accepting lets
+    /// store `allowed_quota` bytes on this node.
+    pub fn request_consent(&self, session_id: String, peer_pubkey: String, peer_name: String, quota: u64, allowed_quota: u64) {
+        let detail = format!("session {}, key {}", session_id, super::key_fingerprint(&peer_pubkey));
+        self.events.record("consent", "requested", Some(&peer_name), Some(allowed_quota), detail);
         let mut lock = self.pending.lock().unwrap();
         lock.insert(session_id.clone(), PendingConsent {
             session_id,
             peer_pubkey: peer_pubkey.clone(),
             peer_name: peer_name.clone(),
             quota,
+            allowed_quota,
             created_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
         });
-        info!("Pending consent created for peer {} (key={}, quota={} bytes)", peer_name, peer_pubkey, quota);  
+        info!("Pending consent created for peer {} (key={}, quota={} bytes, allowed={} bytes)", peer_name, peer_pubkey, quota, allowed_quota);
     }
 
+    /// Denied once the consent timeout passes without an answer.
     pub async fn wait_for_decision(&self, session_id: &str) -> ConsentDecision {
+        let timeout = self.timeout_secs.load(Ordering::Relaxed);
+        if timeout == 0 {
+            return self.next_decision(session_id).await;
+        }
+        match tokio::time::timeout(Duration::from_secs(timeout), self.next_decision(session_id)).await {
+            Ok(decision) => decision,
+            Err(_) => {
+                let expired = self.pending.lock().unwrap().remove(session_id);
+                if let Some(request) = expired {
+                    self.remember_ended(session_id, Ended::Expired(timeout));
+                    self.events.record("consent", "expired", Some(&request.peer_name), None, format!("not answered within {}s", timeout));
+                }
+                info!("Consent request {} was not answered within {}s; denying", session_id, timeout);
+                ConsentDecision::Denied
+            }
+        }
+    }
+
+    async fn next_decision(&self, session_id: &str) -> ConsentDecision {
         let mut rx = self.notifier.subscribe();
         loop {
             match rx.recv().await {
@@ -67,14 +130,42 @@ impl ConsentManager {
     }
 
     pub fn resolve(&self, session_id: &str, decision: ConsentDecision) -> Result<()> {
-        let mut lock = self.pending.lock().unwrap();
-        if lock.remove(session_id).is_some() {
+        let removed = self.pending.lock().unwrap().remove(session_id);
+        if let Some(request) = removed {
+            let (action, detail) = match decision {
+                ConsentDecision::ApprovedOnce => ("approved", format!("session {}", session_id)),
+                ConsentDecision::ApprovedAndTrusted => ("approved", format!("session {}, trusted from now on", session_id)),
+                ConsentDecision::Denied | ConsentDecision::Pending => ("denied", format!("session {}", session_id)),
+            };
+            self.events.record("consent", action, Some(&request.peer_name), None, detail);
             // Notify waiters
             let _ = self.notifier.send((session_id.to_string(), decision));
-            Ok(())
-        } else {
-            anyhow::bail!("No pending request for session {}", session_id);
+            return Ok(());
+        }
+        let ended = self.ended.lock().unwrap().iter().rev().find(|(id, _)| id == session_id).map(|(_, why)| *why);
+        match ended {
+            Some(Ended::Expired(secs)) => anyhow::bail!("Consent request {} expired: it was not answered within {}s and the peer was denied", session_id, secs),
+            Some(Ended::Withdrawn) => anyhow::bail!("Consent request {} was withdrawn: the peer stopped waiting for an answer", session_id),
+            None => anyhow::bail!("No pending request for session {}", session_id),
+        }
+    }
+
+    /// Drop a request that can no longer be answered, e.g. because the peer hung up.
+    pub fn withdraw(&self, session_id: &str) -> bool {
+        let Some(request) = self.pending.lock().unwrap().remove(session_id) else {
+            return false;
+        };
+        self.remember_ended(session_id, Ended::Withdrawn);
+        self.events.record("consent", "withdrawn", Some(&request.peer_name), None, "the peer stopped waiting");
+        true
+    }
+
+    fn remember_ended(&self, session_id: &str, why: Ended) {
+        let mut ended = self.ended.lock().unwrap();
+        if ended.len() == ENDED_KEPT {
+            ended.pop_front();
         }
+        ended.push_back((session_id.to_string(), why));
     }
 
     pub fn get_pending_list(&self) -> Vec<PendingConsent> {
@@ -82,3 +173,24 @@ impl ConsentManager {
         lock.values().cloned().collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_answering_an_ended_request_says_why() {
+        let consent = ConsentManager::new(Arc::default());
+        consent.set_timeout(Duration::from_secs(1));
+        consent.request_consent("late".to_string(), "ab".repeat(32), "laptop".to_string(), 100, 200);
+        assert_eq!(consent.get_pending_list()[0].allowed_quota, 200);
+        assert_eq!(consent.wait_for_decision("late").await, ConsentDecision::Denied);
+        let err = consent.resolve("late", ConsentDecision::ApprovedOnce).unwrap_err().to_string();
+        assert!(err.contains("expired") && err.contains("1s"), "{}", err);
+
+        consent.request_consent("gone".to_string(), "cd".repeat(32), "phone".to_string(), 0, 0);
+        assert!(consent.withdraw("gone"));
+        assert!(consent.resolve("gone", ConsentDecision::Denied).unwrap_err().to_string().contains("withdrawn"));
+        assert!(consent.resolve("never", ConsentDecision::Denied).unwrap_err().to_string().starts_with("No pending request"));
+    }
+}
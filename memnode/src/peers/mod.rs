@@ -1,22 +1,43 @@
 use uuid::Uuid;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use dashmap::DashMap;
 use tokio::net::TcpStream;
-use crate::net::Message;
+use crate::net::{Message, NodeStat};
 use crate::blocks::BlockManager;
 use log::{info, error, warn};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::{Serialize, Deserialize};
 
 use tokio::io::BufWriter;
-use crate::net::auth::{Identity, handshake_initiator};
-use crate::net::secure_stream::SecureWriter;
+use crate::net::auth::{ConnectedToSelf, Identity, IdentityChanged, KeyPinning, Session, handshake_initiator};
+use crate::net::outbound::PeerSender;
+use crate::net::secure_stream::SessionCounters;
 
 pub mod trusted;
 pub mod consent;
 use trusted::TrustedStore;
 use consent::ConsentManager;
+use crate::events::EventLog;
+
+/// How often each peer connection is pinged.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Unanswered pings after which a peer is marked unhealthy.
+pub const KEEPALIVE_UNHEALTHY_AFTER: u32 = 2;
+/// Unanswered pings after which the connection is dropped, even though the
+/// peer still sends other traffic.
+pub const KEEPALIVE_MAX_MISSED: u32 = 3;
+/// A connection that receives nothing for this many intervals is closed.
+pub const KEEPALIVE_READ_DEADLINE_INTERVALS: u32 = 2;
+/// After a Bye, what the peer sent before seeing ours is still read until it
+/// has been quiet this long.
+pub const BYE_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64
+}
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum HandshakeState {
@@ -24,6 +45,117 @@ pub enum HandshakeState {
     WaitingForConsent,
     Authenticated,
     Failed(String),
+    /// The peer presented a different key than the one pinned for it.
+    IdentityChanged(String),
+}
+
+/// How an outgoing connection treats the key the peer presents. Keys are
+/// pinned on first contact either way.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DialPolicy {
+    /// Replace a pinned key that no longer matches instead of failing.
+    pub accept_new_key: bool,
+    /// Only connect if the key is already pinned or trusted (mDNS auto-connect).
+    pub require_known: bool,
+}
+
+impl HandshakeState {
+    pub fn in_flight(&self) -> bool {
+        matches!(self, HandshakeState::Connecting | HandshakeState::WaitingForConsent)
+    }
+}
+
+/// Data read from a peer, with the durability and eviction priority it holds
+/// it under (`None` when the peer did not say).
+pub type RemoteValue = (bytes::Bytes, Option<memsdk::Durability>, Option<u8>);
+// An answer to a key lookup and the peer giving it; `None` when it does not have the key
+type KeyReply = (Uuid, Option<RemoteValue>);
+
+/// A reply was still outstanding when the peer that owed it went away.
+#[derive(Debug)]
+pub struct PeerDisconnected {
+    pub what: &'static str,
+}
+
+impl std::fmt::Display for PeerDisconnected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Peer disconnected before sending the {}", self.what)
+    }
+}
+
+impl std::error::Error for PeerDisconnected {}
+
+/// How long `list_remote_keys` waits for peers to answer.
+pub const REMOTE_LIST_TIMEOUT: Duration = Duration::from_secs(2);
+/// Keys one peer sends, and is accepted from, for a single `ListKeys`.
+pub const REMOTE_LIST_LIMIT: usize = 10_000;
+/// How long `flush_peer` waits for the peer to confirm the flush.
+pub const FLUSH_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `wait_for_key` waits for peers to answer a key lookup.
+pub const KEY_LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long `request_stats` waits for peers to answer.
+pub const CLUSTER_STAT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Keys one peer reported for a `ListKeys` request.
+#[derive(Debug, Clone)]
+pub struct PeerKeys {
+    pub name: String,
+    pub keys: Vec<String>,
+    /// The peer had more matching keys than `REMOTE_LIST_LIMIT`.
+    pub truncated: bool,
+}
+
+// A peer's KeyList: who sent it, the keys, and whether there were more
+type KeyListReply = (Uuid, Vec<String>, bool);
+
+/// How one peer answered a `request_stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerStatReply {
+    Answered(NodeStat),
+    /// Not connected, or no answer within `CLUSTER_STAT_TIMEOUT`
+    Unreachable,
+    /// Connected with a protocol older than `auth::STATS_VERSION`
+    Unsupported,
+}
+
+/// How long a failed outgoing attempt stays visible to `PollConnection`.
+pub const HANDSHAKE_FAILED_TTL: Duration = Duration::from_secs(300);
+
+/// The addresses to dial for `addr`: an `ip:port` as is, else what
+/// `host:port` resolves to (DNS, or mDNS `.local` names where the system
+/// resolver handles them), in the resolver's order.
+pub async fn resolve_peer_addrs(addr: &str) -> Result<Vec<SocketAddr>> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+    if !addr.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()) {
+        bail!("Invalid address '{}'; use ip:port or host:port", addr);
+    }
+    let mut addrs: Vec<SocketAddr> = match tokio::net::lookup_host(addr).await {
+        Ok(resolved) => resolved.collect(),
+        Err(e) => bail!("Could not resolve '{}': {}", addr, e),
+    };
+    let mut seen = std::collections::HashSet::new();
+    addrs.retain(|a| seen.insert(*a));
+    if addrs.is_empty() {
+        bail!("'{}' did not resolve to any address", addr);
+    }
+    Ok(addrs)
+}
+
+/// The address an attempt to connect to `addrs` is tracked under. The lowest
+/// one, so every lookup of a name agrees on it whatever order round-robin
+/// DNS hands the addresses out in.
+pub fn attempt_addr(addrs: &[SocketAddr]) -> SocketAddr {
+    addrs.iter().min().copied().expect("resolved addresses are never empty")
+}
+
+/// An outgoing connection attempt, kept so clients can poll it.
+pub struct OutgoingHandshake {
+    pub state: HandshakeState,
+    updated: Instant,
+    // The task running a Connect RPC's attempt, so it can be cancelled
+    task: Option<tokio::task::AbortHandle>,
 }
 
 #[derive(Debug, Clone)]
@@ -32,13 +164,46 @@ pub struct PeerInfo {
     pub id: Uuid,
     pub addr: SocketAddr,
     pub name: String,
+    pub public_key: String,
     pub total_memory: u64,
     pub used_memory: u64,
     pub ram_quota: u64, // What they can store on US
     pub remote_chunk_size: u64, // Future use?
     pub remote_quota: u64, // What WE can store on THEM
     pub remote_used_storage: u64,
-    pub connection: Option<Arc<tokio::sync::Mutex<SecureWriter>>>, 
+    pub connection: Option<PeerSender>,
+    // Keepalive: pings sent since the last pong, and whether that is still acceptable
+    pub missed_pings: u32,
+    pub healthy: bool,
+    // Nonce and send time of the newest ping, until its pong arrives
+    pub ping_sent: Option<(u64, Instant)>,
+    pub last_rtt: Option<Duration>,
+    // Unix millis of the last pong, or of connecting before the first one
+    pub last_seen: u64,
+    pub handshake: HandshakeSummary,
+}
+
+/// What the handshake with a peer settled, and the traffic of the session
+/// since. The session keys stay with the connection's reader and writer.
+#[derive(Debug, Clone)]
+pub struct HandshakeSummary {
+    pub protocol_version: u16,
+    pub initiated: bool,
+    // Unix millis
+    pub established_at: u64,
+    pub counters: Arc<SessionCounters>,
+}
+
+/// Short, stable name for a hex-encoded identity key, safe to show and log.
+pub fn key_fingerprint(public_key: &str) -> String {
+    let bytes = hex::decode(public_key).unwrap_or_else(|_| public_key.as_bytes().to_vec());
+    format!("blake3:{}", hex::encode(&blake3::hash(&bytes).as_bytes()[..16]))
+}
+
+impl PeerInfo {
+    pub fn rtt_micros(&self) -> Option<u64> {
+        self.last_rtt.map(|d| d.as_micros() as u64)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -46,39 +211,93 @@ pub struct PeerMetadata {
     pub id: String,
     pub name: String,
     pub addr: String,
+    pub public_key: String,
     pub total_memory: u64,
     pub used_memory: u64,
     pub quota: u64, // Remote quota available to us
     pub allowed_quota: u64, // Quota we allow them
+    pub healthy: bool,
+    pub last_rtt_micros: Option<u64>,
+    pub last_seen: u64,
 }
 
+// Callers waiting for a peer's reply, by the request id it echoes, with the peer asked
+type ReplyWaiters<S> = DashMap<u64, (Uuid, S)>;
+
 pub struct PeerManager {
     peers: Arc<DashMap<Uuid, PeerInfo>>,
-    pending_requests: Arc<DashMap<crate::metadata::BlockId, tokio::sync::broadcast::Sender<Vec<u8>>>>,
-    pending_key_requests: Arc<DashMap<String, tokio::sync::broadcast::Sender<Vec<u8>>>>,
+    // Replies carry the durability the peer holds the data with, when it says
+    pending_requests: Arc<DashMap<crate::metadata::BlockId, tokio::sync::broadcast::Sender<RemoteValue>>>,
+    pending_key_requests: Arc<DashMap<String, tokio::sync::broadcast::Sender<KeyReply>>>,
     pending_key_writes: Arc<DashMap<String, tokio::sync::broadcast::Sender<crate::metadata::BlockId>>>,
+    // By the request id UpdateQuota was sent with, which QuotaAck echoes
+    pending_quota_acks: Arc<ReplyWaiters<tokio::sync::oneshot::Sender<(bool, u64)>>>,
+    // By the request id Flush was sent with, which FlushAck or FlushRefused echoes
+    pending_flush_acks: Arc<ReplyWaiters<tokio::sync::broadcast::Sender<Result<memsdk::FlushStats, String>>>>,
+    // Migrated blocks waiting for the peer's BlockStored or PutBlockRejected
+    pending_block_acks: Arc<DashMap<crate::metadata::BlockId, tokio::sync::broadcast::Sender<Result<(), String>>>>,
+    // Key listings in progress, by request id; every answering peer reports here
+    pending_key_lists: Arc<DashMap<u64, tokio::sync::mpsc::Sender<KeyListReply>>>,
+    next_list_request: AtomicU64,
+    // Stat requests in progress, by request id; shares ids with key listings and flushes
+    pending_stats: Arc<DashMap<u64, tokio::sync::mpsc::Sender<(Uuid, NodeStat)>>>,
+    // Every peer that leaves the registry, so waiters on its replies can give up
+    departures: tokio::sync::broadcast::Sender<Uuid>,
     self_id: Uuid,
     self_name: String,
     identity: Arc<Identity>,
     pub trusted_store: Arc<TrustedStore>,
     pub consent_manager: Arc<ConsentManager>,
-    pub outgoing_handshakes: Arc<DashMap<SocketAddr, HandshakeState>>,
+    pub events: Arc<EventLog>,
+    outgoing_handshakes: Arc<DashMap<SocketAddr, OutgoingHandshake>>,
+    keepalive_interval_ms: AtomicU64,
+    max_peer_frame: AtomicU64,
+    // Storage offered to peers that connect, or that discovery dials
+    default_quota: AtomicU64,
+    // Memory the quotas offered to peers must fit in together; 0 leaves them unchecked
+    capacity: AtomicU64,
+    // Transport port actually bound (0 until the listener is up)
+    listen_port: AtomicU16,
+    // Bytes we have offloaded to each peer, counted against the quota it granted us.
+    // Kept across reconnects, since the peer still holds the data.
+    offloaded: Arc<DashMap<Uuid, u64>>,
 }
 
 impl PeerManager {
+    /// A manager with a fresh identity whose trust decisions are not saved.
     pub fn new(self_id: Uuid, self_name: String) -> Self {
-        let identity = Arc::new(Identity::new(self_id, self_name.clone()));
+        Self::with_identity(Identity::new(self_id, self_name), TrustedStore::in_memory())
+    }
+
+    pub fn with_identity(identity: Identity, trusted_store: TrustedStore) -> Self {
+        let (self_id, self_name) = (identity.node_id, identity.name.clone());
+        let identity = Arc::new(identity);
+        let events = Arc::new(EventLog::default());
         Self {
             peers: Arc::new(DashMap::new()),
             pending_requests: Arc::new(DashMap::new()),
             pending_key_requests: Arc::new(DashMap::new()),
             pending_key_writes: Arc::new(DashMap::new()),
+            pending_quota_acks: Arc::new(DashMap::new()),
+            pending_flush_acks: Arc::new(DashMap::new()),
+            pending_block_acks: Arc::new(DashMap::new()),
+            pending_key_lists: Arc::new(DashMap::new()),
+            next_list_request: AtomicU64::new(1),
+            pending_stats: Arc::new(DashMap::new()),
+            departures: tokio::sync::broadcast::channel(64).0,
             self_id,
             self_name,
             identity, 
-            trusted_store: Arc::new(TrustedStore::new()),
-            consent_manager: Arc::new(ConsentManager::new()),
+            trusted_store: Arc::new(trusted_store),
+            consent_manager: Arc::new(ConsentManager::new(events.clone())),
+            events,
             outgoing_handshakes: Arc::new(DashMap::new()),
+            keepalive_interval_ms: AtomicU64::new(KEEPALIVE_INTERVAL.as_millis() as u64),
+            max_peer_frame: AtomicU64::new(crate::net::frame::DEFAULT_MAX_FRAME as u64),
+            default_quota: AtomicU64::new(0),
+            capacity: AtomicU64::new(0),
+            listen_port: AtomicU16::new(0),
+            offloaded: Arc::new(DashMap::new()),
         }
     }
 
@@ -90,18 +309,32 @@ impl PeerManager {
         sys_info::mem_info().map(|m| m.total * 1024).unwrap_or(0)
     }
     
-    pub async fn add_discovered_peer(&self, id: Uuid, addr: SocketAddr, block_manager: Arc<crate::blocks::InMemoryBlockManager>, peer_manager: Arc<PeerManager>, ram_quota: u64) -> Result<PeerMetadata> { 
-        // NOTE: Updated return type to include Metadata!
-        
+    pub async fn add_discovered_peer(&self, id: Uuid, addr: SocketAddr, block_manager: Arc<crate::blocks::InMemoryBlockManager>, peer_manager: Arc<PeerManager>, ram_quota: u64, policy: DialPolicy) -> Result<PeerMetadata> { 
+        let res = self.dial(id, addr, addr, block_manager, peer_manager, ram_quota, policy).await;
+        if let Err(e) = &res {
+            self.record_dial_failure(addr, e);
+        }
+        res
+    }
+
+    // Dial `addr`, reporting progress under `attempt` (the address the caller
+    // polls). Failures are left to the caller to record, since it may go on
+    // to another address.
+    #[allow(clippy::too_many_arguments)]
+    async fn dial(&self, id: Uuid, addr: SocketAddr, attempt: SocketAddr, block_manager: Arc<crate::blocks::InMemoryBlockManager>, peer_manager: Arc<PeerManager>, ram_quota: u64, policy: DialPolicy) -> Result<PeerMetadata> {
         if let Some(entry) = self.peers.get(&id) {
              return Ok(PeerMetadata {
                  id: entry.key().to_string(),
                  name: entry.value().name.clone(),
                  addr: entry.value().addr.to_string(),
+                 public_key: entry.value().public_key.clone(),
                  total_memory: entry.value().total_memory,
                  used_memory: entry.value().used_memory,
                  quota: entry.value().remote_quota,
                  allowed_quota: entry.value().ram_quota,
+                 healthy: entry.value().healthy,
+                 last_rtt_micros: entry.value().rtt_micros(),
+                 last_seen: entry.value().last_seen,
              });
         }
 
@@ -114,10 +347,14 @@ impl PeerManager {
                     id: entry.key().to_string(),
                     name: entry.value().name.clone(),
                     addr: entry.value().addr.to_string(),
+                    public_key: entry.value().public_key.clone(),
                     total_memory: entry.value().total_memory,
                     used_memory: entry.value().used_memory,
                     quota: entry.value().remote_quota,
                     allowed_quota: entry.value().ram_quota,
+                    healthy: entry.value().healthy,
+                    last_rtt_micros: entry.value().rtt_micros(),
+                    last_seen: entry.value().last_seen,
                 });
             }
         }
@@ -125,7 +362,7 @@ impl PeerManager {
         info!("Connecting to peer {} at {}", id, addr);
         
         // Track state immediately so CLI sees "pending" instead of "unknown"
-        self.outgoing_handshakes.insert(addr, HandshakeState::Connecting);
+        self.set_handshake_state(attempt, HandshakeState::Connecting);
         
         let connect_fut = TcpStream::connect(addr);
         let timeout_duration = std::time::Duration::from_secs(5);
@@ -135,35 +372,30 @@ impl PeerManager {
         match stream_res {
             Ok(Ok(mut stream)) => {
                 info!("Connected TCP to {}, starting handshake...", id);
+                crate::net::tune_socket(&stream);
                 
                 let sys_mem = self.get_total_system_memory();
+                let ram_quota = self.clamp_offer(ram_quota, None);
                 
                 let peers_clone = self.peers.clone(); 
-                let handshakes_clone = self.outgoing_handshakes.clone();
-                let addr_clone = addr; // Copy for closure
-
-                match handshake_initiator(&mut stream, &self.identity, ram_quota, sys_mem, move || {
-                    info!("Callback: Waiting for consent from {}", addr_clone);
-                    handshakes_clone.insert(addr_clone, HandshakeState::WaitingForConsent);
+                let pinning = KeyPinning { store: &self.trusted_store, addr, policy };
+                match handshake_initiator(&mut stream, &self.identity, ram_quota, sys_mem, Some(&pinning), || {
+                    info!("Callback: Waiting for consent from {}", addr);
+                    self.set_handshake_state(attempt, HandshakeState::WaitingForConsent);
                 }).await {
                     Ok(session) => {
                         info!("Handshake success with {}. Negotiated encryption.", session.peer_name);
                         
-                        let (reader, writer) = stream.into_split();
-                        
-                        use crate::net::secure_stream::{SecureReader, SecureWriter};
-                        let secure_reader = SecureReader::new(reader, &session.recv_key);
-                        let secure_writer = SecureWriter::from_raw(writer, &session.send_key);
+                        let (secure_reader, secure_writer) = session.split(stream, self.max_peer_frame());
                         
-                        let writer_arc = Arc::new(tokio::sync::Mutex::new(secure_writer));
+                        let (sender, writer_task) = PeerSender::spawn(secure_writer);
 
-                        let peer_id = session.peer_id;
-                        
-                        self.register_authenticated_peer(peer_id, addr, session.peer_name, writer_arc.clone(), ram_quota, session.peer_total_memory, session.peer_quota);
+                        let peer_id = self.register_authenticated_peer(&session, addr, sender.clone(), ram_quota)?;
+                        block_manager.resync_peer_storage(peer_id);
                         
                         use crate::net::handle_connection_split;
                         tokio::spawn(async move {
-                            if let Err(e) = handle_connection_split(secure_reader, writer_arc, addr, peer_id, block_manager, peer_manager).await {
+                            if let Err(e) = handle_connection_split(secure_reader, sender, writer_task, addr, peer_id, block_manager, peer_manager).await {
                                 error!("Connection error (outgoing) to {}: {}", addr, e);
                             }
                         });
@@ -172,46 +404,190 @@ impl PeerManager {
                             id: peer_id.to_string(),
                             name: "authenticated".to_string(), // Simplified, we don't return name in meta usually from this deep fn
                             addr: addr.to_string(),
+                            public_key: session.peer_public_key.clone(),
                             total_memory: session.peer_total_memory,
                             used_memory: 0,
                             quota: session.peer_quota,
                             allowed_quota: ram_quota,
+                            healthy: true,
+                            last_rtt_micros: None,
+                            last_seen: unix_millis(),
                         };
                         
-                        self.outgoing_handshakes.insert(addr, HandshakeState::Authenticated);
+                        self.set_handshake_state(attempt, HandshakeState::Authenticated);
                         
                         Ok(meta)
                     }
+                    Err(e) if e.is::<IdentityChanged>() => {
+                        error!("{}. Not connecting; if the peer was reinstalled, reconnect accepting its new key.", e);
+                        Err(e)
+                    }
+                    Err(e) if e.is::<ConnectedToSelf>() => {
+                        warn!("{} is this node's own address; not connecting", addr);
+                        Err(e)
+                    }
                     Err(e) => {
                         error!("Handshake failed with {}: {}", addr, e);
-                        self.outgoing_handshakes.insert(addr, HandshakeState::Failed(e.to_string()));
                         Err(anyhow::anyhow!("Handshake failed: {}", e))
                     }
                 }
             }
             Ok(Err(e)) => {
                 error!("TCP Connection failed to {}: {}", addr, e);
-                self.outgoing_handshakes.insert(addr, HandshakeState::Failed(format!("TCP Connect Error: {}", e)));
-                Err(anyhow::Error::new(e))
+                Err(anyhow::anyhow!("TCP Connect Error: {}", e))
             }
             Err(_) => {
                 error!("Connection timed out to {}", addr);
-                self.outgoing_handshakes.insert(addr, HandshakeState::Failed("Connection timed out".to_string()));
                 Err(anyhow::anyhow!("Connection timed out"))
             }
         }
     }
 
-    // ...
+    fn record_dial_failure(&self, attempt: SocketAddr, e: &anyhow::Error) {
+        let state = match e.is::<IdentityChanged>() {
+            true => HandshakeState::IdentityChanged(e.to_string()),
+            false => HandshakeState::Failed(e.to_string()),
+        };
+        self.set_handshake_state(attempt, state);
+    }
+
+    /// Connect to `addr_str`, an `ip:port` or a `host:port` to resolve.
+    pub async fn manual_connect(&self, addr_str: &str, block_manager: Arc<crate::blocks::InMemoryBlockManager>, peer_manager: Arc<PeerManager>, ram_quota: u64, policy: DialPolicy) -> Result<PeerMetadata> {
+        let addrs = resolve_peer_addrs(addr_str).await?;
+        self.connect_any(&addrs, block_manager, peer_manager, ram_quota, policy).await
+    }
 
-    pub async fn manual_connect(&self, addr_str: &str, block_manager: Arc<crate::blocks::InMemoryBlockManager>, peer_manager: Arc<PeerManager>, ram_quota: u64) -> Result<PeerMetadata> {
-        let addr: SocketAddr = addr_str.parse()?;
-        let id_placeholder = Uuid::nil();  // Use nil, we will get actual ID from handshake
-        self.add_discovered_peer(id_placeholder, addr, block_manager, peer_manager, ram_quota).await
+    /// Try `addrs` in order until one connects and handshakes. Progress is
+    /// reported under [`attempt_addr`] of the list. A changed peer key stops
+    /// the attempt rather than moving on to the next address.
+    pub async fn connect_any(&self, addrs: &[SocketAddr], block_manager: Arc<crate::blocks::InMemoryBlockManager>, peer_manager: Arc<PeerManager>, ram_quota: u64, policy: DialPolicy) -> Result<PeerMetadata> {
+        if addrs.is_empty() {
+            bail!("No address to connect to");
+        }
+        let attempt = attempt_addr(addrs);
+        let mut last_err = None;
+        for (i, &addr) in addrs.iter().enumerate() {
+            let id_placeholder = Uuid::nil();  // Use nil, we will get actual ID from handshake
+            match self.dial(id_placeholder, addr, attempt, block_manager.clone(), peer_manager.clone(), ram_quota, policy).await {
+                Ok(meta) => return Ok(meta),
+                // The other addresses lead to the same node
+                Err(e) if e.is::<IdentityChanged>() || e.is::<ConnectedToSelf>() => {
+                    last_err = Some(e);
+                    break;
+                }
+                Err(e) => {
+                    if i + 1 < addrs.len() {
+                        warn!("Could not connect to {} ({}), trying the next address", addr, e);
+                    }
+                    last_err = Some(e);
+                }
+            }
+        }
+        let e = last_err.expect("at least one address was tried");
+        self.record_dial_failure(attempt, &e);
+        Err(e)
     }
     
-    // Call from TransportServer after accepting an incoming authenticated connection
-    pub fn register_authenticated_peer(&self, id: Uuid, addr: SocketAddr, name: String, connection: Arc<tokio::sync::Mutex<SecureWriter>>, quota: u64, total_memory: u64, remote_quota: u64) {
+    /// State of the outgoing attempt to `addr`. A peer connected at `addr`
+    /// counts as authenticated even once its entry has been pruned.
+    pub fn handshake_state(&self, addr: SocketAddr) -> Option<HandshakeState> {
+        if self.peers.iter().any(|p| p.value().addr == addr) {
+            return Some(HandshakeState::Authenticated);
+        }
+        self.outgoing_handshakes.get(&addr).map(|h| h.state.clone())
+    }
+
+    fn set_handshake_state(&self, addr: SocketAddr, state: HandshakeState) {
+        if state == HandshakeState::Connecting {
+            self.prune_handshakes();
+        }
+        let now = Instant::now();
+        self.outgoing_handshakes.entry(addr)
+            .and_modify(|h| {
+                h.state = state.clone();
+                h.updated = now;
+            })
+            .or_insert_with(|| OutgoingHandshake { state, updated: now, task: None });
+    }
+
+    /// Start connecting to `addr` with the task from `spawn`, unless an attempt
+    /// is already in flight or the peer is connected. Returns the state to
+    /// report: the existing one, or `Connecting` for a new attempt. A failed
+    /// attempt is replaced.
+    pub fn start_outgoing(&self, addr: SocketAddr, spawn: impl FnOnce() -> tokio::task::AbortHandle) -> HandshakeState {
+        if self.peers.iter().any(|p| p.value().addr == addr) {
+            return HandshakeState::Authenticated;
+        }
+        self.prune_handshakes();
+        let now = Instant::now();
+        // Checked and claimed under the entry's lock, so concurrent Connects
+        // to one address start a single attempt. An authenticated entry whose
+        // peer has since gone may reconnect.
+        let mut attempt = match self.outgoing_handshakes.entry(addr) {
+            dashmap::mapref::entry::Entry::Occupied(e) if e.get().state.in_flight() => return e.get().state.clone(),
+            dashmap::mapref::entry::Entry::Occupied(mut e) => {
+                let h = e.get_mut();
+                h.state = HandshakeState::Connecting;
+                h.updated = now;
+                e.into_ref()
+            }
+            dashmap::mapref::entry::Entry::Vacant(e) => e.insert(OutgoingHandshake { state: HandshakeState::Connecting, updated: now, task: None }),
+        };
+        if let Some(previous) = attempt.task.replace(spawn()) {
+            previous.abort();
+        }
+        HandshakeState::Connecting
+    }
+
+    /// Abort the in-flight attempt to `addr`. Returns false if there was none.
+    pub fn cancel_outgoing(&self, addr: SocketAddr) -> bool {
+        let Some(mut h) = self.outgoing_handshakes.get_mut(&addr) else {
+            return false;
+        };
+        if !h.state.in_flight() {
+            return false;
+        }
+        if let Some(task) = h.task.take() {
+            task.abort();
+        }
+        h.state = HandshakeState::Failed("Cancelled".to_string());
+        h.updated = Instant::now();
+        info!("Cancelled connection attempt to {}", addr);
+        true
+    }
+
+    // Drop attempts nobody needs to poll any more: connected ones whose peer is
+    // registered, and failed ones after HANDSHAKE_FAILED_TTL
+    fn prune_handshakes(&self) {
+        let connected: std::collections::HashSet<SocketAddr> = self.peers.iter().map(|p| p.value().addr).collect();
+        self.outgoing_handshakes.retain(|addr, h| match h.state {
+            HandshakeState::Authenticated => !connected.contains(addr),
+            HandshakeState::Failed(_) | HandshakeState::IdentityChanged(_) => h.updated.elapsed() < HANDSHAKE_FAILED_TTL,
+            _ => true,
+        });
+    }
+
+    /// Add the peer behind an authenticated session and return the id it is
+    /// known by here. That is its own id unless a connected peer with another
+    /// key already uses it (cloned machines); then the new one gets an id
+    /// derived from its key and a name with the key in it, so both stay
+    /// reachable. This node's own id is refused.
+    pub fn register_authenticated_peer(&self, session: &Session, addr: SocketAddr, connection: PeerSender, quota: u64) -> Result<Uuid> {
+         let mut id = session.peer_id;
+         let mut name = session.peer_name.clone();
+         if id == self.identity.node_id {
+             bail!("Peer '{}' at {} uses this node's own id {}", name, addr, id);
+         }
+         let taken = self.peers.get(&id).is_some_and(|p| p.public_key != session.peer_public_key);
+         if taken {
+             let key_prefix = &session.peer_public_key[..session.peer_public_key.len().min(8)];
+             let local_id = key_derived_id(&session.peer_public_key);
+             error!("Node id {} is used by two peers with different keys; '{}' at {} (key {}) is listed as {}. Give one of them a new identity.json.",
+                 id, name, addr, session.peer_public_key, local_id);
+             id = local_id;
+             name = format!("{} [key {}]", name, key_prefix);
+         }
+         let remote_quota = session.peer_quota;
          let final_remote_quota = if remote_quota == 0 {
              if let Some(existing) = self.peers.get(&id) {
                  if existing.remote_quota > 0 {
@@ -227,44 +603,65 @@ impl PeerManager {
              remote_quota
          };
 
+         // Another handshake may have committed memory since this one started
+         let quota = self.clamp_offer(quota, Some(id));
+
          let info = PeerInfo {
              id, 
              addr,
              name,
-              total_memory,
+              public_key: session.peer_public_key.clone(),
+              total_memory: session.peer_total_memory,
               used_memory: 0,
               ram_quota: quota, 
               remote_chunk_size: 0,
               remote_quota: final_remote_quota,
               remote_used_storage: 0,
-              connection: Some(connection)
+              connection: Some(connection),
+              missed_pings: 0,
+              healthy: true,
+              ping_sent: None,
+              last_rtt: None,
+              last_seen: unix_millis(),
+              handshake: HandshakeSummary {
+                  protocol_version: session.peer_version,
+                  initiated: session.initiated,
+                  established_at: unix_millis(),
+                  counters: session.counters.clone(),
+              },
          };
+         let direction = if session.initiated { "outgoing" } else { "incoming" };
+         self.events.record("peer", "connected", Some(&info.name), None, format!("{} ({})", addr, direction));
          self.peers.insert(id, info);
+         Ok(id)
     }
 
-    pub fn handle_peer_disconnect(&self, peer_id: Uuid) {
-        if self.peers.remove(&peer_id).is_some() {
-             info!("Removed peer {} from registry (connection closed).", peer_id);
+    /// Drop the peer when `connection` closes, unless it has already been
+    /// replaced by a newer connection (the peer reconnected first). `reason`
+    /// says why the connection ended.
+    pub fn handle_peer_disconnect(&self, peer_id: Uuid, connection: &PeerSender, reason: &str) {
+        let current = |_: &Uuid, peer: &PeerInfo| peer.connection.as_ref().is_some_and(|c| c.same_connection(connection));
+        if let Some((_, peer)) = self.peers.remove_if(&peer_id, current) {
+             info!("Removed peer {} from registry ({}).", peer_id, reason);
+             self.events.record("peer", "disconnected", Some(&peer.name), None, reason);
+             let _ = self.departures.send(peer_id);
         }
     }
 
     pub async fn disconnect_peer(&self, peer_id: Uuid) -> bool {
-        // Try to send Bye
-        if let Some(peer) = self.peers.get(&peer_id) {
-             if let Some(conn) = &peer.connection {
-                 info!("Sending Bye to {}", peer_id);
-                 let msg = Message::Bye;
-                 if let Ok(data) = bincode::serialize(&msg) {
-                     // We need to lock.
-                     // Note: if handler is reading, writing should be fine (split).
-                     let mut writer = conn.lock().await;
-                     let _ = writer.send_frame(&data).await;
-                 }
+        // Try to send Bye, after anything already queued for the peer
+        let conn = self.peers.get(&peer_id).and_then(|peer| peer.connection.clone());
+        if let Some(conn) = conn {
+             info!("Sending Bye to {}", peer_id);
+             if let Err(e) = conn.send_flushed(&Message::Bye).await {
+                 warn!("Could not say Bye to {}: {}", peer_id, e);
              }
         }
         
-        if self.peers.remove(&peer_id).is_some() {
+        if let Some((_, peer)) = self.peers.remove(&peer_id) {
             info!("Disconnected peer {} manually.", peer_id);
+            self.events.record("peer", "disconnected", Some(&peer.name), None, "disconnected by this node");
+            let _ = self.departures.send(peer_id);
             true
         } else {
             warn!("Attempted to disconnect unknown peer {}", peer_id);
@@ -272,6 +669,14 @@ impl PeerManager {
         }
     }
 
+    /// Say Bye to every connected peer, e.g. when shutting down.
+    pub async fn disconnect_all(&self) {
+        let ids: Vec<Uuid> = self.peers.iter().map(|p| *p.key()).collect();
+        for id in ids {
+            self.disconnect_peer(id).await;
+        }
+    }
+
     pub fn try_reserve_storage(&self, peer_id: Uuid, size: u64) -> bool {
         if let Some(mut peer) = self.peers.get_mut(&peer_id) {
             if peer.remote_used_storage + size <= peer.ram_quota {
@@ -279,39 +684,134 @@ impl PeerManager {
                 return true;
             } else {
                 warn!("Peer {} quota exceeded. Used: {}, Requested: {}, Limit: {}", peer_id, peer.remote_used_storage, size, peer.ram_quota);
+                self.events.record("quota", "rejected", Some(&peer.name), Some(size), format!("{} of {} bytes already used", peer.remote_used_storage, peer.ram_quota));
                 return false;
             }
         }
         false
     }
 
-    pub fn update_peer_ram_quota(&self, peer_id: Uuid, remote_quota: u64) {
+    /// Apply a quota the peer granted us. Returns the value actually applied: the
+    /// grant is clamped to the peer's advertised total memory, since anything
+    /// beyond that can never be used. `None` if the peer is unknown.
+    pub fn update_peer_ram_quota(&self, peer_id: Uuid, remote_quota: u64) -> Option<u64> {
          if let Some(mut peer) = self.peers.get_mut(&peer_id) {
-             info!("Peer {} updated their quota for us to {} bytes", peer_id, remote_quota);
-             peer.remote_quota = remote_quota;
+             let applied = if peer.total_memory > 0 {
+                 remote_quota.min(peer.total_memory)
+             } else {
+                 remote_quota
+             };
+             if applied != remote_quota {
+                 info!("Peer {} granted {} bytes, clamped to their total memory of {} bytes", peer_id, remote_quota, applied);
+             } else {
+                 info!("Peer {} updated their quota for us to {} bytes", peer_id, remote_quota);
+             }
+             peer.remote_quota = applied;
+             Some(applied)
          } else {
              warn!("Received quota update from unknown peer {}", peer_id);
+             None
          }
     }
 
-    pub async fn set_allowed_quota(&self, peer_id: Uuid, new_quota: u64) -> Result<()> {
-        if let Some(mut peer) = self.peers.get_mut(&peer_id) {
+    /// Update the quota we allow a peer and wait for it to acknowledge.
+    /// Returns the quota the peer applied, or `None` if it is not connected
+    /// or did not answer in time.
+    pub async fn set_allowed_quota(&self, peer_id: Uuid, new_quota: u64) -> Result<Option<u64>> {
+        self.check_offer(new_quota, Some(peer_id))?;
+        let conn = if let Some(mut peer) = self.peers.get_mut(&peer_id) {
             info!("Updating allowed quota for peer {} to {} bytes", peer_id, new_quota);
             peer.ram_quota = new_quota;
-            
-            // Notify peer
-            if let Some(conn) = &peer.connection {
-                let mut writer = conn.lock().await;
-                let msg = Message::UpdateQuota { quota: new_quota };
-                let data = bincode::serialize(&msg)?;
-                writer.send_frame(&data).await?;
-            }
-            Ok(())
+            peer.connection.clone()
         } else {
              anyhow::bail!("Peer not found")
+        };
+
+        let Some(conn) = conn else {
+            return Ok(None);
+        };
+
+        // Registered before sending so a fast ack is not missed
+        let request_id = self.next_list_request.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.pending_quota_acks.insert(request_id, (peer_id, tx));
+
+        let ack = async {
+            conn.send(&Message::UpdateQuota { quota: new_quota, request_id }).await?;
+            anyhow::Ok(tokio::time::timeout(std::time::Duration::from_secs(5), rx).await)
+        }.await;
+        self.pending_quota_acks.remove(&request_id);
+
+        match ack? {
+            Ok(Ok((true, applied))) => {
+                if applied != new_quota {
+                    // Reconcile with what the peer will actually use
+                    if let Some(mut peer) = self.peers.get_mut(&peer_id) {
+                        peer.ram_quota = applied;
+                    }
+                }
+                Ok(Some(applied))
+            }
+            Ok(Ok((false, _))) => anyhow::bail!("Peer rejected the quota update"),
+            Ok(Err(e)) => anyhow::bail!("Recv error: {}", e),
+            Err(_) => {
+                warn!("Peer {} did not acknowledge quota update", peer_id);
+                Ok(None)
+            }
         }
     }
 
+    pub fn satisfy_quota_ack(&self, peer_id: Uuid, request_id: u64, accepted: bool, quota: u64) {
+        if let Some((_, (_, tx))) = self.pending_quota_acks.remove_if(&request_id, |_, (from, _)| *from == peer_id) {
+            let _ = tx.send((accepted, quota));
+        }
+    }
+
+    /// Ask `peer_id` to flush and wait for it to report what it removed.
+    pub async fn flush_peer(&self, peer_id: Uuid, pattern: Option<String>, durability: Option<memsdk::Durability>) -> Result<memsdk::FlushStats> {
+        let request_id = self.next_list_request.fetch_add(1, Ordering::Relaxed);
+        // Registered before sending so a fast ack is not missed
+        let (tx, mut rx) = tokio::sync::broadcast::channel(1);
+        self.pending_flush_acks.insert(request_id, (peer_id, tx));
+        let res = async {
+            self.send_to_peer(peer_id, &Message::Flush { request_id, pattern, durability }).await?;
+            self.await_reply(&mut rx, Some(peer_id), FLUSH_ACK_TIMEOUT, "flush acknowledgment").await
+        }.await;
+        self.pending_flush_acks.remove(&request_id);
+        res?.map_err(|reason| {
+            let name = self.peer_name(peer_id).unwrap_or_else(|| peer_id.to_string());
+            anyhow::anyhow!("Peer {} refused the flush: {}", name, reason)
+        })
+    }
+
+    pub fn satisfy_flush_ack(&self, peer_id: Uuid, request_id: u64, reply: Result<memsdk::FlushStats, String>) {
+        if let Some(waiter) = self.pending_flush_acks.get(&request_id).filter(|w| w.0 == peer_id) {
+            let _ = waiter.1.send(reply);
+        }
+    }
+
+    /// Set how much the peer has stored on us, e.g. after a reconnect where the
+    /// fresh registration started the count over at zero.
+    pub fn resync_remote_used_storage(&self, peer_id: Uuid, used: u64) {
+        if let Some(mut peer) = self.peers.get_mut(&peer_id) {
+            if peer.remote_used_storage != used {
+                info!("Peer {} has {} bytes stored here (was accounted as {})", peer_id, used, peer.remote_used_storage);
+            }
+            peer.remote_used_storage = used;
+        }
+    }
+
+    /// Bytes the peer currently has stored on us, as counted against its quota.
+    #[allow(dead_code)]
+    pub fn remote_used_storage(&self, peer_id: Uuid) -> Option<u64> {
+        self.peers.get(&peer_id).map(|p| p.remote_used_storage)
+    }
+
+    /// Bytes `peer_id` lets us store on it.
+    pub fn remote_quota(&self, peer_id: Uuid) -> Option<u64> {
+        self.peers.get(&peer_id).map(|p| p.remote_quota)
+    }
+
     pub fn release_storage(&self, peer_id: Uuid, size: u64) {
         if let Some(mut peer) = self.peers.get_mut(&peer_id) {
             if peer.remote_used_storage >= size {
@@ -322,34 +822,91 @@ impl PeerManager {
         }
     }
     
+    /// Count `size` bytes sent to `peer_id` against the quota it granted us.
+    pub fn record_offloaded(&self, peer_id: Uuid, size: u64) {
+        *self.offloaded.entry(peer_id).or_insert(0) += size;
+    }
+
+    /// Give back quota for data the peer no longer holds (rejected or flushed).
+    pub fn release_offloaded(&self, peer_id: Uuid, size: u64) {
+        if let Some(mut used) = self.offloaded.get_mut(&peer_id) {
+            *used = used.saturating_sub(size);
+        }
+    }
+
+    /// Take the peer's word for how much of ours it holds. Blocks still on
+    /// their way there are missed until its next report.
+    pub fn resync_offloaded(&self, peer_id: Uuid, held: u64) {
+        let old = self.offloaded.insert(peer_id, held).unwrap_or(0);
+        if old != held {
+            info!("Peer {} holds {} bytes of ours (was accounted as {})", peer_id, held, old);
+        }
+    }
+
+    /// Protocol version `peer_id` announced, if it is known.
+    pub fn peer_protocol_version(&self, peer_id: Uuid) -> Option<u16> {
+        self.peers.get(&peer_id).map(|p| p.handshake.protocol_version)
+    }
+
+    /// Bytes we have stored on `peer_id`.
+    pub fn offloaded_storage(&self, peer_id: Uuid) -> u64 {
+        self.offloaded.get(&peer_id).map_or(0, |used| *used)
+    }
+
     pub async fn request_block(&self, peer_id: Uuid, block_id: crate::metadata::BlockId) -> Result<()> {
-        let msg = Message::GetBlock { id: block_id };
+        let msg = Message::GetBlock { id: block_id, trace_id: crate::trace::current_request_id() };
         self.send_to_peer(peer_id, &msg).await
     }
 
-    pub async fn wait_for_block(&self, block_id: crate::metadata::BlockId) -> Result<Vec<u8>> {
-        let tx = self.pending_requests.entry(block_id).or_insert_with(|| {
+    /// Wait for block data from `peer_id`. Listening starts right away, so
+    /// create the future before sending the request.
+    pub fn wait_for_block(&self, peer_id: Uuid, block_id: crate::metadata::BlockId) -> impl std::future::Future<Output = Result<RemoteValue>> + '_ {
+        let mut rx = self.pending_requests.entry(block_id).or_insert_with(|| {
             let (tx, _) = tokio::sync::broadcast::channel(1);
             tx
-        }).clone();
+        }).subscribe();
+        async move { self.await_reply(&mut rx, Some(peer_id), Duration::from_secs(5), "block data").await }
+    }
 
-        let mut rx = tx.subscribe();
-        
-        match tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await {
-            Ok(Ok(data)) => Ok(data),
-            Ok(Err(e)) => anyhow::bail!("Recv error: {}", e),
-            Err(_) => anyhow::bail!("Timeout waiting for block data"),
+    // Wait on `rx` until it yields, the time is up, or `from` disconnects
+    // (`None`: until no peer is left). A reply the peer sent before leaving
+    // is delivered before its departure is announced, so it still arrives.
+    async fn await_reply<T: Clone>(&self, rx: &mut tokio::sync::broadcast::Receiver<T>, from: Option<Uuid>, timeout: Duration, what: &'static str) -> Result<T> {
+        let mut departures = self.departures.subscribe();
+        let gone = |peers: &DashMap<Uuid, PeerInfo>| match from {
+            Some(id) => !peers.contains_key(&id),
+            None => peers.is_empty(),
+        };
+        let wait = async {
+            if gone(&self.peers) {
+                return Err(PeerDisconnected { what }.into());
+            }
+            loop {
+                tokio::select! {
+                    biased;
+                    reply = rx.recv() => return reply.map_err(|e| anyhow::anyhow!("Recv error: {}", e)),
+                    _ = departures.recv() => {
+                        if gone(&self.peers) {
+                            return Err(PeerDisconnected { what }.into());
+                        }
+                    }
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => result,
+            Err(_) => bail!("Timeout waiting for {}", what),
         }
     }
 
-    pub fn satisfy_request(&self, block_id: crate::metadata::BlockId, data: Vec<u8>) {
+    pub fn satisfy_request(&self, block_id: crate::metadata::BlockId, value: RemoteValue) {
         if let Some(tx) = self.pending_requests.get(&block_id) {
-            let _ = tx.send(data);
+            let _ = tx.send(value);
         }
     }
 
     pub async fn broadcast_get_key(&self, key: &str) -> Result<()> {
-        let msg = Message::GetKey { key: key.to_string() };
+        let msg = Message::GetKey { key: key.to_string(), trace_id: crate::trace::current_request_id() };
         let mut connections = Vec::new();
         for item in self.peers.iter() {
             if let Some(conn) = &item.value().connection {
@@ -358,37 +915,52 @@ impl PeerManager {
         }
 
         for conn in connections {
-            let mut w = conn.lock().await;
-            // Serialize
-            let data = bincode::serialize(&msg)?;
-            let _ = w.send_frame(&data).await;
+            let _ = conn.send(&msg).await;
         }
         Ok(())
     }
 
-    pub async fn wait_for_key(&self, key: &str) -> Result<Vec<u8>> {
-        let tx = self.pending_key_requests.entry(key.to_string()).or_insert_with(|| {
-            let (tx, _) = tokio::sync::broadcast::channel(1);
+    /// Wait for the value of `key` from `from`, or from any peer with `None`
+    /// (after a broadcast). Listening starts right away, so create the future
+    /// before sending the request.
+    ///
+    /// An empty value is still a value. `Ok(None)` means the key is absent:
+    /// `from`, or after a broadcast every connected peer, answered without it.
+    pub fn wait_for_key(&self, key: &str, from: Option<Uuid>) -> impl std::future::Future<Output = Result<Option<RemoteValue>>> + '_ {
+        let mut rx = self.pending_key_requests.entry(key.to_string()).or_insert_with(|| {
+            // Room for an answer from each peer of a broadcast
+            let (tx, _) = tokio::sync::broadcast::channel(64);
             tx
-        }).clone();
-
-        let mut rx = tx.subscribe();
-        
-        match tokio::time::timeout(std::time::Duration::from_secs(2), rx.recv()).await {
-            Ok(Ok(data)) => Ok(data),
-            Ok(Err(e)) => anyhow::bail!("Recv error: {}", e),
-            Err(_) => anyhow::bail!("Timeout waiting for key"),
+        }).subscribe();
+        async move {
+            let deadline = Instant::now() + KEY_LOOKUP_TIMEOUT;
+            let mut declined = std::collections::HashSet::new();
+            loop {
+                let left = deadline.saturating_duration_since(Instant::now());
+                let (peer, value) = self.await_reply(&mut rx, from, left, "key").await?;
+                if from.is_some_and(|id| id != peer) {
+                    continue;
+                }
+                if value.is_some() {
+                    return Ok(value);
+                }
+                declined.insert(peer);
+                let connected = self.peers.iter().filter(|p| p.connection.is_some()).count();
+                if from.is_some() || declined.len() >= connected {
+                    return Ok(None);
+                }
+            }
         }
     }
 
-    pub fn satisfy_key_request(&self, key: &str, data: Vec<u8>) {
+    pub fn satisfy_key_request(&self, peer_id: Uuid, key: &str, value: Option<RemoteValue>) {
         if let Some(tx) = self.pending_key_requests.get(key) {
-             let _ = tx.send(data);
+             let _ = tx.send((peer_id, value));
         }
     }
 
-    pub async fn set_key_remote(&self, peer_id: Uuid, key: String, data: Vec<u8>, durability: memsdk::Durability) -> Result<()> {
-        let msg = Message::PutKey { key, data, durability: Some(durability) };
+    pub async fn set_key_remote(&self, peer_id: Uuid, key: String, data: Vec<u8>, durability: memsdk::Durability, priority: Option<u8>) -> Result<()> {
+        let msg = Message::PutKey { key, data, durability: Some(durability), priority };
         self.send_to_peer(peer_id, &msg).await
     }
 
@@ -412,6 +984,148 @@ impl PeerManager {
         }
     }
 
+    /// Start listening for the peer's verdict on block `id`; call before sending it.
+    /// Call `forget_block_ack` once done with the receiver.
+    pub fn expect_block_ack(&self, id: crate::metadata::BlockId) -> tokio::sync::broadcast::Receiver<Result<(), String>> {
+        self.pending_block_acks.entry(id).or_insert_with(|| {
+            let (tx, _) = tokio::sync::broadcast::channel(1);
+            tx
+        }).subscribe()
+    }
+
+    pub fn satisfy_block_ack(&self, id: crate::metadata::BlockId, result: Result<(), String>) {
+        if let Some(tx) = self.pending_block_acks.get(&id) {
+            let _ = tx.send(result);
+        }
+    }
+
+    pub fn forget_block_ack(&self, id: crate::metadata::BlockId) {
+        self.pending_block_acks.remove(&id);
+    }
+
+    /// Ask every connected peer for its keys matching `pattern`. Returns the
+    /// answers that arrived within `REMOTE_LIST_TIMEOUT` and the names of the
+    /// peers that did not answer in time.
+    pub async fn list_remote_keys(&self, pattern: &str) -> (Vec<PeerKeys>, Vec<String>) {
+        let deadline = tokio::time::Instant::now() + REMOTE_LIST_TIMEOUT;
+        let request_id = self.next_list_request.fetch_add(1, Ordering::Relaxed);
+        let targets: Vec<(Uuid, String, PeerSender)> = self.peers.iter()
+            .filter_map(|e| e.value().connection.clone().map(|conn| (*e.key(), e.value().name.clone(), conn)))
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(targets.len().max(1));
+        self.pending_key_lists.insert(request_id, tx);
+
+        let msg = Message::ListKeys { pattern: pattern.to_string(), request_id };
+        let mut waiting = std::collections::HashMap::new();
+        let mut silent = Vec::new();
+        for (peer_id, name, conn) in targets {
+            match tokio::time::timeout_at(deadline, conn.send(&msg)).await {
+                Ok(Ok(())) => {
+                    waiting.insert(peer_id, name);
+                }
+                _ => silent.push(name),
+            }
+        }
+
+        let mut answered = Vec::new();
+        while !waiting.is_empty() {
+            let Ok(Some((peer_id, mut keys, mut truncated))) = tokio::time::timeout_at(deadline, rx.recv()).await else {
+                break;
+            };
+            let Some(name) = waiting.remove(&peer_id) else {
+                continue;
+            };
+            if keys.len() > REMOTE_LIST_LIMIT {
+                keys.truncate(REMOTE_LIST_LIMIT);
+                truncated = true;
+            }
+            answered.push(PeerKeys { name, keys, truncated });
+        }
+        self.pending_key_lists.remove(&request_id);
+        silent.extend(waiting.into_values());
+        silent.sort();
+        (answered, silent)
+    }
+
+    pub fn satisfy_key_list(&self, peer_id: Uuid, request_id: u64, keys: Vec<String>, truncated: bool) {
+        if let Some(tx) = self.pending_key_lists.get(&request_id) {
+            let _ = tx.try_send((peer_id, keys, truncated));
+        }
+    }
+
+    /// Ask every connected peer for its `NodeStat` at once, waiting at most
+    /// `CLUSTER_STAT_TIMEOUT`. Peers too old to understand the request are
+    /// not asked and come back as `PeerStatReply::Unsupported`.
+    pub async fn request_stats(&self) -> Vec<(Uuid, String, PeerStatReply)> {
+        let deadline = tokio::time::Instant::now() + CLUSTER_STAT_TIMEOUT;
+        let request_id = self.next_list_request.fetch_add(1, Ordering::Relaxed);
+        let mut results = Vec::new();
+        let mut targets = Vec::new();
+        for e in self.peers.iter() {
+            let (id, name) = (*e.key(), e.value().name.clone());
+            match e.value().connection.clone() {
+                _ if e.value().handshake.protocol_version < crate::net::auth::STATS_VERSION => results.push((id, name, PeerStatReply::Unsupported)),
+                Some(conn) => targets.push((id, name, conn)),
+                None => results.push((id, name, PeerStatReply::Unreachable)),
+            }
+        }
+        let (tx, mut rx) = tokio::sync::mpsc::channel(targets.len().max(1));
+        self.pending_stats.insert(request_id, tx);
+
+        let msg = Message::StatRequest { request_id };
+        let mut waiting = std::collections::HashMap::new();
+        for (peer_id, name, conn) in targets {
+            match tokio::time::timeout_at(deadline, conn.send(&msg)).await {
+                Ok(Ok(())) => {
+                    waiting.insert(peer_id, name);
+                }
+                _ => results.push((peer_id, name, PeerStatReply::Unreachable)),
+            }
+        }
+
+        while !waiting.is_empty() {
+            let Ok(Some((peer_id, stat))) = tokio::time::timeout_at(deadline, rx.recv()).await else {
+                break;
+            };
+            if let Some(name) = waiting.remove(&peer_id) {
+                results.push((peer_id, name, PeerStatReply::Answered(stat)));
+            }
+        }
+        self.pending_stats.remove(&request_id);
+        results.extend(waiting.into_iter().map(|(id, name)| (id, name, PeerStatReply::Unreachable)));
+        results.sort_by(|a, b| a.1.cmp(&b.1));
+        results
+    }
+
+    pub fn satisfy_stat(&self, peer_id: Uuid, request_id: u64, stat: NodeStat) {
+        if let Some(tx) = self.pending_stats.get(&request_id) {
+            let _ = tx.try_send((peer_id, stat));
+        }
+    }
+
+    pub fn peer_name(&self, peer_id: Uuid) -> Option<String> {
+        self.peers.get(&peer_id).map(|p| p.name.clone())
+    }
+
+    /// Whether the peer is a trusted device allowed to flush all of this
+    /// node's data; otherwise its Flush only reaches the blocks it stored here.
+    pub fn allows_remote_flush(&self, peer_id: Uuid) -> bool {
+        self.peers.get(&peer_id).is_some_and(|p| self.trusted_store.allows_remote_flush(&p.public_key))
+    }
+
+    /// Grant or revoke `allows_remote_flush` for a connected peer. The grant
+    /// is kept with its trust record, so the peer must be trusted.
+    pub fn set_remote_flush(&self, target: &str, allow: bool) -> Result<Uuid> {
+        let id = self.resolve_peer(target)?;
+        let Some(public_key) = self.peers.get(&id).map(|p| p.public_key.clone()) else {
+            bail!("Peer {} not found", target);
+        };
+        if !self.trusted_store.set_remote_flush(&public_key, allow)? {
+            bail!("Invalid peer {}: only trusted devices can be allowed to flush this node; trust it first", target);
+        }
+        Ok(id)
+    }
+
     pub fn get_peer_id_by_name(&self, name: &str) -> Option<Uuid> {
         // Try exact match first
         if let Some(entry) = self.peers.iter().find(|entry| entry.value().name == name) {
@@ -426,20 +1140,171 @@ impl PeerManager {
         None
     }
 
-    pub async fn get_available_peer(&self) -> Option<Uuid> {
-        self.peers.iter().next().map(|e| *e.key())
+    /// The connected peer a user named: by id or an unambiguous prefix of it
+    /// (ids are often copied truncated), or by name in any case.
+    pub fn resolve_peer(&self, target: &str) -> Result<Uuid> {
+        if let Some(p) = self.peers.iter().find(|p| p.value().public_key.eq_ignore_ascii_case(target.trim())) {
+            return Ok(*p.key());
+        }
+        let peers: Vec<(Uuid, String)> = self.peers.iter().map(|e| (*e.key(), e.value().name.clone())).collect();
+        resolve_among(&peers, target)
+    }
+
+    /// A peer to offload `size` bytes to when none was named: the healthy peer
+    /// with the most quota left, provided the block fits. Unhealthy peers are
+    /// skipped; they can still be targeted explicitly by name or id.
+    pub async fn get_available_peer(&self, size: u64) -> Result<Uuid> {
+        self.best_peer(size, None)
+    }
+
+    /// Like `get_available_peer`, never choosing `except`.
+    pub async fn get_available_peer_except(&self, size: u64, except: Uuid) -> Result<Uuid> {
+        self.best_peer(size, Some(except))
+    }
+
+    fn best_peer(&self, size: u64, except: Option<Uuid>) -> Result<Uuid> {
+        let mut best: Option<(Uuid, u64)> = None;
+        for entry in self.peers.iter().filter(|e| e.value().healthy && Some(*e.key()) != except) {
+            let headroom = entry.value().remote_quota.saturating_sub(self.offloaded_storage(*entry.key()));
+            if best.is_none_or(|(_, most)| headroom > most) {
+                best = Some((*entry.key(), headroom));
+            }
+        }
+        match best {
+            Some((id, headroom)) if headroom >= size => Ok(id),
+            Some((_, headroom)) => anyhow::bail!("No peer with sufficient quota (need {}, best available {})", size, headroom),
+            None => anyhow::bail!("No suitable peer found for remote storage"),
+        }
+    }
+
+    /// The transport port peers reach us on, once bound; it may differ from the configured one.
+    pub fn listen_port(&self) -> Option<u16> {
+        Some(self.listen_port.load(Ordering::Relaxed)).filter(|p| *p != 0)
+    }
+
+    pub fn set_listen_port(&self, port: u16) {
+        self.listen_port.store(port, Ordering::Relaxed);
+    }
+
+    pub fn keepalive_interval(&self) -> Duration {
+        Duration::from_millis(self.keepalive_interval_ms.load(Ordering::Relaxed))
+    }
+
+    /// Applies to connections established afterwards.
+    pub fn set_keepalive_interval(&self, interval: Duration) {
+        self.keepalive_interval_ms.store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Largest encrypted frame accepted from a peer.
+    pub fn max_peer_frame(&self) -> usize {
+        self.max_peer_frame.load(Ordering::Relaxed) as usize
+    }
+
+    /// Largest block that can be read back from a peer: replies carry the
+    /// whole block in one frame, which has to fit in `max_peer_frame`.
+    pub fn max_peer_block(&self) -> u64 {
+        self.max_peer_frame().saturating_sub(crate::net::frame::BLOCK_FRAME_OVERHEAD) as u64
+    }
+
+    /// Applies to connections established afterwards.
+    pub fn set_max_peer_frame(&self, max: usize) {
+        self.max_peer_frame.store(max as u64, Ordering::Relaxed);
+    }
+
+    /// Storage offered to a peer when a connection is made without an explicit quota.
+    pub fn default_quota(&self) -> u64 {
+        self.default_quota.load(Ordering::Relaxed)
+    }
+
+    /// Applies to connections established afterwards.
+    pub fn set_default_quota(&self, quota: u64) {
+        self.default_quota.store(quota, Ordering::Relaxed);
+    }
+
+    /// Memory the quotas offered to all peers must fit in together.
+    pub fn set_capacity(&self, bytes: u64) {
+        self.capacity.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Storage offered to connected peers, other than `except`.
+    pub fn committed_quota(&self, except: Option<Uuid>) -> u64 {
+        self.peers.iter().filter(|p| Some(*p.key()) != except).map(|p| p.value().ram_quota).sum()
+    }
+
+    /// Storage that can still be offered to `peer` (`None`: a peer not yet
+    /// connected) without the quotas adding up past this node's memory.
+    pub fn available_quota(&self, peer: Option<Uuid>) -> u64 {
+        match self.capacity.load(Ordering::Relaxed) {
+            0 => u64::MAX,
+            capacity => capacity.saturating_sub(self.committed_quota(peer)),
+        }
+    }
+
+    /// `requested`, cut down to what is left to offer `peer`.
+    pub fn clamp_offer(&self, requested: u64, peer: Option<Uuid>) -> u64 {
+        let available = self.available_quota(peer);
+        if requested > available {
+            warn!("Offering {} bytes instead of {}; the rest of this node's memory is already offered to other peers", available, requested);
+        }
+        requested.min(available)
+    }
+
+    /// Refuse an explicit offer of `requested` bytes to `peer` that would over-commit memory.
+    pub fn check_offer(&self, requested: u64, peer: Option<Uuid>) -> Result<()> {
+        let available = self.available_quota(peer);
+        if requested > available {
+            bail!(
+                "Quota exceeds what this node has left to offer: {} bytes requested, {} of {} bytes not yet offered to other peers",
+                requested, available, self.capacity.load(Ordering::Relaxed)
+            );
+        }
+        Ok(())
+    }
+
+    /// Note that a ping with `nonce` is about to be sent. Returns how many earlier
+    /// pings went unanswered (marking the peer unhealthy once that reaches
+    /// `KEEPALIVE_UNHEALTHY_AFTER`), or `None` if the peer is gone.
+    pub fn record_ping_sent(&self, peer_id: Uuid, nonce: u64) -> Option<u32> {
+        let mut peer = self.peers.get_mut(&peer_id)?;
+        peer.ping_sent = Some((nonce, Instant::now()));
+        let missed = peer.missed_pings;
+        if missed >= KEEPALIVE_UNHEALTHY_AFTER && peer.healthy {
+            warn!("Peer {} missed {} pings, marking unhealthy", peer_id, missed);
+            peer.healthy = false;
+        }
+        peer.missed_pings += 1;
+        Some(missed)
+    }
+
+    /// A pong for the newest ping also sets the peer's round-trip time; late
+    /// pongs for older pings only count as a sign of life.
+    pub fn record_pong(&self, peer_id: Uuid, nonce: u64) {
+        if let Some(mut peer) = self.peers.get_mut(&peer_id) {
+            peer.missed_pings = 0;
+            peer.last_seen = unix_millis();
+            if let Some((sent_nonce, sent_at)) = peer.ping_sent {
+                if sent_nonce == nonce {
+                    peer.last_rtt = Some(sent_at.elapsed());
+                    peer.ping_sent = None;
+                }
+            }
+            if !peer.healthy {
+                info!("Peer {} is answering again, marking healthy", peer_id);
+                peer.healthy = true;
+            }
+        }
     }
     
     pub async fn send_to_peer(&self, peer_id: Uuid, msg: &Message) -> Result<()> {
-         if let Some(peer) = self.peers.get(&peer_id) {
-             if let Some(conn) = &peer.connection {
-                 let mut writer = conn.lock().await;
-                 let data = bincode::serialize(msg)?;
-                 writer.send_frame(&data).await?;
-                 return Ok(());
-             }
+         let conn = self.peers.get(&peer_id).and_then(|peer| peer.connection.clone());
+         match conn {
+             Some(conn) => conn.send(msg).await,
+             None => anyhow::bail!("Peer {} not connected", peer_id),
          }
-         anyhow::bail!("Peer {} not connected", peer_id)
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
     }
 
     pub fn list_peers(&self) -> Vec<String> {
@@ -451,13 +1316,48 @@ impl PeerManager {
             id: e.key().to_string(),
             name: e.value().name.clone(),
             addr: e.value().addr.to_string(),
+            public_key: e.value().public_key.clone(),
             total_memory: e.value().total_memory,
             used_memory: e.value().used_memory,
             quota: e.value().remote_quota,
             allowed_quota: e.value().ram_quota,
+            healthy: e.value().healthy,
+            last_rtt_micros: e.value().rtt_micros(),
+            last_seen: e.value().last_seen,
         }).collect()
     }
     
+    /// The session with the connected peer `target` (a name or id).
+    pub fn inspect_peer(&self, target: &str) -> Result<memsdk::PeerSession> {
+        let id = self.resolve_peer(target)?;
+        let Some(peer) = self.peers.get(&id) else {
+            bail!("Peer '{}' not found", target);
+        };
+        let hs = &peer.handshake;
+        let now = unix_millis();
+        Ok(memsdk::PeerSession {
+            id: id.to_string(),
+            name: peer.name.clone(),
+            addr: peer.addr.to_string(),
+            key_fingerprint: key_fingerprint(&peer.public_key),
+            protocol_version: hs.protocol_version,
+            capabilities: crate::net::auth::capabilities(hs.protocol_version),
+            initiated: hs.initiated,
+            established_at: hs.established_at,
+            uptime_secs: now.saturating_sub(hs.established_at) / 1000,
+            frames_sent: hs.counters.frames_sent.load(Ordering::Relaxed),
+            frames_received: hs.counters.frames_received.load(Ordering::Relaxed),
+            bytes_sent: hs.counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: hs.counters.bytes_received.load(Ordering::Relaxed),
+            allowed_quota: peer.ram_quota,
+            used_here: peer.remote_used_storage,
+            quota: peer.remote_quota,
+            used_there: self.offloaded_storage(id),
+            healthy: peer.healthy,
+            last_rtt_micros: peer.rtt_micros(),
+        })
+    }
+
     pub fn get_self_id(&self) -> Uuid {
         self.self_id
     }
@@ -466,3 +1366,592 @@ impl PeerManager {
         self.self_name.clone()
     }
 }
+
+fn describe(peers: &[&(Uuid, String)]) -> String {
+    peers.iter().map(|(id, name)| format!("{} ({})", name, id)).collect::<Vec<_>>().join(", ")
+}
+
+/// Local id for a peer whose own id is already taken by a peer with another key.
+fn key_derived_id(public_key: &str) -> Uuid {
+    let hash = blake3::hash(public_key.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash.as_bytes()[..16]);
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+// Exact id, exact name, name ignoring case, then id prefix. A step that
+// matches several peers is an error rather than falling through to the next.
+fn resolve_among(peers: &[(Uuid, String)], target: &str) -> Result<Uuid> {
+    let target = target.trim();
+    if target.is_empty() {
+        bail!("Peer name or id must not be empty");
+    }
+    let lower = target.to_lowercase();
+    let hex: String = lower.chars().filter(|&c| c != '-').collect();
+    let is_hex = !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit());
+    type Matcher<'a> = &'a dyn Fn(&(Uuid, String)) -> bool;
+    let steps: [Matcher; 4] = [
+        &|(id, _)| Uuid::parse_str(target).is_ok_and(|t| t == *id),
+        &|(_, name)| name == target,
+        &|(_, name)| name.to_lowercase() == lower,
+        &|(id, _)| is_hex && id.simple().to_string().starts_with(&hex),
+    ];
+    for matches in steps {
+        let found: Vec<_> = peers.iter().filter(|p| matches(p)).collect();
+        match found.as_slice() {
+            [] => continue,
+            [(id, _)] => return Ok(*id),
+            _ => bail!("Peer '{}' is ambiguous: matches {}", target, describe(&found)),
+        }
+    }
+
+    let near: Vec<_> = peers.iter().filter(|(id, name)| {
+        let name = name.to_lowercase();
+        name.contains(&lower) || lower.contains(&name) || edit_distance(&name, &lower) <= 2
+            || (is_hex && id.simple().to_string().contains(&hex))
+    }).collect();
+    if !near.is_empty() {
+        bail!("Peer '{}' not found; did you mean {}?", target, describe(&near));
+    }
+    if peers.is_empty() {
+        bail!("Peer '{}' not found; no peers are connected", target);
+    }
+    bail!("Peer '{}' not found; connected peers: {}", target, describe(&peers.iter().collect::<Vec<_>>()))
+}
+
+// Levenshtein distance, for suggesting peers whose name was mistyped
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let next = (diagonal + usize::from(ca != cb)).min(row[j] + 1).min(row[j + 1] + 1);
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::consent::ConsentDecision;
+    use crate::test_support::{TestNode, wait_until};
+
+    #[test]
+    fn test_resolve_peer_by_prefix_and_name() {
+        let id = |s: &str| Uuid::parse_str(s).unwrap();
+        let peers = vec![
+            (id("3f2a1c00-0000-4000-8000-000000000001"), "Laptop".to_string()),
+            (id("3f2b9e00-0000-4000-8000-000000000002"), "desktop".to_string()),
+            (id("a0000000-0000-4000-8000-000000000003"), "laptop-2".to_string()),
+        ];
+        let resolve = |target: &str| resolve_among(&peers, target);
+
+        assert_eq!(resolve("3f2a1c00-0000-4000-8000-000000000001").unwrap(), peers[0].0);
+        assert_eq!(resolve("3f2a").unwrap(), peers[0].0);
+        assert_eq!(resolve("3F2B9E00-00").unwrap(), peers[1].0);
+        assert_eq!(resolve("laptop").unwrap(), peers[0].0);
+        assert_eq!(resolve("DESKTOP").unwrap(), peers[1].0);
+        // A name that looks like a hex prefix is still a name first
+        assert_eq!(resolve("laptop-2").unwrap(), peers[2].0);
+
+        let err = resolve("3f2").unwrap_err().to_string();
+        assert!(err.contains("ambiguous") && err.contains("Laptop") && err.contains("desktop"), "{}", err);
+        assert!(!err.contains("laptop-2"), "{}", err);
+
+        let err = resolve("laptpo").unwrap_err().to_string();
+        assert!(err.starts_with("Peer 'laptpo' not found; did you mean") && err.contains("Laptop"), "{}", err);
+        let err = resolve("server").unwrap_err().to_string();
+        assert!(err.contains("connected peers:") && err.contains("desktop"), "{}", err);
+        assert!(resolve_among(&[], "server").unwrap_err().to_string().contains("no peers are connected"));
+        assert!(resolve(" ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quota_update_ack_round_trip() {
+        let a = TestNode::spawn("quota-a", 64 * 1024 * 1024).await;
+        let b = TestNode::spawn("quota-b", 64 * 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+
+        // A grants B 16MB; B acknowledges the full amount
+        let requested = 16 * 1024 * 1024;
+        let applied = a.peer_manager.set_allowed_quota(b.id, requested).await.unwrap();
+        assert_eq!(applied, Some(requested));
+        wait_until(|| b.peer_manager.get_peer_metadata_list().iter().any(|p| p.quota == requested)).await;
+
+        // Updates at the same time each get their own ack
+        let (first, second) = tokio::join!(
+            a.peer_manager.set_allowed_quota(b.id, requested / 2),
+            a.peer_manager.set_allowed_quota(b.id, requested / 4),
+        );
+        assert_eq!((first.unwrap(), second.unwrap()), (Some(requested / 2), Some(requested / 4)));
+
+        // A grant larger than A's advertised memory is clamped by B and reconciled on A
+        // (A itself only refuses grants past its memory when it knows the limit).
+        // B takes A at its word, so the word is fixed here rather than read from the host.
+        let total = 32 * 1024 * 1024;
+        b.peer_manager.peers.get_mut(&a.id).unwrap().total_memory = total;
+        a.peer_manager.set_capacity(0);
+        let applied = a.peer_manager.set_allowed_quota(b.id, total + 1).await.unwrap();
+        assert_eq!(applied, Some(total));
+        let meta = a.peer_manager.get_peer_metadata_list();
+        assert_eq!(meta[0].allowed_quota, total);
+    }
+
+    #[tokio::test]
+    async fn test_quotas_offered_cannot_overcommit_memory() {
+        let hub = TestNode::spawn("commit-hub", 16 * 1024 * 1024).await;
+        let a = TestNode::spawn("commit-a", 1024 * 1024).await;
+        let b = TestNode::spawn("commit-b", 1024 * 1024).await;
+        let mb = 1024 * 1024;
+
+        // Offers made while connecting are cut down to what is left
+        hub.connect_to(&a, 10 * mb).await;
+        hub.connect_to(&b, 10 * mb).await;
+        assert_eq!(hub.peer_manager.committed_quota(None), 16 * mb);
+        assert_eq!(hub.peer_manager.available_quota(None), 0);
+        wait_until(|| b.peer_manager.get_peer_metadata_list().iter().any(|p| p.quota == 6 * mb)).await;
+
+        // An explicit update past the rest is refused, leaving the old quota
+        let err = hub.peer_manager.set_allowed_quota(b.id, 8 * mb).await.unwrap_err();
+        assert!(err.to_string().contains("6291456 of 16777216 bytes not yet offered"), "{}", err);
+        assert_eq!(memsdk::ErrorCode::classify(&err.to_string()), memsdk::ErrorCode::QuotaExceeded);
+        assert_eq!(hub.peer_manager.committed_quota(None), 16 * mb);
+
+        // Freeing up quota on one peer makes room for the other
+        hub.peer_manager.set_allowed_quota(a.id, 4 * mb).await.unwrap();
+        hub.peer_manager.set_allowed_quota(b.id, 12 * mb).await.unwrap();
+        assert_eq!(hub.peer_manager.committed_quota(Some(a.id)), 12 * mb);
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_tracks_health() {
+        let a = TestNode::spawn("alive-a", 1024 * 1024).await;
+        let b = TestNode::spawn("alive-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+
+        // Missed pings: the second unanswered one marks the peer unhealthy
+        assert_eq!(a.peer_manager.record_ping_sent(b.id, 1), Some(0));
+        assert_eq!(a.peer_manager.record_ping_sent(b.id, 1), Some(1));
+        assert_eq!(a.peer_manager.get_available_peer(0).await.ok(), Some(b.id));
+        assert_eq!(a.peer_manager.record_ping_sent(b.id, 1), Some(2));
+        assert!(!a.peer_manager.get_peer_metadata_list()[0].healthy);
+        assert!(a.peer_manager.get_available_peer(0).await.is_err());
+        // Still reachable when named explicitly
+        assert_eq!(a.peer_manager.get_peer_id_by_name(&b.id.to_string()), Some(b.id));
+
+        a.peer_manager.record_pong(b.id, 1);
+        assert!(a.peer_manager.get_peer_metadata_list()[0].healthy);
+        assert_eq!(a.peer_manager.record_ping_sent(b.id, 1), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_pong_updates_rtt_and_last_seen() {
+        let a = TestNode::spawn("rtt-a", 1024 * 1024).await;
+        let b = TestNode::spawn("rtt-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let connected = a.peer_manager.get_peer_metadata_list()[0].clone();
+        assert_eq!(connected.last_rtt_micros, None);
+        assert!(connected.last_seen > 0);
+
+        a.peer_manager.record_ping_sent(b.id, 7);
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        // A pong for an older ping leaves the RTT alone
+        a.peer_manager.record_pong(b.id, 6);
+        assert_eq!(a.peer_manager.get_peer_metadata_list()[0].last_rtt_micros, None);
+        a.peer_manager.record_pong(b.id, 7);
+
+        let meta = a.peer_manager.get_peer_metadata_list()[0].clone();
+        assert!(meta.last_rtt_micros.unwrap() >= 5_000);
+        assert!(meta.last_seen >= connected.last_seen + 5);
+    }
+
+    #[tokio::test]
+    async fn test_inspect_reports_handshake_and_traffic() {
+        let a = TestNode::spawn("inspect-a", 1024 * 1024).await;
+        let b = TestNode::spawn("inspect-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let block = a.block_manager.new_block(vec![3; 1000], memsdk::Durability::Pinned);
+        a.block_manager.put_block_remote(block, Some(b.id.to_string())).await.unwrap();
+
+        let from_a = a.peer_manager.inspect_peer(&b.id.to_string()).unwrap();
+        let from_b = b.peer_manager.inspect_peer(&a.id.to_string()).unwrap();
+        assert_eq!((from_a.initiated, from_b.initiated), (true, false));
+        assert_eq!(from_a.protocol_version, crate::net::auth::PROTOCOL_VERSION);
+        assert_eq!(from_a.used_there, 1000);
+        // Both ends count the same frames once the block has arrived
+        wait_until(|| {
+            let (a_side, b_side) = (a.peer_manager.inspect_peer(&b.id.to_string()).unwrap(), b.peer_manager.inspect_peer(&a.id.to_string()).unwrap());
+            b_side.used_here == 1000
+                && b_side.bytes_received > 1000
+                && (a_side.frames_sent, a_side.bytes_sent) == (b_side.frames_received, b_side.bytes_received)
+        }).await;
+
+        // Only a fingerprint of the key goes out
+        let b_key = hex::encode(b.peer_manager.get_identity().public_key().to_bytes());
+        assert_eq!(from_a.key_fingerprint, key_fingerprint(&b_key));
+        assert!(!serde_json::to_string(&from_a).unwrap().contains(&b_key));
+        assert!(a.peer_manager.inspect_peer("nobody").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peer_and_consent_events_are_journaled() {
+        let a = TestNode::spawn("events-a", 1024 * 1024).await;
+        let b = TestNode::spawn("events-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        assert!(a.peer_manager.disconnect_peer(b.id).await);
+        let journal = |node: &TestNode| {
+            let (events, _) = node.peer_manager.events.query(Some(0), None, 100);
+            events.into_iter().map(|e| (e.kind, e.action, e.subject.unwrap_or_default(), e.detail)).collect::<Vec<_>>()
+        };
+        wait_until(|| journal(&b).iter().any(|(_, action, _, _)| action == "disconnected")).await;
+
+        let a_events = journal(&a);
+        assert_eq!(a_events.iter().map(|(k, a, s, _)| (k.as_str(), a.as_str(), s.as_str())).collect::<Vec<_>>(), [
+            ("peer", "connected", "events-b"),
+            ("peer", "disconnected", "events-b"),
+        ]);
+        assert!(a_events[0].3.ends_with("(outgoing)"));
+        assert_eq!(a_events[1].3, "disconnected by this node");
+        let b_events = journal(&b);
+        assert_eq!(b_events.iter().map(|(k, a, s, _)| (k.as_str(), a.as_str(), s.as_str())).collect::<Vec<_>>(), [
+            ("consent", "requested", "events-a"),
+            ("consent", "approved", "events-a"),
+            ("peer", "connected", "events-a"),
+            ("peer", "disconnected", "events-a"),
+        ]);
+        assert_eq!(b_events[3].3, "connection closed");
+    }
+
+    #[tokio::test]
+    async fn test_read_only_peer_refuses_flush() {
+        let a = TestNode::spawn("refuse-a", 1024 * 1024).await;
+        let b = TestNode::spawn("refuse-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        a.block_manager.set_remote("tmp:1", vec![1; 10], "refuse-b", memsdk::Durability::Pinned, None).await.unwrap();
+        b.block_manager.set_read_only(true);
+
+        let err = a.block_manager.flush_remote(b.id.to_string(), None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "Peer refuse-b refused the flush: Node is read-only");
+        assert_eq!(b.block_manager.list_keys("*"), ["tmp:1"]);
+
+        // A peer too old to know the refusal still gets its empty ack
+        b.peer_manager.peers.get_mut(&a.id).unwrap().handshake.protocol_version = crate::net::auth::FLUSH_REFUSAL_VERSION - 1;
+        let stats = a.block_manager.flush_remote(b.id.to_string(), None, None).await.unwrap();
+        assert_eq!(stats, memsdk::FlushStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_cluster_stats_skip_old_and_missing_peers() {
+        let a = TestNode::spawn("cluster-a", 1024 * 1024).await;
+        let b = TestNode::spawn("cluster-b", 2 * 1024 * 1024).await;
+        let c = TestNode::spawn("cluster-c", 1024 * 1024).await;
+        let d = TestNode::spawn("cluster-d", 1024 * 1024).await;
+        for peer in [&b, &c, &d] {
+            a.connect_to(peer, 0).await;
+        }
+        a.block_manager.put_block(a.block_manager.new_block(vec![1; 100], memsdk::Durability::Pinned)).unwrap();
+        b.block_manager.put_block(b.block_manager.new_block(vec![2; 500], memsdk::Durability::Pinned)).unwrap();
+        // c announced an older protocol and d lost its connection
+        a.peer_manager.peers.get_mut(&c.id).unwrap().handshake.protocol_version = crate::net::auth::STATS_VERSION - 1;
+        a.peer_manager.peers.get_mut(&d.id).unwrap().connection = None;
+
+        let stats = a.block_manager.cluster_stats().await;
+        let rows: Vec<(&str, bool, memsdk::ClusterNodeStatus, u64, u64)> =
+            stats.nodes.iter().map(|n| (n.name.as_str(), n.local, n.status, n.blocks, n.memory_usage)).collect();
+        use memsdk::ClusterNodeStatus::*;
+        assert_eq!(rows, [
+            ("cluster-a", true, Ok, 1, 100),
+            ("cluster-b", false, Ok, 1, 500),
+            ("cluster-c", false, Unsupported, 0, 0),
+            ("cluster-d", false, Unreachable, 0, 0),
+        ]);
+        assert_eq!(stats.nodes[1].version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(stats.nodes[1].peers, 1);
+        assert_eq!((stats.blocks, stats.memory_usage, stats.max_memory), (2, 600, 3 * 1024 * 1024));
+        // c was never sent the request, so it is still connected
+        assert!(a.peer_manager.peers.get(&c.id).unwrap().connection.is_some());
+        assert_eq!(c.peer_manager.peer_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_connect_is_idempotent_and_cancellable() {
+        use super::HandshakeState;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let a = TestNode::spawn("dial-a", 1024 * 1024).await;
+        let b = TestNode::spawn("dial-b", 1024 * 1024).await;
+        let addr: std::net::SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let spawned = AtomicUsize::new(0);
+        let tasks = std::sync::Mutex::new(Vec::new());
+        let spawn = || {
+            spawned.fetch_add(1, Ordering::Relaxed);
+            let handle = tokio::spawn(std::future::pending::<()>());
+            let abort = handle.abort_handle();
+            tasks.lock().unwrap().push(handle);
+            abort
+        };
+
+        assert_eq!(a.peer_manager.start_outgoing(addr, spawn), HandshakeState::Connecting);
+        assert_eq!(a.peer_manager.start_outgoing(addr, spawn), HandshakeState::Connecting);
+        assert_eq!(spawned.load(Ordering::Relaxed), 1);
+
+        assert!(a.peer_manager.cancel_outgoing(addr));
+        let task = tasks.lock().unwrap().pop().unwrap();
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert_eq!(a.peer_manager.handshake_state(addr), Some(HandshakeState::Failed("Cancelled".to_string())));
+        assert!(!a.peer_manager.cancel_outgoing(addr));
+
+        // A failed attempt may be retried
+        a.peer_manager.start_outgoing(addr, spawn);
+        assert_eq!(spawned.load(Ordering::Relaxed), 2);
+
+        // Connected peers are reported as such without dialing again
+        a.connect_to(&b, 0).await;
+        let b_addr = b.addr().parse().unwrap();
+        assert_eq!(a.peer_manager.start_outgoing(b_addr, spawn), HandshakeState::Authenticated);
+        assert_eq!(spawned.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_connects_start_one_attempt() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let a = std::sync::Arc::new(TestNode::spawn("race-a", 1024 * 1024).await);
+        let addr: std::net::SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let spawned = std::sync::Arc::new(AtomicUsize::new(0));
+        let start = std::sync::Arc::new(tokio::sync::Barrier::new(16));
+        let dials: Vec<_> = (0..16).map(|_| {
+            let (a, spawned, start) = (a.clone(), spawned.clone(), start.clone());
+            tokio::spawn(async move {
+                start.wait().await;
+                a.peer_manager.start_outgoing(addr, || {
+                    spawned.fetch_add(1, Ordering::Relaxed);
+                    tokio::spawn(std::future::pending::<()>()).abort_handle()
+                })
+            })
+        }).collect();
+        for dial in dials {
+            assert_eq!(dial.await.unwrap(), super::HandshakeState::Connecting);
+        }
+        assert_eq!(spawned.load(Ordering::Relaxed), 1);
+        assert!(a.peer_manager.cancel_outgoing(addr));
+    }
+
+    #[tokio::test]
+    async fn test_consent_prompt_is_withdrawn_when_the_dialer_goes_away() {
+        let a = TestNode::spawn("leave-a", 1024 * 1024).await;
+        let b = TestNode::spawn("leave-b", 1024 * 1024).await;
+        let consent = b.peer_manager.consent_manager.clone();
+        let bm = a.block_manager.clone();
+        let addr = b.addr();
+        let dial = tokio::spawn(async move { bm.connect_peer(&addr, bm.clone(), 0, Default::default()).await });
+        wait_until(|| consent.get_pending_list().len() == 1).await;
+        let session = consent.get_pending_list()[0].session_id.clone();
+
+        // Dropping the dialer closes its stream while B still waits for an answer
+        dial.abort();
+        wait_until(|| consent.get_pending_list().is_empty()).await;
+        let err = consent.resolve(&session, super::consent::ConsentDecision::ApprovedOnce).unwrap_err();
+        assert!(err.to_string().contains("withdrawn"), "{}", err);
+        assert!(b.peer_manager.get_peer_metadata_list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_pings_are_answered() {
+        let a = TestNode::spawn("ping-a", 1024 * 1024).await;
+        let b = TestNode::spawn("ping-b", 1024 * 1024).await;
+        a.peer_manager.set_keepalive_interval(std::time::Duration::from_millis(20));
+        a.connect_to(&b, 0).await;
+
+        // Let several intervals pass; pongs keep resetting the miss counter
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(a.peer_manager.get_peer_metadata_list()[0].healthy);
+        assert!(a.peer_manager.record_ping_sent(b.id, 1).unwrap() <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_available_peer_needs_quota_headroom() {
+        const KB: u64 = 1024;
+        let a = TestNode::spawn("pick-a", 4 * 1024 * KB).await;
+        let b = TestNode::spawn("pick-b", 1024 * KB).await;
+        let c = TestNode::spawn("pick-c", 256 * KB).await;
+        a.connect_to(&b, 0).await;
+        a.connect_to(&c, 0).await;
+
+        // Least loaded first: B granted the larger quota
+        assert_eq!(a.peer_manager.get_available_peer(100 * KB).await.unwrap(), b.id);
+
+        // Nearly fill B; only C can take the next block
+        let filler = a.block_manager.new_block(vec![1; 1014 * KB as usize], memsdk::Durability::Pinned);
+        a.block_manager.put_block_remote(filler, Some(b.id.to_string())).await.unwrap();
+        assert_eq!(a.peer_manager.get_available_peer(100 * KB).await.unwrap(), c.id);
+        let (id, _) = a.block_manager.put_block_remote(a.block_manager.new_block(vec![2; 100 * KB as usize], memsdk::Durability::Pinned), None).await.unwrap();
+        wait_until(|| c.block_manager.blocks.contains_key(&id)).await;
+
+        let err = a.peer_manager.get_available_peer(512 * KB).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("No peer with sufficient quota (need {}, best available {})", 512 * KB, 156 * KB));
+    }
+
+    #[tokio::test]
+    async fn test_offloaded_storage_follows_overwrites_and_evictions() {
+        let a = TestNode::spawn("held-a", 1024 * 1024).await;
+        let b = TestNode::spawn("held-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+        let target = b.id.to_string();
+
+        a.block_manager.set_remote("k", vec![1; 1000], &target, memsdk::Durability::Cache, None).await.unwrap();
+        assert_eq!(a.peer_manager.offloaded_storage(b.id), 1000);
+        a.block_manager.set_remote("k", vec![2; 400], &target, memsdk::Durability::Cache, None).await.unwrap();
+        assert_eq!(a.peer_manager.offloaded_storage(b.id), 400);
+
+        // B drops it on its own; its answer to the next ping says so. The
+        // faster pings start with the next connection.
+        b.block_manager.flush();
+        a.peer_manager.set_keepalive_interval(std::time::Duration::from_millis(20));
+        a.peer_manager.disconnect_peer(b.id).await;
+        a.connect_to(&b, 0).await;
+        wait_until(|| a.peer_manager.offloaded_storage(b.id) == 0).await;
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_keeps_used_storage() {
+        let a = TestNode::spawn("flap-a", 1024 * 1024).await;
+        let b = TestNode::spawn("flap-b", 1024 * 1024).await;
+        a.connect_to(&b, 0).await;
+
+        a.block_manager.set_remote("k", vec![7; 1000], &b.id.to_string(), memsdk::Durability::Pinned, None).await.unwrap();
+        assert_eq!(b.peer_manager.remote_used_storage(a.id), Some(1000));
+
+        // Drop the connection and come back; the fresh registration must not start at zero
+        a.peer_manager.disconnect_peer(b.id).await;
+        wait_until(|| b.peer_manager.remote_used_storage(a.id).is_none()).await;
+        a.connect_to(&b, 0).await;
+        assert_eq!(b.peer_manager.remote_used_storage(a.id), Some(1000));
+    }
+
+    /// Approve every consent prompt `node` gets until aborted.
+    fn approve_all(node: &TestNode) -> tokio::task::JoinHandle<()> {
+        let consent = node.peer_manager.consent_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                for req in consent.get_pending_list() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_changed_peer_key_is_refused() {
+        let a = TestNode::spawn("pin-a", 1024 * 1024).await;
+        let b = TestNode::spawn("pin-b", 1024 * 1024).await;
+        let addr: SocketAddr = b.addr().parse().unwrap();
+        a.connect_to(&b, 0).await;
+        let store = &a.peer_manager.trusted_store;
+        let b_key = hex::encode(b.peer_manager.get_identity().keypair.verifying_key().to_bytes());
+        assert_eq!(store.check_pin(b.id, &b.addr(), &b_key), trusted::PinCheck::Matches);
+
+        // b comes back at the same address with a key a has not pinned
+        a.peer_manager.disconnect_peer(b.id).await;
+        store.pin(b.id, &b.addr(), "pin-b", "00ff").unwrap();
+        let approver = approve_all(&b);
+        let err = a.block_manager.connect_peer(&b.addr(), a.block_manager.clone(), 0, DialPolicy::default()).await.unwrap_err();
+        assert!(err.to_string().contains("identity changed"), "{}", err);
+        assert!(matches!(a.peer_manager.handshake_state(addr), Some(HandshakeState::IdentityChanged(_))));
+
+        // Accepting the new key re-pins it
+        let accept = DialPolicy { accept_new_key: true, require_known: false };
+        a.block_manager.connect_peer(&b.addr(), a.block_manager.clone(), 0, accept).await.unwrap();
+        assert_eq!(store.check_pin(b.id, &b.addr(), &b_key), trusted::PinCheck::Matches);
+        approver.abort();
+
+        // Discovery only dials keys it already knows
+        let c = TestNode::spawn("pin-c", 1024 * 1024).await;
+        let approver = approve_all(&c);
+        let known_only = DialPolicy { accept_new_key: false, require_known: true };
+        assert!(a.block_manager.connect_peer(&c.addr(), a.block_manager.clone(), 0, known_only).await.is_err());
+        approver.abort();
+    }
+
+    #[tokio::test]
+    async fn test_connect_by_hostname() {
+        let a = TestNode::spawn("host-a", 1024 * 1024).await;
+        let b = TestNode::spawn("host-b", 1024 * 1024).await;
+        let c = TestNode::spawn("host-c", 1024 * 1024).await;
+        let approvers = [approve_all(&b), approve_all(&c)];
+
+        a.block_manager.connect_peer(&format!("localhost:{}", b.port), a.block_manager.clone(), 0, DialPolicy::default()).await.unwrap();
+        assert!(a.peer_manager.peers.contains_key(&b.id));
+
+        let err = resolve_peer_addrs("no-such-peer.invalid:8080").await.unwrap_err();
+        assert!(err.to_string().contains("Could not resolve 'no-such-peer.invalid:8080'"), "{}", err);
+        assert!(resolve_peer_addrs("localhost").await.unwrap_err().to_string().contains("host:port"));
+
+        // The first address refuses the connection, so the next one is tried
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let addrs = [closed, c.addr().parse().unwrap()];
+        a.peer_manager.connect_any(&addrs, a.block_manager.clone(), a.peer_manager.clone(), 0, DialPolicy::default()).await.unwrap();
+        assert!(a.peer_manager.peers.contains_key(&c.id));
+        assert_eq!(a.peer_manager.handshake_state(attempt_addr(&addrs)), Some(HandshakeState::Authenticated));
+        approvers.iter().for_each(|t| t.abort());
+    }
+
+    #[tokio::test]
+    async fn test_self_connection_and_id_collision() {
+        let a = TestNode::spawn("self-a", 1024 * 1024).await;
+        let err = a.block_manager.connect_peer(&a.addr(), a.block_manager.clone(), 0, DialPolicy::default()).await.unwrap_err();
+        assert!(err.is::<ConnectedToSelf>(), "{}", err);
+        assert!(a.peer_manager.peers.is_empty());
+        assert!(a.peer_manager.consent_manager.get_pending_list().is_empty());
+
+        // Two peers sharing an id but not a key are both kept
+        let b = TestNode::spawn("self-b", 1024 * 1024).await;
+        let twin = TestNode::spawn_with_id(b.id, "self-b", 1024 * 1024).await;
+        b.connect_to(&a, 0).await;
+        twin.connect_to(&a, 0).await;
+        wait_until(|| a.peer_manager.peers.len() == 2).await;
+        let twin_key = hex::encode(twin.peer_manager.get_identity().public_key().to_bytes());
+        let twin_id = a.peer_manager.resolve_peer(&twin_key).unwrap();
+        assert_ne!(twin_id, b.id);
+        assert!(a.peer_manager.peers.get(&twin_id).unwrap().name.starts_with("self-b [key "));
+        assert_eq!(a.peer_manager.resolve_peer("self-b").unwrap(), b.id);
+    }
+
+    #[tokio::test]
+    async fn test_older_protocol_versions_are_refused() {
+        use crate::net::auth::{HandshakeHello, HandshakeMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let a = TestNode::spawn("version-a", 1024 * 1024).await;
+        let mut stream = tokio::net::TcpStream::connect(a.addr()).await.unwrap();
+        let hello = HandshakeMessage::Hello(HandshakeHello {
+            version: MIN_PROTOCOL_VERSION - 1, nonce: [0; 32], eph_pub: [9; 32], quota: 0, total_memory: 0,
+        });
+        let bytes = bincode::serialize(&hello).unwrap();
+        stream.write_all(&(bytes.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+
+        let reply = crate::net::frame::read_frame(&mut stream, 1024).await.unwrap();
+        let reply: HandshakeMessage = bincode::deserialize(&reply).unwrap();
+        assert!(matches!(reply, HandshakeMessage::VersionMismatch { version: PROTOCOL_VERSION }), "{:?}", reply);
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+        assert!(a.peer_manager.peers.is_empty());
+        assert!(a.peer_manager.consent_manager.get_pending_list().is_empty());
+
+        // A newer peer is answered; it decides whether it can talk to us
+        let mut stream = tokio::net::TcpStream::connect(a.addr()).await.unwrap();
+        let hello = HandshakeMessage::Hello(HandshakeHello {
+            version: PROTOCOL_VERSION + 1, nonce: [0; 32], eph_pub: [9; 32], quota: 0, total_memory: 0,
+        });
+        let bytes = bincode::serialize(&hello).unwrap();
+        stream.write_all(&(bytes.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        let reply = crate::net::frame::read_frame(&mut stream, 1024).await.unwrap();
+        let reply: HandshakeMessage = bincode::deserialize(&reply).unwrap();
+        assert!(matches!(reply, HandshakeMessage::Hello(HandshakeHello { version: PROTOCOL_VERSION, .. })), "{:?}", reply);
+    }
+
+}
@@ -1,9 +1,14 @@
 use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::fs;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::{info, error};
+use uuid::Uuid;
+
+/// Connections kept in a trusted device's history.
+pub const CONNECTION_HISTORY_LEN: usize = 20;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TrustedDevice {
@@ -11,42 +16,103 @@ pub struct TrustedDevice {
     pub name: String,
     pub first_seen: u64,
     pub last_approved: u64,
+    // Files written before connections were tracked lack the fields below
+    #[serde(default)]
+    pub last_connected: Option<u64>,
+    #[serde(default)]
+    pub total_sessions: u64,
+    /// Why the device was trusted, in the user's words.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// The latest `CONNECTION_HISTORY_LEN` connections, oldest first.
+    #[serde(default)]
+    pub history: VecDeque<TrustedConnection>,
+    /// Whether the device may flush all of this node's data, not only the
+    /// blocks it stored here.
+    #[serde(default)]
+    pub allow_remote_flush: bool,
+    /// Where this node last dialed the device. Incoming connections come
+    /// from a port nobody listens on, so they do not change it.
+    #[serde(default)]
+    pub last_addr: Option<String>,
+}
+
+/// One authenticated connection with a trusted device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrustedConnection {
+    pub at: u64,
+    pub addr: String,
+}
+
+/// The key a peer presented the first time we connected to it (trust on
+/// first use). Later outgoing connections to the same node or address must
+/// see the same key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PinnedPeer {
+    pub node_id: Uuid,
+    pub addr: String,
+    pub name: String,
+    pub public_key: String,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Result of comparing a presented key with the pinned ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PinCheck {
+    /// Neither the node nor the address has been connected to before.
+    Unknown,
+    Matches,
+    /// The node or address was pinned to a different key.
+    Changed(PinnedPeer),
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct TrustedStoreData {
     trusted: Vec<TrustedDevice>,
+    #[serde(default)]
+    pinned: Vec<PinnedPeer>,
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 pub struct TrustedStore {
-    file_path: PathBuf,
+    // None when there is no data directory; trust then lasts until the node exits
+    file_path: Option<PathBuf>,
     data: Arc<RwLock<TrustedStoreData>>,
 }
 
 impl TrustedStore {
-    pub fn new() -> Self {
-        let home = dirs::home_dir().expect("Could not find home directory");
-        let path = home.join(".memcloud").join("trusted_devices.json");
-        
+    /// Trust that lasts until the node exits, for nodes without a data directory.
+    pub fn in_memory() -> Self {
+        Self { file_path: None, data: Arc::new(RwLock::new(TrustedStoreData::default())) }
+    }
+
+    /// The store kept in `path`, loaded if it exists.
+    pub fn open(path: PathBuf) -> Self {
+        let file_path = Some(path);
         let store = Self {
-            file_path: path.clone(),
+            file_path,
             data: Arc::new(RwLock::new(TrustedStoreData::default())),
         };
         
         if let Err(e) = store.load() {
-            if path.exists() {
-               error!("Failed to load trusted devices: {}", e);
-            }
+            error!("Failed to load trusted devices: {}", e);
         }
         
         store
     }
 
     fn load(&self) -> Result<()> {
-        if !self.file_path.exists() {
+        let Some(path) = self.file_path.as_ref().filter(|p| p.exists()) else {
             return Ok(());
-        }
-        let content = fs::read_to_string(&self.file_path)?;
+        };
+        let content = fs::read_to_string(path)?;
         let data: TrustedStoreData = serde_json::from_str(&content)?;
         let mut lock = self.data.write().unwrap();
         *lock = data;
@@ -54,13 +120,16 @@ impl TrustedStore {
     }
 
     fn save(&self) -> Result<()> {
+        let Some(path) = &self.file_path else {
+            return Ok(());
+        };
         let lock = self.data.read().unwrap();
         let content = serde_json::to_string_pretty(&*lock)?;
         
-        if let Some(parent) = self.file_path.parent() {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
-        fs::write(&self.file_path, content)?;
+        fs::write(path, content)?;
         Ok(())
     }
 
@@ -69,26 +138,88 @@ impl TrustedStore {
         lock.trusted.iter().any(|d| d.public_key == public_key)
     }
 
-    pub fn add_trusted(&self, public_key: String, name: String) -> Result<()> {
+    /// Trust `public_key`. Approving a key again renames it and keeps its
+    /// history, and its note unless a new one is given.
+    pub fn add_trusted(&self, public_key: String, name: String, note: Option<String>) -> Result<()> {
         {
             let mut lock = self.data.write().unwrap();
-            lock.trusted.retain(|d| d.public_key != public_key);
-            
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-
-            lock.trusted.push(TrustedDevice {
-                public_key,
-                name,
-                first_seen: now,
-                last_approved: now,
-            });
+            let now = unix_now();
+            if let Some(device) = lock.trusted.iter_mut().find(|d| d.public_key == public_key) {
+                device.name = name;
+                device.last_approved = now;
+                device.note = note.or(device.note.take());
+            } else {
+                lock.trusted.push(TrustedDevice {
+                    public_key,
+                    name,
+                    first_seen: now,
+                    last_approved: now,
+                    last_connected: None,
+                    total_sessions: 0,
+                    note,
+                    history: VecDeque::new(),
+                    allow_remote_flush: false,
+                    last_addr: None,
+                });
+            }
+        }
+        self.save()
+    }
+
+    /// Note an authenticated connection with `addr` if `public_key` is
+    /// trusted; `dialed` when this node connected to it there.
+    pub fn record_connection(&self, public_key: &str, addr: &str, dialed: bool) -> Result<()> {
+        {
+            let mut lock = self.data.write().unwrap();
+            let Some(device) = lock.trusted.iter_mut().find(|d| d.public_key == public_key) else {
+                return Ok(());
+            };
+            let now = unix_now();
+            device.last_connected = Some(now);
+            device.total_sessions += 1;
+            if device.history.len() == CONNECTION_HISTORY_LEN {
+                device.history.pop_front();
+            }
+            device.history.push_back(TrustedConnection { at: now, addr: addr.to_string() });
+            if dialed {
+                device.last_addr = Some(addr.to_string());
+            }
         }
         self.save()
     }
 
+    /// Let the trusted device with this key flush everything on this node,
+    /// or take that back. Returns false if the key is not trusted.
+    pub fn set_remote_flush(&self, public_key: &str, allow: bool) -> Result<bool> {
+        {
+            let mut lock = self.data.write().unwrap();
+            let Some(device) = lock.trusted.iter_mut().find(|d| d.public_key == public_key) else {
+                return Ok(false);
+            };
+            device.allow_remote_flush = allow;
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn allows_remote_flush(&self, public_key: &str) -> bool {
+        let lock = self.data.read().unwrap();
+        lock.trusted.iter().any(|d| d.public_key == public_key && d.allow_remote_flush)
+    }
+
+    /// The trusted device with this key, or the only one with this name.
+    pub fn find_trusted(&self, public_key_or_name: &str) -> Result<Option<TrustedDevice>> {
+        let lock = self.data.read().unwrap();
+        if let Some(device) = lock.trusted.iter().find(|d| d.public_key == public_key_or_name) {
+            return Ok(Some(device.clone()));
+        }
+        let named: Vec<&TrustedDevice> = lock.trusted.iter().filter(|d| d.name == public_key_or_name).collect();
+        if named.len() > 1 {
+            bail!("{} trusted devices are named '{}'; give the public key instead", named.len(), public_key_or_name);
+        }
+        Ok(named.first().map(|d| (*d).clone()))
+    }
+
     pub fn remove_trusted(&self, public_key_or_name: &str) -> Result<Vec<TrustedDevice>> {
         let mut removed_items = Vec::new();
         {
@@ -113,4 +244,123 @@ impl TrustedStore {
         let lock = self.data.read().unwrap();
         lock.trusted.clone()
     }
+
+    /// Whether `public_key` was approved or seen on an earlier outgoing connection.
+    pub fn is_known(&self, public_key: &str) -> bool {
+        let lock = self.data.read().unwrap();
+        lock.trusted.iter().any(|d| d.public_key == public_key)
+            || lock.pinned.iter().any(|p| p.public_key == public_key)
+    }
+
+    pub fn check_pin(&self, node_id: Uuid, addr: &str, public_key: &str) -> PinCheck {
+        let lock = self.data.read().unwrap();
+        let mut matched = false;
+        for pin in lock.pinned.iter().filter(|p| p.node_id == node_id || p.addr == addr) {
+            if pin.public_key != public_key {
+                return PinCheck::Changed(pin.clone());
+            }
+            matched = true;
+        }
+        if matched { PinCheck::Matches } else { PinCheck::Unknown }
+    }
+
+    /// Pin `public_key` for the node and the address, replacing whatever
+    /// either was pinned to before.
+    pub fn pin(&self, node_id: Uuid, addr: &str, name: &str, public_key: &str) -> Result<()> {
+        {
+            let mut lock = self.data.write().unwrap();
+            let now = unix_now();
+            let first_seen = lock.pinned.iter()
+                .filter(|p| p.node_id == node_id && p.public_key == public_key)
+                .map(|p| p.first_seen)
+                .min()
+                .unwrap_or(now);
+            lock.pinned.retain(|p| p.node_id != node_id && p.addr != addr);
+            lock.pinned.push(PinnedPeer {
+                node_id,
+                addr: addr.to_string(),
+                name: name.to_string(),
+                public_key: public_key.to_string(),
+                first_seen,
+                last_seen: now,
+            });
+        }
+        self.save()
+    }
+
+    /// Forget the pinned keys of peers with this key or name. Returns how many were dropped.
+    pub fn forget_pins(&self, public_key_or_name: &str) -> Result<usize> {
+        let removed = {
+            let mut lock = self.data.write().unwrap();
+            let before = lock.pinned.len();
+            lock.pinned.retain(|p| p.public_key != public_key_or_name && p.name != public_key_or_name);
+            before - lock.pinned.len()
+        };
+        if removed > 0 {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_follow_node_and_address() {
+        let store = TrustedStore::in_memory();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(store.check_pin(a, "10.0.0.1:8080", "key-a"), PinCheck::Unknown);
+        assert!(!store.is_known("key-a"));
+
+        store.pin(a, "10.0.0.1:8080", "laptop", "key-a").unwrap();
+        assert!(store.is_known("key-a"));
+        assert_eq!(store.check_pin(a, "10.0.0.1:8080", "key-a"), PinCheck::Matches);
+        // The same node at a new address, and another node at the pinned address
+        assert_eq!(store.check_pin(a, "10.0.0.2:8080", "key-a"), PinCheck::Matches);
+        assert!(matches!(store.check_pin(a, "10.0.0.2:8080", "key-x"), PinCheck::Changed(p) if p.public_key == "key-a"));
+        assert!(matches!(store.check_pin(b, "10.0.0.1:8080", "key-b"), PinCheck::Changed(p) if p.name == "laptop"));
+        assert_eq!(store.check_pin(b, "10.0.0.3:8080", "key-b"), PinCheck::Unknown);
+
+        // Accepting the new key replaces the old pin for both
+        store.pin(b, "10.0.0.1:8080", "desktop", "key-b").unwrap();
+        assert_eq!(store.check_pin(b, "10.0.0.1:8080", "key-b"), PinCheck::Matches);
+        assert_eq!(store.check_pin(a, "10.0.0.9:8080", "key-a"), PinCheck::Unknown);
+
+        assert_eq!(store.forget_pins("desktop").unwrap(), 1);
+        assert_eq!(store.check_pin(b, "10.0.0.1:8080", "key-b"), PinCheck::Unknown);
+    }
+
+    #[test]
+    fn test_old_files_load_and_connections_are_recorded() {
+        let path = std::env::temp_dir().join(format!("memnode-trusted-{}.json", Uuid::new_v4()));
+        fs::write(&path, r#"{"trusted":[{"public_key":"key-a","name":"laptop","first_seen":100,"last_approved":200}]}"#).unwrap();
+        let store = TrustedStore::open(path.clone());
+        let device = store.find_trusted("laptop").unwrap().unwrap();
+        assert_eq!((device.first_seen, device.last_connected, device.total_sessions), (100, None, 0));
+        assert!(!device.allow_remote_flush);
+        assert!(store.set_remote_flush("key-a", true).unwrap());
+        assert!(!store.set_remote_flush("key-unknown", true).unwrap());
+
+        for i in 0..CONNECTION_HISTORY_LEN + 2 {
+            store.record_connection("key-a", &format!("10.0.0.{}:8080", i), i == 3).unwrap();
+        }
+        store.record_connection("key-unknown", "10.0.0.9:8080", true).unwrap();
+        // Approving again keeps the history and the first sighting
+        store.add_trusted("key-a".to_string(), "laptop".to_string(), Some("work machine".to_string())).unwrap();
+
+        let device = TrustedStore::open(path.clone()).find_trusted("key-a").unwrap().unwrap();
+        assert_eq!(device.total_sessions, CONNECTION_HISTORY_LEN as u64 + 2);
+        assert_eq!(device.history.len(), CONNECTION_HISTORY_LEN);
+        assert_eq!(device.history.front().unwrap().addr, "10.0.0.2:8080");
+        assert_eq!((device.first_seen, device.note.as_deref()), (100, Some("work machine")));
+        assert!(device.last_connected.is_some());
+        assert!(device.allow_remote_flush);
+        assert_eq!(device.last_addr.as_deref(), Some("10.0.0.3:8080"));
+
+        store.add_trusted("key-b".to_string(), "laptop".to_string(), None).unwrap();
+        assert!(store.find_trusted("laptop").is_err());
+        fs::remove_file(path).unwrap();
+    }
 }
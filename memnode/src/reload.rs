@@ -0,0 +1,108 @@
+//! `SdkCommand::ReloadConfig`: read the config file again, merge it under the
+//! flags the node was started with and apply what can change while running
+//! (see [`RELOADABLE_KEYS`]). Other changed keys only take effect on restart.
+
+use anyhow::{anyhow, Result};
+use log::info;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::blocks::InMemoryBlockManager;
+use crate::config::{ConfigSource, NodeConfig, RELOADABLE_KEYS};
+
+pub struct ConfigReloader {
+    source: ConfigSource,
+    // What the node runs with: the startup config plus every reload applied since
+    current: Mutex<NodeConfig>,
+}
+
+impl ConfigReloader {
+    /// `None` for configs that were not loaded from flags and a file.
+    pub fn new(config: &NodeConfig) -> Option<Self> {
+        let source = config.source.clone()?;
+        Some(Self { source, current: Mutex::new(config.clone()) })
+    }
+
+    /// Returns the keys applied and the changed keys that need a restart. A
+    /// file that fails to parse changes nothing.
+    pub fn reload(&self, block_manager: &InMemoryBlockManager) -> Result<(Vec<String>, Vec<String>)> {
+        let new = self.source.load()?;
+        let mut current = self.current.lock().unwrap();
+        let (mut applied, mut restart) = (Vec::new(), Vec::new());
+        for key in current.changed_keys(&new) {
+            // The logger only follows log_level if it was set up with one
+            let reloadable = RELOADABLE_KEYS.contains(&key)
+                && (key != "log_level" || (current.log_level.is_some() && new.log_level.is_some()));
+            if reloadable {
+                applied.push(key.to_string());
+            } else {
+                restart.push(key.to_string());
+            }
+        }
+
+        let pm = &block_manager.peer_manager;
+        if let Some(level) = new.log_level.filter(|_| applied.iter().any(|k| k == "log_level")) {
+            log::set_max_level(level);
+            current.log_level = Some(level);
+        }
+        block_manager.memory_guard.set_limits(new.rss_soft_limit, new.rss_hard_limit);
+        pm.set_default_quota(new.default_allowed_quota);
+        pm.consent_manager.set_timeout(Duration::from_secs(new.consent_timeout));
+        block_manager.set_orphan_gc_idle(new.orphan_gc_idle);
+        block_manager.set_read_only(new.read_only);
+        block_manager.set_stream_idle_timeout(new.stream_idle_timeout);
+        block_manager.set_max_stream_memory(new.max_stream_memory);
+        current.rss_soft_limit = new.rss_soft_limit;
+        current.rss_hard_limit = new.rss_hard_limit;
+        current.default_allowed_quota = new.default_allowed_quota;
+        current.consent_timeout = new.consent_timeout;
+        current.orphan_gc_idle = new.orphan_gc_idle;
+        current.read_only = new.read_only;
+        current.stream_idle_timeout = new.stream_idle_timeout;
+        current.max_stream_memory = new.max_stream_memory;
+
+        info!("Reloaded config: applied [{}], needs restart [{}]", applied.join(", "), restart.join(", "));
+        Ok((applied, restart))
+    }
+}
+
+impl InMemoryBlockManager {
+    pub fn reload_config(&self) -> Result<(Vec<String>, Vec<String>)> {
+        self.config_reloader.get()
+            .ok_or_else(|| anyhow!("This node was not started from a config file"))?
+            .reload(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use crate::test_support::TestNode;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_reload_applies_runtime_keys_only() {
+        let node = TestNode::spawn("reload", 64 * 1024 * 1024).await;
+        let path = std::env::temp_dir().join(format!("memnode-reload-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "port = 9000\ndefault_allowed_quota = \"32mb\"\nconsent_timeout = 10\n").unwrap();
+        let args = Args::try_parse_from(["memnode", "--config", path.to_str().unwrap(), "--consent-timeout", "5"]).unwrap();
+        let config = ConfigSource::new(args).load().unwrap();
+        assert_eq!(config.consent_timeout, 5);
+        let _ = node.block_manager.config_reloader.set(ConfigReloader::new(&config).unwrap());
+
+        // The flag keeps winning over consent_timeout; port needs a restart
+        std::fs::write(&path, "port = 9001\ndefault_allowed_quota = \"16mb\"\nconsent_timeout = 60\n").unwrap();
+        let (applied, restart) = node.block_manager.reload_config().unwrap();
+        assert_eq!(applied, ["default_allowed_quota"]);
+        assert_eq!(restart, ["port"]);
+        assert_eq!(node.peer_manager.default_quota(), 16 * 1024 * 1024);
+
+        // A broken file changes nothing and says where it is broken
+        std::fs::write(&path, "port = 9001\ndefault_allowed_quota = \"lots\"\n").unwrap();
+        let err = node.block_manager.reload_config().unwrap_err().to_string();
+        assert!(err.contains("line 2") && err.contains("default_allowed_quota"), "{}", err);
+        assert_eq!(node.peer_manager.default_quota(), 16 * 1024 * 1024);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
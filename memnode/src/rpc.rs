@@ -1,48 +1,96 @@
 use serde::{Serialize, Deserialize};
 #[cfg(unix)]
 use tokio::net::{UnixListener, UnixStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use anyhow::Result;
-use log::{info, error};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use anyhow::{bail, Context, Result};
+use log::{info, warn, error};
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 use crate::blocks::{BlockManager, Block, InMemoryBlockManager}; // Need concrete type for async method or cast
+use crate::blocks::apps::{AppTag, CURRENT_APP};
+use crate::blocks::pressure::MemoryPressure;
+use crate::blocks::forward::StreamRejected;
+use crate::blocks::tags::check_tags;
 use crate::metadata::BlockId;
+use crate::net::frame::{read_frame_body_into, FrameTooLarge};
+use crate::peers::{attempt_addr, resolve_peer_addrs};
+use crate::trace;
 
 // Removed local string_id, SdkCommand, SdkResponse, etc. Using memsdk versions.
-use memsdk::{SdkCommand, SdkResponse, TrustedDevice, PendingConsent};
+use memsdk::{CommandTrace, ErrorCode, FreeStats, SdkCommand, SdkResponse, TrustedConnection, TrustedDevice, PendingConsent};
 
 pub struct RpcServer {
     socket_path: String,
+    // Second unix socket whose connections may only read
+    readonly_socket: Option<String>,
+    socket_perms: SocketPerms,
+    // Pre-shared keys required on the TCP listener; the unix sockets rely on file permissions
+    tokens: Arc<RpcTokens>,
+    tcp_addr: Option<String>,
+    limits: RpcLimits,
     // We retain Arc<InMemoryBlockManager> to access specific async methods if trait doesn't have them
     // Or we update trait. For now, let's keep it simple and cast or hold concrete type.
     block_manager: Arc<InMemoryBlockManager>,
 }
 
 impl RpcServer {
-    pub fn new(socket_path: &str, block_manager: Arc<InMemoryBlockManager>) -> Self {
-        let _ = std::fs::remove_file(socket_path);
-        
+    pub fn new(socket_path: &str, readonly_socket: Option<String>, tcp_addr: Option<String>, tokens: RpcTokens, limits: RpcLimits, block_manager: Arc<InMemoryBlockManager>) -> Self {
         Self {
             socket_path: socket_path.to_string(),
+            readonly_socket,
+            socket_perms: SocketPerms::default(),
+            tokens: Arc::new(tokens),
+            tcp_addr,
+            limits,
             block_manager,
         }
     }
 
+    /// Permissions and group for the unix sockets, instead of the owner only.
+    pub fn with_socket_perms(mut self, perms: SocketPerms) -> Self {
+        self.socket_perms = perms;
+        self
+    }
+
+    /// Bind the unix sockets and the TCP listener, so clients can connect as
+    /// soon as this returns.
     #[cfg(unix)]
-    pub async fn run(&self) -> Result<()> {
-        let unix_listener = UnixListener::bind(&self.socket_path)?;
-        let tcp_listener = tokio::net::TcpListener::bind("127.0.0.1:7070").await?;
-        
-        info!("RPC Server listenting on {} and 127.0.0.1:7070 (JSON)", self.socket_path);
+    pub async fn bind(&self) -> Result<RpcListeners> {
+        // By default only the user running the node may send it commands; the
+        // read-only socket may always be shared with the group
+        let gid = self.socket_perms.gid()?;
+        let mode = self.socket_perms.mode;
+        let mut unix = vec![(bind_unix(&self.socket_path, mode, gid).await?, Access::Admin)];
+        if let Some(path) = &self.readonly_socket {
+            unix.push((bind_unix(path, mode | 0o060, gid).await?, Access::Read));
+            info!("Read-only RPC socket at {}", path);
+        }
+        let tcp = match &self.tcp_addr {
+            Some(addr) => {
+                info!("RPC Server listening on {} and {} (MessagePack)", self.socket_path, addr);
+                Some(tokio::net::TcpListener::bind(addr).await?)
+            }
+            None => {
+                info!("RPC Server listening on {}", self.socket_path);
+                None
+            }
+        };
+        Ok(RpcListeners { unix, tcp })
+    }
 
+    #[cfg(unix)]
+    pub async fn serve(&self, listeners: RpcListeners) {
+        let RpcListeners { unix: unix_listeners, tcp: tcp_listener } = listeners;
         loop {
             tokio::select! {
-                res = unix_listener.accept() => {
+                (res, access) = accept_unix(&unix_listeners) => {
                    match res {
                        Ok((stream, _)) => {
                            let bm = self.block_manager.clone();
+                           let limits = self.limits.clone();
                            tokio::spawn(async move {
-                               if let Err(e) = handle_client_unix(stream, bm).await {
+                               if let Err(e) = handle_client_unix(stream, bm, access, limits).await {
                                    error!("RPC Client error (Unix): {}", e);
                                }
                            });
@@ -50,12 +98,14 @@ impl RpcServer {
                        Err(e) => error!("Unix Accept Error: {}", e),
                    }
                 }
-                res = tcp_listener.accept() => {
+                res = accept_optional(&tcp_listener) => {
                     match res {
                         Ok((stream, _)) => {
                             let bm = self.block_manager.clone();
+                            let tokens = self.tokens.clone();
+                            let limits = self.limits.clone();
                             tokio::spawn(async move {
-                                if let Err(e) = handle_client_tcp(stream, bm).await {
+                                if let Err(e) = handle_client_tcp(stream, bm, tokens, limits).await {
                                      error!("RPC Client error (TCP): {}", e);
                                 }
                             });
@@ -68,16 +118,28 @@ impl RpcServer {
     }
 
     #[cfg(windows)]
-    pub async fn run(&self) -> Result<()> {
-        let tcp_listener = tokio::net::TcpListener::bind("127.0.0.1:7070").await?;
-        info!("RPC Server listening on 127.0.0.1:7070 (JSON)");
+    pub async fn bind(&self) -> Result<RpcListeners> {
+        // There is no unix socket to fall back to
+        if self.socket_perms.group.is_some() {
+            warn!("--socket-group has no effect on Windows");
+        }
+        let addr = self.tcp_addr.as_deref().unwrap_or(crate::config::DEFAULT_RPC_ADDR);
+        let tcp = tokio::net::TcpListener::bind(addr).await?;
+        info!("RPC Server listening on {} (MessagePack)", addr);
+        Ok(RpcListeners { tcp })
+    }
 
+    #[cfg(windows)]
+    pub async fn serve(&self, listeners: RpcListeners) {
+        let tcp_listener = listeners.tcp;
         loop {
             match tcp_listener.accept().await {
                 Ok((stream, _)) => {
                     let bm = self.block_manager.clone();
+                    let tokens = self.tokens.clone();
+                    let limits = self.limits.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_client_tcp(stream, bm).await {
+                        if let Err(e) = handle_client_tcp(stream, bm, tokens, limits).await {
                                 error!("RPC Client error (TCP): {}", e);
                         }
                     });
@@ -88,329 +150,1876 @@ impl RpcServer {
     }
 }
 
-// Generic handler using AsyncRead/Write
-async fn handle_generic_stream<S>(mut stream: S, block_manager: Arc<InMemoryBlockManager>) -> Result<()> 
-where S: AsyncReadExt + AsyncWriteExt + Unpin 
+/// Largest read buffer a connection keeps for its next frame.
+const READ_BUFFER_RETAINED: usize = 1024 * 1024;
+/// `Loaded` payloads from this size on are written from the block instead of
+/// being copied into the encoded response first.
+const DIRECT_PAYLOAD_MIN: usize = 64 * 1024;
+
+/// How long a node that already has the socket gets to answer before it is taken for dead.
+#[cfg(unix)]
+const SOCKET_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+// A socket file left at `path` is removed only if nothing answers on it. A
+// live node keeps its socket and this one refuses to start instead, as it
+// does when `path` is something other than a socket.
+#[cfg(unix)]
+async fn claim_socket(path: &str) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match std::fs::symlink_metadata(path) {
+        Err(_) => return Ok(()),
+        Ok(meta) if !meta.file_type().is_socket() => {
+            bail!("{} exists and is not a socket; remove it or start this node with another --socket", path);
+        }
+        Ok(_) => {}
+    }
+    match tokio::time::timeout(SOCKET_PROBE_TIMEOUT, memsdk::MemCloudClient::connect_with_path(path)).await {
+        Ok(Ok(mut client)) => {
+            client.set_timeout(Some(SOCKET_PROBE_TIMEOUT));
+            let owner = match client.ping().await {
+                Ok(health) => format!(
+                    "node name {}, pid {}",
+                    health.name.as_deref().unwrap_or("unknown"),
+                    health.pid.map(|p| p.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                ),
+                Err(_) => "it did not answer a ping".to_string(),
+            };
+            bail!("Another memnode is already serving {} ({}); stop it or start this one with --socket", path, owner);
+        }
+        Ok(Err(memsdk::MemCloudError::Io(e))) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            bail!("RPC socket {} belongs to another user ({}); start this node with --socket or MEMCLOUD_HOME", path, e);
+        }
+        // Refused or timed out: left behind by a node that is gone
+        _ => {
+            std::fs::remove_file(path).with_context(|| format!("Failed to remove stale RPC socket {}", path))?;
+            info!("Removed stale RPC socket {}", path);
+            Ok(())
+        }
+    }
+}
+
+/// Who may connect to the unix sockets besides the user running the node.
+#[derive(Debug, Clone)]
+pub struct SocketPerms {
+    pub mode: u32,
+    /// Group name or id the sockets are given; `None` keeps the node's own
+    pub group: Option<String>,
+}
+
+impl Default for SocketPerms {
+    fn default() -> Self {
+        Self { mode: crate::config::DEFAULT_SOCKET_MODE, group: None }
+    }
+}
+
+impl SocketPerms {
+    #[cfg(unix)]
+    fn gid(&self) -> Result<Option<nix::unistd::Gid>> {
+        let Some(group) = &self.group else {
+            return Ok(None);
+        };
+        if let Ok(id) = group.parse::<u32>() {
+            return Ok(Some(nix::unistd::Gid::from_raw(id)));
+        }
+        match nix::unistd::Group::from_name(group) {
+            Ok(Some(g)) => Ok(Some(g.gid)),
+            Ok(None) => bail!("Invalid --socket-group: no group named '{}'", group),
+            Err(e) => bail!("Could not look up group '{}': {}", group, e),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn bind_unix(path: &str, mode: u32, gid: Option<nix::unistd::Gid>) -> Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+    claim_socket(path).await?;
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind RPC socket {}", path))?;
+    // The group first, so a group-readable mode never applies to the wrong group
+    if let Some(gid) = gid {
+        nix::unistd::chown(path, None, Some(gid)).with_context(|| format!("Failed to give RPC socket {} to group {}", path, gid))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// Listeners returned by [`RpcServer::bind`], not yet accepting. Each unix
+/// socket carries the access its connections get.
+pub struct RpcListeners {
+    #[cfg(unix)]
+    unix: Vec<(UnixListener, Access)>,
+    #[cfg(unix)]
+    tcp: Option<tokio::net::TcpListener>,
+    #[cfg(windows)]
+    tcp: tokio::net::TcpListener,
+}
+
+// The next connection on any of the unix sockets, with that socket's access
+#[cfg(unix)]
+async fn accept_unix(listeners: &[(UnixListener, Access)]) -> (std::io::Result<(UnixStream, tokio::net::unix::SocketAddr)>, Access) {
+    let accepts = listeners.iter().map(|(listener, access)| Box::pin(async move { (listener.accept().await, *access) }));
+    futures::future::select_all(accepts).await.0
+}
+
+// Never resolves when the TCP listener is disabled
+#[cfg(unix)]
+async fn accept_optional(listener: &Option<tokio::net::TcpListener>) -> std::io::Result<(tokio::net::TcpStream, std::net::SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Limits on RPC command execution, shared by every connection of a server.
+#[derive(Clone)]
+pub struct RpcLimits {
+    /// Commands a single connection may have executing at once
+    pub pipeline_depth: usize,
+    /// Payload size from which Store/StreamChunk/Load count as heavy
+    pub heavy_threshold: u64,
+    /// Permits for heavy commands across all connections
+    pub heavy_permits: Arc<Semaphore>,
+    /// Largest request frame accepted; bigger values must be streamed
+    pub max_frame: usize,
+    /// Uploads a single connection may have open at once
+    pub max_streams: usize,
+}
+
+impl RpcLimits {
+    pub fn new(pipeline_depth: usize, max_heavy: usize, heavy_threshold: u64, max_frame: usize, max_streams: usize) -> Self {
+        Self {
+            pipeline_depth: pipeline_depth.max(1),
+            heavy_threshold,
+            heavy_permits: Arc::new(Semaphore::new(max_heavy.max(1))),
+            max_frame,
+            max_streams: max_streams.max(1),
+        }
+    }
+
+    fn is_heavy(&self, cmd: &SdkCommand, block_manager: &InMemoryBlockManager) -> bool {
+        let size = match cmd {
+            SdkCommand::Store { data, .. }
+            | SdkCommand::StoreRemote { data, .. }
+            | SdkCommand::Set { data, .. }
+            | SdkCommand::Append { data, .. }
+            | SdkCommand::StreamChunk { data, .. } => data.len() as u64,
+            // Only local blocks have a known size up front
+            SdkCommand::Load { id } => block_manager.blocks.get(id).map_or(0, |b| b.data.len() as u64),
+            _ => 0,
+        };
+        size >= self.heavy_threshold
+    }
+
+    async fn heavy_permit(&self, cmd: &SdkCommand, block_manager: &InMemoryBlockManager) -> Option<OwnedSemaphorePermit> {
+        if self.is_heavy(cmd, block_manager) {
+            self.heavy_permits.clone().acquire_owned().await.ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for RpcLimits {
+    fn default() -> Self {
+        use crate::config::{DEFAULT_RPC_MAX_HEAVY, DEFAULT_RPC_MAX_STREAMS, DEFAULT_RPC_PIPELINE_DEPTH};
+        Self::new(DEFAULT_RPC_PIPELINE_DEPTH, DEFAULT_RPC_MAX_HEAVY, 1024 * 1024, crate::net::frame::DEFAULT_MAX_FRAME, DEFAULT_RPC_MAX_STREAMS)
+    }
+}
+
+/// What an RPC connection may do. Each level includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    /// Reads and stats: Load, Get, ListKeys, Stat, ListPeers and the like
+    Read,
+    /// Also stores, frees and streams data
+    Write,
+    /// Also flushes, manages peers, trust and consent, and reloads config
+    Admin,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Admin => "admin",
+        })
+    }
+}
+
+/// Pre-shared keys for the TCP listener, one per access level. Without any,
+/// TCP connections get admin access like the main unix socket.
+#[derive(Debug, Clone, Default)]
+pub struct RpcTokens {
+    pub admin: Option<String>,
+    pub write: Option<String>,
+    pub read: Option<String>,
+}
+
+impl RpcTokens {
+    fn is_empty(&self) -> bool {
+        self.admin.is_none() && self.write.is_none() && self.read.is_none()
+    }
+
+    // Every token is compared, so the timing does not tell which one matched
+    fn access_for(&self, given: &str) -> Option<Access> {
+        [(Access::Admin, &self.admin), (Access::Write, &self.write), (Access::Read, &self.read)]
+            .into_iter()
+            .filter(|(_, token)| token.as_deref().is_some_and(|t| token_eq(t, given)))
+            .map(|(access, _)| access)
+            .max()
+    }
+}
+
+/// Where a connection's access comes from.
+#[derive(Clone)]
+pub(crate) enum Grant {
+    /// Fixed by the unix socket it came in on
+    Endpoint(Access),
+    /// Whichever token it sends with Auth; every other command is refused until then
+    Tokens(Arc<RpcTokens>),
+}
+
+fn required_access(cmd: &SdkCommand) -> Access {
+    match cmd {
+        SdkCommand::Load { .. }
+        | SdkCommand::Get { .. }
+        | SdkCommand::ListKeys { .. }
+        | SdkCommand::HotKeys { .. }
+        | SdkCommand::FindByTag { .. }
+        | SdkCommand::BlockInfo { .. }
+        | SdkCommand::ListBlocks { .. }
+        | SdkCommand::Stat
+        | SdkCommand::StatHistory { .. }
+        | SdkCommand::PeerStat { .. }
+        | SdkCommand::PeerInspect { .. }
+        | SdkCommand::Metrics
+        | SdkCommand::ClusterStat
+        | SdkCommand::NodeInfo
+        | SdkCommand::Ping
+        | SdkCommand::ListPeers
+        | SdkCommand::MigrationStatus { .. }
+        | SdkCommand::DrainStatus { .. }
+        | SdkCommand::StreamList
+        | SdkCommand::AppUsage
+        | SdkCommand::Events { .. }
+        | SdkCommand::VmFetch { .. }
+        | SdkCommand::VmConfig { threshold_mb: None }
+        | SdkCommand::Auth { .. }
+        | SdkCommand::ClientHello { .. }
+        | SdkCommand::Pipelined { .. } => Access::Read,
+        SdkCommand::Store { .. }
+        | SdkCommand::StoreRemote { .. }
+        | SdkCommand::Set { .. }
+        | SdkCommand::Incr { .. }
+        | SdkCommand::Append { .. }
+        | SdkCommand::Rename { .. }
+        | SdkCommand::Alias { .. }
+        | SdkCommand::Free { .. }
+        | SdkCommand::FreeMany { .. }
+        | SdkCommand::SetDurability { .. }
+        | SdkCommand::SetPriority { .. }
+        | SdkCommand::StreamStart { .. }
+        | SdkCommand::StreamChunk { .. }
+        | SdkCommand::StreamFinish { .. }
+        | SdkCommand::StreamAbort { .. }
+        | SdkCommand::Migrate { .. }
+        | SdkCommand::VmAlloc { .. }
+        | SdkCommand::VmStore { .. } => Access::Write,
+        SdkCommand::Flush { .. }
+        | SdkCommand::FlushCluster { .. }
+        | SdkCommand::Connect { .. }
+        | SdkCommand::ReconnectTrusted { .. }
+        | SdkCommand::PollConnection { .. }
+        | SdkCommand::CancelConnect { .. }
+        | SdkCommand::Disconnect { .. }
+        | SdkCommand::UpdatePeerQuota { .. }
+        | SdkCommand::TrustList
+        | SdkCommand::TrustAdd { .. }
+        | SdkCommand::TrustRemove { .. }
+        | SdkCommand::TrustInfo { .. }
+        | SdkCommand::AllowRemoteFlush { .. }
+        | SdkCommand::ConsentList
+        | SdkCommand::ConsentApprove { .. }
+        | SdkCommand::ConsentDeny { .. }
+        | SdkCommand::ReloadConfig
+        | SdkCommand::Shutdown
+        | SdkCommand::VmConfig { threshold_mb: Some(_) } => Access::Admin,
+    }
+}
+
+// Commands a read-only node refuses: everything that stores, changes or frees
+// data, here or on peers through this node
+fn changes_data(cmd: &SdkCommand) -> bool {
+    match cmd {
+        SdkCommand::Flush { .. } | SdkCommand::FlushCluster { .. } => true,
+        SdkCommand::Disconnect { drain, .. } | SdkCommand::TrustRemove { drain, .. } => *drain,
+        cmd => required_access(cmd) == Access::Write,
+    }
+}
+
+// Where a response goes: untagged commands are answered in request order,
+// `Pipelined` ones as soon as they finish, tagged with their sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    InOrder(u64),
+    Tagged(u64),
+}
+
+enum Dispatch {
+    /// Cheap bookkeeping; runs at once without taking a pipeline slot
+    Light,
+    /// Runs concurrently within the connection's pipeline depth
+    Concurrent,
+    /// Depends on earlier commands (stream uploads); runs one at a time in order
+    Sequential,
+}
+
+fn dispatch_for(cmd: &SdkCommand) -> Dispatch {
+    match cmd {
+        SdkCommand::Stat
+        | SdkCommand::StatHistory { .. }
+        | SdkCommand::PeerStat { .. }
+        | SdkCommand::PeerInspect { .. }
+        | SdkCommand::Metrics
+        | SdkCommand::NodeInfo
+        | SdkCommand::Ping
+        | SdkCommand::ListPeers
+        | SdkCommand::PollConnection { .. }
+        | SdkCommand::CancelConnect { .. }
+        | SdkCommand::StreamList
+        | SdkCommand::MigrationStatus { .. }
+        | SdkCommand::DrainStatus { .. }
+        | SdkCommand::VmConfig { .. }
+        | SdkCommand::AppUsage
+        | SdkCommand::TrustList
+        | SdkCommand::TrustAdd { .. }
+        | SdkCommand::TrustRemove { .. }
+        | SdkCommand::TrustInfo { .. }
+        | SdkCommand::AllowRemoteFlush { .. }
+        | SdkCommand::ConsentList
+        | SdkCommand::ConsentApprove { .. }
+        | SdkCommand::ConsentDeny { .. } => Dispatch::Light,
+        SdkCommand::StreamStart { .. }
+        | SdkCommand::StreamChunk { .. }
+        | SdkCommand::StreamFinish { .. } => Dispatch::Sequential,
+        _ => Dispatch::Concurrent,
+    }
+}
+
+// Generic handler using AsyncRead/Write: frames in, commands to a `Session`,
+// responses written back in the order `write_responses` puts them.
+async fn handle_generic_stream<S>(stream: S, block_manager: Arc<InMemoryBlockManager>, grant: Grant, limits: RpcLimits) -> Result<()> 
+where S: AsyncRead + AsyncWrite + Unpin + Send + 'static
 {
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (resp_tx, resp_rx) = mpsc::unbounded_channel();
+    let writer_task = tokio::spawn(write_responses(writer, resp_rx, block_manager.clone()));
+    let max_frame = limits.max_frame;
+    let mut session = Session::start(block_manager.clone(), grant, limits, resp_tx.clone());
+
+    let mut next_in_order = 0u64;
+    let mut buf = Vec::new();
     loop {
         let mut len_buf = [0u8; 4];
-        if stream.read_exact(&mut len_buf).await.is_err() {
+        if reader.read_exact(&mut len_buf).await.is_err() {
             break; 
         }
         let len = u32::from_be_bytes(len_buf) as usize;
 
-        let mut buf = vec![0u8; len];
-        stream.read_exact(&mut buf).await?;
+        if let Err(e) = read_frame_body_into(&mut reader, len, max_frame, &mut buf).await {
+            // The rest of the stream can't be framed any more: answer and hang up
+            warn!("Closing RPC connection: {}", e);
+            if e.downcast_ref::<FrameTooLarge>().is_some() {
+                let msg = format!("{}; use streaming for large values", e);
+                let _ = resp_tx.send((Slot::InOrder(next_in_order), SdkResponse::error_with(ErrorCode::InvalidArg, msg)));
+            }
+            break;
+        }
+        block_manager.record_rpc_traffic(4 + len as u64, 0);
 
         // SWITCH TO MessagePack
         let cmd: SdkCommand = rmp_serde::from_slice(&buf)?;
-        
-        let response = match cmd {
-            SdkCommand::Store { data, durability } => {
-                     let mode = durability.unwrap_or(memsdk::Durability::Pinned);
-                     let id = rand::random::<u64>();
-                     
-                     let block = crate::blocks::Block {
-                         id,
-                         data,
-                         durability: mode,
-                         last_accessed: std::sync::atomic::AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()).into(),
-                     };
-                     
-                     match block_manager.put_block(block) {
-                         Ok(_) => SdkResponse::Stored { id },
-                         Err(e) => SdkResponse::Error { msg: e.to_string() },
-                     }
-                }
-            SdkCommand::StoreRemote { data, target, durability } => {
-                     let mode = durability.unwrap_or(memsdk::Durability::Pinned);
-                     let id = rand::random::<u64>();
-                     let block = crate::blocks::Block {
-                         id,
-                         data,
-                         durability: mode,
-                         last_accessed: std::sync::atomic::AtomicU64::new(0).into(),
-                     };
-
-                     match block_manager.put_block_remote(block, target).await {
-                         Ok(_) => SdkResponse::Stored { id },
-                         Err(e) => SdkResponse::Error { msg: e.to_string() },
-                     }
-                }       
-            SdkCommand::Load { id } => {
-                match block_manager.get_block_async(id).await {
-                    Ok(Some(block)) => SdkResponse::Loaded { data: block.data },
-                    Ok(None) => SdkResponse::Error { msg: "Block not found".to_string() },
-                    Err(e) => SdkResponse::Error { msg: e.to_string() },
+        // Its name and the client's trace id, for the span it runs in
+        let sent: Option<CommandTrace> = rmp_serde::from_slice(&buf).ok();
+        // A large frame's buffer is not worth holding on to between frames
+        if buf.capacity() > READ_BUFFER_RETAINED {
+            buf = Vec::new();
+        }
+        let (slot, cmd) = slot_for(cmd, &mut next_in_order);
+        let name = sent.as_ref().map_or("unknown", CommandTrace::name);
+        session.handle(slot, cmd, name, sent.as_ref().and_then(|t| t.trace_id.clone()), len).await?;
+    }
+
+    // The writer finishes once every in-flight command has replied
+    drop(resp_tx);
+    drop(session);
+    writer_task.await?
+}
+
+// Untagged commands are answered in the order they came in; a `Pipelined`
+// one is unwrapped and answered under its sequence number.
+fn slot_for(cmd: SdkCommand, next_in_order: &mut u64) -> (Slot, SdkCommand) {
+    match cmd {
+        SdkCommand::Pipelined { seq, command } => (Slot::Tagged(seq), *command),
+        cmd => {
+            *next_in_order += 1;
+            (Slot::InOrder(*next_in_order - 1), cmd)
+        }
+    }
+}
+
+/// One client connection's commands, however they reach the node: checked
+/// against its access, then run on their dispatch lane. Responses go to the
+/// channel it was started with, tagged with the slot they answer.
+///
+/// Commands above the connection's access level are refused with PermissionDenied,
+/// and on a read-only node those changing data with ReadOnly. Dropping the
+/// session ends its sequential lane, which drops the uploads it left open.
+struct Session {
+    block_manager: Arc<InMemoryBlockManager>,
+    grant: Grant,
+    limits: RpcLimits,
+    // `None` until a token-protected connection has authenticated
+    access: Option<Access>,
+    app: Arc<AppTag>,
+    resp_tx: mpsc::UnboundedSender<(Slot, SdkResponse)>,
+    lane_tx: mpsc::Sender<(Slot, SdkCommand, Arc<AppTag>, Traced)>,
+    pipeline: Arc<Semaphore>,
+}
+
+impl Session {
+    fn start(block_manager: Arc<InMemoryBlockManager>, grant: Grant, limits: RpcLimits, resp_tx: mpsc::UnboundedSender<(Slot, SdkResponse)>) -> Self {
+        let (lane_tx, lane_rx) = mpsc::channel(limits.pipeline_depth);
+        tokio::spawn(run_sequential(lane_rx, block_manager.clone(), limits.clone(), resp_tx.clone()));
+        let access = match &grant {
+            Grant::Endpoint(access) => Some(*access),
+            Grant::Tokens(tokens) if tokens.is_empty() => Some(Access::Admin),
+            Grant::Tokens(_) => None,
+        };
+        Self {
+            block_manager,
+            grant,
+            pipeline: Arc::new(Semaphore::new(limits.pipeline_depth)),
+            limits,
+            access,
+            app: Arc::new(AppTag::unknown()),
+            resp_tx,
+            lane_tx,
+        }
+    }
+
+    /// Run `cmd`, answering on `slot`. `name`, `trace_id` and `size` describe
+    /// the request for its span. Fails only if the connection cannot go on.
+    async fn handle(&mut self, slot: Slot, cmd: SdkCommand, name: &str, trace_id: Option<String>, size: usize) -> Result<()> {
+        let block_manager = &self.block_manager;
+        let immediate = match cmd {
+            SdkCommand::Auth { token: given } => {
+                match &self.grant {
+                    Grant::Tokens(tokens) if !tokens.is_empty() => match tokens.access_for(&given) {
+                        Some(granted) => {
+                            self.access = Some(granted);
+                            SdkResponse::Success
+                        }
+                        None => {
+                            error!("RPC client sent an invalid token");
+                            SdkResponse::error_with(ErrorCode::Unauthorized, "unauthorized")
+                        }
+                    },
+                    _ => SdkResponse::Success,
                 }
             }
-            SdkCommand::Free { id } => {
-                if block_manager.vm_free(id).is_ok() {
-                    SdkResponse::Success
-                } else {
-                    match block_manager.evict_block(id) {
-                        Ok(_) => SdkResponse::Success,
-                        Err(e) => SdkResponse::Error { msg: e.to_string() },
+            _ if self.access.is_none() => SdkResponse::error_with(ErrorCode::Unauthorized, "unauthorized"),
+            ref cmd if Some(required_access(cmd)) > self.access => {
+                let msg = format!(
+                    "Permission denied: the command needs {} access and this connection has {}",
+                    required_access(cmd),
+                    self.access.map_or("no".to_string(), |a| a.to_string()),
+                );
+                SdkResponse::error_with(ErrorCode::PermissionDenied, msg)
+            }
+            ref cmd if block_manager.is_read_only() && changes_data(cmd) => {
+                SdkResponse::error_with(ErrorCode::ReadOnly, "Node is read-only: it does not store, change or free data")
+            }
+            SdkCommand::Pipelined { .. } => SdkResponse::error_with(ErrorCode::InvalidArg, "Nested Pipelined command"),
+            SdkCommand::ClientHello { app_name, pid } => {
+                info!("RPC client identified as '{}' (pid {:?})", app_name, pid);
+                self.app = Arc::new(AppTag { name: app_name, pid });
+                SdkResponse::Success
+            }
+            cmd => {
+                let id = trace::request_id(trace_id);
+                let span = trace::rpc_span(&id, name, size);
+                match dispatch_for(&cmd) {
+                    Dispatch::Light => {
+                        let (bm, tx, app) = (block_manager.clone(), self.resp_tx.clone(), self.app.clone());
+                        tokio::spawn(async move {
+                            let _ = tx.send((slot, CURRENT_APP.scope(app, trace::in_request(id, span, run_command(cmd, bm))).await));
+                        });
+                    }
+                    Dispatch::Sequential => {
+                        self.lane_tx.send((slot, cmd, self.app.clone(), (id, span))).await.map_err(|_| anyhow::anyhow!("RPC sequential lane closed"))?;
+                    }
+                    Dispatch::Concurrent => {
+                        let permit = self.pipeline.clone().acquire_owned().await?;
+                        let (bm, tx, limits, app) = (block_manager.clone(), self.resp_tx.clone(), self.limits.clone(), self.app.clone());
+                        tokio::spawn(async move {
+                            let _heavy = limits.heavy_permit(&cmd, &bm).await;
+                            let resp = CURRENT_APP.scope(app, trace::in_request(id, span, run_command(cmd, bm))).await;
+                            drop(permit);
+                            let _ = tx.send((slot, resp));
+                        });
                     }
                 }
+                return Ok(());
             }
-            SdkCommand::ListPeers => {
-                let peers = block_manager.get_peer_ext_list();
-                let sdk_peers = peers.into_iter().map(|p| memsdk::PeerMetadata {
-                    id: p.id,
-                    name: p.name,
-                    addr: p.addr,
-                    total_memory: p.total_memory,
-                    used_memory: p.used_memory,
-                    quota: p.quota,
-                    allowed_quota: p.allowed_quota,
-                }).collect();
-                SdkResponse::PeerList { peers: sdk_peers }
-            }
-            SdkCommand::Connect { addr, quota } => {
+        };
+        let _ = self.resp_tx.send((slot, immediate));
+        Ok(())
+    }
+}
+
+/// A connection for a client in the same process ([`memsdk::MemCloudClient::embedded`]).
+/// Commands go to a [`Session`] as they are, without framing or MessagePack,
+/// so they are handled exactly as a socket client's would be.
+pub(crate) struct DirectConnection {
+    session: Session,
+    responses: mpsc::UnboundedReceiver<(Slot, SdkResponse)>,
+    next_in_order: u64,
+    // Kept alive for as long as the connection is; see `NodeBuilder::embedded`
+    _owner: Option<Box<dyn std::any::Any + Send + Sync>>,
+}
+
+impl DirectConnection {
+    pub(crate) fn new(block_manager: Arc<InMemoryBlockManager>, limits: RpcLimits, owner: Option<Box<dyn std::any::Any + Send + Sync>>) -> Self {
+        let (resp_tx, responses) = mpsc::unbounded_channel();
+        let session = Session::start(block_manager, Grant::Endpoint(Access::Admin), limits, resp_tx);
+        Self { session, responses, next_in_order: 0, _owner: owner }
+    }
+}
+
+impl memsdk::EmbeddedNode for DirectConnection {
+    fn call(&mut self, cmd: SdkCommand, trace_id: String) -> futures::future::BoxFuture<'_, SdkResponse> {
+        Box::pin(async move {
+            let name = command_name(&cmd);
+            let (slot, cmd) = slot_for(cmd, &mut self.next_in_order);
+            if let Err(e) = self.session.handle(slot, cmd, &name, Some(trace_id), 0).await {
+                return error_response(e);
+            }
+            // Answers to calls dropped before they returned (the client timed
+            // them out) may still be queued ahead of this one; skip them
+            loop {
+                match self.responses.recv().await {
+                    Some((got, resp)) if got == slot => match slot {
+                        Slot::Tagged(seq) => return SdkResponse::Pipelined { seq, response: Box::new(resp) },
+                        Slot::InOrder(_) => return resp,
+                    },
+                    Some((stale, _)) => log::debug!("Dropping the response to an abandoned call ({:?})", stale),
+                    None => return SdkResponse::error("Node stopped"),
+                }
+            }
+        })
+    }
+}
+
+// The variant name of `cmd`: its Debug output up to the first field, without
+// formatting the fields (and payload) themselves.
+fn command_name(cmd: &SdkCommand) -> String {
+    struct UpToFields(String);
+    impl std::fmt::Write for UpToFields {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            match s.find([' ', '(', '{']) {
+                Some(end) => {
+                    self.0.push_str(&s[..end]);
+                    Err(std::fmt::Error)
+                }
+                None => {
+                    self.0.push_str(s);
+                    Ok(())
+                }
+            }
+        }
+    }
+    let mut name = UpToFields(String::new());
+    let _ = std::fmt::Write::write_fmt(&mut name, format_args!("{:?}", cmd));
+    name.0
+}
+
+// Request id and span a dispatched command runs under
+type Traced = (Arc<str>, trace::Span);
+
+// Every upload command of a connection comes through here, so this is also
+// where its open uploads are counted, and dropped once the connection closes.
+async fn run_sequential(mut rx: mpsc::Receiver<(Slot, SdkCommand, Arc<AppTag>, Traced)>, block_manager: Arc<InMemoryBlockManager>, limits: RpcLimits, tx: mpsc::UnboundedSender<(Slot, SdkResponse)>) {
+    let mut streams = HashSet::new();
+    while let Some((slot, cmd, app, (id, span))) = rx.recv().await {
+        if matches!(cmd, SdkCommand::StreamStart { .. }) {
+            streams.retain(|s| block_manager.has_stream(*s));
+            if streams.len() >= limits.max_streams {
+                block_manager.record_stream_rejected();
+                let msg = format!("Too many open streams on this connection (limit {}); finish or abort some first", limits.max_streams);
+                if tx.send((slot, SdkResponse::error_with(ErrorCode::QuotaExceeded, msg))).is_err() {
+                    break;
+                }
+                continue;
+            }
+        }
+        let _heavy = limits.heavy_permit(&cmd, &block_manager).await;
+        let resp = CURRENT_APP.scope(app, trace::in_request(id, span, run_command(cmd, block_manager.clone()))).await;
+        if let SdkResponse::StreamStarted { stream_id } = resp {
+            streams.insert(stream_id);
+        }
+        if tx.send((slot, resp)).is_err() {
+            break;
+        }
+    }
+    block_manager.abort_streams(streams).await;
+}
+
+async fn write_responses<W: AsyncWrite + Unpin>(mut writer: W, mut rx: mpsc::UnboundedReceiver<(Slot, SdkResponse)>, block_manager: Arc<InMemoryBlockManager>) -> Result<()> {
+    let mut next = 0u64;
+    let mut held: BTreeMap<u64, SdkResponse> = BTreeMap::new();
+    while let Some((slot, resp)) = rx.recv().await {
+        match slot {
+            Slot::Tagged(seq) => {
+                let written = write_response(&mut writer, &SdkResponse::Pipelined { seq, response: Box::new(resp) }).await?;
+                block_manager.record_rpc_traffic(0, written);
+            }
+            Slot::InOrder(idx) => {
+                held.insert(idx, resp);
+                while let Some(resp) = held.remove(&next) {
+                    let written = write_response(&mut writer, &resp).await?;
+                    block_manager.record_rpc_traffic(0, written);
+                    next += 1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Returns the bytes written, length prefix included
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, response: &SdkResponse) -> Result<u64> {
+    if let Some((head, payload, tail)) = split_payload(response)? {
+        let len = (head.len() + payload.len() + tail.len()) as u32;
+        writer.write_all(&len.to_be_bytes()).await?;
+        writer.write_all(&head).await?;
+        writer.write_all(payload).await?;
+        writer.write_all(&tail).await?;
+        return Ok(4 + len as u64);
+    }
+    // Serialize MessagePack
+    let resp_bytes = rmp_serde::to_vec_named(response)?;
+    let resp_len = resp_bytes.len() as u32;
+    writer.write_all(&resp_len.to_be_bytes()).await?;
+    writer.write_all(&resp_bytes).await?;
+    Ok(4 + resp_len as u64)
+}
+
+// An encoded response as the bytes before its payload, the payload, and the bytes after it
+type SplitResponse<'a> = (Vec<u8>, &'a [u8], Vec<u8>);
+
+/// A response carrying a `Loaded` payload of at least `DIRECT_PAYLOAD_MIN`
+/// bytes, encoded exactly as `to_vec_named` would but split around the payload,
+/// so the payload can be written straight from the block it was read from.
+fn split_payload(response: &SdkResponse) -> Result<Option<SplitResponse<'_>>> {
+    use rmp::encode::{write_bin_len, write_map_len, write_str};
+    let mut head = Vec::new();
+    let loaded = match response {
+        SdkResponse::Pipelined { seq, response } if matches!(**response, SdkResponse::Loaded { .. }) => {
+            write_map_len(&mut head, 3)?;
+            write_str(&mut head, "res")?;
+            write_str(&mut head, "Pipelined")?;
+            write_str(&mut head, "seq")?;
+            rmp_serde::encode::write_named(&mut head, seq)?;
+            write_str(&mut head, "response")?;
+            &**response
+        }
+        response => response,
+    };
+    let SdkResponse::Loaded { data, durability } = loaded else {
+        return Ok(None);
+    };
+    if data.len() < DIRECT_PAYLOAD_MIN {
+        return Ok(None);
+    }
+    write_map_len(&mut head, 3)?;
+    write_str(&mut head, "res")?;
+    write_str(&mut head, "Loaded")?;
+    write_str(&mut head, "data")?;
+    write_bin_len(&mut head, data.len() as u32)?;
+    let mut tail = Vec::new();
+    write_str(&mut tail, "durability")?;
+    rmp_serde::encode::write_named(&mut tail, durability)?;
+    Ok(Some((head, data, tail)))
+}
+
+// `execute_command`, timed under the command's name for `Metrics`
+async fn run_command(cmd: SdkCommand, block_manager: Arc<InMemoryBlockManager>) -> SdkResponse {
+    let name = command_name(&cmd);
+    let started = std::time::Instant::now();
+    let resp = execute_command(cmd, block_manager.clone()).await;
+    block_manager.record_command_time(&name, started.elapsed());
+    resp
+}
+
+async fn execute_command(cmd: SdkCommand, block_manager: Arc<InMemoryBlockManager>) -> SdkResponse {
+    match cmd {
+        SdkCommand::Store { data, durability, content_addressed, tags, priority } => {
+                 let mode = durability.unwrap_or(memsdk::Durability::Pinned);
+                 if let Err(e) = check_tags(&tags) {
+                     return SdkResponse::error(e.to_string());
+                 }
+                 let priority = priority.unwrap_or(memsdk::DEFAULT_PRIORITY);
+                 let res = if content_addressed {
+                     block_manager.put_content_addressed(data, mode, priority)
+                 } else {
+                     block_manager.put_block(Block { priority, ..block_manager.new_block(data, mode) })
+                 };
+
+                 match res {
+                     Ok((id, size)) => {
+                         // A content-addressed block may already be shared; add to its tags
+                         if content_addressed {
+                             block_manager.merge_tags(id, tags);
+                         } else if !tags.is_empty() {
+                             block_manager.tag_block(id, None, tags);
+                         }
+                         SdkResponse::Stored { id, size: Some(size), location: None }
+                     }
+                     Err(e) => error_response(e),
+                 }
+            }
+        SdkCommand::StoreRemote { data, target, durability, fallback_local } => {
+                 let mode = durability.unwrap_or(memsdk::Durability::Pinned);
+                 let block = block_manager.new_block(data, mode);
+
+                 match block_manager.store_remote(block, target, fallback_local).await {
+                     Ok((id, size, location)) => SdkResponse::Stored { id, size: Some(size), location: Some(location) },
+                     Err(e) => SdkResponse::error(e.to_string()),
+                 }
+            }       
+        SdkCommand::Load { id } => {
+            match block_manager.get_block_async(id).await {
+                Ok(Some(block)) => SdkResponse::Loaded { data: block.data, durability: Some(block.durability) },
+                Ok(None) => SdkResponse::error_with(ErrorCode::NotFound, "Block not found"),
+                Err(e) => SdkResponse::error(e.to_string()),
+            }
+        }
+        SdkCommand::SetDurability { id, durability } => match block_manager.set_durability(id, durability) {
+            Ok(()) => SdkResponse::Success,
+            Err(e) => SdkResponse::error(e.to_string()),
+        },
+        SdkCommand::SetPriority { id_or_key, priority } => {
+            match block_manager.selected_id(&id_or_key).and_then(|id| block_manager.set_priority(id, priority)) {
+                Ok(()) => SdkResponse::Success,
+                Err(e) => SdkResponse::error_with(ErrorCode::NotFound, e.to_string()),
+            }
+        }
+        SdkCommand::Free { id } => match block_manager.free(id).await {
+            Ok(Some(bytes)) => freed_response(FreeStats { count: 1, bytes, ..Default::default() }),
+            // Never an error, as before counts were reported
+            Ok(None) => freed_response(FreeStats { missing: 1, ..Default::default() }),
+            Err(e) => SdkResponse::error(e.to_string()),
+        },
+        SdkCommand::FreeMany { ids, pattern } => {
+            let mut stats = block_manager.free_many(&ids).await;
+            if let Some(pattern) = pattern {
+                let matched = block_manager.free_matching(&pattern).await;
+                stats.count += matched.count;
+                stats.bytes += matched.bytes;
+                stats.missing += matched.missing;
+                stats.failed += matched.failed;
+            }
+            freed_response(stats)
+        }
+        SdkCommand::ListPeers => {
+            let peers = block_manager.get_peer_ext_list();
+            let sdk_peers = peers.into_iter().map(|p| memsdk::PeerMetadata {
+                id: p.id,
+                name: p.name,
+                addr: p.addr,
+                public_key: p.public_key,
+                total_memory: p.total_memory,
+                used_memory: p.used_memory,
+                quota: p.quota,
+                allowed_quota: p.allowed_quota,
+                healthy: p.healthy,
+                last_rtt_micros: p.last_rtt_micros,
+                last_seen: p.last_seen,
+            }).collect();
+            SdkResponse::PeerList { peers: sdk_peers }
+        }
+        SdkCommand::Connect { addr, quota, accept_new_key } => {
+            let addrs = match resolve_peer_addrs(&addr).await {
+                Ok(addrs) => addrs,
+                Err(e) => return SdkResponse::error_with(ErrorCode::InvalidArg, e.to_string()),
+            };
+            if let Err(e) = block_manager.peer_manager.check_offer(quota.unwrap_or(0), None) {
+                return SdkResponse::error_with(ErrorCode::QuotaExceeded, e.to_string());
+            }
+            let state = block_manager.peer_manager.start_outgoing(attempt_addr(&addrs), || {
                 let bm_clone = block_manager.clone();
-                let addr_clone = addr.clone();
-                let quota_clone = quota;
-                
                 tokio::spawn(async move {
-                    let _ = bm_clone.connect_peer(&addr_clone, bm_clone.clone(), quota_clone.unwrap_or(0)).await;
-                });
-                
-                SdkResponse::ConnectionStatus { state: "pending".to_string(), msg: None }
-            }
-            SdkCommand::PollConnection { addr } => {
-                 use std::net::SocketAddr;
-                 use crate::peers::HandshakeState;
-                 
-                 if let Ok(socket_addr) = addr.parse::<SocketAddr>() {
-                     if let Some(state) = block_manager.peer_manager.outgoing_handshakes.get(&socket_addr) {
-                         let (status, msg) = match state.value() {
-                             HandshakeState::Connecting => ("pending", None),
-                             HandshakeState::WaitingForConsent => ("waiting_consent", None),
-                             HandshakeState::Authenticated => ("connected", None),
-                             HandshakeState::Failed(e) => ("failed", Some(e.clone())),
-                         };
-                         SdkResponse::ConnectionStatus { state: status.to_string(), msg: msg.map(|s| s) }
-                     } else {
-                         // Not found - could be not started or potential race if processed very fast?
-                         // Assume idle/none
-                         SdkResponse::ConnectionStatus { state: "unknown".to_string(), msg: Some("No active handshake found".to_string()) }
-                     }
-                 } else {
-                     SdkResponse::Error { msg: "Invalid address format".to_string() }
+                    let policy = crate::peers::DialPolicy { accept_new_key, require_known: false };
+                    // Resolved again; the attempt is tracked under the same address whatever order the lookup gives
+                    let _ = bm_clone.connect_peer(&addr, bm_clone.clone(), quota.unwrap_or(0), policy).await;
+                }).abort_handle()
+            });
+            connection_status(Some(state))
+        }
+        SdkCommand::ReconnectTrusted { quota } => match block_manager.reconnect_trusted(quota.unwrap_or(0)).await {
+            Ok(results) => SdkResponse::ReconnectReport { results },
+            Err(e) => SdkResponse::error_with(ErrorCode::QuotaExceeded, e.to_string()),
+        },
+        SdkCommand::PollConnection { addr } => {
+             match resolve_peer_addrs(&addr).await {
+                 Ok(addrs) => connection_status(block_manager.peer_manager.handshake_state(attempt_addr(&addrs))),
+                 Err(e) => SdkResponse::error_with(ErrorCode::InvalidArg, e.to_string()),
+             }
+        }
+        SdkCommand::CancelConnect { addr } => {
+             match resolve_peer_addrs(&addr).await {
+                 Ok(addrs) if block_manager.peer_manager.cancel_outgoing(attempt_addr(&addrs)) => SdkResponse::Success,
+                 Ok(_) => SdkResponse::error_with(ErrorCode::NotFound, format!("No connection attempt to {} in progress", addr)),
+                 Err(e) => SdkResponse::error_with(ErrorCode::InvalidArg, e.to_string()),
+             }
+        }
+        SdkCommand::UpdatePeerQuota { peer_id, quota } => {
+             if quota > block_manager.get_max_memory() {
+                 SdkResponse::error_with(ErrorCode::QuotaExceeded, format!("Quota exceeds node memory limit ({})", block_manager.get_max_memory()))
+             } else {
+                 match block_manager.update_peer_quota(&peer_id, quota).await {
+                     Ok(Some(applied)) => SdkResponse::QuotaUpdated { requested: quota, applied, acknowledged: true },
+                     Ok(None) => SdkResponse::QuotaUpdated { requested: quota, applied: quota, acknowledged: false },
+                     Err(e) => SdkResponse::error(e.to_string()),
                  }
+             }
+        }
+        SdkCommand::AllowRemoteFlush { peer_id, allow } => match block_manager.peer_manager.set_remote_flush(&peer_id, allow) {
+            Ok(id) => {
+                let name = block_manager.peer_manager.peer_name(id).unwrap_or_else(|| id.to_string());
+                let action = if allow { "remote flush allowed" } else { "remote flush revoked" };
+                block_manager.peer_manager.events.record("trust", action, Some(&name), None, "");
+                SdkResponse::Success
             }
-            SdkCommand::UpdatePeerQuota { peer_id, quota } => {
-                 if quota > block_manager.get_max_memory() {
-                     SdkResponse::Error { msg: format!("Quota exceeds node memory limit ({})", block_manager.get_max_memory()) }
+            Err(e) => SdkResponse::error(e.to_string()),
+        },
+        SdkCommand::Disconnect { peer_id, drain: true, force: false } => {
+            match block_manager.start_drain(&peer_id) {
+                Ok(status) => SdkResponse::Drain { status },
+                Err(e) => SdkResponse::error(e.to_string()),
+            }
+        }
+        SdkCommand::Disconnect { peer_id, drain: true, force: true } => {
+            SdkResponse::error_with(ErrorCode::InvalidArg, format!("Either drain or force the disconnect of {}, not both", peer_id))
+        }
+        SdkCommand::Disconnect { peer_id, force, .. } => {
+            match block_manager.disconnect_peer(&peer_id, force).await {
+                 Ok(true) => SdkResponse::Success,
+                 Ok(false) => SdkResponse::error_with(ErrorCode::PeerUnreachable, "Peer not found"),
+                 Err(e) => SdkResponse::error(e.to_string()),
+            }
+        }
+        SdkCommand::DrainStatus { peer_id } => match block_manager.drain_status(&peer_id) {
+            Some(status) => SdkResponse::Drain { status },
+            None => SdkResponse::error_with(ErrorCode::NotFound, format!("No drain of peer {}", peer_id)),
+        },
+        SdkCommand::Set { key, data, target, durability, tags, priority } => {
+                let mode = durability.unwrap_or(memsdk::Durability::Pinned);
+                 let size = Some(data.len() as u64);
+                 if target.is_some() && !tags.is_empty() {
+                     return SdkResponse::error_with(ErrorCode::InvalidArg, "Tags are only kept on this node; give either a target or tags, not both");
+                 }
+                 if let Some(t) = target {
+                     match block_manager.set_remote(&key, data, &t, mode, priority).await {
+                         Ok(id) => SdkResponse::Stored { id, size, location: None },
+                         Err(e) => SdkResponse::error(e.to_string()),
+                     }
                  } else {
-                     match block_manager.update_peer_quota(&peer_id, quota).await {
-                         Ok(_) => SdkResponse::Success,
-                         Err(e) => SdkResponse::Error { msg: e.to_string() },
+                     // Local set
+                     match block_manager.set_tagged(&key, data, mode, priority.unwrap_or(memsdk::DEFAULT_PRIORITY), tags) {
+                         Ok(id) => SdkResponse::Stored { id, size, location: None },
+                         Err(e) => error_response(e),
                      }
                  }
+            }          
+        SdkCommand::Get { key, target } => {
+            let res = if let Some(t) = target {
+                block_manager.get_remote(&key, &t).await
+            } else {
+                block_manager.get_distributed_key(&key).await
+            };
+
+            match res {
+                Ok(Some((data, durability, _))) => SdkResponse::Loaded { data, durability },
+                Ok(None) => SdkResponse::error_with(ErrorCode::NotFound, "Key not found"),
+                // Only a missing key is NotFound; callers like `get_json` rely on it
+                Err(e) => match ErrorCode::classify(&e.to_string()) {
+                    ErrorCode::NotFound => SdkResponse::error_with(ErrorCode::PeerUnreachable, e.to_string()),
+                    _ => SdkResponse::error(e.to_string()),
+                },
+            }
+        }
+        SdkCommand::Incr { key, delta } => match block_manager.incr(&key, delta) {
+            Ok(value) => SdkResponse::Counter { value },
+            Err(e) => error_response(e),
+        },
+        SdkCommand::Append { key, data } => match block_manager.append(&key, &data) {
+            Ok(len) => SdkResponse::Appended { len },
+            Err(e) => error_response(e),
+        },
+        SdkCommand::Rename { target: Some(_), .. } | SdkCommand::Alias { target: Some(_), .. } => {
+            SdkResponse::error_with(ErrorCode::InvalidArg, "Rename and alias are not supported remotely yet; run them on the node holding the key")
+        }
+        SdkCommand::Rename { from, to, overwrite, target: None } => match block_manager.rename_key(&from, &to, overwrite) {
+            Ok(_) => SdkResponse::Success,
+            Err(e) => error_response(e),
+        },
+        SdkCommand::Alias { key, alias, target: None } => match block_manager.alias_key(&key, &alias) {
+            Ok(_) => SdkResponse::Success,
+            Err(e) => error_response(e),
+        },
+        SdkCommand::ListKeys { pattern, include_remote: true, cursor, limit } => {
+            if cursor.is_some() || limit.is_some() {
+                SdkResponse::error_with(ErrorCode::InvalidArg, "Listing remote keys cannot be paged; leave out cursor and limit")
+            } else {
+                SdkResponse::KeyLocations { listing: block_manager.list_keys_with_remote(&pattern).await }
             }
-            SdkCommand::Disconnect { peer_id } => {
-                match block_manager.disconnect_peer(&peer_id).await {
-                     Ok(true) => SdkResponse::Success,
-                     Ok(false) => SdkResponse::Error { msg: "Peer not found".to_string() },
-                     Err(e) => SdkResponse::Error { msg: e.to_string() },
+        }
+        SdkCommand::ListKeys { pattern, cursor, limit, .. } => {
+            match limit {
+                Some(limit) => {
+                    let (items, next_cursor) = block_manager.list_keys_page(&pattern, cursor.as_deref(), limit as usize);
+                    SdkResponse::KeyPage { items, next_cursor }
+                }
+                None => {
+                    let (items, more) = block_manager.list_keys_page(&pattern, None, memsdk::LIST_KEYS_LIMIT as usize);
+                    let truncated = more.is_some();
+                    if truncated {
+                        warn!("Listing of '{}' cut at {} keys; clients should page with a cursor", pattern, memsdk::LIST_KEYS_LIMIT);
+                    }
+                    SdkResponse::List { items, truncated }
                 }
             }
-            SdkCommand::Set { key, data, target, durability } => {
-                    let mode = durability.unwrap_or(memsdk::Durability::Pinned);
-                     if let Some(t) = target {
-                         match block_manager.set_remote(&key, data, &t, mode).await {
-                             Ok(id) => SdkResponse::Stored { id },
-                             Err(e) => SdkResponse::Error { msg: e.to_string() },
-                         }
-                     } else {
-                         // Local set
-                         match block_manager.set(&key, data, mode) {
-                             Ok(id) => SdkResponse::Stored { id },
-                             Err(e) => SdkResponse::Error { msg: e.to_string() },
-                         }
+        }
+        SdkCommand::ListBlocks { limit, cursor } => {
+            let cursor = match cursor.map(|c| c.parse::<BlockId>()).transpose() {
+                Ok(cursor) => cursor,
+                Err(_) => return SdkResponse::error_with(ErrorCode::InvalidArg, "The cursor must be a block ID from a previous page"),
+            };
+            let (items, next) = block_manager.list_blocks_page(cursor, limit.min(memsdk::LIST_KEYS_LIMIT) as usize);
+            SdkResponse::BlockPage { items, next_cursor: next.map(|id| id.to_string()) }
+        }
+        SdkCommand::Migrate { selector, to, from, keep_local } => {
+            match block_manager.start_migration(selector, to, from, keep_local).await {
+                Ok(migration_id) => SdkResponse::MigrationStarted { migration_id },
+                Err(e) => SdkResponse::error(e.to_string()),
+            }
+        }
+        SdkCommand::MigrationStatus { migration_id } => {
+            match block_manager.migration_status(migration_id) {
+                Some(status) => SdkResponse::Migration { status },
+                None => SdkResponse::error_with(ErrorCode::NotFound, format!("Migration {} not found", migration_id)),
+            }
+        }
+        SdkCommand::HotKeys { top_n, window_secs } => {
+            SdkResponse::HotKeys { items: block_manager.hot_blocks(top_n as usize, window_secs) }
+        }
+        SdkCommand::FindByTag { key, value } => SdkResponse::List { items: block_manager.find_by_tag(&key, &value), truncated: false },
+        SdkCommand::BlockInfo { selector } => match block_manager.block_info(&selector) {
+            Ok(info) => SdkResponse::BlockInfo { info },
+            Err(e) => SdkResponse::error(e.to_string()),
+        },
+        SdkCommand::PeerStat { peer_id } => match block_manager.peer_stats(&peer_id) {
+            Ok(stats) => SdkResponse::PeerStat { used: stats.used, quota: stats.quota, blocks_remote: stats.blocks_remote },
+            Err(e) => SdkResponse::error(e.to_string()),
+        },
+        SdkCommand::PeerInspect { id_or_name } => match block_manager.peer_manager.inspect_peer(&id_or_name) {
+            Ok(session) => SdkResponse::PeerSession { session },
+            Err(e) => error_response(e),
+        },
+         SdkCommand::Stat => {
+              let blocks_count = block_manager.blocks.len();
+              let peers_count = block_manager.get_peer_list().len();
+              let memory = block_manager.used_space() as usize;
+          
+              let (vm_regions, vm_pages) = block_manager.vm_manager.get_stats();
+              let (pinned_bytes, cache_bytes) = block_manager.usage_by_durability();
+              let (streams_rejected, streams_reaped) = block_manager.stream_counters();
+
+              SdkResponse::Status { 
+                  blocks: blocks_count, 
+                  peers: peers_count, 
+                  memory_usage: memory,
+                  vm_regions,
+                  vm_pages_mapped: vm_pages,
+                  vm_memory_in_use: vm_pages * 4096,
+                  total_capacity: block_manager.get_max_memory(),
+                  free_space: block_manager.free_space(),
+                  pinned_bytes,
+                  cache_bytes,
+                  rss_bytes: block_manager.memory_guard.rss(),
+                  pressure: block_manager.memory_guard.level(),
+                  read_only: block_manager.is_read_only(),
+                  streams_rejected,
+                  streams_reaped,
+              }
+         }
+        SdkCommand::StatHistory { window_secs } => SdkResponse::StatHistory { samples: block_manager.stat_history(window_secs) },
+        SdkCommand::Metrics => SdkResponse::Metrics { commands: block_manager.command_metrics() },
+        SdkCommand::ClusterStat => SdkResponse::ClusterStat { stats: block_manager.cluster_stats().await },
+        SdkCommand::Ping => {
+            let (healthy, details) = block_manager.health_report().await;
+            SdkResponse::Pong {
+                uptime_secs: block_manager.health.uptime_secs(),
+                healthy,
+                details,
+                port: block_manager.peer_manager.listen_port(),
+                name: Some(block_manager.peer_manager.get_self_name()),
+                pid: Some(std::process::id()),
+            }
+        }
+        // Streaming Handlers
+        SdkCommand::StreamStart { size_hint, target } => {
+            match block_manager.start_stream(size_hint, target).await {
+                Ok(stream_id) => SdkResponse::StreamStarted { stream_id },
+                Err(e) => error_response(e),
+            }
+        }
+        SdkCommand::StreamChunk { stream_id, chunk_seq: _, data } => {
+            // chunk_seq can be used for ordering if using UDP, but over TCP/Unix it's sequential.
+            // We ignore it for now or could assert it matches expected index.
+            match block_manager.append_stream(stream_id, data).await {
+                Ok(_) => SdkResponse::Success,
+                Err(e) => error_response(e),
+            }
+        }
+        SdkCommand::StreamList => SdkResponse::StreamList { items: block_manager.list_streams() },
+        SdkCommand::StreamAbort { stream_id } => match block_manager.abort_stream(stream_id).await {
+            Ok(()) => SdkResponse::Success,
+            Err(e) => SdkResponse::error(e.to_string()),
+        },
+        SdkCommand::StreamFinish { stream_id, target, durability, priority } => {
+            let mode = durability.unwrap_or(memsdk::Durability::Pinned);
+            match block_manager.finish_stream(stream_id, target, mode, priority.unwrap_or(memsdk::DEFAULT_PRIORITY)).await {
+                Ok((id, size)) => SdkResponse::Stored { id, size: Some(size), location: None },
+                Err(e) => error_response(e),
+            }
+        }
+        SdkCommand::Flush { target, pattern, durability } => {
+            let filter = match (&pattern, durability) {
+                (None, None) => "everything".to_string(),
+                (p, d) => format!("pattern {:?}, durability {:?}", p, d),
+            };
+            let events = &block_manager.peer_manager.events;
+            if let Some(t) = target {
+                match block_manager.flush_remote(t.clone(), pattern, durability).await {
+                     Ok(stats) => {
+                         events.record("flush", "flushed peer", Some(&t), Some(stats.bytes_freed), format!("{} blocks, {}", stats.blocks_removed, filter));
+                         SdkResponse::Flushed { blocks_removed: stats.blocks_removed, bytes_freed: stats.bytes_freed }
                      }
-                }          
-            SdkCommand::Get { key, target } => {
-                let res = if let Some(t) = target {
-                    block_manager.get_remote(&key, &t).await
-                } else {
-                    block_manager.get_distributed_key(&key).await
-                };
-
-                match res {
-                    Ok(Some(data)) => SdkResponse::Loaded { data },
-                    Ok(None) => SdkResponse::Error { msg: "Key not found".to_string() },
-                    Err(e) => SdkResponse::Error { msg: e.to_string() },
+                     Err(e) => SdkResponse::error(e.to_string()),
                 }
+            } else {
+                let stats = block_manager.flush_filtered(pattern.as_deref(), durability);
+                events.record("flush", "flushed", None, Some(stats.bytes_freed), format!("{} blocks, {}", stats.blocks_removed, filter));
+                SdkResponse::Flushed { blocks_removed: stats.blocks_removed, bytes_freed: stats.bytes_freed }
             }
-            SdkCommand::ListKeys { pattern } => {
-                let keys = block_manager.list_keys(&pattern);
-                SdkResponse::List { items: keys }
-            }
-             SdkCommand::Stat => {
-                  let blocks_count = block_manager.blocks.len();
-                  let peers_count = block_manager.get_peer_list().len();
-                  let memory = block_manager.used_space() as usize;
-                  
-                  let (vm_regions, vm_pages) = block_manager.vm_manager.get_stats();
- 
-                  SdkResponse::Status { 
-                      blocks: blocks_count, 
-                      peers: peers_count, 
-                      memory_usage: memory,
-                      vm_regions,
-                      vm_pages_mapped: vm_pages,
-                      vm_memory_in_use: vm_pages * 4096,
-                  }
-             }
-            // Streaming Handlers
-            SdkCommand::StreamStart { size_hint } => {
-                let stream_id = block_manager.start_stream(size_hint);
-                SdkResponse::StreamStarted { stream_id }
-            }
-            SdkCommand::StreamChunk { stream_id, chunk_seq: _, data } => {
-                // chunk_seq can be used for ordering if using UDP, but over TCP/Unix it's sequential.
-                // We ignore it for now or could assert it matches expected index.
-                match block_manager.append_stream(stream_id, data) {
-                    Ok(_) => SdkResponse::Success,
-                    Err(e) => SdkResponse::Error { msg: e.to_string() },
+        }
+        SdkCommand::FlushCluster { include_local, pattern, durability } => {
+            let filter = match (&pattern, durability) {
+                (None, None) => "everything".to_string(),
+                (p, d) => format!("pattern {:?}, durability {:?}", p, d),
+            };
+            let results = block_manager.flush_cluster(include_local, pattern, durability).await;
+            let events = &block_manager.peer_manager.events;
+            for outcome in &results {
+                let action = if outcome.local { "flushed" } else { "flushed peer" };
+                let subject = Some(outcome.node.as_str()).filter(|_| !outcome.local);
+                match (&outcome.stats, &outcome.error) {
+                    (Some(stats), _) => events.record("flush", action, subject, Some(stats.bytes_freed), format!("{} blocks, {}", stats.blocks_removed, filter)),
+                    (None, error) => events.record("flush", "peer flush failed", subject, None, error.clone().unwrap_or_default()),
                 }
             }
-            SdkCommand::StreamFinish { stream_id, target, durability } => {
-                     let mode = durability.unwrap_or(memsdk::Durability::Pinned);
-                     match block_manager.finalize_stream(stream_id) {
-                         Ok(data) => {
-                             if let Some(t) = target {
-                                 let id = rand::random::<u64>();
-                                 let block = crate::blocks::Block { id, data, durability: mode, last_accessed: std::sync::atomic::AtomicU64::new(0).into() };
-                                 match block_manager.put_block_remote(block, Some(t)).await {
-                                     Ok(_) => SdkResponse::Stored { id },
-                                     Err(e) => SdkResponse::Error { msg: e.to_string() },
-                                 }
-                             } else {
-                                 let id = rand::random::<u64>();
-                                 let block = crate::blocks::Block { 
-                                     id, 
-                                     data, 
-                                     durability: mode,
-                                     last_accessed: std::sync::atomic::AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()).into()
-                                 };
-                                 match block_manager.put_block(block) {
-                                     Ok(_) => SdkResponse::Stored { id },
-                                     Err(e) => SdkResponse::Error { msg: e.to_string() },
-                                 }
-                             }
-                         }
-                         Err(e) => SdkResponse::Error { msg: e.to_string() },
-                     }
-                }       
-            SdkCommand::Flush { target } => {
-                if let Some(t) = target {
-                    match block_manager.flush_remote(t).await {
-                         Ok(_) => SdkResponse::FlushSuccess,
-                         Err(e) => SdkResponse::Error { msg: e.to_string() },
+            SdkResponse::FlushReport { results }
+        }
+        SdkCommand::NodeInfo => SdkResponse::NodeInfo {
+            info: memsdk::NodeInfo {
+                id: block_manager.peer_manager.get_self_id().to_string(),
+                name: block_manager.peer_manager.get_self_name(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+        },
+        SdkCommand::Events { since_seq, kinds, limit, wait_secs } => {
+            if let Some(unknown) = kinds.iter().flatten().find(|k| !memsdk::EVENT_KINDS.contains(&k.as_str())) {
+                return SdkResponse::error_with(ErrorCode::InvalidArg, format!("Unknown event kind '{}'; the kinds are {}", unknown, memsdk::EVENT_KINDS.join(", ")));
+            }
+            let journal = &block_manager.peer_manager.events;
+            let limit = limit.min(crate::events::EVENTS_KEPT as u64) as usize;
+            let (events, next_seq) = match (since_seq, wait_secs) {
+                (Some(since), Some(wait)) => {
+                    let wait = std::time::Duration::from_secs(wait.min(memsdk::EVENTS_MAX_WAIT_SECS));
+                    journal.wait_query(since, kinds.as_deref(), limit, wait).await
+                }
+                _ => journal.query(since_seq, kinds.as_deref(), limit),
+            };
+            SdkResponse::Events { events, next_seq }
+        }
+        // Trust & Consent
+        SdkCommand::TrustList => {
+            let items = block_manager.peer_manager.trusted_store.list_trusted();
+            SdkResponse::TrustedList { items: items.into_iter().map(|d| rpc_device(d).0).collect() }
+        }
+        SdkCommand::TrustInfo { key_or_name } => {
+            match block_manager.peer_manager.trusted_store.find_trusted(&key_or_name) {
+                Ok(Some(device)) => {
+                    let (device, history) = rpc_device(device);
+                    SdkResponse::TrustInfo { info: memsdk::TrustInfo { device, history } }
+                }
+                Ok(None) => SdkResponse::error_with(ErrorCode::NotFound, format!("No trusted device '{}'", key_or_name)),
+                Err(e) => SdkResponse::error_with(ErrorCode::InvalidArg, e.to_string()),
+            }
+        }
+        SdkCommand::TrustAdd { public_key, name, note } => {
+            if hex::decode(&public_key).map_or(true, |k| k.len() != 32) {
+                SdkResponse::error_with(ErrorCode::InvalidArg, "The public key must be 64 hex characters, as shown by `memcli peers --verbose`")
+            } else if name.is_empty() {
+                SdkResponse::error_with(ErrorCode::InvalidArg, "A trusted device needs a name")
+            } else {
+                let detail = format!("key {}", crate::peers::key_fingerprint(&public_key.to_lowercase()));
+                match block_manager.peer_manager.trusted_store.add_trusted(public_key.to_lowercase(), name.clone(), note) {
+                    Ok(()) => {
+                        block_manager.peer_manager.events.record("trust", "added", Some(&name), None, detail);
+                        SdkResponse::Success
                     }
-                } else {
-                    block_manager.flush();
-                    SdkResponse::FlushSuccess
+                    Err(e) => SdkResponse::error(e.to_string()),
                 }
             }
-            // Trust & Consent
-            SdkCommand::TrustList => {
-                let items = block_manager.peer_manager.trusted_store.list_trusted();
-                // Map local type to RPC type (duplicated def)
-                let rpc_items = items.into_iter().map(|d| TrustedDevice {
-                    public_key: d.public_key,
-                    name: d.name,
-                    first_seen: d.first_seen,
-                    last_approved: d.last_approved,
-                }).collect();
-                SdkResponse::TrustedList { items: rpc_items }
-            }
-            SdkCommand::TrustRemove { key_or_name } => {
-                 match block_manager.peer_manager.trusted_store.remove_trusted(&key_or_name) {
-                     Ok(removed) => {
-                         if removed.is_empty() {
-                             SdkResponse::Error { msg: "No matching trusted device found".to_string() }
-                         } else {
-                             for device in removed {
-                                 // Disconnect if connected
-                                 if let Some(peer_id) = block_manager.peer_manager.get_peer_id_by_name(&device.name) {
+        }
+        SdkCommand::TrustRemove { key_or_name, note, drain } => {
+             let store = &block_manager.peer_manager.trusted_store;
+             // A pinned key counts as trust for outgoing connections, so it goes too
+             match store.remove_trusted(&key_or_name).and_then(|removed| Ok((removed, store.forget_pins(&key_or_name)?))) {
+                 Ok((removed, unpinned)) => {
+                     if removed.is_empty() && unpinned == 0 {
+                         SdkResponse::error_with(ErrorCode::NotFound, "No matching trusted device found")
+                     } else {
+                         for device in removed {
+                             match &note {
+                                 Some(note) => info!("Removed trusted device {} ({}): {}", device.name, device.public_key, note),
+                                 None => info!("Removed trusted device {} ({})", device.name, device.public_key),
+                             }
+                             let detail = note.clone().unwrap_or_else(|| format!("key {}", crate::peers::key_fingerprint(&device.public_key)));
+                             block_manager.peer_manager.events.record("trust", "removed", Some(&device.name), None, detail);
+                             // Disconnect if connected
+                             if let Some(peer_id) = block_manager.peer_manager.get_peer_id_by_name(&device.name) {
+                                 if drain {
+                                     if let Err(e) = block_manager.start_drain(&peer_id.to_string()) {
+                                         warn!("Could not drain removed peer {} ({}): {}", device.name, peer_id, e);
+                                     }
+                                 } else {
                                      info!("Disconnecting removed peer {} ({})", device.name, peer_id);
+                                     block_manager.abort_drain(peer_id);
                                      block_manager.peer_manager.disconnect_peer(peer_id).await;
                                  }
                              }
-                             SdkResponse::Success
                          }
+                         SdkResponse::Success
                      }
-                     Err(e) => SdkResponse::Error { msg: e.to_string() },
                  }
+                 Err(e) => SdkResponse::error(e.to_string()),
+             }
+        }
+        SdkCommand::ConsentList => {
+            let items = block_manager.peer_manager.consent_manager.get_pending_list();
+            let rpc_items = items.into_iter().map(|c| PendingConsent {
+                session_id: c.session_id,
+                peer_pubkey: c.peer_pubkey,
+                peer_name: c.peer_name,
+                quota: c.quota,
+                allowed_quota: c.allowed_quota,
+                created_at: c.created_at,
+            }).collect();
+            SdkResponse::ConsentList { items: rpc_items }
+        }
+        SdkCommand::ConsentApprove { session_id, trust_always } => {
+             use crate::peers::consent::ConsentDecision;
+             let decision = if trust_always {
+                 ConsentDecision::ApprovedAndTrusted
+             } else {
+                 ConsentDecision::ApprovedOnce
+             };
+         
+             match block_manager.peer_manager.consent_manager.resolve(&session_id, decision) {
+                 Ok(_) => SdkResponse::Success,
+                 Err(e) => SdkResponse::error_with(ErrorCode::NotFound, e.to_string()),
+             }
+        }
+        SdkCommand::ConsentDeny { session_id } => {
+             use crate::peers::consent::ConsentDecision;
+             match block_manager.peer_manager.consent_manager.resolve(&session_id, ConsentDecision::Denied) {
+                 Ok(_) => SdkResponse::Success,
+                 Err(e) => SdkResponse::error_with(ErrorCode::NotFound, e.to_string()),
+             }
+        }
+        SdkCommand::VmAlloc { size } => {
+            let region_id = block_manager.vm_alloc(size);
+            SdkResponse::VmCreated { region_id }
+        }
+        SdkCommand::VmFetch { region_id, page_index } => {
+            match block_manager.vm_fetch(region_id, page_index).await {
+                Ok(data) => SdkResponse::PageData { data },
+                Err(e) => SdkResponse::error(e.to_string()),
             }
-            SdkCommand::ConsentList => {
-                let items = block_manager.peer_manager.consent_manager.get_pending_list();
-                let rpc_items = items.into_iter().map(|c| PendingConsent {
-                    session_id: c.session_id,
-                    peer_pubkey: c.peer_pubkey,
-                    peer_name: c.peer_name,
-                    quota: c.quota,
-                    created_at: c.created_at,
-                }).collect();
-                SdkResponse::ConsentList { items: rpc_items }
-            }
-            SdkCommand::ConsentApprove { session_id, trust_always } => {
-                 use crate::peers::consent::ConsentDecision;
-                 let decision = if trust_always {
-                     ConsentDecision::ApprovedAndTrusted
-                 } else {
-                     ConsentDecision::ApprovedOnce
-                 };
-                 
-                 match block_manager.peer_manager.consent_manager.resolve(&session_id, decision) {
-                     Ok(_) => SdkResponse::Success,
-                     Err(e) => SdkResponse::Error { msg: e.to_string() },
-                 }
-            }
-            SdkCommand::ConsentDeny { session_id } => {
-                 use crate::peers::consent::ConsentDecision;
-                 match block_manager.peer_manager.consent_manager.resolve(&session_id, ConsentDecision::Denied) {
-                     Ok(_) => SdkResponse::Success,
-                     Err(e) => SdkResponse::Error { msg: e.to_string() },
-                 }
-            }
-            SdkCommand::VmAlloc { size } => {
-                let region_id = block_manager.vm_alloc(size);
-                SdkResponse::VmCreated { region_id }
-            }
-            SdkCommand::VmFetch { region_id, page_index } => {
-                match block_manager.vm_fetch(region_id, page_index).await {
-                    Ok(data) => SdkResponse::PageData { data },
-                    Err(e) => SdkResponse::Error { msg: e.to_string() },
-                }
+        }
+        SdkCommand::VmStore { region_id, page_index, data } => {
+            match block_manager.vm_store(region_id, page_index, data).await {
+                Ok(_) => SdkResponse::Success,
+                Err(e) => error_response(e),
             }
-            SdkCommand::VmStore { region_id, page_index, data } => {
-                match block_manager.vm_store(region_id, page_index, data).await {
-                    Ok(_) => SdkResponse::Success,
-                    Err(e) => SdkResponse::Error { msg: e.to_string() },
-                }
+        }
+        SdkCommand::VmConfig { threshold_mb } => {
+            let vm = &block_manager.vm_manager;
+            match threshold_mb.map(|mb| vm.set_threshold_mb(mb)).transpose() {
+                Ok(_) => SdkResponse::VmConfig { threshold_mb: vm.threshold_mb() },
+                Err(e) => SdkResponse::error(e.to_string()),
             }
-        };
+        }
+        SdkCommand::AppUsage => SdkResponse::AppUsage { items: block_manager.app_usage() },
+        SdkCommand::ReloadConfig => match block_manager.reload_config() {
+            Ok((applied, restart_required)) => SdkResponse::ConfigReloaded { applied, restart_required },
+            Err(e) => SdkResponse::error_with(ErrorCode::InvalidArg, e.to_string()),
+        },
+        SdkCommand::Shutdown => {
+            info!("Shutdown requested over RPC");
+            block_manager.peer_manager.events.record("shutdown", "requested", None, None, "by an RPC client");
+            block_manager.request_shutdown();
+            SdkResponse::Success
+        }
+        // Handled by the connection loop before dispatch
+        SdkCommand::Auth { .. } | SdkCommand::ClientHello { .. } | SdkCommand::Pipelined { .. } => SdkResponse::error_with(ErrorCode::InvalidArg, "Unexpected command"),
+    }
+}
+
+fn connection_status(state: Option<crate::peers::HandshakeState>) -> SdkResponse {
+    use crate::peers::HandshakeState;
+    let (state, msg) = match state {
+        Some(HandshakeState::Connecting) => ("pending", None),
+        Some(HandshakeState::WaitingForConsent) => ("waiting_consent", None),
+        Some(HandshakeState::Authenticated) => ("connected", None),
+        Some(HandshakeState::Failed(e)) => ("failed", Some(e)),
+        Some(HandshakeState::IdentityChanged(e)) => ("identity_changed", Some(e)),
+        None => ("unknown", Some("No active handshake found".to_string())),
+    };
+    SdkResponse::ConnectionStatus { state: state.to_string(), msg }
+}
 
-        // Serialize MessagePack
-        let resp_bytes = rmp_serde::to_vec_named(&response)?;
-        let resp_len = resp_bytes.len() as u32;
-        stream.write_all(&resp_len.to_be_bytes()).await?;
-        stream.write_all(&resp_bytes).await?;
+// The store's record of a device, split into the RPC type and its connection history
+fn rpc_device(d: crate::peers::trusted::TrustedDevice) -> (TrustedDevice, Vec<TrustedConnection>) {
+    let history = d.history.into_iter().map(|c| TrustedConnection { at: c.at, addr: c.addr }).collect();
+    let device = TrustedDevice {
+        public_key: d.public_key,
+        name: d.name,
+        first_seen: d.first_seen,
+        last_approved: d.last_approved,
+        last_connected: d.last_connected,
+        total_sessions: d.total_sessions,
+        note: d.note,
+        allow_remote_flush: d.allow_remote_flush,
+        last_addr: d.last_addr,
+    };
+    (device, history)
+}
+
+// Refusals under memory pressure get their own response so clients can back off and retry
+fn freed_response(stats: FreeStats) -> SdkResponse {
+    SdkResponse::Freed { count: stats.count, bytes: stats.bytes, missing: stats.missing, failed: stats.failed }
+}
+
+fn error_response(e: anyhow::Error) -> SdkResponse {
+    if e.downcast_ref::<MemoryPressure>().is_some() {
+        SdkResponse::Overloaded { msg: e.to_string() }
+    } else if let Some(rejected) = e.downcast_ref::<StreamRejected>() {
+        // Classified by the peer's reason, so a full peer reads as QuotaExceeded
+        SdkResponse::error_with(ErrorCode::classify(&rejected.reason), e.to_string())
+    } else {
+        SdkResponse::error(e.to_string())
     }
-    Ok(())
 }
 
 #[cfg(unix)]
-async fn handle_client_unix(stream: UnixStream, bm: Arc<InMemoryBlockManager>) -> Result<()> {
-    handle_generic_stream(stream, bm).await
+pub(crate) async fn handle_client_unix(stream: UnixStream, bm: Arc<InMemoryBlockManager>, access: Access, limits: RpcLimits) -> Result<()> {
+    handle_generic_stream(stream, bm, Grant::Endpoint(access), limits).await
+}
+
+async fn handle_client_tcp(stream: tokio::net::TcpStream, bm: Arc<InMemoryBlockManager>, tokens: Arc<RpcTokens>, limits: RpcLimits) -> Result<()> {
+    handle_generic_stream(stream, bm, Grant::Tokens(tokens), limits).await
 }
 
-async fn handle_client_tcp(stream: tokio::net::TcpStream, bm: Arc<InMemoryBlockManager>) -> Result<()> {
-    handle_generic_stream(stream, bm).await
+// Compare without returning early so the check does not leak how much of the token matched.
+fn token_eq(expected: &str, given: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), given.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{wait_until, TestNode};
+    use memsdk::Durability;
+
+    async fn round_trip<S: AsyncReadExt + AsyncWriteExt + Unpin>(stream: &mut S, cmd: &SdkCommand) -> SdkResponse {
+        let bytes = rmp_serde::to_vec_named(cmd).unwrap();
+        stream.write_all(&(bytes.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        read_response(stream).await
+    }
+
+    fn is_unauthorized(resp: &SdkResponse) -> bool {
+        matches!(resp, SdkResponse::Error { msg, .. } if msg == "unauthorized")
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_socket_is_only_taken_over_when_stale() {
+        use std::os::unix::fs::PermissionsExt;
+        let node = TestNode::spawn("owner", 1024 * 1024).await;
+        let path = std::env::temp_dir().join(format!("memcloud-claim-{}.sock", uuid::Uuid::new_v4())).to_string_lossy().into_owned();
+        let first = RpcServer::new(&path, None, None, RpcTokens::default(), RpcLimits::default(), node.block_manager.clone());
+        let listeners = first.bind().await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let server = tokio::spawn(async move { first.serve(listeners).await });
+
+        let second = RpcServer::new(&path, None, None, RpcTokens::default(), RpcLimits::default(), node.block_manager.clone());
+        let err = second.bind().await.err().expect("second node must not take the socket").to_string();
+        assert!(err.contains("already serving") && err.contains("node name owner"), "{}", err);
+
+        // Once nothing answers, the file is a leftover and gets replaced
+        server.abort();
+        let _ = server.await;
+        assert!(std::path::Path::new(&path).exists());
+        second.bind().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Anything that is not a socket is left alone
+        std::fs::write(&path, b"not a socket").unwrap();
+        let err = second.bind().await.err().expect("a regular file must not be replaced").to_string();
+        assert!(err.contains("not a socket"), "{}", err);
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rpc_token_required_before_commands() {
+        let node = TestNode::spawn("auth", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Tokens(Arc::new(RpcTokens { admin: Some("s3cret".to_string()), ..Default::default() })), RpcLimits::default()));
+
+        let set = SdkCommand::Set { key: "k".to_string(), data: vec![1], target: None, durability: Some(Durability::Pinned), tags: Default::default(), priority: None };
+        assert!(is_unauthorized(&round_trip(&mut client, &set).await));
+        let flush = SdkCommand::Flush { target: None, pattern: None, durability: None };
+        assert!(is_unauthorized(&round_trip(&mut client, &flush).await));
+        assert!(is_unauthorized(&round_trip(&mut client, &SdkCommand::Auth { token: "wrong".to_string() }).await));
+        assert!(node.block_manager.list_keys("*").is_empty());
+
+        let auth = SdkCommand::Auth { token: "s3cret".to_string() };
+        assert!(matches!(round_trip(&mut client, &auth).await, SdkResponse::Success));
+        assert!(matches!(round_trip(&mut client, &set).await, SdkResponse::Stored { .. }));
+        assert_eq!(node.block_manager.list_keys("*"), vec!["k".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_each_access_level_is_refused_commands_above_it() {
+        let node = TestNode::spawn("access", 1024 * 1024).await;
+        let connect = |grant: Grant| {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), grant, RpcLimits::default()));
+            client
+        };
+        let denied = |resp: SdkResponse| matches!(resp, SdkResponse::Error { code: Some(ErrorCode::PermissionDenied), .. });
+        let set = SdkCommand::Set { key: "k".to_string(), data: vec![1], target: None, durability: Some(Durability::Pinned), tags: Default::default(), priority: None };
+        let flush = SdkCommand::Flush { target: None, pattern: None, durability: None };
+        let trust_remove = SdkCommand::TrustRemove { key_or_name: "nobody".to_string(), note: None, drain: false };
+
+        let mut read = connect(Grant::Endpoint(Access::Read));
+        assert!(denied(round_trip(&mut read, &set).await));
+        assert!(denied(round_trip(&mut read, &flush).await));
+        assert!(denied(round_trip(&mut read, &SdkCommand::VmConfig { threshold_mb: Some(32) }).await));
+        assert!(matches!(round_trip(&mut read, &SdkCommand::Stat).await, SdkResponse::Status { .. }));
+        assert!(matches!(round_trip(&mut read, &SdkCommand::ListKeys { pattern: "*".to_string(), cursor: None, limit: None, include_remote: false }).await, SdkResponse::List { .. }));
+        assert!(node.block_manager.list_keys("*").is_empty());
+
+        let mut write = connect(Grant::Endpoint(Access::Write));
+        assert!(matches!(round_trip(&mut write, &set).await, SdkResponse::Stored { .. }));
+        assert!(denied(round_trip(&mut write, &flush).await));
+        assert!(denied(round_trip(&mut write, &trust_remove).await));
+        assert_eq!(node.block_manager.list_keys("*"), vec!["k".to_string()]);
+
+        // On TCP the token picks the level
+        let tokens = Arc::new(RpcTokens {
+            admin: Some("admin".to_string()),
+            write: Some("write".to_string()),
+            read: Some("read".to_string()),
+        });
+        let mut by_token = connect(Grant::Tokens(tokens.clone()));
+        assert!(matches!(round_trip(&mut by_token, &SdkCommand::Auth { token: "read".to_string() }).await, SdkResponse::Success));
+        assert!(denied(round_trip(&mut by_token, &set).await));
+        let mut admin = connect(Grant::Tokens(tokens));
+        assert!(matches!(round_trip(&mut admin, &SdkCommand::Auth { token: "admin".to_string() }).await, SdkResponse::Success));
+        assert!(matches!(round_trip(&mut admin, &flush).await, SdkResponse::Flushed { .. }));
+        assert!(node.block_manager.list_keys("*").is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sockets_get_the_requested_mode_and_group() {
+        use std::os::unix::fs::MetadataExt;
+        let node = TestNode::spawn("perms", 1024 * 1024).await;
+        let dir = std::env::temp_dir().join(format!("memcloud-perms-{}", uuid::Uuid::new_v4()));
+        let (main, ro) = (dir.join("main.sock").to_string_lossy().into_owned(), dir.join("ro.sock").to_string_lossy().into_owned());
+        let gid = nix::unistd::getegid().as_raw();
+        let server = RpcServer::new(&main, Some(ro.clone()), None, RpcTokens::default(), RpcLimits::default(), node.block_manager.clone())
+            .with_socket_perms(SocketPerms { mode: 0o640, group: Some(gid.to_string()) });
+        let _listeners = server.bind().await.unwrap();
+        let (main_meta, ro_meta) = (std::fs::metadata(&main).unwrap(), std::fs::metadata(&ro).unwrap());
+        assert_eq!((main_meta.mode() & 0o777, main_meta.gid()), (0o640, gid));
+        assert_eq!((ro_meta.mode() & 0o777, ro_meta.gid()), (0o660, gid));
+
+        let other = dir.join("other.sock").to_string_lossy().into_owned();
+        let server = RpcServer::new(&other, None, None, RpcTokens::default(), RpcLimits::default(), node.block_manager.clone())
+            .with_socket_perms(SocketPerms { mode: 0o660, group: Some("no-such-memcloud-group".to_string()) });
+        let err = server.bind().await.err().expect("unknown group").to_string();
+        assert!(err.contains("no group named"), "{}", err);
+        assert!(!std::path::Path::new(&other).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_readonly_socket_denies_writes_to_sdk_clients() {
+        let node = TestNode::spawn("readonly", 1024 * 1024).await;
+        let dir = std::env::temp_dir().join(format!("memcloud-ro-{}", uuid::Uuid::new_v4()));
+        let (main, ro) = (dir.join("main.sock").to_string_lossy().into_owned(), dir.join("ro.sock").to_string_lossy().into_owned());
+        let server = RpcServer::new(&main, Some(ro.clone()), None, RpcTokens::default(), RpcLimits::default(), node.block_manager.clone());
+        let listeners = server.bind().await.unwrap();
+        tokio::spawn(async move { server.serve(listeners).await });
+
+        let mut reader = memsdk::MemCloudClient::connect_with_path(&ro).await.unwrap();
+        let err = reader.set("k", b"v", None, Durability::Pinned).await.unwrap_err();
+        assert!(matches!(err, memsdk::MemCloudError::PermissionDenied(_)), "{:?}", err);
+        assert!(reader.list_keys("*").await.unwrap().is_empty());
+
+        let mut writer = memsdk::MemCloudClient::connect_with_path(&main).await.unwrap();
+        writer.set("k", b"v", None, Durability::Pinned).await.unwrap();
+        assert_eq!(reader.get("k", None).await.unwrap(), b"v");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_only_node_serves_reads_and_refuses_writes() {
+        let node = TestNode::spawn("ro-node", 1024 * 1024).await;
+        let peer = TestNode::spawn("ro-peer", 1024 * 1024).await;
+        peer.connect_to(&node, 0).await;
+        node.block_manager.set("k", vec![1, 2, 3], Durability::Pinned).unwrap();
+        node.block_manager.set_read_only(true);
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+        let read_only = |resp: SdkResponse| matches!(resp, SdkResponse::Error { code: Some(ErrorCode::ReadOnly), .. });
+        let get = SdkCommand::Get { key: "k".to_string(), target: None };
+        assert!(matches!(round_trip(&mut client, &get).await, SdkResponse::Loaded { .. }));
+        assert!(matches!(round_trip(&mut client, &SdkCommand::Stat).await, SdkResponse::Status { read_only: true, .. }));
+        let writes = [
+            SdkCommand::Set { key: "k".to_string(), data: vec![9], target: None, durability: None, tags: Default::default(), priority: None },
+            SdkCommand::Store { data: vec![9], durability: None, content_addressed: false, tags: Default::default(), priority: None },
+            SdkCommand::Free { id: 1 },
+            SdkCommand::Flush { target: None, pattern: None, durability: None },
+            SdkCommand::StreamStart { size_hint: None, target: None },
+        ];
+        for cmd in &writes {
+            assert!(read_only(round_trip(&mut client, cmd).await), "{:?}", cmd);
+        }
+        assert_eq!(node.block_manager.list_keys("*"), vec!["k".to_string()]);
+
+        // Peers cannot store on it either
+        let block = peer.block_manager.new_block(vec![7; 10], Durability::Pinned);
+        let err = peer.block_manager.put_block_on_peer(node.id, block).await.unwrap_err();
+        assert!(err.to_string().contains("read-only"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_streams_are_limited_per_connection_and_dropped_with_it() {
+        let node = TestNode::spawn("conn-streams", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let limits = RpcLimits::new(4, 1, 1024 * 1024, 4096, 2);
+        let handler = tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), limits));
+        let start = SdkCommand::StreamStart { size_hint: None, target: None };
+
+        for _ in 0..2 {
+            assert!(matches!(round_trip(&mut client, &start).await, SdkResponse::StreamStarted { .. }));
+        }
+        let resp = round_trip(&mut client, &start).await;
+        assert!(matches!(resp, SdkResponse::Error { code: Some(ErrorCode::QuotaExceeded), .. }), "{:?}", resp);
+        assert_eq!(node.block_manager.list_streams().len(), 2);
+        assert_eq!(node.block_manager.stream_counters(), (1, 0));
+
+        drop(client);
+        handler.await.unwrap().unwrap();
+        wait_until(|| node.block_manager.list_streams().is_empty()).await;
+    }
+
+    #[tokio::test]
+    async fn test_oversized_and_truncated_frames_close_connection() {
+        use rand::{Rng, SeedableRng};
+        let node = TestNode::spawn("frames", 1024 * 1024).await;
+        let limits = RpcLimits::new(4, 1, 1024 * 1024, 4096, 8);
+
+        // An oversized prefix is answered, then the connection is closed
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let handler = tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), limits.clone()));
+        client.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        assert!(matches!(read_response(&mut client).await, SdkResponse::Error { msg, .. } if msg.contains("exceeds")));
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        assert!(handler.await.unwrap().is_ok());
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        for _ in 0..200 {
+            let (mut client, server) = tokio::io::duplex(64 * 1024);
+            let handler = tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), limits.clone()));
+            let len: u32 = if rng.gen_bool(0.5) { rng.gen() } else { rng.gen_range(0..8192) };
+            let body: Vec<u8> = (0..rng.gen_range(0..(len as usize).min(8192) + 1)).map(|_| rng.gen()).collect();
+            let _ = client.write_all(&len.to_be_bytes()).await;
+            let _ = client.write_all(&body).await;
+            drop(client);
+            // Garbage may fail to decode, but the handler must always wind up promptly
+            tokio::time::timeout(std::time::Duration::from_secs(2), handler).await.unwrap().unwrap().ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vm_config_threshold() {
+        let node = TestNode::spawn("vmconfig", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+
+        let threshold = |resp: SdkResponse| match resp {
+            SdkResponse::VmConfig { threshold_mb } => threshold_mb,
+            other => panic!("unexpected {:?}", other),
+        };
+        let get = SdkCommand::VmConfig { threshold_mb: None };
+        assert_eq!(threshold(round_trip(&mut client, &get).await), crate::blocks::vm::DEFAULT_MALLOC_THRESHOLD_MB);
+        assert_eq!(threshold(round_trip(&mut client, &SdkCommand::VmConfig { threshold_mb: Some(32) }).await), 32);
+        assert_eq!(threshold(round_trip(&mut client, &get).await), 32);
+        assert!(matches!(round_trip(&mut client, &SdkCommand::VmConfig { threshold_mb: Some(0) }).await, SdkResponse::Error { .. }));
+        assert_eq!(threshold(round_trip(&mut client, &get).await), 32);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_each_command() {
+        let node = TestNode::spawn("metrics", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+
+        let store = SdkCommand::Store { data: vec![1; 100], durability: None, content_addressed: false, tags: Default::default(), priority: None };
+        let SdkResponse::Stored { id, .. } = round_trip(&mut client, &store).await else { panic!("store failed") };
+        for _ in 0..3 {
+            assert!(matches!(round_trip(&mut client, &SdkCommand::Load { id }).await, SdkResponse::Loaded { .. }));
+        }
+        // Pipelined commands count under their own name, failed ones too
+        let pipelined = SdkCommand::Pipelined { seq: 7, command: Box::new(SdkCommand::Load { id: id + 1 }) };
+        assert!(matches!(round_trip(&mut client, &pipelined).await, SdkResponse::Pipelined { seq: 7, .. }));
+        assert!(matches!(round_trip(&mut client, &SdkCommand::Stat).await, SdkResponse::Status { .. }));
+
+        let SdkResponse::Metrics { commands } = round_trip(&mut client, &SdkCommand::Metrics).await else { panic!("no metrics") };
+        let counts: Vec<_> = commands.iter().map(|m| (m.command.as_str(), m.count)).collect();
+        assert_eq!(counts, [("Load", 4), ("Stat", 1), ("Store", 1)]);
+        for m in &commands {
+            assert_eq!(m.buckets.iter().sum::<u64>(), m.count);
+            assert!(m.max_micros <= m.total_micros);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rename_moves_the_key_without_the_value() {
+        let node = TestNode::spawn("rename", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+        let set = |key: &str, data: &[u8]| SdkCommand::Set { key: key.to_string(), data: data.to_vec(), target: None, durability: None, tags: Default::default(), priority: None };
+        let rename = |overwrite| SdkCommand::Rename { from: "old".to_string(), to: "new".to_string(), overwrite, target: None };
+        let get = |key: &str| SdkCommand::Get { key: key.to_string(), target: None };
+
+        let missing = round_trip(&mut client, &rename(false)).await;
+        assert!(matches!(missing, SdkResponse::Error { code: Some(ErrorCode::NotFound), .. }), "{:?}", missing);
+        let SdkResponse::Stored { id, .. } = round_trip(&mut client, &set("old", b"value")).await else { panic!("not stored") };
+        round_trip(&mut client, &set("new", b"taken")).await;
+        let taken = round_trip(&mut client, &rename(false)).await;
+        assert!(matches!(taken, SdkResponse::Error { code: Some(ErrorCode::Conflict), .. }), "{:?}", taken);
+
+        assert!(matches!(round_trip(&mut client, &rename(true)).await, SdkResponse::Success));
+        assert!(matches!(round_trip(&mut client, &get("new")).await, SdkResponse::Loaded { data, .. } if data == b"value"[..]));
+        let gone = round_trip(&mut client, &get("old")).await;
+        assert!(matches!(gone, SdkResponse::Error { code: Some(ErrorCode::NotFound), .. }), "{:?}", gone);
+        // Same block, and the value it replaced is let go
+        assert_eq!(node.block_manager.get_named_block_id("new"), Some(id));
+        assert_eq!(node.block_manager.list_keys_page("*", None, 10).0, vec!["new"]);
+    }
+
+    #[tokio::test]
+    async fn test_events_wait_for_a_matching_event() {
+        let node = TestNode::spawn("events", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+        let events = |since_seq, kinds: &[&str], wait_secs| SdkCommand::Events {
+            since_seq,
+            kinds: Some(kinds.iter().map(|k| k.to_string()).collect()),
+            limit: 10,
+            wait_secs,
+        };
+
+        let bogus = round_trip(&mut client, &events(None, &["peers"], None)).await;
+        assert!(matches!(bogus, SdkResponse::Error { code: Some(ErrorCode::InvalidArg), .. }), "{:?}", bogus);
+        node.peer_manager.events.record("eviction", "evicted", None, Some(10), "");
+        let SdkResponse::Events { events: none, next_seq } = round_trip(&mut client, &events(None, &["flush"], None)).await else { panic!("no events") };
+        assert!(none.is_empty());
+        assert_eq!(next_seq, 1);
+
+        // Nothing matches yet, so the request waits for the next flush
+        let wait = events(Some(next_seq), &["flush"], Some(5));
+        let flush = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            node.peer_manager.events.record("flush", "flushed", None, Some(64), "1 blocks, pattern tmp");
+        };
+        let (resp, ()) = tokio::join!(round_trip(&mut client, &wait), flush);
+        let SdkResponse::Events { events, next_seq } = resp else { panic!("no events") };
+        assert_eq!(events.iter().map(|e| (e.seq, e.kind.as_str(), e.bytes)).collect::<Vec<_>>(), [(2, "flush", Some(64))]);
+        assert_eq!(next_seq, 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_connect_withdraws_the_peers_consent_prompt() {
+        let a = TestNode::spawn("cancel-a", 1024 * 1024).await;
+        let b = TestNode::spawn("cancel-b", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, a.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+        let connect = SdkCommand::Connect { addr: b.addr(), quota: None, accept_new_key: false };
+        assert!(matches!(round_trip(&mut client, &connect).await, SdkResponse::ConnectionStatus { .. }));
+        wait_until(|| b.peer_manager.consent_manager.get_pending_list().len() == 1).await;
+
+        // What `memcli connect` sends on Ctrl+C
+        let cancel = SdkCommand::CancelConnect { addr: b.addr() };
+        assert!(matches!(round_trip(&mut client, &cancel).await, SdkResponse::Success));
+        wait_until(|| b.peer_manager.consent_manager.get_pending_list().is_empty()).await;
+        let poll = round_trip(&mut client, &SdkCommand::PollConnection { addr: b.addr() }).await;
+        assert!(matches!(&poll, SdkResponse::ConnectionStatus { state, msg } if state == "failed" && msg.as_deref() == Some("Cancelled")), "{:?}", poll);
+        let again = round_trip(&mut client, &cancel).await;
+        assert!(matches!(again, SdkResponse::Error { code: Some(ErrorCode::NotFound), .. }), "{:?}", again);
+    }
+
+    #[tokio::test]
+    async fn test_errors_carry_codes() {
+        let node = TestNode::spawn("codes", 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+
+        let code = |resp: SdkResponse| match resp {
+            SdkResponse::Error { code, .. } => code,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(code(round_trip(&mut client, &SdkCommand::Load { id: 42 }).await), Some(ErrorCode::NotFound));
+        let connect = SdkCommand::Connect { addr: "nowhere".to_string(), quota: None, accept_new_key: false };
+        assert_eq!(code(round_trip(&mut client, &connect).await), Some(ErrorCode::InvalidArg));
+        let big = SdkCommand::Store { data: vec![0; 4096], durability: None, content_addressed: false, tags: Default::default(), priority: None };
+        assert_eq!(code(round_trip(&mut client, &big).await), Some(ErrorCode::QuotaExceeded));
+        let vm = SdkCommand::VmConfig { threshold_mb: Some(0) };
+        assert_eq!(code(round_trip(&mut client, &vm).await), Some(ErrorCode::InvalidArg));
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_attributed_per_app() {
+        let node = TestNode::spawn("apps", 1024 * 1024).await;
+        let connect = || {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+            client
+        };
+        let (mut named, mut anonymous) = (connect(), connect());
+
+        let hello = SdkCommand::ClientHello { app_name: "indexer".to_string(), pid: Some(42) };
+        assert!(matches!(round_trip(&mut named, &hello).await, SdkResponse::Success));
+        let set = SdkCommand::Set { key: "k".to_string(), data: vec![1; 10], target: None, durability: Some(Durability::Pinned), tags: Default::default(), priority: None };
+        assert!(matches!(round_trip(&mut named, &set).await, SdkResponse::Stored { .. }));
+        let store = SdkCommand::Store { data: vec![2; 20], durability: None, content_addressed: false, tags: Default::default(), priority: None };
+        let SdkResponse::Stored { id, .. } = round_trip(&mut named, &store).await else { panic!("store failed") };
+        assert!(matches!(round_trip(&mut anonymous, &store).await, SdkResponse::Stored { .. }));
+
+        let usage = |resp: SdkResponse| match resp {
+            SdkResponse::AppUsage { items } => items.into_iter().map(|a| (a.app_name, a.pid, a.blocks, a.bytes, a.keys)).collect::<Vec<_>>(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(usage(round_trip(&mut anonymous, &SdkCommand::AppUsage).await), [
+            ("indexer".to_string(), Some(42), 2, 30, 1),
+            ("unknown".to_string(), None, 1, 20, 0),
+        ]);
+
+        assert!(matches!(round_trip(&mut anonymous, &SdkCommand::Free { id }).await, SdkResponse::Freed { count: 1, .. }));
+        assert_eq!(usage(round_trip(&mut named, &SdkCommand::AppUsage).await), [
+            ("unknown".to_string(), None, 1, 20, 0),
+            ("indexer".to_string(), Some(42), 1, 10, 1),
+        ]);
+    }
+
+    async fn read_response<S: AsyncReadExt + Unpin>(stream: &mut S) -> SdkResponse {
+        let mut len_buf = [0u8; 4];
+        stream.read_exact(&mut len_buf).await.unwrap();
+        let mut buf = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut buf).await.unwrap();
+        rmp_serde::from_slice(&buf).unwrap()
+    }
+
+    async fn send_command<S: AsyncWriteExt + Unpin>(stream: &mut S, cmd: SdkCommand) {
+        let bytes = rmp_serde::to_vec_named(&cmd).unwrap();
+        stream.write_all(&(bytes.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stat_not_blocked_by_distributed_get() {
+        let node = TestNode::spawn("pipeline", 1024 * 1024).await;
+        let peer = TestNode::spawn("pipeline-peer", 1024 * 1024).await;
+        node.connect_to(&peer, 0).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+
+        // A missing key falls through to the broadcast and waits out its timeout,
+        // since the peer does not answer for keys it lacks
+        let get = SdkCommand::Get { key: "missing".to_string(), target: None };
+        send_command(&mut client, SdkCommand::Pipelined { seq: 1, command: Box::new(get) }).await;
+        send_command(&mut client, SdkCommand::Pipelined { seq: 2, command: Box::new(SdkCommand::Stat) }).await;
+
+        let first = tokio::time::timeout(std::time::Duration::from_millis(500), read_response(&mut client))
+            .await
+            .expect("Stat should not wait for the Get");
+        match first {
+            SdkResponse::Pipelined { seq: 2, response } => assert!(matches!(*response, SdkResponse::Status { .. })),
+            other => panic!("unexpected response {:?}", other),
+        }
+        match read_response(&mut client).await {
+            SdkResponse::Pipelined { seq: 1, response } => assert!(matches!(*response, SdkResponse::Error { .. })),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_untagged_responses_keep_request_order() {
+        let node = TestNode::spawn("ordered", 1024 * 1024).await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+
+        send_command(&mut client, SdkCommand::Get { key: "missing".to_string(), target: None }).await;
+        send_command(&mut client, SdkCommand::Stat).await;
+        assert!(matches!(read_response(&mut client).await, SdkResponse::Error { .. }));
+        assert!(matches!(read_response(&mut client).await, SdkResponse::Status { .. }));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_loads_share_one_copy() {
+        use crate::blocks::pressure::process_rss;
+        const SIZE: usize = 100 * 1024 * 1024;
+        const READERS: usize = 16;
+        let node = TestNode::spawn("shared-loads", 2 * SIZE as u64).await;
+        let (id, _) = node.block_manager.put_block(node.block_manager.new_block(vec![7u8; SIZE], Durability::Pinned)).unwrap();
+        let region = node.block_manager.vm_alloc(4096);
+        node.block_manager.vm_store(region, 0, vec![9; 4096]).await.unwrap();
+
+        let before = process_rss().unwrap();
+        let loads: Vec<_> = (0..READERS).map(|_| tokio::spawn(execute_command(SdkCommand::Load { id }, node.block_manager.clone()))).collect();
+        let mut held = Vec::new();
+        for load in loads {
+            let SdkResponse::Loaded { data, .. } = load.await.unwrap() else { panic!("expected Loaded") };
+            held.push(data);
+        }
+        let grown = process_rss().unwrap().saturating_sub(before);
+        // Copies would add READERS * 100MB; other tests running alongside get some slack
+        assert!(grown < SIZE as u64, "{} readers grew RSS by {} bytes", READERS, grown);
+        let block_data = node.block_manager.blocks.get(&id).unwrap().data.as_ptr();
+        assert!(held.iter().all(|data| data.len() == SIZE && data.as_ptr() == block_data));
+
+        let page = |resp: SdkResponse| match resp {
+            SdkResponse::PageData { data } => data,
+            other => panic!("unexpected {:?}", other),
+        };
+        let fetch = || execute_command(SdkCommand::VmFetch { region_id: region, page_index: 0 }, node.block_manager.clone());
+        let (first, second) = (page(fetch().await), page(fetch().await));
+        assert_eq!((&first[..], first.as_ptr()), (&[9u8; 4096][..], second.as_ptr()));
+
+        // Once the readers are done, evicting the block frees it
+        drop(held);
+        node.block_manager.evict_block(id).unwrap();
+        assert_eq!(node.block_manager.used_space(), 4096);
+    }
+
+    #[tokio::test]
+    async fn test_large_loads_are_written_from_the_block() {
+        let node = TestNode::spawn("direct", 4 * 1024 * 1024).await;
+        let value: Vec<u8> = (0..DIRECT_PAYLOAD_MIN + 70_000).map(|i| i as u8).collect();
+        let (id, _) = node.block_manager.put_block(node.block_manager.new_block(value.clone(), Durability::Pinned)).unwrap();
+
+        let SdkResponse::Loaded { data, .. } = execute_command(SdkCommand::Load { id }, node.block_manager.clone()).await else {
+            panic!("expected Loaded");
+        };
+        assert_eq!(data.as_ptr(), node.block_manager.blocks.get(&id).unwrap().data.as_ptr(), "the response shares the block's data");
+
+        // Split around the payload, the encoding is what the SDK expects, for every size class
+        for len in [DIRECT_PAYLOAD_MIN, 65_535 + 1, value.len()] {
+            for durability in [None, Some(Durability::Ttl { secs: 300 })] {
+                let loaded = || SdkResponse::Loaded { data: data.slice(..len), durability };
+                for response in [loaded(), SdkResponse::Pipelined { seq: 70_000, response: Box::new(loaded()) }] {
+                    let (head, payload, tail) = split_payload(&response).unwrap().unwrap();
+                    assert_eq!([head, payload.to_vec(), tail].concat(), rmp_serde::to_vec_named(&response).unwrap());
+                }
+            }
+        }
+        assert!(split_payload(&SdkResponse::Loaded { data: data.slice(..10), durability: None }).unwrap().is_none());
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_generic_stream(server, node.block_manager.clone(), Grant::Endpoint(Access::Admin), RpcLimits::default()));
+        for _ in 0..2 {
+            send_command(&mut client, SdkCommand::Load { id }).await;
+            match read_response(&mut client).await {
+                SdkResponse::Loaded { data, durability } => assert_eq!((&data[..], durability), (&value[..], Some(Durability::Pinned))),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_scan_yields_every_key_once() {
+        use crate::test_support::spawn_rpc;
+        use futures::StreamExt;
+        use memsdk::MemCloudClient;
+        use std::collections::HashSet;
+
+        let node = TestNode::spawn("scan", 64 * 1024 * 1024).await;
+        for i in 0..700 {
+            node.block_manager.set(&format!("scan:{:04}", i), vec![1], Durability::Cache).unwrap();
+        }
+        node.block_manager.set("other", vec![1], Durability::Cache).unwrap();
+
+        let mut client = MemCloudClient::connect_with_path(&spawn_rpc(node.block_manager.clone())).await.unwrap();
+        let mut seen = HashSet::new();
+        let mut keys = client.scan("scan:*");
+        while let Some(key) = keys.next().await {
+            let key = key.unwrap();
+            assert!(seen.insert(key.clone()), "duplicate key {}", key);
+        }
+        assert_eq!(seen.len(), 700);
+        assert!(!seen.contains("other"));
+    }
 }
@@ -0,0 +1,89 @@
+//! Helpers for tests that need real nodes talking over localhost.
+
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::blocks::InMemoryBlockManager;
+use crate::net::TransportServer;
+use crate::peers::PeerManager;
+use crate::peers::consent::ConsentDecision;
+
+pub struct TestNode {
+    pub id: Uuid,
+    pub port: u16,
+    pub peer_manager: Arc<PeerManager>,
+    pub block_manager: Arc<InMemoryBlockManager>,
+}
+
+impl TestNode {
+    /// Start a node with its transport listening on an ephemeral localhost port.
+    pub async fn spawn(name: &str, memory: u64) -> Self {
+        Self::spawn_with_id(Uuid::new_v4(), name, memory).await
+    }
+
+    /// Like `spawn`, with a chosen node id (and still a fresh key).
+    pub async fn spawn_with_id(id: Uuid, name: &str, memory: u64) -> Self {
+        let peer_manager = Arc::new(PeerManager::new(id, name.to_string()));
+        let block_manager = Arc::new(InMemoryBlockManager::new(peer_manager.clone(), memory));
+        peer_manager.set_default_quota(memory);
+        let (transport, port) = TransportServer::bind("127.0.0.1", 0, block_manager.clone(), peer_manager.clone())
+            .await
+            .expect("bind transport");
+        tokio::spawn(async move { transport.run().await });
+        Self { id, port, peer_manager, block_manager }
+    }
+
+    pub fn addr(&self) -> String {
+        format!("127.0.0.1:{}", self.port)
+    }
+
+    /// Connect to `other`, approving the consent prompt on its side, and wait
+    /// until both ends have registered each other.
+    pub async fn connect_to(&self, other: &TestNode, quota: u64) {
+        let consent = other.peer_manager.consent_manager.clone();
+        let approver = tokio::spawn(async move {
+            loop {
+                if let Some(req) = consent.get_pending_list().into_iter().next() {
+                    let _ = consent.resolve(&req.session_id, ConsentDecision::ApprovedOnce);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        self.block_manager
+            .connect_peer(&other.addr(), self.block_manager.clone(), quota, Default::default())
+            .await
+            .expect("connect to peer");
+        approver.abort();
+
+        wait_until(|| other.peer_manager.get_peer_id_by_name(&self.id.to_string()).is_some()).await;
+    }
+}
+
+/// Poll `cond` until it holds, panicking after a few seconds.
+pub async fn wait_until(cond: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if cond() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition not reached in time");
+}
+
+/// Serve the RPC protocol for `block_manager` on a fresh unix socket and return its path.
+#[cfg(unix)]
+pub fn spawn_rpc(block_manager: Arc<InMemoryBlockManager>) -> String {
+    let path = std::env::temp_dir().join(format!("memcloud-test-{}.sock", Uuid::new_v4()));
+    let listener = tokio::net::UnixListener::bind(&path).expect("bind rpc socket");
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let bm = block_manager.clone();
+            tokio::spawn(async move {
+                let _ = crate::rpc::handle_client_unix(stream, bm, crate::rpc::Access::Admin, crate::rpc::RpcLimits::default()).await;
+            });
+        }
+    });
+    path.to_string_lossy().to_string()
+}
@@ -0,0 +1,23 @@
+//! Fixtures for tests that run nodes in-process: this crate's integration
+//! tests, and other crates that drive a node through the SDK. Built with the
+//! `test-support` feature, so release builds leave it out.
+
+use std::path::Path;
+
+use crate::Node;
+
+/// Start a node named `name` with `memory` bytes, serving RPC on
+/// `<dir>/<name>.sock`. It listens on an ephemeral port, skips mDNS, and
+/// trusts every peer, so tests connect nodes without consent prompts.
+pub async fn spawn_node(dir: &Path, name: &str, memory: u64) -> Node {
+    Node::builder()
+        .name(name)
+        .memory(memory)
+        .port(0)
+        .socket(dir.join(format!("{}.sock", name)))
+        .local_only(true)
+        .trust_all(true)
+        .spawn()
+        .await
+        .expect("spawn node")
+}
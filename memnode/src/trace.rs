@@ -0,0 +1,105 @@
+//! Request tracing. Each RPC command runs inside an `rpc` span carrying its
+//! request id (the trace id the client sent, else a fresh one), the command
+//! name and the frame size. The id also rides along on the peer messages the
+//! command sends, and the peer handles them inside a `peer` span with the
+//! same id, so one request can be followed through every node's log
+//! (`memcli trace <id>`). Without the `tracing` feature there are no spans;
+//! ids are still passed on to peers.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use log::debug;
+use uuid::Uuid;
+
+#[cfg(feature = "tracing")]
+pub use tracing::Span;
+
+/// Stand-in for `tracing::Span` when the feature is off.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+}
+
+tokio::task_local! {
+    static REQUEST_ID: Arc<str>;
+}
+
+// Longest client-supplied trace id taken as the request id
+const MAX_TRACE_ID_LEN: usize = 64;
+
+/// The client's trace id if it is usable in logs, else a new one.
+pub fn request_id(sent: Option<String>) -> Arc<str> {
+    match sent {
+        Some(id) if (1..=MAX_TRACE_ID_LEN).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') => id.into(),
+        _ => memsdk::new_trace_id().into(),
+    }
+}
+
+/// Id of the request the current task works for, to put on peer messages.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.to_string()).ok()
+}
+
+#[cfg(feature = "tracing")]
+pub fn rpc_span(id: &str, cmd: &str, size: usize) -> Span {
+    tracing::info_span!("rpc", request_id = id, cmd, size)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn rpc_span(_id: &str, _cmd: &str, _size: usize) -> Span {
+    Span
+}
+
+/// Span for handling `msg` from `peer` on behalf of request `id`; none for
+/// messages that are not part of a traced request.
+#[cfg(feature = "tracing")]
+pub fn peer_span(id: Option<&str>, msg: &'static str, peer: Uuid) -> Span {
+    match id {
+        Some(id) => tracing::info_span!("peer", request_id = id, msg, %peer),
+        None => Span::none(),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn peer_span(_id: Option<&str>, _msg: &'static str, _peer: Uuid) -> Span {
+    Span
+}
+
+/// Run `fut` as request `id`, inside `span`.
+pub async fn in_request<F: Future>(id: Arc<str>, span: Span, fut: F) -> F::Output {
+    let work = async move {
+        let started = Instant::now();
+        let out = fut.await;
+        debug!("Request finished in {:?}", started.elapsed());
+        out
+    };
+    #[cfg(feature = "tracing")]
+    let work = tracing::Instrument::instrument(work, span);
+    #[cfg(not(feature = "tracing"))]
+    let _ = span;
+    REQUEST_ID.scope(id, work).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_id_reaches_the_task() {
+        assert_eq!(&*request_id(Some("abc-123".to_string())), "abc-123");
+        assert_ne!(&*request_id(Some("no spaces".to_string())), "no spaces");
+        assert_eq!(request_id(None).len(), 16);
+
+        assert_eq!(current_request_id(), None);
+        let id = request_id(Some("feedface".to_string()));
+        let seen = in_request(id, rpc_span("feedface", "Load", 10), async { current_request_id() }).await;
+        assert_eq!(seen.as_deref(), Some("feedface"));
+    }
+}
@@ -0,0 +1,441 @@
+//! Two nodes in one process, driven through the SDK like a real client would.
+#![cfg(unix)]
+
+use memnode::Node;
+use memsdk::{BlockOrKey, Durability, MemCloudClient, MemCloudError};
+use std::path::Path;
+use std::time::Duration;
+
+const MB: u64 = 1024 * 1024;
+
+async fn spawn_node(dir: &Path, name: &str) -> Node {
+    memnode::testing::spawn_node(dir, name, 64 * MB).await
+}
+
+fn temp_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("memnode-cluster-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// How a test's clients reach its nodes. Each scenario runs over both, which
+// must behave the same.
+#[derive(Debug, Clone, Copy)]
+enum Via {
+    Socket,
+    Direct,
+}
+
+async fn client(node: &Node, via: Via) -> MemCloudClient {
+    match via {
+        Via::Socket => MemCloudClient::connect_with_path(node.socket_path()).await.unwrap(),
+        Via::Direct => node.client(),
+    }
+}
+
+macro_rules! over_each_transport {
+    ($($test:ident: $scenario:ident),* $(,)?) => {
+        $(#[tokio::test]
+        async fn $test() {
+            for via in [Via::Socket, Via::Direct] {
+                $scenario(via).await;
+            }
+        })*
+    };
+}
+
+over_each_transport! {
+    test_two_nodes_share_data: two_nodes_share_data,
+    test_remote_pinned_block_survives_cache_pressure: remote_pinned_block_survives_cache_pressure,
+    test_encrypted_data_is_opaque_to_hosting_peer: encrypted_data_is_opaque_to_hosting_peer,
+    test_migrate_key_to_peer_and_back: migrate_key_to_peer_and_back,
+    test_connect_returns_before_the_handshake: connect_returns_before_the_handshake,
+}
+
+async fn wait_connected(client: &mut MemCloudClient, addr: &str) {
+    for _ in 0..500 {
+        let (state, msg) = client.poll_connection(addr).await.unwrap();
+        match state.as_str() {
+            "connected" => return,
+            "failed" => panic!("connection failed: {:?}", msg),
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("peers did not connect in time");
+}
+
+async fn connect_returns_before_the_handshake(via: Via) {
+    let dir = temp_dir();
+    let a = spawn_node(&dir, "dialer").await;
+    let b = spawn_node(&dir, "answerer").await;
+    let mut client_a = client(&a, via).await;
+
+    // Accepts the TCP connection but never answers the handshake
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let silent_addr = silent.local_addr().unwrap().to_string();
+    let started = tokio::time::timeout(Duration::from_secs(2), client_a.connect_peer(&silent_addr, None)).await;
+    assert_eq!(started.expect("connect_peer waited for the handshake").unwrap(), ("pending".to_string(), None));
+    assert_eq!(client_a.poll_connection(&silent_addr).await.unwrap().0, "pending");
+    client_a.cancel_connect(&silent_addr).await.unwrap();
+
+    let addr_b = format!("127.0.0.1:{}", b.port());
+    client_a.connect_peer_blocking(&addr_b, None).await.unwrap();
+    assert_eq!(client_a.poll_connection(&addr_b).await.unwrap().0, "connected");
+    assert_eq!(client_a.list_peers().await.unwrap().len(), 1);
+    assert!(matches!(client_a.connect_peer_blocking("127.0.0.1:1", None).await, Err(memsdk::MemCloudError::PeerUnreachable(_))));
+
+    // The client's timeout bounds the wait, and the connection stays usable after it
+    client_a.set_timeout(Some(Duration::from_millis(300)));
+    assert!(matches!(client_a.connect_peer_blocking(&silent_addr, None).await, Err(memsdk::MemCloudError::Timeout(_))));
+    assert_eq!(client_a.list_peers().await.unwrap().len(), 1);
+
+    drop(client_a);
+    a.shutdown().await;
+    b.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+async fn two_nodes_share_data(via: Via) {
+    let dir = temp_dir();
+    let a = spawn_node(&dir, "alpha").await;
+    let b = spawn_node(&dir, "beta").await;
+
+    let mut client_a = client(&a, via).await;
+    let mut client_b = client(&b, via).await;
+
+    let addr_b = format!("127.0.0.1:{}", b.port());
+    client_a.connect_peer(&addr_b, None).await.unwrap();
+    wait_connected(&mut client_a, &addr_b).await;
+
+    let local = client_a.store(b"local block", Durability::Pinned).await.unwrap();
+    assert_eq!(client_a.load(local).await.unwrap(), b"local block");
+
+    // Stored on beta, readable from both sides
+    let (remote, location) = client_a.store_remote(b"remote block", Some("beta".to_string()), Durability::Pinned, false).await.unwrap();
+    assert_eq!(location, memsdk::StoredLocation::Remote("beta".to_string()));
+    assert_eq!(client_a.load(remote).await.unwrap(), b"remote block");
+    assert_eq!(client_b.load(remote).await.unwrap(), b"remote block");
+
+    client_a.set("greeting", b"hello", Some("beta".to_string()), Durability::Pinned).await.unwrap();
+    assert_eq!(client_b.get("greeting", None).await.unwrap(), b"hello");
+    assert_eq!(client_a.get("greeting", Some("beta".to_string())).await.unwrap(), b"hello");
+
+    let data: Vec<u8> = (0..3 * MB).map(|i| (i % 251) as u8).collect();
+    let streamed = client_a.stream_data(&data[..], Some(data.len() as u64), None).await.unwrap();
+    assert_eq!(client_a.load(streamed).await.unwrap(), data);
+
+    client_a.flush(None).await.unwrap();
+    assert!(client_a.load(local).await.is_err());
+    assert!(client_a.load(streamed).await.is_err());
+    // Flushing alpha leaves beta's data alone
+    assert_eq!(client_b.get("greeting", None).await.unwrap(), b"hello");
+
+    drop((client_a, client_b));
+    a.shutdown().await;
+    b.shutdown().await;
+    assert!(!dir.join("alpha.sock").exists());
+    assert!(!dir.join("beta.sock").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+async fn remote_pinned_block_survives_cache_pressure(via: Via) {
+    let dir = temp_dir();
+    let a = spawn_node(&dir, "origin").await;
+    let b = spawn_node(&dir, "keeper").await;
+
+    let mut client_a = client(&a, via).await;
+    let mut client_b = client(&b, via).await;
+    let addr_b = format!("127.0.0.1:{}", b.port());
+    client_a.connect_peer(&addr_b, None).await.unwrap();
+    wait_connected(&mut client_a, &addr_b).await;
+
+    let pinned_data = vec![7u8; MB as usize];
+    let pinned = client_a.store_remote(&pinned_data, Some("keeper".to_string()), Durability::Pinned, false).await.unwrap().0;
+    client_a.set("session", b"temp", Some("keeper".to_string()), Durability::Ttl { secs: 600 }).await.unwrap();
+    let session = client_a.get_with_durability("session", Some("keeper".to_string())).await.unwrap();
+    assert_eq!(session, (b"temp".to_vec(), Some(Durability::Ttl { secs: 600 })));
+
+    // Fill the keeper well past its capacity with its own cache blocks
+    let filler = vec![1u8; MB as usize];
+    for i in 0..80u8 {
+        let mut data = filler.clone();
+        data[0] = i;
+        client_b.store(&data, Durability::Cache).await.unwrap();
+    }
+
+    assert_eq!(client_b.load_with_durability(pinned).await.unwrap(), (pinned_data.clone(), Some(Durability::Pinned)));
+    assert_eq!(client_a.load_with_durability(pinned).await.unwrap(), (pinned_data, Some(Durability::Pinned)));
+
+    drop((client_a, client_b));
+    a.shutdown().await;
+    b.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+async fn encrypted_data_is_opaque_to_hosting_peer(via: Via) {
+    let dir = temp_dir();
+    let a = spawn_node(&dir, "owner").await;
+    let b = spawn_node(&dir, "host").await;
+
+    let key = memsdk::crypto::derive_key(b"hunter2");
+    let mut owner = client(&a, via).await.with_encryption(key);
+    // Reads the host's memory without a key, like the machine's owner could
+    let mut host = client(&b, via).await;
+
+    let addr_b = format!("127.0.0.1:{}", b.port());
+    owner.connect_peer(&addr_b, None).await.unwrap();
+    wait_connected(&mut owner, &addr_b).await;
+
+    let secret = b"my bank password is hunter2";
+    let contains_secret = |data: &[u8]| data.windows(secret.len()).any(|w| w == secret);
+
+    let id = owner.store_remote(secret, Some("host".to_string()), Durability::Pinned, false).await.unwrap().0;
+    let raw = host.load(id).await.unwrap();
+    assert!(memsdk::crypto::is_encrypted(&raw));
+    assert!(!contains_secret(&raw));
+    // The host can tell which blocks it cannot read
+    assert!(host.block_info(memsdk::BlockOrKey::Id { id }).await.unwrap().encrypted);
+    assert!(host.list_blocks().await.unwrap().iter().any(|b| b.id == id && b.encrypted));
+    assert_eq!(owner.load(id).await.unwrap(), secret);
+
+    owner.set("vault", secret, Some("host".to_string()), Durability::Pinned).await.unwrap();
+    assert!(!contains_secret(&host.get("vault", None).await.unwrap()));
+    assert_eq!(owner.get("vault", Some("host".to_string())).await.unwrap(), secret);
+
+    let large: Vec<u8> = secret.iter().copied().cycle().take(200_000).collect();
+    let streamed = owner.stream_data(&large[..], None, Some("host".to_string())).await.unwrap();
+    assert!(!contains_secret(&host.load(streamed).await.unwrap()));
+    assert_eq!(owner.load(streamed).await.unwrap(), large);
+
+    // Plaintext is refused rather than handed back as if it had been decrypted
+    host.set("plain", b"not encrypted", None, Durability::Pinned).await.unwrap();
+    assert!(!host.block_info(memsdk::BlockOrKey::Key { key: "plain".to_string() }).await.unwrap().encrypted);
+    let err = owner.get("plain", Some("host".to_string())).await.unwrap_err();
+    assert!(err.to_string().contains("not encrypted"), "{}", err);
+
+    drop((owner, host));
+    a.shutdown().await;
+    b.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+async fn migrate_key_to_peer_and_back(via: Via) {
+    let dir = temp_dir();
+    let a = spawn_node(&dir, "laptop").await;
+    let b = spawn_node(&dir, "desktop").await;
+    let small = Node::builder()
+        .name("tiny")
+        .memory(MB)
+        .port(0)
+        .socket(dir.join("tiny.sock"))
+        .local_only(true)
+        .trust_all(true)
+        .spawn()
+        .await
+        .unwrap();
+
+    let mut laptop = client(&a, via).await;
+    let mut desktop = client(&b, via).await;
+    for port in [b.port(), small.port()] {
+        let addr = format!("127.0.0.1:{}", port);
+        laptop.connect_peer(&addr, None).await.unwrap();
+        wait_connected(&mut laptop, &addr).await;
+    }
+
+    // Several chunks' worth, so the transfer is chunked
+    let data: Vec<u8> = (0..2 * MB + 17).map(|i| (i % 253) as u8).collect();
+    laptop.set("dataset", &data, None, Durability::Pinned).await.unwrap();
+    let key = || memsdk::BlockOrKey::Key { key: "dataset".to_string() };
+    laptop.set_priority(key(), 200).await.unwrap();
+
+    // A peer without room refuses it; the original stays put
+    let err = laptop.migrate(key(), Some("tiny".to_string()), None, false).await.unwrap_err();
+    assert!(err.to_string().contains("safe to retry"), "{}", err);
+    assert_eq!(laptop.node_stats().await.unwrap().memory_usage as u64, data.len() as u64);
+
+    let status = laptop.migrate(key(), Some("desktop".to_string()), None, false).await.unwrap();
+    assert_eq!(status.bytes_total, data.len() as u64);
+    assert_eq!(laptop.node_stats().await.unwrap().memory_usage, 0);
+    assert_eq!(desktop.get("dataset", None).await.unwrap(), data);
+    // Still reachable by key from the laptop, and still as hard to evict
+    assert_eq!(laptop.get("dataset", None).await.unwrap(), data);
+    assert_eq!(desktop.block_info(key()).await.unwrap().priority, 200);
+
+    laptop.migrate(key(), None, Some("desktop".to_string()), false).await.unwrap();
+    assert_eq!(laptop.node_stats().await.unwrap().memory_usage as u64, data.len() as u64);
+    assert_eq!(desktop.node_stats().await.unwrap().memory_usage, 0);
+    assert!(desktop.list_keys("*").await.unwrap().is_empty());
+    assert_eq!(laptop.get("dataset", None).await.unwrap(), data);
+    assert_eq!(laptop.block_info(key()).await.unwrap().priority, 200);
+
+    // A migrated counter is not restarted here; the peer keeps its value
+    laptop.incr("visits", 5).await.unwrap();
+    let visits = || memsdk::BlockOrKey::Key { key: "visits".to_string() };
+    laptop.migrate(visits(), Some("desktop".to_string()), None, false).await.unwrap();
+    let err = laptop.incr("visits", 1).await.unwrap_err();
+    assert!(err.to_string().contains("migrated to peer desktop"), "{}", err);
+    assert_eq!(laptop.get("visits", None).await.unwrap(), 5i64.to_le_bytes());
+    laptop.migrate(visits(), None, Some("desktop".to_string()), false).await.unwrap();
+    assert_eq!(laptop.incr("visits", 1).await.unwrap(), 6);
+
+    drop((laptop, desktop));
+    a.shutdown().await;
+    b.shutdown().await;
+    small.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_embedded_client_recovers_from_a_timeout() {
+    let mut client = Node::builder().name("slow").memory(16 * MB).port(0).local_only(true).embedded().await.unwrap();
+    client.set("k", b"mine", None, Durability::Pinned).await.unwrap();
+
+    // The wait outlasts the timeout; its empty answer comes after the call is gone
+    client.set_timeout(Some(Duration::from_millis(50)));
+    let (_, next) = client.events(None, None, 1, None).await.unwrap();
+    let waited = client.events(Some(next), Some(vec!["flush".to_string()]), 10, Some(1)).await;
+    assert!(matches!(waited, Err(MemCloudError::Timeout(_))), "{:?}", waited);
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    client.set_timeout(None);
+    assert_eq!(client.get("k", None).await.unwrap(), b"mine");
+    assert_eq!(client.get("k", None).await.unwrap(), b"mine");
+}
+
+#[tokio::test]
+async fn test_embedded_node_without_daemon() {
+    let dir = temp_dir();
+    let mut embedded = Node::builder().name("embedded").memory(16 * MB).port(0).local_only(true).embedded().await.unwrap();
+    let id = embedded.store(b"in process", Durability::Pinned).await.unwrap();
+    assert_eq!(embedded.load(id).await.unwrap(), b"in process");
+    embedded.set("greeting", b"hello", None, Durability::Pinned).await.unwrap();
+    assert_eq!(embedded.get("greeting", None).await.unwrap(), b"hello");
+    let data: Vec<u8> = (0..3 * MB).map(|i| (i % 249) as u8).collect();
+    let streamed = embedded.stream_data(&data[..], None, None).await.unwrap();
+    assert_eq!(embedded.load(streamed).await.unwrap(), data);
+    assert_eq!(embedded.node_stats().await.unwrap().memory_usage as usize, b"in process".len() + b"hello".len() + data.len());
+
+    // Another embedded node and a socket-serving one coexist with it
+    let other = Node::builder().name("other").port(0).local_only(true).embedded().await.unwrap();
+    let served = spawn_node(&dir, "served").await;
+    let mut served_client = client(&served, Via::Socket).await;
+    served_client.ping().await.unwrap();
+    drop(other);
+
+    // Dropping the client shuts its node down
+    let peer_addr = format!("127.0.0.1:{}", served.port());
+    embedded.connect_peer(&peer_addr, None).await.unwrap();
+    wait_connected(&mut embedded, &peer_addr).await;
+    assert_eq!(served_client.list_peers().await.unwrap().len(), 1);
+    drop(embedded);
+    for _ in 0..500 {
+        if served_client.list_peers().await.unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(served_client.list_peers().await.unwrap().is_empty());
+
+    drop(served_client);
+    served.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_shutdown_command_stops_the_node() {
+    let dir = temp_dir();
+    let mut node = spawn_node(&dir, "stoppable").await;
+    let mut client = client(&node, Via::Socket).await;
+    client.shutdown().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), node.stopped()).await.expect("node stops on Shutdown");
+    let socket = node.socket_path().to_string();
+    node.shutdown().await;
+    assert!(MemCloudClient::connect_with_path(&socket).await.is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_json_values_round_trip() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Job {
+        id: u32,
+        tags: Vec<String>,
+        done: bool,
+    }
+    let mut client = Node::builder().name("json").memory(16 * MB).port(0).local_only(true).embedded().await.unwrap();
+    let job = Job { id: 7, tags: vec!["nightly".to_string()], done: false };
+    client.set_json("job:7", &job, None, Durability::Pinned).await.unwrap();
+    assert_eq!(client.get_json::<Job>("job:7", None).await.unwrap(), Some(job));
+    // The bytes underneath are plain JSON
+    assert_eq!(client.get("job:7", None).await.unwrap(), br#"{"id":7,"tags":["nightly"],"done":false}"#);
+
+    // A missing key is not an error, a value of another shape is
+    assert_eq!(client.get_json::<Job>("job:8", None).await.unwrap(), None);
+    // Only a missing key is `None`; a peer that is not there is an error
+    let err = client.get_json::<Job>("job:7", Some("nowhere".to_string())).await.unwrap_err();
+    assert!(matches!(err, MemCloudError::PeerUnreachable(_)), "{:?}", err);
+    assert!(matches!(client.get_json::<Vec<u32>>("job:7", None).await.unwrap_err(), MemCloudError::Json(_)));
+    client.set("raw", b"\xff not json", None, Durability::Pinned).await.unwrap();
+    assert!(matches!(client.get_json::<Job>("raw", None).await.unwrap_err(), MemCloudError::Json(_)));
+}
+
+#[tokio::test]
+async fn test_priority_is_kept_and_changed() {
+    let client = Node::builder().name("prio").memory(16 * MB).port(0).local_only(true).embedded().await.unwrap();
+    let mut client = client.with_priority(30);
+    let key = |k: &str| BlockOrKey::Key { key: k.to_string() };
+    client.set("thumb", b"small", None, Durability::Cache).await.unwrap();
+    let id = client.store(b"render", Durability::Cache).await.unwrap();
+    assert_eq!(client.block_info(key("thumb")).await.unwrap().priority, 30);
+    assert_eq!(client.block_info(BlockOrKey::Id { id }).await.unwrap().priority, 30);
+
+    client.set_priority(key("thumb"), 200).await.unwrap();
+    assert_eq!(client.block_info(key("thumb")).await.unwrap().priority, 200);
+    let listed = client.list_blocks().await.unwrap();
+    assert_eq!(listed.iter().find(|b| b.id == id).unwrap().priority, 30);
+    assert!(client.set_priority(key("missing"), 1).await.is_err());
+}
+
+#[tokio::test]
+async fn test_client_cache_serves_repeat_reads() {
+    let dir = temp_dir();
+    let node = spawn_node(&dir, "cached").await;
+    let ttl = Duration::from_millis(300);
+    let mut cached = client(&node, Via::Socket).await.with_cache(MB, ttl);
+    let mut other = client(&node, Via::Socket).await;
+
+    cached.set("k", b"v1", None, Durability::Pinned).await.unwrap();
+    assert_eq!(cached.get("k", None).await.unwrap(), b"v1");
+    assert_eq!(cached.get("k", None).await.unwrap(), b"v1");
+    let stats = cached.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    // A write through the same client is seen at once
+    cached.set("k", b"v2", None, Durability::Pinned).await.unwrap();
+    assert_eq!(cached.get("k", None).await.unwrap(), b"v2");
+
+    // One through another client only once the entry is older than the TTL
+    other.set("k", b"v3", None, Durability::Pinned).await.unwrap();
+    assert_eq!(cached.get("k", None).await.unwrap(), b"v2");
+    tokio::time::sleep(ttl).await;
+    assert_eq!(cached.get("k", None).await.unwrap(), b"v3");
+
+    // Failed reads are not cached
+    assert!(cached.get("later", None).await.unwrap_err().is_not_found());
+    other.set("later", b"here", None, Durability::Pinned).await.unwrap();
+    assert_eq!(cached.get("later", None).await.unwrap(), b"here");
+
+    let id = cached.store(b"block", Durability::Pinned).await.unwrap();
+    assert_eq!(cached.load(id).await.unwrap(), b"block");
+    assert_eq!(cached.load(id).await.unwrap(), b"block");
+    cached.free(id).await.unwrap();
+    assert!(cached.load(id).await.is_err());
+    assert_eq!(cached.cache_stats().unwrap().hits, 3);
+
+    // Replacing a key leaves its old block to be freed, so that is not served by id either
+    let old = cached.set("k", b"v4", None, Durability::Pinned).await.unwrap();
+    assert_eq!(cached.load(old).await.unwrap(), b"v4");
+    cached.set("k", b"v5", None, Durability::Pinned).await.unwrap();
+    let misses = cached.cache_stats().unwrap().misses;
+    let _ = cached.load(old).await;
+    assert_eq!(cached.cache_stats().unwrap().misses, misses + 1);
+    assert_eq!(other.cache_stats(), None);
+}
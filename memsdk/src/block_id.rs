@@ -0,0 +1,164 @@
+//! The short form of block ids that people read and type.
+//!
+//! On the wire a block id stays a plain `u64`. Shown to people it is `blk_`
+//! and 15 characters of Crockford base32, encoding a CRC-8 of the id followed
+//! by the id itself: 11400714819323198485 is `blk_r2f3eydsfx57r58`. A mistyped
+//! id then fails its checksum instead of naming some other block. Parsing
+//! also accepts the decimal form older releases printed, so scripts keep
+//! working.
+
+use crate::BlockId;
+use std::fmt;
+use std::str::FromStr;
+
+pub const BLOCK_ID_PREFIX: &str = "blk_";
+// Crockford's alphabet: no i, l, o or u to mistake for 1, 0 or v
+const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+// 8 checksum bits and 64 id bits, padded with 3 zero bits to 15 characters
+const ENCODED_LEN: usize = 15;
+const PAD_BITS: u32 = 3;
+
+/// A block id that displays as `blk_...` and parses from that or decimal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShortId(pub BlockId);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockIdError {
+    /// Neither a `blk_` id nor a decimal one.
+    Malformed(String),
+    /// Well formed, but the checksum does not match: a typo.
+    ChecksumMismatch(String),
+}
+
+impl fmt::Display for BlockIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockIdError::Malformed(s) => write!(f, "invalid block id '{}'; expected {}... or a decimal id", s, BLOCK_ID_PREFIX),
+            BlockIdError::ChecksumMismatch(s) => write!(f, "invalid block id '{}' (checksum mismatch)", s),
+        }
+    }
+}
+
+impl std::error::Error for BlockIdError {}
+
+// CRC-8 with polynomial 0x07: catches every error within one character
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn digit(c: u8) -> Option<u128> {
+    let c = match c.to_ascii_lowercase() {
+        b'o' => b'0',
+        b'i' | b'l' => b'1',
+        c => c,
+    };
+    ALPHABET.iter().position(|a| *a == c).map(|v| v as u128)
+}
+
+impl fmt::Display for ShortId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let crc = crc8(&self.0.to_be_bytes());
+        let bits = (((crc as u128) << 64) | self.0 as u128) << PAD_BITS;
+        let mut encoded = [0u8; ENCODED_LEN];
+        for (i, c) in encoded.iter_mut().enumerate() {
+            let shift = 5 * (ENCODED_LEN - 1 - i);
+            *c = ALPHABET[((bits >> shift) & 31) as usize];
+        }
+        write!(f, "{}{}", BLOCK_ID_PREFIX, std::str::from_utf8(&encoded).unwrap())
+    }
+}
+
+impl FromStr for ShortId {
+    type Err = BlockIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let malformed = || BlockIdError::Malformed(s.to_string());
+        let Some(encoded) = trimmed.get(..BLOCK_ID_PREFIX.len())
+            .filter(|p| p.eq_ignore_ascii_case(BLOCK_ID_PREFIX))
+            .map(|_| &trimmed[BLOCK_ID_PREFIX.len()..])
+        else {
+            return trimmed.parse::<BlockId>().map(ShortId).map_err(|_| malformed());
+        };
+        if encoded.len() != ENCODED_LEN {
+            return Err(malformed());
+        }
+        let mut bits = 0u128;
+        for c in encoded.bytes() {
+            bits = (bits << 5) | digit(c).ok_or_else(malformed)?;
+        }
+        if bits & ((1 << PAD_BITS) - 1) != 0 {
+            return Err(BlockIdError::ChecksumMismatch(s.to_string()));
+        }
+        let id = (bits >> PAD_BITS) as u64;
+        let crc = (bits >> (64 + PAD_BITS)) as u8;
+        if crc8(&id.to_be_bytes()) != crc {
+            return Err(BlockIdError::ChecksumMismatch(s.to_string()));
+        }
+        Ok(ShortId(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [BlockId; 6] = [0, 1, 42, 0x0123_4567_89ab_cdef, u64::MAX - 1, u64::MAX];
+
+    #[test]
+    fn test_round_trips_both_forms() {
+        for id in SAMPLES.into_iter().chain((0..2000u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15))) {
+            let short = ShortId(id).to_string();
+            assert_eq!(short.len(), BLOCK_ID_PREFIX.len() + ENCODED_LEN);
+            assert_eq!(short.parse::<ShortId>(), Ok(ShortId(id)), "{}", short);
+            assert_eq!(short.to_uppercase().parse::<ShortId>(), Ok(ShortId(id)));
+            assert_eq!(id.to_string().parse::<ShortId>(), Ok(ShortId(id)));
+        }
+        // Crockford look-alikes read as the digits they resemble
+        let zero = ShortId(0).to_string();
+        assert_eq!(zero.replace('0', "o").replacen("blk_", "BLK_", 1).parse::<ShortId>(), Ok(ShortId(0)));
+    }
+
+    #[test]
+    fn test_every_single_character_typo_is_caught() {
+        for id in SAMPLES {
+            let short = ShortId(id).to_string();
+            for pos in BLOCK_ID_PREFIX.len()..short.len() {
+                for &c in ALPHABET.iter().filter(|c| **c != short.as_bytes()[pos]) {
+                    let mut typo = short.clone().into_bytes();
+                    typo[pos] = c;
+                    let typo = String::from_utf8(typo).unwrap();
+                    assert!(matches!(typo.parse::<ShortId>(), Err(BlockIdError::ChecksumMismatch(_))), "{} passed as {}", typo, short);
+                }
+            }
+            // Swapping two neighbours changes the id too
+            for pos in BLOCK_ID_PREFIX.len()..short.len() - 1 {
+                let mut swapped = short.clone().into_bytes();
+                swapped.swap(pos, pos + 1);
+                if swapped != short.as_bytes() {
+                    assert!(String::from_utf8(swapped).unwrap().parse::<ShortId>().is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_ids() {
+        let short = ShortId(42).to_string();
+        for bad in ["", "blk_", "blk_u0000000000000", "-1", "18446744073709551616", "abc", &short[..short.len() - 1], &format!("{}0", short)] {
+            let err = bad.parse::<ShortId>().unwrap_err();
+            assert!(matches!(err, BlockIdError::Malformed(_)), "{}: {:?}", bad, err);
+        }
+        assert_eq!(
+            format!("{}1", &short[..short.len() - 1]).parse::<ShortId>().unwrap_err().to_string(),
+            format!("invalid block id '{}1' (checksum mismatch)", &short[..short.len() - 1]),
+        );
+    }
+}
@@ -19,8 +19,11 @@ pub extern "C" fn memcloud_noop() {}
 
 #[no_mangle]
 pub extern "C" fn memcloud_init() -> c_int {
-    let default_path = if cfg!(windows) { "127.0.0.1:7070" } else { "/tmp/memcloud.sock" };
-    let socket_path = std::env::var("MEMCLOUD_SOCKET").unwrap_or_else(|_| default_path.to_string());
+    let socket_path = if cfg!(windows) {
+        std::env::var(crate::SOCKET_ENV).unwrap_or_else(|_| "127.0.0.1:7070".to_string())
+    } else {
+        crate::default_socket_path()
+    };
     RUNTIME.block_on(async {
         match MemCloudClient::connect_with_path(&socket_path).await {
             Ok(client) => {
@@ -32,6 +35,38 @@ pub extern "C" fn memcloud_init() -> c_int {
     })
 }
 
+/// Like `memcloud_init`, and reports `name` to the node so data stored through
+/// this process shows up under it in `memcli stats --by-app`.
+///
+/// # Safety
+/// `name` must be null or point to a NUL-terminated string.
+#[allow(clippy::await_holding_lock)]
+#[no_mangle]
+pub unsafe extern "C" fn memcloud_init_named(name: *const std::os::raw::c_char) -> c_int {
+    if name.is_null() {
+        return -1;
+    }
+    let name = match std::ffi::CStr::from_ptr(name).to_str() {
+        Ok(s) => s.to_string(),
+        Err(_) => return -1,
+    };
+    let rc = memcloud_init();
+    if rc != 0 {
+        return rc;
+    }
+    RUNTIME.block_on(async {
+        let mut guard = CLIENT.lock().unwrap();
+        if let Some(client) = &mut *guard {
+            match client.identify(&name).await {
+                Ok(_) => 0,
+                Err(_) => -2,
+            }
+        } else {
+            -1
+        }
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn memcloud_init_with_path(socket_path: *const std::os::raw::c_char) -> c_int {
     if socket_path.is_null() {
@@ -127,6 +162,34 @@ pub extern "C" fn memcloud_free(id: u64) -> c_int {
     })
 }
 
+/// Frees the `count` ids at `ids` in one round trip. Unknown ids are
+/// skipped. `out_freed` and `out_bytes` may be null.
+///
+/// # Safety
+/// `ids` must point to `count` ids, and the out pointers, when not null, to writable `u64`s.
+// The guard only lives inside block_on on the single-threaded runtime
+#[allow(clippy::await_holding_lock)]
+#[no_mangle]
+pub unsafe extern "C" fn memcloud_free_many(ids: *const u64, count: usize, out_freed: *mut u64, out_bytes: *mut u64) -> c_int {
+    if ids.is_null() && count > 0 { return -1; }
+    let ids = if count == 0 { &[][..] } else { std::slice::from_raw_parts(ids, count) };
+    RUNTIME.block_on(async {
+        let mut guard = CLIENT.lock().unwrap();
+        if let Some(client) = &mut *guard {
+            match client.free_many(ids, None).await {
+                Ok(stats) => {
+                    if !out_freed.is_null() { *out_freed = stats.count };
+                    if !out_bytes.is_null() { *out_bytes = stats.bytes };
+                    0
+                }
+                Err(_) => -2,
+            }
+        } else {
+            -1
+        }
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn memcloud_vm_alloc(size: u64, out_region_id: *mut u64) -> c_int {
     if out_region_id.is_null() { return -1; }
@@ -184,3 +247,28 @@ pub extern "C" fn memcloud_vm_store(region_id: u64, page_index: u64, data: *cons
         }
     })
 }
+
+/// Writes the node's default malloc interception threshold (MB) to `out_mb`.
+///
+/// # Safety
+/// `out_mb` must be null or point to a writable `u64`.
+// The guard only lives inside block_on on the single-threaded runtime
+#[allow(clippy::await_holding_lock)]
+#[no_mangle]
+pub unsafe extern "C" fn memcloud_vm_threshold_mb(out_mb: *mut u64) -> c_int {
+    if out_mb.is_null() { return -1; }
+    RUNTIME.block_on(async {
+        let mut guard = CLIENT.lock().unwrap();
+        if let Some(client) = &mut *guard {
+            match client.vm_config(None).await {
+                Ok(mb) => {
+                    *out_mb = mb;
+                    0
+                }
+                Err(_) => -2,
+            }
+        } else {
+            -1
+        }
+    })
+}
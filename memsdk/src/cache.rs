@@ -0,0 +1,207 @@
+//! The in-process cache of `MemCloudClient::with_cache`.
+//!
+//! Values read with `load` and `get` are kept in the client, so reading the
+//! same ones again skips the round trip to the node. Writes through the same
+//! client drop what they may have changed. Writes by anyone else are only
+//! seen once an entry is older than the TTL, since the node does not tell
+//! clients when a value changes. Only successful reads are cached, and the
+//! least recently used entries make room for new ones.
+
+use crate::{BlockId, Durability};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// One entry may take at most this share of the cache, so a single large
+/// value cannot push out every small hot one.
+pub const CACHE_ENTRY_SHARE: u64 = 8;
+
+/// Hits and misses of `MemCloudClient::cache_stats`, since the cache was set up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    /// Reads that went to the node: not cached, expired, or dropped by a write.
+    pub misses: u64,
+    pub entries: usize,
+    /// Value bytes held now.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum CacheKey {
+    Block(BlockId),
+    // A key read from one peer may differ from the same key elsewhere
+    Key { key: String, target: Option<String> },
+}
+
+struct Entry {
+    data: Vec<u8>,
+    durability: Option<Durability>,
+    cached_at: Instant,
+    // Position in `recency`
+    used: u64,
+}
+
+pub(crate) struct ValueCache {
+    max_bytes: u64,
+    ttl: Duration,
+    entries: HashMap<CacheKey, Entry>,
+    // Entries by last use, oldest first
+    recency: BTreeMap<u64, CacheKey>,
+    next_use: u64,
+    stats: CacheStats,
+}
+
+impl ValueCache {
+    pub(crate) fn new(max_bytes: u64, ttl: Duration) -> Self {
+        Self { max_bytes, ttl, entries: HashMap::new(), recency: BTreeMap::new(), next_use: 0, stats: CacheStats::default() }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// The value cached under `key` less than the TTL before `now`.
+    pub(crate) fn get(&mut self, key: &CacheKey, now: Instant) -> Option<(Vec<u8>, Option<Durability>)> {
+        let fresh = self.entries.get(key).map(|e| now.saturating_duration_since(e.cached_at) < self.ttl);
+        match fresh {
+            Some(true) => {
+                self.stats.hits += 1;
+                let use_id = self.bump();
+                let entry = self.entries.get_mut(key).unwrap();
+                self.recency.remove(&entry.used);
+                entry.used = use_id;
+                self.recency.insert(use_id, key.clone());
+                Some((entry.data.clone(), entry.durability))
+            }
+            Some(false) => {
+                self.stats.misses += 1;
+                self.remove(key);
+                None
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache a value just read. One over the per-entry cap is not kept.
+    pub(crate) fn insert(&mut self, key: CacheKey, data: &[u8], durability: Option<Durability>, now: Instant) {
+        self.remove(&key);
+        let size = data.len() as u64;
+        if size > self.max_bytes / CACHE_ENTRY_SHARE {
+            return;
+        }
+        while self.stats.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else { break };
+            self.remove(&oldest);
+        }
+        let used = self.bump();
+        self.recency.insert(used, key.clone());
+        self.entries.insert(key, Entry { data: data.to_vec(), durability, cached_at: now, used });
+        self.stats.bytes += size;
+        self.stats.entries = self.entries.len();
+    }
+
+    /// Drop `key` as read from any peer.
+    pub(crate) fn forget_key(&mut self, key: &str) {
+        let stale: Vec<CacheKey> = self.entries.keys().filter(|k| matches!(k, CacheKey::Key { key: k, .. } if k == key)).cloned().collect();
+        for k in stale {
+            self.remove(&k);
+        }
+    }
+
+    /// Drop every value read by block id.
+    pub(crate) fn forget_blocks(&mut self) {
+        let stale: Vec<CacheKey> = self.entries.keys().filter(|k| matches!(k, CacheKey::Block(_))).cloned().collect();
+        for k in stale {
+            self.remove(&k);
+        }
+    }
+
+    pub(crate) fn forget(&mut self, key: &CacheKey) {
+        self.remove(key);
+    }
+
+    /// Drop every value, keeping the counters.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.stats.bytes = 0;
+        self.stats.entries = 0;
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+            self.stats.bytes -= entry.data.len() as u64;
+            self.stats.entries = self.entries.len();
+        }
+    }
+
+    fn bump(&mut self) -> u64 {
+        self.next_use += 1;
+        self.next_use
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(k: &str) -> CacheKey {
+        CacheKey::Key { key: k.to_string(), target: None }
+    }
+
+    #[test]
+    fn test_entries_expire_after_the_ttl() {
+        let mut cache = ValueCache::new(1024, Duration::from_secs(5));
+        let start = Instant::now();
+        assert_eq!(cache.get(&key("a"), start), None);
+        cache.insert(key("a"), b"one", Some(Durability::Cache), start);
+        assert_eq!(cache.get(&key("a"), start + Duration::from_secs(4)), Some((b"one".to_vec(), Some(Durability::Cache))));
+        assert_eq!(cache.get(&key("a"), start + Duration::from_secs(5)), None);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2, entries: 0, bytes: 0 });
+    }
+
+    #[test]
+    fn test_least_recently_used_make_room() {
+        let mut cache = ValueCache::new(80, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert(key("a"), &[1; 10], None, now);
+        cache.insert(CacheKey::Block(7), &[2; 10], None, now);
+        // Over the cap of 80 / 8 bytes: not cached at all
+        cache.insert(key("big"), &[3; 11], None, now);
+        assert_eq!(cache.stats().entries, 2);
+
+        for i in 0..6 {
+            cache.insert(CacheKey::Block(i), &[4; 10], None, now);
+        }
+        assert!(cache.get(&key("a"), now).is_some());
+        // Full: the next one pushes out block 7, the oldest not read since
+        cache.insert(key("b"), &[5; 10], None, now);
+        assert!(cache.get(&CacheKey::Block(7), now).is_none());
+        assert!(cache.get(&key("a"), now).is_some());
+        assert_eq!((cache.stats().entries, cache.stats().bytes), (8, 80));
+    }
+
+    #[test]
+    fn test_forgetting_a_key_drops_it_from_every_peer() {
+        let mut cache = ValueCache::new(1024, Duration::from_secs(60));
+        let now = Instant::now();
+        let on_peer = CacheKey::Key { key: "a".to_string(), target: Some("laptop".to_string()) };
+        cache.insert(key("a"), b"here", None, now);
+        cache.insert(on_peer.clone(), b"there", None, now);
+        cache.insert(key("ab"), b"other", None, now);
+        cache.forget_key("a");
+        assert!(cache.get(&key("a"), now).is_none());
+        assert!(cache.get(&on_peer, now).is_none());
+        assert!(cache.get(&key("ab"), now).is_some());
+        cache.insert(CacheKey::Block(1), b"block", None, now);
+        cache.forget_blocks();
+        assert!(cache.get(&CacheKey::Block(1), now).is_none());
+        assert!(cache.get(&key("ab"), now).is_some());
+        cache.clear();
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 3, entries: 0, bytes: 0 });
+    }
+}
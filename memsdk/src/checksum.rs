@@ -0,0 +1,82 @@
+//! Digests a node records for the data it stores (`memnode --checksum-algo`).
+//!
+//! BLAKE3 is the default and is what [`content_id`](crate::content_id) is
+//! derived from. SHA-256 is there for interop with systems that expect it,
+//! such as S3 checksums. Whichever the node used is reported with the digest,
+//! so a reader never has to guess.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgo {
+    #[default]
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgo {
+    /// The 32-byte digest of `data`.
+    pub fn digest(self, data: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgo::Blake3 => *blake3::hash(data).as_bytes(),
+            HashAlgo::Sha256 => Sha256::digest(data).into(),
+        }
+    }
+
+    /// The digest of `data`, tagged with this algorithm.
+    pub fn checksum(self, data: &[u8]) -> Checksum {
+        Checksum { algo: self, digest: hex::encode(self.digest(data)) }
+    }
+}
+
+impl std::fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HashAlgo::Blake3 => "blake3",
+            HashAlgo::Sha256 => "sha256",
+        })
+    }
+}
+
+impl std::str::FromStr for HashAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "blake3" => Ok(HashAlgo::Blake3),
+            "sha256" => Ok(HashAlgo::Sha256),
+            _ => Err(format!("unknown hash algorithm '{}' (expected blake3 or sha256)", s)),
+        }
+    }
+}
+
+/// A digest of a block's data and the algorithm that produced it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algo: HashAlgo,
+    /// Lowercase hex.
+    pub digest: String,
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algo, self.digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let data = b"abc";
+        assert_eq!(HashAlgo::Sha256.checksum(data).digest, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(HashAlgo::Blake3.checksum(data).digest, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        assert_eq!("SHA-256".parse::<HashAlgo>(), Ok(HashAlgo::Sha256));
+        assert!("md5".parse::<HashAlgo>().is_err());
+        assert_eq!(HashAlgo::Sha256.checksum(data).to_string(), format!("sha256:{}", HashAlgo::Sha256.checksum(data).digest));
+    }
+}
@@ -0,0 +1,167 @@
+//! Client-side encryption of block data, so peers hosting a block only ever
+//! see ciphertext.
+//!
+//! Encrypted payloads start with [`MAGIC`], followed by records of
+//! `nonce (12 bytes) | ciphertext length (u32 BE) | ciphertext`. Each record is
+//! sealed with ChaCha20-Poly1305 under a random nonce; its index and whether it
+//! is the last record are bound in as associated data, so reordered, dropped or
+//! truncated records fail to decrypt. `store`/`set` produce a single final
+//! record; streams produce one record per chunk and end with an empty one.
+
+use anyhow::{bail, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Marks encrypted payloads; loading anything else with a key fails loudly.
+pub const MAGIC: &[u8; 4] = b"MCE\x01";
+
+const NONCE_LEN: usize = 12;
+const LEN_LEN: usize = 4;
+const KDF_CONTEXT: &str = "memcloud 2025-01 client-side encryption passphrase";
+/// Extra hashing rounds that make guessing passphrases from stolen blocks slower.
+const KDF_ROUNDS: u32 = 100_000;
+
+/// Whether `data` carries the encryption header.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Derive a 32-byte key from a passphrase. Deterministic (no salt), so the
+/// same passphrase decrypts on any machine.
+pub fn derive_key(passphrase: &[u8]) -> [u8; 32] {
+    let mut key = blake3::derive_key(KDF_CONTEXT, passphrase);
+    for _ in 0..KDF_ROUNDS {
+        key = *blake3::keyed_hash(&key, passphrase).as_bytes();
+    }
+    key
+}
+
+#[derive(Clone)]
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { aead: ChaCha20Poly1305::new(Key::from_slice(&key)) }
+    }
+
+    /// Encrypt a whole payload.
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        self.seal_record(&mut out, 0, data, true);
+        out
+    }
+
+    /// Decrypt a payload produced by [`Cipher::encrypt`] or [`StreamSealer`].
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let Some(mut rest) = data.strip_prefix(MAGIC.as_slice()) else {
+            bail!("Data is not encrypted (missing encryption header)");
+        };
+        let mut plain = Vec::with_capacity(rest.len());
+        let mut index = 0u64;
+        loop {
+            if rest.len() < NONCE_LEN + LEN_LEN {
+                bail!("Encrypted data is truncated");
+            }
+            let (nonce, tail) = rest.split_at(NONCE_LEN);
+            let (len, tail) = tail.split_at(LEN_LEN);
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            if tail.len() < len {
+                bail!("Encrypted data is truncated");
+            }
+            let (ciphertext, tail) = tail.split_at(len);
+            let last = tail.is_empty();
+            let record = self.aead
+                .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &record_aad(index, last) })
+                .map_err(|_| anyhow::anyhow!("Decryption failed (wrong key or corrupted data)"))?;
+            plain.extend_from_slice(&record);
+            if last {
+                return Ok(plain);
+            }
+            rest = tail;
+            index += 1;
+        }
+    }
+
+    fn seal_record(&self, out: &mut Vec<u8>, index: u64, data: &[u8], last: bool) {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.aead
+            .encrypt(&nonce, Payload { msg: data, aad: &record_aad(index, last) })
+            .expect("ChaCha20-Poly1305 encryption cannot fail for in-memory buffers");
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        out.extend_from_slice(&ciphertext);
+    }
+}
+
+fn record_aad(index: u64, last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = last as u8;
+    aad
+}
+
+/// Encrypts a stream chunk by chunk; the pieces concatenate to one payload.
+pub struct StreamSealer<'a> {
+    cipher: &'a Cipher,
+    index: u64,
+}
+
+impl<'a> StreamSealer<'a> {
+    pub fn new(cipher: &'a Cipher) -> Self {
+        Self { cipher, index: 0 }
+    }
+
+    pub fn seal(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.next_record(chunk, false)
+    }
+
+    /// The empty record that ends the stream; nothing may be sealed after it.
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.next_record(&[], true)
+    }
+
+    fn next_record(&mut self, chunk: &[u8], last: bool) -> Vec<u8> {
+        let mut out = if self.index == 0 { MAGIC.to_vec() } else { Vec::new() };
+        self.cipher.seal_record(&mut out, self.index, chunk, last);
+        self.index += 1;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_and_tampering() {
+        let cipher = Cipher::new([7u8; 32]);
+        let sealed = cipher.encrypt(b"secret payload");
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), b"secret payload");
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.decrypt(&tampered).is_err());
+        assert!(Cipher::new([8u8; 32]).decrypt(&sealed).is_err());
+        assert!(cipher.decrypt(b"plain text").is_err());
+    }
+
+    #[test]
+    fn test_stream_records_cannot_be_truncated() {
+        let cipher = Cipher::new(derive_key(b"correct horse"));
+        let mut sealer = StreamSealer::new(&cipher);
+        let mut payload = sealer.seal(b"first ");
+        let first_len = payload.len();
+        payload.extend(sealer.seal(b"second"));
+        let before_end = payload.len();
+        payload.extend(sealer.finish());
+        assert_eq!(cipher.decrypt(&payload).unwrap(), b"first second");
+
+        // Dropping the end marker or the last chunk is detected
+        assert!(cipher.decrypt(&payload[..before_end]).is_err());
+        assert!(cipher.decrypt(&payload[..first_len]).is_err());
+    }
+}
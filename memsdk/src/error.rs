@@ -0,0 +1,261 @@
+//! Errors returned by [`MemCloudClient`](crate::MemCloudClient).
+//!
+//! The node tags each error response with an [`ErrorCode`], and the client
+//! turns it into the matching [`MemCloudError`] variant, so callers can tell a
+//! missing key from a full node without reading the message. Responses from
+//! nodes that predate the code are classified by their message instead.
+//! `MemCloudError` is a `std::error::Error`, so `?` still converts it into
+//! `anyhow::Error` where that is more convenient.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Machine-readable kind of an `SdkResponse::Error`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// No such block, key, stream, region or migration.
+    NotFound,
+    /// The data does not fit in the node's memory or a peer's quota.
+    QuotaExceeded,
+    /// The peer is not connected or did not answer.
+    PeerUnreachable,
+    /// The connection has not authenticated with the node's token.
+    Unauthorized,
+    /// The connection's access level does not allow the command (a read-only
+    /// endpoint or token sending a write or admin command).
+    PermissionDenied,
+    /// The node was started read-only and refuses commands that change data.
+    ReadOnly,
+    /// The command itself is malformed or its arguments are out of range.
+    InvalidArg,
+    /// The name the command would bind is taken, as by a rename onto a key
+    /// that is set without `overwrite`.
+    Conflict,
+    /// Any other failure on the node, and codes this client does not know.
+    #[serde(other)]
+    Internal,
+}
+
+impl ErrorCode {
+    /// Best guess at the code for a node error message. The node tags its
+    /// responses with this; clients fall back to it for untagged ones.
+    pub fn classify(msg: &str) -> Self {
+        let lower = msg.to_lowercase();
+        let has = |needle: &str| lower.contains(needle);
+        if lower == "unauthorized" {
+            ErrorCode::Unauthorized
+        } else if lower.starts_with("permission denied") {
+            ErrorCode::PermissionDenied
+        } else if has("is read-only") {
+            ErrorCode::ReadOnly
+        } else if (lower.starts_with("peer") && (has("not found") || has("not connected") || has("disconnected")))
+            || has("no suitable peer")
+            || lower.starts_with("recv error")
+            || lower.starts_with("timeout waiting for")
+        {
+            ErrorCode::PeerUnreachable
+        } else if has("quota exceed") || has("sufficient quota") || has("out of memory") || has("cannot fit") || has("too many open streams") {
+            ErrorCode::QuotaExceeded
+        } else if has("already exists") {
+            ErrorCode::Conflict
+        } else if has("not found") || has("not stored on this node") {
+            ErrorCode::NotFound
+        } else if lower.starts_with("invalid") || has("must be") || has("not both") || has("is ambiguous") {
+            ErrorCode::InvalidArg
+        } else {
+            ErrorCode::Internal
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MemCloudError {
+    NotFound(String),
+    QuotaExceeded(String),
+    PeerUnreachable(String),
+    Unauthorized(String),
+    /// Authenticated, but with an access level too low for the command.
+    PermissionDenied(String),
+    /// The node is read-only and refused a command that changes data.
+    ReadOnly(String),
+    InvalidArgument(String),
+    /// The name the command would bind is already taken.
+    Conflict(String),
+    /// The node is under memory pressure. Unlike other failures it is worth
+    /// retrying later, possibly against another node.
+    Overloaded(String),
+    /// No response arrived within the client's timeout (see `MemCloudClient::with_timeout`).
+    Timeout(Duration),
+    /// The node sent something this client does not understand, or the
+    /// connection is out of step after a timeout.
+    Protocol(String),
+    /// Data loaded through an encrypting client could not be decrypted.
+    Encryption(String),
+    /// A value could not be turned into JSON by `set_json`, or what
+    /// `get_json` loaded is not JSON of the type asked for.
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    /// Any other error reported by the node.
+    Server(String),
+}
+
+pub type Result<T> = std::result::Result<T, MemCloudError>;
+
+impl MemCloudError {
+    /// The error for an `SdkResponse::Error`, by its code or, failing that, its message.
+    pub fn from_response(msg: String, code: Option<ErrorCode>) -> Self {
+        match code.unwrap_or_else(|| ErrorCode::classify(&msg)) {
+            ErrorCode::NotFound => MemCloudError::NotFound(msg),
+            ErrorCode::QuotaExceeded => MemCloudError::QuotaExceeded(msg),
+            ErrorCode::PeerUnreachable => MemCloudError::PeerUnreachable(msg),
+            ErrorCode::Unauthorized => MemCloudError::Unauthorized(msg),
+            ErrorCode::PermissionDenied => MemCloudError::PermissionDenied(msg),
+            ErrorCode::ReadOnly => MemCloudError::ReadOnly(msg),
+            ErrorCode::InvalidArg => MemCloudError::InvalidArgument(msg),
+            ErrorCode::Conflict => MemCloudError::Conflict(msg),
+            ErrorCode::Internal => MemCloudError::Server(msg),
+        }
+    }
+
+    pub(crate) fn unexpected(command: &str) -> Self {
+        MemCloudError::Protocol(format!("Unexpected response to {}", command))
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, MemCloudError::NotFound(_))
+    }
+
+    /// Whether the same command may succeed if retried later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, MemCloudError::Overloaded(_) | MemCloudError::Timeout(_) | MemCloudError::PeerUnreachable(_))
+    }
+}
+
+impl std::fmt::Display for MemCloudError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemCloudError::NotFound(msg)
+            | MemCloudError::QuotaExceeded(msg)
+            | MemCloudError::PeerUnreachable(msg)
+            | MemCloudError::Unauthorized(msg)
+            | MemCloudError::PermissionDenied(msg)
+            | MemCloudError::ReadOnly(msg)
+            | MemCloudError::InvalidArgument(msg)
+            | MemCloudError::Conflict(msg)
+            | MemCloudError::Overloaded(msg)
+            | MemCloudError::Protocol(msg)
+            | MemCloudError::Encryption(msg)
+            | MemCloudError::Server(msg) => f.write_str(msg),
+            MemCloudError::Timeout(after) => write!(f, "Timed out after {:?} waiting for the node", after),
+            MemCloudError::Io(e) => write!(f, "I/O error talking to the node: {}", e),
+            MemCloudError::Json(e) => write!(f, "Invalid JSON value: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MemCloudError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MemCloudError::Io(e) => Some(e),
+            MemCloudError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MemCloudError {
+    fn from(e: std::io::Error) -> Self {
+        MemCloudError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MemCloudError {
+    fn from(e: serde_json::Error) -> Self {
+        MemCloudError::Json(e)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for MemCloudError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        MemCloudError::Protocol(format!("Failed to encode command: {}", e))
+    }
+}
+
+impl From<rmp_serde::decode::Error> for MemCloudError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        MemCloudError::Protocol(format!("Failed to decode response: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_errors_map_to_variants() {
+        let cases = [
+            ("Block not found", ErrorCode::NotFound),
+            ("Key not found", ErrorCode::NotFound),
+            ("Stream ID 4 not found or already closed", ErrorCode::NotFound),
+            ("Migration 9 not found", ErrorCode::NotFound),
+            ("Block 12 is not stored on this node", ErrorCode::NotFound),
+            ("Quota exceeded", ErrorCode::QuotaExceeded),
+            ("Quota exceeds node memory limit (1024)", ErrorCode::QuotaExceeded),
+            ("No peer with sufficient quota (need 10, best available 2)", ErrorCode::QuotaExceeded),
+            ("Out of Memory: Cache allocation failed", ErrorCode::QuotaExceeded),
+            ("Stream of 9 bytes cannot fit in this node's 8 byte capacity", ErrorCode::QuotaExceeded),
+            ("Too many open streams on this connection (limit 8)", ErrorCode::QuotaExceeded),
+            ("Peer not found", ErrorCode::PeerUnreachable),
+            ("Peer 'laptop' not found", ErrorCode::PeerUnreachable),
+            ("Peer not found: laptop", ErrorCode::PeerUnreachable),
+            ("Peer abc not connected", ErrorCode::PeerUnreachable),
+            ("No suitable peer found for remote storage", ErrorCode::PeerUnreachable),
+            ("Timeout waiting for block data", ErrorCode::PeerUnreachable),
+            ("Peer disconnected before sending the block data", ErrorCode::PeerUnreachable),
+            ("unauthorized", ErrorCode::Unauthorized),
+            ("Permission denied: Flush needs admin access; this connection has read", ErrorCode::PermissionDenied),
+            ("Node is read-only", ErrorCode::ReadOnly),
+            ("Invalid address format", ErrorCode::InvalidArg),
+            ("Threshold must be at least 1 MB", ErrorCode::InvalidArg),
+            ("Peer '3f' is ambiguous: matches a (3f01...), b (3f02...)", ErrorCode::InvalidArg),
+            ("Peer 'laptp' not found; did you mean laptop (3f01...)?", ErrorCode::PeerUnreachable),
+            ("Key 'b' already exists", ErrorCode::Conflict),
+            ("Content hash collision: block 1 holds different data", ErrorCode::Internal),
+        ];
+        let code_of = |e: &MemCloudError| match e {
+            MemCloudError::NotFound(_) => ErrorCode::NotFound,
+            MemCloudError::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+            MemCloudError::PeerUnreachable(_) => ErrorCode::PeerUnreachable,
+            MemCloudError::Unauthorized(_) => ErrorCode::Unauthorized,
+            MemCloudError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            MemCloudError::ReadOnly(_) => ErrorCode::ReadOnly,
+            MemCloudError::InvalidArgument(_) => ErrorCode::InvalidArg,
+            MemCloudError::Conflict(_) => ErrorCode::Conflict,
+            MemCloudError::Server(_) => ErrorCode::Internal,
+            other => panic!("unexpected variant {:?}", other),
+        };
+        for (msg, expected) in cases {
+            assert_eq!(ErrorCode::classify(msg), expected, "{}", msg);
+            // Untagged, as from an older node, and tagged as the node sends it
+            for code in [None, Some(expected)] {
+                let err = MemCloudError::from_response(msg.to_string(), code);
+                assert_eq!(code_of(&err), expected, "{}", msg);
+                assert_eq!(err.to_string(), msg);
+            }
+        }
+
+        // On the wire the code is a snake_case string; unknown ones decode as internal
+        let wire = |code: &str| rmp_serde::to_vec(code).unwrap();
+        assert_eq!(rmp_serde::to_vec(&ErrorCode::NotFound).unwrap(), wire("not_found"));
+        assert_eq!(rmp_serde::to_vec(&ErrorCode::InvalidArg).unwrap(), wire("invalid_arg"));
+        assert_eq!(rmp_serde::to_vec(&ErrorCode::ReadOnly).unwrap(), wire("read_only"));
+        assert_eq!(rmp_serde::from_slice::<ErrorCode>(&wire("some_future_code")).unwrap(), ErrorCode::Internal);
+
+        // An explicit code wins over the message
+        let err = MemCloudError::from_response("gone".to_string(), Some(ErrorCode::NotFound));
+        assert!(err.is_not_found());
+        let err: anyhow::Error = MemCloudError::Timeout(Duration::from_secs(1)).into();
+        assert!(matches!(err.downcast_ref::<MemCloudError>(), Some(MemCloudError::Timeout(_))));
+    }
+}
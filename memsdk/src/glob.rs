@@ -0,0 +1,284 @@
+//! Small glob matcher used for key patterns.
+//!
+//! Supported syntax:
+//! - `*` matches any run of characters (including none)
+//! - `?` matches exactly one character
+//! - `[abc]`, `[a-z]`, `[!a-z]` / `[^a-z]` character classes
+//! - `\x` matches `x` literally (e.g. `\*`, `\?`, `\[`)
+//!
+//! Matching works on `char`s, so `?` consumes one unicode scalar, not one byte.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Any,
+    Star,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// A pre-compiled glob pattern.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self { tokens: tokenize(pattern) }
+    }
+
+    /// True if the pattern has no wildcards, i.e. it only matches itself.
+    pub fn is_literal(&self) -> bool {
+        self.tokens.iter().all(|t| matches!(t, Token::Literal(_)))
+    }
+
+    /// The literal text for patterns where `is_literal()` holds (escapes removed).
+    pub fn literal(&self) -> Option<String> {
+        self.tokens.iter().map(|t| match t {
+            Token::Literal(c) => Some(*c),
+            _ => None,
+        }).collect()
+    }
+
+    /// The literal text before the first wildcard, which every match starts with.
+    pub fn literal_prefix(&self) -> String {
+        self.tokens.iter().map_while(|t| match t {
+            Token::Literal(c) => Some(*c),
+            _ => None,
+        }).collect()
+    }
+
+    /// True if the pattern is only stars and so matches everything.
+    pub fn matches_all(&self) -> bool {
+        !self.tokens.is_empty() && self.tokens.iter().all(|t| *t == Token::Star)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let tokens = &self.tokens;
+
+        let (mut t, mut p) = (0, 0);
+        // Position to resume from when a later token fails after a '*'
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            match tokens.get(p) {
+                Some(Token::Star) => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                Some(tok) if token_matches(tok, text[t]) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((star_p, star_t)) => {
+                    // Let the last '*' swallow one more character and retry
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            }
+        }
+
+        tokens[p..].iter().all(|tok| *tok == Token::Star)
+    }
+}
+
+/// Convenience wrapper for one-off matches.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    Glob::new(pattern).is_match(text)
+}
+
+fn token_matches(tok: &Token, c: char) -> bool {
+    match tok {
+        Token::Literal(l) => *l == c,
+        Token::Any => true,
+        Token::Star => false,
+        Token::Class { negated, ranges } => {
+            let hit = ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi);
+            hit != *negated
+        }
+    }
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[i + 1]));
+                i += 2;
+            }
+            '*' => {
+                // Collapse runs of stars; they are equivalent to a single one
+                if tokens.last() != Some(&Token::Star) {
+                    tokens.push(Token::Star);
+                }
+                i += 1;
+            }
+            '?' => {
+                tokens.push(Token::Any);
+                i += 1;
+            }
+            '[' => match parse_class(&chars, i) {
+                Some((tok, next)) => {
+                    tokens.push(tok);
+                    i = next;
+                }
+                None => {
+                    // Unterminated class: treat '[' literally
+                    tokens.push(Token::Literal('['));
+                    i += 1;
+                }
+            },
+            c => {
+                tokens.push(Token::Literal(c));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+// Parses a class starting at chars[start] == '['. Returns the token and the index after ']'.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        // A ']' right after the opening bracket is a literal member
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        let lo = if c == '\\' {
+            i += 1;
+            *chars.get(i)?
+        } else {
+            c
+        };
+        i += 1;
+
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|c| *c != ']') {
+            let hi = if chars[i + 1] == '\\' {
+                i += 1;
+                *chars.get(i + 1)?
+            } else {
+                chars[i + 1]
+            };
+            ranges.push((lo, hi));
+            i += 2;
+        } else {
+            ranges.push((lo, lo));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_patterns() {
+        assert!(glob_match("user", "user"));
+        assert!(!glob_match("user", "users"));
+        assert!(!glob_match("user", "use"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+        assert!(Glob::new("plain-key").is_literal());
+        assert!(!Glob::new("key*").is_literal());
+        assert_eq!(Glob::new("user:\\*:?x").literal_prefix(), "user:*:");
+        assert_eq!(Glob::new("*x").literal_prefix(), "");
+    }
+
+    #[test]
+    fn test_star_positions() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("user:*", "user:42"));
+        assert!(glob_match("*:session", "user:42:session"));
+        assert!(glob_match("*42*", "user:42:session"));
+        assert!(glob_match("user:*:session", "user:42:session"));
+        assert!(glob_match("user:*:session", "user::session"));
+        assert!(!glob_match("user:*:session", "user:42:sessions"));
+        assert!(!glob_match("user:*:session", "admin:42:session"));
+    }
+
+    #[test]
+    fn test_multiple_stars() {
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(glob_match("a*b*c", "abbbc"));
+        assert!(!glob_match("a*b*c", "acb"));
+        assert!(glob_match("**", "x"));
+        assert!(glob_match("a**b", "ab"));
+        assert!(glob_match("*a*a*a*", "banana-a"));
+        assert!(!glob_match("*a*a*a*", "banan"));
+        assert!(Glob::new("***").matches_all());
+    }
+
+    #[test]
+    fn test_question_mark() {
+        assert!(glob_match("log-2024-0?-*", "log-2024-03-17"));
+        assert!(!glob_match("log-2024-0?-*", "log-2024-10-17"));
+        assert!(glob_match("???", "abc"));
+        assert!(!glob_match("???", "ab"));
+        assert!(!glob_match("???", "abcd"));
+        assert!(glob_match("a?c*", "abcdef"));
+    }
+
+    #[test]
+    fn test_character_classes() {
+        assert!(glob_match("file[0-9]", "file7"));
+        assert!(!glob_match("file[0-9]", "fileA"));
+        assert!(glob_match("[abc]x", "bx"));
+        assert!(!glob_match("[abc]x", "dx"));
+        assert!(glob_match("[!abc]x", "dx"));
+        assert!(glob_match("[^abc]x", "dx"));
+        assert!(!glob_match("[!abc]x", "ax"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[a-]", "-"));
+        // Unterminated class is a literal '['
+        assert!(glob_match("[abc", "[abc"));
+    }
+
+    #[test]
+    fn test_escaped_literals() {
+        assert!(glob_match("a\\*b", "a*b"));
+        assert!(!glob_match("a\\*b", "aXb"));
+        assert!(glob_match("what\\?", "what?"));
+        assert!(!glob_match("what\\?", "whatX"));
+        assert!(glob_match("\\[x\\]", "[x]"));
+        assert!(glob_match("*\\**", "has*star"));
+        assert!(!glob_match("*\\**", "no star"));
+        let g = Glob::new("a\\*b");
+        assert!(g.is_literal());
+        assert_eq!(g.literal().as_deref(), Some("a*b"));
+    }
+
+    #[test]
+    fn test_unicode_keys() {
+        assert!(glob_match("ключ:*", "ключ:значение"));
+        assert!(glob_match("日本?", "日本語"));
+        assert!(!glob_match("日本?", "日本"));
+        assert!(glob_match("*🚀*", "launch🚀now"));
+        assert!(glob_match("?", "é"));
+        assert!(glob_match("[α-ω]", "λ"));
+    }
+}
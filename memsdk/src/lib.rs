@@ -1,15 +1,63 @@
+pub mod block_id;
 pub mod c_api;
+pub mod cache;
+pub mod checksum;
+pub mod crypto;
+pub mod error;
+pub mod glob;
 
-use serde::{Serialize, Deserialize};
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
 #[cfg(unix)]
 use tokio::net::UnixStream;
-#[cfg(windows)]
 use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+pub use error::{ErrorCode, MemCloudError, Result};
+pub use block_id::{BlockIdError, ShortId};
+pub use cache::CacheStats;
+pub use checksum::{Checksum, HashAlgo};
+use futures::Stream;
+use std::collections::{HashMap, VecDeque};
 
+/// Keys fetched per round trip by `MemCloudClient::scan`.
+pub const SCAN_PAGE_SIZE: u32 = 256;
 
-pub fn parse_size(s: &str) -> Result<u64> {
+/// Most keys a `ListKeys` without a limit returns; the first ones in sorted
+/// order, with `truncated` set on the reply when there were more.
+pub const LIST_KEYS_LIMIT: u32 = 10_000;
+
+/// Overrides where clients look for the node's unix socket.
+pub const SOCKET_ENV: &str = "MEMCLOUD_SOCKET";
+/// Overrides `~/.memcloud` as the directory for a node's persisted state.
+pub const DATA_DIR_ENV: &str = "MEMCLOUD_HOME";
+/// Name of a node's RPC socket inside its data directory.
+pub const SOCKET_FILE: &str = "memcloud.sock";
+/// Where nodes listened before sockets moved into the per-user data directory.
+pub const LEGACY_SOCKET: &str = "/tmp/memcloud.sock";
+/// Where a node's TCP RPC listener binds by default. Windows has no unix
+/// sockets, so clients there connect here unless told otherwise.
+pub const DEFAULT_RPC_ADDR: &str = "127.0.0.1:7070";
+
+/// The unix socket a client connects to by default: `$MEMCLOUD_SOCKET`, else
+/// `memcloud.sock` in `$MEMCLOUD_HOME` or `~/.memcloud` if a node made one
+/// there, else the shared `/tmp/memcloud.sock` older nodes use. On Windows
+/// it is `$MEMCLOUD_SOCKET` or else `tcp://` [`DEFAULT_RPC_ADDR`].
+pub fn default_socket_path() -> String {
+    if let Some(path) = std::env::var_os(SOCKET_ENV).filter(|p| !p.is_empty()) {
+        return path.to_string_lossy().into_owned();
+    }
+    if cfg!(windows) {
+        return format!("{}{}", TCP_ENDPOINT_PREFIX, DEFAULT_RPC_ADDR);
+    }
+    let dir = std::env::var_os(DATA_DIR_ENV).filter(|d| !d.is_empty()).map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".memcloud")));
+    match dir.map(|dir| dir.join(SOCKET_FILE)) {
+        Some(path) if path.exists() => path.to_string_lossy().into_owned(),
+        _ => LEGACY_SOCKET.to_string(),
+    }
+}
+
+
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
     let s = s.trim().to_lowercase();
     if s.is_empty() {
         return Ok(0);
@@ -30,6 +78,23 @@ pub fn parse_size(s: &str) -> Result<u64> {
 
 pub type BlockId = u64;
 
+/// Client metadata stored with a value, such as a content type or source.
+/// Tags are not encrypted, even by an encrypting client.
+pub type Tags = HashMap<String, String>;
+
+/// ID of a content-addressed block: the first 8 bytes of the BLAKE3 hash of
+/// its data, little-endian.
+///
+/// Truncating to 64 bits makes collisions possible: among `n` distinct blocks
+/// the chance of any two sharing an ID is about `n² / 2^65` (roughly one in
+/// 37 million for a million blocks). The node compares bytes before treating a
+/// store as a duplicate, so a collision fails the store instead of returning
+/// someone else's data.
+pub fn content_id(data: &[u8]) -> BlockId {
+    let hash = blake3::hash(data);
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
 // Helper for string serialization
 mod string_id {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -51,42 +116,313 @@ mod string_id {
     }
 }
 
+// The same for a list of ids
+mod string_ids {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use super::BlockId;
+
+    pub fn serialize<S>(ids: &[BlockId], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(ids.iter().map(|id| id.to_string()))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<BlockId>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let ids = Vec::<String>::deserialize(deserializer)?;
+        ids.iter().map(|s| s.parse().map_err(serde::de::Error::custom)).collect()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Durability {
     Pinned,
     Cache,
+    /// Evictable like `Cache`, and removed once `secs` have passed since it was stored.
+    Ttl { secs: u64 },
+}
+
+impl Durability {
+    /// Whether the node may drop the block under memory pressure.
+    pub fn is_evictable(&self) -> bool {
+        !matches!(self, Durability::Pinned)
+    }
+}
+
+/// Priority of blocks stored without one. Under memory pressure a node evicts
+/// evictable blocks of the lowest priority first, least recently used first
+/// within a priority, so when every block has this one eviction is plain LRU.
+pub const DEFAULT_PRIORITY: u8 = 128;
+
+fn default_priority() -> u8 {
+    DEFAULT_PRIORITY
+}
+
+/// Key the client adds to a command's map with its trace id. Nodes that do
+/// not know it ignore it, like any unknown field.
+pub const TRACE_ID_FIELD: &str = "trace_id";
+
+/// A fresh id for tying a command to the node's logs of it.
+pub fn new_trace_id() -> String {
+    format!("{:016x}", uuid::Uuid::new_v4().as_u64_pair().0)
+}
+
+/// The parts of a command frame that say what it is, read without decoding
+/// the rest: its name and the trace id the client sent, if any.
+#[derive(Deserialize, Debug)]
+pub struct CommandTrace {
+    pub cmd: String,
+    #[serde(default)]
+    pub trace_id: Option<String>,
+    // The wrapped command of a `Pipelined` one
+    #[serde(default)]
+    command: Option<Box<CommandTrace>>,
+}
+
+impl CommandTrace {
+    /// The command's name, looking through `Pipelined`.
+    pub fn name(&self) -> &str {
+        self.command.as_deref().map_or(&self.cmd, CommandTrace::name)
+    }
+}
+
+// A named command map is re-headed with one more entry, `trace_id`, sent
+// after it: writing `head`, `bytes[skip..]` and `tail` gives the traced
+// command without copying its payload.
+fn trace_frame(bytes: &[u8], trace_id: &str) -> Result<(Vec<u8>, usize, Vec<u8>)> {
+    let bad = |e: &dyn std::fmt::Display| MemCloudError::Protocol(format!("Bad command encoding: {}", e));
+    let mut rest = bytes;
+    let entries = rmp::decode::read_map_len(&mut rest).map_err(|e| bad(&e))?;
+    let skip = bytes.len() - rest.len();
+    let mut head = Vec::with_capacity(5);
+    rmp::encode::write_map_len(&mut head, entries + 1).map_err(|e| bad(&e))?;
+    let mut tail = Vec::with_capacity(TRACE_ID_FIELD.len() + trace_id.len() + 4);
+    rmp::encode::write_str(&mut tail, TRACE_ID_FIELD).map_err(|e| bad(&e))?;
+    rmp::encode::write_str(&mut tail, trace_id).map_err(|e| bad(&e))?;
+    Ok((head, skip, tail))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "cmd")]
 pub enum SdkCommand {
-    Store { #[serde(with = "serde_bytes")] data: Vec<u8>, durability: Option<Durability> },
-    StoreRemote { #[serde(with = "serde_bytes")] data: Vec<u8>, target: Option<String>, durability: Option<Durability> },
+    /// With `content_addressed` the block ID is derived from the data (see
+    /// [`content_id`]) and storing the same bytes again returns the same ID;
+    /// each such store holds a reference, and `Free` removes the block only
+    /// when the last one is released.
+    /// `priority` ranks the block for eviction, [`DEFAULT_PRIORITY`] without it;
+    /// the same goes for `Set` and `StreamFinish`.
+    Store { #[serde(with = "serde_bytes")] data: Vec<u8>, durability: Option<Durability>, #[serde(default)] content_addressed: bool, #[serde(default)] tags: Tags, #[serde(default)] priority: Option<u8> },
+    /// With `fallback_local` the node keeps the block itself, under the same
+    /// durability, when no peer takes it.
+    StoreRemote { #[serde(with = "serde_bytes")] data: Vec<u8>, target: Option<String>, durability: Option<Durability>, #[serde(default)] fallback_local: bool },
     Load { #[serde(with = "string_id")] id: BlockId },
+    /// Frees a local block, or releases one stored on a peer from there.
     Free { #[serde(with = "string_id")] id: BlockId },
+    /// Free each of `ids` as `Free` would and, with `pattern`, the blocks of
+    /// the keys matching it, removing those keys. Ids the node does not know
+    /// are counted as missing instead of failing the rest. Answered with `Freed`.
+    FreeMany { #[serde(with = "string_ids")] ids: Vec<BlockId>, #[serde(default)] pattern: Option<String> },
+    /// Change the durability of a block stored on this node, e.g. to pin a
+    /// cache block without storing it again. Answered with `Success`.
+    SetDurability { #[serde(with = "string_id")] id: BlockId, durability: Durability },
+    /// Change the eviction priority of a block on this node, or of the block
+    /// a local key names. Answered with `Success`.
+    SetPriority { id_or_key: BlockOrKey, priority: u8 },
     ListPeers,
-    Connect { addr: String, quota: Option<u64> },
+    /// Answered with `ConnectionStatus`. The node pins the key the peer
+    /// presents on first contact; `accept_new_key` replaces a pinned key the
+    /// peer no longer presents instead of failing with `identity_changed`.
+    Connect { addr: String, quota: Option<u64>, #[serde(default)] accept_new_key: bool },
+    /// Dial every trusted device that is not connected, at the address this
+    /// node last dialed it on, offering each `quota`. Answered with
+    /// `ReconnectReport`, one outcome per trusted device.
+    ReconnectTrusted { quota: Option<u64> },
     UpdatePeerQuota { peer_id: String, quota: u64 },
-    Disconnect { peer_id: String },
-    Set { key: String, #[serde(with = "serde_bytes")] data: Vec<u8>, target: Option<String>, durability: Option<Durability> },
+    /// Let a connected, trusted peer flush all of this node's data, or take
+    /// that back. Without it a peer's flush only removes the blocks it stored
+    /// here. Kept with the trust record. Answered with `Success`.
+    AllowRemoteFlush { peer_id: String, allow: bool },
+    /// With `drain` the node first takes back every block it stored on the
+    /// peer, moving each to another peer with room or else storing it here,
+    /// and only then says Bye. That is answered with `Drain` right away; poll
+    /// `DrainStatus` for progress. `force` cuts the link at once, abandoning a
+    /// drain in progress.
+    Disconnect { peer_id: String, #[serde(default)] drain: bool, #[serde(default)] force: bool },
+    /// Progress of the drain of a peer, by name or id. Answered with `Drain`.
+    DrainStatus { peer_id: String },
+    /// Replaces the key's tags with `tags`. Tags are only kept on this node,
+    /// so they cannot be combined with a `target`.
+    Set { key: String, #[serde(with = "serde_bytes")] data: Vec<u8>, target: Option<String>, durability: Option<Durability>, #[serde(default)] tags: Tags, #[serde(default)] priority: Option<u8> },
     Get { key: String, target: Option<String> },
-    ListKeys { pattern: String },
+    /// Add `delta` to the 8-byte little-endian counter at `key` (created at
+    /// `delta` if absent) in one step. Answered with `Counter`.
+    Incr { key: String, delta: i64 },
+    /// Append `data` to the value at `key` (created if absent) in one step.
+    /// Answered with `Appended`.
+    Append { key: String, #[serde(with = "serde_bytes")] data: Vec<u8> },
+    /// Move the value at `from` to `to` without copying it. Fails with
+    /// `Conflict` if `to` is set, unless `overwrite`. Answered with `Success`.
+    Rename { from: String, to: String, #[serde(default)] overwrite: bool, #[serde(default)] target: Option<String> },
+    /// Make `alias` a second name for the value at `key`. The value stays
+    /// stored while either name is set. Fails with `Conflict` if `alias` is
+    /// set. Answered with `Success`.
+    Alias { key: String, alias: String, #[serde(default)] target: Option<String> },
+    /// With `limit` set the reply is a `KeyPage`; `cursor` is the `next_cursor` of the previous page.
+    /// With `include_remote` the connected peers are asked as well and the
+    /// reply is a `KeyLocations`; that cannot be paged.
+    ListKeys {
+        pattern: String,
+        cursor: Option<String>,
+        limit: Option<u32>,
+        #[serde(default)]
+        include_remote: bool,
+    },
+    /// The `top_n` most-read blocks, named or not. Without a window they are
+    /// ranked by lifetime reads; with one, only blocks read in the last
+    /// `window_secs` count and they are ranked by recent reads.
+    HotKeys { top_n: u32, window_secs: Option<u64> },
+    /// Local keys whose value is tagged `key = value`. Answered with `List`, sorted.
+    FindByTag { key: String, value: String },
+    /// Size, mode, key and tags of a block on this node. Answered with `BlockInfo`.
+    BlockInfo { selector: BlockOrKey },
+    /// Every block stored on this node, named or not, by ascending ID and at
+    /// most `LIST_KEYS_LIMIT` per page. Answered with `BlockPage`; `cursor` is
+    /// the `next_cursor` of the previous page.
+    ListBlocks { limit: u32, cursor: Option<String> },
+    /// Move existing data between this node and a peer without it passing
+    /// through the client. Pushes go `to` the named peer (or the least-loaded
+    /// one); pulls come `from` a peer. Unless `keep_local` is set the source
+    /// copy is dropped once the destination has confirmed it. Answered with
+    /// `MigrationStarted`; poll `MigrationStatus` for progress.
+    Migrate { selector: BlockOrKey, to: Option<String>, #[serde(default)] from: Option<String>, keep_local: bool },
+    MigrationStatus { migration_id: u64 },
     Stat,
+    /// The node's once-a-second usage samples from the last `window_secs`
+    /// seconds, oldest first, as far back as it keeps them (five minutes).
+    /// Answered with `StatHistory`.
+    StatHistory { window_secs: u64 },
+    /// What this node keeps on one connected peer, named by name or id.
+    /// Answered with `PeerStat`.
+    PeerStat { peer_id: String },
+    /// How the session with a connected peer, by name or id, was set up and
+    /// what has gone through it. Answered with `PeerSession`.
+    PeerInspect { id_or_name: String },
+    /// How long the node has spent running each kind of command since it
+    /// started, not counting the network. Answered with `Metrics`.
+    Metrics,
+    /// Blocks and memory of this node and of each connected peer, asked of
+    /// the peers at once. Answered with `ClusterStat`.
+    ClusterStat,
+    /// Liveness check; the node answers `Pong` with the result of its self-checks.
+    Ping,
     PollConnection { addr: String },
-    StreamStart { size_hint: Option<u64> },
+    /// Abort an outgoing connection attempt that has not finished yet.
+    CancelConnect { addr: String },
+    /// With a `target` the node passes the upload on to that peer as it
+    /// arrives instead of buffering it until StreamFinish.
+    StreamStart { size_hint: Option<u64>, #[serde(default)] target: Option<String> },
     StreamChunk { stream_id: u64, chunk_seq: u32, #[serde(with = "serde_bytes")] data: Vec<u8> },
-    StreamFinish { stream_id: u64, target: Option<String>, durability: Option<Durability> },
-    Flush { target: Option<String> },
+    StreamFinish { stream_id: u64, target: Option<String>, durability: Option<Durability>, #[serde(default)] priority: Option<u8> },
+    /// Uploads started on the node but not finished or aborted yet.
+    StreamList,
+    StreamAbort { stream_id: u64 },
+    /// Without `pattern`/`durability` this clears everything (original behavior).
+    Flush { target: Option<String>, pattern: Option<String>, durability: Option<Durability> },
+    /// `Flush` every connected peer at once, then this node too with
+    /// `include_local`. The node does the fan-out, so the client only waits
+    /// for one reply. Answered with `FlushReport`; running it again retries
+    /// the peers that failed. Nodes before `FLUSH_CLUSTER_SINCE` lack it.
+    FlushCluster { include_local: bool, pattern: Option<String>, durability: Option<Durability> },
+    /// The node's id, name and release. Answered with `NodeInfo`.
+    NodeInfo,
     // VM Allocation & Paging
     VmAlloc { size: u64 },
     VmFetch { region_id: u64, page_index: u64 },
     VmStore { region_id: u64, page_index: u64, #[serde(with = "serde_bytes")] data: Vec<u8> },
+    /// Read, or with `threshold_mb` set, change the node's default malloc interception threshold.
+    VmConfig { threshold_mb: Option<u64> },
     // Trust & Consent
     TrustList,
-    TrustRemove { key_or_name: String },
+    /// Trust a device by its public key ahead of its first connection.
+    TrustAdd { public_key: String, name: String, note: Option<String> },
+    /// `note` says why; the node logs it.
+    /// With `drain` a connected device is drained before it is disconnected,
+    /// as with `Disconnect`.
+    TrustRemove { key_or_name: String, #[serde(default)] note: Option<String>, #[serde(default)] drain: bool },
+    /// A trusted device with its recent connections. Answered with `TrustInfo`.
+    TrustInfo { key_or_name: String },
     ConsentList,
     ConsentApprove { session_id: String, trust_always: bool },
     ConsentDeny { session_id: String },
+    /// Must be the first command on a node started with `--rpc-token`.
+    Auth { token: String },
+    /// Names the program behind this connection. Data it stores is counted
+    /// under this name in `AppUsage`; connections that never send it count as "unknown".
+    ClientHello { app_name: String, pid: Option<u32> },
+    /// Local memory held per client application.
+    AppUsage,
+    /// Read the node's config file again and apply the settings that can
+    /// change at runtime. Answered with `ConfigReloaded`.
+    ReloadConfig,
+    /// Stop the node the way SIGTERM does: say Bye to peers and exit. Answered
+    /// with `Success` before the node goes down.
+    Shutdown,
+    /// Entries of the node's event journal newer than `since_seq`, or the
+    /// latest ones without it, at most `limit`. `kinds` keeps only those of
+    /// the given [`EVENT_KINDS`]. With `wait_secs` the node waits that long,
+    /// at most `EVENTS_MAX_WAIT_SECS`, for a matching event when there is
+    /// none yet. Answered with `Events`.
+    Events {
+        since_seq: Option<u64>,
+        kinds: Option<Vec<String>>,
+        limit: u64,
+        #[serde(default)]
+        wait_secs: Option<u64>,
+    },
+    /// Run `command` without waiting for earlier commands on the connection. The reply
+    /// is a `Pipelined` response with the same `seq`, possibly out of order.
+    Pipelined { seq: u64, command: Box<SdkCommand> },
+}
+
+impl SdkCommand {
+    /// Whether running the command twice has the same effect as running it
+    /// once: it only reads. A client may send these again after losing the
+    /// connection without knowing whether the node saw them.
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            SdkCommand::Load { .. }
+            | SdkCommand::Get { .. }
+            | SdkCommand::ListKeys { .. }
+            | SdkCommand::HotKeys { .. }
+            | SdkCommand::FindByTag { .. }
+            | SdkCommand::BlockInfo { .. }
+            | SdkCommand::ListBlocks { .. }
+            | SdkCommand::Stat
+            | SdkCommand::StatHistory { .. }
+            | SdkCommand::PeerStat { .. }
+            | SdkCommand::PeerInspect { .. }
+            | SdkCommand::Metrics
+            | SdkCommand::ClusterStat
+            | SdkCommand::NodeInfo
+            | SdkCommand::Ping
+            | SdkCommand::ListPeers
+            | SdkCommand::MigrationStatus { .. }
+            | SdkCommand::DrainStatus { .. }
+            | SdkCommand::StreamList
+            | SdkCommand::AppUsage
+            | SdkCommand::Events { .. }
+            | SdkCommand::VmFetch { .. }
+            | SdkCommand::VmConfig { threshold_mb: None }
+            | SdkCommand::TrustList
+            | SdkCommand::TrustInfo { .. }
+            | SdkCommand::ConsentList => true,
+            SdkCommand::Pipelined { command, .. } => command.is_idempotent(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -94,10 +430,26 @@ pub struct PeerMetadata {
     pub id: String,
     pub name: String,
     pub addr: String,
+    /// Hex-encoded key the peer authenticated with; older nodes omit it.
+    #[serde(default)]
+    pub public_key: String,
     pub total_memory: u64,
     pub used_memory: u64,
     pub quota: u64,
     pub allowed_quota: u64,
+    /// False while the peer is not answering keepalive pings; older nodes omit it.
+    #[serde(default = "default_healthy")]
+    pub healthy: bool,
+    /// Round trip of the last answered keepalive ping; `None` until one is answered.
+    #[serde(default)]
+    pub last_rtt_micros: Option<u64>,
+    /// Unix time in milliseconds the peer last answered a ping (or connected); 0 from older nodes.
+    #[serde(default)]
+    pub last_seen: u64,
+}
+
+fn default_healthy() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -106,6 +458,48 @@ pub struct TrustedDevice {
     pub name: String,
     pub first_seen: u64,
     pub last_approved: u64,
+    /// Unix time of the last authenticated connection; `None` if it never connected.
+    #[serde(default)]
+    pub last_connected: Option<u64>,
+    #[serde(default)]
+    pub total_sessions: u64,
+    /// Why the device was trusted.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Whether the device may flush all of the node's data (`AllowRemoteFlush`).
+    #[serde(default)]
+    pub allow_remote_flush: bool,
+    /// Where the node last dialed the device; `None` if it never did.
+    #[serde(default)]
+    pub last_addr: Option<String>,
+}
+
+/// What `ReconnectTrusted` did for one trusted device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectOutcome {
+    pub name: String,
+    pub public_key: String,
+    /// The address dialed, the device's `last_addr`.
+    pub addr: Option<String>,
+    /// `connected`, `already_connected`, `failed`, or `no_address` for a
+    /// device the node never dialed.
+    pub state: String,
+    /// Why it failed.
+    pub msg: Option<String>,
+}
+
+/// One authenticated connection with a trusted device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrustedConnection {
+    pub at: u64,
+    pub addr: String,
+}
+
+/// A trusted device and its latest connections, oldest first, from `TrustInfo`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrustInfo {
+    pub device: TrustedDevice,
+    pub history: Vec<TrustedConnection>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -113,20 +507,518 @@ pub struct PendingConsent {
     pub session_id: String,
     pub peer_pubkey: String,
     pub peer_name: String,
+    /// Bytes the peer offers to store for this node.
     pub quota: u64,
+    /// Bytes approving lets the peer store on this node; absent from older nodes.
+    #[serde(default)]
+    pub allowed_quota: u64,
     pub created_at: u64,
 }
 
+/// Counts reported by a flush.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushStats {
+    pub blocks_removed: u64,
+    pub bytes_freed: u64,
+}
+
+/// How one node took a `FlushCluster`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FlushOutcome {
+    /// The peer's name, or the node's own for its local flush.
+    pub node: String,
+    pub local: bool,
+    /// What was removed; `None` when the flush failed.
+    pub stats: Option<FlushStats>,
+    pub error: Option<String>,
+}
+
+/// First memnode release that answers `FlushCluster`.
+pub const FLUSH_CLUSTER_SINCE: &str = "0.1.2";
+
+/// Who a node is, from `SdkCommand::NodeInfo`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: String,
+    pub name: String,
+    /// The memnode release it runs.
+    pub version: String,
+}
+
+impl NodeInfo {
+    /// Whether the node runs `min` or a later release. Releases compare by
+    /// their dot-separated numbers; anything else in them is ignored.
+    pub fn version_at_least(&self, min: &str) -> bool {
+        let numbers = |v: &str| -> Vec<u64> {
+            v.split(['.', '-', '+']).map_while(|part| part.parse().ok()).collect()
+        };
+        numbers(&self.version) >= numbers(min)
+    }
+}
+
+/// Counts reported by `Free` and `FreeMany`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FreeStats {
+    /// Blocks freed, here or on the peer holding them.
+    pub count: u64,
+    pub bytes: u64,
+    /// Ids the node had no block or region for.
+    pub missing: u64,
+    /// Blocks on a peer that could not be told to release them; they stay listed.
+    pub failed: u64,
+}
+
+/// What a [`NodeEvent`] is about; the filter values of `SdkCommand::Events`.
+pub const EVENT_KINDS: &[&str] = &["peer", "consent", "trust", "eviction", "quota", "flush", "shutdown"];
+
+/// Longest `wait_secs` a node honours for `SdkCommand::Events`.
+pub const EVENTS_MAX_WAIT_SECS: u64 = 60;
+
+/// One entry of a node's event journal, from `SdkCommand::Events`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeEvent {
+    /// Increases by one with every event the node records.
+    pub seq: u64,
+    /// Unix millis.
+    pub at: u64,
+    /// One of [`EVENT_KINDS`].
+    pub kind: String,
+    /// What happened, e.g. "connected" or "denied".
+    pub action: String,
+    /// The peer or device involved, by name, if any.
+    pub subject: Option<String>,
+    pub bytes: Option<u64>,
+    /// Reason and other details, for people.
+    pub detail: String,
+}
+
+/// Outcome of `update_peer_quota`: what was asked for and what the peer applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUpdate {
+    pub requested: u64,
+    pub applied: u64,
+    /// False if the peer never confirmed (older node); `applied` is then just `requested`.
+    pub acknowledged: bool,
+}
+
+/// Everything a node reports for `Stat`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NodeStats {
+    pub blocks: usize,
+    pub peers: usize,
+    pub memory_usage: usize,
+    pub vm_regions: usize,
+    pub vm_pages_mapped: usize,
+    pub vm_memory_in_use: usize,
+    /// The node's memory limit; 0 if the node is too old to report it.
+    pub total_capacity: u64,
+    pub free_space: u64,
+    pub pinned_bytes: u64,
+    pub cache_bytes: u64,
+    /// Resident memory of the node process; 0 where the node cannot measure it.
+    pub rss_bytes: u64,
+    pub pressure: PressureLevel,
+    /// The node refuses commands that change data; false from older nodes.
+    pub read_only: bool,
+    /// Uploads refused or cut off by a stream limit since the node started.
+    pub streams_rejected: u64,
+    /// Uploads dropped after sending nothing for the idle timeout.
+    pub streams_reaped: u64,
+}
+
+/// One of a node's once-a-second usage samples, from `StatHistory`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatSample {
+    /// Unix time the sample was taken.
+    pub at: u64,
+    pub memory_usage: u64,
+    pub blocks: u64,
+    /// RPC bytes received and sent since the previous sample.
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// What a node keeps on one of its peers, from `PeerStat`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeerStats {
+    /// Bytes stored on the peer.
+    pub used: u64,
+    /// Bytes the peer lets us store there.
+    pub quota: u64,
+    /// Blocks this node offloaded to the peer. Keys set directly on the peer
+    /// are indexed there, so they count towards `used` only.
+    pub blocks_remote: usize,
+}
+
+/// Upper bounds, in microseconds, of the buckets of a [`CommandMetrics`]
+/// histogram. A last bucket holds everything slower.
+pub const LATENCY_BUCKETS_MICROS: [u64; 14] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+];
+
+/// Time a node spent running one kind of command, from `Metrics`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommandMetrics {
+    /// Command name, e.g. "Load".
+    pub command: String,
+    pub count: u64,
+    pub total_micros: u64,
+    pub max_micros: u64,
+    /// Commands per bucket of `LATENCY_BUCKETS_MICROS`, and one more for
+    /// those slower than the last bound.
+    pub buckets: Vec<u64>,
+}
+
+impl CommandMetrics {
+    pub fn mean_micros(&self) -> u64 {
+        self.total_micros.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile (0.0 to 1.0); the
+    /// slowest time seen when that falls past the last bound.
+    pub fn quantile_micros(&self, q: f64) -> u64 {
+        let rank = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return LATENCY_BUCKETS_MICROS.get(i).map_or(self.max_micros, |b| (*b).min(self.max_micros));
+            }
+        }
+        self.max_micros
+    }
+}
+
+/// How a node in a [`ClusterStats`] answered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterNodeStatus {
+    Ok,
+    /// The peer is not connected or did not answer in time.
+    Unreachable,
+    /// The peer runs a version that does not report stats.
+    Unsupported,
+}
+
+/// One node of a [`ClusterStats`]. The counts are zero unless `status` is `Ok`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClusterNodeStat {
+    pub id: String,
+    pub name: String,
+    /// The node that answered the command.
+    pub local: bool,
+    pub status: ClusterNodeStatus,
+    pub blocks: u64,
+    pub memory_usage: u64,
+    pub max_memory: u64,
+    /// Peers connected to that node.
+    pub peers: u64,
+    /// The memnode release it runs, when it said.
+    pub version: Option<String>,
+}
+
+/// The cluster as seen from one node, from `SdkCommand::ClusterStat`: that
+/// node first, then its peers by name. Totals cover the nodes that answered.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClusterStats {
+    pub nodes: Vec<ClusterNodeStat>,
+    pub blocks: u64,
+    pub memory_usage: u64,
+    pub max_memory: u64,
+}
+
+/// The session with a connected peer, from `SdkCommand::PeerInspect`. Holds
+/// no key material; the peer's identity key only appears as a fingerprint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerSession {
+    pub id: String,
+    pub name: String,
+    pub addr: String,
+    /// `blake3:` and the first 16 bytes, in hex, of the BLAKE3 hash of the
+    /// peer's ed25519 public key.
+    pub key_fingerprint: String,
+    /// Handshake protocol version the peer announced.
+    pub protocol_version: u16,
+    /// Algorithms the session was set up with.
+    pub capabilities: Vec<String>,
+    /// Whether this node dialed the peer, rather than the peer this node.
+    pub initiated: bool,
+    /// Unix millis the handshake finished.
+    pub established_at: u64,
+    pub uptime_secs: u64,
+    /// Frames each way. Each frame is sealed under the next nonce of its
+    /// direction, so these are also the nonce counters.
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    /// Encrypted bytes each way, length prefixes included.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Bytes the peer may store on this node, and has.
+    pub allowed_quota: u64,
+    pub used_here: u64,
+    /// Bytes this node may store on the peer, and has.
+    pub quota: u64,
+    pub used_there: u64,
+    pub healthy: bool,
+    pub last_rtt_micros: Option<u64>,
+}
+
+/// How close a node's process is to its RSS limits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PressureLevel {
+    #[default]
+    Normal,
+    /// Over the soft limit: cache blocks are shed and new stores refused.
+    Soft,
+    /// Over the hard limit: new streams are refused as well.
+    Hard,
+}
+
+/// Names existing data: a block id or a key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind")]
+pub enum BlockOrKey {
+    Id { #[serde(with = "string_id")] id: BlockId },
+    Key { key: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Progress of a `Migrate`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub migration_id: u64,
+    pub state: MigrationState,
+    /// The block being moved; a pulled key may get a new id locally.
+    #[serde(with = "string_id")]
+    pub id: BlockId,
+    /// Name of the peer on the other end.
+    pub peer: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub error: Option<String>,
+    /// Failed, but the source copy is intact and the migration can be retried.
+    pub retriable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainState {
+    Running,
+    /// Every block that could be taken back was, and the peer is disconnected.
+    Done,
+    /// Cut short by a forced disconnect.
+    Aborted,
+}
+
+/// A block left on a drained peer, and why.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DrainFailure {
+    #[serde(with = "string_id")]
+    pub id: BlockId,
+    pub error: String,
+}
+
+/// Progress of a `Disconnect` with `drain`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DrainStatus {
+    pub state: DrainState,
+    /// Name of the peer being drained.
+    pub peer: String,
+    /// Blocks found on the peer so far; grows if more are stored there meanwhile.
+    pub blocks_total: u64,
+    pub blocks_done: u64,
+    pub bytes_done: u64,
+    /// Of `blocks_done`, those that went to another peer instead of this node.
+    pub blocks_moved: u64,
+    pub failed: Vec<DrainFailure>,
+}
+
+/// One row of the hot-keys report.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HotBlock {
+    #[serde(with = "string_id")]
+    pub id: BlockId,
+    /// Unset for unnamed blocks such as VM pages and streamed uploads.
+    pub key: Option<String>,
+    /// Reads served to clients of this node (loads, gets, VM page fetches).
+    pub local_hits: u64,
+    /// Reads served to peers.
+    pub peer_hits: u64,
+    pub bytes_served: u64,
+    /// Unix time of the last read.
+    pub last_accessed: u64,
+    /// Recent reads: each counts 1 and halves every minute.
+    pub score: f64,
+}
+
+/// One row of `SdkCommand::ListBlocks`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockSummary {
+    #[serde(with = "string_id")]
+    pub id: BlockId,
+    pub size: u64,
+    pub durability: Durability,
+    /// False for blocks no key points at, such as raw `Store`s.
+    pub has_key: bool,
+    /// Eviction priority; older nodes do not send it and evict by LRU alone.
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+    /// The data carries the header of an encrypting client ([`crypto::is_encrypted`]);
+    /// false from older nodes.
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// A block on the node, from `SdkCommand::BlockInfo`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockInfo {
+    #[serde(with = "string_id")]
+    pub id: BlockId,
+    pub key: Option<String>,
+    pub size: u64,
+    pub durability: Durability,
+    pub tags: Tags,
+    /// Other keys naming the block (`SdkCommand::Alias`), besides `key`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Digest recorded when the block was stored, for content-addressed
+    /// blocks; `None` for others and from older nodes.
+    #[serde(default)]
+    pub checksum: Option<Checksum>,
+    /// Eviction priority; older nodes do not send it and evict by LRU alone.
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+    /// The data carries the header of an encrypting client ([`crypto::is_encrypted`]);
+    /// false from older nodes.
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// Holder name for keys stored on the node that answered a listing.
+pub const LOCAL_HOLDER: &str = "local";
+
+/// A key and the nodes holding it: [`LOCAL_HOLDER`] and peer names.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyLocation {
+    pub key: String,
+    pub holders: Vec<String>,
+}
+
+/// Keys matching a pattern across the node and its peers, sorted by key.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyListing {
+    pub items: Vec<KeyLocation>,
+    /// Peers that had more matching keys than they may send; only part of
+    /// their keys are listed.
+    pub truncated: Vec<String>,
+    /// Peers that did not answer in time and are missing from the listing.
+    pub unanswered: Vec<String>,
+}
+
+/// One application's share of a node's local memory, from `AppUsage`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AppUsage {
+    pub app_name: String,
+    /// Process ID the client reported, if any.
+    pub pid: Option<u32>,
+    pub blocks: u64,
+    pub bytes: u64,
+    /// Blocks currently bound to a key.
+    pub keys: u64,
+}
+
+/// An unfinished streamed upload, as listed by `StreamList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub stream_id: u64,
+    pub bytes_buffered: u64,
+    pub size_hint: Option<u64>,
+    /// Seconds since StreamStart.
+    pub age_secs: u64,
+    /// Peer a forwarded upload is going to; its bytes are not buffered here.
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub bytes_forwarded: u64,
+}
+
+/// A node's answer to `ping`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeHealth {
+    pub uptime_secs: u64,
+    pub healthy: bool,
+    pub details: Vec<String>,
+    pub port: Option<u16>,
+    pub name: Option<String>,
+    pub pid: Option<u32>,
+}
+
+/// Where a `StoreRemote` block ended up.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum StoredLocation {
+    Local,
+    /// On the named peer
+    Remote(String),
+}
+
+impl std::fmt::Display for StoredLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoredLocation::Local => write!(f, "this node"),
+            StoredLocation::Remote(peer) => write!(f, "peer '{}'", peer),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "res")]
 pub enum SdkResponse {
-    Stored { #[serde(with = "string_id")] id: BlockId },
-    Loaded { #[serde(with = "serde_bytes")] data: Vec<u8> },
+    Stored {
+        #[serde(with = "string_id")]
+        id: BlockId,
+        /// Bytes stored; absent from older nodes.
+        #[serde(default)]
+        size: Option<u64>,
+        /// Where a `StoreRemote` put the block; absent for other stores and from older nodes.
+        #[serde(default)]
+        location: Option<StoredLocation>,
+    },
+    Loaded {
+        /// Shared with the block it was read from, so a node can answer without copying it.
+        data: bytes::Bytes,
+        /// Mode the serving node holds the value under; absent from older nodes.
+        #[serde(default)]
+        durability: Option<Durability>,
+    },
     Success,
-    List { items: Vec<String> },
+    List {
+        items: Vec<String>,
+        /// Set when an unpaged listing hit `LIST_KEYS_LIMIT`; absent from older nodes.
+        #[serde(default)]
+        truncated: bool,
+    },
+    KeyPage { items: Vec<String>, next_cursor: Option<String> },
+    KeyLocations { listing: KeyListing },
+    HotKeys { items: Vec<HotBlock> },
+    BlockInfo { info: BlockInfo },
+    BlockPage { items: Vec<BlockSummary>, next_cursor: Option<String> },
+    MigrationStarted { migration_id: u64 },
+    Migration { status: MigrationStatus },
+    Drain { status: DrainStatus },
     PeerList { peers: Vec<PeerMetadata> },
     PeerConnected { metadata: PeerMetadata },
-    Error { msg: String },
+    /// Surfaced to callers as the `MemCloudError` variant for `code`.
+    Error {
+        msg: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+    },
+    /// The node is under memory pressure; surfaced to callers as `MemCloudError::Overloaded`.
+    Overloaded { msg: String },
     Status { 
         blocks: usize, 
         peers: usize, 
@@ -134,131 +1026,671 @@ pub enum SdkResponse {
         vm_regions: usize,
         vm_pages_mapped: usize,
         vm_memory_in_use: usize,
+        // Capacity and durability split; zero from older nodes
+        #[serde(default)]
+        total_capacity: u64,
+        #[serde(default)]
+        free_space: u64,
+        #[serde(default)]
+        pinned_bytes: u64,
+        /// Evictable bytes: Cache and Ttl blocks.
+        #[serde(default)]
+        cache_bytes: u64,
+        #[serde(default)]
+        rss_bytes: u64,
+        #[serde(default)]
+        pressure: PressureLevel,
+        /// The node refuses writes (started with `--read-only`).
+        #[serde(default)]
+        read_only: bool,
+        /// Uploads refused or cut off by a stream limit, and uploads dropped
+        /// for sending nothing for too long.
+        #[serde(default)]
+        streams_rejected: u64,
+        #[serde(default)]
+        streams_reaped: u64,
     },
+    PeerStat { used: u64, quota: u64, blocks_remote: usize },
+    PeerSession { session: PeerSession },
+    Metrics { commands: Vec<CommandMetrics> },
+    ClusterStat { stats: ClusterStats },
+    StatHistory { samples: Vec<StatSample> },
     StreamStarted { stream_id: u64 },
+    StreamList { items: Vec<StreamInfo> },
+    AppUsage { items: Vec<AppUsage> },
+    /// `next_seq` is the `since_seq` that asks for what comes after these.
+    Events { events: Vec<NodeEvent>, next_seq: u64 },
+    /// Legacy reply to Flush; still accepted from older nodes.
     FlushSuccess,
+    Flushed { blocks_removed: u64, bytes_freed: u64 },
+    /// Peers first, then the node itself if it flushed too.
+    FlushReport { results: Vec<FlushOutcome> },
+    NodeInfo { info: NodeInfo },
+    /// Trusted devices by name.
+    ReconnectReport { results: Vec<ReconnectOutcome> },
+    Freed { count: u64, bytes: u64, missing: u64, failed: u64 },
     TrustedList { items: Vec<TrustedDevice> },
+    TrustInfo { info: TrustInfo },
     ConsentList { items: Vec<PendingConsent> },
+    /// `state` is one of pending, waiting_consent, connected, failed,
+    /// identity_changed or unknown.
     ConnectionStatus { state: String, msg: Option<String> },
     VmCreated { region_id: u64 },
-    PageData { #[serde(with = "serde_bytes")] data: Vec<u8> },
+    Counter { value: i64 },
+    Appended { len: u64 },
+    VmConfig { threshold_mb: u64 },
+    /// Changed config keys: those now in effect, and those that need a restart.
+    ConfigReloaded { applied: Vec<String>, restart_required: Vec<String> },
+    QuotaUpdated { requested: u64, applied: u64, acknowledged: bool },
+    /// Shared with the block holding the page, like `Loaded`.
+    PageData { data: bytes::Bytes },
+    Pipelined { seq: u64, response: Box<SdkResponse> },
+    /// One line per self-check in `details`, e.g. "transport: ok".
+    Pong {
+        uptime_secs: u64,
+        healthy: bool,
+        details: Vec<String>,
+        /// Transport port the node bound for peers.
+        #[serde(default)]
+        port: Option<u16>,
+        /// Older nodes omit the name and process ID.
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        pid: Option<u32>,
+    },
 }
 
-#[cfg(unix)]
-type InnerStream = UnixStream;
-#[cfg(windows)]
-type InnerStream = TcpStream;
+impl SdkResponse {
+    /// An error response tagged with the code its message classifies as.
+    pub fn error(msg: impl Into<String>) -> Self {
+        let msg = msg.into();
+        let code = ErrorCode::classify(&msg);
+        SdkResponse::Error { msg, code: Some(code) }
+    }
+
+    /// An error response with an explicit code.
+    pub fn error_with(code: ErrorCode, msg: impl Into<String>) -> Self {
+        SdkResponse::Error { msg: msg.into(), code: Some(code) }
+    }
+}
+
+/// Endpoints starting with this name a node's TCP listener (`tcp://127.0.0.1:7070`)
+/// rather than a socket path; see [`MemCloudClient::connect_endpoint`].
+pub const TCP_ENDPOINT_PREFIX: &str = "tcp://";
+
+// A Unix socket or a TCP connection to the node
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Transport for T {}
+
+/// A node running in the same process, which a client hands its commands to
+/// directly instead of framing them over a socket. memnode implements this
+/// (`memnode::NodeBuilder::embedded`); see [`MemCloudClient::embedded`].
+#[cfg(feature = "embedded")]
+pub trait EmbeddedNode: Send + Sync {
+    /// Run `cmd` as the node's RPC server runs a command from one of its
+    /// connections, and return the response it would have sent. A call
+    /// dropped before it returns must not leave its response to the next one.
+    fn call(&mut self, cmd: SdkCommand, trace_id: String) -> futures::future::BoxFuture<'_, SdkResponse>;
+}
+
+// How commands reach the node
+enum Link {
+    Stream(Box<dyn Transport>),
+    #[cfg(feature = "embedded")]
+    Embedded(Box<dyn EmbeddedNode>),
+}
 
 pub struct MemCloudClient {
-    stream: InnerStream,
+    link: Link,
+    // Set by `with_encryption`: payloads are sealed before they leave the client
+    cipher: Option<crypto::Cipher>,
+    timeout: Option<std::time::Duration>,
+    // Set when a command on a stream link timed out: its response may still
+    // arrive, so the stream is out of step. Embedded links match each
+    // response to its call and stay usable.
+    timed_out: bool,
+    last_trace_id: Option<String>,
+    // Where a stream link was opened, to open it again (`with_reconnect`)
+    endpoint: Option<String>,
+    reconnect: bool,
+    // Sent again on a new connection so it is in the same state as the old one
+    token: Option<String>,
+    app_name: Option<String>,
+    // Set by `with_priority`: sent with every store, set and stream
+    priority: Option<u8>,
+    // Set by `with_cache`: values read recently, served without asking the node
+    cache: Option<cache::ValueCache>,
+}
+
+// How a connection to a node that went away fails
+fn is_disconnect(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), BrokenPipe | ConnectionReset | ConnectionAborted | NotConnected | UnexpectedEof)
+}
+
+// Send `cmd` as one frame, with the trace id added to the command's map, and
+// read the response frame
+async fn exchange_framed(stream: &mut Box<dyn Transport>, cmd: &SdkCommand, trace_id: &str) -> Result<SdkResponse> {
+    let bytes = rmp_serde::to_vec_named(cmd)?;
+    let (head, skip, tail) = trace_frame(&bytes, trace_id)?;
+    let len = (head.len() + bytes.len() - skip + tail.len()) as u32;
+
+    // Send
+    log::debug!("RPC request {}: {} bytes", trace_id, len);
+    let started = std::time::Instant::now();
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(&head).await?;
+    stream.write_all(&bytes[skip..]).await?;
+    stream.write_all(&tail).await?;
+
+    // Receive Response
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).await?;
+    let resp_len = u32::from_be_bytes(len_buf) as usize;
+    log::debug!("RPC response {}: {} bytes after {:?}", trace_id, resp_len, started.elapsed());
+
+    let mut resp_buf = vec![0u8; resp_len];
+    stream.read_exact(&mut resp_buf).await?;
+
+    // Deserialize
+    Ok(rmp_serde::from_slice(&resp_buf)?)
 }
 
 impl MemCloudClient {
+    /// Connect to the socket [`default_socket_path`] finds.
     #[cfg(unix)]
     pub async fn connect() -> Result<Self> {
-        Self::connect_with_path("/tmp/memcloud.sock").await
+        Self::connect_with_path(&default_socket_path()).await
     }
 
     #[cfg(unix)]
     pub async fn connect_with_path(path: &str) -> Result<Self> {
         let stream = UnixStream::connect(path).await?;
-        Ok(Self { stream })
+        Ok(Self::from_stream(stream, path.to_string()))
     }
 
+    /// Connect to the endpoint [`default_socket_path`] finds.
     #[cfg(windows)]
     pub async fn connect() -> Result<Self> {
-        Self::connect_with_path("127.0.0.1:7070").await
+        Self::connect_with_path(&default_socket_path()).await
     }
 
+    /// Without unix sockets, `path` is a TCP endpoint: `tcp://host:port` or
+    /// plain `host:port`.
     #[cfg(windows)]
     pub async fn connect_with_path(path: &str) -> Result<Self> {
-        let stream = TcpStream::connect(path).await?;
-        Ok(Self { stream })
+        Self::connect_tcp(path.strip_prefix(TCP_ENDPOINT_PREFIX).unwrap_or(path)).await
     }
 
-    async fn send_command(&mut self, cmd: SdkCommand) -> Result<SdkResponse> {
-        // Serialize
-        let bytes = rmp_serde::to_vec_named(&cmd)?;
-        let len = bytes.len() as u32;
+    /// Connect to a node's TCP listener at `addr` (`host:port`). Nodes
+    /// started with `--rpc-token` want `authenticate` next.
+    pub async fn connect_tcp(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self::from_stream(stream, format!("{}{}", TCP_ENDPOINT_PREFIX, addr)))
+    }
 
-        // Send
-        self.stream.write_all(&len.to_be_bytes()).await?;
-        self.stream.write_all(&bytes).await?;
+    /// Connect to `tcp://host:port` over TCP, or to anything else as a socket path.
+    pub async fn connect_endpoint(endpoint: &str) -> Result<Self> {
+        match endpoint.strip_prefix(TCP_ENDPOINT_PREFIX) {
+            Some(addr) => Self::connect_tcp(addr).await,
+            None => Self::connect_with_path(endpoint).await,
+        }
+    }
+
+    fn from_stream(stream: impl Transport + 'static, endpoint: String) -> Self {
+        Self { endpoint: Some(endpoint), ..Self::from_link(Link::Stream(Box::new(stream))) }
+    }
+
+    /// A client of a node in this process. Commands skip serialization and
+    /// go straight to the node, which handles them exactly as it would a
+    /// socket client's.
+    #[cfg(feature = "embedded")]
+    pub fn embedded(node: impl EmbeddedNode + 'static) -> Self {
+        Self::from_link(Link::Embedded(Box::new(node)))
+    }
+
+    fn from_link(link: Link) -> Self {
+        Self {
+            link,
+            cipher: None,
+            timeout: None,
+            timed_out: false,
+            last_trace_id: None,
+            endpoint: None,
+            reconnect: false,
+            token: None,
+            app_name: None,
+            priority: None,
+            cache: None,
+        }
+    }
+
+    /// Connect and authenticate with the node's `--rpc-token`.
+    pub async fn connect_with_token(path: &str, token: &str) -> Result<Self> {
+        let mut client = Self::connect_with_path(path).await?;
+        client.authenticate(token).await?;
+        Ok(client)
+    }
+
+    /// Encrypt everything stored through this client with `key`
+    /// (ChaCha20-Poly1305) and decrypt it again on load/get. The node and the
+    /// peers hosting the data only see ciphertext. Loading data that was
+    /// stored without encryption then fails instead of returning it as is.
+    pub fn with_encryption(mut self, key: [u8; 32]) -> Self {
+        self.cipher = Some(crypto::Cipher::new(key));
+        self
+    }
+
+    /// Fail any command the node has not answered within `timeout` with a
+    /// `MemCloudError::Timeout`. A timed-out socket client cannot be used further
+    /// until it reconnects, since the late response would be read as the answer
+    /// to the next command; an embedded client carries on. Without
+    /// this, commands wait as long as the node takes.
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.set_timeout(Some(timeout));
+        self
+    }
+
+    /// When the connection breaks, as when the node restarts, connect to the
+    /// same endpoint again instead of failing every later command. A command
+    /// that only reads ([`SdkCommand::is_idempotent`]) is sent again on the
+    /// new connection. Any other still fails with the I/O error, since the
+    /// node may have applied it before the connection broke; the next
+    /// command goes out on the new connection. Authentication and `identify`
+    /// are repeated there. Off by default, and a no-op for embedded clients.
+    pub fn with_reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Store, set and stream blocks with eviction priority `priority` instead
+    /// of [`DEFAULT_PRIORITY`]. Among evictable blocks the node drops lower
+    /// priorities first, so give blocks that are costly to recreate a higher one.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Keep up to `max_bytes` of values read with `load` and `get` in this
+    /// client, and answer the same reads from there for `ttl`. Writes through
+    /// this client drop the values they may change; writes through any other
+    /// client or node are only seen once `ttl` has passed. A value larger
+    /// than `max_bytes / CACHE_ENTRY_SHARE` is never cached. Off by default.
+    pub fn with_cache(mut self, max_bytes: u64, ttl: std::time::Duration) -> Self {
+        self.cache = Some(cache::ValueCache::new(max_bytes, ttl));
+        self
+    }
+
+    /// Hits and misses of the cache set up by `with_cache`, if any.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(cache::ValueCache::stats)
+    }
+
+    // Drop cached values a write may change: those under `key`, or all of them.
+    // Writing a key frees the block it named, and which that was is not known here
+    fn invalidate(&mut self, key: Option<&str>) {
+        match (&mut self.cache, key) {
+            (Some(cache), Some(key)) => {
+                cache.forget_key(key);
+                cache.forget_blocks();
+            }
+            (Some(cache), None) => cache.clear(),
+            (None, _) => {}
+        }
+    }
+
+    // A cached answer to `key`, counting the hit or miss
+    fn cached(&mut self, key: &cache::CacheKey) -> Option<(Vec<u8>, Option<Durability>)> {
+        self.cache.as_mut()?.get(key, std::time::Instant::now())
+    }
+
+    fn remember(&mut self, key: cache::CacheKey, value: &(Vec<u8>, Option<Durability>)) {
+        if let Some(cache) = &mut self.cache {
+            cache.insert(key, &value.0, value.1, std::time::Instant::now());
+        }
+    }
+
+    /// Replace the connection with a new one to the same endpoint, set up
+    /// like the old one, e.g. after the node hung up on a command it does
+    /// not know. A no-op for embedded clients.
+    pub async fn reconnect(&mut self) -> Result<()> {
+        if matches!(self.link, Link::Stream(_)) {
+            self.link = Link::Stream(self.reopen().await?);
+            self.timed_out = false;
+        }
+        Ok(())
+    }
+
+    /// Change the timeout for subsequent commands, e.g. around a slow one.
+    pub fn set_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Trace id of the last command sent; the node's logs of that command
+    /// carry it as `request_id` (`memcli trace <id>` finds them).
+    pub fn last_trace_id(&self) -> Option<&str> {
+        self.last_trace_id.as_deref()
+    }
+
+    // Encrypt outgoing data when encryption is on
+    fn seal(&self, data: &[u8]) -> Vec<u8> {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(data),
+            None => data.to_vec(),
+        }
+    }
+
+    fn open(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(&data).map_err(|e| MemCloudError::Encryption(e.to_string())),
+            None => Ok(data),
+        }
+    }
+
+    pub async fn authenticate(&mut self, token: &str) -> Result<()> {
+        let cmd = SdkCommand::Auth { token: token.to_string() };
+        match self.send_command(cmd).await? {
+            SdkResponse::Success => {
+                self.token = Some(token.to_string());
+                Ok(())
+            }
+            _ => Err(MemCloudError::unexpected("Auth")),
+        }
+    }
+
+    /// Tell the node which program this connection belongs to (sent with this
+    /// process's pid), so `app_usage` can attribute what it stores.
+    pub async fn identify(&mut self, app_name: &str) -> Result<()> {
+        let cmd = SdkCommand::ClientHello { app_name: app_name.to_string(), pid: Some(std::process::id()) };
+        match self.send_command(cmd).await? {
+            SdkResponse::Success => {
+                self.app_name = Some(app_name.to_string());
+                Ok(())
+            }
+            _ => Err(MemCloudError::unexpected("ClientHello")),
+        }
+    }
+
+    /// Entries of the node's event journal; see [`SdkCommand::Events`].
+    /// Returns them oldest first with the `since_seq` for the next call.
+    pub async fn events(&mut self, since_seq: Option<u64>, kinds: Option<Vec<String>>, limit: u64, wait_secs: Option<u64>) -> Result<(Vec<NodeEvent>, u64)> {
+        match self.send_command(SdkCommand::Events { since_seq, kinds, limit, wait_secs }).await? {
+            SdkResponse::Events { events, next_seq } => Ok((events, next_seq)),
+            _ => Err(MemCloudError::unexpected("Events")),
+        }
+    }
+
+    /// Local memory held per application, largest first.
+    pub async fn app_usage(&mut self) -> Result<Vec<AppUsage>> {
+        match self.send_command(SdkCommand::AppUsage).await? {
+            SdkResponse::AppUsage { items } => Ok(items),
+            _ => Err(MemCloudError::unexpected("AppUsage")),
+        }
+    }
 
-        // Receive Response
-        let mut len_buf = [0u8; 4];
-        self.stream.read_exact(&mut len_buf).await?;
-        let resp_len = u32::from_be_bytes(len_buf) as usize;
+    async fn send_command(&mut self, cmd: SdkCommand) -> Result<SdkResponse> {
+        if self.timed_out {
+            return Err(MemCloudError::Protocol("Connection unusable after an earlier command timed out; reconnect".to_string()));
+        }
+        let Some(timeout) = self.timeout else {
+            return self.exchange(cmd).await;
+        };
+        match tokio::time::timeout(timeout, self.exchange(cmd)).await {
+            Ok(resp) => resp,
+            Err(_) => {
+                self.timed_out = matches!(self.link, Link::Stream(_));
+                Err(MemCloudError::Timeout(timeout))
+            }
+        }
+    }
 
-        let mut resp_buf = vec![0u8; resp_len];
-        self.stream.read_exact(&mut resp_buf).await?;
+    async fn exchange(&mut self, cmd: SdkCommand) -> Result<SdkResponse> {
+        let trace_id = new_trace_id();
+        self.last_trace_id = Some(trace_id.clone());
+        let resp = match &mut self.link {
+            Link::Stream(stream) => match exchange_framed(stream, &cmd, &trace_id).await {
+                Err(MemCloudError::Io(e)) if self.reconnect && is_disconnect(&e) => {
+                    log::debug!("Connection to the node lost ({}); reconnecting", e);
+                    let Ok(mut stream) = self.reopen().await else {
+                        return Err(MemCloudError::Io(e));
+                    };
+                    let resent = match cmd.is_idempotent() {
+                        true => Some(exchange_framed(&mut stream, &cmd, &trace_id).await),
+                        false => None,
+                    };
+                    self.link = Link::Stream(stream);
+                    resent.unwrap_or(Err(MemCloudError::Io(e)))?
+                }
+                resp => resp?,
+            },
+            #[cfg(feature = "embedded")]
+            Link::Embedded(node) => node.call(cmd, trace_id).await,
+        };
+        match resp {
+            SdkResponse::Overloaded { msg } => Err(MemCloudError::Overloaded(msg)),
+            SdkResponse::Error { msg, code } => Err(MemCloudError::from_response(msg, code)),
+            resp => Ok(resp),
+        }
+    }
 
-        // Deserialize
-        let resp: SdkResponse = rmp_serde::from_slice(&resp_buf)?;
-        Ok(resp)
+    // A new connection to the endpoint of a broken stream link, in the state
+    // the old one was in
+    async fn reopen(&self) -> Result<Box<dyn Transport>> {
+        let Some(endpoint) = &self.endpoint else {
+            return Err(MemCloudError::Protocol("No endpoint to reconnect to".to_string()));
+        };
+        let mut stream: Box<dyn Transport> = match endpoint.strip_prefix(TCP_ENDPOINT_PREFIX) {
+            Some(addr) => Box::new(TcpStream::connect(addr).await?),
+            #[cfg(unix)]
+            None => Box::new(UnixStream::connect(endpoint).await?),
+            #[cfg(windows)]
+            None => Box::new(TcpStream::connect(endpoint).await?),
+        };
+        let token = self.token.clone().map(|token| SdkCommand::Auth { token });
+        let hello = self.app_name.clone().map(|app_name| SdkCommand::ClientHello { app_name, pid: Some(std::process::id()) });
+        for cmd in token.iter().chain(&hello) {
+            if let SdkResponse::Error { msg, code } = exchange_framed(&mut stream, cmd, &new_trace_id()).await? {
+                return Err(MemCloudError::from_response(msg, code));
+            }
+        }
+        Ok(stream)
     }
 
     pub async fn store(&mut self, data: &[u8], durability: Durability) -> Result<BlockId> {
-        let cmd = SdkCommand::Store { data: data.to_vec(), durability: Some(durability) };
+        Ok(self.store_with_size(data, durability).await?.0)
+    }
+
+    /// Like `store`, also returning the stored size when the node reports it.
+    pub async fn store_with_size(&mut self, data: &[u8], durability: Durability) -> Result<(BlockId, Option<u64>)> {
+        self.store_inner(data, durability, false).await
+    }
+
+    /// Store under an ID derived from the data, so storing identical bytes
+    /// twice keeps one copy and returns the same ID. Each store must be freed
+    /// before the copy goes, so one caller's free leaves the others' data in
+    /// place. With encryption enabled
+    /// every store is sealed under a fresh nonce, so nothing deduplicates.
+    pub async fn store_content_addressed(&mut self, data: &[u8], durability: Durability) -> Result<(BlockId, Option<u64>)> {
+        self.store_inner(data, durability, true).await
+    }
+
+    /// `store` with tags attached; see [`block_info`](Self::block_info).
+    pub async fn store_tagged(&mut self, data: &[u8], durability: Durability, tags: Tags) -> Result<BlockId> {
+        let cmd = SdkCommand::Store { data: self.seal(data), durability: Some(durability), content_addressed: false, tags, priority: self.priority };
         match self.send_command(cmd).await? {
-            SdkResponse::Stored { id } => Ok(id),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::Stored { id, .. } => Ok(id),
+            _ => Err(MemCloudError::unexpected("Store")),
         }
     }
 
-    pub async fn store_remote(&mut self, data: &[u8], target: Option<String>, durability: Durability) -> Result<BlockId> {
-        let cmd = SdkCommand::StoreRemote { data: data.to_vec(), target, durability: Some(durability) };
+    async fn store_inner(&mut self, data: &[u8], durability: Durability, content_addressed: bool) -> Result<(BlockId, Option<u64>)> {
+        let cmd = SdkCommand::Store { data: self.seal(data), durability: Some(durability), content_addressed, tags: Tags::new(), priority: self.priority };
         match self.send_command(cmd).await? {
-            SdkResponse::Stored { id } => Ok(id),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::Stored { id, size, .. } => Ok((id, size)),
+            _ => Err(MemCloudError::unexpected("Store")),
         }
     }
 
-    pub async fn load(&mut self, id: BlockId) -> Result<Vec<u8>> {
-        let cmd = SdkCommand::Load { id };
+    /// Store `data` on `target`, or on any peer with room, returning the
+    /// block ID and where it landed. With `fallback_local` the node keeps the
+    /// block itself if no peer takes it.
+    pub async fn store_remote(&mut self, data: &[u8], target: Option<String>, durability: Durability, fallback_local: bool) -> Result<(BlockId, StoredLocation)> {
+        let (id, _, location) = self.store_remote_with_size(data, target, durability, fallback_local).await?;
+        Ok((id, location))
+    }
+
+    pub async fn store_remote_with_size(&mut self, data: &[u8], target: Option<String>, durability: Durability, fallback_local: bool) -> Result<(BlockId, Option<u64>, StoredLocation)> {
+        let cmd = SdkCommand::StoreRemote { data: self.seal(data), target: target.clone(), durability: Some(durability), fallback_local };
         match self.send_command(cmd).await? {
-            SdkResponse::Loaded { data } => Ok(data),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            // Older nodes have no fallback, so a block they stored is on a peer
+            SdkResponse::Stored { id, size, location } => {
+                let location = location.unwrap_or_else(|| StoredLocation::Remote(target.unwrap_or_else(|| "unknown".to_string())));
+                Ok((id, size, location))
+            }
+            _ => Err(MemCloudError::unexpected("StoreRemote")),
         }
     }
+
+    pub async fn load(&mut self, id: BlockId) -> Result<Vec<u8>> {
+        Ok(self.load_with_durability(id).await?.0)
+    }
+
+    /// Like `load`, also returning the durability the block is held under,
+    /// wherever it lives (`None` if the node does not report it).
+    pub async fn load_with_durability(&mut self, id: BlockId) -> Result<(Vec<u8>, Option<Durability>)> {
+        if let Some(hit) = self.cached(&cache::CacheKey::Block(id)) {
+            return Ok(hit);
+        }
+        let cmd = SdkCommand::Load { id };
+        let loaded = match self.send_command(cmd).await? {
+            SdkResponse::Loaded { data, durability } => (self.open(data.into())?, durability),
+            _ => return Err(MemCloudError::unexpected("Load")),
+        };
+        self.remember(cache::CacheKey::Block(id), &loaded);
+        Ok(loaded)
+    }
     
-    pub async fn free(&mut self, id: BlockId) -> Result<()> {
+    /// Free a block or VM region. Returns `None` when the node could not
+    /// report counts (older nodes).
+    pub async fn free(&mut self, id: BlockId) -> Result<Option<FreeStats>> {
+        // Keys naming the block go with it, and which they are is not known here
+        self.invalidate(None);
         let cmd = SdkCommand::Free { id };
         match self.send_command(cmd).await? {
-            SdkResponse::Success => Ok(()),
-             SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::Freed { count, bytes, missing, failed } => Ok(Some(FreeStats { count, bytes, missing, failed })),
+            SdkResponse::Success => Ok(None),
+            _ => Err(MemCloudError::unexpected("Free")),
+        }
+    }
+
+    /// Free many blocks in one command, and with `pattern` the blocks of the
+    /// keys matching it; see [`SdkCommand::FreeMany`].
+    pub async fn free_many(&mut self, ids: &[BlockId], pattern: Option<String>) -> Result<FreeStats> {
+        self.invalidate(None);
+        match self.send_command(SdkCommand::FreeMany { ids: ids.to_vec(), pattern }).await? {
+            SdkResponse::Freed { count, bytes, missing, failed } => Ok(FreeStats { count, bytes, missing, failed }),
+            _ => Err(MemCloudError::unexpected("FreeMany")),
         }
     }
     
+    /// Make a stored block pinned, cache or TTL from now on. Pinning protects
+    /// it from eviction; a block that is not on the node fails with `NotFound`.
+    pub async fn set_durability(&mut self, id: BlockId, durability: Durability) -> Result<()> {
+        if let Some(cache) = &mut self.cache {
+            cache.forget(&cache::CacheKey::Block(id));
+        }
+        match self.send_command(SdkCommand::SetDurability { id, durability }).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("SetDurability")),
+        }
+    }
+
+    /// Change the eviction priority of a block on the node, by id or key.
+    pub async fn set_priority(&mut self, id_or_key: BlockOrKey, priority: u8) -> Result<()> {
+        match self.send_command(SdkCommand::SetPriority { id_or_key, priority }).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("SetPriority")),
+        }
+    }
+
     pub async fn list_peers(&mut self) -> Result<Vec<PeerMetadata>> {
         let cmd = SdkCommand::ListPeers;
         match self.send_command(cmd).await? {
             SdkResponse::PeerList { peers } => Ok(peers),
             // Fallback for older nodes? No versioning yet.
-            SdkResponse::List { items: _items } => {
+            SdkResponse::List { .. } => {
                 // mock convert? or error?
                 // Assuming version alignment. 
                 // But if we encounter List, it means old node.
                 // Converting string list to metadata is hard because we lack stats/id separation in string?
                 // String was "ID (Name) @ Addr". We could parse it.
                 // Let's iterate and parse if needed, later. For now, assume matching version.
-                anyhow::bail!("Received legacy peer list format")
+                Err(MemCloudError::Protocol("Received legacy peer list format".to_string()))
             },
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("ListPeers")),
         }
     }
 
+    /// Start connecting to the peer at `addr`, offering it `quota` bytes here.
+    /// Returns at once with the attempt's state ("pending" while the handshake
+    /// runs) and its message; follow it with `poll_connection`, or use
+    /// `connect_peer_blocking` to wait for the outcome.
     pub async fn connect_peer(&mut self, addr: &str, quota: Option<u64>) -> Result<(String, Option<String>)> {
-         let cmd = SdkCommand::Connect { addr: addr.to_string(), quota };
+        self.send_connect(addr, quota, false).await
+    }
+
+    /// Connect to the peer at `addr` and wait until the connection is up.
+    /// Fails with `PeerUnreachable` if the handshake fails, the peer presents
+    /// a different key than the one pinned for it, or the attempt is
+    /// cancelled. Without a timeout it waits as long as the peer's user takes
+    /// to give consent. With one (`set_timeout`), that bounds the whole wait:
+    /// once it runs out the attempt is cancelled and this fails with `Timeout`.
+    /// Don't wrap the call in `tokio::time::timeout` instead; dropping it
+    /// mid-command leaves the reply unread and the connection out of step.
+    pub async fn connect_peer_blocking(&mut self, addr: &str, quota: Option<u64>) -> Result<()> {
+        let deadline = self.timeout.map(|after| (after, tokio::time::Instant::now() + after));
+        let (mut state, mut msg) = self.connect_peer(addr, quota).await?;
+        loop {
+            match state.as_str() {
+                "connected" => return Ok(()),
+                "pending" | "waiting_consent" => match deadline {
+                    // Checked between commands, so the stream stays in step
+                    Some((after, at)) if tokio::time::Instant::now() >= at => {
+                        self.cancel_connect(addr).await?;
+                        return Err(MemCloudError::Timeout(after));
+                    }
+                    _ => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+                },
+                "identity_changed" => {
+                    return Err(MemCloudError::PeerUnreachable(format!("Peer identity changed at {}: {}", addr, msg.unwrap_or_default())));
+                }
+                _ => return Err(MemCloudError::PeerUnreachable(msg.unwrap_or_else(|| format!("Connection to {} {}", addr, state)))),
+            }
+            (state, msg) = self.poll_connection(addr).await?;
+        }
+    }
+
+    /// Like `connect_peer`, but a peer that presents a different key than the
+    /// one pinned for it is accepted and re-pinned instead of refused.
+    pub async fn connect_peer_accepting_new_key(&mut self, addr: &str, quota: Option<u64>) -> Result<(String, Option<String>)> {
+        self.send_connect(addr, quota, true).await
+    }
+
+    /// Dial every trusted device that is not connected at its last known
+    /// address; see [`SdkCommand::ReconnectTrusted`]. Waits for all of them.
+    pub async fn reconnect_trusted(&mut self, quota: Option<u64>) -> Result<Vec<ReconnectOutcome>> {
+        match self.send_command(SdkCommand::ReconnectTrusted { quota }).await? {
+            SdkResponse::ReconnectReport { results } => Ok(results),
+            _ => Err(MemCloudError::unexpected("ReconnectTrusted")),
+        }
+    }
+
+    async fn send_connect(&mut self, addr: &str, quota: Option<u64>, accept_new_key: bool) -> Result<(String, Option<String>)> {
+         let cmd = SdkCommand::Connect { addr: addr.to_string(), quota, accept_new_key };
          match self.send_command(cmd).await? {
             SdkResponse::ConnectionStatus { state, msg } => Ok((state, msg)),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to Connect"),
+            _ => Err(MemCloudError::unexpected("Connect")),
         }
     }
     
@@ -266,73 +1698,479 @@ impl MemCloudClient {
          let cmd = SdkCommand::PollConnection { addr: addr.to_string() };
          match self.send_command(cmd).await? {
             SdkResponse::ConnectionStatus { state, msg } => Ok((state, msg)),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to PollConnection"),
+            _ => Err(MemCloudError::unexpected("PollConnection")),
         }
     }
     
+    pub async fn cancel_connect(&mut self, addr: &str) -> Result<()> {
+        match self.send_command(SdkCommand::CancelConnect { addr: addr.to_string() }).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("CancelConnect")),
+        }
+    }
+
     pub async fn disconnect_peer(&mut self, peer_id: &str) -> Result<()> {
-        let cmd = SdkCommand::Disconnect { peer_id: peer_id.to_string() };
+        self.disconnect(peer_id, false).await
+    }
+
+    /// Disconnect at once, cutting short a drain of the peer.
+    pub async fn force_disconnect(&mut self, peer_id: &str) -> Result<()> {
+        self.disconnect(peer_id, true).await
+    }
+
+    async fn disconnect(&mut self, peer_id: &str, force: bool) -> Result<()> {
+        let cmd = SdkCommand::Disconnect { peer_id: peer_id.to_string(), drain: false, force };
         match self.send_command(cmd).await? {
              SdkResponse::Success => Ok(()),
-             SdkResponse::Error { msg } => anyhow::bail!(msg),
-             _ => anyhow::bail!("Unexpected response to Disconnect"),
+             _ => Err(MemCloudError::unexpected("Disconnect")),
+        }
+    }
+
+    /// Start taking back what the node stored on a peer, then disconnect from
+    /// it; see [`SdkCommand::Disconnect`]. Returns the drain already running, if any.
+    pub async fn start_drain(&mut self, peer_id: &str) -> Result<DrainStatus> {
+        let cmd = SdkCommand::Disconnect { peer_id: peer_id.to_string(), drain: true, force: false };
+        match self.send_command(cmd).await? {
+            SdkResponse::Drain { status } => Ok(status),
+            _ => Err(MemCloudError::unexpected("Disconnect")),
+        }
+    }
+
+    pub async fn drain_status(&mut self, peer_id: &str) -> Result<DrainStatus> {
+        match self.send_command(SdkCommand::DrainStatus { peer_id: peer_id.to_string() }).await? {
+            SdkResponse::Drain { status } => Ok(status),
+            _ => Err(MemCloudError::unexpected("DrainStatus")),
+        }
+    }
+
+    /// Drain a peer and disconnect from it, returning the final status. Blocks
+    /// that could not be taken back are listed in its `failed`.
+    pub async fn drain_peer(&mut self, peer_id: &str) -> Result<DrainStatus> {
+        let mut status = self.start_drain(peer_id).await?;
+        while status.state == DrainState::Running {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            status = self.drain_status(peer_id).await?;
         }
+        Ok(status)
     }
 
-    pub async fn update_peer_quota(&mut self, peer_id: &str, quota: u64) -> Result<()> {
+    pub async fn update_peer_quota(&mut self, peer_id: &str, quota: u64) -> Result<QuotaUpdate> {
         let cmd = SdkCommand::UpdatePeerQuota { peer_id: peer_id.to_string(), quota };
         match self.send_command(cmd).await? {
-           SdkResponse::Success => Ok(()),
-           SdkResponse::Error { msg } => anyhow::bail!(msg),
-           _ => anyhow::bail!("Unexpected response"),
+           SdkResponse::QuotaUpdated { requested, applied, acknowledged } => Ok(QuotaUpdate { requested, applied, acknowledged }),
+           // Older nodes do not wait for the peer
+           SdkResponse::Success => Ok(QuotaUpdate { requested: quota, applied: quota, acknowledged: false }),
+           _ => Err(MemCloudError::unexpected("UpdatePeerQuota")),
        }
    }
     
+    /// Let a trusted peer flush everything on the node, or only what it stored there.
+    pub async fn allow_remote_flush(&mut self, peer_id: &str, allow: bool) -> Result<()> {
+        let cmd = SdkCommand::AllowRemoteFlush { peer_id: peer_id.to_string(), allow };
+        match self.send_command(cmd).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("AllowRemoteFlush")),
+        }
+    }
+    
     // KV Methods
     pub async fn set(&mut self, key: &str, data: &[u8], target: Option<String>, durability: Durability) -> Result<BlockId> {
-         let cmd = SdkCommand::Set { key: key.to_string(), data: data.to_vec(), target, durability: Some(durability) };
+         self.invalidate(Some(key));
+         let cmd = SdkCommand::Set { key: key.to_string(), data: self.seal(data), target, durability: Some(durability), tags: Tags::new(), priority: self.priority };
          match self.send_command(cmd).await? {
-            SdkResponse::Stored { id } => Ok(id),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::Stored { id, .. } => Ok(id),
+            _ => Err(MemCloudError::unexpected("Set")),
+        }
+    }
+
+    /// `set` on this node, replacing the key's tags with `tags`.
+    pub async fn set_tagged(&mut self, key: &str, data: &[u8], durability: Durability, tags: Tags) -> Result<BlockId> {
+        self.invalidate(Some(key));
+        let cmd = SdkCommand::Set { key: key.to_string(), data: self.seal(data), target: None, durability: Some(durability), tags, priority: self.priority };
+        match self.send_command(cmd).await? {
+            SdkResponse::Stored { id, .. } => Ok(id),
+            _ => Err(MemCloudError::unexpected("Set")),
+        }
+    }
+
+    /// Keys on this node tagged `key = value`, sorted.
+    pub async fn find_by_tag(&mut self, key: &str, value: &str) -> Result<Vec<String>> {
+        match self.send_command(SdkCommand::FindByTag { key: key.to_string(), value: value.to_string() }).await? {
+            SdkResponse::List { items, .. } => Ok(items),
+            _ => Err(MemCloudError::unexpected("FindByTag")),
+        }
+    }
+
+    pub async fn block_info(&mut self, selector: BlockOrKey) -> Result<BlockInfo> {
+        match self.send_command(SdkCommand::BlockInfo { selector }).await? {
+            SdkResponse::BlockInfo { info } => Ok(info),
+            _ => Err(MemCloudError::unexpected("BlockInfo")),
+        }
+    }
+
+    /// Every block stored on the node, by ascending ID, fetched a page at a time.
+    pub async fn list_blocks(&mut self) -> Result<Vec<BlockSummary>> {
+        let mut blocks = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (items, next) = self.list_blocks_page(cursor, LIST_KEYS_LIMIT).await?;
+            blocks.extend(items);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => return Ok(blocks),
+            }
+        }
+    }
+
+    /// Fetch one page of blocks. Pass the returned cursor back to get the next
+    /// page; `None` means there are no more blocks.
+    pub async fn list_blocks_page(&mut self, cursor: Option<String>, limit: u32) -> Result<(Vec<BlockSummary>, Option<String>)> {
+        match self.send_command(SdkCommand::ListBlocks { limit, cursor }).await? {
+            SdkResponse::BlockPage { items, next_cursor } => Ok((items, next_cursor)),
+            _ => Err(MemCloudError::unexpected("ListBlocks")),
         }
     }
     
     pub async fn get(&mut self, key: &str, target: Option<String>) -> Result<Vec<u8>> {
+        Ok(self.get_with_durability(key, target).await?.0)
+    }
+
+    /// Like `get`, also returning the durability of the copy that answered.
+    pub async fn get_with_durability(&mut self, key: &str, target: Option<String>) -> Result<(Vec<u8>, Option<Durability>)> {
+        let cache_key = cache::CacheKey::Key { key: key.to_string(), target: target.clone() };
+        if let Some(hit) = self.cached(&cache_key) {
+            return Ok(hit);
+        }
         let cmd = SdkCommand::Get { key: key.to_string(), target };
+        let loaded = match self.send_command(cmd).await? {
+            SdkResponse::Loaded { data, durability } => (self.open(data.into())?, durability),
+            _ => return Err(MemCloudError::unexpected("Get")),
+        };
+        self.remember(cache_key, &loaded);
+        Ok(loaded)
+    }
+
+    /// `set` with `value` serialized as JSON.
+    pub async fn set_json<T: Serialize + ?Sized>(&mut self, key: &str, value: &T, target: Option<String>, durability: Durability) -> Result<BlockId> {
+        let data = serde_json::to_vec(value)?;
+        self.set(key, &data, target, durability).await
+    }
+
+    /// `get` a value stored as JSON. A missing key is `None`; a `target` that
+    /// cannot be reached fails with [`MemCloudError::PeerUnreachable`], and a
+    /// value that is not JSON of type `T` with [`MemCloudError::Json`].
+    pub async fn get_json<T: DeserializeOwned>(&mut self, key: &str, target: Option<String>) -> Result<Option<T>> {
+        match self.get(key, target).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.is_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Atomically add `delta` to the counter at `key` and return the new value.
+    /// A missing key starts at 0; a value that is not an 8-byte counter fails.
+    /// Counters live on this node, and are not available with encryption.
+    pub async fn incr(&mut self, key: &str, delta: i64) -> Result<i64> {
+        self.require_plaintext("incr")?;
+        // The value changes in place, under its aliases and block id too
+        self.invalidate(None);
+        match self.send_command(SdkCommand::Incr { key: key.to_string(), delta }).await? {
+            SdkResponse::Counter { value } => Ok(value),
+            _ => Err(MemCloudError::unexpected("Incr")),
+        }
+    }
+
+    /// `incr` by `-delta`.
+    pub async fn decr(&mut self, key: &str, delta: i64) -> Result<i64> {
+        self.incr(key, delta.saturating_neg()).await
+    }
+
+    /// Atomically append `data` to the value at `key` and return its new length.
+    pub async fn append(&mut self, key: &str, data: &[u8]) -> Result<u64> {
+        self.require_plaintext("append")?;
+        self.invalidate(None);
+        match self.send_command(SdkCommand::Append { key: key.to_string(), data: data.to_vec() }).await? {
+            SdkResponse::Appended { len } => Ok(len),
+            _ => Err(MemCloudError::unexpected("Append")),
+        }
+    }
+
+    /// Move the value at `from` to `to`; with `overwrite` a value already at
+    /// `to` is replaced instead of failing with `MemCloudError::Conflict`.
+    pub async fn rename(&mut self, from: &str, to: &str, overwrite: bool, target: Option<String>) -> Result<()> {
+        self.invalidate(Some(from));
+        self.invalidate(Some(to));
+        let cmd = SdkCommand::Rename { from: from.to_string(), to: to.to_string(), overwrite, target };
         match self.send_command(cmd).await? {
-            SdkResponse::Loaded { data } => Ok(data),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("Rename")),
         }
     }
 
+    /// Make `alias` another name for the value at `key`, so removing either
+    /// name leaves the value to the other.
+    pub async fn alias(&mut self, key: &str, alias: &str, target: Option<String>) -> Result<()> {
+        let cmd = SdkCommand::Alias { key: key.to_string(), alias: alias.to_string(), target };
+        match self.send_command(cmd).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("Alias")),
+        }
+    }
+
+    // The node has to see the value to update it in place
+    fn require_plaintext(&self, op: &str) -> Result<()> {
+        if self.cipher.is_some() {
+            return Err(MemCloudError::Encryption(format!("{} is not available on an encrypting client", op)));
+        }
+        Ok(())
+    }
+
+    /// Every key matching `pattern`, sorted. Fetched a page at a time, so it
+    /// is not cut short on nodes with more than `LIST_KEYS_LIMIT` keys.
     pub async fn list_keys(&mut self, pattern: &str) -> Result<Vec<String>> {
-        let cmd = SdkCommand::ListKeys { pattern: pattern.to_string() };
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (items, next) = self.list_keys_page(pattern, cursor, LIST_KEYS_LIMIT).await?;
+            keys.extend(items);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => return Ok(keys),
+            }
+        }
+    }
+
+    /// Fetch one page of keys in sorted order. Pass the returned cursor back to
+    /// get the next page; `None` means there are no more keys.
+    pub async fn list_keys_page(&mut self, pattern: &str, cursor: Option<String>, limit: u32) -> Result<(Vec<String>, Option<String>)> {
+        let cmd = SdkCommand::ListKeys { pattern: pattern.to_string(), cursor, limit: Some(limit), include_remote: false };
+        match self.send_command(cmd).await? {
+            SdkResponse::KeyPage { items, next_cursor } => Ok((items, next_cursor)),
+            _ => Err(MemCloudError::unexpected("ListKeys")),
+        }
+    }
+
+    /// Keys matching `pattern` on the node and on its connected peers, with
+    /// where each one is held. Takes up to about two seconds when a peer is
+    /// slow to answer.
+    pub async fn list_keys_everywhere(&mut self, pattern: &str) -> Result<KeyListing> {
+        let cmd = SdkCommand::ListKeys { pattern: pattern.to_string(), cursor: None, limit: None, include_remote: true };
         match self.send_command(cmd).await? {
-            SdkResponse::List { items } => Ok(items),
-             SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::KeyLocations { listing } => Ok(listing),
+            _ => Err(MemCloudError::unexpected("ListKeys")),
+        }
+    }
+
+    /// The node's most-read blocks, busiest first; see [`SdkCommand::HotKeys`].
+    pub async fn hot_keys(&mut self, top_n: u32, window_secs: Option<u64>) -> Result<Vec<HotBlock>> {
+        match self.send_command(SdkCommand::HotKeys { top_n, window_secs }).await? {
+            SdkResponse::HotKeys { items } => Ok(items),
+            _ => Err(MemCloudError::unexpected("HotKeys")),
+        }
+    }
+
+    /// Start moving data between the node and a peer; see [`SdkCommand::Migrate`].
+    pub async fn start_migration(&mut self, selector: BlockOrKey, to: Option<String>, from: Option<String>, keep_local: bool) -> Result<u64> {
+        match self.send_command(SdkCommand::Migrate { selector, to, from, keep_local }).await? {
+            SdkResponse::MigrationStarted { migration_id } => Ok(migration_id),
+            _ => Err(MemCloudError::unexpected("Migrate")),
+        }
+    }
+
+    pub async fn migration_status(&mut self, migration_id: u64) -> Result<MigrationStatus> {
+        match self.send_command(SdkCommand::MigrationStatus { migration_id }).await? {
+            SdkResponse::Migration { status } => Ok(status),
+            _ => Err(MemCloudError::unexpected("MigrationStatus")),
+        }
+    }
+
+    /// Run a migration to completion, returning its final status. Fails if
+    /// the migration does; the status is in the error message.
+    pub async fn migrate(&mut self, selector: BlockOrKey, to: Option<String>, from: Option<String>, keep_local: bool) -> Result<MigrationStatus> {
+        let migration_id = self.start_migration(selector, to, from, keep_local).await?;
+        loop {
+            let status = self.migration_status(migration_id).await?;
+            match status.state {
+                MigrationState::Running => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+                MigrationState::Done => return Ok(status),
+                MigrationState::Failed => return Err(MemCloudError::Server(status.error.unwrap_or_else(|| "Migration failed".to_string()))),
+            }
         }
     }
 
+    /// Stream every key matching `pattern`, fetching one page at a time.
+    ///
+    /// ```no_run
+    /// # async fn demo(client: &mut memsdk::MemCloudClient) -> anyhow::Result<()> {
+    /// use futures::StreamExt;
+    /// let mut keys = client.scan("tmp:*");
+    /// while let Some(key) = keys.next().await {
+    ///     println!("{}", key?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn scan<'a>(&'a mut self, pattern: &str) -> impl Stream<Item = Result<String>> + Unpin + 'a {
+        struct ScanState<'a> {
+            client: &'a mut MemCloudClient,
+            pattern: String,
+            page: VecDeque<String>,
+            cursor: Option<String>,
+            done: bool,
+        }
+
+        let state = ScanState {
+            client: self,
+            pattern: pattern.to_string(),
+            page: VecDeque::new(),
+            cursor: None,
+            done: false,
+        };
+
+        Box::pin(futures::stream::unfold(state, |mut st| async move {
+            loop {
+                if let Some(key) = st.page.pop_front() {
+                    return Some((Ok(key), st));
+                }
+                if st.done {
+                    return None;
+                }
+                match st.client.list_keys_page(&st.pattern, st.cursor.take(), SCAN_PAGE_SIZE).await {
+                    Ok((items, next)) => {
+                        st.done = next.is_none();
+                        st.cursor = next;
+                        st.page.extend(items);
+                    }
+                    Err(e) => {
+                        st.done = true;
+                        return Some((Err(e), st));
+                    }
+                }
+            }
+        }))
+    }
+
     pub async fn stats(&mut self) -> Result<(usize, usize, usize, usize, usize, usize)> {
         let cmd = SdkCommand::Stat;
         match self.send_command(cmd).await? {
-            SdkResponse::Status { blocks, peers, memory_usage, vm_regions, vm_pages_mapped, vm_memory_in_use } => 
+            SdkResponse::Status { blocks, peers, memory_usage, vm_regions, vm_pages_mapped, vm_memory_in_use, .. } => 
                 Ok((blocks, peers, memory_usage, vm_regions, vm_pages_mapped, vm_memory_in_use)),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("Stat")),
+        }
+    }
+
+    /// Like `stats`, including capacity and the pinned/cache split.
+    pub async fn node_stats(&mut self) -> Result<NodeStats> {
+        match self.send_command(SdkCommand::Stat).await? {
+            SdkResponse::Status {
+                blocks, peers, memory_usage, vm_regions, vm_pages_mapped, vm_memory_in_use,
+                total_capacity, free_space, pinned_bytes, cache_bytes, rss_bytes, pressure, read_only,
+                streams_rejected, streams_reaped,
+            } => Ok(NodeStats {
+                blocks, peers, memory_usage, vm_regions, vm_pages_mapped, vm_memory_in_use,
+                total_capacity, free_space, pinned_bytes, cache_bytes, rss_bytes, pressure, read_only,
+                streams_rejected, streams_reaped,
+            }),
+            _ => Err(MemCloudError::unexpected("Stat")),
+        }
+    }
+
+    /// Usage samples from the last `window_secs` seconds, oldest first; see
+    /// [`SdkCommand::StatHistory`].
+    pub async fn stat_history(&mut self, window_secs: u64) -> Result<Vec<StatSample>> {
+        match self.send_command(SdkCommand::StatHistory { window_secs }).await? {
+            SdkResponse::StatHistory { samples } => Ok(samples),
+            _ => Err(MemCloudError::unexpected("StatHistory")),
+        }
+    }
+
+    /// How much this node has stored on `peer` (a name or id) and how much room is left there.
+    pub async fn peer_stats(&mut self, peer: &str) -> Result<PeerStats> {
+        match self.send_command(SdkCommand::PeerStat { peer_id: peer.to_string() }).await? {
+            SdkResponse::PeerStat { used, quota, blocks_remote } => Ok(PeerStats { used, quota, blocks_remote }),
+            _ => Err(MemCloudError::unexpected("PeerStat")),
+        }
+    }
+
+    /// Time the node has spent on each kind of command, by command name; see
+    /// [`SdkCommand::Metrics`].
+    pub async fn command_metrics(&mut self) -> Result<Vec<CommandMetrics>> {
+        match self.send_command(SdkCommand::Metrics).await? {
+            SdkResponse::Metrics { commands } => Ok(commands),
+            _ => Err(MemCloudError::unexpected("Metrics")),
+        }
+    }
+
+    /// Blocks and memory of the node and each of its peers, with totals; see
+    /// [`SdkCommand::ClusterStat`]. Takes up to two seconds when a peer is slow.
+    pub async fn cluster_stats(&mut self) -> Result<ClusterStats> {
+        match self.send_command(SdkCommand::ClusterStat).await? {
+            SdkResponse::ClusterStat { stats } => Ok(stats),
+            _ => Err(MemCloudError::unexpected("ClusterStat")),
+        }
+    }
+
+    /// Handshake details and traffic counters of the session with `peer` (a name or id).
+    pub async fn peer_inspect(&mut self, peer: &str) -> Result<PeerSession> {
+        match self.send_command(SdkCommand::PeerInspect { id_or_name: peer.to_string() }).await? {
+            SdkResponse::PeerSession { session } => Ok(session),
+            _ => Err(MemCloudError::unexpected("PeerInspect")),
+        }
+    }
+
+    pub async fn ping(&mut self) -> Result<NodeHealth> {
+        match self.send_command(SdkCommand::Ping).await? {
+            SdkResponse::Pong { uptime_secs, healthy, details, port, name, pid } => Ok(NodeHealth { uptime_secs, healthy, details, port, name, pid }),
+            _ => Err(MemCloudError::unexpected("Ping")),
         }
     }
 
     pub async fn flush(&mut self, target: Option<String>) -> Result<()> {
-        let cmd = SdkCommand::Flush { target };
+        self.flush_filtered(target, None, None).await.map(|_| ())
+    }
+
+    /// Flush only keys matching `pattern` and/or blocks with the given durability.
+    /// Returns `None` when the node could not report counts (older nodes).
+    pub async fn flush_filtered(&mut self, target: Option<String>, pattern: Option<String>, durability: Option<Durability>) -> Result<Option<FlushStats>> {
+        self.invalidate(None);
+        let cmd = SdkCommand::Flush { target, pattern, durability };
         match self.send_command(cmd).await? {
-            SdkResponse::FlushSuccess => Ok(()),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            SdkResponse::Flushed { blocks_removed, bytes_freed } => Ok(Some(FlushStats { blocks_removed, bytes_freed })),
+            SdkResponse::FlushSuccess => Ok(None),
+            _ => Err(MemCloudError::unexpected("Flush")),
+        }
+    }
+
+    /// Flush every connected peer, and this node with `include_local`; see
+    /// [`SdkCommand::FlushCluster`]. One outcome per node, failures included.
+    pub async fn flush_cluster(&mut self, include_local: bool, pattern: Option<String>, durability: Option<Durability>) -> Result<Vec<FlushOutcome>> {
+        self.invalidate(None);
+        match self.send_command(SdkCommand::FlushCluster { include_local, pattern, durability }).await? {
+            SdkResponse::FlushReport { results } => Ok(results),
+            _ => Err(MemCloudError::unexpected("FlushCluster")),
+        }
+    }
+
+    /// The node's id, name and release. Nodes older than this command hang
+    /// up instead of answering; [`MemCloudClient::reconnect`] carries on.
+    pub async fn node_info(&mut self) -> Result<NodeInfo> {
+        match self.send_command(SdkCommand::NodeInfo).await? {
+            SdkResponse::NodeInfo { info } => Ok(info),
+            _ => Err(MemCloudError::unexpected("NodeInfo")),
+        }
+    }
+
+    pub async fn list_streams(&mut self) -> Result<Vec<StreamInfo>> {
+        match self.send_command(SdkCommand::StreamList).await? {
+            SdkResponse::StreamList { items } => Ok(items),
+            _ => Err(MemCloudError::unexpected("StreamList")),
+        }
+    }
+
+    pub async fn abort_stream(&mut self, stream_id: u64) -> Result<()> {
+        match self.send_command(SdkCommand::StreamAbort { stream_id }).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("StreamAbort")),
         }
     }
 
@@ -340,41 +2178,48 @@ impl MemCloudClient {
     where R: tokio::io::AsyncRead + Unpin 
     {
         // 1. Start
-        let start_cmd = SdkCommand::StreamStart { size_hint };
+        // Nodes that predate forwarding ignore the target here and use the one on StreamFinish
+        let start_cmd = SdkCommand::StreamStart { size_hint, target: target.clone() };
         let stream_id = match self.send_command(start_cmd).await? {
             SdkResponse::StreamStarted { stream_id } => stream_id,
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to StreamStart"),
+            _ => return Err(MemCloudError::unexpected("StreamStart")),
         };
 
-        // 2. Chunks
+        // 2. Chunks (each sealed on its own when encrypting, plus an end marker)
+        let cipher = self.cipher.clone();
+        let mut sealer = cipher.as_ref().map(crypto::StreamSealer::new);
         let mut buffer = vec![0u8; 1024 * 64]; // 64KB chunks
         let mut seq = 0;
         loop {
             let n = source.read(&mut buffer).await?;
-            if n == 0 { break; }
+            let data = match (&mut sealer, n) {
+                (Some(sealer), 0) => sealer.finish(),
+                (None, 0) => break,
+                (Some(sealer), n) => sealer.seal(&buffer[..n]),
+                (None, n) => buffer[..n].to_vec(),
+            };
             
             let chunk_cmd = SdkCommand::StreamChunk {
                 stream_id,
                 chunk_seq: seq,
-                data: buffer[..n].to_vec(),
+                data,
             
             };
             
             match self.send_command(chunk_cmd).await? {
                 SdkResponse::Success => {},
-                SdkResponse::Error { msg } => anyhow::bail!(msg),
-                _ => anyhow::bail!("Unexpected response to StreamChunk"),
+                _ => return Err(MemCloudError::unexpected("StreamChunk")),
             }
             seq += 1;
+            if n == 0 { break; }
         }
 
         // 3. Finish
-        let finish_cmd = SdkCommand::StreamFinish { stream_id, target, durability: None };
+        log::debug!("Stream {} sent in {} chunks", stream_id, seq);
+        let finish_cmd = SdkCommand::StreamFinish { stream_id, target, durability: None, priority: self.priority };
         match self.send_command(finish_cmd).await? {
-            SdkResponse::Stored { id } => Ok(id),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to StreamFinish"),
+            SdkResponse::Stored { id, .. } => Ok(id),
+            _ => Err(MemCloudError::unexpected("StreamFinish")),
         }
     }
 
@@ -382,17 +2227,15 @@ impl MemCloudClient {
         let cmd = SdkCommand::VmAlloc { size };
         match self.send_command(cmd).await? {
             SdkResponse::VmCreated { region_id } => Ok(region_id),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to VmAlloc"),
+            _ => Err(MemCloudError::unexpected("VmAlloc")),
         }
     }
 
     pub async fn vm_fetch(&mut self, region_id: u64, page_index: u64) -> Result<Vec<u8>> {
         let cmd = SdkCommand::VmFetch { region_id, page_index };
         match self.send_command(cmd).await? {
-            SdkResponse::PageData { data } => Ok(data),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to VmFetch"),
+            SdkResponse::PageData { data } => Ok(data.into()),
+            _ => Err(MemCloudError::unexpected("VmFetch")),
         }
     }
 
@@ -400,8 +2243,33 @@ impl MemCloudClient {
         let cmd = SdkCommand::VmStore { region_id, page_index, data };
         match self.send_command(cmd).await? {
             SdkResponse::Success => Ok(()),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response to VmStore"),
+            _ => Err(MemCloudError::unexpected("VmStore")),
+        }
+    }
+
+    /// The node's malloc interception threshold in MB, after setting it to `threshold_mb` if given.
+    pub async fn vm_config(&mut self, threshold_mb: Option<u64>) -> Result<u64> {
+        match self.send_command(SdkCommand::VmConfig { threshold_mb }).await? {
+            SdkResponse::VmConfig { threshold_mb } => Ok(threshold_mb),
+            _ => Err(MemCloudError::unexpected("VmConfig")),
+        }
+    }
+
+    /// Have the node re-read its config file. Returns the changed keys that
+    /// were applied and those that only take effect after a restart.
+    pub async fn reload_config(&mut self) -> Result<(Vec<String>, Vec<String>)> {
+        match self.send_command(SdkCommand::ReloadConfig).await? {
+            SdkResponse::ConfigReloaded { applied, restart_required } => Ok((applied, restart_required)),
+            _ => Err(MemCloudError::unexpected("ReloadConfig")),
+        }
+    }
+
+    /// Ask the node to shut down. It answers first, so the connection
+    /// closes soon after this returns.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        match self.send_command(SdkCommand::Shutdown).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("Shutdown")),
         }
     }
 
@@ -410,17 +2278,46 @@ impl MemCloudClient {
         let cmd = SdkCommand::TrustList;
         match self.send_command(cmd).await? {
             SdkResponse::TrustedList { items } => Ok(items),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("TrustList")),
+        }
+    }
+
+    pub async fn add_trusted(&mut self, public_key: &str, name: &str, note: Option<&str>) -> Result<()> {
+        let cmd = SdkCommand::TrustAdd { public_key: public_key.to_string(), name: name.to_string(), note: note.map(str::to_string) };
+        match self.send_command(cmd).await? {
+            SdkResponse::Success => Ok(()),
+            _ => Err(MemCloudError::unexpected("TrustAdd")),
         }
     }
 
     pub async fn remove_trusted(&mut self, key_or_name: &str) -> Result<()> {
-        let cmd = SdkCommand::TrustRemove { key_or_name: key_or_name.to_string() };
+        self.remove_trusted_with_note(key_or_name, None).await
+    }
+
+    /// Like `remove_trusted`, leaving `note` in the node's log to say why.
+    pub async fn remove_trusted_with_note(&mut self, key_or_name: &str, note: Option<&str>) -> Result<()> {
+        self.remove_trusted_inner(key_or_name, note, false).await
+    }
+
+    /// Like `remove_trusted_with_note`, but a connected device is drained
+    /// before it is disconnected; poll `drain_status` with its name for progress.
+    pub async fn remove_trusted_draining(&mut self, key_or_name: &str, note: Option<&str>) -> Result<()> {
+        self.remove_trusted_inner(key_or_name, note, true).await
+    }
+
+    async fn remove_trusted_inner(&mut self, key_or_name: &str, note: Option<&str>, drain: bool) -> Result<()> {
+        let cmd = SdkCommand::TrustRemove { key_or_name: key_or_name.to_string(), note: note.map(str::to_string), drain };
         match self.send_command(cmd).await? {
             SdkResponse::Success => Ok(()),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("TrustRemove")),
+        }
+    }
+
+    /// A trusted device, by public key or name, with its recent connections.
+    pub async fn trust_info(&mut self, key_or_name: &str) -> Result<TrustInfo> {
+        match self.send_command(SdkCommand::TrustInfo { key_or_name: key_or_name.to_string() }).await? {
+            SdkResponse::TrustInfo { info } => Ok(info),
+            _ => Err(MemCloudError::unexpected("TrustInfo")),
         }
     }
 
@@ -428,8 +2325,7 @@ impl MemCloudClient {
         let cmd = SdkCommand::ConsentList;
         match self.send_command(cmd).await? {
             SdkResponse::ConsentList { items } => Ok(items),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("ConsentList")),
         }
     }
 
@@ -437,8 +2333,7 @@ impl MemCloudClient {
         let cmd = SdkCommand::ConsentApprove { session_id: session_id.to_string(), trust_always };
         match self.send_command(cmd).await? {
             SdkResponse::Success => Ok(()),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("ConsentApprove")),
         }
     }
 
@@ -446,8 +2341,7 @@ impl MemCloudClient {
         let cmd = SdkCommand::ConsentDeny { session_id: session_id.to_string() };
         match self.send_command(cmd).await? {
             SdkResponse::Success => Ok(()),
-            SdkResponse::Error { msg } => anyhow::bail!(msg),
-            _ => anyhow::bail!("Unexpected response"),
+            _ => Err(MemCloudError::unexpected("ConsentDeny")),
         }
     }
 }
@@ -456,6 +2350,21 @@ impl MemCloudClient {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_trace_id_rides_along_with_command() {
+        let cmd = SdkCommand::Store { data: vec![7; 300], durability: None, content_addressed: false, tags: Tags::default(), priority: None };
+        let bytes = rmp_serde::to_vec_named(&cmd).unwrap();
+        let (head, skip, tail) = trace_frame(&bytes, "00c0ffee00c0ffee").unwrap();
+        let frame = [head, bytes[skip..].to_vec(), tail].concat();
+
+        let SdkCommand::Store { data, .. } = rmp_serde::from_slice(&frame).unwrap() else { panic!("not a Store") };
+        assert_eq!(data, vec![7; 300]);
+        let trace: CommandTrace = rmp_serde::from_slice(&frame).unwrap();
+        assert_eq!((trace.name(), trace.trace_id.as_deref()), ("Store", Some("00c0ffee00c0ffee")));
+        let untraced: CommandTrace = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(untraced.trace_id, None);
+    }
+
     #[test]
     fn test_parse_size() {
         assert_eq!(parse_size("100").unwrap(), 100);
@@ -467,4 +2376,99 @@ mod tests {
         assert_eq!(parse_size("512MB").unwrap(), 512 * 1024 * 1024);
         assert_eq!(parse_size("0").unwrap(), 0);
     }
+
+    #[test]
+    fn test_version_at_least() {
+        let node = |version: &str| NodeInfo { id: String::new(), name: String::new(), version: version.to_string() };
+        assert!(node("0.1.2").version_at_least("0.1.2"));
+        assert!(node("0.1.10").version_at_least("0.1.2"));
+        assert!(node("1.0.0-rc1").version_at_least("0.1.2"));
+        assert!(!node("0.1.1").version_at_least("0.1.2"));
+        assert!(!node("0.1").version_at_least("0.1.2"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timeout_against_silent_node() {
+        let path = std::env::temp_dir().join(format!("memsdk-silent-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        // Accepts and reads, never answers
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut sink = Vec::new();
+            let _ = conn.read_to_end(&mut sink).await;
+        });
+
+        let timeout = std::time::Duration::from_millis(100);
+        let mut client = MemCloudClient::connect_with_path(path.to_str().unwrap()).await.unwrap().with_timeout(timeout);
+        let started = std::time::Instant::now();
+        let err = client.ping().await.unwrap_err();
+        assert!(matches!(err, MemCloudError::Timeout(t) if t == timeout));
+        assert!(started.elapsed() < timeout * 10);
+        // The stream is out of step now, so later commands fail fast
+        assert!(matches!(client.ping().await.unwrap_err(), MemCloudError::Protocol(_)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // A node that answers the first connection to `path`, with `value` for a
+    // Get, until it has answered `commands` commands, then goes away
+    #[cfg(unix)]
+    async fn stub_node(path: &std::path::Path, value: &'static [u8], commands: usize) -> tokio::task::JoinHandle<Vec<String>> {
+        let _ = std::fs::remove_file(path);
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut seen = Vec::new();
+            while seen.len() < commands {
+                let mut len = [0u8; 4];
+                if conn.read_exact(&mut len).await.is_err() {
+                    break;
+                }
+                let mut frame = vec![0u8; u32::from_be_bytes(len) as usize];
+                conn.read_exact(&mut frame).await.unwrap();
+                let trace: CommandTrace = rmp_serde::from_slice(&frame).unwrap();
+                seen.push(trace.name().to_string());
+                let resp = match trace.name() {
+                    "Get" => SdkResponse::Loaded { data: value.to_vec().into(), durability: None },
+                    _ => SdkResponse::Success,
+                };
+                let bytes = rmp_serde::to_vec_named(&resp).unwrap();
+                conn.write_all(&(bytes.len() as u32).to_be_bytes()).await.unwrap();
+                conn.write_all(&bytes).await.unwrap();
+            }
+            seen
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reconnect_after_node_restart() {
+        let path = std::env::temp_dir().join(format!("memsdk-restart-{}.sock", std::process::id()));
+        let first = stub_node(&path, b"one", 2).await;
+        let mut client = MemCloudClient::connect_with_path(path.to_str().unwrap()).await.unwrap().with_reconnect(true);
+        client.identify("restart-test").await.unwrap();
+        assert_eq!(client.get("k", None).await.unwrap(), b"one");
+        // The node restarts: the old connection is gone and a new one answers
+        assert_eq!(first.await.unwrap(), vec!["ClientHello", "Get"]);
+        let second = stub_node(&path, b"two", 3).await;
+
+        // A read is sent again on the new connection, after the session setup
+        assert_eq!(client.get("k", None).await.unwrap(), b"two");
+        let _ = std::fs::remove_file(&path);
+        drop(client);
+        assert_eq!(second.await.unwrap(), vec!["ClientHello", "Get"]);
+
+        // Without reconnect, or for a write, the caller gets the I/O error
+        let third = stub_node(&path, b"three", 0).await;
+        let mut client = MemCloudClient::connect_with_path(path.to_str().unwrap()).await.unwrap();
+        third.await.unwrap();
+        assert!(matches!(client.get("k", None).await.unwrap_err(), MemCloudError::Io(_)));
+        let fourth = stub_node(&path, b"four", 1).await;
+        let mut client = client.with_reconnect(true);
+        assert!(matches!(client.set("k", b"v", None, Durability::Pinned).await.unwrap_err(), MemCloudError::Io(_)));
+        assert_eq!(client.get("k", None).await.unwrap(), b"four");
+        assert_eq!(fourth.await.unwrap(), vec!["Get"]);
+        let _ = std::fs::remove_file(&path);
+    }
 }